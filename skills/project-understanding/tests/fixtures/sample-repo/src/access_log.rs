@@ -0,0 +1,401 @@
+//! Access log.
+//!
+//! `log_request` logs one structured event per request to stdout and, when
+//! `Config::access_log.sink` names a file or the database, also queues an
+//! `AccessEntry` for `spawn_writer` to persist. Queueing never blocks a
+//! request: when the buffer is full the entry is dropped and counted, and the
+//! count is exported at `/metrics`. The writer drains whatever is queued into
+//! one batch per write. Entries still queued when the writer is stopped at
+//! shutdown are lost.
+
+use async_trait::async_trait;
+use axum::{
+    extract::{FromRequestParts, State},
+    http::Request,
+    middleware::Next,
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+use crate::context::RequestContext;
+use crate::AppState;
+
+/// Most entries written in one batch
+const MAX_BATCH: usize = 512;
+
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS access_log (\
+    at timestamptz NOT NULL, method text NOT NULL, path text NOT NULL, \
+    status integer NOT NULL, latency_ms bigint NOT NULL, client_ip text, \
+    actor text NOT NULL, request_id text NOT NULL)";
+
+const INSERT_BATCH: &str = "INSERT INTO access_log \
+    (at, method, path, status, latency_ms, client_ip, actor, request_id) \
+    SELECT * FROM UNNEST($1::timestamptz[], $2::text[], $3::text[], $4::int4[], \
+    $5::int8[], $6::text[], $7::text[], $8::text[])";
+
+/// Where access entries are persisted besides stdout
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AccessSink {
+    /// Stdout only
+    #[default]
+    Stdout,
+    /// JSON lines in a file rotated at `max_bytes`, keeping `keep` old files
+    /// as `<path>.1` (newest) to `<path>.<keep>`
+    File {
+        /// Log file path
+        path: PathBuf,
+        /// Size at which the file is rotated
+        max_bytes: u64,
+        /// Rotated files kept
+        keep: usize,
+    },
+    /// The `access_log` table of `Config::database_url`, created if missing
+    Database,
+}
+
+/// Access log settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessLogConfig {
+    /// Persistent sink
+    pub sink: AccessSink,
+    /// Entries queued for the sink before new ones are dropped
+    pub buffer: usize,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        AccessLogConfig {
+            sink: AccessSink::Stdout,
+            buffer: 4_096,
+        }
+    }
+}
+
+/// One handled request
+#[derive(Debug, Clone, Serialize)]
+pub struct AccessEntry {
+    /// When the response was sent
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// HTTP method
+    pub method: String,
+    /// Request path
+    pub path: String,
+    /// Response status
+    pub status: u16,
+    /// Time to produce the response
+    pub latency_ms: u64,
+    /// Client address, when known
+    pub client_ip: Option<String>,
+    /// Who made the request, as recorded in the audit log
+    pub actor: String,
+    /// Request correlation ID
+    pub request_id: String,
+}
+
+/// Queue between request handling and the sink writer
+pub struct AccessLog {
+    sender: Option<mpsc::Sender<AccessEntry>>,
+    receiver: Mutex<Option<mpsc::Receiver<AccessEntry>>>,
+    dropped: AtomicU64,
+}
+
+impl AccessLog {
+    /// Create the queue; nothing is queued when the sink is stdout
+    pub fn new(config: &AccessLogConfig) -> Self {
+        let (sender, receiver) = match config.sink {
+            AccessSink::Stdout => (None, None),
+            _ => {
+                let (sender, receiver) = mpsc::channel(config.buffer.max(1));
+                (Some(sender), Some(receiver))
+            }
+        };
+        Self {
+            sender,
+            receiver: Mutex::new(receiver),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Queue an entry for the sink without waiting
+    pub fn record(&self, entry: AccessEntry) {
+        if let Some(sender) = &self.sender {
+            if sender.try_send(entry).is_err() {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Entries lost to a full queue or a failed write
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn take_receiver(&self) -> Option<mpsc::Receiver<AccessEntry>> {
+        self.receiver.lock().expect("access log lock poisoned").take()
+    }
+}
+
+/// Destination for batches of entries
+#[async_trait]
+trait Sink: Send {
+    async fn write(&mut self, batch: &[AccessEntry]) -> Result<(), String>;
+}
+
+/// Append-only JSON lines file with size-based rotation
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: File,
+    written: u64,
+}
+
+impl RotatingFile {
+    /// Open `path` for appending, continuing its current size
+    pub async fn open(path: &Path, max_bytes: u64, keep: usize) -> io::Result<Self> {
+        let file = append(path).await?;
+        let written = file.metadata().await?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_bytes,
+            keep: keep.max(1),
+            file,
+            written,
+        })
+    }
+
+    fn rotated(&self, generation: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", generation));
+        name.into()
+    }
+
+    /// Shift `<path>.N` to `<path>.N+1`, dropping the oldest, and start afresh
+    async fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush().await?;
+        for generation in (1..self.keep).rev() {
+            match tokio::fs::rename(self.rotated(generation), self.rotated(generation + 1)).await {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+        tokio::fs::rename(&self.path, self.rotated(1)).await?;
+        self.file = append(&self.path).await?;
+        self.written = 0;
+        Ok(())
+    }
+
+    /// Append entries as JSON lines, rotating first if they would overflow the file
+    pub async fn write_entries(&mut self, batch: &[AccessEntry]) -> io::Result<()> {
+        let mut lines = Vec::new();
+        for entry in batch {
+            serde_json::to_writer(&mut lines, entry)?;
+            lines.push(b'\n');
+        }
+        if self.written > 0 && self.written + lines.len() as u64 > self.max_bytes {
+            self.rotate().await?;
+        }
+        self.file.write_all(&lines).await?;
+        self.file.flush().await?;
+        self.written += lines.len() as u64;
+        Ok(())
+    }
+}
+
+async fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path).await
+}
+
+#[async_trait]
+impl Sink for RotatingFile {
+    async fn write(&mut self, batch: &[AccessEntry]) -> Result<(), String> {
+        self.write_entries(batch).await.map_err(|err| err.to_string())
+    }
+}
+
+/// The `access_log` table
+struct TableSink {
+    pool: PgPool,
+}
+
+impl TableSink {
+    async fn connect(url: &str) -> Result<Self, sqlx::Error> {
+        let pool = PgPoolOptions::new().max_connections(1).connect(url).await?;
+        sqlx::query(CREATE_TABLE).execute(&pool).await?;
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl Sink for TableSink {
+    async fn write(&mut self, batch: &[AccessEntry]) -> Result<(), String> {
+        let column =
+            |field: fn(&AccessEntry) -> String| batch.iter().map(field).collect::<Vec<_>>();
+        sqlx::query(INSERT_BATCH)
+            .bind(batch.iter().map(|entry| entry.timestamp).collect::<Vec<_>>())
+            .bind(column(|entry| entry.method.clone()))
+            .bind(column(|entry| entry.path.clone()))
+            .bind(batch.iter().map(|entry| entry.status as i32).collect::<Vec<_>>())
+            .bind(batch.iter().map(|entry| entry.latency_ms as i64).collect::<Vec<_>>())
+            .bind(batch.iter().map(|entry| entry.client_ip.clone()).collect::<Vec<_>>())
+            .bind(column(|entry| entry.actor.clone()))
+            .bind(column(|entry| entry.request_id.clone()))
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+}
+
+async fn open_sink(state: &AppState) -> Result<Option<Box<dyn Sink>>, String> {
+    match &state.config().access_log.sink {
+        AccessSink::Stdout => Ok(None),
+        AccessSink::File {
+            path,
+            max_bytes,
+            keep,
+        } => {
+            let file = RotatingFile::open(path, *max_bytes, *keep).await;
+            file.map(|file| Some(Box::new(file) as Box<dyn Sink>))
+                .map_err(|err| format!("{}: {}", path.display(), err))
+        }
+        AccessSink::Database => TableSink::connect(state.config().database_url.expose())
+            .await
+            .map(|table| Some(Box::new(table) as Box<dyn Sink>))
+            .map_err(|err| err.to_string()),
+    }
+}
+
+/// Drain queued entries into the configured sink
+pub fn spawn_writer(state: Arc<AppState>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let Some(mut receiver) = state.access_log.take_receiver() else {
+            return;
+        };
+        let mut sink = match open_sink(&state).await {
+            Ok(Some(sink)) => sink,
+            Ok(None) => return,
+            Err(err) => {
+                tracing::warn!("access log sink disabled: {}", err);
+                return;
+            }
+        };
+        let mut batch = Vec::with_capacity(MAX_BATCH);
+        while let Some(entry) = receiver.recv().await {
+            batch.push(entry);
+            while batch.len() < MAX_BATCH {
+                match receiver.try_recv() {
+                    Ok(entry) => batch.push(entry),
+                    Err(_) => break,
+                }
+            }
+            match sink.write(&batch).await {
+                Ok(()) => state.degradations.recover("access_log"),
+                Err(err) => {
+                    tracing::warn!("failed to write {} access log entries: {}", batch.len(), err);
+                    state.access_log.dropped.fetch_add(batch.len() as u64, Ordering::Relaxed);
+                    state.degradations.degrade("access_log", "stdout_only", err);
+                }
+            }
+            batch.clear();
+        }
+    })
+}
+
+/// Request logging middleware, one event per request with structured fields
+pub async fn log_request<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let path = req.uri().path().to_string();
+    let method = req.method().to_string();
+    let (mut parts, body) = req.into_parts();
+    let context = match RequestContext::from_request_parts(&mut parts, &state).await {
+        Ok(context) => context,
+        Err(never) => match never {},
+    };
+    let req = Request::from_parts(parts, body);
+    let started = Instant::now();
+
+    let response = next.run(req).await;
+
+    let status = response.status().as_u16();
+    let latency_ms = started.elapsed().as_millis() as u64;
+    let client_ip = context.client_ip.map(|ip| ip.to_string());
+    tracing::info!(
+        method = %method,
+        path = %path,
+        status,
+        latency_ms,
+        client_ip = client_ip.as_deref().unwrap_or("-"),
+        actor = %context.principal,
+        request_id = %context.request_id,
+        "{} {} - {}",
+        method,
+        path,
+        status
+    );
+    state.access_log.record(AccessEntry {
+        timestamp: chrono::Utc::now(),
+        method,
+        path,
+        status,
+        latency_ms,
+        client_ip,
+        actor: context.principal.to_string(),
+        request_id: context.request_id,
+    });
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str) -> AccessEntry {
+        AccessEntry {
+            timestamp: chrono::Utc::now(),
+            method: "GET".to_string(),
+            path: path.to_string(),
+            status: 200,
+            latency_ms: 1,
+            client_ip: None,
+            actor: "anonymous".to_string(),
+            request_id: "test".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_file_rotates_and_keeps_generations() {
+        let dir = std::env::temp_dir().join(format!("access-log-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join("access.log");
+        let mut file = RotatingFile::open(&path, 100, 2).await.unwrap();
+        for n in 0..4 {
+            file.write_entries(&[entry(&format!("/{}", n))]).await.unwrap();
+        }
+
+        let read = |generation: &str| {
+            let mut name = path.clone().into_os_string();
+            name.push(generation);
+            std::fs::read_to_string(PathBuf::from(name)).unwrap()
+        };
+        assert!(read("").contains("\"path\":\"/3\""));
+        assert!(read(".1").contains("\"path\":\"/2\""));
+        assert!(read(".2").contains("\"path\":\"/1\""));
+        assert!(!dir.join("access.log.3").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
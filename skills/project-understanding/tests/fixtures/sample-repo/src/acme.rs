@@ -0,0 +1,121 @@
+//! Automatic certificates over ACME.
+//!
+//! With `Config::acme` set the API listener serves HTTPS with certificates
+//! it obtains itself from an ACME directory, Let's Encrypt by default, for
+//! the configured domains. Ownership is proven with the TLS-ALPN-01
+//! challenge on the listener itself, so the listener must be reachable on
+//! port 443 under every domain; no separate challenge port or HTTP route is
+//! needed. Certificates and the account key are cached in `cache_dir` and
+//! renewed well before they expire, without a restart. The staging
+//! directory is used unless `production` is set, because Let's Encrypt
+//! rate-limits failed production orders.
+//!
+//! `Config::acme` and `Config::tls` are mutually exclusive; `config_check`
+//! refuses a configuration that sets both.
+
+use axum::{extract::IntoMakeServiceWithConnectInfo, Router};
+use axum_server::Handle;
+use futures::StreamExt;
+use rustls_acme::caches::DirCache;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+
+use crate::tls;
+
+/// ACME certificate settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AcmeConfig {
+    /// Domains the certificate covers, e.g. `api.example.com`
+    pub domains: Vec<String>,
+    /// Contact emails for expiry and account notices
+    #[serde(default)]
+    pub contact: Vec<String>,
+    /// Where certificates and the account key are kept across restarts
+    pub cache_dir: PathBuf,
+    /// Use the production directory rather than staging
+    #[serde(default)]
+    pub production: bool,
+    /// Port redirecting plain HTTP to HTTPS; no redirects when unset
+    #[serde(default)]
+    pub redirect_http_port: Option<u16>,
+}
+
+impl AcmeConfig {
+    /// ACME `mailto:` contact URLs
+    fn contact_urls(&self) -> Vec<String> {
+        self.contact
+            .iter()
+            .map(|email| match email.strip_prefix("mailto:") {
+                Some(_) => email.clone(),
+                None => format!("mailto:{}", email),
+            })
+            .collect()
+    }
+}
+
+/// Serve `app` over HTTPS on `listeners` with ACME certificates until `shutdown` resolves
+pub async fn serve(
+    listeners: Vec<TcpListener>,
+    config: &AcmeConfig,
+    app: IntoMakeServiceWithConnectInfo<Router, SocketAddr>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> std::io::Result<()> {
+    let addrs = tls::local_addrs(&listeners)?;
+    let mut acme = rustls_acme::AcmeConfig::new(config.domains.clone())
+        .contact(config.contact_urls())
+        .cache(DirCache::new(config.cache_dir.clone()))
+        .directory_lets_encrypt(config.production)
+        .state();
+    let acceptor = acme.axum_acceptor(acme.default_rustls_config());
+    tracing::info!(
+        "obtaining certificates for {} over ACME{}",
+        config.domains.join(", "),
+        if config.production { "" } else { " (staging)" }
+    );
+    // Driving the state stream is what orders and renews certificates
+    let orders = tokio::spawn(async move {
+        while let Some(event) = acme.next().await {
+            match event {
+                Ok(event) => tracing::info!("ACME: {:?}", event),
+                Err(err) => tracing::error!("ACME certificate order failed: {}", err),
+            }
+        }
+    });
+
+    let handle = Handle::new();
+    if let Some(port) = config.redirect_http_port {
+        tls::spawn_redirects(&handle, &addrs, port);
+    }
+    tls::stop_on(&handle, shutdown);
+    let servers = listeners.into_iter().map(|listener| {
+        axum_server::from_tcp(listener)
+            .acceptor(acceptor.clone())
+            .handle(handle.clone())
+            .serve(app.clone())
+    });
+    let served = futures::future::try_join_all(servers).await;
+    orders.abort();
+    served.map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contacts_become_mailto_urls() {
+        let config = AcmeConfig {
+            domains: vec!["api.example.com".to_string()],
+            contact: vec!["ops@example.com".to_string(), "mailto:sre@example.com".to_string()],
+            cache_dir: PathBuf::from("/var/lib/app/acme"),
+            production: false,
+            redirect_http_port: None,
+        };
+        assert_eq!(
+            config.contact_urls(),
+            ["mailto:ops@example.com", "mailto:sre@example.com"]
+        );
+    }
+}
@@ -0,0 +1,169 @@
+//! Operator endpoints for a running instance.
+//!
+//! `routes` serves, under `/api/v1/admin`, what an operator needs without a
+//! Prometheus server or a shell on the host: runtime stats (uptime, requests
+//! per route and status class, requests in flight, pool and cache usage),
+//! the configuration in effect with every credential redacted, and two
+//! actions, resetting the request and cache counters and flushing the user
+//! caches. Every route requires the admin token, and the actions are logged
+//! with who ran them. Counters and caches are per instance, so an action
+//! affects only the instance that serves it.
+
+use axum::{
+    extract::State,
+    response::Json,
+    routing::{get, post},
+    Router,
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Arc;
+
+use crate::auth::Admin;
+use crate::cache::CacheStatsSnapshot;
+use crate::context::RequestContext;
+use crate::metrics::RouteRequests;
+use crate::repository::PoolStats;
+use crate::secret::REDACTED;
+use crate::{ApiResponse, AppState, Config};
+
+/// Configuration fields holding credentials in plain text, as JSON pointers
+const PLAIN_CREDENTIALS: [&str; 2] = ["/admin_token", "/setup_token"];
+
+/// Routes of the operator API, relative to the API mount point
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/admin/stats", get(runtime_stats))
+        .route("/admin/config", get(config_view))
+        .route("/admin/counters/reset", post(reset_counters))
+        .route("/admin/caches/flush", post(flush_caches))
+}
+
+/// What the instance has been doing
+#[derive(Debug, Clone, Serialize)]
+pub struct RuntimeStats {
+    /// Seconds since the process started
+    pub uptime_secs: u64,
+    /// Requests counted since startup or the last reset
+    pub requests_handled: u64,
+    /// Requests being handled now
+    pub in_flight: i64,
+    /// Requests by route and status class since startup or the last reset
+    pub routes: Vec<RouteRequests>,
+    /// Connection pool figures, for backends with a pool
+    pub db_pool: Option<PoolStats>,
+    /// User cache lookup counts
+    pub user_cache: CacheStatsSnapshot,
+    /// Users in the cache
+    pub cached_users: usize,
+    /// User lists in the cache
+    pub cached_lists: usize,
+}
+
+/// Entries dropped by a cache flush
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Flushed {
+    /// Users dropped from the user cache
+    pub users: usize,
+    /// Lists dropped from the list cache
+    pub lists: usize,
+}
+
+/// The configuration as JSON, with every credential replaced by `[redacted]`
+///
+/// `Secret` fields serialize redacted already; the older fields holding
+/// tokens and partner secrets as plain strings are redacted here.
+pub fn redacted_config(config: &Config) -> Value {
+    let mut value = serde_json::to_value(config).unwrap_or_default();
+    for pointer in PLAIN_CREDENTIALS {
+        if let Some(field) = value.pointer_mut(pointer).filter(|field| !field.is_null()) {
+            *field = Value::from(REDACTED);
+        }
+    }
+    if let Some(Value::Object(secrets)) = value.pointer_mut("/signatures/secrets") {
+        for secret in secrets.values_mut() {
+            *secret = Value::from(REDACTED);
+        }
+    }
+    value
+}
+
+/// Uptime, request counts, and pool and cache usage
+pub async fn runtime_stats(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+) -> Json<ApiResponse<RuntimeStats>> {
+    Json(ApiResponse::success(RuntimeStats {
+        uptime_secs: state.started.elapsed().as_secs(),
+        requests_handled: state.requests_handled(),
+        in_flight: state.metrics.in_flight(),
+        routes: state.metrics.requests_by_route(),
+        db_pool: state.users.pool_stats(),
+        user_cache: state.user_cache.stats(),
+        cached_users: state.user_cache.len(),
+        cached_lists: state.list_cache.len(),
+    }))
+}
+
+/// The configuration in effect, credentials redacted
+pub async fn config_view(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+) -> Json<ApiResponse<Value>> {
+    Json(ApiResponse::success(redacted_config(&state.config())))
+}
+
+/// Start the request and cache counters again from zero
+pub async fn reset_counters(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    context: RequestContext,
+) -> Json<ApiResponse<RuntimeStats>> {
+    state.request_count.reset();
+    state.metrics.reset();
+    state.user_cache.reset_stats();
+    tracing::info!(actor = %context.principal, "request and cache counters reset");
+    runtime_stats(State(state), Admin).await
+}
+
+/// Drop every cached user and user list; the next reads go to storage
+pub async fn flush_caches(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    context: RequestContext,
+) -> Json<ApiResponse<Flushed>> {
+    let flushed = Flushed {
+        users: state.user_cache.len(),
+        lists: state.list_cache.len(),
+    };
+    state.user_cache.clear();
+    state.list_cache.clear();
+    tracing::info!(actor = %context.principal, ?flushed, "user caches flushed");
+    Json(ApiResponse::success(flushed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_config_view_redacts_every_credential() {
+        let mut config = Config {
+            admin_token: Some("admin-secret".to_string()),
+            setup_token: Some("setup-secret".to_string()),
+            ..Config::default()
+        };
+        let partner = ("acme".to_string(), "partner-secret".to_string());
+        config.signatures.secrets = BTreeMap::from([partner]);
+
+        let view = redacted_config(&config);
+        let text = view.to_string();
+        for secret in ["admin-secret", "setup-secret", "partner-secret", "postgres://"] {
+            assert!(!text.contains(secret), "{} leaked: {}", secret, text);
+        }
+        assert_eq!(view["admin_token"], REDACTED);
+        assert_eq!(view["signatures"]["secrets"]["acme"], REDACTED);
+        assert_eq!(view["port"], serde_json::json!(config.port));
+    }
+}
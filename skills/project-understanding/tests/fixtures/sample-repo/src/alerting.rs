@@ -0,0 +1,422 @@
+//! Alerts with runbook context when the service trips into a bad state.
+//!
+//! Every `alerting.interval_ms` the evaluator collects what has tripped: a
+//! subsystem running in a fallback mode, such as a breaker around a
+//! dependency, a readiness check in outage, and load shedding. Each trip is
+//! sent to the Slack incoming webhook and the PagerDuty Events API, whichever
+//! are configured, with a snapshot of current metrics and the runbook
+//! `alerting.runbooks` lists for what tripped.
+//!
+//! A trip alerts once while it stays tripped, again every `repeat_secs`, and
+//! is resolved once it clears. No more than `max_per_hour` alerts go out from
+//! an instance, so a flapping dependency can't flood the channel; a trip held
+//! back by the limit is sent on a later tick. Resolutions are not limited, so
+//! a page that went out is always closed.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+use crate::degradation::Degradation;
+use crate::health::HealthState;
+use crate::limiter::LimiterSnapshot;
+use crate::repository::PoolStats;
+use crate::secret::Secret;
+use crate::AppState;
+
+/// PagerDuty Events API v2 endpoint
+const PAGERDUTY_URL: &str = "https://events.pagerduty.com/v2/enqueue";
+
+/// Alerting settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AlertConfig {
+    /// Slack incoming webhook URL
+    pub slack_webhook_url: Option<Secret<String>>,
+    /// PagerDuty Events API v2 routing key of the service to page
+    pub pagerduty_routing_key: Option<Secret<String>>,
+    /// Events API endpoint, overridable for EU accounts and tests
+    pub pagerduty_url: String,
+    /// Runbook URLs by what tripped: a subsystem, a health check, or `load_shedding`
+    pub runbooks: BTreeMap<String, String>,
+    /// Runbook for trips without an entry in `runbooks`
+    pub default_runbook: Option<String>,
+    /// How often trips are evaluated
+    pub interval_ms: u64,
+    /// Seconds after which a trip that is still tripped alerts again
+    pub repeat_secs: u64,
+    /// Most alerts sent in any hour
+    pub max_per_hour: usize,
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        AlertConfig {
+            slack_webhook_url: None,
+            pagerduty_routing_key: None,
+            pagerduty_url: PAGERDUTY_URL.to_string(),
+            runbooks: BTreeMap::new(),
+            default_runbook: None,
+            interval_ms: 15_000,
+            repeat_secs: 3_600,
+            max_per_hour: 20,
+        }
+    }
+}
+
+impl AlertConfig {
+    /// Whether any destination is configured
+    pub fn enabled(&self) -> bool {
+        self.slack_webhook_url.is_some() || self.pagerduty_routing_key.is_some()
+    }
+
+    fn runbook(&self, subject: &str) -> Option<String> {
+        self.runbooks.get(subject).or(self.default_runbook.as_ref()).cloned()
+    }
+}
+
+/// How urgent a trip is, named as PagerDuty names severities
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Something is down
+    Critical,
+    /// Something runs with reduced capacity or features
+    Warning,
+}
+
+/// Something that has tripped
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Trip {
+    /// Deduplication key, e.g. `outage:database`
+    pub key: String,
+    /// What tripped, as named in `AlertConfig::runbooks`
+    pub subject: String,
+    /// One line for responders
+    pub summary: String,
+    /// How urgent it is
+    pub severity: Severity,
+}
+
+/// Whether an alert opens or closes an incident
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertStatus {
+    /// The trip is tripped
+    Firing,
+    /// The trip cleared
+    Resolved,
+}
+
+/// Metrics at the time of an alert
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricsSnapshot {
+    /// Requests handled since startup
+    pub requests_handled: u64,
+    /// Database pool usage, for backends with a pool
+    pub db_pool: Option<PoolStats>,
+    /// Adaptive concurrency limits and calls in progress
+    pub limiters: Vec<LimiterSnapshot>,
+    /// Subsystems running in a fallback mode
+    pub degradations: Vec<Degradation>,
+    /// Whether low-priority requests are being shed
+    pub load_shedding: bool,
+}
+
+/// Payload describing one alert
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    /// What tripped
+    #[serde(flatten)]
+    pub trip: Trip,
+    /// Firing or resolved
+    pub status: AlertStatus,
+    /// Runbook for what tripped
+    pub runbook_url: Option<String>,
+    /// Region reporting the alert, when replication names one
+    pub region: Option<String>,
+    /// Metrics when the alert was sent
+    pub metrics: MetricsSnapshot,
+    /// When the alert was sent
+    pub at: chrono::DateTime<chrono::Utc>,
+}
+
+impl Alert {
+    /// Body of a Slack incoming webhook message
+    pub fn slack_message(&self) -> serde_json::Value {
+        let state = match self.status {
+            AlertStatus::Firing => format!("{:?}", self.trip.severity).to_uppercase(),
+            AlertStatus::Resolved => "RESOLVED".to_string(),
+        };
+        let runbook = match &self.runbook_url {
+            Some(url) => format!("\nRunbook: {}", url),
+            None => String::new(),
+        };
+        let metrics = serde_json::to_string_pretty(&self.metrics).unwrap_or_default();
+        let text = format!("[{}] {}{}\n```{}```", state, self.trip.summary, runbook, metrics);
+        serde_json::json!({ "text": text })
+    }
+
+    /// Body of a PagerDuty Events API v2 event
+    pub fn pagerduty_event(&self, routing_key: &str) -> serde_json::Value {
+        let action = match self.status {
+            AlertStatus::Firing => "trigger",
+            AlertStatus::Resolved => "resolve",
+        };
+        let links: Vec<_> = self
+            .runbook_url
+            .iter()
+            .map(|url| serde_json::json!({ "href": url, "text": "Runbook" }))
+            .collect();
+        serde_json::json!({
+            "routing_key": routing_key,
+            "event_action": action,
+            "dedup_key": self.trip.key,
+            "payload": {
+                "summary": self.trip.summary,
+                "source": self.region.as_deref().unwrap_or("app"),
+                "severity": self.trip.severity,
+                "custom_details": self,
+            },
+            "links": links,
+        })
+    }
+}
+
+/// A trip being alerted on
+struct Firing {
+    trip: Trip,
+    /// When it last alerted; unset while held back by the rate limit
+    sent_at: Option<Instant>,
+}
+
+/// Deduplicates, rate-limits, and sends alerts
+pub struct Alerter {
+    firing: Mutex<HashMap<String, Firing>>,
+    sent: Mutex<VecDeque<Instant>>,
+    client: reqwest::Client,
+}
+
+impl Default for Alerter {
+    fn default() -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("failed to build alerting HTTP client");
+        Alerter {
+            firing: Mutex::new(HashMap::new()),
+            sent: Mutex::new(VecDeque::new()),
+            client,
+        }
+    }
+}
+
+impl Alerter {
+    /// Alerts due now that `trips` are tripped, recording them as sent
+    fn due(
+        &self,
+        trips: Vec<Trip>,
+        config: &AlertConfig,
+        now: Instant,
+    ) -> Vec<(Trip, AlertStatus)> {
+        let repeat = Duration::from_secs(config.repeat_secs);
+        let mut firing = self.firing.lock().expect("alerting lock poisoned");
+        let mut due = Vec::new();
+        firing.retain(|key, current| {
+            let tripped = trips.iter().any(|trip| &trip.key == key);
+            if !tripped && current.sent_at.is_some() {
+                due.push((current.trip.clone(), AlertStatus::Resolved));
+            }
+            tripped
+        });
+        for trip in trips {
+            let current = firing
+                .entry(trip.key.clone())
+                .or_insert(Firing { trip: trip.clone(), sent_at: None });
+            current.trip = trip;
+            let owed = match current.sent_at {
+                Some(sent_at) => now.saturating_duration_since(sent_at) >= repeat,
+                None => true,
+            };
+            if owed && self.within_limit(config, now) {
+                current.sent_at = Some(now);
+                due.push((current.trip.clone(), AlertStatus::Firing));
+            }
+        }
+        due
+    }
+
+    /// Take one alert from the hourly budget if any is left
+    fn within_limit(&self, config: &AlertConfig, now: Instant) -> bool {
+        let hour = Duration::from_secs(3_600);
+        let mut sent = self.sent.lock().expect("alerting lock poisoned");
+        while sent
+            .front()
+            .is_some_and(|at| now.saturating_duration_since(*at) >= hour)
+        {
+            sent.pop_front();
+        }
+        if sent.len() >= config.max_per_hour {
+            return false;
+        }
+        sent.push_back(now);
+        true
+    }
+
+    async fn send(&self, alert: &Alert, config: &AlertConfig) {
+        if let Some(url) = &config.slack_webhook_url {
+            self.post("Slack", url.expose(), &alert.slack_message()).await;
+        }
+        if let Some(key) = &config.pagerduty_routing_key {
+            let event = alert.pagerduty_event(key.expose());
+            self.post("PagerDuty", &config.pagerduty_url, &event).await;
+        }
+    }
+
+    async fn post(&self, destination: &str, url: &str, body: &serde_json::Value) {
+        match self.client.post(url).json(body).send().await {
+            Ok(response) if response.status().is_success() => {}
+            Ok(response) => {
+                tracing::warn!("{} refused an alert with status {}", destination, response.status())
+            }
+            Err(err) => tracing::warn!("failed to send an alert to {}: {}", destination, err),
+        }
+    }
+}
+
+/// What is tripped right now
+async fn tripped(state: &AppState) -> Vec<Trip> {
+    let mut trips: Vec<Trip> = state
+        .degradations
+        .active()
+        .into_iter()
+        .map(|degradation| Trip {
+            key: format!("degraded:{}", degradation.subsystem),
+            subject: degradation.subsystem.to_string(),
+            summary: format!(
+                "{} degraded to {}: {}",
+                degradation.subsystem, degradation.mode, degradation.reason
+            ),
+            severity: Severity::Warning,
+        })
+        .collect();
+    let checks = state.readiness.run(&state.health_checks).await;
+    trips.extend(
+        checks
+            .into_iter()
+            .filter(|check| check.state == HealthState::Outage)
+            .map(|check| Trip {
+                key: format!("outage:{}", check.name),
+                subject: check.name.to_string(),
+                summary: format!("{} health check reports an outage", check.name),
+                severity: Severity::Critical,
+            }),
+    );
+    if state.load_shedder.under_pressure(&state.config().priority) {
+        trips.push(Trip {
+            key: "load_shedding".to_string(),
+            subject: "load_shedding".to_string(),
+            summary: "shedding low-priority requests under load".to_string(),
+            severity: Severity::Warning,
+        });
+    }
+    trips
+}
+
+fn snapshot(state: &AppState) -> MetricsSnapshot {
+    MetricsSnapshot {
+        requests_handled: state.requests_handled(),
+        db_pool: state.users.pool_stats(),
+        limiters: state.limiters.iter().map(|limiter| limiter.snapshot()).collect(),
+        degradations: state.degradations.active(),
+        load_shedding: state.load_shedder.under_pressure(&state.config().priority),
+    }
+}
+
+/// Evaluate trips every `alerting.interval_ms` and send the alerts due
+pub fn spawn_evaluator(state: Arc<AppState>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let interval = Duration::from_millis(state.config().alerting.interval_ms);
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            let config = state.config().alerting.clone();
+            if !config.enabled() {
+                continue;
+            }
+            let trips = tripped(&state).await;
+            let due = state.alerts.due(trips, &config, Instant::now());
+            if due.is_empty() {
+                continue;
+            }
+            let metrics = snapshot(&state);
+            let region = state.config().replication.region.clone();
+            for (trip, status) in due {
+                tracing::info!("alerting {:?}: {}", status, trip.summary);
+                let alert = Alert {
+                    runbook_url: config.runbook(&trip.subject),
+                    trip,
+                    status,
+                    region: region.clone(),
+                    metrics: metrics.clone(),
+                    at: chrono::Utc::now(),
+                };
+                state.alerts.send(&alert, &config).await;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trip(key: &str) -> Trip {
+        Trip {
+            key: key.to_string(),
+            subject: key.to_string(),
+            summary: format!("{} tripped", key),
+            severity: Severity::Critical,
+        }
+    }
+
+    #[test]
+    fn test_trips_alert_once_repeat_and_resolve() {
+        let alerter = Alerter::default();
+        let config = AlertConfig { repeat_secs: 60, ..AlertConfig::default() };
+        let start = Instant::now();
+
+        let due = alerter.due(vec![trip("outage:database")], &config, start);
+        assert_eq!(due, vec![(trip("outage:database"), AlertStatus::Firing)]);
+        let later = start + Duration::from_secs(30);
+        assert!(alerter.due(vec![trip("outage:database")], &config, later).is_empty());
+        let repeated = start + Duration::from_secs(60);
+        assert_eq!(alerter.due(vec![trip("outage:database")], &config, repeated).len(), 1);
+
+        let due = alerter.due(Vec::new(), &config, repeated);
+        assert_eq!(due, vec![(trip("outage:database"), AlertStatus::Resolved)]);
+        assert!(alerter.due(Vec::new(), &config, repeated).is_empty());
+    }
+
+    #[test]
+    fn test_alerts_over_the_hourly_limit_wait_for_budget() {
+        let alerter = Alerter::default();
+        let config = AlertConfig {
+            max_per_hour: 1,
+            repeat_secs: 86_400,
+            ..AlertConfig::default()
+        };
+        let start = Instant::now();
+
+        let trips = vec![trip("outage:database"), trip("load_shedding")];
+        assert_eq!(alerter.due(trips.clone(), &config, start).len(), 1);
+        assert!(alerter.due(trips.clone(), &config, start).is_empty());
+        // A trip held back clears without a resolution
+        assert!(alerter.due(vec![trip("outage:database")], &config, start).is_empty());
+        let next_hour = start + Duration::from_secs(3_600);
+        let due = alerter.due(trips, &config, next_hour);
+        assert_eq!(due, vec![(trip("load_shedding"), AlertStatus::Firing)]);
+    }
+}
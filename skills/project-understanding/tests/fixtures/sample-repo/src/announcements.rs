@@ -0,0 +1,165 @@
+//! Operator announcements such as incidents and planned maintenance.
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use crate::audit::{self, AuditAction, AuditContext};
+use crate::auth::Admin;
+use crate::validation::ApiPath;
+use crate::{ApiResponse, AppState};
+
+/// Kind of announcement
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// General notice
+    Info,
+    /// Planned maintenance window
+    Maintenance,
+    /// Ongoing incident
+    Incident,
+}
+
+/// A published announcement
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Announcement {
+    /// Unique identifier
+    pub id: Uuid,
+    /// Short headline
+    pub title: String,
+    /// Longer description shown to users
+    pub message: String,
+    /// Kind of announcement
+    pub severity: Severity,
+    /// Names of affected components, matching health check names
+    #[serde(default)]
+    pub components: Vec<String>,
+    /// When the announcement was published
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    /// When the announcement was resolved, if it has been
+    pub resolved_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Request body for publishing an announcement
+#[derive(Debug, Deserialize)]
+pub struct NewAnnouncement {
+    /// Short headline
+    pub title: String,
+    /// Longer description
+    pub message: String,
+    /// Kind of announcement
+    pub severity: Severity,
+    /// Affected components
+    #[serde(default)]
+    pub components: Vec<String>,
+}
+
+/// In-memory announcement store
+#[derive(Default)]
+pub struct AnnouncementStore {
+    items: RwLock<Vec<Announcement>>,
+}
+
+impl AnnouncementStore {
+    /// Create an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Publish a new announcement
+    pub async fn publish(&self, new: NewAnnouncement) -> Announcement {
+        let announcement = Announcement {
+            id: Uuid::new_v4(),
+            title: new.title,
+            message: new.message,
+            severity: new.severity,
+            components: new.components,
+            started_at: chrono::Utc::now(),
+            resolved_at: None,
+        };
+        self.items.write().await.push(announcement.clone());
+        announcement
+    }
+
+    /// Fetch an announcement by ID
+    pub async fn get(&self, id: Uuid) -> Option<Announcement> {
+        self.items.read().await.iter().find(|item| item.id == id).cloned()
+    }
+
+    /// Mark an announcement resolved, returning it if found
+    pub async fn resolve(&self, id: Uuid) -> Option<Announcement> {
+        let mut items = self.items.write().await;
+        let item = items.iter_mut().find(|item| item.id == id)?;
+        item.resolved_at.get_or_insert_with(chrono::Utc::now);
+        Some(item.clone())
+    }
+
+    /// Announcements that have not been resolved, newest first
+    pub async fn active(&self) -> Vec<Announcement> {
+        let items = self.items.read().await;
+        let mut active: Vec<Announcement> = items
+            .iter()
+            .filter(|item| item.resolved_at.is_none())
+            .cloned()
+            .collect();
+        active.sort_by(|a, b| b.started_at.cmp(&a.started_at));
+        active
+    }
+}
+
+/// List active announcements
+pub async fn list_announcements(
+    State(state): State<Arc<AppState>>,
+) -> Json<ApiResponse<Vec<Announcement>>> {
+    Json(ApiResponse::success(state.announcements.active().await))
+}
+
+/// Publish an announcement
+pub async fn create_announcement(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    context: AuditContext,
+    Json(new): Json<NewAnnouncement>,
+) -> (StatusCode, Json<ApiResponse<Announcement>>) {
+    let announcement = state.announcements.publish(new).await;
+    audit::record(
+        &state,
+        &context,
+        "announcement",
+        announcement.id,
+        AuditAction::Create,
+        None,
+        Some(&announcement),
+    )
+    .await;
+    (StatusCode::CREATED, Json(ApiResponse::success(announcement)))
+}
+
+/// Resolve an announcement
+pub async fn resolve_announcement(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    context: AuditContext,
+    ApiPath(id): ApiPath<Uuid>,
+) -> Result<Json<ApiResponse<Announcement>>, StatusCode> {
+    let before = state.announcements.get(id).await.ok_or(StatusCode::NOT_FOUND)?;
+    let announcement = state.announcements.resolve(id).await.ok_or(StatusCode::NOT_FOUND)?;
+    audit::record(
+        &state,
+        &context,
+        "announcement",
+        id,
+        AuditAction::Update,
+        Some(&before),
+        Some(&announcement),
+    )
+    .await;
+    Ok(Json(ApiResponse::success(announcement)))
+}
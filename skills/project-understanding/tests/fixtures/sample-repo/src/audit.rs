@@ -0,0 +1,316 @@
+//! Audit log of every mutation.
+//!
+//! Each entry records who changed what, when, under which request and from
+//! which client address, and a field-level diff of the entity before and
+//! after the change.
+
+use async_trait::async_trait;
+use axum::{
+    extract::{FromRequestParts, Query, State},
+    http::{request::Parts, StatusCode},
+    response::Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::convert::Infallible;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use crate::auth::Admin;
+use crate::context::RequestContext;
+use crate::ingest::ActivityRow;
+use crate::repository::RepositoryError;
+use crate::{ApiResponse, AppState};
+
+/// What erased values in audit entries read as
+pub const ERASED: &str = "[erased]";
+
+/// Kind of mutation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    /// Entity was created
+    Create,
+    /// Entity was modified
+    Update,
+    /// Entity was deleted
+    Delete,
+}
+
+/// One changed field
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldChange {
+    /// Top-level field name
+    pub field: String,
+    /// Value before the change
+    pub before: Value,
+    /// Value after the change
+    pub after: Value,
+}
+
+/// A recorded mutation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Unique identifier
+    pub id: Uuid,
+    /// Who performed the change
+    pub actor: String,
+    /// When it happened
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// Request that caused it
+    pub request_id: String,
+    /// Entity type, e.g. `user`
+    pub entity: String,
+    /// Entity identifier
+    pub entity_id: Uuid,
+    /// Kind of mutation
+    pub action: AuditAction,
+    /// Fields that changed
+    pub changes: Vec<FieldChange>,
+    /// Address of the client that asked for it, when known
+    #[serde(default)]
+    pub client_ip: Option<IpAddr>,
+}
+
+/// Who is making a change and under which request
+#[derive(Debug, Clone)]
+pub struct AuditContext {
+    /// Actor name
+    pub actor: String,
+    /// Request ID
+    pub request_id: String,
+    /// Client address, when known
+    pub client_ip: Option<IpAddr>,
+}
+
+impl From<&RequestContext> for AuditContext {
+    fn from(context: &RequestContext) -> Self {
+        AuditContext {
+            actor: context.principal.to_string(),
+            request_id: context.request_id.clone(),
+            client_ip: context.client_ip,
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for AuditContext {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let context = RequestContext::from_request_parts(parts, state).await?;
+        Ok(AuditContext::from(&context))
+    }
+}
+
+/// Filters for reading the audit log
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuditQuery {
+    /// Entity type
+    pub entity: String,
+    /// Entity identifier; all entities of the type when omitted
+    pub id: Option<Uuid>,
+}
+
+/// Storage for audit entries
+#[async_trait]
+pub trait AuditStore: Send + Sync {
+    /// Append an entry
+    async fn append(&self, entry: AuditEntry) -> Result<(), RepositoryError>;
+
+    /// Read entries matching the query, newest first
+    async fn query(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>, RepositoryError>;
+
+    /// Drop entries older than `before`, returning how many were dropped
+    async fn prune(&self, before: chrono::DateTime<chrono::Utc>) -> Result<u64, RepositoryError>;
+
+    /// Replace the values in an entity's entries with `ERASED`, keeping who
+    /// changed which fields and when; returns how many entries were changed
+    async fn redact(&self, entity: &str, id: Uuid) -> Result<u64, RepositoryError>;
+}
+
+/// In-memory audit store
+#[derive(Default)]
+pub struct InMemoryAuditStore {
+    entries: RwLock<Vec<AuditEntry>>,
+}
+
+impl InMemoryAuditStore {
+    /// Create an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl AuditStore for InMemoryAuditStore {
+    async fn append(&self, entry: AuditEntry) -> Result<(), RepositoryError> {
+        self.entries.write().await.push(entry);
+        Ok(())
+    }
+
+    async fn query(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>, RepositoryError> {
+        let entries = self.entries.read().await;
+        Ok(entries
+            .iter()
+            .rev()
+            .filter(|entry| entry.entity == query.entity)
+            .filter(|entry| query.id.map_or(true, |id| entry.entity_id == id))
+            .cloned()
+            .collect())
+    }
+
+    async fn prune(&self, before: chrono::DateTime<chrono::Utc>) -> Result<u64, RepositoryError> {
+        let mut entries = self.entries.write().await;
+        let count = entries.len();
+        entries.retain(|entry| entry.timestamp >= before);
+        Ok((count - entries.len()) as u64)
+    }
+
+    async fn redact(&self, entity: &str, id: Uuid) -> Result<u64, RepositoryError> {
+        let mut entries = self.entries.write().await;
+        let mut count = 0;
+        for entry in entries.iter_mut() {
+            if entry.entity == entity && entry.entity_id == id {
+                for change in &mut entry.changes {
+                    change.before = erased(&change.before);
+                    change.after = erased(&change.after);
+                }
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
+/// What a value is replaced with when erased; null stays null, so the entry
+/// still shows whether a field was set or cleared
+fn erased(value: &Value) -> Value {
+    if value.is_null() {
+        Value::Null
+    } else {
+        Value::String(ERASED.to_string())
+    }
+}
+
+/// Compute a field-level diff between two serialized snapshots
+pub fn diff(before: &Value, after: &Value) -> Vec<FieldChange> {
+    let empty = serde_json::Map::new();
+    let before = before.as_object().unwrap_or(&empty);
+    let after = after.as_object().unwrap_or(&empty);
+
+    let mut fields: Vec<&String> = before.keys().chain(after.keys()).collect();
+    fields.sort();
+    fields.dedup();
+    fields
+        .into_iter()
+        .filter_map(|field| {
+            let old = before.get(field).cloned().unwrap_or(Value::Null);
+            let new = after.get(field).cloned().unwrap_or(Value::Null);
+            (old != new).then(|| FieldChange {
+                field: field.clone(),
+                before: old,
+                after: new,
+            })
+        })
+        .collect()
+}
+
+/// Record a mutation; failures are logged rather than failing the request
+pub async fn record<T: Serialize>(
+    state: &AppState,
+    context: &AuditContext,
+    entity: &str,
+    entity_id: Uuid,
+    action: AuditAction,
+    before: Option<&T>,
+    after: Option<&T>,
+) {
+    append(state, entry(context, entity, entity_id, action, before, after)).await;
+}
+
+/// An entry for a mutation, to be appended once it has been made
+pub fn entry<T: Serialize>(
+    context: &AuditContext,
+    entity: &str,
+    entity_id: Uuid,
+    action: AuditAction,
+    before: Option<&T>,
+    after: Option<&T>,
+) -> AuditEntry {
+    let snapshot = |value: Option<&T>| {
+        value
+            .and_then(|v| serde_json::to_value(v).ok())
+            .unwrap_or(Value::Null)
+    };
+    AuditEntry {
+        id: Uuid::new_v4(),
+        actor: context.actor.clone(),
+        timestamp: chrono::Utc::now(),
+        request_id: context.request_id.clone(),
+        entity: entity.to_string(),
+        entity_id,
+        action,
+        changes: diff(&snapshot(before), &snapshot(after)),
+        client_ip: context.client_ip,
+    }
+}
+
+/// Append an entry; failures are logged rather than failing the request
+pub async fn append(state: &AppState, entry: AuditEntry) {
+    if state.config().activity_ingest.enabled {
+        state.activity.offer(ActivityRow::audit(&entry)).await;
+    }
+    let (entity, entity_id) = (entry.entity.clone(), entry.entity_id);
+    if let Err(err) = state.audit.append(entry).await {
+        tracing::error!(
+            "failed to record audit entry for {} {}: {}",
+            entity,
+            entity_id,
+            err
+        );
+    }
+}
+
+/// Read the audit log for an entity
+pub async fn list_audit_entries(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<ApiResponse<Vec<AuditEntry>>>, StatusCode> {
+    let entries = state
+        .audit
+        .query(&query)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(ApiResponse::success(entries)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_diff_reports_changed_fields_only() {
+        let before = json!({"username": "alice", "is_active": true});
+        let after = json!({"username": "alice", "is_active": false});
+        let changes = diff(&before, &after);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].field, "is_active");
+        assert_eq!(changes[0].after, json!(false));
+    }
+
+    #[test]
+    fn test_diff_of_create_lists_every_field() {
+        let after = json!({"username": "alice", "email": "a@example.com"});
+        let changes = diff(&Value::Null, &after);
+        assert_eq!(changes.len(), 2);
+        assert!(changes.iter().all(|c| c.before.is_null()));
+    }
+}
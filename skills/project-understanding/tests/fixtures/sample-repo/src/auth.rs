@@ -0,0 +1,107 @@
+//! Request authentication extractors.
+//!
+//! Credentials only ever arrive in the `Authorization` header, which browsers
+//! never attach on their own, so a cross-site page can't make authenticated
+//! calls and there is no CSRF token check. Anything that starts accepting
+//! credentials from cookies needs one on state-changing routes first.
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header, request::Parts},
+};
+use std::sync::Arc;
+
+use crate::error::AppError;
+use crate::AppState;
+
+/// Extractor that only succeeds when the caller presents the admin token,
+/// either configured or issued by first-run setup
+#[derive(Debug, Clone, Copy)]
+pub struct Admin;
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for Admin {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let expected = admin_token(state)
+            .ok_or_else(|| AppError::Forbidden("admin access is not configured".to_string()))?;
+        let presented = bearer_token(parts).ok_or(AppError::Unauthorized)?;
+        if constant_time_eq(presented.as_bytes(), expected.as_bytes()) {
+            Ok(Admin)
+        } else {
+            Err(AppError::Forbidden("invalid admin token".to_string()))
+        }
+    }
+}
+
+/// What a caller is allowed to see and change, least privileged first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Any caller
+    Public,
+    /// Caller presenting the admin token
+    Admin,
+}
+
+impl Role {
+    /// Role of the caller making a request
+    pub async fn of(parts: &mut Parts, state: &Arc<AppState>) -> Role {
+        match Admin::from_request_parts(parts, state).await {
+            Ok(Admin) => Role::Admin,
+            Err(_) => Role::Public,
+        }
+    }
+}
+
+impl From<Option<Admin>> for Role {
+    fn from(admin: Option<Admin>) -> Self {
+        match admin {
+            Some(Admin) => Role::Admin,
+            None => Role::Public,
+        }
+    }
+}
+
+/// The admin token in effect: configured, or issued by first-run setup
+pub fn admin_token(state: &AppState) -> Option<String> {
+    state
+        .config()
+        .admin_token
+        .clone()
+        .or_else(|| state.setup.admin_token())
+}
+
+/// Extract the token from an `Authorization: Bearer ...` header
+pub fn bearer_token(parts: &Parts) -> Option<&str> {
+    parts
+        .headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+/// Compare secrets without short-circuiting on the first differing byte
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secrets"));
+    }
+}
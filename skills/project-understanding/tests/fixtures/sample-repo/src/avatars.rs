@@ -0,0 +1,295 @@
+//! User avatars uploaded as images.
+//!
+//! `PUT /api/v1/users/:id/avatar` takes a multipart upload, the image in its
+//! first field. The image must be PNG, JPEG, or WebP, with content matching
+//! the declared type, and between `min_dimension` and `max_dimension` pixels
+//! on each side; its dimensions are read from the header before anything is
+//! decoded. Each size in `AvatarConfig::sizes` is cropped to a square from
+//! the center, resized, and kept as PNG. `GET /api/v1/users/:id/avatar`
+//! serves the smallest kept size at least `?size=` pixels wide, or the
+//! largest one, redirecting to a presigned URL when the blob store issues
+//! them so the image is downloaded from storage rather than through us.
+//!
+//! Avatars are kept in the blob store as `avatars/<tenant>/<user>/<size>.png`
+//! beside an `info.json` describing them, written last so it never names a
+//! size that isn't stored yet.
+
+use axum::{
+    extract::{Multipart, Query, State},
+    http::header,
+    response::{IntoResponse, Json, Redirect, Response},
+};
+use image::{imageops::FilterType, ImageFormat};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::audit::{self, AuditAction, AuditContext};
+use crate::blobs::Blob;
+use crate::error::{AppError, ErrorReport};
+use crate::tenancy::Tenant;
+use crate::validation::ApiPath;
+use crate::{ApiResponse, AppState};
+
+/// Limits of avatar uploads and the sizes kept
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AvatarConfig {
+    /// Largest upload accepted
+    pub max_bytes: usize,
+    /// Shortest side accepted, in pixels
+    pub min_dimension: u32,
+    /// Longest side accepted, in pixels
+    pub max_dimension: u32,
+    /// Square sizes kept, in pixels
+    pub sizes: Vec<u32>,
+}
+
+impl Default for AvatarConfig {
+    fn default() -> Self {
+        AvatarConfig {
+            max_bytes: 5 * 1024 * 1024,
+            min_dimension: 32,
+            max_dimension: 4096,
+            sizes: vec![64, 128, 256],
+        }
+    }
+}
+
+/// An avatar as reported after upload
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AvatarInfo {
+    /// Width of the uploaded image
+    pub width: u32,
+    /// Height of the uploaded image
+    pub height: u32,
+    /// Square sizes served, smallest first
+    pub sizes: Vec<u32>,
+    /// When the avatar was uploaded
+    pub uploaded_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// An uploaded avatar resized to each kept size
+#[derive(Debug, Clone)]
+pub struct Avatar {
+    /// What was uploaded
+    pub info: AvatarInfo,
+    /// PNG encodings by size
+    pub renditions: BTreeMap<u32, Vec<u8>>,
+}
+
+/// Smallest of `sizes` at least `wanted` wide, or the largest one
+pub fn pick(sizes: &[u32], wanted: Option<u32>) -> Option<u32> {
+    let wanted = wanted.unwrap_or(u32::MAX);
+    let fitting = sizes.iter().copied().filter(|&size| size >= wanted).min();
+    fitting.or_else(|| sizes.iter().copied().max())
+}
+
+/// Blob key of one file of a user's avatar
+fn key(tenant: &str, id: Uuid, name: &str) -> String {
+    format!("avatars/{}/{}/{}", tenant, id, name)
+}
+
+/// A user's avatar, if one was uploaded
+pub async fn stored(
+    state: &AppState,
+    tenant: &str,
+    id: Uuid,
+) -> Result<Option<AvatarInfo>, AppError> {
+    match state.blobs.get(&key(tenant, id, "info.json")).await? {
+        Some(blob) => serde_json::from_slice(&blob.bytes)
+            .map(Some)
+            .map_err(|err| AppError::Internal(ErrorReport::new(err))),
+        None => Ok(None),
+    }
+}
+
+/// Delete a user's avatar, if one was uploaded
+pub async fn remove(state: &AppState, tenant: &str, id: Uuid) -> Result<(), AppError> {
+    let Some(info) = stored(state, tenant, id).await? else {
+        return Ok(());
+    };
+    // The info first, so a failure part way never leaves it naming a gone size
+    state.blobs.delete(&key(tenant, id, "info.json")).await?;
+    for size in info.sizes {
+        state.blobs.delete(&key(tenant, id, &format!("{}.png", size))).await?;
+    }
+    Ok(())
+}
+
+/// Format of a declared image content type, if it is an accepted one
+fn format_of(content_type: Option<&str>) -> Option<ImageFormat> {
+    let essence = content_type?.split(';').next()?.trim();
+    match essence.to_ascii_lowercase().as_str() {
+        "image/png" => Some(ImageFormat::Png),
+        "image/jpeg" => Some(ImageFormat::Jpeg),
+        "image/webp" => Some(ImageFormat::WebP),
+        _ => None,
+    }
+}
+
+/// Validate an upload and resize it to every configured size
+pub fn process(
+    config: &AvatarConfig,
+    content_type: Option<&str>,
+    bytes: &[u8],
+) -> Result<Avatar, Vec<String>> {
+    let Some(format) = format_of(content_type) else {
+        return Err(vec!["avatar must be a PNG, JPEG, or WebP image".to_string()]);
+    };
+    if image::guess_format(bytes).ok() != Some(format) {
+        return Err(vec![format!("avatar content is not {:?} as declared", format)]);
+    }
+    let reader = || image::io::Reader::with_format(Cursor::new(bytes), format);
+    let (width, height) = reader()
+        .into_dimensions()
+        .map_err(|err| vec![format!("avatar can't be read: {}", err)])?;
+    let (min, max) = (config.min_dimension, config.max_dimension);
+    if width.min(height) < min || width.max(height) > max {
+        return Err(vec![format!(
+            "avatar is {}x{}; each side must be {} to {} pixels",
+            width, height, min, max
+        )]);
+    }
+    let image = reader()
+        .decode()
+        .map_err(|err| vec![format!("avatar can't be decoded: {}", err)])?;
+
+    let mut renditions = BTreeMap::new();
+    for &size in &config.sizes {
+        let mut png = Cursor::new(Vec::new());
+        image
+            .resize_to_fill(size, size, FilterType::Lanczos3)
+            .write_to(&mut png, ImageFormat::Png)
+            .map_err(|err| vec![format!("avatar can't be resized: {}", err)])?;
+        renditions.insert(size, png.into_inner());
+    }
+    let info = AvatarInfo {
+        width,
+        height,
+        sizes: renditions.keys().copied().collect(),
+        uploaded_at: chrono::Utc::now(),
+    };
+    Ok(Avatar { info, renditions })
+}
+
+/// Query parameters for fetching an avatar
+#[derive(Debug, Default, Deserialize)]
+pub struct AvatarParams {
+    /// Width wanted, in pixels
+    pub size: Option<u32>,
+}
+
+/// Upload a user's avatar
+#[tracing::instrument(skip_all)]
+pub async fn upload_avatar(
+    State(state): State<Arc<AppState>>,
+    context: AuditContext,
+    Tenant(tenant): Tenant,
+    ApiPath(id): ApiPath<Uuid>,
+    mut multipart: Multipart,
+) -> Result<Json<ApiResponse<AvatarInfo>>, AppError> {
+    state.services.users.get(&state, &tenant, id, false).await?;
+    let field = multipart
+        .next_field()
+        .await
+        .map_err(|err| AppError::BadRequest(err.to_string()))?
+        .ok_or_else(|| AppError::BadRequest("upload has no image".to_string()))?;
+    let content_type = field.content_type().map(str::to_string);
+    let bytes = field.bytes().await.map_err(|err| AppError::BadRequest(err.to_string()))?;
+    let config = state.config().avatars.clone();
+    if bytes.len() > config.max_bytes {
+        let message = format!("avatar exceeds {} bytes", config.max_bytes);
+        return Err(AppError::PayloadTooLarge(message));
+    }
+
+    // Decoding and resizing are CPU-bound; keep them off the async workers
+    let avatar = tokio::task::spawn_blocking(move || {
+        process(&config, content_type.as_deref(), &bytes)
+    })
+    .await
+    .map_err(|err| AppError::Internal(ErrorReport::new(err)))?
+    .map_err(AppError::Validation)?;
+    let before = stored(&state, &tenant, id).await?;
+    for (size, png) in avatar.renditions {
+        let blob = Blob {
+            content_type: "image/png".to_string(),
+            bytes: png,
+        };
+        state.blobs.put(&key(&tenant, id, &format!("{}.png", size)), blob).await?;
+    }
+    let info = avatar.info;
+    let bytes = serde_json::to_vec(&info).map_err(|err| AppError::Internal(ErrorReport::new(err)))?;
+    let blob = Blob {
+        content_type: "application/json".to_string(),
+        bytes,
+    };
+    state.blobs.put(&key(&tenant, id, "info.json"), blob).await?;
+    // Sizes no longer configured would otherwise stay behind unreferenced
+    let dropped = before.iter().flat_map(|before| &before.sizes);
+    for size in dropped.filter(|size| !info.sizes.contains(*size)) {
+        state.blobs.delete(&key(&tenant, id, &format!("{}.png", size))).await?;
+    }
+    let before = before.as_ref();
+    let action = if before.is_some() { AuditAction::Update } else { AuditAction::Create };
+    audit::record(&state, &context, "user_avatar", id, action, before, Some(&info)).await;
+    Ok(Json(ApiResponse::success(info)))
+}
+
+/// A user's avatar as PNG, or a redirect to where to download it
+pub async fn get_avatar(
+    State(state): State<Arc<AppState>>,
+    Tenant(tenant): Tenant,
+    ApiPath(id): ApiPath<Uuid>,
+    Query(params): Query<AvatarParams>,
+) -> Result<Response, AppError> {
+    state.services.users.get(&state, &tenant, id, false).await?;
+    let missing = || AppError::NotFound(format!("user {} has no avatar", id));
+    let info = stored(&state, &tenant, id).await?.ok_or_else(missing)?;
+    let size = pick(&info.sizes, params.size).ok_or_else(missing)?;
+    let key = key(&tenant, id, &format!("{}.png", size));
+    if let Some(url) = state.blobs.presign(&key, state.config().blobs.presign_ttl()) {
+        return Ok(Redirect::temporary(&url).into_response());
+    }
+    let blob = state.blobs.get(&key).await?.ok_or_else(missing)?;
+    Ok(([(header::CONTENT_TYPE, blob.content_type)], blob.bytes).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut png = Cursor::new(Vec::new());
+        image::DynamicImage::new_rgb8(width, height)
+            .write_to(&mut png, ImageFormat::Png)
+            .unwrap();
+        png.into_inner()
+    }
+
+    #[test]
+    fn test_upload_is_resized_to_every_size() {
+        let config = AvatarConfig::default();
+        let avatar = process(&config, Some("image/png"), &png(300, 200)).unwrap();
+        assert_eq!((avatar.info.width, avatar.info.height), (300, 200));
+        assert_eq!(avatar.info.sizes, vec![64, 128, 256]);
+        let small = image::load_from_memory(&avatar.renditions[&128]).unwrap();
+        assert_eq!((small.width(), small.height()), (128, 128));
+        assert_eq!(pick(&avatar.info.sizes, Some(100)), Some(128));
+        assert_eq!(pick(&avatar.info.sizes, Some(1000)), Some(256));
+        assert_eq!(pick(&avatar.info.sizes, None), Some(256));
+        assert_eq!(pick(&[], Some(64)), None);
+    }
+
+    #[test]
+    fn test_type_content_and_dimensions_are_checked() {
+        let config = AvatarConfig::default();
+        assert!(process(&config, Some("image/gif"), &png(64, 64)).is_err());
+        assert!(process(&config, Some("image/jpeg"), &png(64, 64)).is_err());
+        assert!(process(&config, Some("image/png"), b"not an image").is_err());
+        assert!(process(&config, Some("image/png"), &png(16, 64)).is_err());
+        assert!(process(&config, Some("image/png; charset=binary"), &png(64, 64)).is_ok());
+    }
+}
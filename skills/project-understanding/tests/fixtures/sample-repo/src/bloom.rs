@@ -0,0 +1,191 @@
+//! Bloom filter in front of user existence checks.
+//!
+//! With `Config::existence_filter.enabled`, lookups by ID and "is this email
+//! taken?" checks that the filter rules out are answered without touching
+//! storage. The filter is loaded from storage at startup and then kept
+//! current from writes and the event bus. Bloom filters never forget, so
+//! deleted users and old emails only cost a storage lookup, never a wrong
+//! answer; until the initial load finishes every check goes to storage.
+
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+use crate::repository::{self, UserQuery};
+use crate::{AppState, User};
+
+/// Existence filter sizing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExistenceFilterConfig {
+    /// Build and consult the filter
+    pub enabled: bool,
+    /// Users the filter is sized for; past this the false positive rate climbs
+    pub expected_users: usize,
+    /// Target fraction of absent keys reported as possibly present
+    pub false_positive_rate: f64,
+}
+
+impl Default for ExistenceFilterConfig {
+    fn default() -> Self {
+        ExistenceFilterConfig {
+            enabled: false,
+            expected_users: 100_000,
+            false_positive_rate: 0.01,
+        }
+    }
+}
+
+/// Lock-free Bloom filter over hashable keys
+pub struct BloomFilter {
+    bits: Vec<AtomicU64>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// Size a filter for `items` keys at `false_positive_rate`
+    pub fn new(items: usize, false_positive_rate: f64) -> Self {
+        let items = items.max(1) as f64;
+        let rate = false_positive_rate.clamp(1e-9, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-items * rate.ln() / (ln2 * ln2)).ceil().max(64.0) as usize;
+        let hashes = ((bits as f64 / items) * ln2).round().clamp(1.0, 16.0) as u32;
+        Self {
+            bits: (0..bits.div_ceil(64)).map(|_| AtomicU64::new(0)).collect(),
+            hashes,
+        }
+    }
+
+    /// Bit positions of a key, by double hashing
+    fn positions<K: Hash + ?Sized>(&self, key: &K) -> impl Iterator<Item = usize> {
+        let hash = |seed: u64| {
+            let mut hasher = DefaultHasher::new();
+            seed.hash(&mut hasher);
+            key.hash(&mut hasher);
+            hasher.finish()
+        };
+        let (first, second) = (hash(0), hash(1) | 1);
+        let len = self.bits.len() as u64 * 64;
+        (0..self.hashes as u64)
+            .map(move |i| (first.wrapping_add(i.wrapping_mul(second)) % len) as usize)
+    }
+
+    /// Add a key
+    pub fn insert<K: Hash + ?Sized>(&self, key: &K) {
+        for bit in self.positions(key) {
+            self.bits[bit / 64].fetch_or(1 << (bit % 64), Ordering::Relaxed);
+        }
+    }
+
+    /// False means the key was never inserted; true means it may have been
+    pub fn may_contain<K: Hash + ?Sized>(&self, key: &K) -> bool {
+        self.positions(key)
+            .all(|bit| self.bits[bit / 64].load(Ordering::Relaxed) & (1 << (bit % 64)) != 0)
+    }
+}
+
+/// Filters over user IDs and emails
+pub struct ExistenceFilter {
+    ids: BloomFilter,
+    emails: BloomFilter,
+    loaded: AtomicBool,
+}
+
+impl ExistenceFilter {
+    /// Create an empty, not yet loaded filter
+    pub fn new(config: &ExistenceFilterConfig) -> Self {
+        Self {
+            ids: BloomFilter::new(config.expected_users, config.false_positive_rate),
+            emails: BloomFilter::new(config.expected_users, config.false_positive_rate),
+            loaded: AtomicBool::new(false),
+        }
+    }
+
+    /// Record a user's ID and current email
+    pub fn add(&self, user: &User) {
+        self.ids.insert(&user.id);
+        self.emails.insert(user.email.as_str());
+    }
+
+    /// Whether the filter proves no user has `id`
+    pub fn rules_out_id(&self, id: Uuid) -> bool {
+        self.loaded.load(Ordering::Acquire) && !self.ids.may_contain(&id)
+    }
+
+    /// Whether the filter proves no user has `email`
+    pub fn rules_out_email(&self, email: &str) -> bool {
+        self.loaded.load(Ordering::Acquire) && !self.emails.may_contain(email)
+    }
+}
+
+/// Load the filter from storage, then keep it current from the event bus
+pub fn spawn_loader(state: Arc<AppState>) -> tokio::task::JoinHandle<()> {
+    // Subscribe before the scan so no user created during it is missed
+    let mut events = state.events.subscribe();
+    tokio::spawn(async move {
+        if !state.config().existence_filter.enabled {
+            return;
+        }
+        let query = UserQuery {
+            include_deleted: true,
+            ..UserQuery::default()
+        };
+        match repository::list_every_tenant(&*state.users, &query).await {
+            Ok(users) => {
+                for user in &users {
+                    state.existence_filter.add(user);
+                }
+                state.existence_filter.loaded.store(true, Ordering::Release);
+                tracing::info!("existence filter loaded with {} users", users.len());
+            }
+            Err(err) => {
+                tracing::warn!("existence filter disabled, failed to load users: {}", err);
+                return;
+            }
+        }
+        loop {
+            match events.recv().await {
+                Ok(event) => state.existence_filter.add(&event.user),
+                Err(RecvError::Lagged(skipped)) => {
+                    // Writes through this instance were added directly; others may be missing
+                    tracing::warn!("existence filter lagged, skipped {} events", skipped);
+                    state.existence_filter.loaded.store(false, Ordering::Release);
+                    return;
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inserted_keys_are_never_ruled_out() {
+        let filter = BloomFilter::new(1_000, 0.01);
+        for i in 0..1_000 {
+            filter.insert(&i);
+        }
+        assert!((0..1_000).all(|i| filter.may_contain(&i)));
+        let false_positives = (1_000..11_000).filter(|i| filter.may_contain(i)).count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn test_nothing_is_ruled_out_before_loading() {
+        let filter = ExistenceFilter::new(&ExistenceFilterConfig::default());
+        assert!(!filter.rules_out_id(Uuid::new_v4()));
+        filter.loaded.store(true, Ordering::Release);
+        let user = User::new("alice".to_string(), "alice@example.com".to_string());
+        filter.add(&user);
+        assert!(!filter.rules_out_id(user.id));
+        assert!(!filter.rules_out_email("alice@example.com"));
+        assert!(filter.rules_out_email("nobody@example.com"));
+    }
+}
@@ -0,0 +1,128 @@
+//! Request body size limits.
+//!
+//! `limit_body` refuses bodies over `Config::max_body_bytes`, or over
+//! `Config::upload_max_body_bytes` on upload routes, with a 413 in the usual
+//! error format. A declared `Content-Length` is checked before anything is
+//! read; bodies without one are read up to the limit and handed on from
+//! memory. axum's implicit extractor limit is disabled so these are the only
+//! limits in effect.
+
+use axum::{
+    body::Body,
+    extract::{MatchedPath, State},
+    http::{header, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use hyper::body::HttpBody;
+use std::sync::Arc;
+
+use crate::error::AppError;
+use crate::versioning::{LEGACY_PREFIX, V1_PREFIX};
+use crate::AppState;
+
+/// Routes taking file uploads, relative to an API mount point
+const UPLOAD_ROUTES: [&str; 2] = ["/users/import", "/users/:id/avatar"];
+
+/// Whether a matched route takes uploads
+fn is_upload(route: &str) -> bool {
+    [V1_PREFIX, LEGACY_PREFIX].iter().any(|prefix| {
+        route
+            .strip_prefix(prefix)
+            .is_some_and(|rest| UPLOAD_ROUTES.contains(&rest))
+    })
+}
+
+fn too_large(limit: usize) -> Response {
+    AppError::PayloadTooLarge(format!("request body exceeds {} bytes", limit)).into_response()
+}
+
+/// Read a body, or `None` once it passes `limit`
+async fn read_limited(mut body: Body, limit: usize) -> Result<Option<Vec<u8>>, hyper::Error> {
+    let mut buffered = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if buffered.len() + chunk.len() > limit {
+            return Ok(None);
+        }
+        buffered.extend_from_slice(&chunk);
+    }
+    Ok(Some(buffered))
+}
+
+/// Middleware refusing request bodies over the route's limit
+pub async fn limit_body(
+    State(state): State<Arc<AppState>>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let upload = req
+        .extensions()
+        .get::<MatchedPath>()
+        .is_some_and(|route| is_upload(route.as_str()));
+    let limit = if upload {
+        state.config().upload_max_body_bytes
+    } else {
+        state.config().max_body_bytes
+    };
+    let declared = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    match declared {
+        Some(length) if length > limit as u64 => return too_large(limit),
+        // Framing keeps the body to its declared length
+        Some(_) => return next.run(req).await,
+        None if req.body().is_end_stream() => return next.run(req).await,
+        None => {}
+    }
+
+    let (parts, body) = req.into_parts();
+    match read_limited(body, limit).await {
+        Ok(Some(buffered)) => next.run(Request::from_parts(parts, Body::from(buffered))).await,
+        Ok(None) => too_large(limit),
+        Err(err) => AppError::BadRequest(format!("failed to read request body: {}", err))
+            .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::StatusCode, routing::post, Router};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_uploads_get_the_larger_limit() {
+        let mut config = crate::Config::default();
+        config.max_body_bytes = 8;
+        config.upload_max_body_bytes = 32;
+        let state = AppState::new(config);
+        let app = Router::new()
+            .route("/api/v1/users", post(|| async { "ok" }))
+            .route("/api/v1/users/import", post(|| async { "ok" }))
+            .layer(axum::middleware::from_fn_with_state(state.clone(), limit_body))
+            .with_state(state);
+
+        let send = |path: &str, length: usize, declare: bool| {
+            let mut request = Request::post(path);
+            if declare {
+                request = request.header(header::CONTENT_LENGTH, length);
+            }
+            let request = request.body(Body::from(vec![b'x'; length])).unwrap();
+            app.clone().oneshot(request)
+        };
+        let status = |response: Response| response.status();
+        assert_eq!(status(send("/api/v1/users", 8, true).await.unwrap()), StatusCode::OK);
+        assert_eq!(
+            status(send("/api/v1/users", 9, true).await.unwrap()),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert_eq!(
+            status(send("/api/v1/users", 9, false).await.unwrap()),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert_eq!(status(send("/api/v1/users/import", 32, false).await.unwrap()), StatusCode::OK);
+    }
+}
@@ -0,0 +1,499 @@
+//! Circuit breakers in front of the database and outbound calls.
+//!
+//! A `CircuitBreaker` watches the outcomes of the last `window` calls to one
+//! dependency. Once at least `min_calls` are in and the share that failed
+//! reaches `failure_rate`, it opens: for `open_ms` every call fails at once
+//! instead of waiting out a timeout against a dependency that is down. It
+//! then half-opens and lets `half_open_probes` calls through; if they all
+//! succeed it closes again, and if one fails it opens for another
+//! `open_ms`.
+//!
+//! With `Config::circuit_breaker.enabled` set, the user store, mail delivery, and
+//! each webhook URL get a breaker of their own. Calls refused by the user
+//! store's breaker fail as `RepositoryError::Unavailable`, so clients get a
+//! 503, and reads the cache can answer keep working; refused webhook
+//! deliveries and emails count as failed attempts and are retried, spooled,
+//! or given up on as any other. Conflicts and other answers from a healthy
+//! dependency never count as failures. Every breaker's state, trips, and
+//! refusals are exported at `/metrics`.
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::error::ErrorReport;
+use crate::events::DomainEvent;
+use crate::repository::{
+    Change, LastSeen, OutboxRecord, OutboxStore, PoolStats, RepositoryError, UserQuery,
+    UserRepository,
+};
+use crate::search::{SearchPage, SearchQuery};
+use crate::User;
+
+/// Circuit breaker tuning, shared by every breaker
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BreakerConfig {
+    /// Put breakers in front of the user store, mail, and webhooks
+    pub enabled: bool,
+    /// Recent calls whose outcomes are weighed
+    pub window: usize,
+    /// Calls in the window before the breaker may open
+    pub min_calls: usize,
+    /// Share of failed calls in the window that opens the breaker
+    pub failure_rate: f64,
+    /// How long the breaker stays open before probing
+    pub open_ms: u64,
+    /// Calls let through while half-open, all of which must succeed to close
+    pub half_open_probes: u32,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        BreakerConfig {
+            enabled: false,
+            window: 20,
+            min_calls: 10,
+            failure_rate: 0.5,
+            open_ms: 30_000,
+            half_open_probes: 3,
+        }
+    }
+}
+
+/// Whether a breaker lets calls through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    /// Calls go through
+    Closed,
+    /// Calls fail at once
+    Open,
+    /// A few probe calls go through
+    HalfOpen,
+}
+
+impl BreakerState {
+    /// Value exported as the `circuit_breaker_state` gauge
+    pub fn gauge(self) -> u8 {
+        match self {
+            BreakerState::Closed => 0,
+            BreakerState::HalfOpen => 1,
+            BreakerState::Open => 2,
+        }
+    }
+}
+
+/// Current figures of a breaker
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BreakerSnapshot {
+    /// Dependency name
+    pub name: String,
+    /// Whether calls go through
+    pub state: BreakerState,
+    /// Times the breaker opened since startup
+    pub trips: u64,
+    /// Calls refused since startup
+    pub rejected: u64,
+}
+
+/// A call refused because the breaker is open
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BreakerOpen {
+    /// Dependency name
+    pub name: String,
+}
+
+impl std::fmt::Display for BreakerOpen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "circuit breaker for {} is open", self.name)
+    }
+}
+
+impl std::error::Error for BreakerOpen {}
+
+#[derive(Debug)]
+struct Circuit {
+    state: BreakerState,
+    /// Outcomes of recent calls while closed, `true` for failures
+    outcomes: VecDeque<bool>,
+    opened_at: Instant,
+    probes_in_flight: u32,
+    probes_passed: u32,
+    trips: u64,
+    rejected: u64,
+}
+
+/// Failure-rate circuit breaker for one dependency
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    name: String,
+    config: BreakerConfig,
+    circuit: Mutex<Circuit>,
+}
+
+impl CircuitBreaker {
+    /// Create a closed breaker for the dependency `name`
+    pub fn new(name: impl Into<String>, config: BreakerConfig) -> Self {
+        Self {
+            name: name.into(),
+            config,
+            circuit: Mutex::new(Circuit {
+                state: BreakerState::Closed,
+                outcomes: VecDeque::new(),
+                opened_at: Instant::now(),
+                probes_in_flight: 0,
+                probes_passed: 0,
+                trips: 0,
+                rejected: 0,
+            }),
+        }
+    }
+
+    /// Current state and counts
+    pub fn snapshot(&self) -> BreakerSnapshot {
+        let circuit = self.circuit.lock().expect("breaker lock poisoned");
+        BreakerSnapshot {
+            name: self.name.clone(),
+            state: circuit.state,
+            trips: circuit.trips,
+            rejected: circuit.rejected,
+        }
+    }
+
+    fn trip(&self, circuit: &mut Circuit) {
+        circuit.state = BreakerState::Open;
+        circuit.opened_at = Instant::now();
+        circuit.outcomes.clear();
+        circuit.trips += 1;
+        tracing::warn!(dependency = %self.name, "circuit breaker opened");
+    }
+
+    /// Let a call through, or refuse it while open
+    pub fn admit(&self) -> Result<Attempt<'_>, BreakerOpen> {
+        let mut circuit = self.circuit.lock().expect("breaker lock poisoned");
+        let open_for = Duration::from_millis(self.config.open_ms);
+        if circuit.state == BreakerState::Open && circuit.opened_at.elapsed() >= open_for {
+            circuit.state = BreakerState::HalfOpen;
+            circuit.probes_in_flight = 0;
+            circuit.probes_passed = 0;
+        }
+        let probe = match circuit.state {
+            BreakerState::Closed => false,
+            BreakerState::HalfOpen if circuit.probes_in_flight < self.config.half_open_probes => {
+                circuit.probes_in_flight += 1;
+                true
+            }
+            BreakerState::HalfOpen | BreakerState::Open => {
+                circuit.rejected += 1;
+                return Err(BreakerOpen {
+                    name: self.name.clone(),
+                });
+            }
+        };
+        Ok(Attempt {
+            breaker: self,
+            probe,
+            failed: None,
+        })
+    }
+
+    fn record(&self, probe: bool, failed: Option<bool>) {
+        let mut circuit = self.circuit.lock().expect("breaker lock poisoned");
+        if probe {
+            circuit.probes_in_flight = circuit.probes_in_flight.saturating_sub(1);
+        }
+        let Some(failed) = failed else {
+            return;
+        };
+        match circuit.state {
+            BreakerState::Closed => {
+                circuit.outcomes.push_back(failed);
+                while circuit.outcomes.len() > self.config.window.max(1) {
+                    circuit.outcomes.pop_front();
+                }
+                let calls = circuit.outcomes.len();
+                let failures = circuit.outcomes.iter().filter(|&&failed| failed).count();
+                let rate = failures as f64 / calls as f64;
+                if calls >= self.config.min_calls && rate >= self.config.failure_rate {
+                    self.trip(&mut circuit);
+                }
+            }
+            BreakerState::HalfOpen if probe && failed => self.trip(&mut circuit),
+            BreakerState::HalfOpen if probe => {
+                circuit.probes_passed += 1;
+                if circuit.probes_passed >= self.config.half_open_probes {
+                    circuit.state = BreakerState::Closed;
+                    tracing::info!(dependency = %self.name, "circuit breaker closed");
+                }
+            }
+            // Calls admitted before the breaker opened say nothing new
+            BreakerState::HalfOpen | BreakerState::Open => {}
+        }
+    }
+
+    /// Run `call` unless the breaker is open, counting backend failures
+    pub async fn run<T, F>(&self, call: F) -> Result<T, RepositoryError>
+    where
+        F: Future<Output = Result<T, RepositoryError>>,
+    {
+        let attempt = self
+            .admit()
+            .map_err(|open| RepositoryError::Unavailable(ErrorReport::new(open)))?;
+        let result = call.await;
+        attempt.finish(matches!(
+            result,
+            Err(RepositoryError::Backend(_) | RepositoryError::Unavailable(_))
+        ));
+        result
+    }
+}
+
+/// A call let through, recorded when finished; a cancelled call records nothing
+pub struct Attempt<'a> {
+    breaker: &'a CircuitBreaker,
+    probe: bool,
+    failed: Option<bool>,
+}
+
+impl Attempt<'_> {
+    /// Record how the call went
+    pub fn finish(mut self, failed: bool) {
+        self.failed = Some(failed);
+    }
+}
+
+impl Drop for Attempt<'_> {
+    fn drop(&mut self) {
+        self.breaker.record(self.probe, self.failed);
+    }
+}
+
+/// Every breaker, created on first use while breakers are enabled
+#[derive(Debug)]
+pub struct Breakers {
+    config: BreakerConfig,
+    breakers: Mutex<BTreeMap<String, Arc<CircuitBreaker>>>,
+}
+
+impl Breakers {
+    /// Breakers tuned by `config`
+    pub fn new(config: &BreakerConfig) -> Self {
+        Self {
+            config: config.clone(),
+            breakers: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// The breaker for the dependency `name`, if breakers are enabled
+    pub fn get(&self, name: &str) -> Option<Arc<CircuitBreaker>> {
+        if !self.config.enabled {
+            return None;
+        }
+        let mut breakers = self.breakers.lock().expect("breakers lock poisoned");
+        let breaker = breakers
+            .entry(name.to_string())
+            .or_insert_with(|| Arc::new(CircuitBreaker::new(name, self.config.clone())));
+        Some(breaker.clone())
+    }
+
+    /// Figures of every breaker, by name
+    pub fn snapshots(&self) -> Vec<BreakerSnapshot> {
+        let breakers = self.breakers.lock().expect("breakers lock poisoned");
+        breakers.values().map(|breaker| breaker.snapshot()).collect()
+    }
+}
+
+/// Repository decorator sending every call through a `CircuitBreaker`
+pub struct BreakerUserRepository {
+    inner: Arc<dyn UserRepository>,
+    breaker: Arc<CircuitBreaker>,
+}
+
+impl BreakerUserRepository {
+    /// Wrap `inner`, refusing calls while `breaker` is open
+    pub fn new(inner: Arc<dyn UserRepository>, breaker: Arc<CircuitBreaker>) -> Self {
+        Self { inner, breaker }
+    }
+}
+
+#[async_trait]
+impl OutboxStore for BreakerUserRepository {
+    async fn pending_events(&self, limit: usize) -> Result<Vec<OutboxRecord>, RepositoryError> {
+        self.breaker.run(self.inner.pending_events(limit)).await
+    }
+
+    async fn mark_delivered(&self, ids: &[Uuid]) -> Result<(), RepositoryError> {
+        self.breaker.run(self.inner.mark_delivered(ids)).await
+    }
+}
+
+#[async_trait]
+impl UserRepository for BreakerUserRepository {
+    async fn list(&self, tenant: &str, query: &UserQuery) -> Result<Vec<User>, RepositoryError> {
+        self.breaker.run(self.inner.list(tenant, query)).await
+    }
+
+    async fn get(&self, tenant: &str, id: Uuid) -> Result<Option<User>, RepositoryError> {
+        self.breaker.run(self.inner.get(tenant, id)).await
+    }
+
+    async fn find_by_username(
+        &self,
+        tenant: &str,
+        username: &str,
+    ) -> Result<Option<User>, RepositoryError> {
+        self.breaker.run(self.inner.find_by_username(tenant, username)).await
+    }
+
+    async fn find_by_email(
+        &self,
+        tenant: &str,
+        email: &str,
+    ) -> Result<Option<User>, RepositoryError> {
+        self.breaker.run(self.inner.find_by_email(tenant, email)).await
+    }
+
+    async fn search(
+        &self,
+        tenant: &str,
+        query: &SearchQuery,
+    ) -> Result<SearchPage, RepositoryError> {
+        self.breaker.run(self.inner.search(tenant, query)).await
+    }
+
+    async fn tenants(&self) -> Result<Vec<String>, RepositoryError> {
+        self.breaker.run(self.inner.tenants()).await
+    }
+
+    async fn insert(&self, user: User) -> Result<User, RepositoryError> {
+        self.breaker.run(self.inner.insert(user)).await
+    }
+
+    async fn insert_with_event(
+        &self,
+        user: User,
+        event: DomainEvent,
+    ) -> Result<User, RepositoryError> {
+        self.breaker.run(self.inner.insert_with_event(user, event)).await
+    }
+
+    async fn update(&self, user: User) -> Result<Option<User>, RepositoryError> {
+        self.breaker.run(self.inner.update(user)).await
+    }
+
+    async fn update_with_event(
+        &self,
+        user: User,
+        event: DomainEvent,
+    ) -> Result<Option<User>, RepositoryError> {
+        self.breaker.run(self.inner.update_with_event(user, event)).await
+    }
+
+    async fn commit(&self, changes: &[Change]) -> Result<bool, RepositoryError> {
+        self.breaker.run(self.inner.commit(changes)).await
+    }
+
+    async fn delete(&self, tenant: &str, id: Uuid) -> Result<bool, RepositoryError> {
+        self.breaker.run(self.inner.delete(tenant, id)).await
+    }
+
+    async fn record_last_seen(&self, seen: &[LastSeen]) -> Result<(), RepositoryError> {
+        self.breaker.run(self.inner.record_last_seen(seen)).await
+    }
+
+    async fn last_seen(
+        &self,
+        tenant: &str,
+        id: Uuid,
+    ) -> Result<Option<chrono::DateTime<chrono::Utc>>, RepositoryError> {
+        self.breaker.run(self.inner.last_seen(tenant, id)).await
+    }
+
+    async fn migrate(&self) -> Result<Vec<&'static str>, RepositoryError> {
+        self.inner.migrate().await
+    }
+
+    async fn schema_version(&self) -> Result<Option<String>, RepositoryError> {
+        self.inner.schema_version().await
+    }
+
+    fn has_schema(&self) -> bool {
+        self.inner.has_schema()
+    }
+
+    async fn ping(&self) -> Result<(), RepositoryError> {
+        // Health checks must see the dependency, not the breaker in front of it
+        self.inner.ping().await
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        self.inner.pool_stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker(open_ms: u64) -> CircuitBreaker {
+        let config = BreakerConfig {
+            enabled: true,
+            window: 4,
+            min_calls: 4,
+            open_ms,
+            half_open_probes: 2,
+            ..BreakerConfig::default()
+        };
+        CircuitBreaker::new("test", config)
+    }
+
+    async fn fail(breaker: &CircuitBreaker) -> Result<(), RepositoryError> {
+        breaker.run(async { Err(RepositoryError::Backend(ErrorReport::new("timeout"))) }).await
+    }
+
+    #[tokio::test]
+    async fn test_opens_on_failure_rate_and_closes_after_probes() {
+        let breaker = breaker(20);
+        breaker.run(async { Ok(()) }).await.unwrap();
+        let conflict = RepositoryError::Conflict("email".to_string());
+        let _ = breaker.run(async { Err::<(), _>(conflict) }).await;
+        fail(&breaker).await.unwrap_err();
+        assert_eq!(breaker.snapshot().state, BreakerState::Closed);
+        fail(&breaker).await.unwrap_err();
+        assert_eq!(breaker.snapshot().state, BreakerState::Open);
+
+        let refused = breaker.run(async { Ok(()) }).await;
+        assert!(matches!(refused, Err(RepositoryError::Unavailable(_))));
+        assert_eq!((breaker.snapshot().trips, breaker.snapshot().rejected), (1, 1));
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        breaker.run(async { Ok(()) }).await.unwrap();
+        assert_eq!(breaker.snapshot().state, BreakerState::HalfOpen);
+        breaker.run(async { Ok(()) }).await.unwrap();
+        assert_eq!(breaker.snapshot().state, BreakerState::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_failed_or_cancelled_probe_frees_its_slot() {
+        let breaker = breaker(0);
+        for _ in 0..4 {
+            fail(&breaker).await.unwrap_err();
+        }
+        let first = breaker.admit().unwrap();
+        let second = breaker.admit().unwrap();
+        assert!(breaker.admit().is_err());
+        drop(first);
+        let third = breaker.admit().unwrap();
+        second.finish(true);
+        assert_eq!(breaker.snapshot().trips, 2);
+        assert_eq!(breaker.snapshot().state, BreakerState::Open);
+        // Probes of the last half-open spell don't count towards this one
+        third.finish(false);
+        assert!(breaker.admit().is_ok());
+    }
+}
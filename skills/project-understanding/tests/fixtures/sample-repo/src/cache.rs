@@ -0,0 +1,782 @@
+//! Read-through cache in front of the user repository.
+//!
+//! Invalidation is driven by domain events: the repository decorator applies
+//! each write's event as it stores it, and `spawn_invalidator` applies every
+//! event on the bus, so no caller has to remember to invalidate. Event-derived
+//! tags drop dependent entries such as cached user lists. The cache is still
+//! per instance, so entries expire after a short TTL to bound staleness when
+//! several instances share a database. `Config::user_cache` sets the TTL and
+//! how many users are kept; `/metrics` counts hits and misses. Users are held
+//! in a `moka` cache, which admits and evicts by how often and how recently
+//! each ID is read, so hot users stay cached while a scan of cold IDs passes
+//! through, and eviction never stalls lookups behind a global lock.
+//!
+//! Lookups of a missing user are remembered, per tenant, for a few seconds
+//! so repeated probes for nonexistent IDs skip storage until that ID is
+//! created. A cached user is only served to lookups for its own tenant. An
+//! optional `bloom::ExistenceFilter` rules out most other absent IDs and
+//! emails before they reach storage at all.
+//!
+//! Route response caches follow a `CachePolicy` from `Config::response_cache`:
+//! past its fresh TTL an entry is still served for the route's staleness
+//! budget while one background refresh replaces it.
+
+use async_trait::async_trait;
+use axum::{extract::State, response::Json};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+use crate::auth::Admin;
+use crate::bloom::ExistenceFilter;
+use crate::events::DomainEvent;
+use crate::health::{HealthCheck, HealthState};
+use crate::repository::{
+    Change, LastSeen, OutboxRecord, OutboxStore, PoolStats, RepositoryError, UserQuery,
+    UserRepository,
+};
+use crate::search::{SearchPage, SearchQuery};
+use crate::sharded::ShardedCounter;
+use crate::substate::Shared;
+use crate::{ApiResponse, AppState, User};
+
+/// How long an entry is served before it is refetched, unless configured
+const ENTRY_TTL: Duration = Duration::from_secs(60);
+
+/// How long a confirmed-missing ID is answered without asking storage, unless configured
+const NEGATIVE_TTL: Duration = Duration::from_secs(5);
+
+/// Tag on every cached value derived from more than one user
+pub const USERS_TAG: &str = "users";
+
+/// `Config::response_cache` key of the user list route
+pub const LIST_USERS_ROUTE: &str = "GET /api/v1/users";
+
+/// Freshness rules for one route's cached responses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CachePolicy {
+    /// Seconds a response is served as-is
+    pub fresh_secs: u64,
+    /// Further seconds it is served while being refreshed in the background
+    pub stale_secs: u64,
+}
+
+impl Default for CachePolicy {
+    fn default() -> Self {
+        CachePolicy {
+            fresh_secs: ENTRY_TTL.as_secs(),
+            stale_secs: 30,
+        }
+    }
+}
+
+/// Size and lifetimes of the user lookup cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UserCacheConfig {
+    /// Most users kept, and most missing IDs remembered
+    pub capacity: usize,
+    /// Seconds a cached user is served before it is refetched
+    pub ttl_secs: u64,
+    /// Seconds a confirmed-missing ID is answered without asking storage
+    pub negative_ttl_secs: u64,
+}
+
+impl Default for UserCacheConfig {
+    fn default() -> Self {
+        UserCacheConfig {
+            capacity: 10_000,
+            ttl_secs: ENTRY_TTL.as_secs(),
+            negative_ttl_secs: NEGATIVE_TTL.as_secs(),
+        }
+    }
+}
+
+/// Per-route response cache policies
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResponseCacheConfig {
+    /// Policies by route, e.g. `GET /api/v1/users`; others use the default
+    pub routes: HashMap<String, CachePolicy>,
+}
+
+impl ResponseCacheConfig {
+    /// Policy for a route
+    pub fn policy(&self, route: &str) -> CachePolicy {
+        self.routes.get(route).copied().unwrap_or_default()
+    }
+}
+
+/// Result of a stale-while-revalidate lookup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lookup<V> {
+    /// Within the fresh TTL
+    Fresh(V),
+    /// Past the fresh TTL but within the staleness budget; `refresh` is true
+    /// for the one caller that should fetch a replacement
+    Stale { value: V, refresh: bool },
+    /// Absent or too old to serve
+    Miss,
+}
+
+/// Bounded, TTL-limited cache of users by ID
+pub struct UserCache {
+    entries: moka::sync::Cache<Uuid, User>,
+    missing: RwLock<HashMap<(String, Uuid), Instant>>,
+    capacity: usize,
+    negative_ttl: Duration,
+    stats: CacheStats,
+}
+
+/// Lookup counters of `CachedUserRepository::get`
+#[derive(Debug, Default)]
+struct CacheStats {
+    hits: ShardedCounter,
+    negative_hits: ShardedCounter,
+    filtered: ShardedCounter,
+    misses: ShardedCounter,
+}
+
+/// Lookup counts since startup
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CacheStatsSnapshot {
+    /// Users served from the cache
+    pub hits: u64,
+    /// Missing users answered from the negative cache
+    pub negative_hits: u64,
+    /// Missing users answered from the existence filter
+    pub filtered: u64,
+    /// Lookups that went to storage
+    pub misses: u64,
+}
+
+impl UserCache {
+    /// Create a cache holding at most `capacity` users, with the default TTLs
+    pub fn new(capacity: usize) -> Self {
+        Self::from_config(&UserCacheConfig {
+            capacity,
+            ..UserCacheConfig::default()
+        })
+    }
+
+    /// Create a cache sized and timed by `config`
+    pub fn from_config(config: &UserCacheConfig) -> Self {
+        let entries = moka::sync::Cache::builder()
+            .max_capacity(config.capacity as u64)
+            .time_to_live(Duration::from_secs(config.ttl_secs))
+            .build();
+        Self {
+            entries,
+            missing: RwLock::new(HashMap::new()),
+            capacity: config.capacity,
+            negative_ttl: Duration::from_secs(config.negative_ttl_secs),
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// Whether storage recently confirmed `tenant` has no user `id`
+    pub fn is_known_missing(&self, tenant: &str, id: Uuid) -> bool {
+        let missing = self.missing.read().expect("user cache lock poisoned");
+        let key = (tenant.to_string(), id);
+        missing.get(&key).map_or(false, |checked_at| checked_at.elapsed() < self.negative_ttl)
+    }
+
+    /// Remember that `tenant` has no user `id`; skipped when full of live entries
+    pub fn mark_missing(&self, tenant: &str, id: Uuid) {
+        let mut missing = self.missing.write().expect("user cache lock poisoned");
+        if missing.len() >= self.capacity {
+            missing.retain(|_, checked_at| checked_at.elapsed() < self.negative_ttl);
+            if missing.len() >= self.capacity {
+                return;
+            }
+        }
+        missing.insert((tenant.to_string(), id), Instant::now());
+    }
+
+    /// Start the lookup counts again from zero
+    pub fn reset_stats(&self) {
+        self.stats.hits.reset();
+        self.stats.negative_hits.reset();
+        self.stats.filtered.reset();
+        self.stats.misses.reset();
+    }
+
+    /// Lookup counts since startup, or since `reset_stats`
+    pub fn stats(&self) -> CacheStatsSnapshot {
+        CacheStatsSnapshot {
+            hits: self.stats.hits.sum(),
+            negative_hits: self.stats.negative_hits.sum(),
+            filtered: self.stats.filtered.sum(),
+            misses: self.stats.misses.sum(),
+        }
+    }
+
+    /// Cached user, if present and not expired
+    pub fn get(&self, id: Uuid) -> Option<User> {
+        self.entries.get(&id)
+    }
+
+    /// Store a user; when full, the least valuable entry is evicted
+    pub fn put(&self, user: User) {
+        self.entries.insert(user.id, user);
+    }
+
+    /// Drop a user from the cache, including negative entries for it
+    pub fn invalidate(&self, id: Uuid) {
+        self.entries.invalidate(&id);
+        let mut missing = self.missing.write().expect("user cache lock poisoned");
+        missing.retain(|(_, missing_id), _| *missing_id != id);
+    }
+
+    /// Drop every user from the cache
+    pub fn clear(&self) {
+        self.entries.invalidate_all();
+        self.missing.write().expect("user cache lock poisoned").clear();
+    }
+
+    /// Number of cached users, once pending evictions have run
+    pub fn len(&self) -> usize {
+        self.entries.run_pending_tasks();
+        self.entries.entry_count() as usize
+    }
+
+    /// Whether the cache holds no entries
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Health check of the user cache, which lives in process memory and so only
+/// falls short when configured to hold nothing, sending every lookup to storage
+pub struct UserCacheCheck {
+    cache: Arc<UserCache>,
+}
+
+impl UserCacheCheck {
+    /// Create a check for the given cache
+    pub fn new(cache: Arc<UserCache>) -> Self {
+        Self { cache }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for UserCacheCheck {
+    fn name(&self) -> &'static str {
+        "user_cache"
+    }
+
+    async fn check(&self) -> Result<HealthState, String> {
+        match self.cache.capacity {
+            0 => Ok(HealthState::Degraded),
+            _ => Ok(HealthState::Operational),
+        }
+    }
+}
+
+impl Default for UserCache {
+    fn default() -> Self {
+        Self::from_config(&UserCacheConfig::default())
+    }
+}
+
+/// Bounded, TTL-limited map of values by key, each carrying invalidation tags
+pub struct TaggedCache<V> {
+    entries: RwLock<HashMap<String, (V, Instant, Vec<String>)>>,
+    tags: RwLock<HashMap<String, HashSet<String>>>,
+    refreshing: RwLock<HashSet<String>>,
+    capacity: usize,
+    fresh: Duration,
+    stale: Duration,
+}
+
+impl<V: Clone> TaggedCache<V> {
+    /// Create a cache holding at most `capacity` values under the default policy
+    pub fn new(capacity: usize) -> Self {
+        Self::with_policy(capacity, CachePolicy::default())
+    }
+
+    /// Create a cache holding at most `capacity` values under `policy`
+    pub fn with_policy(capacity: usize, policy: CachePolicy) -> Self {
+        Self {
+            entries: RwLock::new(HashMap::new()),
+            tags: RwLock::new(HashMap::new()),
+            refreshing: RwLock::new(HashSet::new()),
+            capacity,
+            fresh: Duration::from_secs(policy.fresh_secs),
+            stale: Duration::from_secs(policy.stale_secs),
+        }
+    }
+
+    /// Cached value, if present and fresh
+    pub fn get(&self, key: &str) -> Option<V> {
+        match self.lookup(key) {
+            Lookup::Fresh(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Cached value and whether it is fresh, claiming the refresh of a stale one
+    pub fn lookup(&self, key: &str) -> Lookup<V> {
+        let entries = self.entries.read().expect("tagged cache lock poisoned");
+        let Some((value, cached_at, _)) = entries.get(key) else {
+            return Lookup::Miss;
+        };
+        let age = cached_at.elapsed();
+        if age < self.fresh {
+            return Lookup::Fresh(value.clone());
+        }
+        if age >= self.fresh + self.stale {
+            return Lookup::Miss;
+        }
+        let mut refreshing = self.refreshing.write().expect("tagged cache lock poisoned");
+        Lookup::Stale {
+            value: value.clone(),
+            refresh: refreshing.insert(key.to_string()),
+        }
+    }
+
+    /// Store a value fetched after a claiming `lookup`, unless invalidated meanwhile
+    pub fn finish_refresh(&self, key: &str, value: Option<V>, tags: &[&str]) {
+        let claimed = self.refreshing.write().expect("tagged cache lock poisoned").remove(key);
+        if let (true, Some(value)) = (claimed, value) {
+            self.put(key, value, tags);
+        }
+    }
+
+    /// Store a value under `key`, dropped whenever any of `tags` is invalidated
+    pub fn put(&self, key: &str, value: V, tags: &[&str]) {
+        if self.len() >= self.capacity {
+            // Values are cheap to rebuild; start over rather than track recency
+            self.clear();
+        }
+        self.invalidate(key);
+        let tags: Vec<String> = tags.iter().map(|tag| tag.to_string()).collect();
+        let mut index = self.tags.write().expect("tagged cache lock poisoned");
+        for tag in &tags {
+            index.entry(tag.clone()).or_default().insert(key.to_string());
+        }
+        let mut entries = self.entries.write().expect("tagged cache lock poisoned");
+        entries.insert(key.to_string(), (value, Instant::now(), tags));
+    }
+
+    /// Drop one value, abandoning any refresh of it
+    pub fn invalidate(&self, key: &str) {
+        self.refreshing.write().expect("tagged cache lock poisoned").remove(key);
+        let mut index = self.tags.write().expect("tagged cache lock poisoned");
+        let mut entries = self.entries.write().expect("tagged cache lock poisoned");
+        if let Some((_, _, tags)) = entries.remove(key) {
+            for tag in tags {
+                if let Some(keys) = index.get_mut(&tag) {
+                    keys.remove(key);
+                }
+            }
+        }
+    }
+
+    /// Drop every value carrying `tag`, returning how many were dropped
+    pub fn invalidate_tag(&self, tag: &str) -> usize {
+        let keys = self
+            .tags
+            .write()
+            .expect("tagged cache lock poisoned")
+            .remove(tag)
+            .unwrap_or_default();
+        for key in &keys {
+            self.invalidate(key);
+        }
+        keys.len()
+    }
+
+    /// Drop everything
+    pub fn clear(&self) {
+        self.refreshing.write().expect("tagged cache lock poisoned").clear();
+        let mut index = self.tags.write().expect("tagged cache lock poisoned");
+        let mut entries = self.entries.write().expect("tagged cache lock poisoned");
+        index.clear();
+        entries.clear();
+    }
+
+    /// Number of cached values, including expired ones not yet evicted
+    pub fn len(&self) -> usize {
+        self.entries.read().expect("tagged cache lock poisoned").len()
+    }
+
+    /// Whether the cache holds no values
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<V: Clone> Default for TaggedCache<V> {
+    fn default() -> Self {
+        Self::new(1_000)
+    }
+}
+
+/// Tags whose values an event makes stale
+pub fn event_tags(_event: &DomainEvent) -> Vec<&'static str> {
+    // Any user change can alter any list; finer tags go here as lists gain filters
+    vec![USERS_TAG]
+}
+
+/// Drop every cached value an event makes stale
+pub fn invalidate_for(users: &UserCache, lists: &TaggedCache<Vec<User>>, event: &DomainEvent) {
+    users.invalidate(event.user.id);
+    for tag in event_tags(event) {
+        lists.invalidate_tag(tag);
+    }
+}
+
+/// User cache lookup counts
+pub async fn cache_stats(
+    State(cache): State<Shared<UserCache>>,
+    _admin: Admin,
+) -> Json<ApiResponse<CacheStatsSnapshot>> {
+    Json(ApiResponse::success(cache.stats()))
+}
+
+/// Apply every event on the bus to the caches
+pub fn spawn_invalidator(state: Arc<AppState>) -> tokio::task::JoinHandle<()> {
+    let mut events = state.events.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => invalidate_for(&state.user_cache, &state.list_cache, &event),
+                Err(RecvError::Lagged(skipped)) => {
+                    // Any of the missed events could have touched any entry
+                    tracing::warn!("cache invalidator lagged, skipped {} events", skipped);
+                    state.user_cache.clear();
+                    state.list_cache.clear();
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Repository decorator serving `get` from a `UserCache`.
+///
+/// Every write invalidates through its event, or as if it had one, so both
+/// the user and any cached list containing it are dropped.
+pub struct CachedUserRepository {
+    inner: Arc<dyn UserRepository>,
+    cache: Arc<UserCache>,
+    lists: Arc<TaggedCache<Vec<User>>>,
+    filter: Option<Arc<ExistenceFilter>>,
+}
+
+impl CachedUserRepository {
+    /// Wrap `inner`, reading and writing through `cache` and dropping stale `lists`
+    pub fn new(
+        inner: Arc<dyn UserRepository>,
+        cache: Arc<UserCache>,
+        lists: Arc<TaggedCache<Vec<User>>>,
+    ) -> Self {
+        Self {
+            inner,
+            cache,
+            lists,
+            filter: None,
+        }
+    }
+
+    /// Answer lookups the filter rules out without asking `inner`, and keep it current
+    pub fn with_existence_filter(mut self, filter: Arc<ExistenceFilter>) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Record a user about to be written, before anyone can look it up
+    fn remember(&self, user: &User) {
+        if let Some(filter) = &self.filter {
+            filter.add(user);
+        }
+    }
+
+    /// Invalidate for a write with no event of its own
+    fn invalidate_user(&self, id: Uuid) {
+        self.cache.invalidate(id);
+        self.lists.invalidate_tag(USERS_TAG);
+    }
+}
+
+#[async_trait]
+impl OutboxStore for CachedUserRepository {
+    async fn pending_events(&self, limit: usize) -> Result<Vec<OutboxRecord>, RepositoryError> {
+        self.inner.pending_events(limit).await
+    }
+
+    async fn mark_delivered(&self, ids: &[Uuid]) -> Result<(), RepositoryError> {
+        self.inner.mark_delivered(ids).await
+    }
+}
+
+#[async_trait]
+impl UserRepository for CachedUserRepository {
+    async fn list(&self, tenant: &str, query: &UserQuery) -> Result<Vec<User>, RepositoryError> {
+        self.inner.list(tenant, query).await
+    }
+
+    async fn get(&self, tenant: &str, id: Uuid) -> Result<Option<User>, RepositoryError> {
+        let stats = &self.cache.stats;
+        if let Some(user) = self.cache.get(id) {
+            stats.hits.increment();
+            // IDs are unique across tenants, so another tenant's user means none here
+            return Ok(Some(user).filter(|user| user.tenant_id == tenant));
+        }
+        if self.cache.is_known_missing(tenant, id) {
+            stats.negative_hits.increment();
+            return Ok(None);
+        }
+        if self.filter.as_ref().map_or(false, |filter| filter.rules_out_id(id)) {
+            stats.filtered.increment();
+            return Ok(None);
+        }
+        stats.misses.increment();
+        let user = self.inner.get(tenant, id).await?;
+        match &user {
+            Some(user) => self.cache.put(user.clone()),
+            None => self.cache.mark_missing(tenant, id),
+        }
+        Ok(user)
+    }
+
+    async fn find_by_username(
+        &self,
+        tenant: &str,
+        username: &str,
+    ) -> Result<Option<User>, RepositoryError> {
+        self.inner.find_by_username(tenant, username).await
+    }
+
+    async fn find_by_email(
+        &self,
+        tenant: &str,
+        email: &str,
+    ) -> Result<Option<User>, RepositoryError> {
+        if self.filter.as_ref().map_or(false, |filter| filter.rules_out_email(email)) {
+            self.cache.stats.filtered.increment();
+            return Ok(None);
+        }
+        self.inner.find_by_email(tenant, email).await
+    }
+
+    async fn search(
+        &self,
+        tenant: &str,
+        query: &SearchQuery,
+    ) -> Result<SearchPage, RepositoryError> {
+        self.inner.search(tenant, query).await
+    }
+
+    async fn tenants(&self) -> Result<Vec<String>, RepositoryError> {
+        self.inner.tenants().await
+    }
+
+    async fn insert(&self, user: User) -> Result<User, RepositoryError> {
+        self.remember(&user);
+        let id = user.id;
+        let inserted = self.inner.insert(user).await;
+        self.invalidate_user(id);
+        inserted
+    }
+
+    async fn insert_with_event(
+        &self,
+        user: User,
+        event: DomainEvent,
+    ) -> Result<User, RepositoryError> {
+        self.remember(&user);
+        let inserted = self.inner.insert_with_event(user, event.clone()).await;
+        invalidate_for(&self.cache, &self.lists, &event);
+        inserted
+    }
+
+    async fn update(&self, user: User) -> Result<Option<User>, RepositoryError> {
+        self.remember(&user);
+        let id = user.id;
+        let updated = self.inner.update(user).await;
+        self.invalidate_user(id);
+        updated
+    }
+
+    async fn update_with_event(
+        &self,
+        user: User,
+        event: DomainEvent,
+    ) -> Result<Option<User>, RepositoryError> {
+        self.remember(&user);
+        let updated = self.inner.update_with_event(user, event.clone()).await;
+        invalidate_for(&self.cache, &self.lists, &event);
+        updated
+    }
+
+    async fn commit(&self, changes: &[Change]) -> Result<bool, RepositoryError> {
+        for change in changes {
+            if let Change::Insert(user) | Change::Update(user) = change {
+                self.remember(user);
+            }
+        }
+        let committed = self.inner.commit(changes).await;
+        for change in changes {
+            match change {
+                Change::Insert(user) | Change::Update(user) => self.invalidate_user(user.id),
+                Change::Enqueue(event) => invalidate_for(&self.cache, &self.lists, event),
+            }
+        }
+        committed
+    }
+
+    async fn delete(&self, tenant: &str, id: Uuid) -> Result<bool, RepositoryError> {
+        let deleted = self.inner.delete(tenant, id).await;
+        self.invalidate_user(id);
+        deleted
+    }
+
+    async fn record_last_seen(&self, seen: &[LastSeen]) -> Result<(), RepositoryError> {
+        self.inner.record_last_seen(seen).await
+    }
+
+    async fn last_seen(
+        &self,
+        tenant: &str,
+        id: Uuid,
+    ) -> Result<Option<chrono::DateTime<chrono::Utc>>, RepositoryError> {
+        self.inner.last_seen(tenant, id).await
+    }
+
+    async fn migrate(&self) -> Result<Vec<&'static str>, RepositoryError> {
+        self.inner.migrate().await
+    }
+
+    async fn schema_version(&self) -> Result<Option<String>, RepositoryError> {
+        self.inner.schema_version().await
+    }
+
+    fn has_schema(&self) -> bool {
+        self.inner.has_schema()
+    }
+
+    async fn ping(&self) -> Result<(), RepositoryError> {
+        self.inner.ping().await
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        self.inner.pool_stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventKind;
+    use crate::repository::InMemoryUserRepository;
+    use crate::tenancy::DEFAULT_TENANT;
+
+    fn repository(
+        cache: Arc<UserCache>,
+        lists: Arc<TaggedCache<Vec<User>>>,
+    ) -> CachedUserRepository {
+        CachedUserRepository::new(Arc::new(InMemoryUserRepository::new()), cache, lists)
+    }
+
+    #[tokio::test]
+    async fn test_update_invalidates_cached_user() {
+        let cache = Arc::new(UserCache::new(10));
+        let repository = repository(cache.clone(), Arc::default());
+        let mut user = User::new("alice".to_string(), "alice@example.com".to_string());
+        repository.insert(user.clone()).await.unwrap();
+        repository.get(DEFAULT_TENANT, user.id).await.unwrap();
+        assert_eq!(cache.len(), 1);
+
+        user.deactivate();
+        repository.update(user.clone()).await.unwrap();
+        assert!(cache.get(user.id).is_none());
+        assert!(!repository.get(DEFAULT_TENANT, user.id).await.unwrap().unwrap().is_active);
+    }
+
+    #[tokio::test]
+    async fn test_write_events_invalidate_tagged_lists() {
+        let lists = Arc::new(TaggedCache::new(10));
+        let repository = repository(Arc::new(UserCache::new(10)), lists.clone());
+        lists.put("users?include_deleted=false", Vec::new(), &[USERS_TAG]);
+        lists.put("unrelated", Vec::new(), &["other"]);
+
+        let user = User::new("alice".to_string(), "alice@example.com".to_string());
+        let event = DomainEvent::user(EventKind::UserCreated, &user);
+        repository.insert_with_event(user, event).await.unwrap();
+        assert!(lists.get("users?include_deleted=false").is_none());
+        assert!(lists.get("unrelated").is_some());
+        assert_eq!(lists.invalidate_tag("other"), 1);
+        assert!(lists.is_empty());
+    }
+
+    #[test]
+    fn test_stale_values_are_served_while_one_caller_refreshes() {
+        let policy = CachePolicy {
+            fresh_secs: 0,
+            stale_secs: 60,
+        };
+        let cache = TaggedCache::with_policy(10, policy);
+        cache.put("key", 1, &[USERS_TAG]);
+        assert_eq!(cache.lookup("key"), Lookup::Stale { value: 1, refresh: true });
+        assert_eq!(cache.lookup("key"), Lookup::Stale { value: 1, refresh: false });
+        assert!(cache.get("key").is_none());
+
+        // A write during the refresh wins over the refreshed value
+        cache.invalidate_tag(USERS_TAG);
+        cache.finish_refresh("key", Some(2), &[USERS_TAG]);
+        assert_eq!(cache.lookup("key"), Lookup::Miss);
+    }
+
+    #[tokio::test]
+    async fn test_missing_ids_are_negatively_cached_until_created() {
+        let cache = Arc::new(UserCache::new(10));
+        let repository = repository(cache.clone(), Arc::default());
+        let user = User::new("alice".to_string(), "alice@example.com".to_string());
+        assert!(repository.get(DEFAULT_TENANT, user.id).await.unwrap().is_none());
+        assert!(repository.get(DEFAULT_TENANT, user.id).await.unwrap().is_none());
+        assert_eq!(cache.stats().negative_hits, 1);
+        assert_eq!(cache.stats().misses, 1);
+
+        repository.insert(user.clone()).await.unwrap();
+        assert!(repository.get(DEFAULT_TENANT, user.id).await.unwrap().is_some());
+        // Cached for its own tenant only
+        assert!(repository.get("acme", user.id).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_configured_ttl_expires_entries() {
+        let cache = UserCache::from_config(&UserCacheConfig {
+            ttl_secs: 0,
+            negative_ttl_secs: 0,
+            ..UserCacheConfig::default()
+        });
+        let user = User::new("alice".to_string(), "alice@example.com".to_string());
+        cache.put(user.clone());
+        cache.mark_missing(DEFAULT_TENANT, Uuid::nil());
+        assert!(cache.get(user.id).is_none());
+        assert!(!cache.is_known_missing(DEFAULT_TENANT, Uuid::nil()));
+    }
+
+    #[test]
+    fn test_put_respects_capacity_and_keeps_hot_users() {
+        let cache = UserCache::new(2);
+        let hot = User::new("hot".to_string(), "hot@example.com".to_string());
+        cache.put(hot.clone());
+        for _ in 0..10 {
+            assert!(cache.get(hot.id).is_some());
+        }
+        for name in ["a", "b", "c", "d"] {
+            cache.put(User::new(name.to_string(), format!("{}@example.com", name)));
+            // Settle admission and eviction before the next cold user arrives
+            cache.len();
+        }
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(hot.id).is_some());
+    }
+}
@@ -0,0 +1,118 @@
+//! Cancellation when clients disconnect.
+//!
+//! When a client goes away mid-request, the server drops the request's
+//! future, and with it every future the handler was awaiting, repository
+//! queries included. `detect_abandonment` notices that drop: it cancels the
+//! request's `Cancellation` token, so work that outlives the handler's own
+//! future can stop too, and counts the request and the time already spent
+//! on it at `/metrics`. Server-sent event streams end on the token. Work
+//! deliberately detached from the request, such as cache refreshes and
+//! async operations, doesn't watch it and keeps running.
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, MatchedPath, State},
+    http::{request::Parts, Request},
+    middleware::Next,
+    response::Response,
+};
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use crate::AppState;
+
+/// Cancelled once the client stops waiting for the response
+#[derive(Debug, Clone, Default)]
+pub struct Cancellation(pub CancellationToken);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Cancellation {
+    type Rejection = Infallible;
+
+    /// The request's token, or one never cancelled outside `detect_abandonment`
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts.extensions.get::<Cancellation>().cloned().unwrap_or_default())
+    }
+}
+
+/// Cancels the token if dropped before the response is ready
+struct Watch {
+    state: Arc<AppState>,
+    token: CancellationToken,
+    route: String,
+    started: Instant,
+    done: bool,
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        self.token.cancel();
+        let seconds = self.started.elapsed().as_secs_f64();
+        tracing::debug!("client abandoned request to {} after {:.3}s", self.route, seconds);
+        self.state.metrics.observe_abandoned(&self.route, seconds);
+    }
+}
+
+/// Middleware giving each request a token cancelled if its client leaves
+pub async fn detect_abandonment<B>(
+    State(state): State<Arc<AppState>>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let token = CancellationToken::new();
+    req.extensions_mut().insert(Cancellation(token.clone()));
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let mut watch = Watch {
+        state,
+        token,
+        route,
+        started: Instant::now(),
+        done: false,
+    };
+    let response = next.run(req).await;
+    watch.done = true;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get, Router};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_dropped_request_cancels_its_token() {
+        let state = AppState::new(crate::Config::default());
+        let (tokens, mut seen) = tokio::sync::mpsc::unbounded_channel();
+        let app = Router::new()
+            .route(
+                "/slow",
+                get(move |Cancellation(token): Cancellation| async move {
+                    tokens.send(token).unwrap();
+                    std::future::pending::<()>().await
+                }),
+            )
+            .layer(axum::middleware::from_fn_with_state(state.clone(), detect_abandonment))
+            .with_state(state.clone());
+
+        let request = app.oneshot(Request::get("/slow").body(Body::empty()).unwrap());
+        let abandoned = tokio::time::timeout(Duration::from_millis(50), request).await;
+        assert!(abandoned.is_err());
+        assert!(seen.recv().await.unwrap().is_cancelled());
+
+        let metrics = crate::metrics::metrics(State(state)).await;
+        let body = hyper::body::to_bytes(metrics.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("http_requests_abandoned_total{route=\"/slow\"} 1"));
+    }
+}
@@ -0,0 +1,77 @@
+//! Panic-catching middleware.
+//!
+//! A panicking handler would otherwise drop the connection without a
+//! response. This layer turns the panic into a logged 500 whose body names
+//! the request ID, so a client report can be matched to the log line.
+
+use axum::{
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use futures::FutureExt;
+use std::any::Any;
+use std::panic::AssertUnwindSafe;
+
+use crate::error::ErrorCode;
+use crate::request_id::RequestId;
+use crate::ApiResponse;
+
+/// Text of a panic payload, when it has one
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("non-string panic payload")
+}
+
+/// Middleware answering handler panics with a 500 `ApiResponse::failure`
+pub async fn catch_panic<B>(req: Request<B>, next: Next<B>) -> Response {
+    let request_id = req
+        .extensions()
+        .get::<RequestId>()
+        .cloned()
+        .unwrap_or_else(|| RequestId("unknown".to_string()));
+    match AssertUnwindSafe(next.run(req)).catch_unwind().await {
+        Ok(response) => response,
+        Err(payload) => {
+            tracing::error!(
+                request_id = %request_id,
+                "handler panicked: {}",
+                panic_message(payload.as_ref())
+            );
+            let message = format!("internal error (request {})", request_id);
+            let body = ApiResponse::<()>::failure(ErrorCode::INTERNAL, message);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(body)).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request_id::{propagate_request_id, REQUEST_ID_HEADER};
+    use axum::{body::Body, routing::get, Router};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_panic_becomes_500_with_request_id() {
+        let app: Router = Router::new()
+            .route("/boom", get(|| async { panic!("boom") }))
+            .layer(axum::middleware::from_fn(catch_panic))
+            .layer(axum::middleware::from_fn(propagate_request_id));
+        let req = Request::get("/boom")
+            .header(REQUEST_ID_HEADER, "req-42")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "req-42");
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["success"], false);
+        assert_eq!(body["error"], "internal error (request req-42)");
+    }
+}
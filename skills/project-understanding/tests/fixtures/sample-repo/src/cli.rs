@@ -0,0 +1,151 @@
+//! Command-line parsing.
+//!
+//! With no arguments the binary serves the API, as it always has. The other
+//! subcommands load the same configuration and exit: `migrate` brings the
+//! database schema up to date, `create-admin` creates the initial admin user,
+//! prompting for whatever its options leave out, and `check-config` reports
+//! whether the configuration is valid without starting anything.
+
+use std::io::{self, BufRead, Write};
+
+/// Usage text printed on a parse error
+pub const USAGE: &str = "usage: app [serve [--mock] | smoke --base-url URL [--admin-token TOKEN] \
+    | migrate | create-admin [--username NAME] [--email EMAIL] | check-config]";
+
+/// What the binary was asked to do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Serve the API, or canned responses from the OpenAPI spec with `--mock`
+    Serve {
+        /// Serve the mock instead of the real API
+        mock: bool,
+    },
+    /// Run the golden-path smoke test against a deployment
+    Smoke {
+        /// Root URL of the deployment
+        base_url: String,
+        /// Admin token enabling the admin-only checks
+        admin_token: Option<String>,
+    },
+    /// Apply pending database migrations
+    Migrate,
+    /// Create the initial admin user
+    CreateAdmin {
+        /// Username, prompted for when absent
+        username: Option<String>,
+        /// Email, prompted for when absent
+        email: Option<String>,
+    },
+    /// Load and validate the configuration, then exit
+    CheckConfig,
+}
+
+/// Values of the `--name VALUE` options in `names`, refusing any other argument
+fn options<const N: usize>(
+    mut args: impl Iterator<Item = String>,
+    names: [&str; N],
+) -> Result<[Option<String>; N], String> {
+    let mut values = std::array::from_fn(|_| None);
+    while let Some(arg) = args.next() {
+        let Some(index) = names.iter().position(|name| *name == arg) else {
+            return Err(format!("unknown option {}\n{}", arg, USAGE));
+        };
+        let value = args.next().ok_or_else(|| format!("{} needs a value", arg))?;
+        values[index] = Some(value);
+    }
+    Ok(values)
+}
+
+/// Ask for a value on the terminal, returning the trimmed line entered
+pub fn prompt(label: &str) -> io::Result<String> {
+    print!("{}: ", label);
+    io::stdout().flush()?;
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    Ok(line.trim().to_string())
+}
+
+impl Command {
+    /// Parse arguments, excluding the program name
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut args = args.into_iter();
+        let command = match args.next().as_deref() {
+            None => return Ok(Command::Serve { mock: false }),
+            Some("serve") => {
+                let mut mock = false;
+                for arg in args {
+                    match arg.as_str() {
+                        "--mock" => mock = true,
+                        other => return Err(format!("unknown option {}\n{}", other, USAGE)),
+                    }
+                }
+                Command::Serve { mock }
+            }
+            Some("smoke") => {
+                let [base_url, admin_token] = options(args, ["--base-url", "--admin-token"])?;
+                Command::Smoke {
+                    base_url: base_url.ok_or_else(|| format!("--base-url is required\n{}", USAGE))?,
+                    admin_token,
+                }
+            }
+            Some("migrate") => {
+                options(args, [])?;
+                Command::Migrate
+            }
+            Some("create-admin") => {
+                let [username, email] = options(args, ["--username", "--email"])?;
+                Command::CreateAdmin { username, email }
+            }
+            Some("check-config") => {
+                options(args, [])?;
+                Command::CheckConfig
+            }
+            Some(other) => return Err(format!("unknown command {}\n{}", other, USAGE)),
+        };
+        Ok(command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        Command::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_serve() {
+        assert_eq!(parse(&[]), Ok(Command::Serve { mock: false }));
+        assert_eq!(parse(&["serve", "--mock"]), Ok(Command::Serve { mock: true }));
+        assert!(parse(&["serve", "--nope"]).is_err());
+        assert!(parse(&["frobnicate"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_smoke() {
+        assert_eq!(
+            parse(&["smoke", "--base-url", "https://api.example.com"]),
+            Ok(Command::Smoke {
+                base_url: "https://api.example.com".to_string(),
+                admin_token: None,
+            })
+        );
+        assert!(parse(&["smoke"]).is_err());
+        assert!(parse(&["smoke", "--base-url"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_maintenance_commands() {
+        assert_eq!(parse(&["migrate"]), Ok(Command::Migrate));
+        assert_eq!(parse(&["check-config"]), Ok(Command::CheckConfig));
+        assert_eq!(
+            parse(&["create-admin", "--email", "root@example.com"]),
+            Ok(Command::CreateAdmin {
+                username: None,
+                email: Some("root@example.com".to_string()),
+            })
+        );
+        assert!(parse(&["migrate", "--force"]).is_err());
+    }
+}
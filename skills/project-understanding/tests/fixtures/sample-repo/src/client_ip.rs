@@ -0,0 +1,236 @@
+//! The address of the client behind any trusted proxies.
+//!
+//! Behind a load balancer every connection comes from the balancer, and the
+//! client's address arrives in `Forwarded` or `X-Forwarded-For` instead:
+//! headers anyone can send. `Config::trusted_proxies` lists the addresses and
+//! CIDR ranges of the proxies in front of us, and the headers are only read
+//! when the peer is one of them. Hops are read right to left, each added by
+//! the proxy before it; the first hop that isn't a trusted proxy is the
+//! client, so a client writing the header itself can't choose its address.
+//! `Forwarded` wins when both are sent, and a hop that isn't an address, such
+//! as `unknown`, ends the walk at the proxy that reported it. With no trusted
+//! proxies the client is the peer, whatever the headers say.
+//!
+//! `ClientIp` resolves the client of a request. The rate limiter, the access
+//! log, and audit entries all go through it, so they agree on who called.
+
+use async_trait::async_trait;
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{request::Parts, Extensions, HeaderMap},
+};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::AppState;
+
+/// An address or CIDR range of proxies whose forwarding headers we believe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TrustedProxy {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl TrustedProxy {
+    /// Whether `ip` is in the range
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for TrustedProxy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("{:?} is not an IP address or CIDR range", value);
+        let (address, prefix) = match value.trim().split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value.trim(), None),
+        };
+        let network = IpAddr::from_str(address).map_err(|_| invalid())?.to_canonical();
+        let bits = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().ok().filter(|&prefix| prefix <= bits),
+            None => Some(bits),
+        };
+        Ok(TrustedProxy {
+            network,
+            prefix: prefix.ok_or_else(invalid)?,
+        })
+    }
+}
+
+impl TryFrom<String> for TrustedProxy {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<TrustedProxy> for String {
+    fn from(proxy: TrustedProxy) -> Self {
+        format!("{}/{}", proxy.network, proxy.prefix)
+    }
+}
+
+/// Address of one `Forwarded` or `X-Forwarded-For` hop, without its port
+fn hop_address(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim().trim_matches('"');
+    if let Some(bracketed) = hop.strip_prefix('[') {
+        return bracketed.split(']').next()?.parse().ok();
+    }
+    hop.parse::<IpAddr>()
+        .or_else(|_| hop.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .ok()
+}
+
+/// Forwarded hops, client first, or `None` for a hop that isn't an address
+fn forwarded_hops(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let values = |name: &str| -> Vec<String> {
+        let raw = headers.get_all(name).iter().filter_map(|value| value.to_str().ok());
+        raw.flat_map(|value| value.split(',')).map(str::to_string).collect()
+    };
+    let forwarded = values("forwarded");
+    if !forwarded.is_empty() {
+        return forwarded
+            .iter()
+            .map(|element| {
+                let pairs = element.split(';').filter_map(|pair| pair.split_once('='));
+                let mut pairs = pairs.map(|(name, value)| (name.trim(), value));
+                let (_, value) = pairs.find(|(name, _)| name.eq_ignore_ascii_case("for"))?;
+                hop_address(value)
+            })
+            .collect();
+    }
+    values("x-forwarded-for").iter().map(|hop| hop_address(hop)).collect()
+}
+
+/// The client of a request from `peer`, trusting only `trusted` proxies
+pub fn resolve(headers: &HeaderMap, peer: IpAddr, trusted: &[TrustedProxy]) -> IpAddr {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|proxy| proxy.contains(ip));
+    let mut client = peer.to_canonical();
+    for hop in forwarded_hops(headers).into_iter().rev() {
+        if !is_trusted(client) {
+            break;
+        }
+        match hop {
+            Some(hop) => client = hop.to_canonical(),
+            None => break,
+        }
+    }
+    client
+}
+
+/// Extractor for the client's address, when the connection's peer is known
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl ClientIp {
+    /// The client of a request, as `trusted` proxies report it
+    pub fn of(headers: &HeaderMap, extensions: &Extensions, trusted: &[TrustedProxy]) -> Self {
+        let peer = extensions.get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| addr.ip());
+        ClientIp(peer.map(|peer| resolve(headers, peer, trusted)))
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let trusted = &state.config().trusted_proxies;
+        Ok(ClientIp::of(&parts.headers, &parts.extensions, trusted))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestServer;
+    use crate::Config;
+    use axum::http::HeaderValue;
+    use reqwest::{Method, StatusCode};
+
+    fn proxies(ranges: &[&str]) -> Vec<TrustedProxy> {
+        ranges.iter().map(|range| range.parse().unwrap()).collect()
+    }
+
+    fn headers(name: &'static str, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn test_ranges_match_addresses_inside_them() {
+        let [lan, host, v6] = [
+            "10.0.0.0/8".parse::<TrustedProxy>().unwrap(),
+            "192.0.2.1".parse().unwrap(),
+            "2001:db8::/32".parse().unwrap(),
+        ];
+        assert!(lan.contains("10.200.3.4".parse().unwrap()));
+        assert!(lan.contains("::ffff:10.0.0.1".parse().unwrap()));
+        assert!(!lan.contains("11.0.0.1".parse().unwrap()));
+        assert!(host.contains("192.0.2.1".parse().unwrap()));
+        assert!(!host.contains("192.0.2.2".parse().unwrap()));
+        assert!(v6.contains("2001:db8:1::7".parse().unwrap()));
+        assert!("0.0.0.0/0".parse::<TrustedProxy>().unwrap().contains("8.8.8.8".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<TrustedProxy>().is_err());
+        assert_eq!(String::from(lan), "10.0.0.0/8");
+    }
+
+    #[test]
+    fn test_forwarded_headers_only_believed_from_trusted_proxies() {
+        let trusted = proxies(&["10.0.0.0/8"]);
+        let proxy: IpAddr = "10.0.0.2".parse().unwrap();
+        let spoofed = headers("x-forwarded-for", "6.6.6.6, 203.0.113.7, 10.0.0.9");
+        assert_eq!(resolve(&spoofed, proxy, &trusted).to_string(), "203.0.113.7");
+        let stranger: IpAddr = "198.51.100.1".parse().unwrap();
+        assert_eq!(resolve(&spoofed, stranger, &trusted), stranger);
+        assert_eq!(resolve(&spoofed, proxy, &[]), proxy);
+
+        let hops = "for=\"[2001:db8::1]:4711\";proto=https, for=10.0.0.9";
+        let forwarded = headers("forwarded", hops);
+        assert_eq!(resolve(&forwarded, proxy, &trusted).to_string(), "2001:db8::1");
+        let unknown = headers("forwarded", "for=203.0.113.7, for=unknown");
+        assert_eq!(resolve(&unknown, proxy, &trusted), proxy);
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_attributed_to_the_client_behind_trusted_proxies() {
+        let config = Config {
+            trusted_proxies: proxies(&["127.0.0.1"]),
+            ..Config::default()
+        };
+        let server = TestServer::start(config).await;
+        let body = serde_json::json!({ "username": "alice", "email": "alice@example.com" });
+        let request = server.request(Method::POST, "/api/v1/users").json(&body);
+        let response = request.header("x-forwarded-for", "203.0.113.7").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let (status, _) = server.create_user("/api/v1/users", "bob").await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let entries = server.audit("user").await;
+        let mut ips: Vec<_> = entries.iter().map(|entry| entry["client_ip"].clone()).collect();
+        ips.sort_by_key(|ip| ip.to_string());
+        assert_eq!(ips, vec!["127.0.0.1", "203.0.113.7"]);
+    }
+}
@@ -0,0 +1,240 @@
+//! Negotiated response compression.
+//!
+//! `compress_responses` gzip- or brotli-encodes JSON responses of at least
+//! `CompressionConfig::min_bytes` for clients that accept it, preferring
+//! brotli when both are equally acceptable. Bodies are compressed in memory,
+//! so it only touches JSON; event streams, metrics, and responses a handler
+//! already encoded pass through as they are. Layered outside
+//! `negotiate_problems`, which rebuilds error bodies, so problem details are
+//! compressed like any other JSON. HEAD requests are encoded like the GET
+//! they stand for, so they get the same `Content-Encoding`, `Vary`, and
+//! `Content-Length`; axum drops the body afterwards.
+
+use axum::{
+    body::{boxed, Body, Full},
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use crate::AppState;
+
+/// Response compression settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CompressionConfig {
+    /// Whether responses are compressed at all
+    pub enabled: bool,
+    /// From 1 (fastest) to 9 (smallest), for gzip and brotli alike
+    pub level: u32,
+    /// Smallest body worth compressing
+    pub min_bytes: usize,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig {
+            enabled: true,
+            level: 6,
+            min_bytes: 1024,
+        }
+    }
+}
+
+/// A content coding we can produce
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Gzip,
+    Brotli,
+}
+
+impl Encoding {
+    /// Preferred coding acceptable to the client, if any
+    fn negotiate(headers: &HeaderMap) -> Option<Self> {
+        let mut best: Option<(Self, f32)> = None;
+        let accepted = headers
+            .get_all(header::ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','));
+        for entry in accepted {
+            let mut params = entry.split(';');
+            let encoding = match params.next().unwrap_or_default().trim() {
+                name if name.eq_ignore_ascii_case("br") => Self::Brotli,
+                name if name.eq_ignore_ascii_case("gzip") => Self::Gzip,
+                _ => continue,
+            };
+            let quality = params
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|quality| quality.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            let better = match best {
+                None => quality > 0.0,
+                Some((_, current)) => {
+                    quality > current || (quality == current && encoding == Self::Brotli)
+                }
+            };
+            if better {
+                best = Some((encoding, quality));
+            }
+        }
+        best.map(|(encoding, _)| encoding)
+    }
+
+    fn header_value(self) -> HeaderValue {
+        match self {
+            Self::Gzip => HeaderValue::from_static("gzip"),
+            Self::Brotli => HeaderValue::from_static("br"),
+        }
+    }
+
+    fn encode(self, data: &[u8], level: u32) -> io::Result<Vec<u8>> {
+        let level = level.clamp(1, 9);
+        match self {
+            Self::Gzip => {
+                let compression = flate2::Compression::new(level);
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), compression);
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Self::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, level, 22);
+                encoder.write_all(data)?;
+                Ok(encoder.into_inner())
+            }
+        }
+    }
+}
+
+/// Whether a response carries JSON, including problem details
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|mime| {
+            let mime = mime.trim();
+            mime.eq_ignore_ascii_case("application/json") || mime.ends_with("+json")
+        })
+}
+
+/// Middleware compressing JSON responses for clients that accept it
+pub async fn compress_responses<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let current = state.config();
+    let config = &current.compression;
+    if !config.enabled {
+        return next.run(req).await;
+    }
+    let encoding = Encoding::negotiate(req.headers());
+    let mut response = next.run(req).await;
+    let eligible = is_json(response.headers())
+        && !response.headers().contains_key(header::CONTENT_ENCODING)
+        && !matches!(response.status(), StatusCode::NO_CONTENT | StatusCode::NOT_MODIFIED);
+    if !eligible {
+        return response;
+    }
+    response
+        .headers_mut()
+        .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    let Some(encoding) = encoding else {
+        return response;
+    };
+    let declared = response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if declared.is_some_and(|length| length < config.min_bytes) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            tracing::error!("failed to buffer response for compression: {}", err);
+            return Response::from_parts(parts, boxed(Body::empty()));
+        }
+    };
+    if bytes.len() < config.min_bytes {
+        return Response::from_parts(parts, boxed(Full::from(bytes)));
+    }
+    match encoding.encode(&bytes, config.level) {
+        Ok(compressed) => {
+            parts.headers.insert(header::CONTENT_ENCODING, encoding.header_value());
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Response::from_parts(parts, boxed(Full::from(compressed)))
+        }
+        Err(err) => {
+            tracing::warn!("failed to compress response, sending it as is: {}", err);
+            Response::from_parts(parts, boxed(Full::from(bytes)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{response::Json, routing::get, Router};
+    use std::io::Read;
+    use tower::ServiceExt;
+
+    fn accepting(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_ENCODING, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_negotiation_honors_quality() {
+        assert_eq!(Encoding::negotiate(&accepting("gzip, deflate, br")), Some(Encoding::Brotli));
+        assert_eq!(Encoding::negotiate(&accepting("br;q=0.5, gzip")), Some(Encoding::Gzip));
+        assert_eq!(Encoding::negotiate(&accepting("gzip;q=0, identity")), None);
+        assert_eq!(Encoding::negotiate(&HeaderMap::new()), None);
+    }
+
+    #[tokio::test]
+    async fn test_large_json_is_gzipped_and_small_json_is_not() {
+        let state = AppState::new(crate::Config::default());
+        let app = Router::new()
+            .route("/large", get(|| async { Json(vec!["user"; 1000]) }))
+            .route("/small", get(|| async { Json("user") }))
+            .layer(axum::middleware::from_fn_with_state(state.clone(), compress_responses))
+            .with_state(state);
+        let fetch = |path: &str| {
+            let request = Request::get(path)
+                .header(header::ACCEPT_ENCODING, "gzip")
+                .body(Body::empty())
+                .unwrap();
+            app.clone().oneshot(request)
+        };
+
+        let response = fetch("/large").await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(response.headers()[header::VARY], "accept-encoding");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let mut json = String::new();
+        flate2::read::GzDecoder::new(&body[..]).read_to_string(&mut json).unwrap();
+        assert_eq!(serde_json::from_str::<Vec<String>>(&json).unwrap().len(), 1000);
+
+        let response = fetch("/small").await.unwrap();
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+
+        let request = Request::head("/large")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(response.headers()[header::VARY], "accept-encoding");
+        assert!(hyper::body::to_bytes(response.into_body()).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,146 @@
+//! Checks on a loaded configuration.
+//!
+//! `reload::load` validates every configuration it builds, at startup and on
+//! reload, after secret references are resolved. Every problem is collected
+//! before any is reported, so a bad deployment is fixed in one pass rather
+//! than one restart per field, and startup stops there instead of panicking
+//! later on the first value setup trips over. Messages name the field but
+//! never repeat a secret's value.
+
+use std::net::SocketAddr;
+
+use crate::blobs::BlobBackend;
+use crate::tenancy::TenantSource;
+use crate::validation::Validate;
+use crate::{feature_flags, scheduler, Config};
+
+/// Schemes `database_url` may use besides `memory:` and `sqlite:`
+const DATABASE_SCHEMES: [&str; 2] = ["postgres", "postgresql"];
+
+/// A problem with a port field, if it has one
+fn check_port(field: &str, port: u16, api_port: u16) -> Option<String> {
+    if port == 0 {
+        Some(format!("{} must be between 1 and 65535", field))
+    } else if port == api_port {
+        Some(format!("{} must differ from port {}", field, api_port))
+    } else {
+        None
+    }
+}
+
+impl Validate for Config {
+    fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if self.host.trim().is_empty() {
+            errors.push("host is required".to_string());
+        } else if format!("{}:{}", self.host, self.port).parse::<SocketAddr>().is_err() {
+            errors.push(format!("host {:?} is not an IP address", self.host));
+        }
+        if self.port == 0 {
+            errors.push("port must be between 1 and 65535".to_string());
+        }
+
+        let database_url = self.database_url.expose();
+        let sqlite = database_url.starts_with("sqlite:");
+        if !database_url.starts_with("memory:") && !sqlite {
+            match reqwest::Url::parse(database_url) {
+                Ok(url) if DATABASE_SCHEMES.contains(&url.scheme()) => {}
+                Ok(url) => errors.push(format!(
+                    "database_url must be a postgres:// URL, sqlite:, or memory:, not {}://",
+                    url.scheme()
+                )),
+                Err(err) => errors.push(format!("database_url is not a valid URL: {}", err)),
+            }
+        }
+        if sqlite && !self.read_replicas.urls.is_empty() {
+            errors.push("read_replicas need a Postgres database_url".to_string());
+        }
+        for (index, url) in self.read_replicas.urls.iter().enumerate() {
+            let field = format!("read_replicas.urls[{}]", index);
+            match reqwest::Url::parse(url.expose()) {
+                Ok(url) if DATABASE_SCHEMES.contains(&url.scheme()) => {}
+                Ok(url) => errors.push(format!(
+                    "{} must be a postgres:// URL, not {}://",
+                    field,
+                    url.scheme()
+                )),
+                Err(err) => errors.push(format!("{} is not a valid URL: {}", field, err)),
+            }
+        }
+        if self.log_level.parse::<tracing::level_filters::LevelFilter>().is_err() {
+            errors.push(format!("log_level {:?} is not a log level", self.log_level));
+        }
+
+        let tls_redirect = self.tls.as_ref().and_then(|tls| tls.redirect_http_port);
+        let acme_redirect = self.acme.as_ref().and_then(|acme| acme.redirect_http_port);
+        let ports = [
+            ("grpc_port", self.grpc_port),
+            ("tls.redirect_http_port", tls_redirect),
+            ("acme.redirect_http_port", acme_redirect),
+        ];
+        for (field, port) in ports {
+            errors.extend(port.and_then(|port| check_port(field, port, self.port)));
+        }
+
+        if self.tls.is_some() && self.acme.is_some() {
+            errors.push("tls and acme are mutually exclusive; set one".to_string());
+        }
+        if self.acme.as_ref().is_some_and(|acme| acme.domains.is_empty()) {
+            errors.push("acme.domains must name at least one domain".to_string());
+        }
+        errors.extend(self.feature_flags.keys().filter_map(|name| feature_flags::check_name(name)));
+        errors.extend(self.schedule.tasks.keys().filter_map(|name| scheduler::check_name(name)));
+        let by_subdomain = self.tenancy.sources.contains(&TenantSource::Subdomain);
+        if by_subdomain && self.tenancy.base_domain.is_none() {
+            errors.push("tenancy.sources names subdomain without a base_domain".to_string());
+        }
+        if let Some(url) = &self.rate_limit.per_client.redis_url {
+            let field = "rate_limit.per_client.redis_url";
+            match reqwest::Url::parse(url.expose()) {
+                _ if cfg!(not(feature = "redis")) => {
+                    errors.push(format!("{} needs the redis feature", field))
+                }
+                Ok(url) if ["redis", "rediss"].contains(&url.scheme()) => {}
+                Ok(url) => errors.push(format!(
+                    "{} must be a redis:// URL, not {}://",
+                    field,
+                    url.scheme()
+                )),
+                Err(err) => errors.push(format!("{} is not a valid URL: {}", field, err)),
+            }
+        }
+        if let BlobBackend::S3 { endpoint, .. } = &self.blobs.backend {
+            match reqwest::Url::parse(endpoint) {
+                Ok(url) if url.has_host() => {}
+                Ok(_) => errors.push("blobs.backend.endpoint must name a host".to_string()),
+                Err(err) => {
+                    errors.push(format!("blobs.backend.endpoint is not a valid URL: {}", err))
+                }
+            }
+        }
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::Secret;
+
+    #[test]
+    fn test_every_invalid_field_is_reported() {
+        assert!(Config::default().validate().is_empty());
+
+        let config = Config {
+            host: String::new(),
+            port: 0,
+            database_url: Secret::new("mysql://root:pw@db/app".to_string()),
+            log_level: "loud".to_string(),
+            grpc_port: Some(0),
+            ..Config::default()
+        };
+        let errors = config.validate();
+        assert_eq!(errors.len(), 5, "{:?}", errors);
+        assert!(errors.iter().all(|error| !error.contains("root:pw")));
+    }
+}
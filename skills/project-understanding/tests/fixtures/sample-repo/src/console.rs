@@ -0,0 +1,121 @@
+//! Interactive API console for debug builds.
+//!
+//! Mounted only when `Config::debug` is on. `GET /console` serves a single
+//! HTML page listing the routes in `handlers::ROUTES`; picking one fills in
+//! a request form, an optional bearer token is attached to every request,
+//! and the raw status, headers, and body of the response are shown.
+
+use axum::{
+    response::{Html, Json},
+    routing::get,
+    Router,
+};
+use std::sync::Arc;
+
+use crate::handlers::{RouteInfo, ROUTES};
+use crate::{ApiResponse, AppState};
+
+/// Console routes
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/console", get(console_page))
+        .route("/console/routes", get(list_routes))
+}
+
+/// Serve the console page
+async fn console_page() -> Html<&'static str> {
+    Html(PAGE)
+}
+
+/// The route introspection table
+async fn list_routes() -> Json<ApiResponse<Vec<RouteInfo>>> {
+    Json(ApiResponse::success(ROUTES.to_vec()))
+}
+
+/// Self-contained page; the token is kept in session storage only
+const PAGE: &str = r#"<!doctype html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>API console</title>
+<style>
+  body { font-family: sans-serif; display: flex; margin: 0; height: 100vh; }
+  nav { width: 340px; overflow-y: auto; border-right: 1px solid #ccc; padding: 8px; }
+  nav div { cursor: pointer; font-family: monospace; padding: 2px 4px; }
+  nav div:hover { background: #eef; }
+  main { flex: 1; padding: 8px; display: flex; flex-direction: column; gap: 6px; }
+  textarea, pre { font-family: monospace; width: 100%; box-sizing: border-box; }
+  pre { flex: 1; overflow: auto; background: #f6f6f6; margin: 0; padding: 6px; }
+  .admin { color: #a00; }
+</style>
+</head>
+<body>
+<nav id="routes"></nav>
+<main>
+  <label>Bearer token <input id="token" type="password" size="40"></label>
+  <div>
+    <select id="method">
+      <option>GET</option><option>POST</option><option>PUT</option><option>DELETE</option>
+    </select>
+    <input id="path" size="60" value="/healthz">
+    <button id="send">Send</button>
+  </div>
+  <textarea id="body" rows="8" placeholder="JSON body"></textarea>
+  <pre id="response"></pre>
+</main>
+<script>
+  const $ = (id) => document.getElementById(id);
+  $("token").value = sessionStorage.getItem("console-token") || "";
+  $("token").onchange = () => sessionStorage.setItem("console-token", $("token").value);
+
+  fetch("/console/routes").then((r) => r.json()).then(({ data }) => {
+    for (const route of data) {
+      const item = document.createElement("div");
+      item.textContent = route.method.padEnd(7) + route.path;
+      if (route.admin) item.className = "admin";
+      item.onclick = () => { $("method").value = route.method; $("path").value = route.path; };
+      $("routes").appendChild(item);
+    }
+  });
+
+  $("send").onclick = async () => {
+    const headers = {};
+    if ($("token").value) headers["authorization"] = "Bearer " + $("token").value;
+    const init = { method: $("method").value, headers };
+    if ($("body").value.trim() && init.method !== "GET") {
+      headers["content-type"] = "application/json";
+      init.body = $("body").value;
+    }
+    try {
+      const response = await fetch($("path").value, init);
+      let text = response.status + " " + response.statusText + "\n";
+      response.headers.forEach((value, name) => { text += name + ": " + value + "\n"; });
+      $("response").textContent = text + "\n" + await response.text();
+    } catch (err) {
+      $("response").textContent = String(err);
+    }
+  };
+</script>
+</body>
+</html>
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::openapi::ApiDoc;
+    use utoipa::OpenApi;
+
+    #[test]
+    fn test_route_table_covers_documented_paths() {
+        let spec = ApiDoc::openapi();
+        for path in spec.paths.paths.keys() {
+            let pattern = path.replace("{id}", ":id");
+            assert!(
+                ROUTES.iter().any(|route| route.path == pattern),
+                "{} missing from ROUTES",
+                pattern
+            );
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! What every layer needs to know about a request, worked out once.
+//!
+//! `attach_context` runs just inside request ID propagation and reads the
+//! request head a single time: who is calling, for which tenant, in which
+//! language, from where, and until when the caller will wait. It stores the
+//! result as a `RequestContext` extension, and the access log, tracing span,
+//! timeouts, tenant fairness, debug targets, localization, query tags, and
+//! audit entries all read it from there instead of reparsing headers, so
+//! they can't disagree about a request. Handlers take it as an extractor.
+//!
+//! The principal starts out as the admin, for a valid admin token, or
+//! anonymous. On signed routes `signature` refines it to the partner once
+//! the body has been verified, so layers outside that one, such as the
+//! access log, see the caller as it presented itself.
+
+use async_trait::async_trait;
+use axum::{
+    extract::{FromRequestParts, MatchedPath, State},
+    http::{request::Parts, Request},
+    middleware::Next,
+    response::Response,
+};
+use std::convert::Infallible;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::auth::Admin;
+use crate::client_ip::ClientIp;
+use crate::localization::Locale;
+use crate::request_id::RequestId;
+use crate::{tenancy, timeout, AppState};
+
+/// Header naming the tenant a request is made for
+pub const TENANT_HEADER: &str = "x-tenant-id";
+
+/// Who a request is made by
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Principal {
+    /// No credentials, or none we accept
+    Anonymous,
+    /// Caller presenting the admin token
+    Admin,
+    /// Partner whose secret signed the request
+    Partner(String),
+}
+
+impl fmt::Display for Principal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Principal::Anonymous => write!(f, "anonymous"),
+            Principal::Admin => write!(f, "admin"),
+            Principal::Partner(partner) => write!(f, "partner:{}", partner),
+        }
+    }
+}
+
+/// Attributes of one request shared by every layer handling it
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    /// Who is calling
+    pub principal: Principal,
+    /// Tenant the request names by `Config::tenancy.sources`, if any
+    pub tenant: Option<String>,
+    /// Locale negotiated from `Accept-Language`, if the header was sent
+    pub locale: Option<Locale>,
+    /// Request ID
+    pub request_id: String,
+    /// Client address, behind any of `Config::trusted_proxies`
+    pub client_ip: Option<IpAddr>,
+    /// When the context was built, as the request arrived
+    pub started: Instant,
+    /// When the request times out: its route's timeout or its caller's
+    /// deadline, whichever comes first
+    pub deadline: Option<Instant>,
+}
+
+impl RequestContext {
+    /// Work the context out from the request head
+    pub async fn from_parts(parts: &mut Parts, state: &Arc<AppState>) -> Self {
+        let config = state.config();
+        let principal = match Admin::from_request_parts(parts, state).await {
+            Ok(Admin) => Principal::Admin,
+            Err(_) => Principal::Anonymous,
+        };
+        let RequestId(request_id) = match RequestId::from_request_parts(parts, state).await {
+            Ok(request_id) => request_id,
+            Err(never) => match never {},
+        };
+        let tenant = tenancy::resolve(&config.tenancy, &parts.headers);
+        let route = parts.extensions.get::<MatchedPath>().map(MatchedPath::as_str);
+        let started = Instant::now();
+        let limit = timeout::limit(&config.timeouts, route, &parts.headers, chrono::Utc::now());
+        RequestContext {
+            principal,
+            tenant,
+            locale: Locale::negotiate(&parts.headers),
+            request_id,
+            client_ip: ClientIp::of(&parts.headers, &parts.extensions, &config.trusted_proxies).0,
+            started,
+            deadline: limit.map(|limit| started + limit),
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for RequestContext {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        match parts.extensions.get::<RequestContext>() {
+            Some(context) => Ok(context.clone()),
+            None => Ok(RequestContext::from_parts(parts, state).await),
+        }
+    }
+}
+
+/// Middleware building the request's context
+pub async fn attach_context<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let (mut parts, body) = req.into_parts();
+    let context = RequestContext::from_parts(&mut parts, &state).await;
+    parts.extensions.insert(context);
+    next.run(Request::from_parts(parts, body)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_context_is_read_from_the_request_head_once() {
+        let state = AppState::new(crate::Config::default());
+        let (mut parts, ()) = Request::builder()
+            .header(TENANT_HEADER, "acme")
+            .header("accept-language", "fr")
+            .header(timeout::GRPC_TIMEOUT_HEADER, "2S")
+            .body(())
+            .unwrap()
+            .into_parts();
+        parts.extensions.insert(RequestId("req-1".to_string()));
+
+        let context = RequestContext::from_parts(&mut parts, &state).await;
+        assert_eq!(context.principal, Principal::Anonymous);
+        assert_eq!(context.tenant.as_deref(), Some("acme"));
+        assert_eq!(context.locale, Some(Locale::Fr));
+        assert_eq!(context.request_id, "req-1");
+        assert_eq!(context.deadline, Some(context.started + Duration::from_secs(2)));
+
+        let signed = RequestContext {
+            principal: Principal::Partner("acme".to_string()),
+            ..context
+        };
+        parts.extensions.insert(signed);
+        let extracted = RequestContext::from_request_parts(&mut parts, &state).await.unwrap();
+        assert_eq!(extracted.principal.to_string(), "partner:acme");
+    }
+}
@@ -0,0 +1,228 @@
+//! Cross-origin resource sharing.
+//!
+//! Requests from an origin listed in `Config::cors.allowed_origins` get the
+//! `Access-Control-*` headers browsers need to hand a response to a script
+//! on that origin; `*` allows any origin. Preflights from allowed origins are
+//! answered here without reaching a handler, and preflights from other
+//! origins get a 403. Requests without an `Origin` header, and every request
+//! while no origins are configured, pass through untouched.
+//!
+//! `*` with `allow_credentials` would let any site make authenticated calls,
+//! so that combination is refused at startup and credentials stay off.
+
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::error::AppError;
+use crate::AppState;
+
+/// CORS settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CorsConfig {
+    /// Origins allowed to call the API, e.g. `https://app.example.com`, or `*`
+    pub allowed_origins: Vec<String>,
+    /// Methods allowed in cross-origin requests
+    pub allowed_methods: Vec<String>,
+    /// Request headers allowed in cross-origin requests
+    pub allowed_headers: Vec<String>,
+    /// Whether browsers may send cookies and `Authorization` cross-origin
+    pub allow_credentials: bool,
+    /// How long browsers may cache a preflight result
+    pub max_age_secs: u64,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            allowed_origins: Vec::new(),
+            allowed_methods: ["GET", "POST", "PUT", "PATCH", "DELETE"]
+                .map(String::from)
+                .to_vec(),
+            allowed_headers: ["authorization", "content-type", "x-request-id"]
+                .map(String::from)
+                .to_vec(),
+            allow_credentials: false,
+            max_age_secs: 600,
+        }
+    }
+}
+
+/// `Config::cors` with header values prepared
+#[derive(Debug)]
+pub struct Cors {
+    any_origin: bool,
+    origins: Vec<String>,
+    allow_methods: Option<HeaderValue>,
+    allow_headers: Option<HeaderValue>,
+    allow_credentials: bool,
+    max_age: HeaderValue,
+}
+
+/// Comma-separated list header, or `None` for an empty or invalid list
+fn list(values: &[String], what: &str) -> Option<HeaderValue> {
+    if values.is_empty() {
+        return None;
+    }
+    match HeaderValue::from_str(&values.join(", ")) {
+        Ok(value) => Some(value),
+        Err(_) => {
+            tracing::warn!("ignoring invalid CORS {} {:?}", what, values);
+            None
+        }
+    }
+}
+
+impl Cors {
+    /// Prepare the configured policy
+    pub fn new(config: &CorsConfig) -> Self {
+        let any_origin = config.allowed_origins.iter().any(|origin| origin == "*");
+        let mut allow_credentials = config.allow_credentials;
+        if any_origin && allow_credentials {
+            tracing::warn!("CORS credentials can't be allowed for any origin; leaving them off");
+            allow_credentials = false;
+        }
+        Self {
+            any_origin,
+            origins: config
+                .allowed_origins
+                .iter()
+                .map(|origin| origin.trim_end_matches('/').to_ascii_lowercase())
+                .collect(),
+            allow_methods: list(&config.allowed_methods, "methods"),
+            allow_headers: list(&config.allowed_headers, "headers"),
+            allow_credentials,
+            max_age: HeaderValue::from(config.max_age_secs),
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        !self.origins.is_empty()
+    }
+
+    fn allows(&self, origin: &str) -> bool {
+        self.any_origin || self.origins.iter().any(|allowed| allowed.eq_ignore_ascii_case(origin))
+    }
+
+    /// Headers granting `origin` access to a response
+    fn grant(&self, origin: &HeaderValue, headers: &mut HeaderMap) {
+        if self.any_origin {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+        } else {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+            headers.append(header::VARY, HeaderValue::from_static("origin"));
+        }
+        if self.allow_credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+    }
+
+    /// Answer to a preflight from an allowed origin
+    fn preflight(&self, origin: &HeaderValue) -> Response {
+        let mut response = StatusCode::NO_CONTENT.into_response();
+        let headers = response.headers_mut();
+        self.grant(origin, headers);
+        if let Some(methods) = &self.allow_methods {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, methods.clone());
+        }
+        if let Some(allowed) = &self.allow_headers {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, allowed.clone());
+        }
+        headers.insert(header::ACCESS_CONTROL_MAX_AGE, self.max_age.clone());
+        response
+    }
+}
+
+/// Middleware applying `Config::cors`
+pub async fn apply_cors<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let cors = state.cors.load_full();
+    let origin = req.headers().get(header::ORIGIN).cloned();
+    let Some(origin) = origin.filter(|_| cors.is_enabled()) else {
+        return next.run(req).await;
+    };
+    let allowed = origin.to_str().is_ok_and(|value| cors.allows(value));
+    let is_preflight = req.method() == Method::OPTIONS
+        && req.headers().contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+    if is_preflight {
+        if !allowed {
+            return AppError::Forbidden("origin not allowed".to_string()).into_response();
+        }
+        return cors.preflight(&origin);
+    }
+
+    let mut response = next.run(req).await;
+    if allowed {
+        cors.grant(&origin, response.headers_mut());
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get, Router};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_allowed_origin_gets_preflight_and_response_headers() {
+        let mut config = crate::Config::default();
+        config.cors.allowed_origins = vec!["https://app.example.com".to_string()];
+        config.cors.allow_credentials = true;
+        let state = AppState::new(config);
+        let app = Router::new()
+            .route("/users", get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn_with_state(state.clone(), apply_cors))
+            .with_state(state);
+        let send = |method: Method, origin: &str| {
+            let request = Request::builder()
+                .method(method)
+                .uri("/users")
+                .header(header::ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+                .body(Body::empty())
+                .unwrap();
+            app.clone().oneshot(request)
+        };
+
+        let preflight = send(Method::OPTIONS, "https://app.example.com").await.unwrap();
+        assert_eq!(preflight.status(), StatusCode::NO_CONTENT);
+        let headers = preflight.headers();
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "https://app.example.com");
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "600");
+
+        let response = send(Method::GET, "https://app.example.com").await.unwrap();
+        assert_eq!(response.headers()[header::VARY], "origin");
+        let response = send(Method::GET, "https://evil.example.com").await.unwrap();
+        assert!(!response.headers().contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        let preflight = send(Method::OPTIONS, "https://evil.example.com").await.unwrap();
+        assert_eq!(preflight.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn test_any_origin_never_allows_credentials() {
+        let config = CorsConfig {
+            allowed_origins: vec!["*".to_string()],
+            allow_credentials: true,
+            ..CorsConfig::default()
+        };
+        let cors = Cors::new(&config);
+        let mut headers = HeaderMap::new();
+        cors.grant(&HeaderValue::from_static("https://anywhere.example"), &mut headers);
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_CREDENTIALS));
+    }
+}
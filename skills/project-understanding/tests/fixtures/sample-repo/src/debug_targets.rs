@@ -0,0 +1,316 @@
+//! Temporary, targeted log verbosity.
+//!
+//! Admins can raise the log level for one route, user, or tenant for a
+//! limited time. The targeting middleware scopes the raised level to the
+//! matching request's task, and `TargetedFilter` lets events through when
+//! they pass either the base level or the request's raised level.
+//!
+//! Users are identified by the `X-User-Id` request header, and tenants are
+//! the ones `tenancy` resolves.
+
+use axum::{
+    extract::{MatchedPath, State},
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::{Json, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+use tracing::{level_filters::LevelFilter, subscriber::Interest, Metadata};
+use tracing_subscriber::layer::{Context, Filter};
+use uuid::Uuid;
+
+use crate::auth::Admin;
+use crate::context::RequestContext;
+use crate::validation::ApiPath;
+use crate::{ApiResponse, AppState};
+
+/// Longest a target may stay active
+const MAX_TTL_SECONDS: i64 = 3600;
+
+tokio::task_local! {
+    static REQUEST_LEVEL: LevelFilter;
+}
+
+/// What a debug target applies to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetSelector {
+    /// A route pattern such as `/api/users/:id`
+    Route(String),
+    /// A user ID
+    User(String),
+    /// A tenant ID
+    Tenant(String),
+}
+
+/// Raised verbosity for a target
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DebugLevel {
+    /// `DEBUG` and above
+    Debug,
+    /// Everything
+    Trace,
+}
+
+impl From<DebugLevel> for LevelFilter {
+    fn from(level: DebugLevel) -> Self {
+        match level {
+            DebugLevel::Debug => LevelFilter::DEBUG,
+            DebugLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+/// An active debug target
+#[derive(Debug, Clone, Serialize)]
+pub struct DebugTarget {
+    /// Unique identifier
+    pub id: Uuid,
+    /// What the target applies to
+    pub selector: TargetSelector,
+    /// Raised level
+    pub level: DebugLevel,
+    /// When the target stops applying
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Request body for adding a target
+#[derive(Debug, Deserialize)]
+pub struct NewDebugTarget {
+    /// What the target applies to
+    pub selector: TargetSelector,
+    /// Raised level
+    pub level: DebugLevel,
+    /// Lifetime in seconds, capped at one hour
+    pub ttl_seconds: i64,
+}
+
+/// Identity of a request for matching against targets
+#[derive(Debug, Default)]
+pub struct TargetContext<'a> {
+    /// Matched route pattern
+    pub route: Option<&'a str>,
+    /// Calling user
+    pub user: Option<&'a str>,
+    /// Calling tenant
+    pub tenant: Option<&'a str>,
+}
+
+/// Registry of active debug targets
+#[derive(Default)]
+pub struct DebugTargets {
+    targets: RwLock<Vec<DebugTarget>>,
+}
+
+impl DebugTargets {
+    /// Add a target, clamping its lifetime
+    pub fn add(&self, new: NewDebugTarget) -> DebugTarget {
+        let ttl = new.ttl_seconds.clamp(1, MAX_TTL_SECONDS);
+        let target = DebugTarget {
+            id: Uuid::new_v4(),
+            selector: new.selector,
+            level: new.level,
+            expires_at: chrono::Utc::now() + chrono::Duration::seconds(ttl),
+        };
+        let mut targets = self.targets.write().expect("debug targets lock poisoned");
+        targets.retain(|target| target.expires_at > chrono::Utc::now());
+        targets.push(target.clone());
+        target
+    }
+
+    /// Remove a target, returning whether it existed
+    pub fn remove(&self, id: Uuid) -> bool {
+        let mut targets = self.targets.write().expect("debug targets lock poisoned");
+        let before = targets.len();
+        targets.retain(|target| target.id != id);
+        targets.len() != before
+    }
+
+    /// Drop expired targets, returning how many were dropped
+    pub fn purge_expired(&self) -> usize {
+        let now = chrono::Utc::now();
+        let mut targets = self.targets.write().expect("debug targets lock poisoned");
+        let before = targets.len();
+        targets.retain(|target| target.expires_at > now);
+        before - targets.len()
+    }
+
+    /// Targets that have not expired
+    pub fn active(&self) -> Vec<DebugTarget> {
+        let now = chrono::Utc::now();
+        let targets = self.targets.read().expect("debug targets lock poisoned");
+        targets
+            .iter()
+            .filter(|target| target.expires_at > now)
+            .cloned()
+            .collect()
+    }
+
+    /// Most verbose level of any live target matching the request
+    pub fn level_for(&self, context: &TargetContext<'_>) -> Option<LevelFilter> {
+        let now = chrono::Utc::now();
+        let targets = self.targets.read().expect("debug targets lock poisoned");
+        targets
+            .iter()
+            .filter(|target| target.expires_at > now)
+            .filter(|target| match &target.selector {
+                TargetSelector::Route(route) => context.route == Some(route.as_str()),
+                TargetSelector::User(user) => context.user == Some(user.as_str()),
+                TargetSelector::Tenant(tenant) => context.tenant == Some(tenant.as_str()),
+            })
+            .map(|target| LevelFilter::from(target.level))
+            .max()
+    }
+}
+
+/// Level every event passes without a target, shared by all filters
+static BASE_LEVEL: RwLock<LevelFilter> = RwLock::new(LevelFilter::INFO);
+
+fn base_level() -> LevelFilter {
+    *BASE_LEVEL.read().expect("log level lock poisoned")
+}
+
+/// Change the base level of every `TargetedFilter`, e.g. on config reload
+pub fn set_base_level(level: LevelFilter) {
+    *BASE_LEVEL.write().expect("log level lock poisoned") = level;
+    // Callsites cached as always enabled must be asked again
+    tracing::callsite::rebuild_interest_cache();
+}
+
+/// Per-layer filter combining the base level with request-scoped overrides
+pub struct TargetedFilter(());
+
+impl TargetedFilter {
+    /// Create a filter with the given base level
+    pub fn new(base: LevelFilter) -> Self {
+        set_base_level(base);
+        Self(())
+    }
+}
+
+impl<S> Filter<S> for TargetedFilter {
+    fn enabled(&self, meta: &Metadata<'_>, _cx: &Context<'_, S>) -> bool {
+        if *meta.level() <= base_level() {
+            return true;
+        }
+        REQUEST_LEVEL
+            .try_with(|level| *meta.level() <= *level)
+            .unwrap_or(false)
+    }
+
+    fn callsite_enabled(&self, meta: &'static Metadata<'static>) -> Interest {
+        if *meta.level() <= base_level() {
+            Interest::always()
+        } else {
+            // Re-evaluate per event, since a request may have raised the level
+            Interest::sometimes()
+        }
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Some(LevelFilter::TRACE)
+    }
+}
+
+/// Middleware scoping a raised log level to matching requests
+pub async fn apply_debug_targets<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let context = req.extensions().get::<RequestContext>();
+    let level = state.debug_targets.level_for(&TargetContext {
+        route: req.extensions().get::<MatchedPath>().map(MatchedPath::as_str),
+        user: req.headers().get("x-user-id").and_then(|value| value.to_str().ok()),
+        tenant: context.and_then(|context| context.tenant.as_deref()),
+    });
+    match level {
+        Some(level) => REQUEST_LEVEL.scope(level, next.run(req)).await,
+        None => next.run(req).await,
+    }
+}
+
+/// Add a debug target
+pub async fn create_debug_target(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    Json(new): Json<NewDebugTarget>,
+) -> (StatusCode, Json<ApiResponse<DebugTarget>>) {
+    let target = state.debug_targets.add(new);
+    tracing::info!(
+        "debug logging raised to {:?} for {:?} until {}",
+        target.level,
+        target.selector,
+        target.expires_at
+    );
+    (StatusCode::CREATED, Json(ApiResponse::success(target)))
+}
+
+/// List active debug targets
+pub async fn list_debug_targets(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+) -> Json<ApiResponse<Vec<DebugTarget>>> {
+    Json(ApiResponse::success(state.debug_targets.active()))
+}
+
+/// Remove a debug target before it expires
+pub async fn delete_debug_target(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    ApiPath(id): ApiPath<Uuid>,
+) -> StatusCode {
+    if state.debug_targets.remove(id) || state.config().idempotent_deletes {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matching_target_raises_level() {
+        let targets = DebugTargets::default();
+        targets.add(NewDebugTarget {
+            selector: TargetSelector::Route("/api/users/:id".to_string()),
+            level: DebugLevel::Debug,
+            ttl_seconds: 60,
+        });
+        targets.add(NewDebugTarget {
+            selector: TargetSelector::Tenant("acme".to_string()),
+            level: DebugLevel::Trace,
+            ttl_seconds: 60,
+        });
+
+        let route_only = TargetContext {
+            route: Some("/api/users/:id"),
+            ..TargetContext::default()
+        };
+        assert_eq!(targets.level_for(&route_only), Some(LevelFilter::DEBUG));
+
+        let both = TargetContext {
+            route: Some("/api/users/:id"),
+            tenant: Some("acme"),
+            ..TargetContext::default()
+        };
+        assert_eq!(targets.level_for(&both), Some(LevelFilter::TRACE));
+        assert_eq!(targets.level_for(&TargetContext::default()), None);
+    }
+
+    #[test]
+    fn test_ttl_is_capped() {
+        let targets = DebugTargets::default();
+        let target = targets.add(NewDebugTarget {
+            selector: TargetSelector::User("42".to_string()),
+            level: DebugLevel::Debug,
+            ttl_seconds: 86_400,
+        });
+        let max = chrono::Utc::now() + chrono::Duration::seconds(MAX_TTL_SECONDS);
+        assert!(target.expires_at <= max);
+    }
+}
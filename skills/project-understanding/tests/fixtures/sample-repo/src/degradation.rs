@@ -0,0 +1,134 @@
+//! Degraded operating modes of background subsystems.
+//!
+//! A subsystem whose dependency fails keeps serving through its fallback and
+//! reports the mode it fell back to here; it clears the report once the
+//! dependency works again. The policies in this tree are:
+//!
+//! - `access_log`: the sink can't be written, entries survive only in the
+//!   structured stdout log (`stdout_only`)
+//! - `activity_ingest`: copies into Postgres fail, rows spill to disk
+//!   (`spill_to_disk`) or wait in memory when no spill file is configured
+//!   (`buffer_in_memory`)
+//! - `last_seen`: flushes fail, times are merged back for the next flush
+//!   (`buffer_in_memory`)
+//! - `event_stream`: the broker rejects events, they go to the disk spool
+//!   (`spool_to_disk`) or are dropped after being logged (`dropping_events`)
+//! - `webhooks`: deliveries run out of retries and go to the disk spool
+//!   (`spool_to_disk`)
+//! - `rate_limit`: Redis can't be reached, each instance limits callers with
+//!   its own buckets (`limit_per_instance`)
+//!
+//! `DegradationCheck` turns any active report into a `degraded` state in
+//! `/readyz`, which stays ready: degraded instances still take traffic.
+
+use async_trait::async_trait;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use crate::health::{HealthCheck, HealthState};
+
+/// Fallback a subsystem is running in
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Degradation {
+    /// Subsystem name, matching its lifecycle component or task
+    pub subsystem: &'static str,
+    /// Fallback in use, e.g. `spill_to_disk`
+    pub mode: &'static str,
+    /// Most recent failure that kept the subsystem degraded
+    pub reason: String,
+    /// When the subsystem first fell back
+    pub since: chrono::DateTime<chrono::Utc>,
+}
+
+/// Subsystems currently running in a fallback mode
+#[derive(Debug, Default)]
+pub struct Degradations {
+    active: Mutex<BTreeMap<&'static str, Degradation>>,
+}
+
+impl Degradations {
+    /// Report that `subsystem` fell back to `mode` because of `reason`
+    pub fn degrade(&self, subsystem: &'static str, mode: &'static str, reason: impl ToString) {
+        let reason = reason.to_string();
+        let mut active = self.active.lock().expect("degradation lock poisoned");
+        match active.get_mut(subsystem) {
+            Some(current) if current.mode == mode => current.reason = reason,
+            _ => {
+                tracing::warn!("{} degraded to {}: {}", subsystem, mode, reason);
+                let since = chrono::Utc::now();
+                active.insert(subsystem, Degradation { subsystem, mode, reason, since });
+            }
+        }
+    }
+
+    /// Report that `subsystem` works normally again
+    pub fn recover(&self, subsystem: &'static str) {
+        let recovered = self
+            .active
+            .lock()
+            .expect("degradation lock poisoned")
+            .remove(subsystem);
+        if let Some(degradation) = recovered {
+            tracing::info!("{} recovered from {}", subsystem, degradation.mode);
+        }
+    }
+
+    /// Active degradations by subsystem name
+    pub fn active(&self) -> Vec<Degradation> {
+        let active = self.active.lock().expect("degradation lock poisoned");
+        active.values().cloned().collect()
+    }
+}
+
+/// Health check reporting `degraded` while any subsystem runs in a fallback
+pub struct DegradationCheck {
+    degradations: Arc<Degradations>,
+}
+
+impl DegradationCheck {
+    /// Create a check over the given reports
+    pub fn new(degradations: Arc<Degradations>) -> Self {
+        Self { degradations }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for DegradationCheck {
+    fn name(&self) -> &'static str {
+        "fallbacks"
+    }
+
+    async fn check(&self) -> Result<HealthState, String> {
+        if self.degradations.active().is_empty() {
+            Ok(HealthState::Operational)
+        } else {
+            Ok(HealthState::Degraded)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_degrade_and_recover() {
+        let degradations = Arc::new(Degradations::default());
+        let check = DegradationCheck::new(degradations.clone());
+        assert_eq!(check.check().await, Ok(HealthState::Operational));
+
+        degradations.degrade("activity_ingest", "spill_to_disk", "connection refused");
+        let since = degradations.active()[0].since;
+        degradations.degrade("activity_ingest", "spill_to_disk", "timed out");
+        let active = degradations.active();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].reason, "timed out");
+        assert_eq!(active[0].since, since);
+        assert_eq!(check.check().await, Ok(HealthState::Degraded));
+
+        degradations.recover("activity_ingest");
+        assert!(degradations.active().is_empty());
+        assert_eq!(check.check().await, Ok(HealthState::Operational));
+    }
+}
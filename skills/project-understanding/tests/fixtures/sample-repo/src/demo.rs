@@ -0,0 +1,182 @@
+//! Demo mode with resettable sandbox data.
+//!
+//! With `Config::demo.enabled`, the server uses the in-memory store seeded
+//! with sample users. Every mutation is allowed; the dataset is wiped and
+//! reseeded every `reset_interval_secs` and on `POST /api/admin/demo/reset`.
+//! Outbound integrations stay inside the sandbox: webhook deliveries are
+//! logged rather than sent and the event stream publisher is not started.
+
+use async_trait::async_trait;
+use axum::{extract::State, http::StatusCode, response::Json};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+use crate::audit::AuditContext;
+use crate::auth::Admin;
+use crate::lifecycle::Component;
+use crate::repository::{self, RepositoryError, UserQuery};
+use crate::{ApiResponse, AppState, User};
+
+/// Demo mode settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DemoConfig {
+    /// Run as a demo sandbox
+    pub enabled: bool,
+    /// Seconds between automatic resets; zero disables them
+    pub reset_interval_secs: u64,
+}
+
+impl Default for DemoConfig {
+    fn default() -> Self {
+        DemoConfig {
+            enabled: false,
+            reset_interval_secs: 3600,
+        }
+    }
+}
+
+/// Result of a reset
+#[derive(Debug, Serialize)]
+pub struct DemoReset {
+    /// Users removed
+    pub removed: usize,
+    /// Sample users inserted
+    pub seeded: usize,
+}
+
+/// Sample users covering active, inactive, and soft-deleted states
+pub fn sample_users() -> Vec<User> {
+    let mut users: Vec<User> = [
+        ("alice", "alice@example.com"),
+        ("bob", "bob@example.com"),
+        ("carol", "carol@example.com"),
+        ("dave", "dave@example.com"),
+    ]
+    .into_iter()
+    .map(|(username, email)| User::new(username.to_string(), email.to_string()))
+    .collect();
+    users[2].deactivate();
+    users[3].soft_delete();
+    users
+}
+
+/// Replace every user with the sample data
+pub async fn reset(state: &AppState) -> Result<DemoReset, RepositoryError> {
+    let query = UserQuery {
+        include_deleted: true,
+        ..UserQuery::default()
+    };
+    let existing = repository::list_every_tenant(&*state.users, &query).await?;
+    for user in &existing {
+        state.users.delete(&user.tenant_id, user.id).await?;
+    }
+    let seeds = sample_users();
+    let seeded = seeds.len();
+    for user in seeds {
+        state.users.insert(user).await?;
+    }
+    Ok(DemoReset {
+        removed: existing.len(),
+        seeded,
+    })
+}
+
+/// Lifecycle component seeding the sandbox and running scheduled resets
+#[derive(Default)]
+pub struct DemoSandbox {
+    handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+#[async_trait]
+impl Component for DemoSandbox {
+    fn name(&self) -> &'static str {
+        "demo"
+    }
+
+    async fn start(&self, state: &Arc<AppState>) -> Result<(), String> {
+        let current = state.config();
+        let config = &current.demo;
+        if !config.enabled {
+            return Ok(());
+        }
+        reset(state).await.map_err(|err| err.to_string())?;
+        tracing::warn!("demo mode: data resets every {}s", config.reset_interval_secs);
+        if config.reset_interval_secs == 0 {
+            return Ok(());
+        }
+
+        let period = Duration::from_secs(config.reset_interval_secs);
+        let state = state.clone();
+        let handle = tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(period);
+            // The first tick fires immediately and we just seeded
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                if state.read_only.is_enabled() {
+                    tracing::info!("read-only mode: skipping demo data reset");
+                    continue;
+                }
+                match reset(&state).await {
+                    Ok(result) => tracing::info!("demo data reset: {:?}", result),
+                    Err(err) => tracing::error!("demo data reset failed: {}", err),
+                }
+            }
+        });
+        *self.handle.lock().expect("demo handle lock poisoned") = Some(handle);
+        Ok(())
+    }
+
+    async fn stop(&self) -> Result<(), String> {
+        if let Some(handle) = self.handle.lock().expect("demo handle lock poisoned").take() {
+            handle.abort();
+        }
+        Ok(())
+    }
+}
+
+/// Reset the sandbox now
+pub async fn reset_demo(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    context: AuditContext,
+) -> Result<Json<ApiResponse<DemoReset>>, StatusCode> {
+    if !state.config().demo.enabled {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let result = reset(&state)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    tracing::info!(
+        "demo data reset by {} in request {}",
+        context.actor,
+        context.request_id
+    );
+    Ok(Json(ApiResponse::success(result)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tenancy::DEFAULT_TENANT;
+    use crate::Config;
+
+    #[tokio::test]
+    async fn test_reset_replaces_data_with_samples() {
+        let state = AppState::new(Config::default());
+        let extra = User::new("mallory".to_string(), "mallory@example.com".to_string());
+        state.users.insert(extra.clone()).await.unwrap();
+
+        let first = reset(&state).await.unwrap();
+        assert_eq!(first.removed, 1);
+        let second = reset(&state).await.unwrap();
+        assert_eq!(second.removed, second.seeded);
+
+        assert!(state.users.get(DEFAULT_TENANT, extra.id).await.unwrap().is_none());
+        let visible = state.users.list(DEFAULT_TENANT, &UserQuery::default()).await.unwrap();
+        assert_eq!(visible.len(), sample_users().len() - 1);
+    }
+}
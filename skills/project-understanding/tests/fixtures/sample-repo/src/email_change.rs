@@ -0,0 +1,250 @@
+//! Changing a user's email address, confirmed from the new address.
+//!
+//! Updates can't change `email`; it changes in two steps instead.
+//! `POST /api/v1/users/:id/email-change` with the new address checks that no
+//! other user of the tenant has it, then mails a confirmation link to it and
+//! answers 202; the stored address stays as it was. The link carries a
+//! single-use token, valid for `Config::email_change.ttl_secs`, which the
+//! front end posts to `POST /api/v1/email-change/confirm`. Only then is the
+//! address swapped, audited and published like any update, and the old
+//! address told of the change so its owner notices one they didn't make.
+//!
+//! A new request for the same user replaces the one pending. Pending
+//! changes are kept in memory on the instance that took the request, so a
+//! restart drops them and the change is simply requested again.
+
+use axum::{extract::State, http::StatusCode, response::Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use crate::audit::AuditContext;
+use crate::auth::{constant_time_eq, Admin};
+use crate::error::{AppError, ErrorCode};
+use crate::mail::{Email, Template};
+use crate::redaction::Redacted;
+use crate::setup::generate_token;
+use crate::tenancy::Tenant;
+use crate::validation::{ApiPath, Validate, ValidatedJson};
+use crate::{service, ApiResponse, AppState, User};
+
+/// Email change settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EmailChangeConfig {
+    /// Seconds a confirmation link stays valid
+    pub ttl_secs: u64,
+}
+
+impl Default for EmailChangeConfig {
+    fn default() -> Self {
+        EmailChangeConfig { ttl_secs: 86_400 }
+    }
+}
+
+/// Request to change a user's address
+#[derive(Debug, Deserialize)]
+pub struct NewEmail {
+    /// Address to change to
+    pub email: String,
+}
+
+impl Validate for NewEmail {
+    fn validate(&self) -> Vec<String> {
+        if service::is_email(self.email.trim()) {
+            Vec::new()
+        } else {
+            vec!["email is not a valid address".to_string()]
+        }
+    }
+}
+
+/// Confirmation of a change, with the token from the link
+#[derive(Debug, Deserialize)]
+pub struct Confirmation {
+    /// Token the confirmation link carried
+    pub token: String,
+}
+
+/// A change waiting for confirmation, as reported to the requester
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PendingEmailChange {
+    /// User whose address changes
+    pub user_id: Uuid,
+    /// Address it changes to once confirmed
+    pub email: String,
+    /// When the confirmation link stops working
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+struct Pending {
+    tenant: String,
+    token: String,
+    change: PendingEmailChange,
+}
+
+/// Changes waiting for confirmation, one per user
+#[derive(Debug, Default)]
+pub struct EmailChanges {
+    pending: Mutex<HashMap<Uuid, Pending>>,
+}
+
+impl EmailChanges {
+    /// Start a change, replacing any pending for the user, and return its token
+    fn begin(&self, tenant: &str, change: PendingEmailChange) -> String {
+        let token = generate_token();
+        let pending = Pending {
+            tenant: tenant.to_string(),
+            token: token.clone(),
+            change,
+        };
+        let mut changes = self.pending.lock().expect("email changes lock poisoned");
+        changes.retain(|_, pending| pending.change.expires_at > Utc::now());
+        changes.insert(pending.change.user_id, pending);
+        token
+    }
+
+    /// Take the unexpired change `token` confirms, so it can't be used twice
+    fn take(&self, token: &str, now: DateTime<Utc>) -> Option<Pending> {
+        let mut changes = self.pending.lock().expect("email changes lock poisoned");
+        let user_id = changes
+            .values()
+            .find(|pending| constant_time_eq(pending.token.as_bytes(), token.as_bytes()))?
+            .change
+            .user_id;
+        changes.remove(&user_id).filter(|pending| pending.change.expires_at > now)
+    }
+}
+
+fn invalid_token() -> AppError {
+    AppError::NotFound("email change token is unknown or expired".to_string())
+        .with_code(ErrorCode::EMAIL_CHANGE_NOT_FOUND)
+}
+
+/// Check the new address and mail it a confirmation link, returning the token
+pub async fn request_change(
+    state: &AppState,
+    tenant: &str,
+    id: Uuid,
+    email: &str,
+) -> Result<(String, PendingEmailChange), AppError> {
+    let user = state.services.users.get(state, tenant, id, false).await?;
+    let email = email.trim().to_ascii_lowercase();
+    if email == user.email {
+        return Err(AppError::Conflict("user already has this address".to_string()));
+    }
+    if state.users.find_by_email(tenant, &email).await?.is_some() {
+        return Err(service::ServiceError::Taken("email".to_string()).into());
+    }
+    let ttl = chrono::Duration::seconds(state.config().email_change.ttl_secs as i64);
+    let change = PendingEmailChange {
+        user_id: id,
+        email: email.clone(),
+        expires_at: state.services.clock.now() + ttl,
+    };
+    let token = state.email_changes.begin(tenant, change.clone());
+    let template = Template::EmailChange {
+        username: user.username,
+        token: token.clone(),
+    };
+    let message = Email::new(&state.config().mail, &email, &template);
+    state.services.mail.send(state, message).await?;
+    Ok((token, change))
+}
+
+/// Swap in the address `token` confirms and tell the old address
+pub async fn confirm(
+    state: &AppState,
+    context: &AuditContext,
+    token: &str,
+) -> Result<User, AppError> {
+    let now = state.services.clock.now();
+    let pending = state.email_changes.take(token.trim(), now).ok_or_else(invalid_token)?;
+    let (tenant, id) = (&pending.tenant, pending.change.user_id);
+    let before = state.services.users.get(state, tenant, id, false).await?;
+    let old = before.email.clone();
+    let user = service::change_email(state, context, before, &pending.change.email).await?;
+    let template = Template::EmailChanged {
+        username: user.username.clone(),
+        email: user.email.clone(),
+    };
+    // The change is made; a notice that can't be queued is no reason to fail it
+    let notice = Email::new(&state.config().mail, &old, &template);
+    if let Err(err) = state.services.mail.send(state, notice).await {
+        tracing::warn!(user = %user.id, "failed to queue email change notice: {}", err);
+    }
+    Ok(user)
+}
+
+/// Ask to change a user's address, pending confirmation from the new one
+pub async fn request_email_change(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    Tenant(tenant): Tenant,
+    ApiPath(id): ApiPath<Uuid>,
+    ValidatedJson(request): ValidatedJson<NewEmail>,
+) -> Result<(StatusCode, Json<ApiResponse<PendingEmailChange>>), AppError> {
+    let (_token, change) = request_change(&state, &tenant, id, &request.email).await?;
+    Ok((StatusCode::ACCEPTED, Json(ApiResponse::success(change))))
+}
+
+/// Confirm a change with the token from its link
+pub async fn confirm_email_change(
+    State(state): State<Arc<AppState>>,
+    context: AuditContext,
+    Json(confirmation): Json<Confirmation>,
+) -> Result<Redacted<Json<ApiResponse<User>>>, AppError> {
+    let user = confirm(&state, &context, &confirmation.token).await?;
+    Ok(Redacted::of::<User>(Json(ApiResponse::success(user))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tenancy::DEFAULT_TENANT as T;
+    use crate::Config;
+
+    #[tokio::test]
+    async fn test_email_changes_only_once_confirmed() {
+        let state = AppState::new(Config::default());
+        let context = AuditContext {
+            actor: "admin".to_string(),
+            request_id: "test".to_string(),
+            client_ip: None,
+        };
+        let user = service::create_user(&state, &context, T, "alice", "alice@example.com")
+            .await
+            .unwrap();
+        service::create_user(&state, &context, T, "bob", "bob@example.com").await.unwrap();
+        assert!(request_change(&state, T, user.id, "Bob@example.com").await.is_err());
+
+        let requested = request_change(&state, T, user.id, "Alice@New.example").await;
+        let (token, change) = requested.unwrap();
+        assert_eq!(change.email, "alice@new.example");
+        let stored = service::get_user(&state, T, user.id, false).await.unwrap();
+        assert_eq!(stored.email, "alice@example.com");
+
+        assert!(confirm(&state, &context, "wrong").await.is_err());
+        let changed = confirm(&state, &context, &token).await.unwrap();
+        assert_eq!(changed.email, "alice@new.example");
+        assert!(confirm(&state, &context, &token).await.is_err());
+    }
+
+    #[test]
+    fn test_expired_change_is_refused() {
+        let changes = EmailChanges::default();
+        let change = PendingEmailChange {
+            user_id: Uuid::new_v4(),
+            email: "new@example.com".to_string(),
+            expires_at: Utc::now() + chrono::Duration::seconds(60),
+        };
+        let token = changes.begin(T, change.clone());
+        let later = change.expires_at + chrono::Duration::seconds(1);
+        assert!(changes.take(&token, later).is_none());
+        let token = changes.begin(T, change.clone());
+        assert_eq!(changes.take(&token, Utc::now()).unwrap().change, change);
+    }
+}
@@ -0,0 +1,101 @@
+//! Erasure of a user's personal data, on request.
+//!
+//! `POST /api/v1/users/:id/erase` anonymizes a user for good, unlike
+//! deactivation or a soft delete, which keep everything and can be undone.
+//! The record stays, so team memberships, audit entries, and outbox events
+//! that name the user's ID still resolve, but its username and email become
+//! placeholders derived from the ID and its metadata is emptied; the user
+//! is also deactivated and soft-deleted. Audit entries about the user keep
+//! who changed which fields and when, with every value replaced by
+//! `audit::ERASED`. The avatar and the archives of past exports are deleted.
+//!
+//! Erasing is refused with a 409 once done. Restoring an erased user brings
+//! back only the anonymized record.
+
+use axum::{extract::State, response::Json};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::audit::AuditContext;
+use crate::auth::Admin;
+use crate::error::AppError;
+use crate::redaction::Redacted;
+use crate::tenancy::Tenant;
+use crate::validation::ApiPath;
+use crate::{avatars, exports, service, ApiResponse, AppState, User};
+
+/// Erase a tenant's user and everything kept apart from the record
+pub async fn erase(
+    state: &AppState,
+    context: &AuditContext,
+    tenant: &str,
+    id: Uuid,
+) -> Result<User, AppError> {
+    let user = service::erase_user(state, context, tenant, id).await?;
+    for entity in exports::AUDITED_AS {
+        state.audit.redact(entity, id).await?;
+    }
+    avatars::remove(state, tenant, id).await?;
+    exports::remove_archives(state, tenant, id).await?;
+    tracing::info!(user = %id, actor = %context.actor, "user erased");
+    Ok(user)
+}
+
+/// Irreversibly anonymize a user
+#[tracing::instrument(skip_all)]
+pub async fn erase_user(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    context: AuditContext,
+    Tenant(tenant): Tenant,
+    ApiPath(id): ApiPath<Uuid>,
+) -> Result<Redacted<Json<ApiResponse<User>>>, AppError> {
+    let user = erase(&state, &context, &tenant, id).await?;
+    Ok(Redacted::of::<User>(Json(ApiResponse::success(user))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::{AuditAction, AuditQuery, ERASED};
+    use crate::service::UserPatch;
+    use crate::tenancy::DEFAULT_TENANT as T;
+    use crate::Config;
+
+    #[tokio::test]
+    async fn test_erase_anonymizes_user_and_audit_values() {
+        let state = AppState::new(Config::default());
+        let context = AuditContext {
+            actor: "admin".to_string(),
+            request_id: "test".to_string(),
+            client_ip: None,
+        };
+        let user = service::create_user(&state, &context, T, "alice", "alice@example.com")
+            .await
+            .unwrap();
+        let patch = UserPatch {
+            username: Some("alice2".to_string()),
+            email: None,
+            is_active: None,
+            metadata: Some(serde_json::json!({"city": "Lyon"})),
+        };
+        service::update_user(&state, &context, user.clone(), &patch).await.unwrap();
+
+        let erased = erase(&state, &context, T, user.id).await.unwrap();
+        assert!(erased.is_erased() && erased.is_deleted() && !erased.is_active);
+        assert!(!erased.username.contains("alice") && !erased.email.contains("alice"));
+        assert!(erase(&state, &context, T, user.id).await.is_err());
+
+        let query = AuditQuery {
+            entity: "user".to_string(),
+            id: Some(user.id),
+        };
+        let entries = state.audit.query(&query).await.unwrap();
+        let actions: Vec<AuditAction> = entries.iter().map(|entry| entry.action).collect();
+        assert_eq!(actions, [AuditAction::Delete, AuditAction::Update, AuditAction::Create]);
+        let text = serde_json::to_string(&entries).unwrap();
+        assert!(!text.contains("alice") && !text.contains("Lyon"));
+        assert!(entries[1].changes.iter().any(|change| change.field == "username"));
+        assert!(entries[1].changes.iter().all(|change| change.after == ERASED));
+    }
+}
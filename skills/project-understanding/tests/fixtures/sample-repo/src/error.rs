@@ -0,0 +1,810 @@
+//! Application error type for HTTP handlers.
+//!
+//! Handlers return `AppError` so failures reach clients as the usual
+//! `ApiResponse` envelope with a message, not a bare status. Storage and
+//! internal detail is logged and replaced with a generic message. Each error
+//! also carries a registered `ErrorCode` for clients to branch on, since
+//! messages may change: the generic code of its variant, or a more specific
+//! one attached with `AppError::with_code`. `ErrorCode::ALL` is the registry,
+//! which the OpenAPI spec documents.
+//!
+//! Clients that send `Accept: application/problem+json`, or every client
+//! when `Config::error_format` is `problem`, get RFC 7807 problem details
+//! instead; `negotiate_problems` rewrites the envelope on the way out.
+//!
+//! Storage and internal failures carry an `ErrorReport`: the underlying
+//! error with its whole source chain, say a sqlx error and the I/O error
+//! under it, and a backtrace when `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE`
+//! enables them. Turned into a response, the report is logged as one event
+//! with the chain in `causes` and the backtrace in `backtrace`; the client
+//! only sees "internal error". With `Config::debug`, `negotiate_problems`
+//! adds the chain to the body as `causes` as well, for local debugging.
+//!
+//! `not_found` and `method_not_allowed` give unknown paths and wrong
+//! methods the same JSON bodies instead of axum's empty defaults. The 405's
+//! `Allow` header comes from the methods the router has for the path, plus
+//! `OPTIONS`, and an `OPTIONS` request to any route gets that list with a
+//! 204 instead of a 405.
+
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use serde::{ser::SerializeStruct, Deserialize, Serialize, Serializer};
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::repository::RepositoryError;
+use crate::service::ServiceError;
+use crate::{ApiResponse, AppState};
+
+/// Media type of RFC 7807 problem details
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Error body shape sent to clients that don't ask for one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorFormat {
+    /// The `ApiResponse` envelope
+    #[default]
+    Envelope,
+    /// RFC 7807 problem details
+    Problem,
+}
+
+/// A registered, machine-readable error code with the status it's sent with
+///
+/// Codes are part of the API: once released, an entry in `ErrorCode::ALL`
+/// keeps its code, status, and meaning. Retired errors keep their entry, and
+/// new meanings get new codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorCode {
+    /// Sent as `error_code`, e.g. `USER_NOT_FOUND`
+    pub code: &'static str,
+    /// HTTP status
+    pub status: StatusCode,
+    /// Short summary, the same for every error with the code
+    pub title: &'static str,
+}
+
+const fn error_code(code: &'static str, status: StatusCode, title: &'static str) -> ErrorCode {
+    ErrorCode { code, status, title }
+}
+
+impl ErrorCode {
+    pub const NOT_FOUND: Self =
+        error_code("NOT_FOUND", StatusCode::NOT_FOUND, "Resource not found");
+    pub const BAD_REQUEST: Self =
+        error_code("BAD_REQUEST", StatusCode::BAD_REQUEST, "Malformed request");
+    pub const VALIDATION_FAILED: Self =
+        error_code("VALIDATION_FAILED", StatusCode::UNPROCESSABLE_ENTITY, "Validation failed");
+    pub const CONFLICT: Self =
+        error_code("CONFLICT", StatusCode::CONFLICT, "Conflict with current state");
+    pub const UNAUTHORIZED: Self =
+        error_code("UNAUTHORIZED", StatusCode::UNAUTHORIZED, "Authentication required");
+    pub const FORBIDDEN: Self = error_code("FORBIDDEN", StatusCode::FORBIDDEN, "Access denied");
+    pub const PAYLOAD_TOO_LARGE: Self =
+        error_code("PAYLOAD_TOO_LARGE", StatusCode::PAYLOAD_TOO_LARGE, "Request body too large");
+    pub const METHOD_NOT_ALLOWED: Self =
+        error_code("METHOD_NOT_ALLOWED", StatusCode::METHOD_NOT_ALLOWED, "Method not allowed");
+    pub const OVERLOADED: Self =
+        error_code("OVERLOADED", StatusCode::SERVICE_UNAVAILABLE, "Service overloaded");
+    pub const RATE_LIMITED: Self =
+        error_code("RATE_LIMITED", StatusCode::TOO_MANY_REQUESTS, "Too many requests");
+    pub const TIMEOUT: Self =
+        error_code("TIMEOUT", StatusCode::GATEWAY_TIMEOUT, "Request timed out");
+    pub const INTERNAL: Self =
+        error_code("INTERNAL", StatusCode::INTERNAL_SERVER_ERROR, "Internal error");
+    pub const USER_NOT_FOUND: Self =
+        error_code("USER_NOT_FOUND", StatusCode::NOT_FOUND, "No such user");
+    pub const USERNAME_TAKEN: Self =
+        error_code("USERNAME_TAKEN", StatusCode::CONFLICT, "Username already in use");
+    pub const EMAIL_TAKEN: Self =
+        error_code("EMAIL_TAKEN", StatusCode::CONFLICT, "Email already in use");
+    pub const FIELD_NOT_WRITABLE: Self =
+        error_code("FIELD_NOT_WRITABLE", StatusCode::FORBIDDEN, "Field can't be changed");
+    pub const SIGNATURE_INVALID: Self =
+        error_code("SIGNATURE_INVALID", StatusCode::UNAUTHORIZED, "Missing or wrong signature");
+    pub const SIGNATURE_EXPIRED: Self =
+        error_code("SIGNATURE_EXPIRED", StatusCode::UNAUTHORIZED, "Signature outside window");
+    pub const SIGNATURE_REPLAYED: Self =
+        error_code("SIGNATURE_REPLAYED", StatusCode::UNAUTHORIZED, "Signature already used");
+    pub const OPERATION_NOT_FOUND: Self =
+        error_code("OPERATION_NOT_FOUND", StatusCode::NOT_FOUND, "No such operation");
+    pub const READ_ONLY: Self =
+        error_code("READ_ONLY", StatusCode::SERVICE_UNAVAILABLE, "Instance is read-only");
+    pub const ENDPOINT_DISABLED: Self =
+        error_code("ENDPOINT_DISABLED", StatusCode::SERVICE_UNAVAILABLE, "Endpoint disabled");
+    pub const DATABASE_UNAVAILABLE: Self = error_code(
+        "DATABASE_UNAVAILABLE",
+        StatusCode::SERVICE_UNAVAILABLE,
+        "Database unavailable",
+    );
+    pub const PRECONDITION_FAILED: Self = error_code(
+        "PRECONDITION_FAILED",
+        StatusCode::PRECONDITION_FAILED,
+        "Resource changed since it was read",
+    );
+    pub const IDEMPOTENCY_KEY_IN_USE: Self = error_code(
+        "IDEMPOTENCY_KEY_IN_USE",
+        StatusCode::CONFLICT,
+        "Request with this key still running",
+    );
+    pub const IDEMPOTENCY_KEY_REUSED: Self = error_code(
+        "IDEMPOTENCY_KEY_REUSED",
+        StatusCode::UNPROCESSABLE_ENTITY,
+        "Key already used for another request",
+    );
+    pub const TEAM_NOT_FOUND: Self =
+        error_code("TEAM_NOT_FOUND", StatusCode::NOT_FOUND, "No such team");
+    pub const TEAM_NAME_TAKEN: Self =
+        error_code("TEAM_NAME_TAKEN", StatusCode::CONFLICT, "Team name already in use");
+    pub const JOB_NOT_FOUND: Self =
+        error_code("JOB_NOT_FOUND", StatusCode::NOT_FOUND, "No such job");
+    pub const EXPORT_NOT_FOUND: Self =
+        error_code("EXPORT_NOT_FOUND", StatusCode::NOT_FOUND, "No such export");
+    pub const EMAIL_CHANGE_NOT_FOUND: Self = error_code(
+        "EMAIL_CHANGE_NOT_FOUND",
+        StatusCode::NOT_FOUND,
+        "Unknown or expired email change",
+    );
+    pub const UNSUPPORTED_MEDIA_TYPE: Self = error_code(
+        "UNSUPPORTED_MEDIA_TYPE",
+        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        "Body format not accepted",
+    );
+
+    /// The registry: every code an `AppError` can carry, append only
+    pub const ALL: &'static [Self] = &[
+        Self::NOT_FOUND,
+        Self::BAD_REQUEST,
+        Self::VALIDATION_FAILED,
+        Self::CONFLICT,
+        Self::UNAUTHORIZED,
+        Self::FORBIDDEN,
+        Self::PAYLOAD_TOO_LARGE,
+        Self::METHOD_NOT_ALLOWED,
+        Self::OVERLOADED,
+        Self::RATE_LIMITED,
+        Self::TIMEOUT,
+        Self::INTERNAL,
+        Self::USER_NOT_FOUND,
+        Self::USERNAME_TAKEN,
+        Self::EMAIL_TAKEN,
+        Self::FIELD_NOT_WRITABLE,
+        Self::SIGNATURE_INVALID,
+        Self::SIGNATURE_EXPIRED,
+        Self::SIGNATURE_REPLAYED,
+        Self::OPERATION_NOT_FOUND,
+        Self::READ_ONLY,
+        Self::DATABASE_UNAVAILABLE,
+        Self::ENDPOINT_DISABLED,
+        Self::PRECONDITION_FAILED,
+        Self::IDEMPOTENCY_KEY_IN_USE,
+        Self::IDEMPOTENCY_KEY_REUSED,
+        Self::TEAM_NOT_FOUND,
+        Self::TEAM_NAME_TAKEN,
+        Self::JOB_NOT_FOUND,
+        Self::EXPORT_NOT_FOUND,
+        Self::EMAIL_CHANGE_NOT_FOUND,
+        Self::UNSUPPORTED_MEDIA_TYPE,
+    ];
+
+    /// Codes any route can answer with, whatever its handler does
+    pub const COMMON: &'static [Self] = &[
+        Self::BAD_REQUEST,
+        Self::PAYLOAD_TOO_LARGE,
+        Self::RATE_LIMITED,
+        Self::INTERNAL,
+        Self::OVERLOADED,
+        Self::TIMEOUT,
+        Self::DATABASE_UNAVAILABLE,
+        Self::ENDPOINT_DISABLED,
+    ];
+}
+
+impl Serialize for ErrorCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("ErrorCode", 3)?;
+        state.serialize_field("code", self.code)?;
+        state.serialize_field("status", &self.status.as_u16())?;
+        state.serialize_field("title", self.title)?;
+        state.end()
+    }
+}
+
+/// A failure on our side, kept whole for the logs
+///
+/// Displays as the underlying error and reports that error's sources as its
+/// own, so wrapping adds no line to the chain.
+#[derive(Debug)]
+pub struct ErrorReport {
+    error: Box<dyn Error + Send + Sync>,
+    backtrace: Backtrace,
+}
+
+impl ErrorReport {
+    /// Capture an error, or a message, with a backtrace of where it happened
+    pub fn new(error: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        ErrorReport {
+            error: error.into(),
+            backtrace: Backtrace::capture(),
+        }
+    }
+
+    /// Messages of the error and each of its sources, outermost first
+    pub fn chain(&self) -> Vec<String> {
+        let mut chain = Vec::new();
+        let mut next: Option<&(dyn Error + 'static)> = Some(self.error.as_ref());
+        while let Some(error) = next {
+            chain.push(error.to_string());
+            next = error.source();
+        }
+        chain
+    }
+
+    /// Where the error was captured, if backtraces are enabled
+    pub fn backtrace(&self) -> Option<&Backtrace> {
+        (self.backtrace.status() == BacktraceStatus::Captured).then_some(&self.backtrace)
+    }
+}
+
+impl std::fmt::Display for ErrorReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl Error for ErrorReport {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.error.source()
+    }
+}
+
+/// Errors surfaced by HTTP handlers
+#[derive(Debug)]
+pub enum AppError {
+    /// The requested resource does not exist
+    NotFound(String),
+    /// The request is malformed
+    BadRequest(String),
+    /// The input failed validation
+    Validation(Vec<String>),
+    /// The request conflicts with current state
+    Conflict(String),
+    /// An `If-Match` precondition no longer holds
+    PreconditionFailed(String),
+    /// Credentials are missing
+    Unauthorized,
+    /// Credentials do not permit the request
+    Forbidden(String),
+    /// The request body is over the size limit
+    PayloadTooLarge(String),
+    /// The request body is in a format the route doesn't accept
+    UnsupportedMediaType(String),
+    /// The route exists but not for this method, with the ones it accepts
+    MethodNotAllowed(Vec<String>),
+    /// The request was shed to protect more important traffic
+    Overloaded(String),
+    /// The caller is over the rate limit, until the given wait has passed
+    RateLimited(Duration),
+    /// The handler ran past its timeout
+    Timeout(Duration),
+    /// The instance refuses writes in read-only mode
+    ReadOnly,
+    /// The storage backend failed
+    Database(ErrorReport),
+    /// The storage backend can't be reached while it reconnects
+    Unavailable(ErrorReport),
+    /// Anything else that went wrong on our side
+    Internal(ErrorReport),
+    /// Another error with a more specific code of the same status
+    Coded(ErrorCode, Box<AppError>),
+}
+
+impl AppError {
+    /// The error with `code` in place of its generic one
+    pub fn with_code(self, code: ErrorCode) -> Self {
+        debug_assert_eq!(code.status, self.status(), "{} changes the status", code.code);
+        AppError::Coded(code, Box::new(self.base_owned()))
+    }
+
+    /// The error without a specific code
+    fn base(&self) -> &AppError {
+        match self {
+            AppError::Coded(_, error) => error.base(),
+            error => error,
+        }
+    }
+
+    fn base_owned(self) -> AppError {
+        match self {
+            AppError::Coded(_, error) => error.base_owned(),
+            error => error,
+        }
+    }
+
+    /// HTTP status for the error
+    pub fn status(&self) -> StatusCode {
+        self.base().generic_code().status
+    }
+
+    /// Registered code for the error
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AppError::Coded(code, _) => *code,
+            error => error.generic_code(),
+        }
+    }
+
+    /// Code of the error's variant
+    fn generic_code(&self) -> ErrorCode {
+        match self {
+            AppError::Coded(_, error) => error.generic_code(),
+            AppError::NotFound(_) => ErrorCode::NOT_FOUND,
+            AppError::BadRequest(_) => ErrorCode::BAD_REQUEST,
+            AppError::Validation(_) => ErrorCode::VALIDATION_FAILED,
+            AppError::Conflict(_) => ErrorCode::CONFLICT,
+            AppError::PreconditionFailed(_) => ErrorCode::PRECONDITION_FAILED,
+            AppError::Unauthorized => ErrorCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => ErrorCode::FORBIDDEN,
+            AppError::PayloadTooLarge(_) => ErrorCode::PAYLOAD_TOO_LARGE,
+            AppError::UnsupportedMediaType(_) => ErrorCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::MethodNotAllowed(_) => ErrorCode::METHOD_NOT_ALLOWED,
+            AppError::Overloaded(_) => ErrorCode::OVERLOADED,
+            AppError::RateLimited(_) => ErrorCode::RATE_LIMITED,
+            AppError::Timeout(_) => ErrorCode::TIMEOUT,
+            AppError::ReadOnly => ErrorCode::READ_ONLY,
+            AppError::Database(_) | AppError::Internal(_) => ErrorCode::INTERNAL,
+            AppError::Unavailable(_) => ErrorCode::DATABASE_UNAVAILABLE,
+        }
+    }
+
+    /// Problem type slug
+    fn problem_type(&self) -> &'static str {
+        match self.base() {
+            AppError::NotFound(_) => "not-found",
+            AppError::BadRequest(_) => "bad-request",
+            AppError::Validation(_) => "validation",
+            AppError::Conflict(_) => "conflict",
+            AppError::PreconditionFailed(_) => "precondition-failed",
+            AppError::Unauthorized => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::PayloadTooLarge(_) => "payload-too-large",
+            AppError::UnsupportedMediaType(_) => "unsupported-media-type",
+            AppError::MethodNotAllowed(_) => "method-not-allowed",
+            AppError::Overloaded(_) => "overloaded",
+            AppError::RateLimited(_) => "rate-limited",
+            AppError::Timeout(_) => "timeout",
+            AppError::ReadOnly => "read-only",
+            AppError::Unavailable(_) => "unavailable",
+            AppError::Database(_) | AppError::Internal(_) | AppError::Coded(..) => "internal",
+        }
+    }
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::NotFound(message)
+            | AppError::BadRequest(message)
+            | AppError::Conflict(message)
+            | AppError::PreconditionFailed(message)
+            | AppError::Forbidden(message)
+            | AppError::PayloadTooLarge(message)
+            | AppError::UnsupportedMediaType(message)
+            | AppError::Overloaded(message) => write!(f, "{}", message),
+            AppError::Validation(errors) => write!(f, "invalid input: {}", errors.join("; ")),
+            AppError::Unauthorized => write!(f, "authentication required"),
+            AppError::RateLimited(_) => write!(f, "rate limit exceeded"),
+            AppError::ReadOnly => write!(f, "instance is read-only; writes are refused"),
+            AppError::Timeout(limit) => {
+                write!(f, "request timed out after {}ms", limit.as_millis())
+            }
+            AppError::MethodNotAllowed(allowed) => {
+                write!(f, "method not allowed; allowed: {}", allowed.join(", "))
+            }
+            AppError::Database(report) => write!(f, "storage error: {}", report),
+            AppError::Unavailable(report) => write!(f, "storage unavailable: {}", report),
+            AppError::Internal(report) => write!(f, "internal error: {}", report),
+            AppError::Coded(_, error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for AppError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AppError::Database(report)
+            | AppError::Internal(report)
+            | AppError::Unavailable(report) => Some(report),
+            AppError::Coded(_, error) => error.source(),
+            _ => None,
+        }
+    }
+}
+
+/// Error detail attached to responses for problem negotiation
+#[derive(Debug, Clone)]
+struct Problem {
+    slug: &'static str,
+    title: &'static str,
+    code: ErrorCode,
+    detail: String,
+    errors: Vec<String>,
+    /// Source chain of an internal failure, sent only in debug mode
+    causes: Vec<String>,
+}
+
+impl Problem {
+    /// The RFC 7807 body for a request to `instance`
+    fn body(&self, status: StatusCode, instance: &str) -> serde_json::Value {
+        let mut body = serde_json::json!({
+            "type": format!("/problems/{}", self.slug),
+            "title": self.title,
+            "status": status.as_u16(),
+            "detail": self.detail,
+            "instance": instance,
+            "error_code": self.code.code,
+        });
+        if !self.errors.is_empty() {
+            body["errors"] = serde_json::json!(self.errors);
+        }
+        body
+    }
+
+    /// The `ApiResponse` envelope the error was sent as
+    fn envelope(&self) -> serde_json::Value {
+        serde_json::to_value(ApiResponse::<()>::failure(self.code, &self.detail))
+            .unwrap_or_default()
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let causes = match self.base() {
+            AppError::Database(report)
+            | AppError::Internal(report)
+            | AppError::Unavailable(report) => report.chain(),
+            _ => Vec::new(),
+        };
+        let message = match self.base() {
+            AppError::Database(report) | AppError::Internal(report) => {
+                tracing::error!(
+                    causes = ?causes,
+                    backtrace = report.backtrace().map(tracing::field::display),
+                    "{}",
+                    self
+                );
+                "internal error".to_string()
+            }
+            // Expected while the database restarts, and retried once it's back
+            AppError::Unavailable(_) => {
+                tracing::warn!(causes = ?causes, "{}", self);
+                "database unavailable".to_string()
+            }
+            _ => self.to_string(),
+        };
+        let code = self.code();
+        let allow = match self.base() {
+            AppError::MethodNotAllowed(allowed) => HeaderValue::from_str(&allowed.join(", ")).ok(),
+            _ => None,
+        };
+        let retry_after = match self.base() {
+            AppError::Overloaded(_) | AppError::Unavailable(_) => Some(1),
+            AppError::RateLimited(wait) => Some(wait.as_secs_f64().ceil().max(1.0) as u64),
+            _ => None,
+        };
+        let problem = Problem {
+            slug: self.problem_type(),
+            title: self.generic_code().title,
+            code,
+            detail: message.clone(),
+            errors: match self.base_owned() {
+                AppError::Validation(errors) => errors,
+                _ => Vec::new(),
+            },
+            causes,
+        };
+        let body = ApiResponse::<()>::failure(code, message);
+        let mut response = (status, Json(body)).into_response();
+        if let Some(allow) = allow {
+            response.headers_mut().insert(header::ALLOW, allow);
+        }
+        if let Some(seconds) = retry_after {
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        response.extensions_mut().insert(problem);
+        response
+    }
+}
+
+/// Whether a request asked for problem details
+fn wants_problem(accept: Option<&str>, format: ErrorFormat) -> bool {
+    format == ErrorFormat::Problem || accept.map_or(false, |accept| accept.contains(PROBLEM_JSON))
+}
+
+/// Middleware replacing `AppError` envelopes with problem details on request,
+/// and adding internal causes to either in debug mode
+pub async fn negotiate_problems<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let accept = req.headers().get(header::ACCEPT).and_then(|value| value.to_str().ok());
+    let wanted = wants_problem(accept, state.config().error_format);
+    let verbose = state.config().debug;
+    let instance = req.uri().path().to_string();
+    let response = next.run(req).await;
+    if !wanted && !verbose {
+        return response;
+    }
+    let Some(problem) = response.extensions().get::<Problem>().cloned() else {
+        return response;
+    };
+    if !wanted && problem.causes.is_empty() {
+        return response;
+    }
+
+    let status = response.status();
+    let mut headers = response.headers().clone();
+    headers.remove(header::CONTENT_LENGTH);
+    let mut body = if wanted {
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+        problem.body(status, &instance)
+    } else {
+        problem.envelope()
+    };
+    if verbose && !problem.causes.is_empty() {
+        body["causes"] = serde_json::json!(problem.causes);
+    }
+    let mut response = (status, Json(body)).into_response();
+    response.headers_mut().extend(headers);
+    response
+}
+
+/// Router fallback for paths no route matches
+pub async fn not_found(method: Method, uri: Uri) -> AppError {
+    AppError::NotFound(format!("no route for {} {}", method, uri.path()))
+}
+
+/// Methods listed in an `Allow` header
+fn allowed_methods(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get(header::ALLOW)
+        .and_then(|value| value.to_str().ok())
+        .map(|allow| {
+            allow
+                .split(',')
+                .map(|method| method.trim().to_string())
+                .filter(|method| !method.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Middleware giving axum's empty 405s an `AppError` body, and answering
+/// `OPTIONS` with the route's methods
+///
+/// Layered inside `negotiate_problems` so these can become problem details too.
+pub async fn method_not_allowed<B>(req: Request<B>, next: Next<B>) -> Response {
+    let options = req.method() == Method::OPTIONS;
+    let response = next.run(req).await;
+    let bare = response.status() == StatusCode::METHOD_NOT_ALLOWED
+        && !response.headers().contains_key(header::CONTENT_TYPE);
+    if !bare {
+        return response;
+    }
+    let mut allowed = allowed_methods(response.headers());
+    allowed.push(Method::OPTIONS.to_string());
+    if !options {
+        return AppError::MethodNotAllowed(allowed).into_response();
+    }
+    let mut response = StatusCode::NO_CONTENT.into_response();
+    if let Ok(allow) = HeaderValue::from_str(&allowed.join(", ")) {
+        response.headers_mut().insert(header::ALLOW, allow);
+    }
+    response
+}
+
+impl From<ServiceError> for AppError {
+    fn from(err: ServiceError) -> Self {
+        match err {
+            ServiceError::NotFound => {
+                AppError::NotFound(err.to_string()).with_code(ErrorCode::USER_NOT_FOUND)
+            }
+            ServiceError::Taken(ref field) => {
+                let code = match field.as_str() {
+                    "username" => ErrorCode::USERNAME_TAKEN,
+                    "email" => ErrorCode::EMAIL_TAKEN,
+                    _ => ErrorCode::CONFLICT,
+                };
+                AppError::Conflict(err.to_string()).with_code(code)
+            }
+            ServiceError::Conflict(message) => AppError::Conflict(message),
+            ServiceError::Invalid(errors) => AppError::Validation(errors),
+            ServiceError::Backend(report) => AppError::Database(report),
+            ServiceError::Unavailable(report) => AppError::Unavailable(report),
+            ServiceError::ReadOnly => AppError::ReadOnly,
+        }
+    }
+}
+
+impl From<RepositoryError> for AppError {
+    fn from(err: RepositoryError) -> Self {
+        ServiceError::from(err).into()
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(err: sqlx::Error) -> Self {
+        match err {
+            sqlx::Error::RowNotFound => AppError::NotFound("not found".to_string()),
+            other => RepositoryError::from(other).into(),
+        }
+    }
+}
+
+impl From<serde_json::Error> for AppError {
+    fn from(err: serde_json::Error) -> Self {
+        match err.classify() {
+            serde_json::error::Category::Io => AppError::Internal(ErrorReport::new(err)),
+            _ => AppError::BadRequest(format!("invalid JSON: {}", err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registered_codes_never_change() {
+        // Append new codes; never edit or remove a released entry
+        let registry: Vec<_> =
+            ErrorCode::ALL.iter().map(|error| (error.code, error.status.as_u16())).collect();
+        assert_eq!(
+            registry,
+            [
+                ("NOT_FOUND", 404),
+                ("BAD_REQUEST", 400),
+                ("VALIDATION_FAILED", 422),
+                ("CONFLICT", 409),
+                ("UNAUTHORIZED", 401),
+                ("FORBIDDEN", 403),
+                ("PAYLOAD_TOO_LARGE", 413),
+                ("METHOD_NOT_ALLOWED", 405),
+                ("OVERLOADED", 503),
+                ("RATE_LIMITED", 429),
+                ("TIMEOUT", 504),
+                ("INTERNAL", 500),
+                ("USER_NOT_FOUND", 404),
+                ("USERNAME_TAKEN", 409),
+                ("EMAIL_TAKEN", 409),
+                ("FIELD_NOT_WRITABLE", 403),
+                ("SIGNATURE_INVALID", 401),
+                ("SIGNATURE_EXPIRED", 401),
+                ("SIGNATURE_REPLAYED", 401),
+                ("OPERATION_NOT_FOUND", 404),
+                ("READ_ONLY", 503),
+                ("DATABASE_UNAVAILABLE", 503),
+                ("ENDPOINT_DISABLED", 503),
+                ("PRECONDITION_FAILED", 412),
+                ("IDEMPOTENCY_KEY_IN_USE", 409),
+                ("IDEMPOTENCY_KEY_REUSED", 422),
+                ("TEAM_NOT_FOUND", 404),
+                ("TEAM_NAME_TAKEN", 409),
+                ("JOB_NOT_FOUND", 404),
+                ("EXPORT_NOT_FOUND", 404),
+                ("EMAIL_CHANGE_NOT_FOUND", 404),
+                ("UNSUPPORTED_MEDIA_TYPE", 415),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_service_errors_map_to_statuses() {
+        let missing = AppError::from(ServiceError::NotFound);
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+        assert_eq!(missing.code(), ErrorCode::USER_NOT_FOUND);
+        let taken = AppError::from(ServiceError::Taken("email".to_string()));
+        assert_eq!(taken.code(), ErrorCode::EMAIL_TAKEN);
+        assert_eq!(taken.to_string(), "email already exists");
+        let invalid = AppError::from(ServiceError::Invalid(vec!["bad".to_string()]));
+        assert_eq!(invalid.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let down = AppError::from(ServiceError::Unavailable(ErrorReport::new("refused")));
+        assert_eq!(down.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(down.code(), ErrorCode::DATABASE_UNAVAILABLE);
+        let json = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        assert_eq!(AppError::from(json).status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_problem_body_and_negotiation() {
+        assert!(wants_problem(Some("application/problem+json"), ErrorFormat::Envelope));
+        assert!(wants_problem(None, ErrorFormat::Problem));
+        assert!(!wants_problem(Some("application/json"), ErrorFormat::Envelope));
+
+        let response = AppError::Validation(vec!["email is not a valid address".to_string()])
+            .into_response();
+        let problem = response.extensions().get::<Problem>().unwrap();
+        let body = problem.body(response.status(), "/api/v1/users");
+        assert_eq!(body["type"], "/problems/validation");
+        assert_eq!(body["status"], 422);
+        assert_eq!(body["instance"], "/api/v1/users");
+        assert_eq!(body["errors"][0], "email is not a valid address");
+    }
+
+    #[test]
+    fn test_rate_limited_rounds_retry_after_up() {
+        let response = AppError::RateLimited(Duration::from_millis(1_500)).into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "2");
+    }
+
+    #[tokio::test]
+    async fn test_unknown_routes_and_methods_get_json_bodies() {
+        use axum::{body::Body, routing::get, Router};
+        use tower::ServiceExt;
+
+        let app = Router::new()
+            .route("/users", get(|| async { "users" }))
+            .fallback(not_found)
+            .layer(axum::middleware::from_fn(method_not_allowed));
+
+        let req = Request::post("/users").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        let allow = response.headers()[header::ALLOW].to_str().unwrap().to_string();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], format!("method not allowed; allowed: {}", allow));
+        assert!(allow.contains("GET"));
+        assert!(allow.ends_with("OPTIONS"));
+
+        let req = Request::options("/users").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()[header::ALLOW], allow);
+
+        let req = Request::get("/nope").body(Body::empty()).unwrap();
+        let response = app.oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "no route for GET /nope");
+    }
+
+    #[test]
+    fn test_storage_errors_keep_their_source_chain() {
+        let io = std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "connection refused");
+        let error = AppError::from(sqlx::Error::Io(io));
+        let chain: Vec<String> = std::iter::successors(error.source(), |error| error.source())
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(chain.len(), 2);
+        assert_eq!(chain[1], "connection refused");
+
+        let response = error.into_response();
+        let problem = response.extensions().get::<Problem>().unwrap();
+        assert_eq!(problem.causes, chain);
+        assert_eq!(problem.detail, "internal error");
+    }
+
+    #[tokio::test]
+    async fn test_backend_detail_is_not_sent() {
+        let response = AppError::Database(ErrorReport::new("password authentication failed"))
+            .into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "internal error");
+        assert_eq!(body["error_code"], "INTERNAL");
+        assert_eq!(body["success"], false);
+    }
+}
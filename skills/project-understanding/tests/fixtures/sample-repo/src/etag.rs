@@ -0,0 +1,151 @@
+//! Entity tags and conditional requests on users.
+//!
+//! `GET /api/v1/users/:id` sends a strong `ETag` hashing the user as the
+//! caller may see it, and the list a weak one over every user it returns;
+//! diagnostic `meta` isn't covered. A request whose `If-None-Match` names the
+//! current tag gets a bodiless 304. Writes to a user honor `If-Match` for
+//! optimistic locking: when the tag no longer names the stored user, someone
+//! changed it since the caller read it, and the write is refused with 412
+//! `PRECONDITION_FAILED` rather than overwriting their change. The check runs
+//! just before the write, not inside it, so it guards against changes made
+//! while the caller was deciding, not against one racing the write itself.
+
+use async_trait::async_trait;
+use axum::{
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::convert::Infallible;
+
+use crate::auth::Role;
+use crate::error::AppError;
+
+/// Tag of one exact representation of `value` as `role` sees it
+pub fn strong<T: Serialize>(value: &T, role: Role) -> String {
+    format!("\"{}\"", digest(value, role))
+}
+
+/// Tag of a representation that stays equivalent, not byte-identical
+pub fn weak<T: Serialize>(value: &T, role: Role) -> String {
+    format!("W/{}", strong(value, role))
+}
+
+fn digest<T: Serialize>(value: &T, role: Role) -> String {
+    let mut hasher = Sha256::new();
+    // Admins see fields others don't, so each role gets its own tag
+    hasher.update(format!("{:?}", role));
+    hasher.update(serde_json::to_vec(value).unwrap_or_default());
+    hex::encode(&hasher.finalize()[..16])
+}
+
+/// The `If-None-Match` and `If-Match` headers of a request
+#[derive(Debug, Default)]
+pub struct Conditions {
+    if_none_match: Option<String>,
+    if_match: Option<String>,
+}
+
+impl Conditions {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let value = |name| headers.get(name).and_then(|value| value.to_str().ok());
+        Conditions {
+            if_none_match: value(header::IF_NONE_MATCH).map(str::to_string),
+            if_match: value(header::IF_MATCH).map(str::to_string),
+        }
+    }
+
+    /// Whether the caller sent `If-Match` and a write must be checked
+    pub fn has_if_match(&self) -> bool {
+        self.if_match.is_some()
+    }
+
+    /// Refuse a write unless `If-Match` is absent or names `etag`
+    pub fn check(&self, etag: &str) -> Result<(), AppError> {
+        match &self.if_match {
+            Some(tags) if !matches(tags, etag, true) => Err(AppError::PreconditionFailed(
+                "the user changed since it was read; fetch it again and retry".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// `response` tagged with `etag`, or a bare 304 if the caller already has it
+    pub fn respond(&self, etag: &str, response: impl IntoResponse) -> Response {
+        let not_modified =
+            self.if_none_match.as_deref().is_some_and(|tags| matches(tags, etag, false));
+        if not_modified {
+            tagged(etag, StatusCode::NOT_MODIFIED)
+        } else {
+            tagged(etag, response)
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Conditions {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Conditions::from_headers(&parts.headers))
+    }
+}
+
+/// `response` with `etag` as its `ETag` header
+pub fn tagged(etag: &str, response: impl IntoResponse) -> Response {
+    let mut response = response.into_response();
+    if let Ok(value) = HeaderValue::from_str(etag) {
+        response.headers_mut().insert(header::ETAG, value);
+    }
+    response
+}
+
+/// Whether a comma-separated tag list names `etag`; strong comparison skips weak tags
+fn matches(tags: &str, etag: &str, strong: bool) -> bool {
+    tags.split(',').map(str::trim).any(|tag| {
+        tag == "*"
+            || if strong {
+                !tag.starts_with("W/") && tag == etag
+            } else {
+                tag.trim_start_matches("W/") == etag.trim_start_matches("W/")
+            }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::User;
+
+    fn conditions(name: header::HeaderName, value: &str) -> Conditions {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        Conditions::from_headers(&headers)
+    }
+
+    #[test]
+    fn test_if_none_match_answers_not_modified_and_if_match_refuses_stale_writes() {
+        let mut alice = User::new("alice".to_string(), "alice@example.com".to_string());
+        let etag = strong(&alice, Role::Public);
+        assert_ne!(etag, strong(&alice, Role::Admin));
+
+        let cached = conditions(header::IF_NONE_MATCH, &format!("\"other\", W/{}", etag));
+        let response = cached.respond(&etag, "body");
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::ETAG], etag.as_str());
+        let fresh = Conditions::default().respond(&etag, "body");
+        assert_eq!(fresh.status(), StatusCode::OK);
+
+        let read = conditions(header::IF_MATCH, &etag);
+        assert!(read.check(&etag).is_ok());
+        alice.username = "alice2".to_string();
+        let error = read.check(&strong(&alice, Role::Public)).unwrap_err();
+        assert_eq!(error.status(), StatusCode::PRECONDITION_FAILED);
+        // A weak tag never satisfies `If-Match`
+        let weak_read = conditions(header::IF_MATCH, &format!("W/{}", etag));
+        assert!(weak_read.check(&etag).is_err());
+        assert!(conditions(header::IF_MATCH, "*").check(&etag).is_ok());
+    }
+}
@@ -0,0 +1,161 @@
+//! Domain events and the in-process event bus.
+//!
+//! Handlers publish an event after each successful mutation; subscribers
+//! such as webhook delivery consume them from a broadcast channel.
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::replication::UserStamps;
+use crate::User;
+
+/// Kind of user lifecycle event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum EventKind {
+    /// A user was created
+    #[serde(rename = "user.created")]
+    UserCreated,
+    /// A user was modified
+    #[serde(rename = "user.updated")]
+    UserUpdated,
+    /// A user was deleted
+    #[serde(rename = "user.deleted")]
+    UserDeleted,
+}
+
+impl EventKind {
+    /// Wire name of the event
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::UserCreated => "user.created",
+            EventKind::UserUpdated => "user.updated",
+            EventKind::UserDeleted => "user.deleted",
+        }
+    }
+}
+
+/// An event describing a completed mutation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DomainEvent {
+    /// Unique identifier
+    pub id: Uuid,
+    /// Kind of event
+    #[serde(rename = "type")]
+    pub kind: EventKind,
+    /// When the mutation happened
+    pub occurred_at: chrono::DateTime<chrono::Utc>,
+    /// State of the user after the mutation
+    pub user: User,
+    /// Region the mutation was made in, under `Config::replication`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// Replication stamps of the user's fields, under `Config::replication`
+    #[serde(default, skip_serializing_if = "UserStamps::is_empty")]
+    pub stamps: UserStamps,
+}
+
+impl DomainEvent {
+    /// Create an event for a user
+    pub fn user(kind: EventKind, user: &User) -> Self {
+        DomainEvent {
+            id: Uuid::new_v4(),
+            kind,
+            occurred_at: chrono::Utc::now(),
+            user: user.clone(),
+            region: None,
+            stamps: UserStamps::new(),
+        }
+    }
+}
+
+/// Broadcast bus for domain events.
+///
+/// The most recent events are also kept so a reconnecting subscriber can
+/// resume from the last event it saw.
+pub struct EventBus {
+    sender: broadcast::Sender<DomainEvent>,
+    history: Mutex<VecDeque<DomainEvent>>,
+    history_capacity: usize,
+}
+
+impl EventBus {
+    /// Create a bus buffering up to `capacity` events per slow subscriber
+    /// and retaining as many for resumption
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self {
+            sender,
+            history: Mutex::new(VecDeque::with_capacity(capacity)),
+            history_capacity: capacity,
+        }
+    }
+
+    /// Publish an event; having no subscribers is not an error
+    pub fn publish(&self, event: DomainEvent) {
+        // Send under the history lock so `subscribe_after` sees each event
+        // either in the replay or on the receiver, never both or neither
+        let mut history = self.history.lock().expect("event history lock poisoned");
+        if history.len() == self.history_capacity {
+            history.pop_front();
+        }
+        history.push_back(event.clone());
+        let _ = self.sender.send(event);
+    }
+
+    /// Subscribe to events published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<DomainEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Subscribe, also returning retained events published after `last_id`.
+    ///
+    /// The replay is `None` when `last_id` is no longer retained, meaning the
+    /// subscriber may have missed events.
+    pub fn subscribe_after(
+        &self,
+        last_id: Uuid,
+    ) -> (Option<Vec<DomainEvent>>, broadcast::Receiver<DomainEvent>) {
+        let history = self.history.lock().expect("event history lock poisoned");
+        let receiver = self.sender.subscribe();
+        let replay = history
+            .iter()
+            .position(|event| event.id == last_id)
+            .map(|index| history.iter().skip(index + 1).cloned().collect());
+        (replay, receiver)
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new(1024)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscribe_after_replays_newer_events() {
+        let bus = EventBus::new(2);
+        let user = User::new("alice".to_string(), "alice@example.com".to_string());
+        let first = DomainEvent::user(EventKind::UserCreated, &user);
+        let second = DomainEvent::user(EventKind::UserUpdated, &user);
+        let third = DomainEvent::user(EventKind::UserDeleted, &user);
+        let (first_id, second_id, third_id) = (first.id, second.id, third.id);
+        bus.publish(first);
+        bus.publish(second);
+        bus.publish(third);
+
+        let (replay, _) = bus.subscribe_after(second_id);
+        let replay = replay.unwrap();
+        assert_eq!(replay.len(), 1);
+        assert_eq!(replay[0].id, third_id);
+
+        // Evicted from the two-event history
+        assert!(bus.subscribe_after(first_id).0.is_none());
+    }
+}
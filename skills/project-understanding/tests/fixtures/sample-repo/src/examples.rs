@@ -0,0 +1,212 @@
+//! Ready-to-run request examples derived from the OpenAPI spec.
+//!
+//! `GET /api/v1/admin/routes/:name/examples` takes an operation ID such as
+//! `create_user` and returns curl and HTTPie commands for it. Bodies are
+//! built from the request schema, preferring the examples declared on the
+//! schema fields; admin routes get a `$ADMIN_TOKEN` placeholder.
+
+use axum::{
+    extract::{Path, State},
+    response::Json,
+};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::sync::Arc;
+use utoipa::OpenApi;
+
+use crate::auth::Admin;
+use crate::error::AppError;
+use crate::handlers::ROUTES;
+use crate::openapi::ApiDoc;
+use crate::{ApiResponse, AppState};
+
+/// A documented operation with everything needed to call it
+#[derive(Debug, Clone, Serialize)]
+pub struct OperationInfo {
+    /// Operation ID
+    pub name: String,
+    /// HTTP method, uppercase
+    pub method: String,
+    /// Path with `{param}` placeholders
+    pub path: String,
+    /// First tag, used to group operations
+    pub tag: Option<String>,
+    /// One-line summary
+    pub summary: Option<String>,
+    /// Whether the admin bearer token is required
+    pub admin: bool,
+    /// Example JSON body, if the operation takes one
+    pub body: Option<Value>,
+}
+
+/// The spec as plain JSON, which is easier to walk than the typed model
+pub fn spec() -> Value {
+    serde_json::to_value(ApiDoc::openapi()).expect("OpenAPI spec serializes")
+}
+
+/// Example value for a schema, resolving `$ref`s against the spec
+pub fn example_for(schema: &Value, spec: &Value) -> Value {
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        let pointer = reference.trim_start_matches('#');
+        return spec
+            .pointer(pointer)
+            .map_or(Value::Null, |target| example_for(target, spec));
+    }
+    if let Some(example) = schema.get("example") {
+        return example.clone();
+    }
+    let format = schema.get("format").and_then(Value::as_str);
+    match schema.get("type").and_then(Value::as_str) {
+        Some("object") => {
+            let required: Vec<&str> = schema
+                .get("required")
+                .and_then(Value::as_array)
+                .map(|fields| fields.iter().filter_map(Value::as_str).collect())
+                .unwrap_or_default();
+            let mut object = Map::new();
+            if let Some(properties) = schema.get("properties").and_then(Value::as_object) {
+                for (name, property) in properties {
+                    if required.contains(&name.as_str()) {
+                        object.insert(name.clone(), example_for(property, spec));
+                    }
+                }
+            }
+            Value::Object(object)
+        }
+        Some("array") => {
+            let item = schema.get("items").map_or(Value::Null, |items| example_for(items, spec));
+            json!([item])
+        }
+        Some("string") => match format {
+            Some("uuid") => json!("3fa85f64-5717-4562-b3fc-2c963f66afa6"),
+            Some("date-time") => json!("2024-01-01T00:00:00Z"),
+            Some("email") => json!("user@example.com"),
+            _ => json!("string"),
+        },
+        Some("integer") => json!(0),
+        Some("number") => json!(0.0),
+        Some("boolean") => json!(true),
+        _ => Value::Null,
+    }
+}
+
+/// Every documented operation
+pub fn operations() -> Vec<OperationInfo> {
+    let spec = spec();
+    let mut operations = Vec::new();
+    let Some(paths) = spec.get("paths").and_then(Value::as_object) else {
+        return operations;
+    };
+    for (path, item) in paths {
+        let Some(methods) = item.as_object() else {
+            continue;
+        };
+        let pattern = path.replace('{', ":").replace('}', "");
+        for (method, operation) in methods {
+            let Some(name) = operation.get("operationId").and_then(Value::as_str) else {
+                continue;
+            };
+            let method = method.to_uppercase();
+            let admin = ROUTES
+                .iter()
+                .any(|route| route.admin && route.method == method && route.path == pattern);
+            let body = operation
+                .pointer("/requestBody/content/application~1json/schema")
+                .map(|schema| example_for(schema, &spec));
+            operations.push(OperationInfo {
+                name: name.to_string(),
+                method,
+                path: path.clone(),
+                tag: operation.pointer("/tags/0").and_then(Value::as_str).map(str::to_string),
+                summary: operation.get("summary").and_then(Value::as_str).map(str::to_string),
+                admin,
+                body,
+            });
+        }
+    }
+    operations
+}
+
+/// Quote for a POSIX shell
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Example commands for one operation
+#[derive(Debug, Serialize)]
+pub struct Examples {
+    /// The operation
+    pub operation: OperationInfo,
+    /// curl command
+    pub curl: String,
+    /// HTTPie command
+    pub httpie: String,
+}
+
+impl Examples {
+    /// Build commands against `base_url`
+    pub fn new(operation: OperationInfo, base_url: &str) -> Self {
+        let path = operation.path.replace('{', "<").replace('}', ">");
+        let url = shell_quote(&format!("{}{}", base_url, path));
+        let body = operation.body.as_ref().map(|body| shell_quote(&body.to_string()));
+
+        let mut curl = format!("curl -X {} {}", operation.method, url);
+        let mut httpie = format!("http {} {}", operation.method, url);
+        if operation.admin {
+            curl.push_str(" -H \"Authorization: Bearer $ADMIN_TOKEN\"");
+            httpie.push_str(" \"Authorization:Bearer $ADMIN_TOKEN\"");
+        }
+        if let Some(body) = body {
+            curl.push_str(&format!(" -H 'Content-Type: application/json' -d {}", body));
+            httpie = format!("echo {} | {}", body, httpie);
+        }
+        Examples {
+            operation,
+            curl,
+            httpie,
+        }
+    }
+}
+
+/// Example commands for the named operation
+pub async fn route_examples(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    Path(name): Path<String>,
+) -> Result<Json<ApiResponse<Examples>>, AppError> {
+    let operation = operations()
+        .into_iter()
+        .find(|operation| operation.name == name)
+        .ok_or_else(|| AppError::NotFound(format!("no documented route named {}", name)))?;
+    let base_url = format!("http://{}:{}", state.config().host, state.config().port);
+    Ok(Json(ApiResponse::success(Examples::new(operation, &base_url))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::CreateUser;
+    use crate::validation::Validate;
+
+    fn operation(name: &str) -> OperationInfo {
+        operations().into_iter().find(|operation| operation.name == name).unwrap()
+    }
+
+    #[test]
+    fn test_example_body_passes_validation() {
+        let body = operation("create_user").body.unwrap();
+        let body: CreateUser = serde_json::from_value(body).unwrap();
+        assert!(body.validate().is_empty());
+    }
+
+    #[test]
+    fn test_admin_routes_get_placeholder_auth() {
+        let examples = Examples::new(operation("restore_user"), "http://localhost:8080");
+        assert_eq!(
+            examples.curl,
+            "curl -X POST 'http://localhost:8080/api/v1/users/<id>/restore' \
+             -H \"Authorization: Bearer $ADMIN_TOKEN\""
+        );
+        assert!(!Examples::new(operation("get_user"), "").curl.contains("ADMIN_TOKEN"));
+    }
+}
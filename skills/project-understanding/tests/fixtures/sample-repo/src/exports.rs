@@ -0,0 +1,311 @@
+//! Per-user data exports, for subject access requests.
+//!
+//! `POST /api/v1/users/:id/export` queues a `user_export` job and answers
+//! 202 with the export's ID, which is the job's. The job assembles
+//! everything stored about the user into one JSON archive: the profile,
+//! including soft-deleted users, when they were last seen, their avatar,
+//! their team memberships, and every audit entry about them. No sessions
+//! are stored server side, so there are none to export. The archive is kept
+//! in the blob store as `exports/<tenant>/<user>/<export>.json`.
+//!
+//! `GET /api/v1/users/:id/exports/:export` reports whether the export is
+//! pending, ready, or failed; `.../download` serves the archive, or
+//! redirects to a presigned URL when the blob store issues them.
+
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Json, Redirect, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::audit::{AuditEntry, AuditQuery};
+use crate::auth::Admin;
+use crate::avatars::{self, AvatarInfo};
+use crate::blobs::Blob;
+use crate::error::{AppError, ErrorCode, ErrorReport};
+use crate::jobs::{self, Failure, Job, JobQuery, JobStatus};
+use crate::service::{self, ServiceError};
+use crate::teams::{Membership, Team};
+use crate::tenancy::Tenant;
+use crate::validation::ApiPath;
+use crate::{ApiResponse, AppState, User};
+
+/// Audit entities whose entries are about a user, keyed by the user's ID
+pub const AUDITED_AS: [&str; 2] = ["user", "user_avatar"];
+
+/// Payload of a `user_export` job
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportRequest {
+    /// Tenant of the user
+    pub tenant: String,
+    /// User to export
+    pub user_id: Uuid,
+}
+
+/// A team the user belongs to, and how
+#[derive(Debug, Clone, Serialize)]
+pub struct TeamMembership {
+    /// The team
+    pub team: Team,
+    /// The user's role in it
+    pub membership: Membership,
+}
+
+/// Everything stored about one user
+#[derive(Debug, Clone, Serialize)]
+pub struct UserArchive {
+    /// When the archive was assembled
+    pub exported_at: DateTime<Utc>,
+    /// The user's profile
+    pub profile: User,
+    /// When the user was last seen, if ever recorded
+    pub last_seen: Option<DateTime<Utc>>,
+    /// The user's avatar, if uploaded
+    pub avatar: Option<AvatarInfo>,
+    /// Teams the user belongs to
+    pub teams: Vec<TeamMembership>,
+    /// Audit entries about the user, newest first
+    pub audit: Vec<AuditEntry>,
+}
+
+/// Blob key of an export's archive
+fn key(tenant: &str, user_id: Uuid, export: Uuid) -> String {
+    format!("exports/{}/{}/{}.json", tenant, user_id, export)
+}
+
+/// Assemble the archive of a user loaded with `service::get_user`
+pub async fn assemble(state: &AppState, profile: User) -> Result<UserArchive, AppError> {
+    let (tenant, id) = (profile.tenant_id.as_str(), profile.id);
+    let last_seen = state.users.last_seen(tenant, id).await?;
+    let avatar = avatars::stored(state, tenant, id).await?;
+    let teams = state
+        .teams
+        .memberships_of(tenant, id)
+        .into_iter()
+        .map(|(team, membership)| TeamMembership { team, membership })
+        .collect();
+    let mut audit = Vec::new();
+    for entity in AUDITED_AS {
+        let query = AuditQuery {
+            entity: entity.to_string(),
+            id: Some(id),
+        };
+        audit.extend(state.audit.query(&query).await?);
+    }
+    audit.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+    Ok(UserArchive {
+        exported_at: Utc::now(),
+        profile,
+        last_seen,
+        avatar,
+        teams,
+        audit,
+    })
+}
+
+impl From<ServiceError> for Failure {
+    fn from(err: ServiceError) -> Self {
+        match err {
+            // The user was erased since the export was requested
+            ServiceError::NotFound => Failure::Permanent(err.to_string()),
+            err => Failure::Transient(err.to_string()),
+        }
+    }
+}
+
+/// Delete the archives of every export of a tenant's user
+pub async fn remove_archives(state: &AppState, tenant: &str, id: Uuid) -> Result<(), AppError> {
+    let query = JobQuery {
+        status: Some(JobStatus::Succeeded),
+        kind: Some(jobs::USER_EXPORT.to_string()),
+        limit: Some(1_000),
+    };
+    let request = ExportRequest {
+        tenant: tenant.to_string(),
+        user_id: id,
+    };
+    for job in state.jobs.list(&query).await? {
+        if serde_json::from_value::<ExportRequest>(job.payload).ok().as_ref() == Some(&request) {
+            state.blobs.delete(&key(tenant, id, job.id)).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Run a `user_export` job: assemble the archive and store it
+pub async fn run(state: &AppState, job: &Job) -> Result<(), Failure> {
+    let request: ExportRequest = serde_json::from_value(job.payload.clone())
+        .map_err(|err| Failure::Permanent(format!("invalid export payload: {}", err)))?;
+    let profile = service::get_user(state, &request.tenant, request.user_id, true).await?;
+    let archive = assemble(state, profile)
+        .await
+        .map_err(|err| Failure::Transient(err.to_string()))?;
+    let bytes = serde_json::to_vec_pretty(&archive)
+        .map_err(|err| Failure::Permanent(format!("archive can't be encoded: {}", err)))?;
+    let blob = Blob {
+        content_type: "application/json".to_string(),
+        bytes,
+    };
+    let key = key(&request.tenant, request.user_id, job.id);
+    state.blobs.put(&key, blob).await.map_err(|err| Failure::Transient(err.to_string()))
+}
+
+/// Where an export is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportState {
+    /// Queued or being assembled
+    Pending,
+    /// Ready to download
+    Ready,
+    /// Could not be assembled
+    Failed,
+}
+
+/// An export as reported to its requester
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportStatus {
+    /// Export identifier
+    pub id: Uuid,
+    /// User exported
+    pub user_id: Uuid,
+    /// Where the export is
+    pub state: ExportState,
+    /// When it was requested
+    pub requested_at: DateTime<Utc>,
+    /// Why it failed, if it did
+    pub error: Option<String>,
+}
+
+impl ExportStatus {
+    fn of(job: &Job, user_id: Uuid) -> Self {
+        let state = match job.status {
+            JobStatus::Queued | JobStatus::Running => ExportState::Pending,
+            JobStatus::Succeeded => ExportState::Ready,
+            JobStatus::Dead => ExportState::Failed,
+        };
+        ExportStatus {
+            id: job.id,
+            user_id,
+            state,
+            requested_at: job.created_at,
+            error: job.last_error.clone().filter(|_| state == ExportState::Failed),
+        }
+    }
+}
+
+/// The export job `export` of a tenant's user, if it is one
+async fn find(state: &AppState, tenant: &str, id: Uuid, export: Uuid) -> Result<Job, AppError> {
+    let missing = || {
+        AppError::NotFound(format!("export {} not found", export))
+            .with_code(ErrorCode::EXPORT_NOT_FOUND)
+    };
+    let job = state.jobs.get(export).await?.ok_or_else(missing)?;
+    let request = ExportRequest {
+        tenant: tenant.to_string(),
+        user_id: id,
+    };
+    let mine = job.kind == jobs::USER_EXPORT
+        && serde_json::from_value::<ExportRequest>(job.payload.clone()).ok() == Some(request);
+    if mine {
+        Ok(job)
+    } else {
+        Err(missing())
+    }
+}
+
+/// Queue an export of everything stored about a user
+pub async fn request_export(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    Tenant(tenant): Tenant,
+    ApiPath(id): ApiPath<Uuid>,
+) -> Result<(StatusCode, Json<ApiResponse<ExportStatus>>), AppError> {
+    state.services.users.get(&state, &tenant, id, true).await?;
+    let request = ExportRequest {
+        tenant,
+        user_id: id,
+    };
+    let payload =
+        serde_json::to_value(&request).map_err(|err| AppError::Internal(ErrorReport::new(err)))?;
+    let job = state.jobs.enqueue(&state.config().jobs, jobs::USER_EXPORT, payload).await?;
+    Ok((StatusCode::ACCEPTED, Json(ApiResponse::success(ExportStatus::of(&job, id)))))
+}
+
+/// Where an export is
+pub async fn get_export(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    Tenant(tenant): Tenant,
+    ApiPath((id, export)): ApiPath<(Uuid, Uuid)>,
+) -> Result<Json<ApiResponse<ExportStatus>>, AppError> {
+    let job = find(&state, &tenant, id, export).await?;
+    Ok(Json(ApiResponse::success(ExportStatus::of(&job, id))))
+}
+
+/// Download a ready export, or be redirected to where to download it
+pub async fn download_export(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    Tenant(tenant): Tenant,
+    ApiPath((id, export)): ApiPath<(Uuid, Uuid)>,
+) -> Result<Response, AppError> {
+    let job = find(&state, &tenant, id, export).await?;
+    if job.status != JobStatus::Succeeded {
+        let message = format!("export {} is not ready", export);
+        return Err(AppError::Conflict(message));
+    }
+    let key = key(&tenant, id, export);
+    if let Some(url) = state.blobs.presign(&key, state.config().blobs.presign_ttl()) {
+        return Ok(Redirect::temporary(&url).into_response());
+    }
+    let gone = || AppError::NotFound(format!("archive of export {} is gone", export));
+    let blob = state.blobs.get(&key).await?.ok_or_else(gone)?;
+    let disposition = format!("attachment; filename=\"user-{}-export.json\"", id);
+    let headers = [
+        (header::CONTENT_TYPE, blob.content_type),
+        (header::CONTENT_DISPOSITION, disposition),
+    ];
+    Ok((headers, blob.bytes).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuditContext;
+    use crate::tenancy::DEFAULT_TENANT as T;
+    use crate::Config;
+
+    #[tokio::test]
+    async fn test_export_job_stores_the_archive() {
+        let state = AppState::new(Config::default());
+        let context = AuditContext {
+            actor: "admin".to_string(),
+            request_id: "test".to_string(),
+            client_ip: None,
+        };
+        let user = service::create_user(&state, &context, T, "alice", "alice@example.com")
+            .await
+            .unwrap();
+        let request = ExportRequest {
+            tenant: T.to_string(),
+            user_id: user.id,
+        };
+        let payload = serde_json::to_value(&request).unwrap();
+        let job = state.jobs.enqueue(&state.config().jobs, jobs::USER_EXPORT, payload).await;
+        let job = job.unwrap();
+        assert!(state.jobs.work_one(&state).await.unwrap());
+
+        let job = find(&state, T, user.id, job.id).await.unwrap();
+        assert_eq!(ExportStatus::of(&job, user.id).state, ExportState::Ready);
+        let blob = state.blobs.get(&key(T, user.id, job.id)).await.unwrap().unwrap();
+        let archive: serde_json::Value = serde_json::from_slice(&blob.bytes).unwrap();
+        assert_eq!(archive["profile"]["username"], "alice");
+        assert_eq!(archive["audit"][0]["action"], "create");
+        assert!(find(&state, T, Uuid::new_v4(), job.id).await.is_err());
+    }
+}
@@ -0,0 +1,308 @@
+//! Per-tenant fair sharing of request capacity.
+//!
+//! Tenants are the ones `tenancy` resolves, as for debug targets;
+//! requests without one share the `default` tenant. With
+//! `Config::fairness.enabled`, each tenant with requests in flight or
+//! waiting gets a quota of `max_in_flight` proportional to its weight among
+//! those tenants, so a lone tenant can use everything and a noisy one is
+//! held to its share once others show up. Requests over quota wait up to
+//! `queue_timeout_ms` for a slot and are then refused with a 503. Critical
+//! requests (see `priority`) are never queued. Per-tenant figures are
+//! exported at `/metrics`.
+
+use axum::{
+    extract::State,
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+use crate::context::RequestContext;
+use crate::error::AppError;
+use crate::priority::Priority;
+use crate::tenancy::DEFAULT_TENANT;
+use crate::AppState;
+
+/// Tenant that tenants past `max_tenants` are counted under
+pub const OVERFLOW_TENANT: &str = "other";
+
+/// Fair sharing settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FairnessConfig {
+    /// Enforce per-tenant quotas
+    pub enabled: bool,
+    /// Requests in flight shared between tenants
+    pub max_in_flight: usize,
+    /// Relative share per tenant; unlisted tenants weigh 1
+    pub weights: HashMap<String, f64>,
+    /// Longest a request waits for its tenant's quota
+    pub queue_timeout_ms: u64,
+    /// Distinct tenants tracked; later ones share one bucket
+    pub max_tenants: usize,
+}
+
+impl Default for FairnessConfig {
+    fn default() -> Self {
+        FairnessConfig {
+            enabled: false,
+            max_in_flight: 256,
+            weights: HashMap::new(),
+            queue_timeout_ms: 1_000,
+            max_tenants: 1_000,
+        }
+    }
+}
+
+impl FairnessConfig {
+    fn weight(&self, tenant: &str) -> f64 {
+        self.weights.get(tenant).copied().unwrap_or(1.0).max(f64::MIN_POSITIVE)
+    }
+}
+
+#[derive(Debug, Default)]
+struct TenantState {
+    in_flight: usize,
+    waiting: usize,
+    admitted: u64,
+    throttled: u64,
+}
+
+impl TenantState {
+    fn active(&self) -> bool {
+        self.in_flight > 0 || self.waiting > 0
+    }
+}
+
+type Tenants = HashMap<String, TenantState>;
+
+/// Current figures for one tenant
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TenantSnapshot {
+    /// Tenant ID
+    pub tenant: String,
+    /// Requests it may have in flight right now
+    pub quota: usize,
+    /// Requests in flight
+    pub in_flight: usize,
+    /// Requests waiting for quota
+    pub waiting: usize,
+    /// Requests admitted since startup
+    pub admitted: u64,
+    /// Requests refused after waiting since startup
+    pub throttled: u64,
+}
+
+/// Weighted per-tenant admission
+#[derive(Debug, Default)]
+pub struct FairScheduler {
+    tenants: Mutex<Tenants>,
+    released: Notify,
+}
+
+impl FairScheduler {
+    /// Quota of `tenant` given who else is active
+    fn quota(config: &FairnessConfig, tenants: &Tenants, tenant: &str) -> usize {
+        let others: f64 = tenants
+            .iter()
+            .filter(|(name, state)| name.as_str() != tenant && state.active())
+            .map(|(name, _)| config.weight(name))
+            .sum();
+        let weight = config.weight(tenant);
+        let share = config.max_in_flight as f64 * weight / (weight + others);
+        (share.floor() as usize).max(1)
+    }
+
+    /// Bucket a tenant is tracked under
+    fn bucket(config: &FairnessConfig, tenants: &Tenants, tenant: &str) -> String {
+        if tenants.contains_key(tenant) || tenants.len() < config.max_tenants {
+            tenant.to_string()
+        } else {
+            OVERFLOW_TENANT.to_string()
+        }
+    }
+
+    fn try_admit(&self, config: &FairnessConfig, tenant: &str) -> bool {
+        let mut tenants = self.tenants.lock().expect("fairness lock poisoned");
+        let total: usize = tenants.values().map(|state| state.in_flight).sum();
+        let quota = Self::quota(config, &tenants, tenant);
+        let state = tenants.entry(tenant.to_string()).or_default();
+        if total >= config.max_in_flight || state.in_flight >= quota {
+            return false;
+        }
+        state.in_flight += 1;
+        state.admitted += 1;
+        true
+    }
+
+    /// Wait for a slot within the tenant's quota, up to the queue timeout
+    async fn admit(&self, config: &FairnessConfig, tenant: &str) -> Option<TenantPermit<'_>> {
+        let tenant = {
+            let tenants = self.tenants.lock().expect("fairness lock poisoned");
+            Self::bucket(config, &tenants, tenant)
+        };
+        let waiting = Waiting::enter(self, &tenant);
+        let deadline = tokio::time::Instant::now() + Duration::from_millis(config.queue_timeout_ms);
+        loop {
+            // Register interest before checking so a release in between isn't missed
+            let released = self.released.notified();
+            if self.try_admit(config, &tenant) {
+                drop(waiting);
+                return Some(TenantPermit {
+                    scheduler: self,
+                    tenant,
+                });
+            }
+            if tokio::time::timeout_at(deadline, released).await.is_err() {
+                drop(waiting);
+                let mut tenants = self.tenants.lock().expect("fairness lock poisoned");
+                tenants.entry(tenant).or_default().throttled += 1;
+                return None;
+            }
+        }
+    }
+
+    /// Figures for every tracked tenant, by tenant ID
+    pub fn snapshot(&self, config: &FairnessConfig) -> Vec<TenantSnapshot> {
+        let tenants = self.tenants.lock().expect("fairness lock poisoned");
+        let mut snapshot: Vec<_> = tenants
+            .iter()
+            .map(|(tenant, state)| TenantSnapshot {
+                tenant: tenant.clone(),
+                quota: Self::quota(config, &tenants, tenant),
+                in_flight: state.in_flight,
+                waiting: state.waiting,
+                admitted: state.admitted,
+                throttled: state.throttled,
+            })
+            .collect();
+        snapshot.sort_by(|a, b| a.tenant.cmp(&b.tenant));
+        snapshot
+    }
+}
+
+/// Counts a request as waiting until dropped, even if the client goes away
+struct Waiting<'a> {
+    scheduler: &'a FairScheduler,
+    tenant: String,
+}
+
+impl<'a> Waiting<'a> {
+    fn enter(scheduler: &'a FairScheduler, tenant: &str) -> Self {
+        let mut tenants = scheduler.tenants.lock().expect("fairness lock poisoned");
+        tenants.entry(tenant.to_string()).or_default().waiting += 1;
+        Self {
+            scheduler,
+            tenant: tenant.to_string(),
+        }
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        let mut tenants = self.scheduler.tenants.lock().expect("fairness lock poisoned");
+        if let Some(state) = tenants.get_mut(&self.tenant) {
+            state.waiting -= 1;
+        }
+    }
+}
+
+/// An admitted request, released when dropped
+struct TenantPermit<'a> {
+    scheduler: &'a FairScheduler,
+    tenant: String,
+}
+
+impl Drop for TenantPermit<'_> {
+    fn drop(&mut self) {
+        let mut tenants = self.scheduler.tenants.lock().expect("fairness lock poisoned");
+        if let Some(state) = tenants.get_mut(&self.tenant) {
+            state.in_flight -= 1;
+        }
+        drop(tenants);
+        self.scheduler.released.notify_waiters();
+    }
+}
+
+/// Middleware holding each tenant to its fair share of capacity
+pub async fn fair_share<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let current = state.config();
+    let config = &current.fairness;
+    let critical = req.extensions().get::<Priority>() == Some(&Priority::Critical);
+    if !config.enabled || critical {
+        return next.run(req).await;
+    }
+    let tenant = req
+        .extensions()
+        .get::<RequestContext>()
+        .and_then(|context| context.tenant.as_deref())
+        .unwrap_or(DEFAULT_TENANT)
+        .to_string();
+    let Some(permit) = state.fair_scheduler.admit(config, &tenant).await else {
+        let message = format!("tenant {} is over its share of capacity", tenant);
+        return AppError::Overloaded(message).into_response();
+    };
+    let response = next.run(req).await;
+    drop(permit);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> FairnessConfig {
+        FairnessConfig {
+            enabled: true,
+            max_in_flight: 6,
+            weights: HashMap::from([("big".to_string(), 2.0)]),
+            queue_timeout_ms: 10,
+            ..FairnessConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lone_tenant_uses_everything_until_others_arrive() {
+        let config = config();
+        let scheduler = FairScheduler::default();
+        let mut held = Vec::new();
+        for _ in 0..5 {
+            held.push(scheduler.admit(&config, "small").await.unwrap());
+        }
+        // "big" weighs 2 of 3, so it may have 4 of the 6 slots, but only one is free
+        held.push(scheduler.admit(&config, "big").await.unwrap());
+        assert!(scheduler.admit(&config, "big").await.is_none());
+
+        let small = |snapshot: &[TenantSnapshot]| snapshot[1].clone();
+        let snapshot = scheduler.snapshot(&config);
+        assert_eq!((snapshot[0].quota, snapshot[0].throttled), (4, 1));
+        assert_eq!((small(&snapshot).quota, small(&snapshot).in_flight), (2, 5));
+        // Over quota, "small" has to wait even as its own requests finish
+        held.remove(0);
+        assert!(scheduler.admit(&config, "small").await.is_none());
+        assert!(scheduler.admit(&config, "big").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_tenants_past_the_cap_share_a_bucket() {
+        let config = FairnessConfig {
+            max_tenants: 1,
+            ..config()
+        };
+        let scheduler = FairScheduler::default();
+        let _first = scheduler.admit(&config, "a").await.unwrap();
+        let _second = scheduler.admit(&config, "b").await.unwrap();
+        let tenants: Vec<_> =
+            scheduler.snapshot(&config).into_iter().map(|tenant| tenant.tenant).collect();
+        assert_eq!(tenants, vec!["a".to_string(), OVERFLOW_TENANT.to_string()]);
+    }
+}
@@ -0,0 +1,256 @@
+//! Feature flags gating experimental endpoints and behaviors.
+//!
+//! A flag is a name that is on or off. `Config::feature_flags` sets flags
+//! per environment, in a profile's defaults or the configuration file, and
+//! takes effect on reload. An admin overrides one on this instance with `PUT
+//! /api/v1/admin/feature-flags/:name` and hands it back to the configuration
+//! with `DELETE`. A flag named in neither is off. Overrides live in memory on
+//! each instance, the way read-only mode does, so a restart goes by the
+//! configuration again.
+//!
+//! Code declares a flag as a type implementing `Flag`. A handler taking
+//! `Enabled<F>` answers 404 while `F` is off, as if the route didn't exist,
+//! and other code branches on `feature_flags::enabled(&state, F::NAME)`.
+
+use async_trait::async_trait;
+use axum::{
+    extract::{FromRequestParts, State},
+    http::{request::Parts, StatusCode},
+    response::Json,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::sync::{Arc, RwLock};
+
+use crate::auth::Admin;
+use crate::error::AppError;
+use crate::validation::ApiPath;
+use crate::{ApiResponse, AppState};
+
+/// Longest flag name accepted
+const MAX_NAME_LEN: usize = 64;
+
+/// A flag code can be gated on
+pub trait Flag {
+    /// Name the flag is configured and overridden by
+    const NAME: &'static str;
+}
+
+/// Why a flag name is unusable, if it is
+pub fn check_name(name: &str) -> Option<String> {
+    let valid = name
+        .bytes()
+        .all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit() || byte == b'_');
+    if name.is_empty() || name.len() > MAX_NAME_LEN || !valid {
+        Some(format!(
+            "feature flag {:?} must be 1 to {} lowercase letters, digits, or underscores",
+            name, MAX_NAME_LEN
+        ))
+    } else {
+        None
+    }
+}
+
+/// A flag set at runtime, ahead of the configuration
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FlagOverride {
+    /// Whether the flag is on
+    pub enabled: bool,
+    /// Note for other operators
+    pub reason: Option<String>,
+    /// When the override was set
+    pub since: chrono::DateTime<chrono::Utc>,
+}
+
+/// One flag, as reported by the admin API
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FlagStatus {
+    /// Flag name
+    pub name: String,
+    /// Whether the flag is on now
+    pub enabled: bool,
+    /// Value in the configuration, if it names the flag
+    pub configured: Option<bool>,
+    /// Runtime override, if one is set
+    pub overridden: Option<FlagOverride>,
+}
+
+/// Request body for overriding a flag
+#[derive(Debug, Deserialize)]
+pub struct SetFlag {
+    /// Turn the flag on or off
+    pub enabled: bool,
+    /// Note for other operators, e.g. the experiment being run
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// Runtime overrides of the configured flags
+#[derive(Debug, Default)]
+pub struct FeatureFlags {
+    overrides: RwLock<BTreeMap<String, FlagOverride>>,
+}
+
+impl FeatureFlags {
+    /// Whether `name` is on, given the configured flags
+    pub fn is_enabled(&self, configured: &BTreeMap<String, bool>, name: &str) -> bool {
+        let overrides = self.overrides.read().expect("feature flags lock poisoned");
+        match overrides.get(name) {
+            Some(overridden) => overridden.enabled,
+            None => configured.get(name).copied().unwrap_or(false),
+        }
+    }
+
+    /// Override a flag, returning the override
+    pub fn set(&self, name: &str, enabled: bool, reason: Option<String>) -> FlagOverride {
+        let overridden = FlagOverride {
+            enabled,
+            reason,
+            since: chrono::Utc::now(),
+        };
+        let mut overrides = self.overrides.write().expect("feature flags lock poisoned");
+        overrides.insert(name.to_string(), overridden.clone());
+        overridden
+    }
+
+    /// Remove a flag's override, returning it if there was one
+    pub fn clear(&self, name: &str) -> Option<FlagOverride> {
+        self.overrides.write().expect("feature flags lock poisoned").remove(name)
+    }
+
+    /// Every flag configured or overridden, by name
+    pub fn statuses(&self, configured: &BTreeMap<String, bool>) -> Vec<FlagStatus> {
+        let overrides = self.overrides.read().expect("feature flags lock poisoned");
+        let mut names: Vec<&String> = configured.keys().chain(overrides.keys()).collect();
+        names.sort();
+        names.dedup();
+        names
+            .into_iter()
+            .map(|name| {
+                let overridden = overrides.get(name).cloned();
+                let configured = configured.get(name).copied();
+                FlagStatus {
+                    name: name.clone(),
+                    enabled: overridden
+                        .as_ref()
+                        .map_or(configured.unwrap_or(false), |overridden| overridden.enabled),
+                    configured,
+                    overridden,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Whether `name` is on for this instance right now
+pub fn enabled(state: &AppState, name: &str) -> bool {
+    state.feature_flags.is_enabled(&state.config().feature_flags, name)
+}
+
+/// Extractor succeeding only while `F` is on; off, the route answers 404
+pub struct Enabled<F>(PhantomData<F>);
+
+#[async_trait]
+impl<F: Flag> FromRequestParts<Arc<AppState>> for Enabled<F> {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        if enabled(state, F::NAME) {
+            return Ok(Enabled(PhantomData));
+        }
+        let message = format!("no route for {} {}", parts.method, parts.uri.path());
+        Err(AppError::NotFound(message))
+    }
+}
+
+/// Every flag configured or overridden
+pub async fn list_feature_flags(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+) -> Json<ApiResponse<Vec<FlagStatus>>> {
+    let statuses = state.feature_flags.statuses(&state.config().feature_flags);
+    Json(ApiResponse::success(statuses))
+}
+
+/// Override a flag on this instance
+pub async fn set_feature_flag(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    ApiPath(name): ApiPath<String>,
+    Json(request): Json<SetFlag>,
+) -> Result<Json<ApiResponse<FlagOverride>>, AppError> {
+    if let Some(problem) = check_name(&name) {
+        return Err(AppError::BadRequest(problem));
+    }
+    let note = request.reason.as_deref().unwrap_or("no reason given");
+    let switched = if request.enabled { "on" } else { "off" };
+    tracing::warn!("feature flag {} switched {}: {}", name, switched, note);
+    let overridden = state.feature_flags.set(&name, request.enabled, request.reason);
+    Ok(Json(ApiResponse::success(overridden)))
+}
+
+/// Hand a flag back to the configuration
+pub async fn clear_feature_flag(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    ApiPath(name): ApiPath<String>,
+) -> StatusCode {
+    if state.feature_flags.clear(&name).is_none() && !state.config().idempotent_deletes {
+        return StatusCode::NOT_FOUND;
+    }
+    tracing::info!("feature flag {} follows the configuration again", name);
+    StatusCode::NO_CONTENT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use tower::ServiceExt;
+
+    struct Beta;
+
+    impl Flag for Beta {
+        const NAME: &'static str = "beta_search";
+    }
+
+    async fn beta(_flag: Enabled<Beta>) -> &'static str {
+        "beta"
+    }
+
+    #[tokio::test]
+    async fn test_override_wins_over_config_until_cleared() {
+        let state = AppState::new(crate::Config {
+            feature_flags: BTreeMap::from([(Beta::NAME.to_string(), true)]),
+            ..crate::Config::default()
+        });
+        let app = Router::new().route("/beta", get(beta)).with_state(state.clone());
+        let status = |app: Router| async move {
+            let request = Request::get("/beta").body(Body::empty()).unwrap();
+            app.oneshot(request).await.unwrap().status()
+        };
+        assert_eq!(status(app.clone()).await, StatusCode::OK);
+
+        state.feature_flags.set(Beta::NAME, false, Some("bad results".to_string()));
+        assert_eq!(status(app.clone()).await, StatusCode::NOT_FOUND);
+        let statuses = state.feature_flags.statuses(&state.config().feature_flags);
+        assert_eq!(statuses.len(), 1);
+        assert_eq!((statuses[0].enabled, statuses[0].configured), (false, Some(true)));
+
+        assert!(state.feature_flags.clear(Beta::NAME).is_some());
+        assert_eq!(status(app).await, StatusCode::OK);
+        assert!(!enabled(&state, "never_configured"));
+    }
+
+    #[test]
+    fn test_names_are_checked() {
+        assert_eq!(check_name("beta_search_2"), None);
+        for name in ["", "Beta", "beta-search", &"x".repeat(65)] {
+            assert!(check_name(name).is_some(), "{:?}", name);
+        }
+    }
+}
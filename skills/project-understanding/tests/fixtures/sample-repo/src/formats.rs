@@ -0,0 +1,200 @@
+//! MessagePack and CBOR as alternatives to JSON on the wire.
+//!
+//! Clients preferring `application/msgpack` or `application/cbor` to JSON
+//! in `Accept` get response envelopes in that format. `encode_responses`
+//! re-encodes the JSON body once redaction, problem details, and `meta` have
+//! had their say, so every format carries the same fields; JSON wins ties,
+//! and responses that aren't JSON, such as event streams and metrics, pass
+//! through. Compression only handles JSON, so binary bodies go out as they
+//! are.
+//!
+//! Request bodies sent as either format are turned into JSON by
+//! `decode_requests` before handlers see them, so every JSON extractor
+//! accepts them. It runs inside `verify_signature`, so signatures cover the
+//! bytes as sent. A body that doesn't decode is a 400.
+
+use axum::{
+    body::{boxed, Body, Full},
+    http::{header, HeaderMap, HeaderValue, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::error::AppError;
+
+/// A binary format we read and write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// MessagePack, with maps keyed by field name
+    MessagePack,
+    /// CBOR
+    Cbor,
+}
+
+impl Format {
+    /// Media type responses are labelled with
+    pub fn mime(self) -> &'static str {
+        match self {
+            Format::MessagePack => "application/msgpack",
+            Format::Cbor => "application/cbor",
+        }
+    }
+
+    /// Format of a media type, including the older MessagePack names
+    fn of(mime: &str) -> Option<Self> {
+        let mime = mime.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+        match mime.as_str() {
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Format::MessagePack)
+            }
+            "application/cbor" => Some(Format::Cbor),
+            _ => None,
+        }
+    }
+
+    /// Binary format the client prefers to JSON, if any
+    fn negotiate(headers: &HeaderMap) -> Option<Self> {
+        let mut json = 0.0_f32;
+        let mut best: Option<(Self, f32)> = None;
+        let accepted = headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','));
+        for entry in accepted {
+            let mut params = entry.split(';');
+            let mime = params.next().unwrap_or_default().trim();
+            let quality = params
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|quality| quality.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            match Self::of(mime) {
+                Some(format) if best.map_or(true, |(_, current)| quality > current) => {
+                    best = Some((format, quality));
+                }
+                Some(_) => {}
+                None if mime.ends_with("json") || mime == "*/*" || mime == "application/*" => {
+                    json = json.max(quality);
+                }
+                None => {}
+            }
+        }
+        best.filter(|&(_, quality)| quality > 0.0 && quality > json).map(|(format, _)| format)
+    }
+
+    fn encode(self, value: &serde_json::Value) -> Result<Vec<u8>, String> {
+        match self {
+            Format::MessagePack => rmp_serde::to_vec_named(value).map_err(|err| err.to_string()),
+            Format::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::ser::into_writer(value, &mut bytes).map_err(|err| err.to_string())?;
+                Ok(bytes)
+            }
+        }
+    }
+
+    fn decode(self, bytes: &[u8]) -> Result<serde_json::Value, String> {
+        match self {
+            Format::MessagePack => rmp_serde::from_slice(bytes).map_err(|err| err.to_string()),
+            Format::Cbor => ciborium::de::from_reader(bytes).map_err(|err| err.to_string()),
+        }
+    }
+}
+
+/// Middleware turning MessagePack and CBOR request bodies into JSON
+pub async fn decode_requests(req: Request<Body>, next: Next<Body>) -> Response {
+    let format = req.headers().get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok());
+    let Some(format) = format.and_then(Format::of) else {
+        return next.run(req).await;
+    };
+    let (mut parts, body) = req.into_parts();
+    let decoded = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => format.decode(&bytes),
+        Err(err) => Err(err.to_string()),
+    };
+    let json = decoded.and_then(|value| serde_json::to_vec(&value).map_err(|err| err.to_string()));
+    let json = match json {
+        Ok(json) => json,
+        Err(err) => {
+            let message = format!("request body is not valid {}: {}", format.mime(), err);
+            return AppError::BadRequest(message).into_response();
+        }
+    };
+    let json_type = HeaderValue::from_static("application/json");
+    parts.headers.insert(header::CONTENT_TYPE, json_type);
+    parts.headers.insert(header::CONTENT_LENGTH, HeaderValue::from(json.len()));
+    next.run(Request::from_parts(parts, Body::from(json))).await
+}
+
+/// Middleware re-encoding JSON envelopes for clients preferring a binary format
+pub async fn encode_responses<B>(req: Request<B>, next: Next<B>) -> Response {
+    let format = Format::negotiate(req.headers());
+    let mut response = next.run(req).await;
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    if !is_json {
+        return response;
+    }
+    response.headers_mut().append(header::VARY, HeaderValue::from_static("accept"));
+    let Some(format) = format else {
+        return response;
+    };
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            tracing::error!("failed to buffer response for {}: {}", format.mime(), err);
+            return Response::from_parts(parts, boxed(Body::empty()));
+        }
+    };
+    let encoded = serde_json::from_slice::<serde_json::Value>(&bytes)
+        .map_err(|err| err.to_string())
+        .and_then(|value| format.encode(&value));
+    match encoded {
+        Ok(encoded) => {
+            parts.headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(format.mime()));
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Response::from_parts(parts, boxed(Full::from(encoded)))
+        }
+        // Answering in JSON beats not answering
+        Err(err) => {
+            tracing::warn!("failed to encode response as {}: {}", format.mime(), err);
+            Response::from_parts(parts, boxed(Full::from(bytes)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accept(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn test_binary_format_only_when_preferred_to_json() {
+        assert_eq!(Format::negotiate(&accept("application/msgpack")), Some(Format::MessagePack));
+        let preferred = accept("application/json;q=0.5, application/cbor");
+        assert_eq!(Format::negotiate(&preferred), Some(Format::Cbor));
+        assert_eq!(Format::negotiate(&accept("application/cbor, application/json")), None);
+        assert_eq!(Format::negotiate(&accept("*/*")), None);
+        assert_eq!(Format::negotiate(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn test_formats_round_trip_envelopes() {
+        let value = serde_json::json!({"success": true, "data": {"id": 7, "name": "alice"}});
+        for format in [Format::MessagePack, Format::Cbor] {
+            let bytes = format.encode(&value).unwrap();
+            assert_ne!(bytes, serde_json::to_vec(&value).unwrap());
+            assert_eq!(format.decode(&bytes).unwrap(), value);
+        }
+        assert!(Format::Cbor.decode(b"\xff\x00").is_err());
+    }
+}
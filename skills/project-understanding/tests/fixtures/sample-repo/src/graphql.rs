@@ -0,0 +1,323 @@
+//! GraphQL API alongside REST.
+//!
+//! Queries and mutations go through `services.users`, so visibility rules, events,
+//! and audit entries match the REST handlers. The schema is built once; the
+//! app state, caller identity, and tenant are attached to each request.
+//! Subscriptions are served over WebSocket at `/graphql/ws`.
+
+use async_graphql::{Context, Data, ErrorExtensions, Json, Object, Schema, Subscription, ID};
+use async_graphql_axum::{GraphQLProtocol, GraphQLRequest, GraphQLResponse, GraphQLWebSocket};
+use axum::{
+    extract::{ws::WebSocketUpgrade, State},
+    response::Response,
+};
+use futures::stream::{self, Stream};
+use std::sync::{Arc, OnceLock};
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+use crate::audit::AuditContext;
+use crate::auth::Admin;
+use crate::events::DomainEvent;
+use crate::repository::UserQuery;
+use crate::service::ServiceError;
+use crate::tenancy::Tenant;
+use crate::{metadata, AppState, User};
+
+/// The full schema type
+pub type UserSchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
+
+/// Whether the caller presented the admin token
+#[derive(Debug, Clone, Copy)]
+struct Viewer {
+    admin: bool,
+}
+
+/// GraphQL view of a user
+struct UserObject(User);
+
+#[Object(name = "User")]
+impl UserObject {
+    async fn id(&self) -> ID {
+        ID(self.0.id.to_string())
+    }
+
+    async fn username(&self) -> &str {
+        &self.0.username
+    }
+
+    async fn email(&self) -> &str {
+        &self.0.email
+    }
+
+    async fn created_at(&self) -> chrono::DateTime<chrono::Utc> {
+        self.0.created_at
+    }
+
+    async fn is_active(&self) -> bool {
+        self.0.is_active
+    }
+
+    async fn deleted_at(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.0.deleted_at
+    }
+
+    async fn metadata(&self) -> Json<&serde_json::Value> {
+        Json(&self.0.metadata)
+    }
+}
+
+/// GraphQL view of a user lifecycle event
+struct UserEventObject(DomainEvent);
+
+#[Object(name = "UserEvent")]
+impl UserEventObject {
+    async fn id(&self) -> ID {
+        ID(self.0.id.to_string())
+    }
+
+    /// Event type, e.g. `user.created`
+    #[graphql(name = "type")]
+    async fn kind(&self) -> &'static str {
+        self.0.kind.as_str()
+    }
+
+    async fn occurred_at(&self) -> chrono::DateTime<chrono::Utc> {
+        self.0.occurred_at
+    }
+
+    async fn user(&self) -> UserObject {
+        UserObject(self.0.user.clone())
+    }
+}
+
+impl ErrorExtensions for ServiceError {
+    fn extend(&self) -> async_graphql::Error {
+        let code = match self {
+            ServiceError::NotFound => "NOT_FOUND",
+            ServiceError::Taken(_) | ServiceError::Conflict(_) => "CONFLICT",
+            ServiceError::Invalid(_) => "INVALID",
+            ServiceError::Backend(_) => "INTERNAL",
+            ServiceError::Unavailable(_) => "UNAVAILABLE",
+            ServiceError::ReadOnly => "READ_ONLY",
+        };
+        // Backend detail is for logs, as with REST
+        let message = match self {
+            ServiceError::Backend(_) => "internal error".to_string(),
+            ServiceError::Unavailable(_) => "database unavailable".to_string(),
+            _ => self.to_string(),
+        };
+        async_graphql::Error::new(message).extend_with(|_, e| e.set("code", code))
+    }
+}
+
+fn app_state<'a>(ctx: &Context<'a>) -> &'a Arc<AppState> {
+    ctx.data_unchecked::<Arc<AppState>>()
+}
+
+/// Tenant of the request the operation came in on
+fn tenant<'a>(ctx: &Context<'a>) -> &'a str {
+    &ctx.data_unchecked::<Tenant>().0
+}
+
+fn parse_id(id: &ID) -> async_graphql::Result<Uuid> {
+    id.parse::<Uuid>().map_err(|_| {
+        async_graphql::Error::new("malformed user ID")
+            .extend_with(|_, e| e.set("code", "BAD_REQUEST"))
+    })
+}
+
+/// Reject `include_deleted` for non-admins, as the REST handlers do
+fn authorize_deleted(ctx: &Context<'_>, include_deleted: bool) -> async_graphql::Result<bool> {
+    if include_deleted && !ctx.data_unchecked::<Viewer>().admin {
+        return Err(forbidden());
+    }
+    Ok(include_deleted)
+}
+
+fn forbidden() -> async_graphql::Error {
+    async_graphql::Error::new("admin access required")
+        .extend_with(|_, e| e.set("code", "FORBIDDEN"))
+}
+
+/// Read operations
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// A user by ID
+    async fn user(
+        &self,
+        ctx: &Context<'_>,
+        id: ID,
+        #[graphql(default)] include_deleted: bool,
+    ) -> async_graphql::Result<Option<UserObject>> {
+        let include_deleted = authorize_deleted(ctx, include_deleted)?;
+        let id = parse_id(&id)?;
+        let state = app_state(ctx);
+        match state.services.users.get(state, tenant(ctx), id, include_deleted).await {
+            Ok(user) => Ok(Some(UserObject(user))),
+            Err(ServiceError::NotFound) => Ok(None),
+            Err(err) => Err(err.extend()),
+        }
+    }
+
+    /// All users
+    async fn users(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] include_deleted: bool,
+    ) -> async_graphql::Result<Vec<UserObject>> {
+        let query = UserQuery {
+            include_deleted: authorize_deleted(ctx, include_deleted)?,
+            ..UserQuery::default()
+        };
+        let state = app_state(ctx);
+        let users = state
+            .services
+            .users
+            .list(state, tenant(ctx), &query)
+            .await
+            .map_err(|err| err.extend())?;
+        Ok(users.into_iter().map(UserObject).collect())
+    }
+}
+
+/// Write operations
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    /// Create a user
+    async fn create_user(
+        &self,
+        ctx: &Context<'_>,
+        username: String,
+        email: String,
+    ) -> async_graphql::Result<UserObject> {
+        let context = ctx.data_unchecked::<AuditContext>();
+        let state = app_state(ctx);
+        let user = state
+            .services
+            .users
+            .create(state, context, tenant(ctx), &username, &email, metadata::empty())
+            .await
+            .map_err(|err| err.extend())?;
+        Ok(UserObject(user))
+    }
+
+    /// Soft-delete a user
+    async fn delete_user(&self, ctx: &Context<'_>, id: ID) -> async_graphql::Result<UserObject> {
+        let context = ctx.data_unchecked::<AuditContext>();
+        let state = app_state(ctx);
+        let user = state
+            .services
+            .users
+            .delete(state, context, tenant(ctx), parse_id(&id)?)
+            .await
+            .map_err(|err| err.extend())?;
+        Ok(UserObject(user))
+    }
+
+    /// Restore a soft-deleted user; admin only
+    async fn restore_user(&self, ctx: &Context<'_>, id: ID) -> async_graphql::Result<UserObject> {
+        if !ctx.data_unchecked::<Viewer>().admin {
+            return Err(forbidden());
+        }
+        let context = ctx.data_unchecked::<AuditContext>();
+        let state = app_state(ctx);
+        let user = state
+            .services
+            .users
+            .restore(state, context, tenant(ctx), parse_id(&id)?)
+            .await
+            .map_err(|err| err.extend())?;
+        Ok(UserObject(user))
+    }
+}
+
+/// Event streams
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// The tenant's user lifecycle events, optionally limited to the given user IDs
+    async fn user_events(
+        &self,
+        ctx: &Context<'_>,
+        ids: Option<Vec<ID>>,
+    ) -> async_graphql::Result<impl Stream<Item = UserEventObject>> {
+        let ids = ids
+            .map(|ids| ids.iter().map(parse_id).collect::<async_graphql::Result<Vec<_>>>())
+            .transpose()?;
+        let receiver = app_state(ctx).events.subscribe();
+        let filter = (tenant(ctx).to_string(), ids);
+        Ok(stream::unfold((receiver, filter), |(mut receiver, filter)| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => {
+                        let (tenant, ids) = &filter;
+                        let wanted = event.user.tenant_id == *tenant
+                            && ids.as_ref().map_or(true, |ids| ids.contains(&event.user.id));
+                        if wanted {
+                            return Some((UserEventObject(event), (receiver, filter)));
+                        }
+                    }
+                    // GraphQL subscriptions have no gap signal; skip ahead
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        }))
+    }
+}
+
+/// The schema, built on first use
+pub fn schema() -> &'static UserSchema {
+    static SCHEMA: OnceLock<UserSchema> = OnceLock::new();
+    SCHEMA.get_or_init(|| Schema::build(QueryRoot, MutationRoot, SubscriptionRoot).finish())
+}
+
+/// Execute a query or mutation
+pub async fn graphql_handler(
+    State(state): State<Arc<AppState>>,
+    admin: Option<Admin>,
+    context: AuditContext,
+    tenant: Tenant,
+    request: GraphQLRequest,
+) -> GraphQLResponse {
+    let request = request
+        .into_inner()
+        .data(state)
+        .data(Viewer {
+            admin: admin.is_some(),
+        })
+        .data(context)
+        .data(tenant);
+    schema().execute(request).await.into()
+}
+
+/// Serve subscriptions over the graphql-ws protocols
+pub async fn graphql_subscriptions(
+    State(state): State<Arc<AppState>>,
+    admin: Option<Admin>,
+    context: AuditContext,
+    tenant: Tenant,
+    protocol: GraphQLProtocol,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let mut data = Data::default();
+    data.insert(state);
+    data.insert(Viewer {
+        admin: admin.is_some(),
+    });
+    data.insert(context);
+    data.insert(tenant);
+    upgrade
+        .protocols(async_graphql::http::ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |socket| {
+            GraphQLWebSocket::new(socket, schema().clone(), protocol)
+                .with_data(data)
+                .serve()
+        })
+}
@@ -0,0 +1,270 @@
+//! gRPC user service on a second port.
+//!
+//! Generated from `proto/users.proto` by `build.rs`. Calls go through
+//! `services.users` like the REST and GraphQL front ends. Admin calls present the
+//! same bearer token in the `authorization` metadata; `x-request-id` is
+//! honored for audit entries, and `x-tenant-id` names the tenant as it does
+//! over HTTP. Enabled by setting `Config::grpc_port`.
+
+use async_trait::async_trait;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tonic::{Request, Response, Status};
+use uuid::Uuid;
+
+use crate::audit::AuditContext;
+use crate::auth::{self, constant_time_eq};
+use crate::client_ip;
+use crate::lifecycle::Component;
+use crate::repository::UserQuery;
+use crate::request_id::REQUEST_ID_HEADER;
+use crate::service::ServiceError;
+use crate::{metadata, tenancy, AppState, User};
+
+/// Generated protobuf types and service traits
+pub mod pb {
+    tonic::include_proto!("users.v1");
+}
+
+use pb::user_service_server::{UserService, UserServiceServer};
+
+fn timestamp(at: chrono::DateTime<chrono::Utc>) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: at.timestamp(),
+        nanos: at.timestamp_subsec_nanos() as i32,
+    }
+}
+
+impl From<User> for pb::User {
+    fn from(user: User) -> Self {
+        pb::User {
+            id: user.id.to_string(),
+            username: user.username,
+            email: user.email,
+            created_at: Some(timestamp(user.created_at)),
+            is_active: user.is_active,
+            deleted_at: user.deleted_at.map(timestamp),
+        }
+    }
+}
+
+impl From<ServiceError> for Status {
+    fn from(err: ServiceError) -> Self {
+        match err {
+            ServiceError::NotFound => Status::not_found(err.to_string()),
+            ServiceError::Taken(_) | ServiceError::Conflict(_) => {
+                Status::failed_precondition(err.to_string())
+            }
+            ServiceError::Invalid(_) => Status::invalid_argument(err.to_string()),
+            ServiceError::ReadOnly => Status::unavailable(err.to_string()),
+            // Backend detail is for logs, as with REST
+            ServiceError::Backend(_) => Status::internal("internal error"),
+            ServiceError::Unavailable(_) => Status::unavailable("database unavailable"),
+        }
+    }
+}
+
+fn parse_id(id: &str) -> Result<Uuid, Status> {
+    id.parse().map_err(|_| Status::invalid_argument("malformed user ID"))
+}
+
+/// `UserService` implementation over the shared service layer
+pub struct GrpcUsers {
+    state: Arc<AppState>,
+}
+
+impl GrpcUsers {
+    /// Create the service
+    pub fn new(state: Arc<AppState>) -> Self {
+        Self { state }
+    }
+
+    /// Whether the call carries the admin token
+    fn is_admin<T>(&self, request: &Request<T>) -> bool {
+        let Some(expected) = auth::admin_token(&self.state) else {
+            return false;
+        };
+        request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|token| constant_time_eq(token.as_bytes(), expected.as_bytes()))
+    }
+
+    fn require_admin_for<T>(&self, request: &Request<T>, needed: bool) -> Result<(), Status> {
+        if needed && !self.is_admin(request) {
+            return Err(Status::permission_denied("admin access required"));
+        }
+        Ok(())
+    }
+
+    /// Tenant the call is served for, from its metadata
+    fn tenant<T>(&self, request: &Request<T>) -> Result<String, Status> {
+        let config = &self.state.config().tenancy;
+        let named = tenancy::resolve(config, &request.metadata().clone().into_headers());
+        tenancy::serve(config, named).map_err(Status::invalid_argument)
+    }
+
+    fn audit_context<T>(&self, request: &Request<T>) -> AuditContext {
+        let actor = if self.is_admin(request) { "admin" } else { "anonymous" };
+        let request_id = request
+            .metadata()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        let headers = request.metadata().clone().into_headers();
+        let trusted = &self.state.config().trusted_proxies;
+        let client_ip = request
+            .remote_addr()
+            .map(|peer| client_ip::resolve(&headers, peer.ip(), trusted));
+        AuditContext {
+            actor: actor.to_string(),
+            request_id,
+            client_ip,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl UserService for GrpcUsers {
+    async fn get_user(
+        &self,
+        request: Request<pb::GetUserRequest>,
+    ) -> Result<Response<pb::User>, Status> {
+        self.require_admin_for(&request, request.get_ref().include_deleted)?;
+        let tenant = self.tenant(&request)?;
+        let message = request.into_inner();
+        let id = parse_id(&message.id)?;
+        let users = &self.state.services.users;
+        let user = users.get(&self.state, &tenant, id, message.include_deleted).await?;
+        Ok(Response::new(user.into()))
+    }
+
+    async fn list_users(
+        &self,
+        request: Request<pb::ListUsersRequest>,
+    ) -> Result<Response<pb::ListUsersResponse>, Status> {
+        let include_deleted = request.get_ref().include_deleted;
+        self.require_admin_for(&request, include_deleted)?;
+        let tenant = self.tenant(&request)?;
+        let query = UserQuery {
+            include_deleted,
+            ..UserQuery::default()
+        };
+        let users = self.state.services.users.list(&self.state, &tenant, &query).await?;
+        Ok(Response::new(pb::ListUsersResponse {
+            users: users.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn create_user(
+        &self,
+        request: Request<pb::CreateUserRequest>,
+    ) -> Result<Response<pb::User>, Status> {
+        let context = self.audit_context(&request);
+        let tenant = self.tenant(&request)?;
+        let message = request.into_inner();
+        let (username, email) = (&message.username, &message.email);
+        let users = &self.state.services.users;
+        let metadata = metadata::empty();
+        let user = users.create(&self.state, &context, &tenant, username, email, metadata).await?;
+        Ok(Response::new(user.into()))
+    }
+
+    async fn delete_user(
+        &self,
+        request: Request<pb::DeleteUserRequest>,
+    ) -> Result<Response<pb::User>, Status> {
+        let context = self.audit_context(&request);
+        let tenant = self.tenant(&request)?;
+        let id = parse_id(&request.get_ref().id)?;
+        let user = self.state.services.users.delete(&self.state, &context, &tenant, id).await?;
+        Ok(Response::new(user.into()))
+    }
+
+    async fn restore_user(
+        &self,
+        request: Request<pb::RestoreUserRequest>,
+    ) -> Result<Response<pb::User>, Status> {
+        self.require_admin_for(&request, true)?;
+        let context = self.audit_context(&request);
+        let tenant = self.tenant(&request)?;
+        let id = parse_id(&request.get_ref().id)?;
+        let user = self.state.services.users.restore(&self.state, &context, &tenant, id).await?;
+        Ok(Response::new(user.into()))
+    }
+}
+
+/// Lifecycle component serving gRPC when a port is configured
+#[derive(Default)]
+pub struct GrpcServer {
+    running: Mutex<Option<(oneshot::Sender<()>, JoinHandle<()>)>>,
+}
+
+#[async_trait]
+impl Component for GrpcServer {
+    fn name(&self) -> &'static str {
+        "grpc"
+    }
+
+    async fn start(&self, state: &Arc<AppState>) -> Result<(), String> {
+        let Some(port) = state.config().grpc_port else {
+            return Ok(());
+        };
+        let addr: SocketAddr = format!("{}:{}", state.config().host, port)
+            .parse()
+            .map_err(|err| format!("invalid gRPC address: {}", err))?;
+        // Bind here so a port conflict fails startup instead of a background task
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .map_err(|err| format!("failed to bind {}: {}", addr, err))?;
+        let incoming = tokio_stream::wrappers::TcpListenerStream::new(listener);
+
+        let (shutdown, shutdown_signal) = oneshot::channel();
+        let service = UserServiceServer::new(GrpcUsers::new(state.clone()));
+        let handle = tokio::spawn(async move {
+            let result = tonic::transport::Server::builder()
+                .add_service(service)
+                .serve_with_incoming_shutdown(incoming, async {
+                    let _ = shutdown_signal.await;
+                })
+                .await;
+            if let Err(err) = result {
+                tracing::error!("gRPC server failed: {}", err);
+            }
+        });
+        tracing::info!("gRPC listening on {}", addr);
+        *self.running.lock().expect("grpc lock poisoned") = Some((shutdown, handle));
+        Ok(())
+    }
+
+    async fn stop(&self) -> Result<(), String> {
+        let running = self.running.lock().expect("grpc lock poisoned").take();
+        if let Some((shutdown, handle)) = running {
+            let _ = shutdown.send(());
+            handle.await.map_err(|err| err.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorReport;
+
+    #[test]
+    fn test_service_errors_map_to_grpc_codes() {
+        assert_eq!(Status::from(ServiceError::NotFound).code(), tonic::Code::NotFound);
+        assert_eq!(
+            Status::from(ServiceError::Invalid(vec!["bad".to_string()])).code(),
+            tonic::Code::InvalidArgument
+        );
+        let internal = Status::from(ServiceError::Backend(ErrorReport::new("connection refused")));
+        assert_eq!(internal.message(), "internal error");
+    }
+}
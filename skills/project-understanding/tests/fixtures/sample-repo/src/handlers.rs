@@ -3,81 +3,751 @@
 //! This module contains all request handlers organized by resource type.
 
 use axum::{
-    extract::{Path, State},
+    extract::{DefaultBodyLimit, Query, State},
     http::StatusCode,
-    response::Json,
+    response::{Json, Response},
     routing::{get, post, put, delete},
     Router,
 };
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
+use tower::Layer;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
 
-use crate::{AppState, ApiResponse, User};
+use crate::audit::{self, AuditContext};
+use crate::auth::{Admin, Role};
+use crate::error::{self, AppError, ErrorCode};
+use crate::etag::{self, Conditions};
+use crate::metadata::MetadataFilter;
+use crate::pagination::{PageParams, PageRequest};
+use crate::redaction::{self, Redacted};
+use crate::repository::UserQuery;
+use crate::service::{ServiceError, UserPatch};
+use crate::tenancy::Tenant;
+use crate::validation::{ApiPath, Validate, ValidatedJson};
+use crate::{
+    access_log, admin, announcements, avatars, blobs, body_limit, cache, cancellation, catch_panic,
+    compression, console, context, cors, debug_targets, demo, email_change, erasure, examples,
+    exports, fairness, formats, feature_flags, graphql, header_policy, health, idempotency, import,
+    integrity, jobs, kill_switch, localization, mail, metadata, method_override, metrics, openapi,
+    operations, path_policy, permissions, postman, priority, rate_limit, read_only, reload,
+    replicas, replication, request_id, response_meta, scheduler, search, service, setup, signature,
+    slow_requests, sql_comment, sse, status, teams, telemetry, timeout, version, versioning,
+    webhooks, write_behind, ws, AppState, ApiResponse, User,
+};
 
 /// Create router with all routes
 pub fn create_router(state: Arc<AppState>) -> Router {
+    let legacy = api_v1().layer(axum::middleware::from_fn_with_state(
+        state.clone(),
+        versioning::deprecated,
+    ));
+    let deprecated_probe = axum::middleware::from_fn(health::deprecated_probe);
+    // A future version mounts alongside: `.nest("/api/v2", api_v2())`
+    let mut router = Router::new()
+        .route("/healthz", get(health_check))
+        .route("/readyz", get(health::readiness))
+        .route("/health", get(health_check).layer(deprecated_probe.clone()))
+        .route("/health/ready", get(health::readiness).layer(deprecated_probe))
+        .route("/metrics", get(metrics::metrics))
+        .route("/status", get(status::status_page))
+        .route("/version", get(version::version))
+        .route("/ws", get(ws::websocket))
+        .route("/graphql", post(graphql::graphql_handler))
+        .route("/graphql/ws", get(graphql::graphql_subscriptions))
+        .nest(versioning::V1_PREFIX, api_v1())
+        .nest(versioning::LEGACY_PREFIX, legacy);
+    if state.config().api_docs {
+        router = router.merge(openapi::docs());
+    }
+    if state.config().debug {
+        router = router.merge(console::routes());
+    }
+
+    let router = router
+        .fallback(error::not_found)
+        .layer(axum::middleware::from_fn(error::method_not_allowed))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            redaction::redact_responses,
+        ))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), idempotency::replay_retries))
+        .layer(axum::middleware::from_fn(localization::localize_responses))
+        .layer(axum::middleware::from_fn(formats::decode_requests))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            signature::verify_signature,
+        ))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), body_limit::limit_body))
+        .layer(DefaultBodyLimit::disable())
+        .layer(axum::middleware::from_fn_with_state(state.clone(), timeout::enforce_timeout))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            slow_requests::detect_slow_requests,
+        ))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), fairness::fair_share))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), priority::shed_load))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), rate_limit::limit_rate))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            priority::classify_request,
+        ))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), read_only::reject_writes))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            kill_switch::enforce_kill_switches,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            error::negotiate_problems,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            response_meta::attach_meta,
+        ))
+        .layer(axum::middleware::from_fn(formats::encode_responses))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            compression::compress_responses,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            debug_targets::apply_debug_targets,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            write_behind::track_last_seen,
+        ))
+        .layer(axum::middleware::from_fn(sql_comment::scope_query_context))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), replicas::route_reads))
+        .layer(axum::middleware::from_fn(catch_panic::catch_panic))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            cancellation::detect_abandonment,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            metrics::track_requests,
+        ))
+        .layer(axum::middleware::from_fn(telemetry::trace_request))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            header_policy::apply_header_policy,
+        ))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), cors::apply_cors))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), access_log::log_request))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), context::attach_context))
+        .layer(axum::middleware::from_fn(request_id::propagate_request_id))
+        .with_state(state.clone());
+    // These change which route a request takes, so they wrap the router
+    let overrides =
+        axum::middleware::from_fn_with_state(state.clone(), method_override::override_method);
+    let paths = axum::middleware::from_fn_with_state(state, path_policy::normalize_path);
+    Router::new().fallback_service(paths.layer(overrides.layer(router)))
+}
+
+/// Version 1 of the REST API, relative to its mount point
+fn api_v1() -> Router<Arc<AppState>> {
     Router::new()
-        .route("/health", get(health_check))
-        .route("/api/users", get(list_users).post(create_user))
-        .route("/api/users/:id", get(get_user).put(update_user).delete(delete_user))
-        .with_state(state)
+        .route("/announcements", get(announcements::list_announcements))
+        .route("/admin/announcements", post(announcements::create_announcement))
+        .route("/admin/demo/reset", post(demo::reset_demo))
+        .route("/admin/routes/:name/examples", get(examples::route_examples))
+        .route("/admin/postman", get(postman::export_collection))
+        .route("/admin/cache", get(cache::cache_stats))
+        .route("/admin/slow-requests", get(slow_requests::list_slow_requests))
+        .route("/admin/reload", post(reload::reload))
+        .route(
+            "/admin/integrity",
+            get(integrity::integrity_status).post(integrity::run_integrity_checks),
+        )
+        .route(
+            "/admin/announcements/:id/resolve",
+            post(announcements::resolve_announcement),
+        )
+        .route("/setup", get(setup::setup_status).post(setup::run_setup))
+        .route("/users", get(list_users).post(create_user))
+        .route("/users/import", post(import::import_users))
+        .route("/users/events", get(sse::stream_user_events))
+        .route("/users/search", get(search::search_users))
+        .route(
+            "/users/:id",
+            get(get_user).put(update_user).patch(patch_user).delete(delete_user),
+        )
+        .route("/users/:id/restore", post(restore_user))
+        .route("/users/:id/erase", post(erasure::erase_user))
+        .route("/users/:id/email-change", post(email_change::request_email_change))
+        .route("/email-change/confirm", post(email_change::confirm_email_change))
+        .route(
+            "/users/:id/avatar",
+            get(avatars::get_avatar).put(avatars::upload_avatar),
+        )
+        .route("/users/:id/export", post(exports::request_export))
+        .route("/users/:id/exports/:export", get(exports::get_export))
+        .route("/users/:id/exports/:export/download", get(exports::download_export))
+        .route("/teams", get(teams::list_teams).post(teams::create_team))
+        .route(
+            "/teams/:id",
+            get(teams::get_team).put(teams::update_team).delete(teams::delete_team),
+        )
+        .route(
+            "/teams/:id/members",
+            get(teams::list_members).post(teams::add_member),
+        )
+        .route("/teams/:id/members/:user_id", delete(teams::remove_member))
+        .route("/blobs/*key", get(blobs::download_blob))
+        .route("/operations/:id", get(operations::get_operation))
+        .route("/audit", get(audit::list_audit_entries))
+        .route(
+            "/admin/webhooks",
+            get(webhooks::list_webhooks).post(webhooks::create_webhook),
+        )
+        .route("/admin/webhooks/:id", delete(webhooks::delete_webhook))
+        .route("/admin/webhooks/:id/deliveries", get(webhooks::list_deliveries))
+        .route("/admin/mail", get(mail::mail_stats))
+        .route("/admin/mail/test", post(mail::send_test_mail))
+        .route("/admin/jobs", get(jobs::list_jobs))
+        .route("/admin/jobs/:id", get(jobs::get_job))
+        .route("/admin/jobs/:id/retry", post(jobs::retry_job))
+        .route("/admin/schedule", get(scheduler::list_schedule))
+        .route(
+            "/admin/debug-targets",
+            get(debug_targets::list_debug_targets).post(debug_targets::create_debug_target),
+        )
+        .route(
+            "/admin/debug-targets/:id",
+            delete(debug_targets::delete_debug_target),
+        )
+        .route("/admin/replication/events", post(replication::receive_event))
+        .route(
+            "/admin/kill-switches",
+            get(kill_switch::list_kill_switches).post(kill_switch::create_kill_switch),
+        )
+        .route("/admin/kill-switches/:id", delete(kill_switch::delete_kill_switch))
+        .route(
+            "/admin/read-only",
+            get(read_only::read_only_status).put(read_only::set_read_only),
+        )
+        .route("/admin/feature-flags", get(feature_flags::list_feature_flags))
+        .route(
+            "/admin/feature-flags/:name",
+            put(feature_flags::set_feature_flag).delete(feature_flags::clear_feature_flag),
+        )
+        .merge(admin::routes())
 }
 
-/// Health check endpoint
-async fn health_check(State(state): State<Arc<AppState>>) -> Json<ApiResponse<serde_json::Value>> {
-    let count = state.increment_counter().await;
+/// One entry in the route introspection table
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct RouteInfo {
+    /// HTTP method
+    pub method: &'static str,
+    /// Route pattern
+    pub path: &'static str,
+    /// Whether the admin bearer token is required
+    pub admin: bool,
+    /// Errors the handler can answer with, beyond the ones every route can
+    pub errors: &'static [ErrorCode],
+}
+
+const fn route(method: &'static str, path: &'static str, admin: bool) -> RouteInfo {
+    RouteInfo {
+        method,
+        path,
+        admin,
+        errors: &[],
+    }
+}
+
+impl RouteInfo {
+    /// The route, documenting the errors its handler can answer with
+    const fn errors(self, errors: &'static [ErrorCode]) -> Self {
+        RouteInfo { errors, ..self }
+    }
+
+    /// Every error the route can answer with, by status
+    pub fn possible_errors(&self) -> Vec<ErrorCode> {
+        let admin: &[ErrorCode] = if self.admin {
+            &[ErrorCode::UNAUTHORIZED, ErrorCode::FORBIDDEN]
+        } else {
+            &[]
+        };
+        let writes: &[ErrorCode] = match self.method {
+            "GET" | "HEAD" | "OPTIONS" => &[],
+            _ if self.path == read_only::READ_ONLY_PATH || self.path == "/graphql" => &[],
+            _ => &[ErrorCode::READ_ONLY],
+        };
+        let retries: &[ErrorCode] = match self.method {
+            "POST" => &[ErrorCode::IDEMPOTENCY_KEY_IN_USE, ErrorCode::IDEMPOTENCY_KEY_REUSED],
+            _ => &[],
+        };
+        let mut errors: Vec<ErrorCode> = ErrorCode::COMMON.to_vec();
+        for error in admin.iter().chain(writes).chain(retries).chain(self.errors) {
+            if !errors.contains(error) {
+                errors.push(*error);
+            }
+        }
+        errors.sort_by_key(|error| error.status);
+        errors
+    }
+}
+
+/// Errors of updating a user through PUT or PATCH
+const UPDATE_USER_ERRORS: &[ErrorCode] = &[
+    ErrorCode::FIELD_NOT_WRITABLE,
+    ErrorCode::USER_NOT_FOUND,
+    ErrorCode::USERNAME_TAKEN,
+    ErrorCode::PRECONDITION_FAILED,
+    ErrorCode::VALIDATION_FAILED,
+];
+
+/// Routes served by `create_router`; update alongside it
+pub const ROUTES: &[RouteInfo] = &[
+    route("GET", "/healthz", false),
+    route("GET", "/readyz", false),
+    route("GET", "/health", false),
+    route("GET", "/health/ready", false),
+    route("GET", "/metrics", false),
+    route("GET", "/status", false),
+    route("GET", "/version", false),
+    route("GET", "/ws", false),
+    route("POST", "/graphql", false),
+    route("GET", "/graphql/ws", false),
+    route("GET", "/api/v1/announcements", false),
+    route("POST", "/api/v1/admin/announcements", true),
+    route("POST", "/api/v1/admin/announcements/:id/resolve", true),
+    route("POST", "/api/v1/admin/demo/reset", true),
+    route("GET", "/api/v1/admin/routes/:name/examples", true),
+    route("GET", "/api/v1/admin/postman", true),
+    route("GET", "/api/v1/admin/cache", true),
+    route("GET", "/api/v1/admin/slow-requests", true),
+    route("POST", "/api/v1/admin/reload", true),
+    route("GET", "/api/v1/admin/integrity", true),
+    route("POST", "/api/v1/admin/integrity", true),
+    route("GET", "/api/v1/setup", false),
+    route("POST", "/api/v1/setup", false),
+    route("GET", "/api/v1/users", false).errors(&[ErrorCode::FORBIDDEN]),
+    route("POST", "/api/v1/users", false).errors(&[
+        ErrorCode::USERNAME_TAKEN,
+        ErrorCode::EMAIL_TAKEN,
+        ErrorCode::VALIDATION_FAILED,
+    ]),
+    route("POST", "/api/v1/users/import", false).errors(&[ErrorCode::UNSUPPORTED_MEDIA_TYPE]),
+    route("GET", "/api/v1/users/events", false),
+    route("GET", "/api/v1/users/search", false)
+        .errors(&[ErrorCode::VALIDATION_FAILED, ErrorCode::FORBIDDEN]),
+    route("GET", "/api/v1/users/:id", false)
+        .errors(&[ErrorCode::FORBIDDEN, ErrorCode::USER_NOT_FOUND]),
+    route("PUT", "/api/v1/users/:id", false).errors(UPDATE_USER_ERRORS),
+    route("PATCH", "/api/v1/users/:id", false).errors(UPDATE_USER_ERRORS),
+    route("DELETE", "/api/v1/users/:id", false)
+        .errors(&[ErrorCode::USER_NOT_FOUND, ErrorCode::PRECONDITION_FAILED]),
+    route("POST", "/api/v1/users/:id/restore", true)
+        .errors(&[ErrorCode::USER_NOT_FOUND, ErrorCode::CONFLICT]),
+    route("POST", "/api/v1/users/:id/erase", true)
+        .errors(&[ErrorCode::USER_NOT_FOUND, ErrorCode::CONFLICT]),
+    route("POST", "/api/v1/users/:id/email-change", true).errors(&[
+        ErrorCode::USER_NOT_FOUND,
+        ErrorCode::VALIDATION_FAILED,
+        ErrorCode::CONFLICT,
+        ErrorCode::EMAIL_TAKEN,
+        ErrorCode::OVERLOADED,
+    ]),
+    route("POST", "/api/v1/email-change/confirm", false).errors(&[
+        ErrorCode::EMAIL_CHANGE_NOT_FOUND,
+        ErrorCode::USER_NOT_FOUND,
+        ErrorCode::EMAIL_TAKEN,
+    ]),
+    route("PUT", "/api/v1/users/:id/avatar", false).errors(&[
+        ErrorCode::USER_NOT_FOUND,
+        ErrorCode::VALIDATION_FAILED,
+        ErrorCode::PAYLOAD_TOO_LARGE,
+    ]),
+    route("GET", "/api/v1/users/:id/avatar", false)
+        .errors(&[ErrorCode::USER_NOT_FOUND, ErrorCode::NOT_FOUND]),
+    route("POST", "/api/v1/users/:id/export", true).errors(&[ErrorCode::USER_NOT_FOUND]),
+    route("GET", "/api/v1/users/:id/exports/:export", true)
+        .errors(&[ErrorCode::EXPORT_NOT_FOUND]),
+    route("GET", "/api/v1/users/:id/exports/:export/download", true).errors(&[
+        ErrorCode::EXPORT_NOT_FOUND,
+        ErrorCode::CONFLICT,
+        ErrorCode::NOT_FOUND,
+    ]),
+    route("GET", "/api/v1/teams", false),
+    route("POST", "/api/v1/teams", true)
+        .errors(&[ErrorCode::TEAM_NAME_TAKEN, ErrorCode::VALIDATION_FAILED]),
+    route("GET", "/api/v1/teams/:id", false).errors(&[ErrorCode::TEAM_NOT_FOUND]),
+    route("PUT", "/api/v1/teams/:id", true).errors(&[
+        ErrorCode::TEAM_NOT_FOUND,
+        ErrorCode::TEAM_NAME_TAKEN,
+        ErrorCode::VALIDATION_FAILED,
+    ]),
+    route("DELETE", "/api/v1/teams/:id", true).errors(&[ErrorCode::TEAM_NOT_FOUND]),
+    route("GET", "/api/v1/teams/:id/members", false).errors(&[ErrorCode::TEAM_NOT_FOUND]),
+    route("POST", "/api/v1/teams/:id/members", true)
+        .errors(&[ErrorCode::TEAM_NOT_FOUND, ErrorCode::USER_NOT_FOUND]),
+    route("DELETE", "/api/v1/teams/:id/members/:user_id", true)
+        .errors(&[ErrorCode::TEAM_NOT_FOUND, ErrorCode::NOT_FOUND]),
+    route("GET", "/api/v1/blobs/*key", false)
+        .errors(&[ErrorCode::NOT_FOUND, ErrorCode::FORBIDDEN]),
+    route("GET", "/api/v1/operations/:id", false).errors(&[ErrorCode::OPERATION_NOT_FOUND]),
+    route("GET", "/api/v1/audit", true),
+    route("GET", "/api/v1/admin/webhooks", true),
+    route("POST", "/api/v1/admin/webhooks", true),
+    route("DELETE", "/api/v1/admin/webhooks/:id", true),
+    route("GET", "/api/v1/admin/webhooks/:id/deliveries", true),
+    route("GET", "/api/v1/admin/mail", true),
+    route("POST", "/api/v1/admin/mail/test", true)
+        .errors(&[ErrorCode::VALIDATION_FAILED, ErrorCode::OVERLOADED]),
+    route("GET", "/api/v1/admin/jobs", true),
+    route("GET", "/api/v1/admin/jobs/:id", true).errors(&[ErrorCode::JOB_NOT_FOUND]),
+    route("POST", "/api/v1/admin/jobs/:id/retry", true)
+        .errors(&[ErrorCode::JOB_NOT_FOUND, ErrorCode::CONFLICT]),
+    route("GET", "/api/v1/admin/schedule", true),
+    route("GET", "/api/v1/admin/debug-targets", true),
+    route("POST", "/api/v1/admin/debug-targets", true),
+    route("DELETE", "/api/v1/admin/debug-targets/:id", true),
+    route("POST", "/api/v1/admin/replication/events", true),
+    route("GET", "/api/v1/admin/read-only", true),
+    route("PUT", "/api/v1/admin/read-only", true),
+    route("GET", "/api/v1/admin/kill-switches", true),
+    route("POST", "/api/v1/admin/kill-switches", true),
+    route("DELETE", "/api/v1/admin/kill-switches/:id", true),
+    route("GET", "/api/v1/admin/stats", true),
+    route("GET", "/api/v1/admin/config", true),
+    route("POST", "/api/v1/admin/counters/reset", true),
+    route("POST", "/api/v1/admin/caches/flush", true),
+    route("GET", "/api/v1/admin/feature-flags", true),
+    route("PUT", "/api/v1/admin/feature-flags/:name", true),
+    route("DELETE", "/api/v1/admin/feature-flags/:name", true),
+];
+
+/// Liveness probe; `/health` is its deprecated alias
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "health",
+    responses((status = 200, description = "Process is serving", body = HealthResponse))
+)]
+pub(crate) async fn health_check(
+    State(state): State<Arc<AppState>>,
+) -> Json<ApiResponse<serde_json::Value>> {
+    // Liveness must answer even when the database doesn't, so it asks it nothing
     let response = serde_json::json!({
         "status": "ok",
-        "requests_handled": count,
+        "profile": state.config().profile,
+        "requests_handled": state.requests_handled(),
     });
     Json(ApiResponse::success(response))
 }
 
-/// List all users
-async fn list_users() -> Json<ApiResponse<Vec<User>>> {
-    // TODO: Implement database query
-    let users: Vec<User> = vec![];
-    Json(ApiResponse::success(users))
+/// Query flag exposing soft-deleted users to admins
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct DeletedFilter {
+    /// Include soft-deleted users; admin only
+    #[serde(default)]
+    include_deleted: bool,
+}
+
+impl DeletedFilter {
+    /// Reject the flag for callers that are not admins
+    fn authorize(&self, admin: Option<Admin>) -> Result<bool, AppError> {
+        match (self.include_deleted, admin) {
+            (true, None) => Err(AppError::Forbidden("include_deleted requires admin".to_string())),
+            (include_deleted, _) => Ok(include_deleted),
+        }
+    }
+}
+
+/// Body of `POST /api/v1/users`; IDs and timestamps are assigned by the server
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CreateUser {
+    /// Letters, digits, `.`, `_`, and `-`; at most 64 characters
+    #[schema(example = "alice")]
+    username: String,
+    /// Email address, stored lowercased
+    #[schema(example = "alice@example.com")]
+    email: String,
+    /// Attributes of the integrator's own, as a JSON object
+    #[schema(value_type = Object)]
+    #[serde(default = "metadata::empty")]
+    metadata: serde_json::Value,
+}
+
+impl Validate for CreateUser {
+    fn validate(&self) -> Vec<String> {
+        service::validate(&self.username, &self.email)
+    }
+}
+
+/// List users, a page at a time when `per_page` is given
+#[utoipa::path(
+    get,
+    path = "/api/v1/users",
+    tag = "users",
+    params(DeletedFilter, PageParams),
+    responses(
+        (status = 200, description = "Users, with a weak `ETag`", body = UserListResponse),
+        (status = 304, description = "`If-None-Match` names the current `ETag`"),
+        (status = 403, description = "`include_deleted` requested without admin token"),
+    ),
+    security((), ("admin_token" = []))
+)]
+#[tracing::instrument(skip_all)]
+pub(crate) async fn list_users(
+    State(state): State<Arc<AppState>>,
+    admin: Option<Admin>,
+    Tenant(tenant): Tenant,
+    conditions: Conditions,
+    Query(filter): Query<DeletedFilter>,
+    MetadataFilter(metadata): MetadataFilter,
+    page: PageRequest,
+) -> Result<Response, AppError> {
+    let query = UserQuery {
+        include_deleted: filter.authorize(admin)?,
+        metadata,
+    };
+    let users = state.services.users.list(&state, &tenant, &query).await?;
+    let (users, meta, links) = page.paginate(users);
+    let body = ApiResponse::page(users, meta, links);
+    let etag = etag::weak(&body, Role::from(admin));
+    Ok(conditions.respond(&etag, Redacted::of::<User>(Json(body))))
 }
 
 /// Get user by ID
-async fn get_user(Path(id): Path<String>) -> Result<Json<ApiResponse<User>>, StatusCode> {
-    // TODO: Implement database query
-    Err(StatusCode::NOT_IMPLEMENTED)
+#[utoipa::path(
+    get,
+    path = "/api/v1/users/{id}",
+    tag = "users",
+    params(("id" = Uuid, Path, description = "User ID"), DeletedFilter),
+    responses(
+        (status = 200, description = "The user, with a strong `ETag`", body = UserResponse),
+        (status = 304, description = "`If-None-Match` names the current `ETag`"),
+        (status = 400, description = "Malformed ID"),
+        (status = 403, description = "`include_deleted` requested without admin token"),
+        (status = 404, description = "No such user"),
+    ),
+    security((), ("admin_token" = []))
+)]
+#[tracing::instrument(skip_all)]
+pub(crate) async fn get_user(
+    State(state): State<Arc<AppState>>,
+    admin: Option<Admin>,
+    Tenant(tenant): Tenant,
+    conditions: Conditions,
+    ApiPath(id): ApiPath<Uuid>,
+    Query(filter): Query<DeletedFilter>,
+) -> Result<Response, AppError> {
+    let include_deleted = filter.authorize(admin)?;
+    let user = state.services.users.get(&state, &tenant, id, include_deleted).await?;
+    let etag = etag::strong(&user, Role::from(admin));
+    Ok(conditions.respond(&etag, Redacted::of::<User>(Json(ApiResponse::success(user)))))
 }
 
 /// Create new user
-async fn create_user(Json(user): Json<User>) -> Result<Json<ApiResponse<User>>, StatusCode> {
-    // TODO: Implement database insert
-    Ok(Json(ApiResponse::success(user)))
+#[utoipa::path(
+    post,
+    path = "/api/v1/users",
+    tag = "users",
+    request_body = CreateUser,
+    responses(
+        (status = 201, description = "Created user", body = UserResponse),
+        (status = 409, description = "Username or email already exists"),
+        (status = 422, description = "Validation failed; every failing field is listed"),
+    )
+)]
+#[tracing::instrument(skip_all)]
+pub(crate) async fn create_user(
+    State(state): State<Arc<AppState>>,
+    context: AuditContext,
+    Tenant(tenant): Tenant,
+    ValidatedJson(body): ValidatedJson<CreateUser>,
+) -> Result<Redacted<(StatusCode, Json<ApiResponse<User>>)>, AppError> {
+    let CreateUser { username, email, metadata } = body;
+    let users = &state.services.users;
+    let user = users.create(&state, &context, &tenant, &username, &email, metadata).await?;
+    Ok(Redacted::of::<User>((StatusCode::CREATED, Json(ApiResponse::success(user)))))
 }
 
-/// Update existing user
-async fn update_user(
-    Path(id): Path<String>,
+/// Write the fields a patch changes, once the caller is allowed to change them
+/// and the user is still the one `If-Match` names
+async fn apply_patch(
+    state: &AppState,
+    context: &AuditContext,
+    role: Role,
+    conditions: &Conditions,
+    tenant: &str,
+    id: Uuid,
+    patch: UserPatch,
+) -> Result<Response, AppError> {
+    let current = state.services.users.get(state, tenant, id, false).await?;
+    conditions.check(&etag::strong(&current, role))?;
+    permissions::authorize_changes::<User>(&patch.changed_fields(&current), role)?;
+    let user = state.services.users.update(state, context, current, &patch).await?;
+    let etag = etag::strong(&user, role);
+    Ok(etag::tagged(&etag, Redacted::of::<User>(Json(ApiResponse::success(user)))))
+}
+
+/// Replace a user's editable fields
+#[utoipa::path(
+    put,
+    path = "/api/v1/users/{id}",
+    tag = "users",
+    params(("id" = Uuid, Path, description = "User ID")),
+    request_body = User,
+    responses(
+        (status = 200, description = "Updated user, with its new `ETag`", body = UserResponse),
+        (status = 403, description = "A changed field may not be changed by the caller"),
+        (status = 404, description = "No such user"),
+        (status = 409, description = "Username already exists"),
+        (status = 412, description = "`If-Match` no longer names the user"),
+    ),
+    security((), ("admin_token" = []))
+)]
+#[tracing::instrument(skip_all)]
+pub(crate) async fn update_user(
+    State(state): State<Arc<AppState>>,
+    admin: Option<Admin>,
+    context: AuditContext,
+    conditions: Conditions,
+    Tenant(tenant): Tenant,
+    ApiPath(id): ApiPath<Uuid>,
     Json(user): Json<User>,
-) -> Result<Json<ApiResponse<User>>, StatusCode> {
-    // TODO: Implement database update
-    Ok(Json(ApiResponse::success(user)))
-}
-
-/// Delete user
-async fn delete_user(Path(id): Path<String>) -> StatusCode {
-    // TODO: Implement database delete
-    StatusCode::NO_CONTENT
-}
-
-/// Request logging middleware
-pub async fn log_request<B>(
-    req: axum::http::Request<B>,
-    next: axum::middleware::Next<B>,
-) -> axum::response::Response {
-    let path = req.uri().path().to_string();
-    let method = req.method().to_string();
-    
-    let response = next.run(req).await;
-    
-    let status = response.status();
-    tracing::info!("{} {} - {}", method, path, status);
-    
-    response
+) -> Result<Response, AppError> {
+    let patch = UserPatch::replacing(&user);
+    let role = Role::from(admin);
+    apply_patch(&state, &context, role, &conditions, &tenant, id, patch).await
+}
+
+/// Change some of a user's fields
+#[utoipa::path(
+    patch,
+    path = "/api/v1/users/{id}",
+    tag = "users",
+    params(("id" = Uuid, Path, description = "User ID")),
+    request_body = UserPatch,
+    responses(
+        (status = 200, description = "Updated user, with its new `ETag`", body = UserResponse),
+        (status = 403, description = "A changed field may not be changed by the caller"),
+        (status = 404, description = "No such user"),
+        (status = 409, description = "Username already exists"),
+        (status = 412, description = "`If-Match` no longer names the user"),
+    ),
+    security((), ("admin_token" = []))
+)]
+#[tracing::instrument(skip_all)]
+pub(crate) async fn patch_user(
+    State(state): State<Arc<AppState>>,
+    admin: Option<Admin>,
+    context: AuditContext,
+    conditions: Conditions,
+    Tenant(tenant): Tenant,
+    ApiPath(id): ApiPath<Uuid>,
+    Json(patch): Json<UserPatch>,
+) -> Result<Response, AppError> {
+    let role = Role::from(admin);
+    apply_patch(&state, &context, role, &conditions, &tenant, id, patch).await
+}
+
+/// Soft-delete user
+#[utoipa::path(
+    delete,
+    path = "/api/v1/users/{id}",
+    tag = "users",
+    params(("id" = Uuid, Path, description = "User ID")),
+    responses(
+        (status = 204, description = "User deleted, or already gone with `idempotent_deletes`"),
+        (status = 400, description = "Malformed ID"),
+        (status = 404, description = "No such user"),
+        (status = 412, description = "`If-Match` no longer names the user"),
+    )
+)]
+#[tracing::instrument(skip_all)]
+pub(crate) async fn delete_user(
+    State(state): State<Arc<AppState>>,
+    admin: Option<Admin>,
+    context: AuditContext,
+    conditions: Conditions,
+    Tenant(tenant): Tenant,
+    ApiPath(id): ApiPath<Uuid>,
+) -> Result<StatusCode, AppError> {
+    if conditions.has_if_match() {
+        match state.services.users.get(&state, &tenant, id, false).await {
+            Ok(current) => conditions.check(&etag::strong(&current, Role::from(admin)))?,
+            // No tag to compare; the delete below answers as it would unconditionally
+            Err(ServiceError::NotFound) => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    match state.services.users.delete(&state, &context, &tenant, id).await {
+        // Nothing left to delete, so nothing is recorded or published
+        Err(ServiceError::NotFound) if state.config().idempotent_deletes => {
+            Ok(StatusCode::NO_CONTENT)
+        }
+        result => result.map(|_| StatusCode::NO_CONTENT).map_err(AppError::from),
+    }
+}
+
+/// Restore a soft-deleted user
+#[utoipa::path(
+    post,
+    path = "/api/v1/users/{id}/restore",
+    tag = "users",
+    params(("id" = Uuid, Path, description = "User ID")),
+    responses(
+        (status = 200, description = "Restored user", body = UserResponse),
+        (status = 401, description = "Missing admin token"),
+        (status = 403, description = "Wrong admin token"),
+        (status = 404, description = "No such user"),
+        (status = 409, description = "User is not deleted"),
+    ),
+    security(("admin_token" = []))
+)]
+#[tracing::instrument(skip_all)]
+pub(crate) async fn restore_user(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    context: AuditContext,
+    Tenant(tenant): Tenant,
+    ApiPath(id): ApiPath<Uuid>,
+) -> Result<Redacted<Json<ApiResponse<User>>>, AppError> {
+    let user = state.services.users.restore(&state, &context, &tenant, id).await?;
+    Ok(Redacted::of::<User>(Json(ApiResponse::success(user))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tenancy::DEFAULT_TENANT;
+    use crate::Config;
+
+    #[tokio::test]
+    async fn test_repeated_delete_succeeds_with_idempotent_deletes() {
+        let state = AppState::new(Config {
+            idempotent_deletes: true,
+            ..Config::default()
+        });
+        let context = AuditContext {
+            actor: "test".to_string(),
+            request_id: "req-1".to_string(),
+            client_ip: None,
+        };
+        let alice = service::create_user(&state, &context, DEFAULT_TENANT, "alice", "a@example.com")
+            .await
+            .unwrap();
+        let delete = |context: AuditContext| {
+            let tenant = Tenant(DEFAULT_TENANT.to_string());
+            let conditions = Conditions::default();
+            delete_user(State(state.clone()), None, context, conditions, tenant, ApiPath(alice.id))
+        };
+        for _ in 0..2 {
+            assert_eq!(delete(context.clone()).await.unwrap(), StatusCode::NO_CONTENT);
+        }
+
+        state.config.store(Arc::new(Config::default()));
+        let again = delete(context).await;
+        assert_eq!(again.unwrap_err().status(), StatusCode::NOT_FOUND);
+    }
 }
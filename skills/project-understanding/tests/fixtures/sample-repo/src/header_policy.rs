@@ -0,0 +1,245 @@
+//! Declarative request and response header policies.
+//!
+//! `Config::header_policy.default` applies to every request; each entry in
+//! `groups` overrides it for paths under its `prefix`, the longest matching
+//! prefix winning. A group strips its own headers on top of the default ones
+//! and sets or defaults headers by name, replacing a default entry of the
+//! same name; an empty value cancels that entry for the group.
+//!
+//! Request headers are stripped before any handler sees them. Response
+//! headers are stripped, then `set_response` entries replace whatever the
+//! handler sent, and `default_response` entries fill in only what it didn't.
+//! Invalid header names or values are logged and ignored at startup.
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, HeaderName, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::AppState;
+
+/// Header rules for a set of routes
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeaderPolicy {
+    /// Request headers removed before routing
+    pub strip_request: Vec<String>,
+    /// Response headers removed from handler responses
+    pub strip_response: Vec<String>,
+    /// Response headers always sent, replacing the handler's
+    pub set_response: BTreeMap<String, String>,
+    /// Response headers sent when the handler didn't set them
+    pub default_response: BTreeMap<String, String>,
+}
+
+/// Policy overriding the default under a path prefix
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteGroupPolicy {
+    /// Path prefix, e.g. `/api/v1/admin`
+    pub prefix: String,
+    /// Rules merged over the default policy
+    #[serde(flatten)]
+    pub policy: HeaderPolicy,
+}
+
+/// Header policy settings
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeaderPolicyConfig {
+    /// Rules for every route
+    pub default: HeaderPolicy,
+    /// Overrides by path prefix
+    pub groups: Vec<RouteGroupPolicy>,
+}
+
+/// A policy with parsed header names and values
+#[derive(Debug, Clone, Default)]
+struct Compiled {
+    strip_request: Vec<HeaderName>,
+    strip_response: Vec<HeaderName>,
+    set_response: Vec<(HeaderName, HeaderValue)>,
+    default_response: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl Compiled {
+    fn new(policy: &HeaderPolicy) -> Self {
+        Self {
+            strip_request: names(&policy.strip_request),
+            strip_response: names(&policy.strip_response),
+            set_response: values(&policy.set_response),
+            default_response: values(&policy.default_response),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.strip_request.is_empty()
+            && self.strip_response.is_empty()
+            && self.set_response.is_empty()
+            && self.default_response.is_empty()
+    }
+
+    fn apply_to_response(&self, headers: &mut HeaderMap) {
+        for name in &self.strip_response {
+            headers.remove(name);
+        }
+        for (name, value) in &self.set_response {
+            headers.insert(name.clone(), value.clone());
+        }
+        for (name, value) in &self.default_response {
+            if !headers.contains_key(name) {
+                headers.insert(name.clone(), value.clone());
+            }
+        }
+    }
+}
+
+fn names(names: &[String]) -> Vec<HeaderName> {
+    names
+        .iter()
+        .filter_map(|name| match HeaderName::try_from(name.as_str()) {
+            Ok(name) => Some(name),
+            Err(_) => {
+                tracing::warn!("ignoring invalid header name {:?} in header policy", name);
+                None
+            }
+        })
+        .collect()
+}
+
+fn values(entries: &BTreeMap<String, String>) -> Vec<(HeaderName, HeaderValue)> {
+    entries
+        .iter()
+        .filter(|(_, value)| !value.is_empty())
+        .filter_map(|(name, value)| {
+            match (HeaderName::try_from(name.as_str()), HeaderValue::from_str(value)) {
+                (Ok(name), Ok(value)) => Some((name, value)),
+                _ => {
+                    tracing::warn!("ignoring invalid header {:?} in header policy", name);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// `base` with `group` merged over it
+fn merge(base: &HeaderPolicy, group: &HeaderPolicy) -> HeaderPolicy {
+    let mut merged = base.clone();
+    merged.strip_request.extend(group.strip_request.iter().cloned());
+    merged.strip_response.extend(group.strip_response.iter().cloned());
+    merged.set_response.extend(group.set_response.clone());
+    merged.default_response.extend(group.default_response.clone());
+    merged
+}
+
+/// Effective policies by path prefix, longest first
+#[derive(Debug, Default)]
+pub struct HeaderPolicies {
+    default: Compiled,
+    groups: Vec<(String, Compiled)>,
+}
+
+impl HeaderPolicies {
+    /// Compile the configured policies
+    pub fn new(config: &HeaderPolicyConfig) -> Self {
+        let mut groups: Vec<_> = config
+            .groups
+            .iter()
+            .map(|group| {
+                let prefix = group.prefix.trim_end_matches('/').to_string();
+                (prefix, Compiled::new(&merge(&config.default, &group.policy)))
+            })
+            .collect();
+        groups.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()));
+        Self {
+            default: Compiled::new(&config.default),
+            groups,
+        }
+    }
+
+    /// Policy for a request path
+    fn for_path(&self, path: &str) -> &Compiled {
+        self.groups
+            .iter()
+            .find(|(prefix, _)| {
+                path.strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+            .map_or(&self.default, |(_, policy)| policy)
+    }
+}
+
+/// Middleware applying the header policy of the request's route group
+pub async fn apply_header_policy<B>(
+    State(state): State<Arc<AppState>>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let policy = state.header_policies.for_path(req.uri().path());
+    if policy.is_empty() {
+        return next.run(req).await;
+    }
+    for name in &policy.strip_request {
+        req.headers_mut().remove(name);
+    }
+    let mut response = next.run(req).await;
+    policy.apply_to_response(response.headers_mut());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(strip: &[&str], set: &[(&str, &str)]) -> HeaderPolicy {
+        HeaderPolicy {
+            strip_response: strip.iter().map(|name| name.to_string()).collect(),
+            set_response: set
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            ..HeaderPolicy::default()
+        }
+    }
+
+    #[test]
+    fn test_longest_group_overrides_default() {
+        let config = HeaderPolicyConfig {
+            default: policy(&["x-tracking-id"], &[("cache-control", "no-store")]),
+            groups: vec![
+                RouteGroupPolicy {
+                    prefix: "/api/v1".to_string(),
+                    policy: policy(&[], &[("x-api", "v1")]),
+                },
+                RouteGroupPolicy {
+                    prefix: "/api/v1/admin/".to_string(),
+                    policy: policy(&["server"], &[("cache-control", "")]),
+                },
+            ],
+        };
+        let policies = HeaderPolicies::new(&config);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-tracking-id", HeaderValue::from_static("abc"));
+        headers.insert("server", HeaderValue::from_static("axum"));
+        policies.for_path("/api/v1/admin/cache").apply_to_response(&mut headers);
+        assert!(!headers.contains_key("x-tracking-id"));
+        assert!(!headers.contains_key("server"));
+        assert!(!headers.contains_key("cache-control"));
+
+        let mut headers = HeaderMap::new();
+        policies.for_path("/api/v1/users").apply_to_response(&mut headers);
+        assert_eq!(headers["cache-control"], "no-store");
+        assert_eq!(headers["x-api"], "v1");
+
+        // Prefixes match whole segments only
+        let mut headers = HeaderMap::new();
+        policies.for_path("/api/v1x").apply_to_response(&mut headers);
+        assert!(!headers.contains_key("x-api"));
+    }
+}
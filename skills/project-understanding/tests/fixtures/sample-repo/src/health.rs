@@ -0,0 +1,442 @@
+//! Dependency health checks and the Kubernetes probes.
+//!
+//! Subsystems register a `HealthCheck` in `AppState` so status and
+//! readiness reporting can probe them without knowing their internals.
+//!
+//! `GET /healthz` is the liveness probe: it answers as long as the process
+//! serves requests, touching no dependency, so a database outage never gets
+//! the instance restarted. `GET /readyz` is the readiness probe: it runs
+//! every check, the database, migrations, replication, the job queue, and
+//! the user cache among them, and reports each one's state alongside cache
+//! warmup, draining, maintenance, and load shedding. `/health` and
+//! `/health/ready`, the probes before these, still answer as aliases, marked
+//! deprecated.
+
+use async_trait::async_trait;
+use axum::{
+    extract::State,
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::{Json, Response},
+};
+use futures::future::join_all;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::degradation::Degradation;
+use crate::repository::{PoolStats, UserRepository};
+use crate::warmup::WarmupReport;
+use crate::{versioning, ApiResponse, AppState};
+
+/// Probe paths replaced by `/healthz` and `/readyz`, with their successors
+const LEGACY_PROBES: [(&str, &str); 2] = [("/health", "/healthz"), ("/health/ready", "/readyz")];
+
+/// Coarse health of a component, ordered from best to worst
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthState {
+    /// Working normally
+    Operational,
+    /// Working with reduced capacity or features
+    Degraded,
+    /// Not working
+    Outage,
+}
+
+/// A dependency whose health can be probed
+#[async_trait]
+pub trait HealthCheck: Send + Sync {
+    /// Component name shown in reports
+    fn name(&self) -> &'static str;
+
+    /// Probe the component; the error detail is meant for logs, not clients
+    async fn check(&self) -> Result<HealthState, String>;
+}
+
+/// Health check that pings the user repository
+pub struct DatabaseCheck {
+    users: Arc<dyn UserRepository>,
+}
+
+impl DatabaseCheck {
+    /// Create a check for the given repository
+    pub fn new(users: Arc<dyn UserRepository>) -> Self {
+        Self { users }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for DatabaseCheck {
+    fn name(&self) -> &'static str {
+        "database"
+    }
+
+    async fn check(&self) -> Result<HealthState, String> {
+        self.users.ping().await.map_err(|err| err.to_string())?;
+        // Answering, but queries queue for a connection
+        if self.users.pool_stats().is_some_and(|pool| pool.exhausted()) {
+            return Ok(HealthState::Degraded);
+        }
+        Ok(HealthState::Operational)
+    }
+}
+
+/// Run a check, folding errors into an outage and logging their detail
+pub async fn probe(check: &dyn HealthCheck) -> HealthState {
+    match check.check().await {
+        Ok(state) => state,
+        Err(detail) => {
+            tracing::warn!("health check {} failed: {}", check.name(), detail);
+            HealthState::Outage
+        }
+    }
+}
+
+/// Last completed result of a check
+#[derive(Debug, Clone, Copy)]
+struct Snapshot {
+    state: HealthState,
+    checked_at: Instant,
+}
+
+/// Result of one check as seen by a probe request
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckReport {
+    /// Check name
+    pub name: &'static str,
+    /// Current or last-known state; `outage` if the check never completed
+    pub state: HealthState,
+    /// Whether the check completed within the timeout for this request
+    pub fresh: bool,
+    /// Milliseconds since the reported state was observed
+    pub age_ms: Option<u64>,
+}
+
+/// Marks a check in flight until its task ends, finished or panicked
+struct InFlight {
+    in_flight: Arc<Mutex<HashSet<&'static str>>>,
+    name: &'static str,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if let Ok(mut in_flight) = self.in_flight.lock() {
+            in_flight.remove(self.name);
+        }
+    }
+}
+
+/// Runs health checks concurrently with per-check timeouts.
+///
+/// Each check runs in its own task so a hung dependency can't stall the
+/// caller; its result still lands in the cache when it eventually finishes,
+/// and no second probe of that check is started while one is in flight. A
+/// check that panics counts as an outage and is probed again next time.
+pub struct ReadinessProbe {
+    timeout: Duration,
+    snapshots: Arc<Mutex<HashMap<&'static str, Snapshot>>>,
+    in_flight: Arc<Mutex<HashSet<&'static str>>>,
+}
+
+impl ReadinessProbe {
+    /// Create a probe allowing each check `timeout` to respond
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            snapshots: Arc::new(Mutex::new(HashMap::new())),
+            in_flight: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    /// Run every check, answering within roughly one timeout
+    pub async fn run(&self, checks: &[Arc<dyn HealthCheck>]) -> Vec<CheckReport> {
+        join_all(checks.iter().map(|check| self.run_one(check.clone()))).await
+    }
+
+    /// Last results younger than `max_age`, running only the checks without one
+    pub async fn recent(
+        &self,
+        checks: &[Arc<dyn HealthCheck>],
+        max_age: Duration,
+    ) -> Vec<CheckReport> {
+        join_all(checks.iter().map(|check| async move {
+            let name = check.name();
+            let snapshot = self.snapshots.lock().expect("probe lock poisoned").get(name).copied();
+            match snapshot {
+                Some(snapshot) if snapshot.checked_at.elapsed() < max_age => CheckReport {
+                    name,
+                    state: snapshot.state,
+                    fresh: false,
+                    age_ms: Some(snapshot.checked_at.elapsed().as_millis() as u64),
+                },
+                _ => self.run_one(check.clone()).await,
+            }
+        }))
+        .await
+    }
+
+    async fn run_one(&self, check: Arc<dyn HealthCheck>) -> CheckReport {
+        let name = check.name();
+        let started = self.in_flight.lock().expect("probe lock poisoned").insert(name);
+        if started {
+            let snapshots = self.snapshots.clone();
+            let guard = InFlight {
+                in_flight: self.in_flight.clone(),
+                name,
+            };
+            let task = tokio::spawn(async move {
+                let _guard = guard;
+                let state = probe(check.as_ref()).await;
+                let checked_at = Instant::now();
+                snapshots
+                    .lock()
+                    .expect("probe lock poisoned")
+                    .insert(name, Snapshot { state, checked_at });
+                state
+            });
+            let state = match tokio::time::timeout(self.timeout, task).await {
+                Ok(Ok(state)) => Some(state),
+                Ok(Err(err)) => {
+                    tracing::warn!("health check {} failed to run: {}", name, err);
+                    let snapshot = Snapshot {
+                        state: HealthState::Outage,
+                        checked_at: Instant::now(),
+                    };
+                    self.snapshots.lock().expect("probe lock poisoned").insert(name, snapshot);
+                    Some(HealthState::Outage)
+                }
+                Err(_) => {
+                    tracing::warn!("health check {} timed out after {:?}", name, self.timeout);
+                    None
+                }
+            };
+            if let Some(state) = state {
+                return CheckReport {
+                    name,
+                    state,
+                    fresh: true,
+                    age_ms: Some(0),
+                };
+            }
+        }
+
+        let snapshot = self.snapshots.lock().expect("probe lock poisoned").get(name).copied();
+        CheckReport {
+            name,
+            state: snapshot.map_or(HealthState::Outage, |snapshot| snapshot.state),
+            fresh: false,
+            age_ms: snapshot.map(|snapshot| snapshot.checked_at.elapsed().as_millis() as u64),
+        }
+    }
+}
+
+/// Readiness body
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    /// Whether the instance should receive traffic
+    pub ready: bool,
+    /// Per-check results
+    pub checks: Vec<CheckReport>,
+    /// Cache warmup result, absent until warmup has run
+    pub warmup: Option<WarmupReport>,
+    /// Subsystems serving through a fallback; they don't affect readiness
+    pub degraded: Vec<Degradation>,
+    /// Whether load shedding is refusing or about to refuse requests
+    pub under_pressure: bool,
+    /// Whether shutdown has started
+    pub draining: bool,
+    /// Whether `Config::maintenance` takes the instance out of rotation
+    pub maintenance: bool,
+    /// Latest migration applied, for backends with a schema
+    pub schema_version: Option<String>,
+    /// Connection pool figures, for backends with a pool
+    pub db_pool: Option<PoolStats>,
+}
+
+/// Readiness endpoint: 200 once warmup has run, before shutdown starts,
+/// outside maintenance, while no dependency is in outage, and, with
+/// `unready_under_pressure`, while load shedding is quiet; 503 otherwise
+pub async fn readiness(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, Json<ApiResponse<Readiness>>) {
+    let config = state.config();
+    let checks = state.readiness.run(&state.health_checks).await;
+    let warmup = state.warmup.report();
+    let degraded = state.degradations.active();
+    let draining = state.draining.load(Ordering::SeqCst);
+    let under_pressure = state.load_shedder.under_pressure(&config.priority);
+    let shedding_traffic = under_pressure && config.priority.unready_under_pressure;
+    let budget = Duration::from_millis(config.health_check_timeout_ms);
+    let schema_version = match tokio::time::timeout(budget, state.users.schema_version()).await {
+        Ok(Ok(version)) => version,
+        Ok(Err(_)) | Err(_) => None,
+    };
+    let ready = !draining
+        && !config.maintenance
+        && !shedding_traffic
+        && warmup.is_some()
+        && checks.iter().all(|check| check.state != HealthState::Outage);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let response = ApiResponse {
+        success: ready,
+        data: Some(Readiness {
+            ready,
+            checks,
+            warmup,
+            degraded,
+            under_pressure,
+            draining,
+            maintenance: config.maintenance,
+            schema_version,
+            db_pool: state.users.pool_stats(),
+        }),
+        error: (!ready).then(|| "not ready".to_string()),
+        error_code: (!ready).then(|| "NOT_READY".to_string()),
+        warnings: Vec::new(),
+        partial: false,
+        meta: None,
+        links: None,
+    };
+    (status, Json(response))
+}
+
+/// Middleware marking responses to the legacy probe paths as deprecated
+pub async fn deprecated_probe<B>(req: Request<B>, next: Next<B>) -> Response {
+    let successor = LEGACY_PROBES
+        .iter()
+        .find(|(legacy, _)| *legacy == req.uri().path())
+        .map(|(_, successor)| *successor);
+    let mut response = next.run(req).await;
+    if let Some(successor) = successor {
+        versioning::mark_deprecated(&mut response, successor, None);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct HungCheck;
+
+    #[async_trait]
+    impl HealthCheck for HungCheck {
+        fn name(&self) -> &'static str {
+            "hung"
+        }
+
+        async fn check(&self) -> Result<HealthState, String> {
+            std::future::pending().await
+        }
+    }
+
+    struct HealthyCheck;
+
+    #[async_trait]
+    impl HealthCheck for HealthyCheck {
+        fn name(&self) -> &'static str {
+            "healthy"
+        }
+
+        async fn check(&self) -> Result<HealthState, String> {
+            Ok(HealthState::Operational)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hung_check_does_not_stall_probe() {
+        let probe = ReadinessProbe::new(Duration::from_millis(20));
+        let checks: Vec<Arc<dyn HealthCheck>> = vec![Arc::new(HungCheck), Arc::new(HealthyCheck)];
+
+        let started = Instant::now();
+        let reports = probe.run(&checks).await;
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        assert_eq!(reports[0].state, HealthState::Outage);
+        assert!(!reports[0].fresh);
+        assert_eq!(reports[1].state, HealthState::Operational);
+        assert!(reports[1].fresh);
+    }
+
+    #[tokio::test]
+    async fn test_liveness_ignores_database_and_legacy_path_is_deprecated() {
+        use axum::body::Body;
+        use tower::ServiceExt;
+
+        let state = AppState::new(crate::Config {
+            maintenance: true,
+            ..crate::Config::default()
+        });
+        let app = crate::handlers::create_router(state);
+        let live = Request::get("/healthz").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(live).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get("deprecation").is_none());
+
+        let legacy = Request::get("/health/ready").body(Body::empty()).unwrap();
+        let response = app.oneshot(legacy).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["deprecation"], "true");
+        assert_eq!(response.headers()["link"], "</readyz>; rel=\"successor-version\"");
+    }
+
+    /// Panics on its first probe, then recovers
+    #[derive(Default)]
+    struct PanickingCheck {
+        probed: std::sync::atomic::AtomicBool,
+    }
+
+    #[async_trait]
+    impl HealthCheck for PanickingCheck {
+        fn name(&self) -> &'static str {
+            "panicking"
+        }
+
+        async fn check(&self) -> Result<HealthState, String> {
+            if !self.probed.swap(true, Ordering::SeqCst) {
+                panic!("check blew up");
+            }
+            Ok(HealthState::Operational)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_panicking_check_is_an_outage_and_probed_again() {
+        let probe = ReadinessProbe::new(Duration::from_secs(1));
+        let checks: Vec<Arc<dyn HealthCheck>> = vec![Arc::new(PanickingCheck::default())];
+        let first = probe.run(&checks).await;
+        assert_eq!((first[0].state, first[0].fresh), (HealthState::Outage, true));
+
+        let second = probe.run(&checks).await;
+        assert_eq!((second[0].state, second[0].fresh), (HealthState::Operational, true));
+    }
+
+    #[tokio::test]
+    async fn test_recent_results_are_reused_until_too_old() {
+        let probe = ReadinessProbe::new(Duration::from_secs(1));
+        let checks: Vec<Arc<dyn HealthCheck>> = vec![Arc::new(HealthyCheck)];
+        let first = probe.recent(&checks, Duration::from_secs(60)).await;
+        assert!(first[0].fresh);
+        let reused = probe.recent(&checks, Duration::from_secs(60)).await;
+        assert_eq!((reused[0].state, reused[0].fresh), (HealthState::Operational, false));
+        assert!(probe.recent(&checks, Duration::ZERO).await[0].fresh);
+    }
+
+    #[tokio::test]
+    async fn test_in_flight_check_is_not_restarted() {
+        let probe = ReadinessProbe::new(Duration::from_millis(10));
+        let checks: Vec<Arc<dyn HealthCheck>> = vec![Arc::new(HungCheck)];
+        probe.run(&checks).await;
+
+        let started = Instant::now();
+        probe.run(&checks).await;
+        assert!(started.elapsed() < Duration::from_millis(10));
+    }
+}
@@ -0,0 +1,306 @@
+//! Hedged reads against the user store.
+//!
+//! When a read hasn't answered within `delay_ms`, a second identical attempt
+//! is sent and whichever finishes first wins; the other is dropped. Only
+//! idempotent reads are hedged. Each read earns `budget_ratio` of a hedge and
+//! each hedge spends one, so hedging adds at most that fraction of extra load
+//! even when the store is slow across the board. The second attempt goes
+//! through the same store, so it uses another pooled connection, or with
+//! `Config::read_replicas` usually the next replica in rotation.
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::events::DomainEvent;
+use crate::repository::{
+    Change, LastSeen, OutboxRecord, OutboxStore, PoolStats, RepositoryError, UserQuery,
+    UserRepository,
+};
+use crate::search::{SearchPage, SearchQuery};
+use crate::User;
+
+/// Most unspent hedges saved up for a burst of slow reads
+const MAX_SAVED_HEDGES: f64 = 10.0;
+
+/// Hedging settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HedgeConfig {
+    /// Hedge user store reads
+    pub enabled: bool,
+    /// Wait before sending the second attempt
+    pub delay_ms: u64,
+    /// Hedges allowed per read, e.g. 0.05 for at most 5% extra reads
+    pub budget_ratio: f64,
+}
+
+impl Default for HedgeConfig {
+    fn default() -> Self {
+        HedgeConfig {
+            enabled: false,
+            delay_ms: 20,
+            budget_ratio: 0.05,
+        }
+    }
+}
+
+/// Hedge counts since startup
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct HedgeStats {
+    /// Second attempts sent
+    pub sent: u64,
+    /// Second attempts that answered first
+    pub won: u64,
+    /// Slow reads not hedged because the budget was spent
+    pub over_budget: u64,
+}
+
+/// Hedging policy and budget shared by every hedged read
+#[derive(Debug)]
+pub struct Hedger {
+    delay: Duration,
+    ratio: f64,
+    budget: Mutex<f64>,
+    sent: AtomicU64,
+    won: AtomicU64,
+    over_budget: AtomicU64,
+}
+
+impl Hedger {
+    /// Create a hedger from configuration
+    pub fn new(config: &HedgeConfig) -> Self {
+        Self {
+            delay: Duration::from_millis(config.delay_ms),
+            ratio: config.budget_ratio.max(0.0),
+            budget: Mutex::new(0.0),
+            sent: AtomicU64::new(0),
+            won: AtomicU64::new(0),
+            over_budget: AtomicU64::new(0),
+        }
+    }
+
+    /// Hedge counts since startup
+    pub fn stats(&self) -> HedgeStats {
+        HedgeStats {
+            sent: self.sent.load(Ordering::Relaxed),
+            won: self.won.load(Ordering::Relaxed),
+            over_budget: self.over_budget.load(Ordering::Relaxed),
+        }
+    }
+
+    fn earn(&self) {
+        let mut budget = self.budget.lock().expect("hedge budget lock poisoned");
+        *budget = (*budget + self.ratio).min(MAX_SAVED_HEDGES);
+    }
+
+    fn try_spend(&self) -> bool {
+        let mut budget = self.budget.lock().expect("hedge budget lock poisoned");
+        if *budget < 1.0 {
+            return false;
+        }
+        *budget -= 1.0;
+        true
+    }
+
+    /// Run `attempt`, starting a second copy if the first is slow and budget allows
+    pub async fn run<T, F, Fut>(&self, attempt: F) -> Result<T, RepositoryError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, RepositoryError>>,
+    {
+        self.earn();
+        let first = attempt();
+        tokio::pin!(first);
+        tokio::select! {
+            result = &mut first => return result,
+            _ = tokio::time::sleep(self.delay) => {}
+        }
+        if !self.try_spend() {
+            self.over_budget.fetch_add(1, Ordering::Relaxed);
+            return first.await;
+        }
+
+        self.sent.fetch_add(1, Ordering::Relaxed);
+        let second = attempt();
+        tokio::pin!(second);
+        tokio::select! {
+            result = &mut first => result,
+            result = &mut second => {
+                self.won.fetch_add(1, Ordering::Relaxed);
+                result
+            }
+        }
+    }
+}
+
+/// Repository decorator hedging reads and passing writes straight through
+pub struct HedgedUserRepository {
+    inner: Arc<dyn UserRepository>,
+    hedger: Arc<Hedger>,
+}
+
+impl HedgedUserRepository {
+    /// Wrap `inner`, hedging its reads with `hedger`
+    pub fn new(inner: Arc<dyn UserRepository>, hedger: Arc<Hedger>) -> Self {
+        Self { inner, hedger }
+    }
+}
+
+#[async_trait]
+impl OutboxStore for HedgedUserRepository {
+    async fn pending_events(&self, limit: usize) -> Result<Vec<OutboxRecord>, RepositoryError> {
+        self.inner.pending_events(limit).await
+    }
+
+    async fn mark_delivered(&self, ids: &[Uuid]) -> Result<(), RepositoryError> {
+        self.inner.mark_delivered(ids).await
+    }
+}
+
+#[async_trait]
+impl UserRepository for HedgedUserRepository {
+    async fn list(&self, tenant: &str, query: &UserQuery) -> Result<Vec<User>, RepositoryError> {
+        self.hedger.run(|| self.inner.list(tenant, query)).await
+    }
+
+    async fn get(&self, tenant: &str, id: Uuid) -> Result<Option<User>, RepositoryError> {
+        self.hedger.run(|| self.inner.get(tenant, id)).await
+    }
+
+    async fn find_by_username(
+        &self,
+        tenant: &str,
+        username: &str,
+    ) -> Result<Option<User>, RepositoryError> {
+        self.hedger.run(|| self.inner.find_by_username(tenant, username)).await
+    }
+
+    async fn find_by_email(
+        &self,
+        tenant: &str,
+        email: &str,
+    ) -> Result<Option<User>, RepositoryError> {
+        self.hedger.run(|| self.inner.find_by_email(tenant, email)).await
+    }
+
+    async fn search(
+        &self,
+        tenant: &str,
+        query: &SearchQuery,
+    ) -> Result<SearchPage, RepositoryError> {
+        self.hedger.run(|| self.inner.search(tenant, query)).await
+    }
+
+    async fn tenants(&self) -> Result<Vec<String>, RepositoryError> {
+        self.inner.tenants().await
+    }
+
+    async fn insert(&self, user: User) -> Result<User, RepositoryError> {
+        self.inner.insert(user).await
+    }
+
+    async fn insert_with_event(
+        &self,
+        user: User,
+        event: DomainEvent,
+    ) -> Result<User, RepositoryError> {
+        self.inner.insert_with_event(user, event).await
+    }
+
+    async fn update(&self, user: User) -> Result<Option<User>, RepositoryError> {
+        self.inner.update(user).await
+    }
+
+    async fn update_with_event(
+        &self,
+        user: User,
+        event: DomainEvent,
+    ) -> Result<Option<User>, RepositoryError> {
+        self.inner.update_with_event(user, event).await
+    }
+
+    async fn commit(&self, changes: &[Change]) -> Result<bool, RepositoryError> {
+        self.inner.commit(changes).await
+    }
+
+    async fn delete(&self, tenant: &str, id: Uuid) -> Result<bool, RepositoryError> {
+        self.inner.delete(tenant, id).await
+    }
+
+    async fn record_last_seen(&self, seen: &[LastSeen]) -> Result<(), RepositoryError> {
+        self.inner.record_last_seen(seen).await
+    }
+
+    async fn last_seen(
+        &self,
+        tenant: &str,
+        id: Uuid,
+    ) -> Result<Option<chrono::DateTime<chrono::Utc>>, RepositoryError> {
+        self.hedger.run(|| self.inner.last_seen(tenant, id)).await
+    }
+
+    async fn migrate(&self) -> Result<Vec<&'static str>, RepositoryError> {
+        self.inner.migrate().await
+    }
+
+    async fn schema_version(&self) -> Result<Option<String>, RepositoryError> {
+        self.inner.schema_version().await
+    }
+
+    fn has_schema(&self) -> bool {
+        self.inner.has_schema()
+    }
+
+    async fn ping(&self) -> Result<(), RepositoryError> {
+        self.inner.ping().await
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        self.inner.pool_stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    fn hedger(budget_ratio: f64) -> Hedger {
+        Hedger::new(&HedgeConfig {
+            enabled: true,
+            delay_ms: 10,
+            budget_ratio,
+        })
+    }
+
+    /// First attempt is slow, later attempts answer at once
+    async fn stuck_then_fast(calls: &AtomicUsize) -> Result<usize, RepositoryError> {
+        let call = calls.fetch_add(1, Ordering::SeqCst);
+        if call == 0 {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+        Ok(call)
+    }
+
+    #[tokio::test]
+    async fn test_slow_read_is_hedged_within_budget() {
+        let hedger = hedger(1.0);
+        let calls = AtomicUsize::new(0);
+        assert_eq!(hedger.run(|| stuck_then_fast(&calls)).await.unwrap(), 1);
+        assert_eq!(hedger.stats(), HedgeStats { sent: 1, won: 1, over_budget: 0 });
+    }
+
+    #[tokio::test]
+    async fn test_no_hedge_without_budget() {
+        let hedger = hedger(0.0);
+        let calls = AtomicUsize::new(0);
+        assert_eq!(hedger.run(|| stuck_then_fast(&calls)).await.unwrap(), 0);
+        assert_eq!(hedger.stats().over_budget, 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,327 @@
+//! Replaying responses to retried POST requests.
+//!
+//! A POST sending `Idempotency-Key: <key>` runs once. When it succeeds, its
+//! response is kept for `Config::idempotency.ttl_secs`, and a retry with the
+//! same key gets that response back, marked `Idempotent-Replayed: true`,
+//! instead of running again, so a client retrying after a dropped connection
+//! doesn't create a second user. Keys are scoped to the caller, the tenant,
+//! and the path, so two tenants' callers sending the same key don't share it.
+//! A retry arriving while the first request is still running is refused with
+//! 409 `IDEMPOTENCY_KEY_IN_USE`, and a key sent again with a different body
+//! with 422 `IDEMPOTENCY_KEY_REUSED`. A request that fails releases its key,
+//! so it can be retried: nothing it was asked to do happened. Responses are
+//! kept in memory, per instance, like seen request signatures; a retry routed
+//! to another instance runs again and finds any user it would duplicate
+//! already taken.
+
+use axum::{
+    body::{boxed, Body, Bytes, Full},
+    extract::State,
+    http::{HeaderMap, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::context::RequestContext;
+use crate::error::{AppError, ErrorCode, ErrorReport};
+use crate::{tenancy, AppState};
+
+/// Header naming the operation a POST performs, the same on every retry
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Header marking a response as replayed from an earlier request
+pub const REPLAYED_HEADER: &str = "idempotent-replayed";
+
+/// Longest key accepted, as the IETF draft suggests for UUIDs and the like
+const MAX_KEY_LEN: usize = 255;
+
+/// Idempotency key settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IdempotencyConfig {
+    /// How long a response is replayed for its key; zero ignores the header
+    pub ttl_secs: u64,
+    /// Most keys kept at once; the oldest response is forgotten past it
+    pub max_keys: usize,
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        IdempotencyConfig {
+            ttl_secs: 86_400,
+            max_keys: 10_000,
+        }
+    }
+}
+
+/// A response kept to replay
+#[derive(Debug, Clone)]
+struct StoredResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl IntoResponse for StoredResponse {
+    fn into_response(self) -> Response {
+        let mut response = Response::new(boxed(Full::from(self.body)));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers;
+        response.headers_mut().insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+        response
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// Hash of the request body the key was first sent with
+    fingerprint: String,
+    started: Instant,
+    /// None while the first request is still running
+    response: Option<StoredResponse>,
+}
+
+/// Responses by scoped idempotency key
+#[derive(Debug, Default)]
+pub struct IdempotencyKeys {
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl IdempotencyKeys {
+    /// Claim `key` to run a request, or get the response to replay for it
+    fn claim(
+        &self,
+        config: &IdempotencyConfig,
+        key: &str,
+        fingerprint: &str,
+        now: Instant,
+    ) -> Result<Option<StoredResponse>, AppError> {
+        let ttl = Duration::from_secs(config.ttl_secs);
+        let mut entries = self.entries.lock().expect("idempotency keys lock poisoned");
+        entries.retain(|_, entry| now.saturating_duration_since(entry.started) < ttl);
+        if let Some(entry) = entries.get(key) {
+            if entry.fingerprint != fingerprint {
+                let reused = "Idempotency-Key was already used with a different request body";
+                return Err(AppError::Validation(vec![reused.to_string()])
+                    .with_code(ErrorCode::IDEMPOTENCY_KEY_REUSED));
+            }
+            return match &entry.response {
+                Some(response) => Ok(Some(response.clone())),
+                None => Err(AppError::Conflict(
+                    "a request with this Idempotency-Key is still running".to_string(),
+                )
+                .with_code(ErrorCode::IDEMPOTENCY_KEY_IN_USE)),
+            };
+        }
+        if entries.len() >= config.max_keys.max(1) {
+            let oldest = entries
+                .iter()
+                .filter(|(_, entry)| entry.response.is_some())
+                .min_by_key(|(_, entry)| entry.started)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        let entry = Entry {
+            fingerprint: fingerprint.to_string(),
+            started: now,
+            response: None,
+        };
+        entries.insert(key.to_string(), entry);
+        Ok(None)
+    }
+
+    /// Keep the response for a claimed key, or release the key without one
+    fn complete(&self, key: &str, response: Option<StoredResponse>) {
+        let mut entries = self.entries.lock().expect("idempotency keys lock poisoned");
+        match response {
+            Some(response) => {
+                if let Some(entry) = entries.get_mut(key) {
+                    entry.response = Some(response);
+                }
+            }
+            None => {
+                entries.remove(key);
+            }
+        }
+    }
+
+    /// Drop keys past their lifetime, returning how many were dropped
+    pub fn purge_expired(&self, config: &IdempotencyConfig, now: Instant) -> usize {
+        let ttl = Duration::from_secs(config.ttl_secs);
+        let mut entries = self.entries.lock().expect("idempotency keys lock poisoned");
+        let before = entries.len();
+        entries.retain(|_, entry| now.saturating_duration_since(entry.started) < ttl);
+        before - entries.len()
+    }
+}
+
+/// A claimed key, released unless a response is kept for it
+///
+/// The request can be dropped partway, by a timeout or a disconnect, and its
+/// retry must then run rather than wait out the TTL.
+struct Claim<'a> {
+    keys: &'a IdempotencyKeys,
+    key: String,
+    kept: bool,
+}
+
+impl Claim<'_> {
+    fn keep(mut self, response: StoredResponse) {
+        self.keys.complete(&self.key, Some(response));
+        self.kept = true;
+    }
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        if !self.kept {
+            self.keys.complete(&self.key, None);
+        }
+    }
+}
+
+/// Middleware running a keyed POST once and replaying its response to retries
+pub async fn replay_retries(
+    State(state): State<Arc<AppState>>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let config = state.config().idempotency.clone();
+    let key = req
+        .headers()
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|key| key.to_str().ok())
+        .map(str::to_string);
+    let key = match key {
+        Some(key) if req.method() == Method::POST && config.ttl_secs > 0 => key,
+        _ => return next.run(req).await,
+    };
+    if key.is_empty() || key.len() > MAX_KEY_LEN {
+        let message = format!("Idempotency-Key must be 1 to {} characters", MAX_KEY_LEN);
+        return AppError::BadRequest(message).into_response();
+    }
+
+    let (parts, body) = req.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(err) => {
+            return AppError::BadRequest(format!("failed to read request body: {}", err))
+                .into_response()
+        }
+    };
+    let (principal, tenant) = match parts.extensions.get::<RequestContext>() {
+        Some(context) => {
+            let tenant = tenancy::serve(&state.config().tenancy, context.tenant.clone());
+            (context.principal.to_string(), tenant.unwrap_or_default())
+        }
+        None => Default::default(),
+    };
+    let scoped = format!("{} {} {} {}", principal, tenant, parts.uri.path(), key);
+    let fingerprint = hex::encode(Sha256::digest(&body));
+    let claimed = state.idempotency.claim(&config, &scoped, &fingerprint, Instant::now());
+    match claimed {
+        Ok(Some(stored)) => return stored.into_response(),
+        Ok(None) => {}
+        Err(err) => return err.into_response(),
+    }
+
+    let claim = Claim {
+        keys: &state.idempotency,
+        key: scoped,
+        kept: false,
+    };
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    if !response.status().is_success() {
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(err) => return AppError::Internal(ErrorReport::new(err)).into_response(),
+    };
+    claim.keep(StoredResponse {
+        status: parts.status,
+        headers: parts.headers.clone(),
+        body: body.clone(),
+    });
+    Response::from_parts(parts, boxed(Full::from(body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::TENANT_HEADER;
+    use crate::test_support::{expect_data, TestServer};
+    use crate::Config;
+    use serde_json::json;
+
+    fn stored(body: &'static str) -> StoredResponse {
+        StoredResponse {
+            status: StatusCode::CREATED,
+            headers: HeaderMap::new(),
+            body: Bytes::from_static(body.as_bytes()),
+        }
+    }
+
+    #[test]
+    fn test_retry_replays_response_until_ttl_and_refuses_other_bodies() {
+        let keys = IdempotencyKeys::default();
+        let config = IdempotencyConfig::default();
+        let start = Instant::now();
+        assert!(keys.claim(&config, "k", "body", start).unwrap().is_none());
+
+        let running = keys.claim(&config, "k", "body", start).unwrap_err();
+        assert_eq!(running.code(), ErrorCode::IDEMPOTENCY_KEY_IN_USE);
+        keys.complete("k", Some(stored("alice")));
+        let replayed = keys.claim(&config, "k", "body", start).unwrap().unwrap();
+        assert_eq!(replayed.body, "alice");
+        let reused = keys.claim(&config, "k", "other", start).unwrap_err();
+        assert_eq!(reused.code(), ErrorCode::IDEMPOTENCY_KEY_REUSED);
+
+        let later = start + Duration::from_secs(config.ttl_secs);
+        assert!(keys.claim(&config, "k", "other", later).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_dropped_claim_releases_key_for_retry() {
+        let keys = IdempotencyKeys::default();
+        let config = IdempotencyConfig::default();
+        let now = Instant::now();
+        keys.claim(&config, "k", "body", now).unwrap();
+        drop(Claim {
+            keys: &keys,
+            key: "k".to_string(),
+            kept: false,
+        });
+        assert!(keys.claim(&config, "k", "body", now).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_tenants_sending_the_same_key_each_create_their_own_user() {
+        let server = TestServer::start(Config::default()).await;
+        let body = json!({ "username": "alice", "email": "alice@example.com" });
+        let mut ids = Vec::new();
+        for tenant in ["acme", "globex"] {
+            let response = server
+                .request(Method::POST, "/api/v1/users")
+                .header(TENANT_HEADER, tenant)
+                .header(IDEMPOTENCY_KEY_HEADER, "create-alice")
+                .json(&body)
+                .send()
+                .await
+                .unwrap();
+            assert!(response.headers().get(REPLAYED_HEADER).is_none(), "{}", tenant);
+            let user = expect_data(response, StatusCode::CREATED).await;
+            assert_eq!(user["tenant_id"], tenant);
+            ids.push(user["id"].clone());
+        }
+        assert_ne!(ids[0], ids[1]);
+    }
+}
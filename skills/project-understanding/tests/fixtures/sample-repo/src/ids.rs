@@ -0,0 +1,54 @@
+//! How new user IDs are generated.
+//!
+//! `Config::id_strategy` picks the UUID version of users created from then
+//! on. The default, v7, starts with the creation time in milliseconds, so
+//! IDs sort the way users were created and new rows land at the end of the
+//! primary key index instead of at random pages of it. v4 IDs are entirely
+//! random, as every ID was before. Either strategy only affects new users:
+//! IDs are stored and looked up as plain UUIDs whatever their version, so
+//! existing v4 IDs keep working alongside v7 ones.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::{NoContext, Timestamp, Uuid};
+
+/// UUID version of new user IDs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdStrategy {
+    /// Time-ordered, from the creation time
+    #[default]
+    V7,
+    /// Random
+    V4,
+}
+
+impl IdStrategy {
+    /// A new ID for a record created at `at`
+    pub fn id_at(self, at: DateTime<Utc>) -> Uuid {
+        match self {
+            IdStrategy::V7 => {
+                let nanos = at.timestamp_subsec_nanos();
+                let timestamp = Timestamp::from_unix(NoContext, at.timestamp() as u64, nanos);
+                Uuid::new_v7(timestamp)
+            }
+            IdStrategy::V4 => Uuid::new_v4(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_v7_ids_sort_by_creation_time() {
+        let start = Utc::now();
+        let ids: Vec<Uuid> = (0..5)
+            .map(|ms| IdStrategy::V7.id_at(start + chrono::Duration::milliseconds(ms)))
+            .collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(ids.iter().all(|id| id.get_version_num() == 7));
+        assert_eq!(IdStrategy::V4.id_at(start).get_version_num(), 4);
+    }
+}
@@ -0,0 +1,367 @@
+//! Bulk user import from CSV or NDJSON uploads.
+//!
+//! Each row is validated independently; valid rows are inserted and
+//! invalid ones are reported back with their row number. A response with
+//! rejected rows carries a `ROWS_REJECTED` warning, and is marked partial
+//! when some rows went in anyway. With `Prefer: respond-async` the rows are
+//! processed as an operation and the request returns 202 right away; the
+//! operation's result is the report. Users are imported into the request's
+//! tenant, each row in its own `UnitOfWork`, so a row is inserted with its
+//! outbox event and audit entry or not at all.
+
+use axum::{
+    extract::{Multipart, Query, State},
+    http::HeaderMap,
+    response::{IntoResponse, Json, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::audit::{AuditAction, AuditContext};
+use crate::error::AppError;
+use crate::events::{DomainEvent, EventKind};
+use crate::operations::{self, ProgressReporter};
+use crate::tenancy::Tenant;
+use crate::unit_of_work::UnitOfWork;
+use crate::{replication, service, ApiResponse, AppState, User};
+
+/// Query parameters for the import endpoint
+#[derive(Debug, Default, Deserialize)]
+pub struct ImportParams {
+    /// Validate only, without inserting anything
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Supported upload formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    /// Comma-separated values with a header row
+    Csv,
+    /// One JSON object per line
+    NdJson,
+}
+
+impl ImportFormat {
+    /// Detect the format from a part's content type, falling back to the file name
+    pub fn detect(content_type: Option<&str>, file_name: Option<&str>) -> Option<Self> {
+        match content_type {
+            Some("text/csv") => return Some(ImportFormat::Csv),
+            Some("application/x-ndjson") | Some("application/jsonl") => {
+                return Some(ImportFormat::NdJson)
+            }
+            _ => {}
+        }
+        let file_name = file_name?.to_ascii_lowercase();
+        if file_name.ends_with(".csv") {
+            Some(ImportFormat::Csv)
+        } else if file_name.ends_with(".ndjson") || file_name.ends_with(".jsonl") {
+            Some(ImportFormat::NdJson)
+        } else {
+            None
+        }
+    }
+}
+
+/// A single user record as it appears in an upload
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImportRow {
+    /// Username
+    pub username: String,
+    /// Email address
+    pub email: String,
+    /// Active status, defaults to active
+    #[serde(default)]
+    pub is_active: Option<bool>,
+}
+
+/// Validation failure for one row
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct RowError {
+    /// 1-based row number, excluding any header
+    pub row: usize,
+    /// Human-readable reasons the row was rejected
+    pub errors: Vec<String>,
+}
+
+/// Outcome of an import request
+#[derive(Debug, Serialize)]
+pub struct ImportReport {
+    /// Number of data rows read from the upload
+    pub total: usize,
+    /// Rows inserted (or that would be inserted in dry-run mode)
+    pub imported: usize,
+    /// Rows rejected
+    pub failed: usize,
+    /// Whether this was a dry run
+    pub dry_run: bool,
+    /// Per-row error details
+    pub errors: Vec<RowError>,
+}
+
+/// Parse an upload into rows; rows that fail to parse are returned as errors
+pub fn parse_rows(format: ImportFormat, body: &[u8]) -> Vec<(usize, Result<ImportRow, String>)> {
+    match format {
+        ImportFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(body);
+            reader
+                .deserialize::<ImportRow>()
+                .enumerate()
+                .map(|(index, row)| (index + 1, row.map_err(|e| e.to_string())))
+                .collect()
+        }
+        ImportFormat::NdJson => String::from_utf8_lossy(body)
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                (index + 1, serde_json::from_str(line).map_err(|e| e.to_string()))
+            })
+            .collect(),
+    }
+}
+
+/// Check a row's fields, returning every problem found
+pub fn validate_row(row: &ImportRow) -> Vec<String> {
+    service::validate(&row.username, &row.email)
+}
+
+/// Import users from a multipart upload, in the background if asked to
+#[tracing::instrument(skip_all)]
+pub async fn import_users(
+    State(state): State<Arc<AppState>>,
+    context: AuditContext,
+    Tenant(tenant): Tenant,
+    Query(params): Query<ImportParams>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<Response, AppError> {
+    let field = multipart
+        .next_field()
+        .await
+        .map_err(|err| AppError::BadRequest(err.to_string()))?
+        .ok_or_else(|| AppError::BadRequest("upload has no file".to_string()))?;
+    let format = ImportFormat::detect(field.content_type(), field.file_name()).ok_or_else(|| {
+        AppError::UnsupportedMediaType("upload is neither CSV nor NDJSON".to_string())
+    })?;
+    let body = field.bytes().await.map_err(|err| AppError::BadRequest(err.to_string()))?;
+    let rows = parse_rows(format, &body);
+
+    if operations::wants_async(&headers) {
+        let operation = operations::spawn(&state, "user_import", |progress| {
+            let state = state.clone();
+            async move {
+                let dry_run = params.dry_run;
+                let report = run_import(&state, &context, &tenant, dry_run, rows, Some(&progress))
+                    .await
+                    .map_err(|_| "failed to look up existing users".to_string())?;
+                serde_json::to_value(report).map_err(|err| err.to_string())
+            }
+        });
+        return Ok(operations::accepted(operation));
+    }
+
+    let report = run_import(&state, &context, &tenant, params.dry_run, rows, None).await?;
+    let (failed, total, imported) = (report.failed, report.total, report.imported);
+    let mut response = ApiResponse::success(report);
+    if failed > 0 {
+        let message = format!("{} of {} rows were rejected", failed, total);
+        response = response.with_warning("ROWS_REJECTED", message);
+        if imported > 0 {
+            response = response.into_partial();
+        }
+    }
+    Ok(Json(response).into_response())
+}
+
+/// Validate and insert parsed rows, reporting progress row by row
+async fn run_import(
+    state: &Arc<AppState>,
+    context: &AuditContext,
+    tenant: &str,
+    dry_run: bool,
+    rows: Vec<(usize, Result<ImportRow, String>)>,
+    progress: Option<&ProgressReporter>,
+) -> Result<ImportReport, AppError> {
+    let mut report = ImportReport {
+        total: rows.len(),
+        imported: 0,
+        failed: 0,
+        dry_run,
+        errors: Vec::new(),
+    };
+    let mut seen_usernames = HashSet::new();
+    let mut seen_emails = HashSet::new();
+
+    for (index, (row_number, parsed)) in rows.into_iter().enumerate() {
+        if let Some(progress) = progress {
+            progress.report(index, report.total);
+        }
+        let row = match parsed {
+            Ok(row) => row,
+            Err(message) => {
+                report.errors.push(RowError { row: row_number, errors: vec![message] });
+                continue;
+            }
+        };
+
+        let mut errors = validate_row(&row);
+        let username = row.username.trim().to_string();
+        let email = row.email.trim().to_ascii_lowercase();
+        if !seen_usernames.insert(username.clone()) {
+            errors.push("username is duplicated within the upload".to_string());
+        }
+        if !seen_emails.insert(email.clone()) {
+            errors.push("email is duplicated within the upload".to_string());
+        }
+        if errors.is_empty() {
+            let users = &state.users;
+            if users.find_by_username(tenant, &username).await?.is_some() {
+                errors.push("username already exists".to_string());
+            }
+            if users.find_by_email(tenant, &email).await?.is_some() {
+                errors.push("email already exists".to_string());
+            }
+        }
+        if !errors.is_empty() {
+            report.errors.push(RowError { row: row_number, errors });
+            continue;
+        }
+
+        if !dry_run {
+            let created_at = state.services.clock.now();
+            let mut user = User {
+                id: state.services.ids.user_id(created_at),
+                tenant_id: tenant.to_string(),
+                created_at,
+                ..User::new(username, email)
+            };
+            if row.is_active == Some(false) {
+                user.deactivate();
+            }
+            let event = DomainEvent::user(EventKind::UserCreated, &user);
+            let event = replication::tag(state, None, event);
+            let mut work = UnitOfWork::begin(state);
+            work.insert(&user);
+            work.enqueue(event);
+            work.audit(context, "user", user.id, AuditAction::Create, None, Some(&user));
+            if let Err(err) = work.commit().await {
+                report.errors.push(RowError { row: row_number, errors: vec![err.to_string()] });
+                continue;
+            }
+        }
+        report.imported += 1;
+    }
+
+    report.failed = report.errors.len();
+    if let Some(progress) = progress {
+        progress.report(report.total, report.total);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::{AuditQuery, AuditStore};
+    use crate::repository::InMemoryUserRepository;
+    use crate::services::{IdGenerator, Services};
+    use crate::tenancy::DEFAULT_TENANT;
+    use crate::test_support::{expect_error, TestServer};
+    use crate::Config;
+    use reqwest::{Method, StatusCode};
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(ImportFormat::detect(Some("text/csv"), None), Some(ImportFormat::Csv));
+        assert_eq!(ImportFormat::detect(None, Some("users.NDJSON")), Some(ImportFormat::NdJson));
+        assert_eq!(ImportFormat::detect(Some("image/png"), Some("a.png")), None);
+    }
+
+    #[test]
+    fn test_parse_csv_rows() {
+        let body = b"username,email\nalice,alice@example.com\nbob,bob@example.com\n";
+        let rows = parse_rows(ImportFormat::Csv, body);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1].0, 2);
+        assert_eq!(rows[1].1.as_ref().unwrap().username, "bob");
+    }
+
+    #[test]
+    fn test_parse_ndjson_reports_bad_lines() {
+        let body = b"{\"username\":\"alice\",\"email\":\"a@example.com\"}\nnot json\n";
+        let rows = parse_rows(ImportFormat::NdJson, body);
+        assert!(rows[0].1.is_ok());
+        assert_eq!(rows[1].0, 2);
+        assert!(rows[1].1.is_err());
+    }
+
+    #[test]
+    fn test_validate_row() {
+        let row = ImportRow {
+            username: "".to_string(),
+            email: "nope".to_string(),
+            is_active: None,
+        };
+        assert_eq!(validate_row(&row).len(), 2);
+    }
+
+    struct FixedIds(uuid::Uuid);
+
+    impl IdGenerator for FixedIds {
+        fn user_id(&self, _at: chrono::DateTime<chrono::Utc>) -> uuid::Uuid {
+            self.0
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rows_commit_with_their_audit_entry_or_not_at_all() {
+        let services = Services {
+            ids: Arc::new(FixedIds(uuid::Uuid::new_v4())),
+            ..Services::from_config(&Config::default())
+        };
+        let users = Arc::new(InMemoryUserRepository::new());
+        let state = AppState::with_services(Config::default(), users, services);
+        let context = AuditContext {
+            actor: "admin".to_string(),
+            request_id: "test".to_string(),
+            client_ip: None,
+        };
+        // Both rows get the same ID, so the second can't be stored
+        let body = b"username,email\nalice,alice@example.com\nbob,bob@example.com\n";
+        let rows = parse_rows(ImportFormat::Csv, body);
+        let report = run_import(&state, &context, DEFAULT_TENANT, false, rows, None).await.unwrap();
+        assert_eq!((report.imported, report.failed), (1, 1));
+        assert_eq!(report.errors[0].row, 2);
+
+        let query = AuditQuery { entity: "user".to_string(), id: None };
+        let audited = state.audit.query(&query).await.unwrap();
+        assert_eq!(audited.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_uploads_answer_with_the_error_envelope() {
+        let server = TestServer::start(Config::default()).await;
+        let body = "--b\r\nContent-Disposition: form-data; name=\"file\"; \
+                    filename=\"users.xml\"\r\nContent-Type: application/xml\r\n\r\n\
+                    <users/>\r\n--b--\r\n";
+        let response = server
+            .request(Method::POST, "/api/v1/users/import")
+            .header("content-type", "multipart/form-data; boundary=b")
+            .body(body)
+            .send()
+            .await
+            .unwrap();
+        expect_error(response, StatusCode::UNSUPPORTED_MEDIA_TYPE, "UNSUPPORTED_MEDIA_TYPE").await;
+
+        let empty = server
+            .request(Method::POST, "/api/v1/users/import")
+            .header("content-type", "multipart/form-data; boundary=b")
+            .body("--b--\r\n")
+            .send()
+            .await
+            .unwrap();
+        expect_error(empty, StatusCode::BAD_REQUEST, "BAD_REQUEST").await;
+    }
+}
@@ -0,0 +1,523 @@
+//! Buffered bulk ingestion of append-only activity rows.
+//!
+//! With `Config::activity_ingest.enabled`, every audit entry is also offered
+//! to `ActivityIngester` as an `activity` row for analytics. Rows collect in
+//! memory and are written with Postgres `COPY` in batches of `batch_rows`,
+//! at least every `flush_interval_ms`. When the memory buffer is full, rows
+//! overflow to `spill_path` as JSON lines and are copied once the buffer
+//! has drained, including after a restart. `offer` tells producers where a
+//! row went, so they can back off once rows start spilling or are rejected
+//! because the spill file is full too. Rows are not copied in strict order.
+//!
+//! Expects an `activity` table `at timestamptz, kind text, actor text,
+//! request_id text, subject uuid, payload jsonb`, created if missing.
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::{PgPool, PgPoolCopyExt, PgPoolOptions};
+use std::collections::VecDeque;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::audit::AuditEntry;
+use crate::lifecycle::Component;
+use crate::AppState;
+
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS activity (\
+    at timestamptz NOT NULL, kind text NOT NULL, actor text NOT NULL, \
+    request_id text NOT NULL, subject uuid, payload jsonb NOT NULL)";
+
+const COPY_ACTIVITY: &str = "COPY activity (at, kind, actor, request_id, subject, payload) \
+    FROM STDIN WITH (FORMAT csv)";
+
+/// Ingestion settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IngestConfig {
+    /// Copy activity rows to the database
+    pub enabled: bool,
+    /// Rows per `COPY`
+    pub batch_rows: usize,
+    /// Longest a row waits in memory
+    pub flush_interval_ms: u64,
+    /// Rows held in memory before spilling
+    pub max_buffered: usize,
+    /// Overflow file; without one, rows over `max_buffered` are rejected
+    pub spill_path: Option<PathBuf>,
+    /// Size past which the spill file rejects rows
+    pub spill_max_bytes: u64,
+}
+
+impl Default for IngestConfig {
+    fn default() -> Self {
+        IngestConfig {
+            enabled: false,
+            batch_rows: 1_000,
+            flush_interval_ms: 1_000,
+            max_buffered: 10_000,
+            spill_path: None,
+            spill_max_bytes: 256 * 1024 * 1024,
+        }
+    }
+}
+
+/// One row of the `activity` table
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActivityRow {
+    /// When it happened
+    pub at: chrono::DateTime<chrono::Utc>,
+    /// What happened, e.g. `user.update`
+    pub kind: String,
+    /// Who did it
+    pub actor: String,
+    /// Request that caused it
+    pub request_id: String,
+    /// Entity it happened to
+    pub subject: Option<Uuid>,
+    /// Details
+    pub payload: serde_json::Value,
+}
+
+impl ActivityRow {
+    /// Row for an audit entry, carrying its field changes
+    pub fn audit(entry: &AuditEntry) -> Self {
+        let action = serde_json::to_value(entry.action).unwrap_or_default();
+        Self {
+            at: entry.timestamp,
+            kind: format!("{}.{}", entry.entity, action.as_str().unwrap_or("unknown")),
+            actor: entry.actor.clone(),
+            request_id: entry.request_id.clone(),
+            subject: Some(entry.entity_id),
+            payload: serde_json::json!({ "changes": entry.changes }),
+        }
+    }
+}
+
+/// Where an offered row went
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    /// Held in memory
+    Buffered,
+    /// Written to the spill file; producers should slow down
+    Spilled,
+    /// Dropped because memory and spill are full; producers should back off
+    Rejected,
+}
+
+/// Ingestion counts since startup
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct IngestStats {
+    /// Rows held in memory now
+    pub buffered: usize,
+    /// Rows written to the spill file
+    pub spilled: u64,
+    /// Rows dropped
+    pub rejected: u64,
+    /// Rows copied to the database
+    pub copied: u64,
+}
+
+/// Destination of copied rows
+#[async_trait]
+pub trait CopyTarget: Send + Sync {
+    /// Write every row or none
+    async fn copy(&self, rows: &[ActivityRow]) -> Result<(), String>;
+}
+
+/// The `activity` table, written with `COPY`
+pub struct PgCopyTarget {
+    pool: PgPool,
+}
+
+impl PgCopyTarget {
+    /// Connect and create the table if missing
+    pub async fn connect(url: &str) -> Result<Self, sqlx::Error> {
+        let pool = PgPoolOptions::new().max_connections(1).connect(url).await?;
+        sqlx::query(CREATE_TABLE).execute(&pool).await?;
+        Ok(Self { pool })
+    }
+}
+
+/// Quote a CSV field; `None` is an unquoted empty field, which `COPY` reads as NULL
+fn csv_field(out: &mut String, value: Option<&str>) {
+    if let Some(value) = value {
+        out.push('"');
+        out.push_str(&value.replace('"', "\"\""));
+        out.push('"');
+    }
+}
+
+/// Rows in the CSV layout `COPY_ACTIVITY` expects
+fn to_csv(rows: &[ActivityRow]) -> String {
+    let mut out = String::new();
+    for row in rows {
+        let subject = row.subject.map(|id| id.to_string());
+        let fields = [
+            Some(row.at.to_rfc3339()),
+            Some(row.kind.clone()),
+            Some(row.actor.clone()),
+            Some(row.request_id.clone()),
+            subject,
+            Some(row.payload.to_string()),
+        ];
+        for (i, field) in fields.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            csv_field(&mut out, field.as_deref());
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[async_trait]
+impl CopyTarget for PgCopyTarget {
+    async fn copy(&self, rows: &[ActivityRow]) -> Result<(), String> {
+        let mut copy = self.pool.copy_in_raw(COPY_ACTIVITY).await.map_err(|err| err.to_string())?;
+        if let Err(err) = copy.send(to_csv(rows).into_bytes()).await {
+            let _ = copy.abort(err.to_string()).await;
+            return Err(err.to_string());
+        }
+        copy.finish().await.map(|_| ()).map_err(|err| err.to_string())
+    }
+}
+
+/// Memory buffer and spill file in front of a `CopyTarget`
+pub struct ActivityIngester {
+    config: IngestConfig,
+    buffer: Mutex<VecDeque<ActivityRow>>,
+    /// Serializes access to the spill file
+    spill: tokio::sync::Mutex<()>,
+    ready: Notify,
+    spilled: AtomicU64,
+    rejected: AtomicU64,
+    copied: AtomicU64,
+}
+
+impl ActivityIngester {
+    /// Create an empty ingester
+    pub fn new(config: &IngestConfig) -> Self {
+        Self {
+            config: config.clone(),
+            buffer: Mutex::new(VecDeque::new()),
+            spill: tokio::sync::Mutex::new(()),
+            ready: Notify::new(),
+            spilled: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+            copied: AtomicU64::new(0),
+        }
+    }
+
+    /// Queue a row, spilling or rejecting it when memory is full
+    pub async fn offer(&self, row: ActivityRow) -> Admission {
+        let row = {
+            let mut buffer = self.buffer.lock().expect("ingest buffer lock poisoned");
+            if buffer.len() < self.config.max_buffered {
+                buffer.push_back(row);
+                if buffer.len() >= self.config.batch_rows {
+                    self.ready.notify_one();
+                }
+                return Admission::Buffered;
+            }
+            row
+        };
+        match self.spill_rows(&[row], true).await {
+            Ok(true) => {
+                self.spilled.fetch_add(1, Ordering::Relaxed);
+                Admission::Spilled
+            }
+            Ok(false) => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                Admission::Rejected
+            }
+            Err(err) => {
+                tracing::warn!("failed to spill activity row: {}", err);
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                Admission::Rejected
+            }
+        }
+    }
+
+    /// Whether producers should slow down
+    pub fn under_pressure(&self) -> bool {
+        self.buffer.lock().expect("ingest buffer lock poisoned").len() >= self.config.max_buffered
+    }
+
+    /// Ingestion counts since startup
+    pub fn stats(&self) -> IngestStats {
+        IngestStats {
+            buffered: self.buffer.lock().expect("ingest buffer lock poisoned").len(),
+            spilled: self.spilled.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            copied: self.copied.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Append rows to the spill file, unless `bounded` and they don't fit
+    async fn spill_rows(&self, rows: &[ActivityRow], bounded: bool) -> io::Result<bool> {
+        let Some(path) = &self.config.spill_path else {
+            return Ok(false);
+        };
+        let mut lines = Vec::new();
+        for row in rows {
+            serde_json::to_writer(&mut lines, row)?;
+            lines.push(b'\n');
+        }
+        let _guard = self.spill.lock().await;
+        let size = match tokio::fs::metadata(path).await {
+            Ok(metadata) => metadata.len(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err),
+        };
+        if bounded && size + lines.len() as u64 > self.config.spill_max_bytes {
+            return Ok(false);
+        }
+        let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(path).await?;
+        file.write_all(&lines).await?;
+        file.flush().await?;
+        Ok(true)
+    }
+
+    /// Read and empty the spill file
+    async fn take_spilled(&self, path: &Path) -> io::Result<Vec<ActivityRow>> {
+        let _guard = self.spill.lock().await;
+        let contents = match tokio::fs::read_to_string(path).await {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        tokio::fs::remove_file(path).await?;
+        Ok(contents.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+    }
+
+    /// Put rows whose copy failed back, at the front so they go first
+    fn requeue(&self, rows: Vec<ActivityRow>) {
+        let mut buffer = self.buffer.lock().expect("ingest buffer lock poisoned");
+        for row in rows.into_iter().rev() {
+            buffer.push_front(row);
+        }
+    }
+
+    /// Copy one batch from memory, or the spill file once memory is empty;
+    /// returns the rows copied
+    pub async fn flush_once(&self, target: &dyn CopyTarget) -> Result<usize, String> {
+        let batch: Vec<_> = {
+            let mut buffer = self.buffer.lock().expect("ingest buffer lock poisoned");
+            let len = buffer.len().min(self.config.batch_rows.max(1));
+            buffer.drain(..len).collect()
+        };
+        if batch.is_empty() {
+            return self.drain_spill(target).await;
+        }
+        match target.copy(&batch).await {
+            Ok(()) => {
+                self.copied.fetch_add(batch.len() as u64, Ordering::Relaxed);
+                Ok(batch.len())
+            }
+            Err(err) => {
+                self.requeue(batch);
+                Err(err)
+            }
+        }
+    }
+
+    async fn drain_spill(&self, target: &dyn CopyTarget) -> Result<usize, String> {
+        let Some(path) = &self.config.spill_path else {
+            return Ok(0);
+        };
+        let rows = self.take_spilled(path).await.map_err(|err| err.to_string())?;
+        let mut copied = 0;
+        for chunk in rows.chunks(self.config.batch_rows.max(1)) {
+            if let Err(err) = target.copy(chunk).await {
+                // They fit before, so put them back regardless of the size limit
+                if let Err(spill_err) = self.spill_rows(&rows[copied..], false).await {
+                    tracing::error!("lost {} spilled rows: {}", rows.len() - copied, spill_err);
+                }
+                return Err(err);
+            }
+            copied += chunk.len();
+            self.copied.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        }
+        Ok(copied)
+    }
+
+    /// Copy everything in memory, spilling what can't be copied
+    async fn flush_all(&self, target: &dyn CopyTarget) {
+        loop {
+            match self.flush_once(target).await {
+                Ok(0) => return,
+                Ok(_) => {}
+                Err(err) => {
+                    tracing::warn!("final activity copy failed: {}", err);
+                    return self.spill_all().await;
+                }
+            }
+        }
+    }
+
+    /// Spill everything in memory without copying it
+    async fn spill_all(&self) {
+        let mut buffer = self.buffer.lock().expect("ingest buffer lock poisoned");
+        let rest: Vec<_> = buffer.drain(..).collect();
+        drop(buffer);
+        if rest.is_empty() {
+            return;
+        }
+        match self.spill_rows(&rest, false).await {
+            Ok(true) => {}
+            Ok(false) => tracing::error!("lost {} activity rows", rest.len()),
+            Err(err) => tracing::error!("lost {} activity rows: {}", rest.len(), err),
+        }
+    }
+}
+
+/// Background copier that flushes or spills what is left when stopped
+#[derive(Default)]
+pub struct ActivityIngestion {
+    running: Mutex<Option<(JoinHandle<()>, Arc<Notify>)>>,
+}
+
+#[async_trait]
+impl Component for ActivityIngestion {
+    fn name(&self) -> &'static str {
+        "activity_ingest"
+    }
+
+    async fn start(&self, state: &Arc<AppState>) -> Result<(), String> {
+        if !state.config().activity_ingest.enabled {
+            return Ok(());
+        }
+        let url = state.config().database_url.expose().to_string();
+        if url.starts_with("memory:") || url.starts_with("sqlite:") {
+            tracing::info!("storage is not Postgres: not copying activity rows");
+            return Ok(());
+        }
+        let target = PgCopyTarget::connect(&url).await.map_err(|err| err.to_string())?;
+        let stopping = Arc::new(Notify::new());
+        let interval = Duration::from_millis(state.config().activity_ingest.flush_interval_ms);
+        let handle = tokio::spawn({
+            let (state, stopping) = (state.clone(), stopping.clone());
+            async move {
+                loop {
+                    tokio::select! {
+                        _ = tokio::time::sleep(interval) => {}
+                        _ = state.activity.ready.notified() => {}
+                        _ = stopping.notified() => break,
+                    }
+                    // Rows overflow to the spill file until writes resume
+                    if state.read_only.is_enabled() {
+                        continue;
+                    }
+                    // Keep copying while full batches are waiting
+                    loop {
+                        match state.activity.flush_once(&target).await {
+                            Ok(copied) if copied >= state.config().activity_ingest.batch_rows => {}
+                            Ok(_) => {
+                                state.degradations.recover("activity_ingest");
+                                break;
+                            }
+                            Err(err) => {
+                                tracing::warn!("activity copy failed: {}", err);
+                                let mode = match state.config().activity_ingest.spill_path {
+                                    Some(_) => "spill_to_disk",
+                                    None => "buffer_in_memory",
+                                };
+                                state.degradations.degrade("activity_ingest", mode, err);
+                                break;
+                            }
+                        }
+                    }
+                }
+                if state.read_only.is_enabled() {
+                    state.activity.spill_all().await;
+                } else {
+                    state.activity.flush_all(&target).await;
+                }
+            }
+        });
+        *self.running.lock().expect("ingest handle lock poisoned") = Some((handle, stopping));
+        Ok(())
+    }
+
+    async fn stop(&self) -> Result<(), String> {
+        let running = self.running.lock().expect("ingest handle lock poisoned").take();
+        if let Some((handle, stopping)) = running {
+            // Stored permit, so a copy in progress finishes before the final one
+            stopping.notify_one();
+            handle.await.map_err(|err| err.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Recorded(Mutex<Vec<ActivityRow>>);
+
+    #[async_trait]
+    impl CopyTarget for Recorded {
+        async fn copy(&self, rows: &[ActivityRow]) -> Result<(), String> {
+            self.0.lock().unwrap().extend_from_slice(rows);
+            Ok(())
+        }
+    }
+
+    fn row(kind: &str) -> ActivityRow {
+        ActivityRow {
+            at: chrono::Utc::now(),
+            kind: kind.to_string(),
+            actor: "admin".to_string(),
+            request_id: "test".to_string(),
+            subject: None,
+            payload: serde_json::json!({ "note": "say \"hi\"" }),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_overflow_spills_then_drains() {
+        let dir = std::env::temp_dir().join(format!("ingest-{}", Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let config = IngestConfig {
+            enabled: true,
+            batch_rows: 10,
+            max_buffered: 1,
+            spill_path: Some(dir.join("spill.ndjson")),
+            spill_max_bytes: 200,
+            ..IngestConfig::default()
+        };
+        let ingester = ActivityIngester::new(&config);
+        assert_eq!(ingester.offer(row("a")).await, Admission::Buffered);
+        assert_eq!(ingester.offer(row("b")).await, Admission::Spilled);
+        assert!(ingester.under_pressure());
+        // Each spilled line is well over half the spill limit
+        assert_eq!(ingester.offer(row("c")).await, Admission::Rejected);
+
+        let target = Recorded::default();
+        assert_eq!(ingester.flush_once(&target).await, Ok(1));
+        assert_eq!(ingester.flush_once(&target).await, Ok(1));
+        assert_eq!(ingester.flush_once(&target).await, Ok(0));
+        let kinds: Vec<_> = target.0.lock().unwrap().iter().map(|row| row.kind.clone()).collect();
+        assert_eq!(kinds, vec!["a".to_string(), "b".to_string()]);
+        let stats = ingester.stats();
+        assert_eq!((stats.spilled, stats.rejected, stats.copied), (1, 1, 2));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_csv_quotes_fields_and_leaves_nulls_bare() {
+        let csv = to_csv(&[row("user.update")]);
+        let fields = r#","user.update","admin","test",,"{""note"":""say \""hi\""""}""#;
+        assert!(csv.ends_with(&format!("{}\n", fields)));
+    }
+}
@@ -0,0 +1,326 @@
+//! Scheduled data integrity checks.
+//!
+//! Every `Config::integrity.interval_secs` the checker runs each
+//! `IntegrityCheck` and keeps the latest report, so silent corruption shows
+//! up in `GET /api/v1/admin/integrity` and the logs instead of in a support
+//! ticket. Checks cover the stores that exist today: users missing their
+//! creation audit entry, and cached users that drifted from storage.
+
+use async_trait::async_trait;
+use axum::{extract::State, response::Json};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+use crate::audit::{AuditAction, AuditQuery};
+use crate::auth::Admin;
+use crate::error::AppError;
+use crate::repository::{self, UserQuery};
+use crate::substate::Shared;
+use crate::{ApiResponse, AppState};
+
+/// Largest number of example problems kept per check
+const MAX_EXAMPLES: usize = 20;
+
+/// Checker schedule and sampling
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IntegrityConfig {
+    /// Seconds between runs; zero disables the schedule
+    pub interval_secs: u64,
+    /// Most rows a sampling check compares per run
+    pub sample_size: usize,
+}
+
+impl Default for IntegrityConfig {
+    fn default() -> Self {
+        IntegrityConfig {
+            interval_secs: 3600,
+            sample_size: 100,
+        }
+    }
+}
+
+/// What one check found
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Finding {
+    /// Rows examined
+    pub checked: usize,
+    /// Problems found
+    pub problems: usize,
+    /// Up to `MAX_EXAMPLES` descriptions of problems
+    pub examples: Vec<String>,
+}
+
+impl Finding {
+    fn problem(&mut self, description: String) {
+        self.problems += 1;
+        if self.examples.len() < MAX_EXAMPLES {
+            self.examples.push(description);
+        }
+    }
+}
+
+/// A consistency rule over stored data
+#[async_trait]
+pub trait IntegrityCheck: Send + Sync {
+    /// Name shown in reports
+    fn name(&self) -> &'static str;
+
+    /// Examine the data; errors mean the check could not run
+    async fn run(&self, state: &AppState, sample_size: usize) -> Result<Finding, String>;
+}
+
+/// Users with no `create` audit entry
+pub struct AuditGaps;
+
+#[async_trait]
+impl IntegrityCheck for AuditGaps {
+    fn name(&self) -> &'static str {
+        "audit_gaps"
+    }
+
+    async fn run(&self, state: &AppState, _sample_size: usize) -> Result<Finding, String> {
+        let query = UserQuery {
+            include_deleted: true,
+            ..UserQuery::default()
+        };
+        let users = repository::list_every_tenant(&*state.users, &query)
+            .await
+            .map_err(|err| err.to_string())?;
+        let entries = state
+            .audit
+            .query(&AuditQuery {
+                entity: "user".to_string(),
+                id: None,
+            })
+            .await
+            .map_err(|err| err.to_string())?;
+        let created: HashSet<_> = entries
+            .iter()
+            .filter(|entry| entry.action == AuditAction::Create)
+            .map(|entry| entry.entity_id)
+            .collect();
+
+        let mut finding = Finding {
+            checked: users.len(),
+            ..Finding::default()
+        };
+        for user in users.iter().filter(|user| !created.contains(&user.id)) {
+            finding.problem(format!("user {} has no create audit entry", user.id));
+        }
+        Ok(finding)
+    }
+}
+
+/// Cached users that differ from storage, on a sample
+pub struct CacheDrift;
+
+#[async_trait]
+impl IntegrityCheck for CacheDrift {
+    fn name(&self) -> &'static str {
+        "cache_drift"
+    }
+
+    async fn run(&self, state: &AppState, sample_size: usize) -> Result<Finding, String> {
+        // Listing bypasses the cache, so it reflects storage
+        let query = UserQuery {
+            include_deleted: true,
+            ..UserQuery::default()
+        };
+        let users = repository::list_every_tenant(&*state.users, &query)
+            .await
+            .map_err(|err| err.to_string())?;
+        let mut finding = Finding::default();
+        for stored in users.iter().take(sample_size) {
+            let Some(cached) = state.user_cache.get(stored.id) else {
+                continue;
+            };
+            finding.checked += 1;
+            let same = serde_json::to_value(&cached).ok() == serde_json::to_value(stored).ok();
+            if !same {
+                finding.problem(format!("cached user {} differs from storage", stored.id));
+            }
+        }
+        Ok(finding)
+    }
+}
+
+/// Every check, in report order
+fn checks() -> Vec<Box<dyn IntegrityCheck>> {
+    vec![Box::new(AuditGaps), Box::new(CacheDrift)]
+}
+
+/// Result of one check within a run
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    /// Check name
+    pub name: &'static str,
+    /// What it found, absent if it failed to run
+    pub finding: Option<Finding>,
+    /// Why it failed to run
+    pub error: Option<String>,
+}
+
+/// Result of a full run
+#[derive(Debug, Clone, Serialize)]
+pub struct IntegrityReport {
+    /// When the run started
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    /// Run duration
+    pub elapsed_ms: u64,
+    /// Problems found across all checks
+    pub problems: usize,
+    /// Per-check results
+    pub checks: Vec<CheckResult>,
+}
+
+/// Latest report and running totals
+#[derive(Debug, Default)]
+pub struct IntegrityStatus {
+    latest: RwLock<Option<IntegrityReport>>,
+    runs: AtomicU64,
+    problems: AtomicU64,
+}
+
+/// Status body, with totals since startup
+#[derive(Debug, Serialize)]
+pub struct IntegritySummary {
+    /// Completed runs
+    pub runs_total: u64,
+    /// Problems found across all runs
+    pub problems_total: u64,
+    /// Most recent report
+    pub latest: Option<IntegrityReport>,
+}
+
+impl IntegrityStatus {
+    /// Current totals and latest report
+    pub fn summary(&self) -> IntegritySummary {
+        IntegritySummary {
+            runs_total: self.runs.load(Ordering::Relaxed),
+            problems_total: self.problems.load(Ordering::Relaxed),
+            latest: self.latest.read().expect("integrity lock poisoned").clone(),
+        }
+    }
+}
+
+/// Run every check once and store the report
+pub async fn run_checks(state: &AppState) -> IntegrityReport {
+    let started_at = chrono::Utc::now();
+    let started = Instant::now();
+    let sample_size = state.config().integrity.sample_size;
+    let mut results = Vec::new();
+    for check in checks() {
+        let result = match check.run(state, sample_size).await {
+            Ok(finding) => {
+                if finding.problems > 0 {
+                    let name = check.name();
+                    tracing::warn!("integrity check {}: {} problems", name, finding.problems);
+                }
+                CheckResult {
+                    name: check.name(),
+                    finding: Some(finding),
+                    error: None,
+                }
+            }
+            Err(err) => {
+                tracing::error!("integrity check {} failed: {}", check.name(), err);
+                CheckResult {
+                    name: check.name(),
+                    finding: None,
+                    error: Some(err),
+                }
+            }
+        };
+        results.push(result);
+    }
+
+    let problems = results
+        .iter()
+        .filter_map(|result| result.finding.as_ref())
+        .map(|finding| finding.problems)
+        .sum();
+    let report = IntegrityReport {
+        started_at,
+        elapsed_ms: started.elapsed().as_millis() as u64,
+        problems,
+        checks: results,
+    };
+    let status = &state.integrity;
+    status.runs.fetch_add(1, Ordering::Relaxed);
+    status.problems.fetch_add(problems as u64, Ordering::Relaxed);
+    *status.latest.write().expect("integrity lock poisoned") = Some(report.clone());
+    report
+}
+
+/// Run the checks on the configured schedule
+pub fn spawn_checker(state: Arc<AppState>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let interval_secs = state.config().integrity.interval_secs;
+        if interval_secs == 0 {
+            return;
+        }
+        let mut ticks = tokio::time::interval(Duration::from_secs(interval_secs));
+        // Skip the immediate first tick; startup is busy enough
+        ticks.tick().await;
+        loop {
+            ticks.tick().await;
+            run_checks(&state).await;
+        }
+    })
+}
+
+/// Latest report and totals
+pub async fn integrity_status(
+    State(integrity): State<Shared<IntegrityStatus>>,
+    _admin: Admin,
+) -> Json<ApiResponse<IntegritySummary>> {
+    Json(ApiResponse::success(integrity.summary()))
+}
+
+/// Run the checks now
+pub async fn run_integrity_checks(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+) -> Result<Json<ApiResponse<IntegrityReport>>, AppError> {
+    Ok(Json(ApiResponse::success(run_checks(&state).await)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuditContext;
+    use crate::tenancy::DEFAULT_TENANT;
+    use crate::{service, Config, User};
+
+    #[tokio::test]
+    async fn test_reports_audit_gaps_and_cache_drift() {
+        let state = AppState::new(Config::default());
+        let context = AuditContext {
+            actor: "admin".to_string(),
+            request_id: "test".to_string(),
+            client_ip: None,
+        };
+        let audited =
+            service::create_user(&state, &context, DEFAULT_TENANT, "alice", "alice@example.com")
+                .await
+                .unwrap();
+        let unaudited = User::new("bob".to_string(), "bob@example.com".to_string());
+        state.users.insert(unaudited.clone()).await.unwrap();
+        let mut stale = audited.clone();
+        stale.deactivate();
+        state.user_cache.put(stale);
+
+        let report = run_checks(&state).await;
+        assert_eq!(report.problems, 2);
+        let gaps = report.checks[0].finding.as_ref().unwrap();
+        let expected = format!("user {} has no create audit entry", unaudited.id);
+        assert_eq!(gaps.examples, vec![expected]);
+        assert_eq!(report.checks[1].finding.as_ref().unwrap().problems, 1);
+        assert_eq!(state.integrity.summary().runs_total, 1);
+    }
+}
@@ -0,0 +1,741 @@
+//! Background job queue.
+//!
+//! Work that should happen after a request returns, and survive failures
+//! while it waits, is enqueued as a `Job`: a kind naming what to do and a
+//! JSON payload describing it. `jobs.workers` worker loops, spawned with the
+//! server, claim due jobs and run them by kind. A job that fails is retried
+//! with delays doubling from `retry_base_ms` up to `retry_max_ms`; after
+//! `max_attempts` failures, or at once for a failure retrying can't fix such
+//! as an unknown kind, it is dead-lettered: kept with its last error, never
+//! run again unless an admin retries it.
+//!
+//! With `jobs.backend` set to `database`, jobs live in a `jobs` table of
+//! `Config::database_url`, created if missing. Workers of every instance
+//! share it, claiming with `FOR UPDATE SKIP LOCKED` so each job runs on one
+//! of them at a time, and a claim holds for `lease_secs`: a job whose
+//! worker died mid-run is claimed again once its lease runs out. The
+//! `memory` backend keeps jobs on this instance only, lost on restart.
+//!
+//! `GET /api/v1/admin/jobs` lists jobs by status and kind with counts per
+//! status; `GET /api/v1/admin/jobs/:id` shows one, and
+//! `POST /api/v1/admin/jobs/:id/retry` queues a dead job again.
+
+use async_trait::async_trait;
+use axum::{
+    extract::{Query, State},
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use sqlx::types::Json as SqlJson;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::auth::Admin;
+use crate::error::{AppError, ErrorCode};
+use crate::exports;
+use crate::health::{HealthCheck, HealthState};
+use crate::mail::Email;
+use crate::repository::RepositoryError;
+use crate::validation::ApiPath;
+use crate::webhooks::RetryPolicy;
+use crate::{ApiResponse, AppState};
+
+/// Kind of jobs sending one `mail::Email`
+pub const EMAIL: &str = "email";
+
+/// Kind of jobs assembling an `exports::UserArchive`
+pub const USER_EXPORT: &str = "user_export";
+
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS jobs (\
+    id uuid PRIMARY KEY, kind text NOT NULL, payload jsonb NOT NULL, status text NOT NULL, \
+    attempts int4 NOT NULL, max_attempts int4 NOT NULL, run_at timestamptz NOT NULL, \
+    last_error text, created_at timestamptz NOT NULL, updated_at timestamptz NOT NULL)";
+
+const CREATE_INDEX: &str = "CREATE INDEX IF NOT EXISTS jobs_due ON jobs (run_at) \
+    WHERE status IN ('queued', 'running')";
+
+const JOB_COLUMNS: &str =
+    "id, kind, payload, status, attempts, max_attempts, run_at, last_error, created_at, updated_at";
+
+/// Where jobs are kept
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobBackend {
+    /// In memory on this instance
+    #[default]
+    Memory,
+    /// The `jobs` table of `Config::database_url`, shared by every instance
+    Database,
+}
+
+/// Job queue settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JobConfig {
+    /// Where jobs are kept
+    pub backend: JobBackend,
+    /// Worker loops on this instance
+    pub workers: usize,
+    /// How often idle workers look for due jobs
+    pub poll_interval_ms: u64,
+    /// Attempts per job, including the first
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for each one after
+    pub retry_base_ms: u64,
+    /// Longest delay between retries
+    pub retry_max_ms: u64,
+    /// How long a claim holds before another worker may take the job
+    pub lease_secs: u64,
+}
+
+impl Default for JobConfig {
+    fn default() -> Self {
+        JobConfig {
+            backend: JobBackend::Memory,
+            workers: 2,
+            poll_interval_ms: 1_000,
+            max_attempts: 5,
+            retry_base_ms: 1_000,
+            retry_max_ms: 600_000,
+            lease_secs: 300,
+        }
+    }
+}
+
+impl JobConfig {
+    /// Retry schedule of failed jobs
+    pub fn retry(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts.max(1),
+            base_delay: Duration::from_millis(self.retry_base_ms),
+            max_delay: Duration::from_millis(self.retry_max_ms),
+        }
+    }
+}
+
+/// Where a job is in its life
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    /// Waiting for `run_at`
+    Queued,
+    /// Claimed by a worker until `run_at`
+    Running,
+    /// Ran to completion
+    Succeeded,
+    /// Failed for good; kept for inspection
+    Dead,
+}
+
+impl JobStatus {
+    /// Name stored in the `status` column
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Succeeded => "succeeded",
+            JobStatus::Dead => "dead",
+        }
+    }
+
+    fn parse(status: &str) -> Self {
+        match status {
+            "queued" => JobStatus::Queued,
+            "running" => JobStatus::Running,
+            "succeeded" => JobStatus::Succeeded,
+            _ => JobStatus::Dead,
+        }
+    }
+}
+
+/// A unit of background work
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Job {
+    /// Unique identifier
+    pub id: Uuid,
+    /// What to do, e.g. `email`
+    pub kind: String,
+    /// What to do it with
+    pub payload: Value,
+    /// Where it is in its life
+    pub status: JobStatus,
+    /// Runs started so far
+    pub attempts: u32,
+    /// Runs allowed before it is dead-lettered
+    pub max_attempts: u32,
+    /// When it is due if queued, or when its claim runs out if running
+    pub run_at: DateTime<Utc>,
+    /// Why the last run failed
+    pub last_error: Option<String>,
+    /// When it was enqueued
+    pub created_at: DateTime<Utc>,
+    /// When it last changed
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Job {
+    /// A job of `kind`, due now
+    pub fn new(config: &JobConfig, kind: &str, payload: Value) -> Self {
+        let now = Utc::now();
+        Job {
+            id: Uuid::new_v4(),
+            kind: kind.to_string(),
+            payload,
+            status: JobStatus::Queued,
+            attempts: 0,
+            max_attempts: config.retry().max_attempts,
+            run_at: now,
+            last_error: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+/// Which jobs to list
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct JobQuery {
+    /// Only jobs in this status
+    pub status: Option<JobStatus>,
+    /// Only jobs of this kind
+    pub kind: Option<String>,
+    /// Most jobs returned, newest first
+    pub limit: Option<usize>,
+}
+
+impl JobQuery {
+    fn limit(&self) -> usize {
+        self.limit.unwrap_or(100).min(1_000)
+    }
+
+    fn matches(&self, job: &Job) -> bool {
+        self.status.map_or(true, |status| job.status == status)
+            && self.kind.as_ref().map_or(true, |kind| &job.kind == kind)
+    }
+}
+
+/// Storage of jobs that workers claim from
+#[async_trait]
+pub trait JobStore: Send + Sync {
+    /// Create whatever the store needs, once at startup
+    async fn prepare(&self) -> Result<(), RepositoryError> {
+        Ok(())
+    }
+
+    /// Add a new job
+    async fn insert(&self, job: &Job) -> Result<(), RepositoryError>;
+
+    /// Claim the job due longest, counting an attempt and holding it until
+    /// `lease_until`
+    async fn claim(
+        &self,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+    ) -> Result<Option<Job>, RepositoryError>;
+
+    /// Write back a job's status, due time, attempts and error
+    async fn save(&self, job: &Job) -> Result<(), RepositoryError>;
+
+    /// A job by ID
+    async fn get(&self, id: Uuid) -> Result<Option<Job>, RepositoryError>;
+
+    /// Jobs matching `query`, newest first
+    async fn list(&self, query: &JobQuery) -> Result<Vec<Job>, RepositoryError>;
+
+    /// Jobs per status
+    async fn counts(&self) -> Result<BTreeMap<JobStatus, u64>, RepositoryError>;
+}
+
+/// Jobs kept in memory on this instance
+#[derive(Debug, Default)]
+pub struct InMemoryJobStore {
+    jobs: Mutex<HashMap<Uuid, Job>>,
+}
+
+#[async_trait]
+impl JobStore for InMemoryJobStore {
+    async fn insert(&self, job: &Job) -> Result<(), RepositoryError> {
+        self.jobs.lock().expect("jobs lock poisoned").insert(job.id, job.clone());
+        Ok(())
+    }
+
+    async fn claim(
+        &self,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+    ) -> Result<Option<Job>, RepositoryError> {
+        let mut jobs = self.jobs.lock().expect("jobs lock poisoned");
+        let due = jobs
+            .values_mut()
+            .filter(|job| matches!(job.status, JobStatus::Queued | JobStatus::Running))
+            .filter(|job| job.run_at <= now)
+            .min_by_key(|job| job.run_at);
+        Ok(due.map(|job| {
+            job.status = JobStatus::Running;
+            job.attempts += 1;
+            job.run_at = lease_until;
+            job.updated_at = now;
+            job.clone()
+        }))
+    }
+
+    async fn save(&self, job: &Job) -> Result<(), RepositoryError> {
+        self.insert(job).await
+    }
+
+    async fn get(&self, id: Uuid) -> Result<Option<Job>, RepositoryError> {
+        Ok(self.jobs.lock().expect("jobs lock poisoned").get(&id).cloned())
+    }
+
+    async fn list(&self, query: &JobQuery) -> Result<Vec<Job>, RepositoryError> {
+        let jobs = self.jobs.lock().expect("jobs lock poisoned");
+        let mut listed: Vec<Job> =
+            jobs.values().filter(|job| query.matches(job)).cloned().collect();
+        listed.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        listed.truncate(query.limit());
+        Ok(listed)
+    }
+
+    async fn counts(&self) -> Result<BTreeMap<JobStatus, u64>, RepositoryError> {
+        let mut counts = BTreeMap::new();
+        for job in self.jobs.lock().expect("jobs lock poisoned").values() {
+            *counts.entry(job.status).or_insert(0) += 1;
+        }
+        Ok(counts)
+    }
+}
+
+/// Row shape of the `jobs` table
+#[derive(sqlx::FromRow)]
+struct JobRow {
+    id: Uuid,
+    kind: String,
+    payload: SqlJson<Value>,
+    status: String,
+    attempts: i32,
+    max_attempts: i32,
+    run_at: DateTime<Utc>,
+    last_error: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl From<JobRow> for Job {
+    fn from(row: JobRow) -> Self {
+        Job {
+            id: row.id,
+            kind: row.kind,
+            payload: row.payload.0,
+            status: JobStatus::parse(&row.status),
+            attempts: row.attempts.max(0) as u32,
+            max_attempts: row.max_attempts.max(0) as u32,
+            run_at: row.run_at,
+            last_error: row.last_error,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        }
+    }
+}
+
+/// Jobs kept in the `jobs` table
+pub struct PgJobStore {
+    pool: PgPool,
+}
+
+impl PgJobStore {
+    /// Store over `url`, connecting on first use
+    pub fn connect_lazy(url: &str) -> Result<Self, sqlx::Error> {
+        let pool = PgPoolOptions::new().max_connections(4).connect_lazy(url)?;
+        Ok(PgJobStore { pool })
+    }
+}
+
+#[async_trait]
+impl JobStore for PgJobStore {
+    async fn prepare(&self) -> Result<(), RepositoryError> {
+        sqlx::query(CREATE_TABLE).execute(&self.pool).await?;
+        sqlx::query(CREATE_INDEX).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn insert(&self, job: &Job) -> Result<(), RepositoryError> {
+        let query = format!(
+            "INSERT INTO jobs ({}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+            JOB_COLUMNS
+        );
+        sqlx::query(&query)
+            .bind(job.id)
+            .bind(&job.kind)
+            .bind(SqlJson(&job.payload))
+            .bind(job.status.as_str())
+            .bind(job.attempts as i32)
+            .bind(job.max_attempts as i32)
+            .bind(job.run_at)
+            .bind(&job.last_error)
+            .bind(job.created_at)
+            .bind(job.updated_at)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn claim(
+        &self,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+    ) -> Result<Option<Job>, RepositoryError> {
+        let query = format!(
+            "UPDATE jobs SET status = 'running', attempts = attempts + 1, run_at = $2, \
+             updated_at = $1 WHERE id = (SELECT id FROM jobs \
+             WHERE status IN ('queued', 'running') AND run_at <= $1 \
+             ORDER BY run_at LIMIT 1 FOR UPDATE SKIP LOCKED) RETURNING {}",
+            JOB_COLUMNS
+        );
+        let row = sqlx::query_as::<_, JobRow>(&query)
+            .bind(now)
+            .bind(lease_until)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(Job::from))
+    }
+
+    async fn save(&self, job: &Job) -> Result<(), RepositoryError> {
+        sqlx::query(
+            "UPDATE jobs SET status = $2, attempts = $3, run_at = $4, last_error = $5, \
+             updated_at = $6 WHERE id = $1",
+        )
+        .bind(job.id)
+        .bind(job.status.as_str())
+        .bind(job.attempts as i32)
+        .bind(job.run_at)
+        .bind(&job.last_error)
+        .bind(job.updated_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get(&self, id: Uuid) -> Result<Option<Job>, RepositoryError> {
+        let query = format!("SELECT {} FROM jobs WHERE id = $1", JOB_COLUMNS);
+        let row = sqlx::query_as::<_, JobRow>(&query)
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(Job::from))
+    }
+
+    async fn list(&self, query: &JobQuery) -> Result<Vec<Job>, RepositoryError> {
+        let sql = format!(
+            "SELECT {} FROM jobs WHERE ($1::text IS NULL OR status = $1) \
+             AND ($2::text IS NULL OR kind = $2) ORDER BY created_at DESC LIMIT $3",
+            JOB_COLUMNS
+        );
+        let rows = sqlx::query_as::<_, JobRow>(&sql)
+            .bind(query.status.map(JobStatus::as_str))
+            .bind(&query.kind)
+            .bind(query.limit() as i64)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(Job::from).collect())
+    }
+
+    async fn counts(&self) -> Result<BTreeMap<JobStatus, u64>, RepositoryError> {
+        let rows: Vec<(String, i64)> =
+            sqlx::query_as("SELECT status, count(*) FROM jobs GROUP BY status")
+                .fetch_all(&self.pool)
+                .await?;
+        let counts = rows
+            .into_iter()
+            .map(|(status, count)| (JobStatus::parse(&status), count.max(0) as u64));
+        Ok(counts.collect())
+    }
+}
+
+/// Why a run failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Failure {
+    /// Worth another attempt, such as a relay that didn't answer
+    Transient(String),
+    /// Retrying can't help, such as a payload that doesn't parse
+    Permanent(String),
+}
+
+/// Run a claimed job by its kind
+async fn run(state: &AppState, job: &Job) -> Result<(), Failure> {
+    match job.kind.as_str() {
+        EMAIL => {
+            let email: Email = serde_json::from_value(job.payload.clone())
+                .map_err(|err| Failure::Permanent(format!("invalid email payload: {}", err)))?;
+            state.mailer.deliver(&email).await.map_err(Failure::Transient)
+        }
+        USER_EXPORT => exports::run(state, job).await,
+        other => Err(Failure::Permanent(format!("no handler for job kind {:?}", other))),
+    }
+}
+
+/// The job queue and its settings
+pub struct JobQueue {
+    store: Arc<dyn JobStore>,
+    durable: bool,
+    ready: Notify,
+}
+
+impl JobQueue {
+    /// Queue over `store`; `durable` when it outlives this instance
+    pub fn new(store: Arc<dyn JobStore>, durable: bool) -> Self {
+        JobQueue {
+            store,
+            durable,
+            ready: Notify::new(),
+        }
+    }
+
+    /// Queue in the backend `config` selects
+    pub fn from_config(config: &JobConfig, database_url: &str) -> Self {
+        if config.backend == JobBackend::Memory {
+            return Self::new(Arc::new(InMemoryJobStore::default()), false);
+        }
+        if database_url.starts_with("memory:") || database_url.starts_with("sqlite:") {
+            tracing::warn!("storage is not Postgres: keeping jobs in memory");
+            return Self::new(Arc::new(InMemoryJobStore::default()), false);
+        }
+        match PgJobStore::connect_lazy(database_url) {
+            Ok(store) => Self::new(Arc::new(store), true),
+            Err(err) => {
+                tracing::error!("job store unusable, keeping jobs in memory: {}", err);
+                Self::new(Arc::new(InMemoryJobStore::default()), false)
+            }
+        }
+    }
+
+    /// Whether queued jobs survive a restart
+    pub fn is_durable(&self) -> bool {
+        self.durable
+    }
+
+    /// Queue a job of `kind`, due now
+    pub async fn enqueue(
+        &self,
+        config: &JobConfig,
+        kind: &str,
+        payload: Value,
+    ) -> Result<Job, RepositoryError> {
+        let job = Job::new(config, kind, payload);
+        self.store.insert(&job).await?;
+        self.ready.notify_one();
+        Ok(job)
+    }
+
+    /// Claim and run one due job, returning whether there was one
+    pub async fn work_one(&self, state: &AppState) -> Result<bool, RepositoryError> {
+        let config = state.config().jobs.clone();
+        let now = Utc::now();
+        let lease = chrono::Duration::seconds(config.lease_secs as i64);
+        let Some(mut job) = self.store.claim(now, now + lease).await? else {
+            return Ok(false);
+        };
+        let result = run(state, &job).await;
+        let now = Utc::now();
+        job.updated_at = now;
+        match result {
+            Ok(()) => {
+                job.status = JobStatus::Succeeded;
+                job.last_error = None;
+            }
+            Err(Failure::Transient(err)) if job.attempts < job.max_attempts => {
+                let delay = config.retry().backoff(job.attempts);
+                tracing::warn!(job = %job.id, kind = %job.kind, "job failed, retrying: {}", err);
+                job.status = JobStatus::Queued;
+                job.run_at = now + chrono::Duration::from_std(delay).unwrap_or_default();
+                job.last_error = Some(err);
+            }
+            Err(Failure::Transient(err) | Failure::Permanent(err)) => {
+                tracing::error!(job = %job.id, kind = %job.kind, "job dead-lettered: {}", err);
+                job.status = JobStatus::Dead;
+                job.last_error = Some(err);
+            }
+        }
+        self.store.save(&job).await?;
+        Ok(true)
+    }
+
+    /// Jobs matching `query`, newest first
+    pub async fn list(&self, query: &JobQuery) -> Result<Vec<Job>, RepositoryError> {
+        self.store.list(query).await
+    }
+
+    /// A job by ID
+    pub async fn get(&self, id: Uuid) -> Result<Option<Job>, RepositoryError> {
+        self.store.get(id).await
+    }
+
+    /// Queue a dead job again with fresh attempts
+    pub async fn retry(&self, id: Uuid) -> Result<Option<Job>, AppError> {
+        let Some(mut job) = self.store.get(id).await? else {
+            return Ok(None);
+        };
+        if job.status != JobStatus::Dead {
+            let message = format!("job {} is {}, not dead", id, job.status.as_str());
+            return Err(AppError::Conflict(message));
+        }
+        let now = Utc::now();
+        job.status = JobStatus::Queued;
+        job.attempts = 0;
+        job.run_at = now;
+        job.updated_at = now;
+        self.store.save(&job).await?;
+        self.ready.notify_one();
+        Ok(Some(job))
+    }
+
+    /// A health check of the queue's store
+    pub fn health_check(&self) -> JobQueueCheck {
+        JobQueueCheck {
+            store: self.store.clone(),
+        }
+    }
+}
+
+/// Health check counting jobs in the store, as workers and admins read it
+pub struct JobQueueCheck {
+    store: Arc<dyn JobStore>,
+}
+
+#[async_trait]
+impl HealthCheck for JobQueueCheck {
+    fn name(&self) -> &'static str {
+        "jobs"
+    }
+
+    async fn check(&self) -> Result<HealthState, String> {
+        self.store.counts().await.map_err(|err| err.to_string())?;
+        Ok(HealthState::Operational)
+    }
+}
+
+/// One worker: run due jobs, then wait for new ones or the next poll
+async fn work(state: Arc<AppState>) {
+    let interval = Duration::from_millis(state.config().jobs.poll_interval_ms);
+    loop {
+        match state.jobs.work_one(&state).await {
+            Ok(true) => continue,
+            Ok(false) => {}
+            Err(err) => tracing::warn!("failed to claim a job: {}", err),
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = state.jobs.ready.notified() => {}
+        }
+    }
+}
+
+/// Spawn the worker loops, after creating what the store needs
+pub fn spawn_workers(state: Arc<AppState>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        if let Err(err) = state.jobs.store.prepare().await {
+            tracing::error!("job store unavailable, no jobs will run: {}", err);
+            return;
+        }
+        let workers = state.config().jobs.workers.max(1);
+        futures::future::join_all((0..workers).map(|_| work(state.clone()))).await;
+    })
+}
+
+/// Listed jobs with counts per status
+#[derive(Debug, Serialize)]
+pub struct JobListing {
+    /// Jobs per status, over all jobs
+    pub counts: BTreeMap<JobStatus, u64>,
+    /// Jobs matching the query, newest first
+    pub jobs: Vec<Job>,
+}
+
+fn not_found(id: Uuid) -> AppError {
+    AppError::NotFound(format!("job {} not found", id)).with_code(ErrorCode::JOB_NOT_FOUND)
+}
+
+/// Jobs by status and kind
+pub async fn list_jobs(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    Query(query): Query<JobQuery>,
+) -> Result<Json<ApiResponse<JobListing>>, AppError> {
+    let counts = state.jobs.store.counts().await?;
+    let jobs = state.jobs.store.list(&query).await?;
+    Ok(Json(ApiResponse::success(JobListing { counts, jobs })))
+}
+
+/// One job
+pub async fn get_job(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    ApiPath(id): ApiPath<Uuid>,
+) -> Result<Json<ApiResponse<Job>>, AppError> {
+    let job = state.jobs.store.get(id).await?.ok_or_else(|| not_found(id))?;
+    Ok(Json(ApiResponse::success(job)))
+}
+
+/// Queue a dead-lettered job again
+pub async fn retry_job(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    ApiPath(id): ApiPath<Uuid>,
+) -> Result<Json<ApiResponse<Job>>, AppError> {
+    let job = state.jobs.retry(id).await?.ok_or_else(|| not_found(id))?;
+    Ok(Json(ApiResponse::success(job)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+
+    #[tokio::test]
+    async fn test_failing_job_is_retried_then_dead_lettered() {
+        let mut config = Config::default();
+        config.jobs.max_attempts = 2;
+        config.jobs.retry_base_ms = 0;
+        let state = AppState::new(config);
+        let jobs_config = state.config().jobs.clone();
+        let job = state.jobs.enqueue(&jobs_config, "unknown", Value::Null).await.unwrap();
+        assert!(state.jobs.work_one(&state).await.unwrap());
+        let dead = state.jobs.store.get(job.id).await.unwrap().unwrap();
+        assert_eq!((dead.status, dead.attempts), (JobStatus::Dead, 1));
+        assert!(!state.jobs.work_one(&state).await.unwrap());
+
+        let email = serde_json::json!({"to": "ann@acme.test", "subject": "Hi", "body": "Hello"});
+        let job = state.jobs.enqueue(&jobs_config, EMAIL, email).await.unwrap();
+        assert!(state.jobs.work_one(&state).await.unwrap());
+        let sent = state.jobs.store.get(job.id).await.unwrap().unwrap();
+        assert_eq!(sent.status, JobStatus::Succeeded);
+
+        let retried = state.jobs.retry(dead.id).await.unwrap().unwrap();
+        assert_eq!((retried.status, retried.attempts), (JobStatus::Queued, 0));
+        assert!(state.jobs.retry(sent.id).await.is_err());
+        let counts = state.jobs.store.counts().await.unwrap();
+        assert_eq!(counts.get(&JobStatus::Queued), Some(&1));
+        assert_eq!(counts.get(&JobStatus::Succeeded), Some(&1));
+    }
+
+    #[tokio::test]
+    async fn test_claim_takes_due_jobs_and_expired_leases() {
+        let store = InMemoryJobStore::default();
+        let job = Job::new(&JobConfig::default(), EMAIL, Value::Null);
+        store.insert(&job).await.unwrap();
+        let now = job.run_at;
+        let lease = now + chrono::Duration::seconds(60);
+        let claimed = store.claim(now, lease).await.unwrap().unwrap();
+        assert_eq!((claimed.status, claimed.attempts), (JobStatus::Running, 1));
+        assert_eq!(claimed.run_at, lease);
+        assert!(store.claim(now, lease).await.unwrap().is_none());
+        let later = lease + chrono::Duration::seconds(1);
+        assert_eq!(store.claim(later, later).await.unwrap().unwrap().attempts, 2);
+    }
+}
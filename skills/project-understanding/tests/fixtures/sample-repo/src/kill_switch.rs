@@ -0,0 +1,262 @@
+//! Per-endpoint kill switches for shedding expensive traffic in incidents.
+//!
+//! Admins can switch off one route, such as an export or a search, for a
+//! limited time without a deploy: `POST /api/v1/admin/kill-switches` names a
+//! route pattern from `handlers::ROUTES`, optionally one method of it, a
+//! reason, and a lifetime. Matching requests, including through the legacy
+//! `/api/*` alias, get a 503 `ENDPOINT_DISABLED` until the switch is removed
+//! or expires. Switches live in memory on each instance, the way debug
+//! targets do, and adding or removing one is audited. The kill switch routes
+//! themselves can't be switched off.
+
+use axum::{
+    extract::{MatchedPath, State},
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+use uuid::Uuid;
+
+use crate::audit::{self, AuditAction, AuditContext};
+use crate::auth::Admin;
+use crate::error::{AppError, ErrorCode};
+use crate::handlers::ROUTES;
+use crate::validation::ApiPath;
+use crate::{versioning, ApiResponse, AppState};
+
+/// Longest a switch may stay on; incidents outlasting it renew the switch
+const MAX_TTL_SECONDS: i64 = 86_400;
+
+/// Routes managing switches, which stay on so switches can be removed
+const KILL_SWITCH_ROUTES: [&str; 2] =
+    ["/api/v1/admin/kill-switches", "/api/v1/admin/kill-switches/:id"];
+
+/// A route switched off
+#[derive(Debug, Clone, Serialize)]
+pub struct KillSwitch {
+    /// Unique identifier
+    pub id: Uuid,
+    /// Route pattern as listed in `handlers::ROUTES`
+    pub route: String,
+    /// Method switched off, or every method of the route
+    pub method: Option<String>,
+    /// Why, for other operators
+    pub reason: String,
+    /// When the switch turns itself off
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Request body for switching a route off
+#[derive(Debug, Deserialize)]
+pub struct NewKillSwitch {
+    /// Route pattern, e.g. `/api/v1/users/import`
+    pub route: String,
+    /// Only this method, e.g. `POST`
+    #[serde(default)]
+    pub method: Option<String>,
+    /// Why, for other operators
+    pub reason: String,
+    /// Lifetime in seconds, capped at a day
+    pub ttl_seconds: i64,
+}
+
+/// Active kill switches
+#[derive(Default)]
+pub struct KillSwitches {
+    switches: RwLock<Vec<KillSwitch>>,
+}
+
+impl KillSwitches {
+    /// Switch a route off, clamping the lifetime; fails for unknown routes
+    pub fn add(&self, new: NewKillSwitch) -> Result<KillSwitch, AppError> {
+        let method = new.method.map(|method| method.to_ascii_uppercase());
+        let known = ROUTES
+            .iter()
+            .any(|route| route.path == new.route && covers(method.as_deref(), route.method));
+        if !known {
+            let message = format!("no route {} {}", method.as_deref().unwrap_or("*"), new.route);
+            return Err(AppError::Validation(vec![message]));
+        }
+        if KILL_SWITCH_ROUTES.contains(&new.route.as_str()) {
+            let message = "kill switch routes can't be switched off".to_string();
+            return Err(AppError::Validation(vec![message]));
+        }
+        let ttl = new.ttl_seconds.clamp(1, MAX_TTL_SECONDS);
+        let switch = KillSwitch {
+            id: Uuid::new_v4(),
+            route: new.route,
+            method,
+            reason: new.reason,
+            expires_at: chrono::Utc::now() + chrono::Duration::seconds(ttl),
+        };
+        let mut switches = self.switches.write().expect("kill switch lock poisoned");
+        switches.retain(|switch| switch.expires_at > chrono::Utc::now());
+        switches.push(switch.clone());
+        Ok(switch)
+    }
+
+    /// Turn a switch off early, returning it if it was on
+    pub fn remove(&self, id: Uuid) -> Option<KillSwitch> {
+        let mut switches = self.switches.write().expect("kill switch lock poisoned");
+        let index = switches.iter().position(|switch| switch.id == id)?;
+        Some(switches.remove(index))
+    }
+
+    /// Switches that have not expired
+    pub fn active(&self) -> Vec<KillSwitch> {
+        let now = chrono::Utc::now();
+        let switches = self.switches.read().expect("kill switch lock poisoned");
+        switches
+            .iter()
+            .filter(|switch| switch.expires_at > now)
+            .cloned()
+            .collect()
+    }
+
+    /// The live switch turning off `method` on the matched `route`, if any
+    pub fn matching(&self, method: &str, route: &str) -> Option<KillSwitch> {
+        let route = versioning::v1_route(route);
+        let now = chrono::Utc::now();
+        let switches = self.switches.read().expect("kill switch lock poisoned");
+        switches
+            .iter()
+            .filter(|switch| switch.expires_at > now)
+            .find(|switch| switch.route == route && covers(switch.method.as_deref(), method))
+            .cloned()
+    }
+}
+
+/// Whether a switch for `switched`, or every method when unset, covers `method`
+fn covers(switched: Option<&str>, method: &str) -> bool {
+    match switched {
+        Some(switched) => switched == method,
+        None => true,
+    }
+}
+
+/// Middleware refusing requests to switched-off routes
+pub async fn enforce_kill_switches<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(route) = req.extensions().get::<MatchedPath>() else {
+        return next.run(req).await;
+    };
+    let Some(switch) = state.kill_switches.matching(req.method().as_str(), route.as_str()) else {
+        return next.run(req).await;
+    };
+    tracing::debug!("{} {} refused by kill switch {}", req.method(), switch.route, switch.id);
+    let message = "this endpoint is temporarily disabled".to_string();
+    AppError::Overloaded(message)
+        .with_code(ErrorCode::ENDPOINT_DISABLED)
+        .into_response()
+}
+
+/// Switch a route off
+pub async fn create_kill_switch(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    context: AuditContext,
+    Json(new): Json<NewKillSwitch>,
+) -> Result<(StatusCode, Json<ApiResponse<KillSwitch>>), AppError> {
+    let switch = state.kill_switches.add(new)?;
+    tracing::warn!(
+        "{} {} switched off until {}: {}",
+        switch.method.as_deref().unwrap_or("*"),
+        switch.route,
+        switch.expires_at,
+        switch.reason
+    );
+    audit::record(
+        &state,
+        &context,
+        "kill_switch",
+        switch.id,
+        AuditAction::Create,
+        None,
+        Some(&switch),
+    )
+    .await;
+    Ok((StatusCode::CREATED, Json(ApiResponse::success(switch))))
+}
+
+/// List active kill switches
+pub async fn list_kill_switches(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+) -> Json<ApiResponse<Vec<KillSwitch>>> {
+    Json(ApiResponse::success(state.kill_switches.active()))
+}
+
+/// Turn a switch off before it expires
+pub async fn delete_kill_switch(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    context: AuditContext,
+    ApiPath(id): ApiPath<Uuid>,
+) -> StatusCode {
+    let Some(switch) = state.kill_switches.remove(id) else {
+        return if state.config().idempotent_deletes {
+            StatusCode::NO_CONTENT
+        } else {
+            StatusCode::NOT_FOUND
+        };
+    };
+    tracing::info!("{} switched back on", switch.route);
+    audit::record(
+        &state,
+        &context,
+        "kill_switch",
+        id,
+        AuditAction::Delete,
+        Some(&switch),
+        None,
+    )
+    .await;
+    StatusCode::NO_CONTENT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn switch_off(route: &str, method: Option<&str>) -> Result<KillSwitch, AppError> {
+        KillSwitches::default().add(NewKillSwitch {
+            route: route.to_string(),
+            method: method.map(str::to_string),
+            reason: "incident".to_string(),
+            ttl_seconds: 60,
+        })
+    }
+
+    #[test]
+    fn test_switch_matches_route_method_and_legacy_alias() {
+        let switches = KillSwitches::default();
+        switches
+            .add(NewKillSwitch {
+                route: "/api/v1/users/import".to_string(),
+                method: Some("post".to_string()),
+                reason: "imports overload the database".to_string(),
+                ttl_seconds: 600,
+            })
+            .unwrap();
+
+        assert!(switches.matching("POST", "/api/v1/users/import").is_some());
+        assert!(switches.matching("POST", "/api/users/import").is_some());
+        assert!(switches.matching("GET", "/api/v1/users/import").is_none());
+        assert!(switches.matching("POST", "/api/v1/users").is_none());
+    }
+
+    #[test]
+    fn test_unknown_and_kill_switch_routes_are_refused() {
+        assert!(switch_off("/api/v1/nowhere", None).is_err());
+        assert!(switch_off("/api/v1/users", Some("TRACE")).is_err());
+        assert!(switch_off("/api/v1/admin/kill-switches/:id", None).is_err());
+        let switch = switch_off("/api/v1/users", Some("GET")).unwrap();
+        let max = chrono::Utc::now() + chrono::Duration::seconds(MAX_TTL_SECONDS);
+        assert!(switch.expires_at <= max);
+    }
+}
@@ -0,0 +1,407 @@
+//! Ordered startup and shutdown of background components.
+//!
+//! Components declare the components they depend on. `Lifecycle::start`
+//! starts them in dependency order, each within its timeout; if one fails,
+//! those already started are stopped again. `Lifecycle::stop` stops them in
+//! reverse order and reports every failure rather than stopping at the first.
+
+use async_trait::async_trait;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+use crate::AppState;
+
+/// Time allowed for a start or stop hook unless a component overrides it
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Phase a lifecycle failure happened in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Starting up
+    Start,
+    /// Shutting down
+    Stop,
+}
+
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Phase::Start => write!(f, "start"),
+            Phase::Stop => write!(f, "stop"),
+        }
+    }
+}
+
+/// Errors returned by lifecycle operations
+#[derive(Debug, PartialEq)]
+pub enum LifecycleError {
+    /// A component depends on a name that was never registered
+    UnknownDependency {
+        /// Component declaring the dependency
+        component: &'static str,
+        /// Missing dependency
+        dependency: &'static str,
+    },
+    /// The listed components depend on each other
+    Cycle(Vec<&'static str>),
+    /// A hook returned an error
+    Failed {
+        /// Component whose hook failed
+        component: &'static str,
+        /// Start or stop
+        phase: Phase,
+        /// Reason reported by the hook
+        reason: String,
+    },
+    /// A hook did not finish in time
+    TimedOut {
+        /// Component whose hook hung
+        component: &'static str,
+        /// Start or stop
+        phase: Phase,
+        /// Time the hook was given
+        timeout: Duration,
+    },
+}
+
+impl std::fmt::Display for LifecycleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LifecycleError::UnknownDependency { component, dependency } => {
+                write!(f, "{} depends on unregistered component {}", component, dependency)
+            }
+            LifecycleError::Cycle(components) => {
+                write!(f, "dependency cycle among {}", components.join(", "))
+            }
+            LifecycleError::Failed { component, phase, reason } => {
+                write!(f, "{} failed to {}: {}", component, phase, reason)
+            }
+            LifecycleError::TimedOut { component, phase, timeout } => {
+                write!(f, "{} did not {} within {:?}", component, phase, timeout)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LifecycleError {}
+
+/// A subsystem with start and stop hooks
+#[async_trait]
+pub trait Component: Send + Sync {
+    /// Unique name used in dependencies and reports
+    fn name(&self) -> &'static str;
+
+    /// Components that must be started before this one
+    fn dependencies(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    /// Time allowed for each of `start` and `stop`
+    fn timeout(&self) -> Duration {
+        DEFAULT_TIMEOUT
+    }
+
+    /// Bring the component up
+    async fn start(&self, state: &Arc<AppState>) -> Result<(), String>;
+
+    /// Shut the component down
+    async fn stop(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Component wrapping a spawned background loop, aborted on stop
+pub struct BackgroundTask {
+    name: &'static str,
+    dependencies: Vec<&'static str>,
+    spawn: fn(Arc<AppState>) -> JoinHandle<()>,
+    handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl BackgroundTask {
+    /// Create a component that runs `spawn` on start
+    pub fn new(name: &'static str, spawn: fn(Arc<AppState>) -> JoinHandle<()>) -> Self {
+        Self {
+            name,
+            dependencies: Vec::new(),
+            spawn,
+            handle: Mutex::new(None),
+        }
+    }
+
+    /// Start only after the given components
+    pub fn after(mut self, dependencies: &[&'static str]) -> Self {
+        self.dependencies.extend_from_slice(dependencies);
+        self
+    }
+}
+
+#[async_trait]
+impl Component for BackgroundTask {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn dependencies(&self) -> Vec<&'static str> {
+        self.dependencies.clone()
+    }
+
+    async fn start(&self, state: &Arc<AppState>) -> Result<(), String> {
+        let handle = (self.spawn)(state.clone());
+        *self.handle.lock().expect("task handle lock poisoned") = Some(handle);
+        Ok(())
+    }
+
+    async fn stop(&self) -> Result<(), String> {
+        if let Some(handle) = self.handle.lock().expect("task handle lock poisoned").take() {
+            handle.abort();
+        }
+        Ok(())
+    }
+}
+
+/// Registry of components and the order they were started in
+#[derive(Default)]
+pub struct Lifecycle {
+    components: Vec<Arc<dyn Component>>,
+    started: Vec<Arc<dyn Component>>,
+}
+
+impl Lifecycle {
+    /// Create an empty lifecycle
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a component
+    pub fn register(&mut self, component: impl Component + 'static) -> &mut Self {
+        self.components.push(Arc::new(component));
+        self
+    }
+
+    /// Dependency order, keeping registration order among independent components
+    fn start_order(&self) -> Result<Vec<Arc<dyn Component>>, LifecycleError> {
+        for component in &self.components {
+            for dependency in component.dependencies() {
+                if !self.components.iter().any(|other| other.name() == dependency) {
+                    return Err(LifecycleError::UnknownDependency {
+                        component: component.name(),
+                        dependency,
+                    });
+                }
+            }
+        }
+
+        let mut pending = self.components.clone();
+        let mut ordered: Vec<Arc<dyn Component>> = Vec::with_capacity(pending.len());
+        while !pending.is_empty() {
+            let ready = pending.iter().position(|component| {
+                component
+                    .dependencies()
+                    .iter()
+                    .all(|dependency| ordered.iter().any(|done| done.name() == *dependency))
+            });
+            match ready {
+                Some(index) => ordered.push(pending.remove(index)),
+                None => {
+                    let names = pending.iter().map(|component| component.name()).collect();
+                    return Err(LifecycleError::Cycle(names));
+                }
+            }
+        }
+        Ok(ordered)
+    }
+
+    /// Start every component, rolling back on the first failure
+    pub async fn start(&mut self, state: &Arc<AppState>) -> Result<(), LifecycleError> {
+        for component in self.start_order()? {
+            let name = component.name();
+            let timeout = component.timeout();
+            tracing::info!("starting {}", name);
+            let error = match tokio::time::timeout(timeout, component.start(state)).await {
+                Ok(Ok(())) => {
+                    self.started.push(component);
+                    continue;
+                }
+                Ok(Err(reason)) => LifecycleError::Failed {
+                    component: name,
+                    phase: Phase::Start,
+                    reason,
+                },
+                Err(_) => LifecycleError::TimedOut {
+                    component: name,
+                    phase: Phase::Start,
+                    timeout,
+                },
+            };
+            for failure in self.stop().await {
+                tracing::error!("while rolling back startup: {}", failure);
+            }
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    /// Stop started components in reverse order, returning every failure
+    pub async fn stop(&mut self) -> Vec<LifecycleError> {
+        let mut failures = Vec::new();
+        while let Some(component) = self.started.pop() {
+            let name = component.name();
+            let timeout = component.timeout();
+            tracing::info!("stopping {}", name);
+            match tokio::time::timeout(timeout, component.stop()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(reason)) => failures.push(LifecycleError::Failed {
+                    component: name,
+                    phase: Phase::Stop,
+                    reason,
+                }),
+                Err(_) => failures.push(LifecycleError::TimedOut {
+                    component: name,
+                    phase: Phase::Stop,
+                    timeout,
+                }),
+            }
+        }
+        failures
+    }
+}
+
+/// Wait for SIGINT or SIGTERM
+pub async fn termination() {
+    let interrupt = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install SIGINT handler");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+}
+
+/// Resolve once the process has been asked to shut down and has drained.
+///
+/// Readiness fails from the moment the signal arrives, but requests keep
+/// being accepted for `Config::shutdown_drain_ms` so load balancers can
+/// route around this instance before it stops listening.
+pub async fn shutdown_signal(state: Arc<AppState>) {
+    termination().await;
+    state.draining.store(true, Ordering::SeqCst);
+    let drain = Duration::from_millis(state.config().shutdown_drain_ms);
+    tracing::info!("shutdown requested, draining for {:?}", drain);
+    tokio::time::sleep(drain).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+
+    struct Recorder {
+        name: &'static str,
+        dependencies: Vec<&'static str>,
+        fail_start: bool,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Recorder {
+        fn new(
+            name: &'static str,
+            dependencies: &[&'static str],
+            log: &Arc<Mutex<Vec<String>>>,
+        ) -> Self {
+            Self {
+                name,
+                dependencies: dependencies.to_vec(),
+                fail_start: false,
+                log: log.clone(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Component for Recorder {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn dependencies(&self) -> Vec<&'static str> {
+            self.dependencies.clone()
+        }
+
+        async fn start(&self, _state: &Arc<AppState>) -> Result<(), String> {
+            if self.fail_start {
+                return Err("boom".to_string());
+            }
+            self.log.lock().unwrap().push(format!("start {}", self.name));
+            Ok(())
+        }
+
+        async fn stop(&self) -> Result<(), String> {
+            self.log.lock().unwrap().push(format!("stop {}", self.name));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_starts_in_dependency_order_and_stops_in_reverse() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut lifecycle = Lifecycle::new();
+        lifecycle
+            .register(Recorder::new("scheduler", &["migrations"], &log))
+            .register(Recorder::new("migrations", &[], &log));
+
+        lifecycle.start(&AppState::new(Config::default())).await.unwrap();
+        assert!(lifecycle.stop().await.is_empty());
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["start migrations", "start scheduler", "stop scheduler", "stop migrations"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_start_rolls_back() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut failing = Recorder::new("listener", &["migrations"], &log);
+        failing.fail_start = true;
+        let mut lifecycle = Lifecycle::new();
+        lifecycle
+            .register(Recorder::new("migrations", &[], &log))
+            .register(failing);
+
+        let err = lifecycle.start(&AppState::new(Config::default())).await.unwrap_err();
+        assert_eq!(
+            err,
+            LifecycleError::Failed {
+                component: "listener",
+                phase: Phase::Start,
+                reason: "boom".to_string(),
+            }
+        );
+        assert_eq!(*log.lock().unwrap(), vec!["start migrations", "stop migrations"]);
+    }
+
+    #[test]
+    fn test_cycle_is_reported() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut lifecycle = Lifecycle::new();
+        lifecycle
+            .register(Recorder::new("a", &["b"], &log))
+            .register(Recorder::new("b", &["a"], &log));
+
+        assert!(matches!(lifecycle.start_order(), Err(LifecycleError::Cycle(_))));
+    }
+}
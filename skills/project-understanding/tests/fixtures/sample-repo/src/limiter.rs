@@ -0,0 +1,370 @@
+//! Adaptive concurrency limits for downstream dependencies.
+//!
+//! `AdaptiveLimiter` caps concurrent calls to one dependency and adjusts the
+//! cap by AIMD: each call that finishes within the latency target raises the
+//! limit by `1 / limit` (about one per round of calls), and each slow or
+//! failed call cuts it by `backoff`. Throughput therefore tracks what the
+//! dependency can currently sustain instead of a static pool size. Callers
+//! over the limit wait their turn. Current limits are exported at `/metrics`.
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::events::DomainEvent;
+use crate::repository::{
+    Change, LastSeen, OutboxRecord, OutboxStore, PoolStats, RepositoryError, UserQuery,
+    UserRepository,
+};
+use crate::search::{SearchPage, SearchQuery};
+use crate::User;
+
+/// Limiter tuning
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LimiterConfig {
+    /// Wrap the user store in a limiter
+    pub enabled: bool,
+    /// Limit before any feedback
+    pub initial_limit: f64,
+    /// Floor of the limit
+    pub min_limit: f64,
+    /// Ceiling of the limit
+    pub max_limit: f64,
+    /// Calls slower than this count as overload
+    pub target_latency_ms: u64,
+    /// Factor the limit is multiplied by on overload
+    pub backoff: f64,
+}
+
+impl Default for LimiterConfig {
+    fn default() -> Self {
+        LimiterConfig {
+            enabled: false,
+            initial_limit: 20.0,
+            min_limit: 1.0,
+            max_limit: 200.0,
+            target_latency_ms: 50,
+            backoff: 0.9,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct LimiterState {
+    limit: f64,
+    in_flight: usize,
+}
+
+/// AIMD concurrency limiter for one dependency
+#[derive(Debug)]
+pub struct AdaptiveLimiter {
+    name: &'static str,
+    config: LimiterConfig,
+    state: Mutex<LimiterState>,
+    released: Notify,
+}
+
+/// Current figures of a limiter
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LimiterSnapshot {
+    /// Dependency name
+    pub name: &'static str,
+    /// Current limit
+    pub limit: f64,
+    /// Calls in progress
+    pub in_flight: usize,
+}
+
+impl AdaptiveLimiter {
+    /// Create a limiter for the dependency `name`
+    pub fn new(name: &'static str, config: LimiterConfig) -> Self {
+        let limit = config.initial_limit.clamp(config.min_limit, config.max_limit);
+        Self {
+            name,
+            config,
+            state: Mutex::new(LimiterState {
+                limit,
+                in_flight: 0,
+            }),
+            released: Notify::new(),
+        }
+    }
+
+    /// Current limit and calls in progress
+    pub fn snapshot(&self) -> LimiterSnapshot {
+        let state = self.state.lock().expect("limiter lock poisoned");
+        LimiterSnapshot {
+            name: self.name,
+            limit: state.limit,
+            in_flight: state.in_flight,
+        }
+    }
+
+    async fn acquire(&self) -> Permit<'_> {
+        loop {
+            // Register interest before checking so a release in between isn't missed
+            let released = self.released.notified();
+            {
+                let mut state = self.state.lock().expect("limiter lock poisoned");
+                if (state.in_flight as f64) < state.limit.floor() {
+                    state.in_flight += 1;
+                    return Permit {
+                        limiter: self,
+                        started: Instant::now(),
+                        outcome: None,
+                    };
+                }
+            }
+            released.await;
+        }
+    }
+
+    /// Free a slot, adjusting the limit if the call finished
+    fn release(&self, finished: Option<(Duration, bool)>) {
+        let mut state = self.state.lock().expect("limiter lock poisoned");
+        state.in_flight -= 1;
+        if let Some((latency, overloaded)) = finished {
+            let slow = latency > Duration::from_millis(self.config.target_latency_ms);
+            state.limit = if overloaded || slow {
+                state.limit * self.config.backoff
+            } else {
+                state.limit + 1.0 / state.limit
+            }
+            .clamp(self.config.min_limit, self.config.max_limit);
+        }
+        drop(state);
+        self.released.notify_waiters();
+    }
+
+    /// Run `call` once a slot is free, feeding its latency and outcome back
+    pub async fn run<T, F>(&self, call: F) -> Result<T, RepositoryError>
+    where
+        F: Future<Output = Result<T, RepositoryError>>,
+    {
+        let mut permit = self.acquire().await;
+        let result = call.await;
+        let failed = matches!(
+            result,
+            Err(RepositoryError::Backend(_) | RepositoryError::Unavailable(_))
+        );
+        permit.outcome = Some(failed);
+        result
+    }
+}
+
+/// A held slot, released when dropped; a cancelled call says nothing about load
+struct Permit<'a> {
+    limiter: &'a AdaptiveLimiter,
+    started: Instant,
+    /// Whether the finished call signalled overload
+    outcome: Option<bool>,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let finished = self.outcome.map(|overloaded| (self.started.elapsed(), overloaded));
+        self.limiter.release(finished);
+    }
+}
+
+/// Repository decorator sending every call through an `AdaptiveLimiter`
+pub struct LimitedUserRepository {
+    inner: Arc<dyn UserRepository>,
+    limiter: Arc<AdaptiveLimiter>,
+}
+
+impl LimitedUserRepository {
+    /// Wrap `inner`, limiting its concurrency with `limiter`
+    pub fn new(inner: Arc<dyn UserRepository>, limiter: Arc<AdaptiveLimiter>) -> Self {
+        Self { inner, limiter }
+    }
+}
+
+#[async_trait]
+impl OutboxStore for LimitedUserRepository {
+    async fn pending_events(&self, limit: usize) -> Result<Vec<OutboxRecord>, RepositoryError> {
+        self.limiter.run(self.inner.pending_events(limit)).await
+    }
+
+    async fn mark_delivered(&self, ids: &[Uuid]) -> Result<(), RepositoryError> {
+        self.limiter.run(self.inner.mark_delivered(ids)).await
+    }
+}
+
+#[async_trait]
+impl UserRepository for LimitedUserRepository {
+    async fn list(&self, tenant: &str, query: &UserQuery) -> Result<Vec<User>, RepositoryError> {
+        self.limiter.run(self.inner.list(tenant, query)).await
+    }
+
+    async fn get(&self, tenant: &str, id: Uuid) -> Result<Option<User>, RepositoryError> {
+        self.limiter.run(self.inner.get(tenant, id)).await
+    }
+
+    async fn find_by_username(
+        &self,
+        tenant: &str,
+        username: &str,
+    ) -> Result<Option<User>, RepositoryError> {
+        self.limiter.run(self.inner.find_by_username(tenant, username)).await
+    }
+
+    async fn find_by_email(
+        &self,
+        tenant: &str,
+        email: &str,
+    ) -> Result<Option<User>, RepositoryError> {
+        self.limiter.run(self.inner.find_by_email(tenant, email)).await
+    }
+
+    async fn search(
+        &self,
+        tenant: &str,
+        query: &SearchQuery,
+    ) -> Result<SearchPage, RepositoryError> {
+        self.limiter.run(self.inner.search(tenant, query)).await
+    }
+
+    async fn tenants(&self) -> Result<Vec<String>, RepositoryError> {
+        self.limiter.run(self.inner.tenants()).await
+    }
+
+    async fn insert(&self, user: User) -> Result<User, RepositoryError> {
+        self.limiter.run(self.inner.insert(user)).await
+    }
+
+    async fn insert_with_event(
+        &self,
+        user: User,
+        event: DomainEvent,
+    ) -> Result<User, RepositoryError> {
+        self.limiter.run(self.inner.insert_with_event(user, event)).await
+    }
+
+    async fn update(&self, user: User) -> Result<Option<User>, RepositoryError> {
+        self.limiter.run(self.inner.update(user)).await
+    }
+
+    async fn update_with_event(
+        &self,
+        user: User,
+        event: DomainEvent,
+    ) -> Result<Option<User>, RepositoryError> {
+        self.limiter.run(self.inner.update_with_event(user, event)).await
+    }
+
+    async fn commit(&self, changes: &[Change]) -> Result<bool, RepositoryError> {
+        self.limiter.run(self.inner.commit(changes)).await
+    }
+
+    async fn delete(&self, tenant: &str, id: Uuid) -> Result<bool, RepositoryError> {
+        self.limiter.run(self.inner.delete(tenant, id)).await
+    }
+
+    async fn record_last_seen(&self, seen: &[LastSeen]) -> Result<(), RepositoryError> {
+        self.limiter.run(self.inner.record_last_seen(seen)).await
+    }
+
+    async fn last_seen(
+        &self,
+        tenant: &str,
+        id: Uuid,
+    ) -> Result<Option<chrono::DateTime<chrono::Utc>>, RepositoryError> {
+        self.limiter.run(self.inner.last_seen(tenant, id)).await
+    }
+
+    async fn migrate(&self) -> Result<Vec<&'static str>, RepositoryError> {
+        self.inner.migrate().await
+    }
+
+    async fn schema_version(&self) -> Result<Option<String>, RepositoryError> {
+        self.inner.schema_version().await
+    }
+
+    fn has_schema(&self) -> bool {
+        self.inner.has_schema()
+    }
+
+    async fn ping(&self) -> Result<(), RepositoryError> {
+        // Health checks must see the dependency, not the queue in front of it
+        self.inner.ping().await
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        self.inner.pool_stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorReport;
+
+    fn limiter() -> AdaptiveLimiter {
+        AdaptiveLimiter::new(
+            "test",
+            LimiterConfig {
+                initial_limit: 10.0,
+                ..LimiterConfig::default()
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn test_limit_grows_when_fast_and_shrinks_on_failure() {
+        let limiter = limiter();
+        limiter.run(async { Ok(()) }).await.unwrap();
+        assert!((limiter.snapshot().limit - 10.1).abs() < 1e-9);
+
+        let failed = limiter
+            .run(async { Err::<(), _>(RepositoryError::Backend(ErrorReport::new("timeout"))) })
+            .await;
+        assert!(failed.is_err());
+        assert!((limiter.snapshot().limit - 9.09).abs() < 1e-9);
+        // Conflicts are the caller's problem, not a sign of overload
+        let _ = limiter
+            .run(async { Err::<(), _>(RepositoryError::Conflict("email".to_string())) })
+            .await;
+        assert!(limiter.snapshot().limit > 9.09);
+        assert_eq!(limiter.snapshot().in_flight, 0);
+    }
+
+    #[tokio::test]
+    async fn test_calls_over_the_limit_wait() {
+        let limiter = Arc::new(AdaptiveLimiter::new(
+            "test",
+            LimiterConfig {
+                initial_limit: 1.0,
+                max_limit: 1.0,
+                ..LimiterConfig::default()
+            },
+        ));
+        let (release, hold) = tokio::sync::oneshot::channel::<()>();
+        let first = {
+            let limiter = limiter.clone();
+            tokio::spawn(async move {
+                let held = async move {
+                    hold.await.map_err(|err| RepositoryError::Backend(ErrorReport::new(err)))
+                };
+                limiter.run(held).await
+            })
+        };
+        tokio::task::yield_now().await;
+        assert_eq!(limiter.snapshot().in_flight, 1);
+
+        let second = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.run(async { Ok(()) }).await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!second.is_finished());
+        release.send(()).unwrap();
+        first.await.unwrap().unwrap();
+        second.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,372 @@
+//! Listening socket setup for zero-downtime restarts.
+//!
+//! Two ways to replace a process on one host without refusing connections:
+//!
+//! - **Inherited listeners**: a supervisor (systemd socket activation,
+//!   `systemfd`, or the previous process) passes already-bound sockets
+//!   using the `LISTEN_FDS`/`LISTEN_PID` protocol, and they are used as-is.
+//! - **`SO_REUSEPORT`**: with `Config::reuse_port`, the new process binds the
+//!   same port while the old one is still serving and the kernel spreads
+//!   new connections across both.
+//!
+//! Either way the old process is then sent SIGTERM: it reports not ready,
+//! keeps serving for `Config::shutdown_drain_ms` so load balancers notice,
+//! then stops accepting and finishes in-flight requests. With `SO_REUSEPORT`,
+//! connections still queued on the old socket when it closes are reset by
+//! the kernel, so the drain delay should exceed the balancer's probe interval.
+//!
+//! With `Config::unix_socket` the server also listens on a Unix domain
+//! socket, for deployments behind a proxy on the same host such as nginx,
+//! and with `keep_tcp` off on that socket alone. A stale socket file left by
+//! a previous run is replaced; any other file at the path is an error.
+//! Requests over the socket carry no client address, so access logs should
+//! rely on the proxy's `X-Forwarded-For`.
+//!
+//! `Config::bind` lists TCP addresses to listen on in place of `host`/`port`,
+//! each with TLS on or off, for example `0.0.0.0:8443` and `[::]:8443` over
+//! HTTPS plus a plain port for in-cluster traffic. An IPv6 wildcard is
+//! dual-stack by default on most systems and then also takes IPv4; when an
+//! IPv4 address on the same port is listed too, the IPv6 socket is bound
+//! IPv6-only so the two don't collide.
+//!
+//! Under socket activation nothing is bound from configuration: the server
+//! listens on whatever it was passed, any number of TCP sockets and at most
+//! one Unix stream socket, and `Config::bind`, `host`/`port`, and
+//! `Config::unix_socket` are ignored. Passed TCP sockets use TLS when it is
+//! configured. Because
+//! systemd keeps the sockets open while the service restarts, connections
+//! arriving in between wait in the backlog rather than being refused, and a
+//! privileged port such as 443 needs no root in the service itself. A
+//! passed Unix socket's file belongs to systemd and is left in place.
+
+use axum::Router;
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::future::Future;
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+
+/// First inherited descriptor in the `LISTEN_FDS` protocol
+#[cfg(unix)]
+const LISTEN_FDS_START: std::os::unix::io::RawFd = 3;
+
+/// Pending connection backlog for sockets we bind ourselves
+const BACKLOG: i32 = 1024;
+
+/// One TCP address to listen on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BindConfig {
+    /// Address and port, e.g. `0.0.0.0:8443` or `[::]:8443`
+    pub addr: SocketAddr,
+    /// Serve HTTPS here when `Config::tls` or `Config::acme` is set
+    #[serde(default = "default_tls")]
+    pub tls: bool,
+}
+
+fn default_tls() -> bool {
+    true
+}
+
+/// Unix domain socket settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnixSocketConfig {
+    /// Socket file to create, e.g. `/run/app/api.sock`
+    pub path: PathBuf,
+    /// Octal permissions of the socket file, so the proxy's group can connect
+    #[serde(default = "default_socket_mode")]
+    pub mode: String,
+    /// Keep listening on the TCP addresses as well
+    #[serde(default)]
+    pub keep_tcp: bool,
+}
+
+fn default_socket_mode() -> String {
+    "660".to_string()
+}
+
+/// Sockets to serve on
+#[derive(Debug, Default)]
+pub struct Listeners {
+    /// TCP listeners, none when serving on the Unix socket alone
+    pub tcp: Vec<TcpBinding>,
+    /// Unix domain socket listener
+    pub unix: Option<UnixListener>,
+}
+
+/// A listening TCP socket
+#[derive(Debug)]
+pub struct TcpBinding {
+    /// Bound and listening
+    pub listener: TcpListener,
+    /// Whether it may serve HTTPS
+    pub tls: bool,
+}
+
+/// A listening Unix socket
+#[derive(Debug)]
+pub struct UnixListener {
+    socket: Socket,
+    /// Socket file to remove when done, if we created it
+    created: Option<PathBuf>,
+}
+
+/// Sort passed sockets into TCP listeners and at most one Unix listener
+fn from_sockets(sockets: Vec<Socket>) -> Listeners {
+    let mut listeners = Listeners::default();
+    for socket in sockets {
+        let Ok(local) = socket.local_addr() else {
+            tracing::warn!("ignoring inherited descriptor that is not a socket");
+            continue;
+        };
+        if socket.r#type().ok() != Some(Type::STREAM) {
+            tracing::warn!("ignoring inherited socket that is not a stream socket");
+            continue;
+        }
+        if local.as_socket().is_some() {
+            let listener = socket.into();
+            listeners.tcp.push(TcpBinding { listener, tls: true });
+        } else if local.is_unix() {
+            match listeners.unix {
+                Some(_) => tracing::warn!("ignoring extra inherited Unix listener"),
+                None => listeners.unix = Some(UnixListener { socket, created: None }),
+            }
+        } else {
+            tracing::warn!("ignoring inherited socket of unsupported family");
+        }
+    }
+    listeners
+}
+
+/// Take the sockets passed by a supervisor, if any
+#[cfg(unix)]
+fn inherited() -> Option<Listeners> {
+    use std::os::unix::io::FromRawFd;
+
+    let pid: u32 = std::env::var("LISTEN_PID").ok()?.parse().ok()?;
+    let count: i32 = std::env::var("LISTEN_FDS").ok()?.parse().ok()?;
+    if pid != std::process::id() || count <= 0 {
+        return None;
+    }
+    // Don't let children we spawn believe the descriptors are theirs
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+    let sockets = (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| {
+            // SAFETY: per the protocol, descriptors from 3 on are open and handed
+            // to this process, and nothing else in the process owns them
+            let socket = unsafe { Socket::from_raw_fd(fd) };
+            // They arrive inheritable; keep them from leaking into children
+            if let Err(err) = socket.set_cloexec(true) {
+                tracing::warn!("failed to mark inherited descriptor {} close-on-exec: {}", fd, err);
+            }
+            socket
+        })
+        .collect();
+    Some(from_sockets(sockets))
+}
+
+/// Take the sockets passed by a supervisor, if any
+#[cfg(not(unix))]
+fn inherited() -> Option<Listeners> {
+    None
+}
+
+/// Bind `addr`, optionally sharing the port with other processes
+pub fn bind(addr: SocketAddr, reuse_port: bool, v6_only: bool) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    if v6_only {
+        socket.set_only_v6(true)?;
+    }
+    if reuse_port {
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        #[cfg(not(unix))]
+        tracing::warn!("SO_REUSEPORT is not supported on this platform; binding exclusively");
+    }
+    socket.bind(&addr.into())?;
+    socket.listen(BACKLOG)?;
+    Ok(socket.into())
+}
+
+/// Whether the IPv6 socket for `addr` must leave IPv4 to another in `binds`
+fn needs_v6_only(addr: SocketAddr, binds: &[BindConfig]) -> bool {
+    addr.is_ipv6()
+        && binds.iter().any(|bind| bind.addr.is_ipv4() && bind.addr.port() == addr.port())
+}
+
+/// The sockets to serve on: inherited if any were passed, otherwise bound
+pub fn open(
+    binds: &[BindConfig],
+    reuse_port: bool,
+    unix_socket: Option<&UnixSocketConfig>,
+) -> std::io::Result<Listeners> {
+    let listeners = match inherited() {
+        Some(listeners) => {
+            tracing::info!(
+                "using inherited listeners (tcp: {}, unix: {})",
+                listeners.tcp.len(),
+                listeners.unix.is_some()
+            );
+            listeners
+        }
+        None => Listeners {
+            tcp: match unix_socket {
+                Some(config) if !config.keep_tcp => Vec::new(),
+                _ => binds
+                    .iter()
+                    .map(|bind_config| {
+                        let v6_only = needs_v6_only(bind_config.addr, binds);
+                        let listener = bind(bind_config.addr, reuse_port, v6_only)?;
+                        Ok(TcpBinding { listener, tls: bind_config.tls })
+                    })
+                    .collect::<std::io::Result<_>>()?,
+            },
+            unix: unix_socket.map(bind_unix).transpose()?,
+        },
+    };
+    for tcp in &listeners.tcp {
+        tcp.listener.set_nonblocking(true)?;
+    }
+    if let Some(unix) = &listeners.unix {
+        unix.socket.set_nonblocking(true)?;
+    }
+    Ok(listeners)
+}
+
+/// Bind the configured socket, replacing a stale one, with its permissions set
+#[cfg(unix)]
+fn bind_unix(config: &UnixSocketConfig) -> std::io::Result<UnixListener> {
+    use std::io::{Error, ErrorKind};
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    let mode = u32::from_str_radix(config.mode.trim_start_matches("0o"), 8).map_err(|_| {
+        Error::new(ErrorKind::InvalidInput, format!("invalid socket mode {:?}", config.mode))
+    })?;
+    match std::fs::symlink_metadata(&config.path) {
+        Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(&config.path)?,
+        Ok(_) => {
+            let message = format!("{} exists and is not a socket", config.path.display());
+            return Err(Error::new(ErrorKind::AlreadyExists, message));
+        }
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+    let listener = std::os::unix::net::UnixListener::bind(&config.path)?;
+    std::fs::set_permissions(&config.path, std::fs::Permissions::from_mode(mode))?;
+    Ok(UnixListener {
+        socket: listener.into(),
+        created: Some(config.path.clone()),
+    })
+}
+
+/// Unix sockets don't exist here
+#[cfg(not(unix))]
+fn bind_unix(_config: &UnixSocketConfig) -> std::io::Result<UnixListener> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "Unix domain sockets are not supported on this platform",
+    ))
+}
+
+/// Serve `app` on a Unix socket until `shutdown` resolves
+#[cfg(unix)]
+pub async fn serve_unix(
+    listener: UnixListener,
+    app: Router,
+    shutdown: impl Future<Output = ()>,
+) -> std::io::Result<()> {
+    let UnixListener { socket, created } = listener;
+    let listener = tokio::net::UnixListener::from_std(socket.into())?;
+    match listener.local_addr()?.as_pathname() {
+        Some(path) => tracing::info!("listening on {}", path.display()),
+        None => tracing::info!("listening on an unnamed Unix socket"),
+    }
+    let incoming = tokio_stream::wrappers::UnixListenerStream::new(listener);
+    let served = axum::Server::builder(hyper::server::accept::from_stream(incoming))
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(shutdown)
+        .await
+        .map_err(std::io::Error::other);
+    if let Some(path) = created {
+        if let Err(err) = std::fs::remove_file(&path) {
+            tracing::warn!("failed to remove {}: {}", path.display(), err);
+        }
+    }
+    served
+}
+
+/// Unix sockets don't exist here, so there is never one to serve
+#[cfg(not(unix))]
+pub async fn serve_unix(
+    _listener: UnixListener,
+    _app: Router,
+    _shutdown: impl Future<Output = ()>,
+) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_reuse_port_allows_second_bind() {
+        let first = bind("127.0.0.1:0".parse().unwrap(), true, false).unwrap();
+        let addr = first.local_addr().unwrap();
+        assert!(bind(addr, true, false).is_ok());
+    }
+
+    #[test]
+    fn test_ipv6_is_v6_only_beside_ipv4_on_same_port() {
+        let bind = |addr: &str| BindConfig { addr: addr.parse().unwrap(), tls: true };
+        let binds = [bind("0.0.0.0:8443"), bind("[::]:8443"), bind("[::]:9090")];
+        assert!(!needs_v6_only(binds[0].addr, &binds));
+        assert!(needs_v6_only(binds[1].addr, &binds));
+        assert!(!needs_v6_only(binds[2].addr, &binds));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_socket_replaces_stale_socket_and_sets_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("uds-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let config = UnixSocketConfig {
+            path: dir.join("api.sock"),
+            mode: "600".to_string(),
+            keep_tcp: false,
+        };
+        drop(bind_unix(&config).unwrap());
+        bind_unix(&config).unwrap();
+        let mode = std::fs::metadata(&config.path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let file = UnixSocketConfig { path: dir.join("file"), ..config };
+        std::fs::write(&file.path, "").unwrap();
+        assert!(bind_unix(&file).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_passed_sockets_are_sorted_by_family() {
+        let dir = std::env::temp_dir().join(format!("uds-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let unix = std::os::unix::net::UnixListener::bind(dir.join("api.sock")).unwrap();
+        let tcp = bind("127.0.0.1:0".parse().unwrap(), false, false).unwrap();
+        let second = bind("127.0.0.1:0".parse().unwrap(), false, false).unwrap();
+        let udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let addr = tcp.local_addr().unwrap();
+        let sockets = vec![unix.into(), tcp.into(), second.into(), udp.into()];
+        let listeners = from_sockets(sockets);
+        assert_eq!(listeners.tcp.len(), 2);
+        assert_eq!(listeners.tcp[0].listener.local_addr().unwrap(), addr);
+        assert!(listeners.unix.unwrap().created.is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,251 @@
+//! Localized derived fields, chosen by `Accept-Language`.
+//!
+//! Requests sending `Accept-Language` get extra, display-ready fields next
+//! to the raw ones in JSON envelopes: every RFC 3339 `*_at` timestamp gains a
+//! humanized `*_at_display` ("3 days ago"), and every `is_active` flag gains a
+//! `status_label`. The raw fields stay as they are, so clients can ignore the
+//! extras. English, German, and French are supported. Anything else falls
+//! back to English, and requests without the header are left untouched.
+//!
+//! `localize_responses` runs after redaction, so hidden fields never get a
+//! localized twin. Responses carry `Content-Language` and
+//! `Vary: accept-language`, so shared caches keep one copy per locale. The
+//! in-process user caches hold unlocalized users, so one entry serves every
+//! locale.
+
+use axum::{
+    body::{boxed, Body, Full},
+    http::{header, HeaderMap, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+use crate::context::RequestContext;
+
+/// A language we have strings for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Locale {
+    /// English, the fallback
+    En,
+    /// German
+    De,
+    /// French
+    Fr,
+}
+
+impl Locale {
+    fn from_tag(tag: &str) -> Option<Self> {
+        let language = tag.split('-').next().unwrap_or(tag);
+        match language.to_ascii_lowercase().as_str() {
+            "en" => Some(Self::En),
+            "de" => Some(Self::De),
+            "fr" => Some(Self::Fr),
+            _ => None,
+        }
+    }
+
+    /// Best supported locale for an `Accept-Language` header, if one was sent
+    pub fn negotiate(headers: &HeaderMap) -> Option<Self> {
+        let header = headers.get(header::ACCEPT_LANGUAGE)?.to_str().ok()?;
+        let mut best: Option<(Self, f32)> = None;
+        for entry in header.split(',') {
+            let mut params = entry.split(';');
+            let Some(locale) = Self::from_tag(params.next().unwrap_or_default().trim()) else {
+                continue;
+            };
+            let quality = params
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|quality| quality.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if quality > 0.0 && best.map_or(true, |(_, current)| quality > current) {
+                best = Some((locale, quality));
+            }
+        }
+        Some(best.map_or(Self::En, |(locale, _)| locale))
+    }
+
+    /// Language tag for `Content-Language`
+    pub fn tag(self) -> &'static str {
+        match self {
+            Self::En => "en",
+            Self::De => "de",
+            Self::Fr => "fr",
+        }
+    }
+
+    /// Label for an account's `is_active` flag
+    pub fn status_label(self, active: bool) -> &'static str {
+        match (self, active) {
+            (Self::En, true) => "Active",
+            (Self::En, false) => "Inactive",
+            (Self::De, true) => "Aktiv",
+            (Self::De, false) => "Inaktiv",
+            (Self::Fr, true) => "Actif",
+            (Self::Fr, false) => "Inactif",
+        }
+    }
+
+    /// How long ago `at` was, e.g. "3 days ago"
+    pub fn humanize(self, at: DateTime<Utc>, now: DateTime<Utc>) -> String {
+        let seconds = (now - at).num_seconds().max(0);
+        // Unit index, then count: minutes, hours, days, months, years
+        let (unit, count) = match seconds {
+            0..=59 => {
+                return match self {
+                    Self::En => "just now",
+                    Self::De => "gerade eben",
+                    Self::Fr => "à l'instant",
+                }
+                .to_string()
+            }
+            60..=3_599 => (0, seconds / 60),
+            3_600..=86_399 => (1, seconds / 3_600),
+            86_400..=2_591_999 => (2, seconds / 86_400),
+            2_592_000..=31_535_999 => (3, seconds / 2_592_000),
+            _ => (4, seconds / 31_536_000),
+        };
+        // Singular and plural of each unit
+        let units: [(&str, &str); 5] = match self {
+            Self::En => [
+                ("minute", "minutes"),
+                ("hour", "hours"),
+                ("day", "days"),
+                ("month", "months"),
+                ("year", "years"),
+            ],
+            Self::De => [
+                ("Minute", "Minuten"),
+                ("Stunde", "Stunden"),
+                ("Tag", "Tagen"),
+                ("Monat", "Monaten"),
+                ("Jahr", "Jahren"),
+            ],
+            Self::Fr => [
+                ("minute", "minutes"),
+                ("heure", "heures"),
+                ("jour", "jours"),
+                ("mois", "mois"),
+                ("an", "ans"),
+            ],
+        };
+        let (one, many) = units[unit];
+        let unit = if count == 1 { one } else { many };
+        match self {
+            Self::En => format!("{} {} ago", count, unit),
+            Self::De => format!("vor {} {}", count, unit),
+            Self::Fr => format!("il y a {} {}", count, unit),
+        }
+    }
+}
+
+/// Add localized twins of the derived fields throughout `value`
+pub fn localize(value: &mut Value, locale: Locale, now: DateTime<Utc>) {
+    match value {
+        Value::Object(map) => {
+            let mut derived = Vec::new();
+            for (key, field) in map.iter_mut() {
+                match field {
+                    Value::String(text) if key.ends_with("_at") => {
+                        if let Ok(at) = DateTime::parse_from_rfc3339(text) {
+                            let display = locale.humanize(at.with_timezone(&Utc), now);
+                            derived.push((format!("{}_display", key), display));
+                        }
+                    }
+                    Value::Bool(active) if key == "is_active" => {
+                        let label = locale.status_label(*active).to_string();
+                        derived.push(("status_label".to_string(), label));
+                    }
+                    _ => localize(field, locale, now),
+                }
+            }
+            for (key, text) in derived {
+                map.insert(key, Value::String(text));
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                localize(item, locale, now);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Middleware localizing envelope `data` in the request context's locale
+pub async fn localize_responses<B>(req: Request<B>, next: Next<B>) -> Response {
+    let locale = req.extensions().get::<RequestContext>().and_then(|context| context.locale);
+    let Some(locale) = locale else {
+        return next.run(req).await;
+    };
+    let response = next.run(req).await;
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    if !is_json {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            tracing::error!("failed to buffer response for localization: {}", err);
+            return Response::from_parts(parts, boxed(Body::empty()));
+        }
+    };
+    let mut value = match serde_json::from_slice::<Value>(&bytes) {
+        Ok(value) if value.get("success").is_some() => value,
+        _ => return Response::from_parts(parts, boxed(Full::from(bytes))),
+    };
+    if let Some(data) = value.get_mut("data") {
+        localize(data, locale, Utc::now());
+    }
+    let body = serde_json::to_vec(&value).unwrap_or_default();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(header::CONTENT_LANGUAGE, HeaderValue::from_static(locale.tag()));
+    parts
+        .headers
+        .append(header::VARY, HeaderValue::from_static("accept-language"));
+    Response::from_parts(parts, boxed(Full::from(body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn accepting(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_LANGUAGE, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_negotiation_prefers_best_supported_language() {
+        assert_eq!(Locale::negotiate(&HeaderMap::new()), None);
+        assert_eq!(Locale::negotiate(&accepting("de-CH, fr;q=0.8")), Some(Locale::De));
+        assert_eq!(Locale::negotiate(&accepting("ja, fr;q=0.5, en;q=0.4")), Some(Locale::Fr));
+        assert_eq!(Locale::negotiate(&accepting("ja")), Some(Locale::En));
+    }
+
+    #[test]
+    fn test_users_gain_localized_fields() {
+        let now = Utc::now();
+        let created = (now - chrono::Duration::days(3)).to_rfc3339();
+        let mut data = json!([{ "created_at": created, "deleted_at": null, "is_active": false }]);
+        localize(&mut data, Locale::De, now);
+        assert_eq!(data[0]["created_at"], created);
+        assert_eq!(data[0]["created_at_display"], "vor 3 Tagen");
+        assert_eq!(data[0]["status_label"], "Inaktiv");
+        assert!(data[0].get("deleted_at_display").is_none());
+
+        let hour_ago = now - chrono::Duration::minutes(61);
+        assert_eq!(Locale::En.humanize(hour_ago, now), "1 hour ago");
+        assert_eq!(Locale::Fr.humanize(now, now), "à l'instant");
+    }
+}
@@ -0,0 +1,725 @@
+//! Outbound email.
+//!
+//! Features that email users render a `Template`, such as an address
+//! verification, a password reset, or a lockout notice, and hand it to
+//! `Mailer::enqueue`, which returns at once. The `mail` task sends queued
+//! messages over SMTP with `Config::mail.smtp`, retrying a failed send with
+//! doubling delays up to `max_attempts` before dropping it with an error in
+//! the log. Without SMTP settings, or in demo mode, messages are logged
+//! instead of sent, so development never emails anyone by accident. With
+//! circuit breakers enabled, sends fail at once while the `mail` breaker is
+//! open, and are retried as any other failure.
+//!
+//! `mail::send` hands messages to the job queue instead when it is durable,
+//! where they survive restarts and dead-letter when retries run out.
+//! Otherwise the mailer's queue is in memory, per instance: messages still
+//! queued at shutdown are lost, and `enqueue` refuses new ones while
+//! `queue_capacity` are waiting. `GET /api/v1/admin/mail` reports the counts, and
+//! `POST /api/v1/admin/mail/test` sends a sample of a template, to check the
+//! SMTP settings.
+
+use async_trait::async_trait;
+use axum::{extract::State, http::StatusCode, response::Json};
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+use crate::auth::Admin;
+use crate::breaker::CircuitBreaker;
+use crate::error::{AppError, ErrorReport};
+use crate::secret::Secret;
+use crate::validation::{Validate, ValidatedJson};
+use crate::webhooks::RetryPolicy;
+use crate::{jobs, service, ApiResponse, AppState};
+
+/// How the SMTP connection is secured
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpSecurity {
+    /// Upgrade a plain connection with STARTTLS, usually on port 587
+    #[default]
+    StartTls,
+    /// TLS from the start, usually on port 465
+    Tls,
+    /// No encryption, for a local relay or test server only
+    None,
+}
+
+/// SMTP relay settings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SmtpConfig {
+    /// Relay host name
+    pub host: String,
+    /// Relay port
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    /// How the connection is secured
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    /// User to authenticate as, if the relay wants one
+    #[serde(default)]
+    pub username: Option<String>,
+    /// Password of `username`
+    #[serde(default)]
+    pub password: Option<Secret<String>>,
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+/// Outbound email settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MailConfig {
+    /// SMTP relay; without one, messages are logged instead of sent
+    pub smtp: Option<SmtpConfig>,
+    /// Sender address, optionally with a name: `Acme <no-reply@acme.test>`
+    pub from: String,
+    /// Product name templates sign messages with
+    pub product_name: String,
+    /// Base URL of the pages that verification and reset links open
+    pub link_base_url: String,
+    /// Messages waiting before new ones are refused
+    pub queue_capacity: usize,
+    /// Send attempts per message, including the first
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for each one after
+    pub retry_base_ms: u64,
+    /// Longest delay between retries
+    pub retry_max_ms: u64,
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        MailConfig {
+            smtp: None,
+            from: "no-reply@localhost".to_string(),
+            product_name: "User Service".to_string(),
+            link_base_url: "http://localhost:3000".to_string(),
+            queue_capacity: 1_000,
+            max_attempts: 5,
+            retry_base_ms: 2_000,
+            retry_max_ms: 300_000,
+        }
+    }
+}
+
+impl MailConfig {
+    /// Retry schedule of failed sends
+    pub fn retry(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts.max(1),
+            base_delay: Duration::from_millis(self.retry_base_ms),
+            max_delay: Duration::from_millis(self.retry_max_ms),
+        }
+    }
+}
+
+/// A message users are sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Template {
+    /// Confirm an address by opening a link
+    Verification {
+        /// Username greeted
+        username: String,
+        /// Token the link carries
+        token: String,
+    },
+    /// Choose a new password by opening a link
+    PasswordReset {
+        /// Username greeted
+        username: String,
+        /// Token the link carries
+        token: String,
+    },
+    /// The account was locked after repeated failed sign-ins
+    LockoutNotice {
+        /// Username greeted
+        username: String,
+        /// When the lock lifts
+        until: chrono::DateTime<chrono::Utc>,
+    },
+    /// Confirm a new address by opening a link, sent to the new address
+    EmailChange {
+        /// Username greeted
+        username: String,
+        /// Token the link carries
+        token: String,
+    },
+    /// The account's address was changed, sent to the old address
+    EmailChanged {
+        /// Username greeted
+        username: String,
+        /// The new address
+        email: String,
+    },
+}
+
+const VERIFICATION_SUBJECT: &str = "Confirm your email address for {product}";
+const VERIFICATION_BODY: &str = "Hi {username},
+
+Please confirm this is your email address by opening the link below:
+
+{link}
+
+If you didn't create a {product} account, you can ignore this message.
+
+The {product} team
+";
+
+const PASSWORD_RESET_SUBJECT: &str = "Reset your {product} password";
+const PASSWORD_RESET_BODY: &str = "Hi {username},
+
+Someone asked to reset the password of your {product} account. To choose a
+new one, open the link below:
+
+{link}
+
+If it wasn't you, ignore this message; your password stays as it is.
+
+The {product} team
+";
+
+const LOCKOUT_SUBJECT: &str = "Your {product} account was locked";
+const LOCKOUT_BODY: &str = "Hi {username},
+
+Your {product} account was locked after several failed sign-in attempts. You
+can sign in again after {until}.
+
+If those attempts weren't yours, reset your password once the lock lifts.
+
+The {product} team
+";
+
+const EMAIL_CHANGE_SUBJECT: &str = "Confirm your new email address for {product}";
+const EMAIL_CHANGE_BODY: &str = "Hi {username},
+
+Someone asked to use this address for your {product} account. To confirm,
+open the link below:
+
+{link}
+
+Until you do, your account keeps its current address. If it wasn't you, you
+can ignore this message.
+
+The {product} team
+";
+
+const EMAIL_CHANGED_SUBJECT: &str = "Your {product} email address was changed";
+const EMAIL_CHANGED_BODY: &str = "Hi {username},
+
+The email address of your {product} account was changed to {email}. Messages
+will go there from now on.
+
+If you didn't make this change, contact us right away.
+
+The {product} team
+";
+
+/// `template` with each `{name}` replaced by its value
+fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    values.iter().fold(template.to_string(), |text, (name, value)| {
+        text.replace(&format!("{{{}}}", name), value)
+    })
+}
+
+impl Template {
+    /// Subject and plain-text body, in the product's name
+    pub fn render(&self, config: &MailConfig) -> (String, String) {
+        let base = config.link_base_url.trim_end_matches('/');
+        let product = config.product_name.as_str();
+        let (subject, body, username, extra) = match self {
+            Template::Verification { username, token } => {
+                let link = format!("{}/verify-email?token={}", base, token);
+                (VERIFICATION_SUBJECT, VERIFICATION_BODY, username, ("link", link))
+            }
+            Template::PasswordReset { username, token } => {
+                let link = format!("{}/reset-password?token={}", base, token);
+                (PASSWORD_RESET_SUBJECT, PASSWORD_RESET_BODY, username, ("link", link))
+            }
+            Template::LockoutNotice { username, until } => {
+                let until = until.format("%Y-%m-%d %H:%M UTC").to_string();
+                (LOCKOUT_SUBJECT, LOCKOUT_BODY, username, ("until", until))
+            }
+            Template::EmailChange { username, token } => {
+                let link = format!("{}/confirm-email-change?token={}", base, token);
+                (EMAIL_CHANGE_SUBJECT, EMAIL_CHANGE_BODY, username, ("link", link))
+            }
+            Template::EmailChanged { username, email } => {
+                let email = email.clone();
+                (EMAIL_CHANGED_SUBJECT, EMAIL_CHANGED_BODY, username, ("email", email))
+            }
+        };
+        let (name, value) = (extra.0, extra.1.as_str());
+        let values = [("product", product), ("username", username.as_str()), (name, value)];
+        (fill(subject, &values), fill(body, &values))
+    }
+}
+
+/// A rendered message to one recipient
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Email {
+    /// Recipient address
+    pub to: String,
+    /// Subject line
+    pub subject: String,
+    /// Plain-text body
+    pub body: String,
+}
+
+impl Email {
+    /// `template` rendered for `to`
+    pub fn new(config: &MailConfig, to: &str, template: &Template) -> Self {
+        let (subject, body) = template.render(config);
+        Email {
+            to: to.to_string(),
+            subject,
+            body,
+        }
+    }
+}
+
+/// Where messages go
+#[async_trait]
+pub trait MailTransport: Send + Sync {
+    /// Deliver one message, or say why it wasn't
+    async fn send(&self, from: &str, email: &Email) -> Result<(), String>;
+}
+
+/// Logs messages instead of sending them
+#[derive(Debug, Default)]
+pub struct LogSink;
+
+#[async_trait]
+impl MailTransport for LogSink {
+    async fn send(&self, from: &str, email: &Email) -> Result<(), String> {
+        tracing::info!(
+            from,
+            to = %email.to,
+            subject = %email.subject,
+            "email not sent (no SMTP relay configured):\n{}",
+            email.body
+        );
+        Ok(())
+    }
+}
+
+type Relay = AsyncSmtpTransport<Tokio1Executor>;
+
+/// Sends messages through an SMTP relay
+pub struct SmtpTransport {
+    transport: Relay,
+}
+
+impl SmtpTransport {
+    /// Transport to the relay `config` names
+    pub fn new(config: &SmtpConfig) -> Result<Self, String> {
+        let builder = match config.security {
+            SmtpSecurity::StartTls => Relay::starttls_relay(&config.host),
+            SmtpSecurity::Tls => Relay::relay(&config.host),
+            SmtpSecurity::None => Ok(Relay::builder_dangerous(&config.host)),
+        };
+        let mut builder = builder
+            .map_err(|err| format!("mail.smtp.host {:?} is unusable: {}", config.host, err))?
+            .port(config.port);
+        if let Some(username) = &config.username {
+            let password = config.password.as_ref().map(|password| password.expose().clone());
+            let credentials = Credentials::new(username.clone(), password.unwrap_or_default());
+            builder = builder.credentials(credentials);
+        }
+        Ok(SmtpTransport {
+            transport: builder.build(),
+        })
+    }
+}
+
+#[async_trait]
+impl MailTransport for SmtpTransport {
+    async fn send(&self, from: &str, email: &Email) -> Result<(), String> {
+        let message = Message::builder()
+            .from(from.parse().map_err(|err| format!("mail.from is invalid: {}", err))?)
+            .to(email.to.parse().map_err(|err| format!("recipient is invalid: {}", err))?)
+            .subject(email.subject.clone())
+            .header(ContentType::TEXT_PLAIN)
+            .body(email.body.clone())
+            .map_err(|err| err.to_string())?;
+        self.transport.send(message).await.map_err(|err| err.to_string())?;
+        Ok(())
+    }
+}
+
+/// Mail queue counts since startup
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct MailStats {
+    /// Messages waiting to be sent or retried
+    pub queued: usize,
+    /// Messages sent
+    pub sent: u64,
+    /// Failed sends that were retried
+    pub retried: u64,
+    /// Messages dropped after their last attempt failed
+    pub failed: u64,
+    /// Messages refused because the queue was full
+    pub refused: u64,
+}
+
+/// Why a message wasn't queued
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MailError {
+    /// `queue_capacity` messages are already waiting
+    QueueFull,
+}
+
+impl std::fmt::Display for MailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MailError::QueueFull => write!(f, "mail queue is full"),
+        }
+    }
+}
+
+impl From<MailError> for AppError {
+    fn from(err: MailError) -> Self {
+        AppError::Overloaded(err.to_string())
+    }
+}
+
+/// A queued message and its attempts so far
+#[derive(Debug)]
+struct Queued {
+    email: Email,
+    attempts: u32,
+    not_before: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    waiting: VecDeque<Queued>,
+    stats: MailStats,
+}
+
+/// Queue of outbound messages and the transport sending them
+pub struct Mailer {
+    transport: Arc<dyn MailTransport>,
+    from: String,
+    capacity: usize,
+    retry: RetryPolicy,
+    queue: Mutex<Queue>,
+    ready: Notify,
+    breaker: Option<Arc<CircuitBreaker>>,
+}
+
+impl Mailer {
+    /// Mailer sending through `transport`
+    pub fn new(config: &MailConfig, transport: Arc<dyn MailTransport>) -> Self {
+        Mailer {
+            transport,
+            from: config.from.clone(),
+            capacity: config.queue_capacity,
+            retry: config.retry(),
+            queue: Mutex::new(Queue::default()),
+            ready: Notify::new(),
+            breaker: None,
+        }
+    }
+
+    /// Mailer for `config`: SMTP when a relay is set, logging otherwise
+    pub fn from_config(config: &MailConfig, demo: bool) -> Self {
+        let transport: Arc<dyn MailTransport> = match config.smtp.as_ref().filter(|_| !demo) {
+            Some(smtp) => match SmtpTransport::new(smtp) {
+                Ok(transport) => Arc::new(transport),
+                Err(err) => {
+                    tracing::error!("{}; logging emails instead of sending them", err);
+                    Arc::new(LogSink)
+                }
+            },
+            None => Arc::new(LogSink),
+        };
+        Self::new(config, transport)
+    }
+
+    /// Refuse sends while `breaker`, if any, is open
+    pub fn with_breaker(mut self, breaker: Option<Arc<CircuitBreaker>>) -> Self {
+        self.breaker = breaker;
+        self
+    }
+
+    /// Send through the transport unless the breaker is open
+    async fn send_once(&self, email: &Email) -> Result<(), String> {
+        let Some(breaker) = &self.breaker else {
+            return self.transport.send(&self.from, email).await;
+        };
+        let attempt = breaker.admit().map_err(|open| open.to_string())?;
+        let result = self.transport.send(&self.from, email).await;
+        attempt.finish(result.is_err());
+        result
+    }
+
+    /// Queue a message for sending
+    pub fn enqueue(&self, email: Email) -> Result<(), MailError> {
+        let mut queue = self.queue.lock().expect("mail queue lock poisoned");
+        if queue.waiting.len() >= self.capacity {
+            queue.stats.refused += 1;
+            return Err(MailError::QueueFull);
+        }
+        queue.waiting.push_back(Queued {
+            email,
+            attempts: 0,
+            not_before: Instant::now(),
+        });
+        drop(queue);
+        self.ready.notify_one();
+        Ok(())
+    }
+
+    /// Send a message once, now, leaving retries to the caller
+    pub async fn deliver(&self, email: &Email) -> Result<(), String> {
+        self.send_once(email).await?;
+        self.queue.lock().expect("mail queue lock poisoned").stats.sent += 1;
+        Ok(())
+    }
+
+    /// Counts since startup
+    pub fn stats(&self) -> MailStats {
+        let queue = self.queue.lock().expect("mail queue lock poisoned");
+        MailStats {
+            queued: queue.waiting.len(),
+            ..queue.stats
+        }
+    }
+
+    /// The first message due to be sent, if any, or how long until one is
+    fn next_due(&self) -> Result<Queued, Option<Duration>> {
+        let mut queue = self.queue.lock().expect("mail queue lock poisoned");
+        let now = Instant::now();
+        match queue.waiting.iter().position(|queued| queued.not_before <= now) {
+            Some(index) => Ok(queue.waiting.remove(index).expect("index is in range")),
+            None => Err(queue.waiting.iter().map(|queued| queued.not_before - now).min()),
+        }
+    }
+
+    /// Send every message that is due, returning how long until the next one
+    pub async fn send_due(&self) -> Option<Duration> {
+        loop {
+            let mut queued = match self.next_due() {
+                Ok(queued) => queued,
+                Err(wait) => return wait,
+            };
+            queued.attempts += 1;
+            let result = self.send_once(&queued.email).await;
+            let mut queue = self.queue.lock().expect("mail queue lock poisoned");
+            match result {
+                Ok(()) => queue.stats.sent += 1,
+                Err(err) if queued.attempts >= self.retry.max_attempts => {
+                    queue.stats.failed += 1;
+                    tracing::error!(
+                        to = %queued.email.to,
+                        subject = %queued.email.subject,
+                        attempts = queued.attempts,
+                        "giving up on email: {}",
+                        err
+                    );
+                }
+                Err(err) => {
+                    queue.stats.retried += 1;
+                    let delay = self.retry.backoff(queued.attempts);
+                    tracing::warn!(to = %queued.email.to, "email send failed, retrying: {}", err);
+                    queued.not_before = Instant::now() + delay;
+                    queue.waiting.push_back(queued);
+                }
+            }
+        }
+    }
+}
+
+/// Send a message through the job queue when it is durable, so it survives
+/// a restart, or else through the mailer's own queue
+pub async fn send(state: &AppState, email: Email) -> Result<(), AppError> {
+    if !state.jobs.is_durable() {
+        return Ok(state.mailer.enqueue(email)?);
+    }
+    let payload =
+        serde_json::to_value(&email).map_err(|err| AppError::Internal(ErrorReport::new(err)))?;
+    state.jobs.enqueue(&state.config().jobs, jobs::EMAIL, payload).await?;
+    Ok(())
+}
+
+/// Spawn the task sending queued mail
+pub fn spawn_sender(state: Arc<AppState>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            match state.mailer.send_due().await {
+                Some(wait) => {
+                    tokio::select! {
+                        _ = tokio::time::sleep(wait) => {}
+                        _ = state.mailer.ready.notified() => {}
+                    }
+                }
+                None => state.mailer.ready.notified().await,
+            }
+        }
+    })
+}
+
+/// Template a test message is a sample of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TemplateKind {
+    /// `Template::Verification`
+    Verification,
+    /// `Template::PasswordReset`
+    PasswordReset,
+    /// `Template::LockoutNotice`
+    LockoutNotice,
+    /// `Template::EmailChange`
+    EmailChange,
+    /// `Template::EmailChanged`
+    EmailChanged,
+}
+
+/// Request to send a sample message
+#[derive(Debug, Deserialize)]
+pub struct TestMail {
+    /// Recipient address
+    pub to: String,
+    /// Template the sample renders
+    pub template: TemplateKind,
+}
+
+impl Validate for TestMail {
+    fn validate(&self) -> Vec<String> {
+        if service::is_email(self.to.trim()) {
+            Vec::new()
+        } else {
+            vec!["to is not a valid address".to_string()]
+        }
+    }
+}
+
+/// Mail queue counts
+pub async fn mail_stats(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+) -> Json<ApiResponse<MailStats>> {
+    Json(ApiResponse::success(state.mailer.stats()))
+}
+
+/// Queue a sample of a template, to check the mail settings
+pub async fn send_test_mail(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    ValidatedJson(request): ValidatedJson<TestMail>,
+) -> Result<(StatusCode, Json<ApiResponse<MailStats>>), AppError> {
+    let username = "sample-user".to_string();
+    let template = match request.template {
+        TemplateKind::Verification => Template::Verification {
+            username,
+            token: "sample-token".to_string(),
+        },
+        TemplateKind::PasswordReset => Template::PasswordReset {
+            username,
+            token: "sample-token".to_string(),
+        },
+        TemplateKind::LockoutNotice => Template::LockoutNotice {
+            username,
+            until: chrono::Utc::now() + chrono::Duration::minutes(15),
+        },
+        TemplateKind::EmailChange => Template::EmailChange {
+            username,
+            token: "sample-token".to_string(),
+        },
+        TemplateKind::EmailChanged => Template::EmailChanged {
+            username,
+            email: "new-address@example.com".to_string(),
+        },
+    };
+    let email = Email::new(&state.config().mail, request.to.trim(), &template);
+    send(&state, email).await?;
+    Ok((StatusCode::ACCEPTED, Json(ApiResponse::success(state.mailer.stats()))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Fails the first `failures` sends
+    struct Flaky {
+        failures: u32,
+        attempts: AtomicU32,
+    }
+
+    #[async_trait]
+    impl MailTransport for Flaky {
+        async fn send(&self, _from: &str, _email: &Email) -> Result<(), String> {
+            let attempt = self.attempts.fetch_add(1, Ordering::SeqCst) + 1;
+            if attempt <= self.failures {
+                Err("connection refused".to_string())
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    #[test]
+    fn test_templates_fill_in_product_user_and_link() {
+        let config = MailConfig {
+            product_name: "Acme".to_string(),
+            link_base_url: "https://acme.test/".to_string(),
+            ..MailConfig::default()
+        };
+        let template = Template::PasswordReset {
+            username: "ann".to_string(),
+            token: "t0k".to_string(),
+        };
+        let email = Email::new(&config, "ann@acme.test", &template);
+        assert_eq!(email.subject, "Reset your Acme password");
+        assert!(email.body.starts_with("Hi ann,"));
+        assert!(email.body.contains("https://acme.test/reset-password?token=t0k"));
+        assert!(!email.body.contains('{'));
+    }
+
+    #[tokio::test]
+    async fn test_failed_sends_are_retried_then_dropped() {
+        let config = MailConfig {
+            queue_capacity: 1,
+            max_attempts: 3,
+            retry_base_ms: 0,
+            ..MailConfig::default()
+        };
+        let template = Template::Verification {
+            username: "ann".to_string(),
+            token: "t".to_string(),
+        };
+        let email = Email::new(&config, "ann@acme.test", &template);
+
+        let flaky = Arc::new(Flaky {
+            failures: 2,
+            attempts: AtomicU32::new(0),
+        });
+        let mailer = Mailer::new(&config, flaky.clone());
+        mailer.enqueue(email.clone()).unwrap();
+        assert_eq!(mailer.enqueue(email.clone()), Err(MailError::QueueFull));
+        assert_eq!(mailer.send_due().await, None);
+        assert_eq!(flaky.attempts.load(Ordering::SeqCst), 3);
+        let stats = mailer.stats();
+        assert_eq!((stats.sent, stats.retried, stats.failed, stats.refused), (1, 2, 0, 1));
+
+        let failing = Flaky {
+            failures: u32::MAX,
+            attempts: AtomicU32::new(0),
+        };
+        let mailer = Mailer::new(&config, Arc::new(failing));
+        mailer.enqueue(email).unwrap();
+        assert_eq!(mailer.send_due().await, None);
+        let stats = mailer.stats();
+        assert_eq!((stats.queued, stats.sent, stats.failed), (0, 0, 1));
+    }
+}
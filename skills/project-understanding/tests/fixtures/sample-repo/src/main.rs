@@ -3,9 +3,180 @@
 //! This module defines the primary application structure and
 //! initialization logic for the Rust-based API server.
 
+mod access_log;
+mod acme;
+mod admin;
+mod alerting;
+mod announcements;
+mod audit;
+mod auth;
+mod avatars;
+mod blobs;
+mod bloom;
+mod body_limit;
+mod breaker;
+mod cache;
+mod cancellation;
+mod catch_panic;
+mod cli;
+mod client_ip;
+mod compression;
+mod config_check;
+mod console;
+mod context;
+mod cors;
+mod debug_targets;
+mod degradation;
+mod demo;
+mod email_change;
+mod erasure;
+mod error;
+mod etag;
+mod events;
+mod examples;
+mod exports;
+mod fairness;
+mod feature_flags;
+mod formats;
+mod graphql;
+mod grpc;
+mod handlers;
+mod header_policy;
+mod health;
+mod hedge;
+mod idempotency;
+mod ids;
+mod import;
+mod ingest;
+mod integrity;
+mod jobs;
+mod kill_switch;
+mod lifecycle;
+mod limiter;
+mod listener;
+mod localization;
+mod mail;
+mod metadata;
+mod method_override;
+mod metrics;
+mod migrations;
+mod mock;
+mod openapi;
+mod operations;
+mod otel;
+mod outbox;
+mod pagination;
+mod path_policy;
+mod permissions;
+mod postman;
+mod priority;
+mod profile;
+mod postgres;
+mod rate_limit;
+mod read_only;
+mod redaction;
+mod reload;
+mod replicas;
+mod replication;
+mod repository;
+mod request_id;
+mod response_meta;
+#[cfg(test)]
+mod scenarios;
+mod scheduler;
+mod search;
+mod secret;
+mod secret_source;
+mod service;
+mod services;
+mod sharded;
+mod setup;
+mod signature;
+mod slow_requests;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod smoke;
+mod spool;
+mod sql_comment;
+mod sse;
+mod status;
+mod streaming;
+mod substate;
+mod teams;
+mod telemetry;
+#[cfg(any(test, feature = "test-support"))]
+mod test_support;
+mod tenancy;
+mod timeout;
+mod tasks;
+mod tls;
+mod unit_of_work;
+mod validation;
+mod version;
+mod versioning;
+mod warmup;
+mod webhooks;
+mod write_behind;
+mod ws;
+
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
+use arc_swap::ArcSwap;
+use futures::FutureExt;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use access_log::AccessLog;
+use alerting::Alerter;
+use announcements::AnnouncementStore;
+use audit::{AuditStore, InMemoryAuditStore};
+use blobs::BlobStore;
+use bloom::ExistenceFilter;
+use breaker::{BreakerUserRepository, Breakers};
+use cache::{CachedUserRepository, TaggedCache, UserCache, UserCacheCheck};
+use debug_targets::DebugTargets;
+use email_change::EmailChanges;
+use degradation::{DegradationCheck, Degradations};
+use events::EventBus;
+use fairness::FairScheduler;
+use feature_flags::FeatureFlags;
+use cors::Cors;
+use header_policy::HeaderPolicies;
+use health::{DatabaseCheck, HealthCheck, ReadinessProbe};
+use hedge::{HedgedUserRepository, Hedger};
+use idempotency::IdempotencyKeys;
+use ingest::ActivityIngester;
+use integrity::IntegrityStatus;
+use jobs::JobQueue;
+use kill_switch::KillSwitches;
+use lifecycle::{BackgroundTask, Lifecycle};
+use limiter::{AdaptiveLimiter, LimitedUserRepository};
+use mail::Mailer;
+use metrics::Metrics;
+use migrations::MigrationsCheck;
+use operations::Operations;
+use priority::LoadShedder;
+use rate_limit::{ClientLimiter, TokenBucket};
+use read_only::ReadOnlyMode;
+use permissions::{FieldGrant, Writable};
+use redaction::{FieldPolicy, Redact};
+use replicas::{ReplicaCheck, ReplicaSet, ReplicatedUserRepository};
+use replication::{Replication, ReplicationLagCheck};
+use repository::{InMemoryUserRepository, UserRepository};
+use scheduler::Scheduler;
+use secret::Secret;
+use services::Services;
+use setup::SetupState;
+use sharded::ShardedCounter;
+use signature::SignatureVerifier;
+use slow_requests::SlowRequestLog;
+use spool::Spool;
+use tasks::TaskSupervisor;
+use teams::TeamStore;
+use warmup::{RecentUsers, Warmup, WarmupStatus};
+use webhooks::{WebhookRegistry, WebhookSpool};
+use write_behind::LastSeenBuffer;
 
 /// Application configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -14,10 +185,250 @@ pub struct Config {
     pub host: String,
     /// Server port
     pub port: u16,
-    /// Database connection string
-    pub database_url: String,
+    /// Database connection string; redacted in logs and dumps
+    pub database_url: Secret<String>,
     /// Enable debug mode
     pub debug: bool,
+    /// Bearer token granting admin access; admin routes are disabled when unset
+    pub admin_token: Option<String>,
+    /// One-time token for first-run setup; generated and logged when unset
+    #[serde(default)]
+    pub setup_token: Option<String>,
+    /// Tag repository queries with request ID, route, and handler comments
+    #[serde(default)]
+    pub sql_comments: bool,
+    /// Base log level, e.g. `info` or `warn`
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+    /// Log line format: `pretty` or `json`
+    #[serde(default)]
+    pub log_format: telemetry::LogFormat,
+    /// Request trace sampling
+    #[serde(default)]
+    pub trace_sampling: telemetry::SamplingConfig,
+    /// How long each readiness check may run before its last-known state is reported
+    #[serde(default = "default_health_check_timeout_ms")]
+    pub health_check_timeout_ms: u64,
+    /// Publish user events to Kafka or NATS; disabled when unset
+    #[serde(default)]
+    pub event_stream: Option<streaming::StreamConfig>,
+    /// Time allowed for cache warmup before the server starts listening
+    #[serde(default = "default_warmup_budget_ms")]
+    pub warmup_budget_ms: u64,
+    /// Bind with `SO_REUSEPORT` so a replacement process can share the port
+    #[serde(default)]
+    pub reuse_port: bool,
+    /// How long to keep serving after a shutdown signal while reporting not ready
+    #[serde(default = "default_shutdown_drain_ms")]
+    pub shutdown_drain_ms: u64,
+    /// Port for the gRPC user service on `host`; disabled when unset
+    #[serde(default)]
+    pub grpc_port: Option<u16>,
+    /// Demo sandbox with sample data and periodic resets
+    #[serde(default)]
+    pub demo: demo::DemoConfig,
+    /// Serve `/openapi.json` and Swagger UI at `/docs`
+    #[serde(default = "default_api_docs")]
+    pub api_docs: bool,
+    /// Date the unversioned `/api/*` aliases go away, sent as `Sunset`
+    #[serde(default)]
+    pub legacy_api_sunset: Option<chrono::DateTime<chrono::Utc>>,
+    /// Error body shape for clients that don't send `Accept: application/problem+json`
+    #[serde(default)]
+    pub error_format: error::ErrorFormat,
+    /// Latency and error injection for `serve --mock`
+    #[serde(default)]
+    pub mock: mock::MockConfig,
+    /// Schedule and sampling for the data integrity checker
+    #[serde(default)]
+    pub integrity: integrity::IntegrityConfig,
+    /// Freshness and staleness budgets of cached route responses
+    #[serde(default)]
+    pub response_cache: cache::ResponseCacheConfig,
+    /// OTLP trace export
+    #[serde(default)]
+    pub otel: otel::OtelConfig,
+    /// Bloom filter short-circuiting lookups of users that don't exist
+    #[serde(default)]
+    pub existence_filter: bloom::ExistenceFilterConfig,
+    /// Adaptive concurrency limit on user store calls
+    #[serde(default)]
+    pub user_store_limiter: limiter::LimiterConfig,
+    /// Hedging of slow user store reads
+    #[serde(default)]
+    pub hedging: hedge::HedgeConfig,
+    /// Access log sink and buffering
+    #[serde(default)]
+    pub access_log: access_log::AccessLogConfig,
+    /// Request priority classes and load shedding
+    #[serde(default)]
+    pub priority: priority::PriorityConfig,
+    /// Per-tenant shares of request capacity
+    #[serde(default)]
+    pub fairness: fairness::FairnessConfig,
+    /// Slow request thresholds and how many offenders to keep
+    #[serde(default)]
+    pub slow_requests: slow_requests::SlowRequestConfig,
+    /// Write-behind tracking of when users were last seen
+    #[serde(default)]
+    pub last_seen: write_behind::LastSeenConfig,
+    /// Bulk copying of activity rows for analytics
+    #[serde(default)]
+    pub activity_ingest: ingest::IngestConfig,
+    /// Global request rate limit
+    #[serde(default)]
+    pub rate_limit: rate_limit::RateLimitConfig,
+    /// Disk spool for deliveries whose destination is unreachable
+    #[serde(default)]
+    pub spool: spool::SpoolConfig,
+    /// Headers stripped from and injected into requests and responses
+    #[serde(default)]
+    pub header_policy: header_policy::HeaderPolicyConfig,
+    /// Handler timeouts
+    #[serde(default)]
+    pub timeouts: timeout::TimeoutConfig,
+    /// Largest request body accepted
+    #[serde(default = "default_max_body_bytes")]
+    pub max_body_bytes: usize,
+    /// Largest request body accepted on upload routes such as user import
+    #[serde(default = "default_upload_max_body_bytes")]
+    pub upload_max_body_bytes: usize,
+    /// Compression of JSON responses
+    #[serde(default)]
+    pub compression: compression::CompressionConfig,
+    /// Origins allowed to call the API from a browser
+    #[serde(default)]
+    pub cors: cors::CorsConfig,
+    /// Routes that only accept HMAC-signed requests from partner systems
+    #[serde(default)]
+    pub signatures: signature::SignatureConfig,
+    /// Whether every envelope carries a `meta` block, not only on request
+    #[serde(default)]
+    pub response_meta: bool,
+    /// Serve HTTPS directly instead of behind a TLS-terminating proxy
+    #[serde(default)]
+    pub tls: Option<tls::TlsConfig>,
+    /// Obtain and renew HTTPS certificates over ACME; not together with `tls`
+    #[serde(default)]
+    pub acme: Option<acme::AcmeConfig>,
+    /// Listen on a Unix domain socket, instead of or as well as over TCP
+    #[serde(default)]
+    pub unix_socket: Option<listener::UnixSocketConfig>,
+    /// TCP addresses to listen on, each with TLS on or off; `host`/`port` when empty
+    #[serde(default)]
+    pub bind: Vec<listener::BindConfig>,
+    /// Environment whose defaults apply, from `APP_ENV`
+    #[serde(default)]
+    pub profile: profile::Profile,
+    /// Refresh of secrets referenced from files or Vault
+    #[serde(default)]
+    pub secrets: secret_source::SecretSourceConfig,
+    /// Answer DELETE of something already gone with 204 rather than 404, so
+    /// a retried delete succeeds like the first
+    #[serde(default)]
+    pub idempotent_deletes: bool,
+    /// Route admin POSTs with `X-HTTP-Method-Override` as the method it names
+    #[serde(default)]
+    pub method_override: bool,
+    /// Trailing-slash and case normalization of request paths
+    #[serde(default)]
+    pub path_policy: path_policy::PathPolicyConfig,
+    /// Apply pending schema migrations at startup, before anything reads users
+    #[serde(default)]
+    pub migrate_on_startup: bool,
+    /// Region ID and conflict resolution for active-active replication
+    #[serde(default)]
+    pub replication: replication::ReplicationConfig,
+    /// Size and timeouts of the Postgres connection pool
+    #[serde(default)]
+    pub db_pool: postgres::PoolConfig,
+    /// Start in read-only mode, refusing writes until an admin switches it off
+    #[serde(default)]
+    pub read_only: bool,
+    /// Read replicas serving reads of GET requests, beside the primary `database_url`
+    #[serde(default)]
+    pub read_replicas: replicas::ReplicaConfig,
+    /// Slack and PagerDuty alerts, with runbooks, when the service trips
+    #[serde(default)]
+    pub alerting: alerting::AlertConfig,
+    /// Capacity and TTLs of the user lookup cache
+    #[serde(default)]
+    pub user_cache: cache::UserCacheConfig,
+    /// How long responses to `Idempotency-Key` POSTs are replayed
+    #[serde(default)]
+    pub idempotency: idempotency::IdempotencyConfig,
+    /// Take the instance out of rotation: `/readyz` fails, `/healthz` still passes
+    #[serde(default)]
+    pub maintenance: bool,
+    /// Feature flags by name, overridable at runtime through the admin API
+    #[serde(default)]
+    pub feature_flags: std::collections::BTreeMap<String, bool>,
+    /// How requests are assigned to tenants
+    #[serde(default)]
+    pub tenancy: tenancy::TenancyConfig,
+    /// Largest a user's metadata may be, serialized as JSON
+    #[serde(default = "default_metadata_max_bytes")]
+    pub metadata_max_bytes: usize,
+    /// Limits of avatar uploads and the sizes they are resized to
+    #[serde(default)]
+    pub avatars: avatars::AvatarConfig,
+    /// Where uploaded files such as avatars are stored
+    #[serde(default)]
+    pub blobs: blobs::BlobConfig,
+    /// Outbound email: the SMTP relay, sender, and retries
+    #[serde(default)]
+    pub mail: mail::MailConfig,
+    /// Background jobs: where they are kept, workers, and retries
+    #[serde(default)]
+    pub jobs: jobs::JobConfig,
+    /// Maintenance tasks enabled and how often each runs
+    #[serde(default)]
+    pub schedule: scheduler::ScheduleConfig,
+    /// How long links confirming a new email address stay valid
+    #[serde(default)]
+    pub email_change: email_change::EmailChangeConfig,
+    /// UUID version of new user IDs
+    #[serde(default)]
+    pub id_strategy: ids::IdStrategy,
+    /// Circuit breakers in front of the user store, mail, and webhooks
+    #[serde(default)]
+    pub circuit_breaker: breaker::BreakerConfig,
+    /// Addresses and CIDR ranges of proxies whose `Forwarded` and
+    /// `X-Forwarded-For` headers name the client
+    #[serde(default)]
+    pub trusted_proxies: Vec<client_ip::TrustedProxy>,
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_health_check_timeout_ms() -> u64 {
+    1000
+}
+
+fn default_max_body_bytes() -> usize {
+    2 * 1024 * 1024
+}
+
+fn default_upload_max_body_bytes() -> usize {
+    64 * 1024 * 1024
+}
+
+fn default_metadata_max_bytes() -> usize {
+    16 * 1024
+}
+
+fn default_warmup_budget_ms() -> u64 {
+    5000
+}
+
+fn default_shutdown_drain_ms() -> u64 {
+    5000
+}
+
+fn default_api_docs() -> bool {
+    true
 }
 
 impl Default for Config {
@@ -25,61 +436,648 @@ impl Default for Config {
         Config {
             host: "127.0.0.1".to_string(),
             port: 8080,
-            database_url: "postgres://localhost/app".to_string(),
+            database_url: Secret::new("postgres://localhost/app".to_string()),
             debug: false,
+            admin_token: None,
+            setup_token: None,
+            sql_comments: false,
+            log_level: default_log_level(),
+            log_format: telemetry::LogFormat::default(),
+            trace_sampling: telemetry::SamplingConfig::default(),
+            health_check_timeout_ms: default_health_check_timeout_ms(),
+            event_stream: None,
+            warmup_budget_ms: default_warmup_budget_ms(),
+            reuse_port: false,
+            shutdown_drain_ms: default_shutdown_drain_ms(),
+            grpc_port: None,
+            demo: demo::DemoConfig::default(),
+            api_docs: default_api_docs(),
+            legacy_api_sunset: None,
+            error_format: error::ErrorFormat::default(),
+            mock: mock::MockConfig::default(),
+            integrity: integrity::IntegrityConfig::default(),
+            response_cache: cache::ResponseCacheConfig::default(),
+            otel: otel::OtelConfig::default(),
+            existence_filter: bloom::ExistenceFilterConfig::default(),
+            user_store_limiter: limiter::LimiterConfig::default(),
+            hedging: hedge::HedgeConfig::default(),
+            access_log: access_log::AccessLogConfig::default(),
+            priority: priority::PriorityConfig::default(),
+            fairness: fairness::FairnessConfig::default(),
+            slow_requests: slow_requests::SlowRequestConfig::default(),
+            last_seen: write_behind::LastSeenConfig::default(),
+            activity_ingest: ingest::IngestConfig::default(),
+            rate_limit: rate_limit::RateLimitConfig::default(),
+            spool: spool::SpoolConfig::default(),
+            header_policy: header_policy::HeaderPolicyConfig::default(),
+            timeouts: timeout::TimeoutConfig::default(),
+            max_body_bytes: default_max_body_bytes(),
+            upload_max_body_bytes: default_upload_max_body_bytes(),
+            compression: compression::CompressionConfig::default(),
+            cors: cors::CorsConfig::default(),
+            signatures: signature::SignatureConfig::default(),
+            response_meta: false,
+            tls: None,
+            acme: None,
+            unix_socket: None,
+            bind: Vec::new(),
+            profile: profile::Profile::default(),
+            secrets: secret_source::SecretSourceConfig::default(),
+            idempotent_deletes: false,
+            method_override: false,
+            path_policy: path_policy::PathPolicyConfig::default(),
+            migrate_on_startup: false,
+            replication: replication::ReplicationConfig::default(),
+            db_pool: postgres::PoolConfig::default(),
+            read_only: false,
+            read_replicas: replicas::ReplicaConfig::default(),
+            alerting: alerting::AlertConfig::default(),
+            user_cache: cache::UserCacheConfig::default(),
+            idempotency: idempotency::IdempotencyConfig::default(),
+            maintenance: false,
+            feature_flags: std::collections::BTreeMap::new(),
+            tenancy: tenancy::TenancyConfig::default(),
+            metadata_max_bytes: default_metadata_max_bytes(),
+            avatars: avatars::AvatarConfig::default(),
+            blobs: blobs::BlobConfig::default(),
+            mail: mail::MailConfig::default(),
+            jobs: jobs::JobConfig::default(),
+            schedule: scheduler::ScheduleConfig::default(),
+            email_change: email_change::EmailChangeConfig::default(),
+            id_strategy: ids::IdStrategy::default(),
+            circuit_breaker: breaker::BreakerConfig::default(),
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let command = match cli::Command::parse(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(usage) => {
+            eprintln!("{}", usage);
+            std::process::exit(2);
+        }
+    };
+    let config = match reload::load().await {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+    telemetry::init(&config);
+
+    let addr: std::net::SocketAddr = format!("{}:{}", config.host, config.port)
+        .parse()
+        .expect("invalid bind address");
+    match command {
+        cli::Command::Serve { mock: true } => mock::serve(addr, config.mock).await,
+        cli::Command::Serve { mock: false } => serve(config, addr).await,
+        cli::Command::Smoke {
+            base_url,
+            admin_token,
+        } => {
+            let reports = smoke::Smoke::new(&base_url, admin_token).run().await;
+            if !smoke::print_report(&reports) {
+                std::process::exit(1);
+            }
+        }
+        cli::Command::Migrate => migrate(&config).await,
+        cli::Command::CreateAdmin { username, email } => {
+            create_admin(config, username, email).await
+        }
+        cli::Command::CheckConfig => println!("configuration is valid"),
+    }
+}
+
+/// Connect to the configured Postgres database, exiting if it can't be reached
+async fn connect(config: &Config) -> postgres::PgUserRepository {
+    let url = config.database_url.expose();
+    match postgres::PgUserRepository::connect(url, &config.db_pool, config.sql_comments).await {
+        Ok(repository) => repository,
+        Err(err) => {
+            eprintln!("failed to connect to database: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Open the configured SQLite database, exiting if it can't be opened
+#[cfg(feature = "sqlite")]
+async fn open_sqlite(config: &Config) -> Arc<dyn UserRepository> {
+    let url = config.database_url.expose();
+    match sqlite::SqliteUserRepository::open(url, &config.db_pool).await {
+        Ok(repository) => Arc::new(repository),
+        Err(err) => {
+            eprintln!("failed to open database: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+async fn open_sqlite(_config: &Config) -> Arc<dyn UserRepository> {
+    eprintln!("sqlite: database URLs need a build with the sqlite feature");
+    std::process::exit(1);
+}
+
+/// The user store the configuration names
+async fn open_users(config: &Config) -> Arc<dyn UserRepository> {
+    let url = config.database_url.expose();
+    if config.demo.enabled || url.starts_with("memory:") {
+        Arc::new(InMemoryUserRepository::new())
+    } else if url.starts_with("sqlite:") {
+        open_sqlite(config).await
+    } else {
+        Arc::new(connect(config).await)
+    }
+}
+
+/// Bring the database schema up to date
+async fn migrate(config: &Config) {
+    match open_users(config).await.migrate().await {
+        Ok(applied) if applied.is_empty() => println!("schema is up to date"),
+        Ok(applied) => println!("applied {}", applied.join(", ")),
+        Err(err) => {
+            eprintln!("migration failed: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Create the initial admin user, prompting for what the options leave out
+async fn create_admin(config: Config, username: Option<String>, email: Option<String>) {
+    let ask = |value: Option<String>, label: &str| match value {
+        Some(value) => value,
+        None => cli::prompt(label).unwrap_or_else(|err| {
+            eprintln!("failed to read {}: {}", label, err);
+            std::process::exit(1);
+        }),
+    };
+    let username = ask(username, "username");
+    let email = ask(email, "email");
+    let users = open_users(&config).await;
+    let state = AppState::with_repository(config, users);
+    let context = audit::AuditContext {
+        actor: "cli".to_string(),
+        request_id: "create-admin".to_string(),
+        client_ip: None,
+    };
+    match service::create_user(&state, &context, tenancy::DEFAULT_TENANT, &username, &email).await {
+        Ok(user) => println!("created admin user {} ({})", user.username, user.id),
+        Err(err) => {
+            eprintln!("failed to create admin user: {}", err);
+            std::process::exit(1);
+        }
+    }
+    if state.config().admin_token.is_none() {
+        println!("admin_token is not set; admin routes stay disabled until it is, for example:");
+        println!("  \"admin_token\": \"{}\"", setup::generate_token());
+    }
+}
+
+/// Run the API server until shutdown
+async fn serve(config: Config, addr: std::net::SocketAddr) {
+    let users = open_users(&config).await;
+    let state = AppState::with_repository(config, users);
+
+    let warmup_budget = Duration::from_millis(state.config().warmup_budget_ms);
+    let mut lifecycle = Lifecycle::new();
+    lifecycle
+        // Started first so it stops last, after everything that spawns tasks
+        .register(tasks::TaskDrain::default())
+        .register(migrations::Migrations)
+        // Seed demo data before bootstrap looks for existing users
+        .register(demo::DemoSandbox::default())
+        .register(setup::Bootstrap)
+        .register(Warmup::new(warmup_budget).with_source(RecentUsers::new(1000)))
+        .register(BackgroundTask::new("webhooks", webhooks::spawn_dispatcher))
+        .register(BackgroundTask::new("webhook_spool", webhooks::spawn_spool_drainer))
+        .register(BackgroundTask::new("cache_invalidation", cache::spawn_invalidator))
+        .register(BackgroundTask::new("existence_filter", bloom::spawn_loader))
+        .register(streaming::EventStream::default())
+        .register(grpc::GrpcServer::default())
+        .register(BackgroundTask::new("integrity", integrity::spawn_checker))
+        .register(BackgroundTask::new("read_replicas", replicas::spawn_monitor))
+        .register(BackgroundTask::new("alerting", alerting::spawn_evaluator))
+        .register(BackgroundTask::new("config_reload", reload::spawn_listener))
+        .register(BackgroundTask::new("secret_refresh", secret_source::spawn_refresher))
+        .register(BackgroundTask::new("access_log", access_log::spawn_writer))
+        .register(BackgroundTask::new("mail", mail::spawn_sender))
+        .register(BackgroundTask::new("jobs", jobs::spawn_workers))
+        .register(BackgroundTask::new("scheduler", scheduler::spawn_runner))
+        .register(write_behind::LastSeenFlusher::default())
+        .register(ingest::ActivityIngestion::default())
+        // Bus subscribers first so the outbox never publishes into a gap
+        .register(
+            BackgroundTask::new("outbox", outbox::spawn_dispatcher)
+                .after(&["webhooks", "cache_invalidation", "existence_filter", "event_stream"]),
+        );
+    if let Err(err) = lifecycle.start(&state).await {
+        tracing::error!("startup failed: {}", err);
+        std::process::exit(1);
+    }
+
+    let app = handlers::create_router(state.clone());
+    let shutdown = lifecycle::shutdown_signal(state.clone()).shared();
+    let current = state.config();
+    let config = &*current;
+    let host_port = [listener::BindConfig { addr, tls: true }];
+    let binds = if config.bind.is_empty() { &host_port[..] } else { &config.bind[..] };
+    let listeners = listener::open(binds, config.reuse_port, config.unix_socket.as_ref())
+        .expect("failed to open listeners");
+    let tcp = serve_tcp(config, listeners.tcp, app.clone(), shutdown.clone());
+    let unix = async {
+        match listeners.unix {
+            Some(listener) => listener::serve_unix(listener, app.clone(), shutdown.clone()).await,
+            None => Ok(()),
         }
+    };
+    tokio::try_join!(tcp, unix).expect("server error");
+    for failure in lifecycle.stop().await {
+        tracing::error!("shutdown: {}", failure);
     }
+    otel::shutdown();
+}
+
+/// Serve `app` on `listeners`, over TLS where configured, until `shutdown` resolves
+async fn serve_tcp(
+    config: &Config,
+    listeners: Vec<listener::TcpBinding>,
+    app: axum::Router,
+    shutdown: impl std::future::Future<Output = ()> + Clone + Send + 'static,
+) -> std::io::Result<()> {
+    let app = app.into_make_service_with_connect_info::<std::net::SocketAddr>();
+    let https = config.acme.is_some() || config.tls.is_some();
+    let mut secure = Vec::new();
+    let mut plain = Vec::new();
+    for binding in listeners {
+        let addr = binding.listener.local_addr()?;
+        if https && binding.tls {
+            tracing::info!("listening on {} (HTTPS)", addr);
+            secure.push(binding.listener);
+        } else {
+            tracing::info!("listening on {}", addr);
+            plain.push(binding.listener);
+        }
+    }
+
+    let secure = async {
+        match (&config.acme, &config.tls) {
+            _ if secure.is_empty() => Ok(()),
+            (Some(acme), _) => acme::serve(secure, acme, app.clone(), shutdown.clone()).await,
+            (None, Some(tls)) => tls::serve(secure, tls, app.clone(), shutdown.clone()).await,
+            (None, None) => Ok(()),
+        }
+    };
+    let plain = futures::future::try_join_all(plain.into_iter().map(|listener| {
+        let (app, shutdown) = (app.clone(), shutdown.clone());
+        async move {
+            axum::Server::from_tcp(listener)
+                .map_err(std::io::Error::other)?
+                .serve(app)
+                .with_graceful_shutdown(shutdown)
+                .await
+                .map_err(std::io::Error::other)
+        }
+    }));
+    tokio::try_join!(secure, plain).map(|_| ())
 }
 
 /// Application state shared across handlers
 pub struct AppState {
-    /// Application configuration
-    pub config: Config,
-    /// Request counter
-    pub request_count: RwLock<u64>,
+    /// Application configuration, swapped by `reload`; read it with `config()`
+    pub config: ArcSwap<Config>,
+    /// Requests seen by `metrics::track_requests`
+    pub request_count: ShardedCounter,
+    /// User storage backend, read through `user_cache`
+    pub users: Arc<dyn UserRepository>,
+    /// Cache of users by ID
+    pub user_cache: Arc<UserCache>,
+    /// Cached user lists, tagged for event-driven invalidation
+    pub list_cache: Arc<TaggedCache<Vec<User>>>,
+    /// Bloom filter over user IDs and emails, loaded by `bloom::spawn_loader`
+    pub existence_filter: Arc<ExistenceFilter>,
+    /// Request counts and latencies served at `/metrics`
+    pub metrics: Metrics,
+    /// Adaptive concurrency limiters, one per limited dependency
+    pub limiters: Vec<Arc<AdaptiveLimiter>>,
+    /// Read hedging policy, when enabled
+    pub hedger: Option<Arc<Hedger>>,
+    /// Result of the boot-time cache warmup
+    pub warmup: WarmupStatus,
+    /// Set once shutdown has begun so readiness fails while connections drain
+    pub draining: AtomicBool,
+    /// Dependency health checks reported on the status page
+    pub health_checks: Vec<Arc<dyn HealthCheck>>,
+    /// Timeout-bounded runner for `health_checks`
+    pub readiness: ReadinessProbe,
+    /// Incident and maintenance announcements
+    pub announcements: AnnouncementStore,
+    /// Audit log of mutations
+    pub audit: Arc<dyn AuditStore>,
+    /// Domain event bus
+    pub events: EventBus,
+    /// Registered outbound webhooks
+    pub webhooks: WebhookRegistry,
+    /// Temporary per-route, per-user, and per-tenant log verbosity
+    pub debug_targets: DebugTargets,
+    /// First-run setup gate and its results
+    pub setup: SetupState,
+    /// Latest data integrity report
+    pub integrity: Arc<IntegrityStatus>,
+    /// Queue of access entries for the persistent sink
+    pub access_log: AccessLog,
+    /// Admission control by request priority
+    pub load_shedder: LoadShedder,
+    /// Per-tenant admission under `Config::fairness`
+    pub fair_scheduler: FairScheduler,
+    /// Most recent requests over their slow threshold
+    pub slow_requests: SlowRequestLog,
+    /// Last-seen times waiting to be written
+    pub last_seen: LastSeenBuffer,
+    /// Activity rows waiting to be copied
+    pub activity: ActivityIngester,
+    /// Token bucket behind `Config::rate_limit`
+    pub rate_limiter: ArcSwap<TokenBucket>,
+    /// Per-caller buckets behind `Config::rate_limit.per_client`
+    pub client_limiter: ClientLimiter,
+    /// Subsystems running in a fallback mode
+    pub degradations: Arc<Degradations>,
+    /// Webhooks that ran out of retries, under `Config::spool`
+    pub webhook_spool: Option<WebhookSpool>,
+    /// Stream events the broker rejected, under `Config::spool`
+    pub event_spool: Option<Spool<events::DomainEvent>>,
+    /// Compiled `Config::header_policy`
+    pub header_policies: HeaderPolicies,
+    /// Prepared `Config::cors`
+    pub cors: ArcSwap<Cors>,
+    /// Signatures accepted under `Config::signatures`
+    pub signatures: SignatureVerifier,
+    /// Long-running operations started on this instance
+    pub operations: Arc<Operations>,
+    /// Tasks spawned off requests and events, drained on shutdown
+    pub tasks: TaskSupervisor,
+    /// Field stamps and lag of replication between regions
+    pub replication: Arc<Replication>,
+    /// Whether writes are refused, switched by the admin API
+    pub read_only: ReadOnlyMode,
+    /// Routes switched off by admins during incidents
+    pub kill_switches: KillSwitches,
+    /// Read replicas in rotation, empty unless configured
+    pub replicas: Arc<ReplicaSet>,
+    /// Deduplicated, rate-limited alerts sent to on-call
+    pub alerts: Alerter,
+    /// Responses replayed to retried POSTs, by idempotency key
+    pub idempotency: IdempotencyKeys,
+    /// When the process started, for uptime
+    pub started: std::time::Instant,
+    /// Feature flags overridden by admins on this instance
+    pub feature_flags: FeatureFlags,
+    /// Teams of every tenant, with their members
+    pub teams: TeamStore,
+    /// Uploaded files, in the backend `Config::blobs` selects
+    pub blobs: Arc<dyn BlobStore>,
+    /// Outbound email queue, sent by `mail::spawn_sender`
+    pub mailer: Mailer,
+    /// Background jobs, run by `jobs::spawn_workers`
+    pub jobs: JobQueue,
+    /// Runs of each maintenance task, driven by `scheduler::spawn_runner`
+    pub scheduler: Scheduler,
+    /// Email address changes waiting for confirmation
+    pub email_changes: EmailChanges,
+    /// Circuit breakers, one per dependency called since startup
+    pub breakers: Arc<Breakers>,
+    /// User operations, mail, clock, and IDs, replaceable in tests
+    pub services: Services,
 }
 
 impl AppState {
     /// Create new application state
     pub fn new(config: Config) -> Arc<Self> {
+        Self::with_repository(config, Arc::new(InMemoryUserRepository::new()))
+    }
+    
+    /// Create application state backed by the given user repository
+    pub fn with_repository(config: Config, users: Arc<dyn UserRepository>) -> Arc<Self> {
+        let services = Services::from_config(&config);
+        Self::with_services(config, users, services)
+    }
+    
+    /// Create application state backed by `users`, calling `services`; every
+    /// dependency of the running service is wired here
+    pub fn with_services(
+        config: Config,
+        users: Arc<dyn UserRepository>,
+        services: Services,
+    ) -> Arc<Self> {
+        let replicas = Arc::new(ReplicaSet::from_config(&config));
+        let users: Arc<dyn UserRepository> = if replicas.is_empty() {
+            users
+        } else {
+            Arc::new(ReplicatedUserRepository::new(users, replicas.clone()))
+        };
+        // Inside the limiter so refused calls never wait for a slot
+        let breakers = Arc::new(Breakers::new(&config.circuit_breaker));
+        let users: Arc<dyn UserRepository> = match breakers.get("user_store") {
+            Some(breaker) => Arc::new(BreakerUserRepository::new(users, breaker)),
+            None => users,
+        };
+        let mut limiters = Vec::new();
+        let users: Arc<dyn UserRepository> = if config.user_store_limiter.enabled {
+            let limiter = Arc::new(AdaptiveLimiter::new(
+                "user_store",
+                config.user_store_limiter.clone(),
+            ));
+            limiters.push(limiter.clone());
+            Arc::new(LimitedUserRepository::new(users, limiter))
+        } else {
+            users
+        };
+        // Outside the limiter so each attempt of a hedged read takes its own slot
+        let hedger = config.hedging.enabled.then(|| Arc::new(Hedger::new(&config.hedging)));
+        let users: Arc<dyn UserRepository> = match &hedger {
+            Some(hedger) => Arc::new(HedgedUserRepository::new(users, hedger.clone())),
+            None => users,
+        };
+        let user_cache = Arc::new(UserCache::from_config(&config.user_cache));
+        let list_policy = config.response_cache.policy(cache::LIST_USERS_ROUTE);
+        let list_cache = Arc::new(TaggedCache::with_policy(1_000, list_policy));
+        let existence_filter = Arc::new(ExistenceFilter::new(&config.existence_filter));
+        let users: Arc<dyn UserRepository> = Arc::new(
+            CachedUserRepository::new(users, user_cache.clone(), list_cache.clone())
+                .with_existence_filter(existence_filter.clone()),
+        );
+        let degradations = Arc::new(Degradations::default());
+        let replication = Arc::new(Replication::default());
+        let jobs = JobQueue::from_config(&config.jobs, config.database_url.expose());
+        let mut health_checks: Vec<Arc<dyn HealthCheck>> = vec![
+            Arc::new(DatabaseCheck::new(users.clone())),
+            Arc::new(DegradationCheck::new(degradations.clone())),
+            Arc::new(MigrationsCheck::new(users.clone())),
+            Arc::new(UserCacheCheck::new(user_cache.clone())),
+            Arc::new(jobs.health_check()),
+        ];
+        if config.replication.region.is_some() {
+            let check = ReplicationLagCheck::new(replication.clone(), &config.replication);
+            health_checks.push(Arc::new(check));
+        }
+        if !replicas.is_empty() {
+            health_checks.push(Arc::new(ReplicaCheck::new(replicas.clone())));
+        }
+        let readiness =
+            ReadinessProbe::new(Duration::from_millis(config.health_check_timeout_ms));
+        let webhooks = WebhookRegistry::default()
+            .sandboxed(config.demo.enabled)
+            .with_breakers(breakers.clone());
+        let access_log = AccessLog::new(&config.access_log);
+        let last_seen = write_behind::last_seen_buffer(&config.last_seen);
+        let activity = ActivityIngester::new(&config.activity_ingest);
+        let rate_limiter = TokenBucket::from_config(&config.rate_limit);
+        let client_limiter = ClientLimiter::from_config(&config.rate_limit.per_client);
+        let webhook_spool = Spool::new(&config.spool, "webhooks");
+        let event_spool = Spool::new(&config.spool, "event_stream");
+        let header_policies = HeaderPolicies::new(&config.header_policy);
+        let cors = Cors::new(&config.cors);
+        let read_only = ReadOnlyMode::new(config.read_only);
+        let blobs = blobs::open(&config.blobs);
+        let mailer = Mailer::from_config(&config.mail, config.demo.enabled)
+            .with_breaker(breakers.get("mail"));
         Arc::new(Self {
-            config,
-            request_count: RwLock::new(0),
+            config: ArcSwap::from_pointee(config),
+            request_count: ShardedCounter::default(),
+            users,
+            user_cache,
+            list_cache,
+            existence_filter,
+            metrics: Metrics::default(),
+            limiters,
+            hedger,
+            warmup: WarmupStatus::default(),
+            draining: AtomicBool::new(false),
+            health_checks,
+            readiness,
+            announcements: AnnouncementStore::new(),
+            audit: Arc::new(InMemoryAuditStore::new()),
+            events: EventBus::default(),
+            webhooks,
+            debug_targets: DebugTargets::default(),
+            setup: SetupState::default(),
+            integrity: Arc::default(),
+            access_log,
+            load_shedder: LoadShedder::default(),
+            fair_scheduler: FairScheduler::default(),
+            slow_requests: SlowRequestLog::default(),
+            last_seen,
+            activity,
+            rate_limiter: ArcSwap::from_pointee(rate_limiter),
+            client_limiter,
+            degradations,
+            webhook_spool,
+            event_spool,
+            header_policies,
+            cors: ArcSwap::from_pointee(cors),
+            signatures: SignatureVerifier::default(),
+            operations: Arc::default(),
+            tasks: TaskSupervisor::default(),
+            replication,
+            read_only,
+            kill_switches: KillSwitches::default(),
+            replicas,
+            alerts: Alerter::default(),
+            idempotency: IdempotencyKeys::default(),
+            started: std::time::Instant::now(),
+            feature_flags: FeatureFlags::default(),
+            teams: TeamStore::default(),
+            blobs,
+            mailer,
+            jobs,
+            scheduler: Scheduler::default(),
+            email_changes: EmailChanges::default(),
+            breakers,
+            services,
         })
     }
     
-    /// Increment and return request count
-    pub async fn increment_counter(&self) -> u64 {
-        let mut count = self.request_count.write().await;
-        *count += 1;
-        *count
+    /// The configuration currently in effect
+    pub fn config(&self) -> Arc<Config> {
+        self.config.load_full()
+    }
+
+    /// Count a request
+    pub fn record_request(&self) {
+        self.request_count.increment();
+    }
+
+    /// Requests counted so far
+    pub fn requests_handled(&self) -> u64 {
+        self.request_count.sum()
     }
 }
 
 /// User entity
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct User {
     /// Unique identifier
     pub id: uuid::Uuid,
+    /// Tenant the user belongs to
+    #[schema(example = "default")]
+    #[serde(default = "tenancy::default_tenant")]
+    pub tenant_id: String,
     /// Username
+    #[schema(example = "alice")]
     pub username: String,
     /// Email address
+    #[schema(example = "alice@example.com")]
     pub email: String,
     /// Account creation timestamp
     pub created_at: chrono::DateTime<chrono::Utc>,
     /// Active status
     pub is_active: bool,
+    /// Soft-deletion timestamp; deleted users are hidden by default
+    #[serde(default)]
+    pub deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Attributes integrators keep on the user, as a JSON object
+    #[schema(value_type = Object)]
+    #[serde(default = "metadata::empty")]
+    pub metadata: serde_json::Value,
+}
+
+impl Redact for User {
+    const FIELDS: &'static [FieldPolicy] = &[FieldPolicy {
+        field: "email",
+        visible_to: auth::Role::Admin,
+    }];
+}
+
+impl Writable for User {
+    const GRANTS: &'static [FieldGrant] = &[
+        FieldGrant {
+            field: "is_active",
+            writable_by: Some(auth::Role::Admin),
+        },
+        FieldGrant {
+            field: "email",
+            writable_by: None,
+        },
+    ];
 }
 
 impl User {
-    /// Create a new user
+    /// Create a new user in the default tenant, with a v7 ID
     pub fn new(username: String, email: String) -> Self {
+        let created_at = chrono::Utc::now();
         User {
-            id: uuid::Uuid::new_v4(),
+            id: ids::IdStrategy::V7.id_at(created_at),
+            tenant_id: tenancy::default_tenant(),
             username,
             email,
-            created_at: chrono::Utc::now(),
+            created_at,
             is_active: true,
+            deleted_at: None,
+            metadata: metadata::empty(),
         }
     }
     
@@ -92,10 +1090,59 @@ impl User {
     pub fn activate(&mut self) {
         self.is_active = true;
     }
+    
+    /// Mark user as deleted without removing it
+    pub fn soft_delete(&mut self) {
+        self.deleted_at = Some(chrono::Utc::now());
+    }
+    
+    /// Undo a soft delete
+    pub fn restore(&mut self) {
+        self.deleted_at = None;
+    }
+    
+    /// Whether the user has been soft-deleted
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+    
+    /// Replace personal data with placeholders derived from the ID, deactivate,
+    /// and soft-delete
+    pub fn erase(&mut self) {
+        self.username = format!("erased-{}", self.id.simple());
+        self.email = format!("{}@erased.invalid", self.username);
+        self.metadata = metadata::empty();
+        self.is_active = false;
+        if self.deleted_at.is_none() {
+            self.soft_delete();
+        }
+    }
+    
+    /// Whether the user's personal data has been erased
+    pub fn is_erased(&self) -> bool {
+        self.username == format!("erased-{}", self.id.simple())
+    }
+}
+
+/// Something a request that went through wants its caller to know about
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Warning {
+    /// Stable warning code, e.g. `ROWS_REJECTED`
+    #[schema(example = "ROWS_REJECTED")]
+    pub code: String,
+    /// Human-readable explanation
+    pub message: String,
 }
 
 /// API response wrapper
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[aliases(
+    HealthResponse = ApiResponse<serde_json::Value>,
+    UserResponse = ApiResponse<User>,
+    UserListResponse = ApiResponse<Vec<User>>,
+    VersionResponse = ApiResponse<version::VersionInfo>,
+    ErrorResponse = ApiResponse<serde_json::Value>
+)]
 pub struct ApiResponse<T> {
     /// Response status
     pub success: bool,
@@ -103,6 +1150,21 @@ pub struct ApiResponse<T> {
     pub data: Option<T>,
     /// Error message if applicable
     pub error: Option<String>,
+    /// Stable error code from `error::ErrorCode`, for clients to branch on
+    pub error_code: Option<String>,
+    /// Problems that didn't stop the request; omitted when there are none
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<Warning>,
+    /// Whether only part of the request was carried out; omitted unless so
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub partial: bool,
+    /// Pagination of list endpoints, and server-side diagnostics when enabled
+    /// or asked for with `X-Include-Meta`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<response_meta::Meta>,
+    /// URLs of this page and its neighbours, for list endpoints
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub links: Option<pagination::PageLinks>,
 }
 
 impl<T> ApiResponse<T> {
@@ -112,6 +1174,23 @@ impl<T> ApiResponse<T> {
             success: true,
             data: Some(data),
             error: None,
+            error_code: None,
+            warnings: Vec::new(),
+            partial: false,
+            meta: None,
+            links: None,
+        }
+    }
+    
+    /// Create successful response holding one page of a list
+    pub fn page(data: T, page: pagination::PageMeta, links: pagination::PageLinks) -> Self {
+        ApiResponse {
+            meta: Some(response_meta::Meta {
+                page: Some(page),
+                diagnostics: None,
+            }),
+            links: Some(links),
+            ..Self::success(data)
         }
     }
     
@@ -121,8 +1200,36 @@ impl<T> ApiResponse<T> {
             success: false,
             data: None,
             error: Some(message.into()),
+            error_code: None,
+            warnings: Vec::new(),
+            partial: false,
+            meta: None,
+            links: None,
+        }
+    }
+
+    /// Create error response with a stable code
+    pub fn failure(code: error::ErrorCode, message: impl Into<String>) -> Self {
+        ApiResponse {
+            error_code: Some(code.code.to_string()),
+            ..Self::error(message)
         }
     }
+
+    /// The response with a warning attached
+    pub fn with_warning(mut self, code: impl Into<String>, message: impl Into<String>) -> Self {
+        self.warnings.push(Warning {
+            code: code.into(),
+            message: message.into(),
+        });
+        self
+    }
+
+    /// The response marked as only partly carried out
+    pub fn into_partial(mut self) -> Self {
+        self.partial = true;
+        self
+    }
 }
 
 #[cfg(test)]
@@ -143,6 +1250,15 @@ mod tests {
         assert!(!user.is_active);
     }
     
+    #[test]
+    fn test_user_soft_delete_and_restore() {
+        let mut user = User::new("test".to_string(), "test@test.com".to_string());
+        user.soft_delete();
+        assert!(user.is_deleted());
+        user.restore();
+        assert!(!user.is_deleted());
+    }
+    
     #[test]
     fn test_api_response_success() {
         let response = ApiResponse::success("data");
@@ -158,4 +1274,19 @@ mod tests {
         assert!(response.data.is_none());
         assert_eq!(response.error, Some("Something went wrong".to_string()));
     }
+
+    #[test]
+    fn test_warnings_and_partial_are_omitted_unless_set() {
+        let plain = serde_json::to_value(ApiResponse::success(1)).unwrap();
+        assert!(plain.get("warnings").is_none());
+        assert!(plain.get("partial").is_none());
+
+        let partial = ApiResponse::success(1)
+            .with_warning("ROWS_REJECTED", "1 of 2 rows was rejected")
+            .into_partial();
+        let body = serde_json::to_value(&partial).unwrap();
+        assert_eq!(body["warnings"][0]["code"], "ROWS_REJECTED");
+        assert_eq!(body["partial"], true);
+        assert!(body["success"].as_bool().unwrap());
+    }
 }
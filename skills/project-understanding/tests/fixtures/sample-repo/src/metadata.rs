@@ -0,0 +1,150 @@
+//! Integrator-defined user metadata.
+//!
+//! `User::metadata` is a JSON object integrators keep attributes of their own
+//! in; the service gives its keys no meaning. It is stored as JSONB, with
+//! top-level keys of up to 64 letters, digits, `_` or `-`, and at most
+//! `Config::metadata_max_bytes` serialized.
+//!
+//! Updates merge into it as a JSON merge patch (RFC 7386): keys sent replace
+//! the stored ones, a key sent as `null` is removed, and keys left out stay,
+//! so integrators writing keys of their own don't erase each other's. `GET
+//! /api/v1/users?metadata.plan=pro` lists the users whose `plan` is the
+//! string `pro`; every `metadata.<key>` parameter given must match.
+
+use async_trait::async_trait;
+use axum::{
+    extract::{FromRequestParts, Query},
+    http::request::Parts,
+};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+use crate::error::AppError;
+
+/// Query parameter prefix of metadata filters, as in `metadata.plan=pro`
+pub const FILTER_PREFIX: &str = "metadata.";
+
+/// Longest top-level key accepted
+const MAX_KEY_LEN: usize = 64;
+
+/// Metadata of users created without any
+pub fn empty() -> Value {
+    Value::Object(Map::new())
+}
+
+/// Why a top-level key is unusable, if it is
+fn check_key(key: &str) -> Option<String> {
+    let valid = key
+        .bytes()
+        .all(|byte| byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-');
+    if key.is_empty() || key.len() > MAX_KEY_LEN || !valid {
+        Some(format!(
+            "metadata key {:?} must be 1 to {} letters, digits, '_' or '-'",
+            key, MAX_KEY_LEN
+        ))
+    } else {
+        None
+    }
+}
+
+/// Every problem with metadata about to be stored
+pub fn check(metadata: &Value, max_bytes: usize) -> Vec<String> {
+    let Some(object) = metadata.as_object() else {
+        return vec!["metadata must be a JSON object".to_string()];
+    };
+    let mut errors: Vec<String> = object.keys().filter_map(|key| check_key(key)).collect();
+    let size = metadata.to_string().len();
+    if size > max_bytes {
+        errors.push(format!("metadata is {} bytes, over the limit of {}", size, max_bytes));
+    }
+    errors
+}
+
+/// Apply `patch` to `target` as a JSON merge patch
+pub fn merge(target: &mut Value, patch: &Value) {
+    let Value::Object(changes) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = empty();
+    }
+    if let Value::Object(fields) = target {
+        for (key, value) in changes {
+            if value.is_null() {
+                fields.remove(key);
+            } else {
+                merge(fields.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+/// Whether every filtered key of `metadata` holds the string given for it
+pub fn matches(metadata: &Value, filter: &BTreeMap<String, String>) -> bool {
+    filter
+        .iter()
+        .all(|(key, value)| metadata.get(key).and_then(Value::as_str) == Some(value.as_str()))
+}
+
+/// The object every matching user's metadata contains, for JSONB containment
+pub fn containing(filter: &BTreeMap<String, String>) -> Value {
+    let fields = filter
+        .iter()
+        .map(|(key, value)| (key.clone(), Value::from(value.as_str())))
+        .collect();
+    Value::Object(fields)
+}
+
+/// Metadata filters of a list request, from its `metadata.<key>` parameters
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetadataFilter(pub BTreeMap<String, String>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for MetadataFilter {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(pairs) = Query::<Vec<(String, String)>>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| AppError::BadRequest(rejection.body_text()))?;
+        let mut filter = BTreeMap::new();
+        for (name, value) in pairs {
+            let Some(key) = name.strip_prefix(FILTER_PREFIX) else {
+                continue;
+            };
+            if let Some(problem) = check_key(key) {
+                return Err(AppError::BadRequest(problem));
+            }
+            filter.insert(key.to_string(), value);
+        }
+        Ok(MetadataFilter(filter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_updates_merge_and_null_removes_a_key() {
+        let mut metadata = json!({"plan": "pro", "crm": {"id": 7, "owner": "ann"}});
+        merge(&mut metadata, &json!({"plan": null, "crm": {"owner": "bo"}, "seats": 5}));
+        assert_eq!(metadata, json!({"crm": {"id": 7, "owner": "bo"}, "seats": 5}));
+
+        let filter = BTreeMap::from([("crm".to_string(), "7".to_string())]);
+        assert!(!matches(&metadata, &filter));
+        let filter = BTreeMap::from([("tier".to_string(), "gold".to_string())]);
+        assert!(matches(&json!({"tier": "gold", "seats": 5}), &filter));
+        assert_eq!(containing(&filter), json!({"tier": "gold"}));
+    }
+
+    #[test]
+    fn test_check_limits_shape_keys_and_size() {
+        assert!(check(&json!({"plan": "pro"}), 64).is_empty());
+        assert_eq!(check(&json!(["plan"]), 64).len(), 1);
+        assert_eq!(check(&json!({"bad key": 1}), 64).len(), 1);
+        assert_eq!(check(&json!({"notes": "x".repeat(64)}), 64).len(), 1);
+    }
+}
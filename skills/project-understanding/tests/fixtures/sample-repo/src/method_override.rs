@@ -0,0 +1,87 @@
+//! `X-HTTP-Method-Override` for clients behind GET/POST-only proxies.
+//!
+//! With `Config::method_override`, a POST carrying
+//! `X-HTTP-Method-Override: PUT` (or `PATCH`, or `DELETE`) is routed as that
+//! method. Only admin-authenticated callers may override, so a cross-site
+//! form post can't turn into a delete; an override from anyone else, on
+//! anything but POST, or to another method is refused instead of being
+//! handled as the POST it arrived as. With the setting off the header is
+//! ignored. Routing decides which handler runs, so `override_method` wraps
+//! the router rather than being one of its layers.
+
+use axum::{
+    extract::{FromRequestParts, State},
+    http::{Method, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+
+use crate::auth::Admin;
+use crate::error::AppError;
+use crate::AppState;
+
+/// Header naming the method a POST stands for
+pub const METHOD_OVERRIDE_HEADER: &str = "x-http-method-override";
+
+/// Methods a POST may be overridden to
+const OVERRIDABLE: [Method; 3] = [Method::PUT, Method::PATCH, Method::DELETE];
+
+/// Middleware routing overridden POSTs as the method they name
+pub async fn override_method<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if !state.config().method_override || !req.headers().contains_key(METHOD_OVERRIDE_HEADER) {
+        return next.run(req).await;
+    }
+    let (mut parts, body) = req.into_parts();
+    let requested = parts
+        .headers
+        .remove(METHOD_OVERRIDE_HEADER)
+        .and_then(|value| Method::from_bytes(value.as_bytes()).ok());
+    let Some(method) = requested.filter(|method| OVERRIDABLE.contains(method)) else {
+        let message = "X-HTTP-Method-Override must be PUT, PATCH, or DELETE";
+        return AppError::BadRequest(message.to_string()).into_response();
+    };
+    if parts.method != Method::POST {
+        let message = "X-HTTP-Method-Override is only honored on POST";
+        return AppError::BadRequest(message.to_string()).into_response();
+    }
+    if let Err(err) = Admin::from_request_parts(&mut parts, &state).await {
+        return err.into_response();
+    }
+    tracing::debug!("POST {} routed as {}", parts.uri.path(), method);
+    parts.method = method;
+    next.run(Request::from_parts(parts, body)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::StatusCode, routing::delete, Router};
+    use tower::{Layer, ServiceExt};
+
+    #[tokio::test]
+    async fn test_only_admins_may_override() {
+        let state = AppState::new(crate::Config {
+            admin_token: Some("t0ken".to_string()),
+            method_override: true,
+            ..crate::Config::default()
+        });
+        let router = Router::new().route("/users/1", delete(|| async { StatusCode::NO_CONTENT }));
+        let app = axum::middleware::from_fn_with_state(state, override_method).layer(router);
+        let send = |token: Option<&str>| {
+            let mut request = Request::post("/users/1").header(METHOD_OVERRIDE_HEADER, "DELETE");
+            if let Some(token) = token {
+                request = request.header("authorization", format!("Bearer {}", token));
+            }
+            app.clone().oneshot(request.body(Body::empty()).unwrap())
+        };
+
+        assert_eq!(send(Some("t0ken")).await.unwrap().status(), StatusCode::NO_CONTENT);
+        assert_eq!(send(None).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(send(Some("wrong")).await.unwrap().status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,473 @@
+//! Prometheus metrics.
+//!
+//! `track_requests` counts every request by route and status, observes its
+//! latency into a per-route histogram, and tracks requests in flight.
+//! Every request writes these, so they are kept in `sharded` per-thread
+//! shards and only combined when scraped.
+//! Requests whose client left first never finish, so they're counted apart,
+//! by `cancellation::detect_abandonment`.
+//! `GET /metrics` renders those together with connection pool, dependency
+//! limiter, circuit breaker, load shedding, tenant fairness, read hedging, user cache, access
+//! log, last-seen, activity ingestion, and background task figures in the
+//! Prometheus text format.
+
+use axum::{
+    extract::{MatchedPath, State},
+    http::{header, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::fairness::TenantSnapshot;
+use crate::sharded::Sharded;
+use crate::AppState;
+
+/// Upper bounds of the latency histogram buckets, in seconds
+const LATENCY_BUCKETS: [f64; 11] =
+    [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Latency observations for one route
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+
+    fn merge(&mut self, other: &Histogram) {
+        for (bucket, count) in self.buckets.iter_mut().zip(other.buckets) {
+            *bucket += count;
+        }
+        self.sum += other.sum;
+        self.count += other.count;
+    }
+}
+
+/// Requests counted for one route
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RouteRequests {
+    /// HTTP method
+    pub method: String,
+    /// Matched route pattern
+    pub route: String,
+    /// Requests finished
+    pub requests: u64,
+    /// Of those, answered with a 5xx
+    pub server_errors: u64,
+    /// Requests finished by status class, such as `2xx`
+    pub statuses: BTreeMap<&'static str, u64>,
+}
+
+/// Class of a status code, such as `4xx`
+fn status_class(status: u16) -> &'static str {
+    match status / 100 {
+        1 => "1xx",
+        2 => "2xx",
+        3 => "3xx",
+        4 => "4xx",
+        _ => "5xx",
+    }
+}
+
+/// Request metrics since startup, or since the last reset
+#[derive(Debug, Default)]
+pub struct Metrics {
+    in_flight: AtomicI64,
+    requests: Sharded<BTreeMap<(String, String, u16), u64>>,
+    latency: Sharded<BTreeMap<(String, String), Histogram>>,
+    /// Abandoned requests and the seconds spent on them, by route
+    abandoned: Mutex<BTreeMap<String, (u64, f64)>>,
+}
+
+impl Metrics {
+    /// Record one finished request
+    pub fn observe(&self, method: &str, route: &str, status: u16, seconds: f64) {
+        let key = (method.to_string(), route.to_string());
+        *self.requests.local().entry((key.0.clone(), key.1.clone(), status)).or_default() += 1;
+        self.latency.local().entry(key).or_default().observe(seconds);
+    }
+
+    /// Record a request whose client left after `seconds`
+    pub fn observe_abandoned(&self, route: &str, seconds: f64) {
+        let mut abandoned = self.abandoned.lock().expect("metrics lock poisoned");
+        let (count, spent) = abandoned.entry(route.to_string()).or_default();
+        *count += 1;
+        *spent += seconds;
+    }
+
+    /// Requests being handled now
+    pub fn in_flight(&self) -> i64 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Requests, server errors, and status classes by method and route, in
+    /// route order
+    pub fn requests_by_route(&self) -> Vec<RouteRequests> {
+        let mut routes = BTreeMap::<(String, String), RouteRequests>::new();
+        self.requests.each(|shard| {
+            for ((method, route, status), count) in shard {
+                let key = (method.clone(), route.clone());
+                let entry = routes.entry(key).or_insert_with(|| RouteRequests {
+                    method: method.clone(),
+                    route: route.clone(),
+                    requests: 0,
+                    server_errors: 0,
+                    statuses: BTreeMap::new(),
+                });
+                entry.requests += count;
+                *entry.statuses.entry(status_class(*status)).or_default() += count;
+                if *status >= 500 {
+                    entry.server_errors += count;
+                }
+            }
+        });
+        routes.into_values().collect()
+    }
+
+    /// Forget every request counted so far; requests in flight still count
+    pub fn reset(&self) {
+        self.requests.reset();
+        self.latency.reset();
+        self.abandoned.lock().expect("metrics lock poisoned").clear();
+    }
+
+    /// Prometheus text exposition of the request metrics
+    fn render(&self, out: &mut String) {
+        out.push_str("# HELP http_requests_total Requests handled.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        let mut requests = BTreeMap::<_, u64>::new();
+        self.requests.each(|shard| {
+            for (key, count) in shard {
+                *requests.entry(key.clone()).or_default() += count;
+            }
+        });
+        for ((method, route, status), count) in requests {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                method,
+                escape(&route),
+                status,
+                count
+            );
+        }
+
+        out.push_str("# HELP http_request_duration_seconds Request latency.\n");
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        let mut latency = BTreeMap::<_, Histogram>::new();
+        self.latency.each(|shard| {
+            for (key, histogram) in shard {
+                latency.entry(key.clone()).or_default().merge(histogram);
+            }
+        });
+        for ((method, route), histogram) in latency {
+            let labels = format!("method=\"{}\",route=\"{}\"", method, escape(&route));
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, bound, count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, histogram.count
+            );
+            let (sum, count) = (histogram.sum, histogram.count);
+            let _ = writeln!(out, "http_request_duration_seconds_sum{{{}}} {}", labels, sum);
+            let _ = writeln!(out, "http_request_duration_seconds_count{{{}}} {}", labels, count);
+        }
+
+        out.push_str("# HELP http_requests_in_flight Requests being handled.\n");
+        out.push_str("# TYPE http_requests_in_flight gauge\n");
+        let in_flight = self.in_flight.load(Ordering::Relaxed);
+        let _ = writeln!(out, "http_requests_in_flight {}", in_flight);
+
+        let abandoned = self.abandoned.lock().expect("metrics lock poisoned");
+        out.push_str("# HELP http_requests_abandoned_total Requests whose client left first.\n");
+        out.push_str("# TYPE http_requests_abandoned_total counter\n");
+        for (route, (count, _)) in abandoned.iter() {
+            let route = escape(route);
+            let _ = writeln!(out, "http_requests_abandoned_total{{route=\"{}\"}} {}", route, count);
+        }
+        out.push_str("# HELP http_abandoned_seconds_total Time spent on abandoned requests.\n");
+        out.push_str("# TYPE http_abandoned_seconds_total counter\n");
+        for (route, (_, spent)) in abandoned.iter() {
+            let route = escape(route);
+            let _ = writeln!(out, "http_abandoned_seconds_total{{route=\"{}\"}} {}", route, spent);
+        }
+    }
+}
+
+/// Counts a request in flight until dropped, even if the client goes away
+struct InFlight<'a>(&'a AtomicI64);
+
+impl<'a> InFlight<'a> {
+    fn enter(gauge: &'a AtomicI64) -> Self {
+        gauge.fetch_add(1, Ordering::Relaxed);
+        Self(gauge)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Escape a label value
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Middleware feeding `Metrics`
+pub async fn track_requests<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = req.method().to_string();
+    let started = Instant::now();
+    state.record_request();
+
+    let in_flight = InFlight::enter(&state.metrics.in_flight);
+    let response = next.run(req).await;
+    drop(in_flight);
+    let seconds = started.elapsed().as_secs_f64();
+    state.metrics.observe(&method, &route, response.status().as_u16(), seconds);
+    response
+}
+
+/// Prometheus scrape endpoint
+pub async fn metrics(State(state): State<Arc<AppState>>) -> Response {
+    let mut out = String::new();
+    state.metrics.render(&mut out);
+
+    if let Some(pool) = state.users.pool_stats() {
+        out.push_str("# HELP db_pool_connections Database pool connections.\n");
+        out.push_str("# TYPE db_pool_connections gauge\n");
+        let _ = writeln!(out, "db_pool_connections{{state=\"idle\"}} {}", pool.idle);
+        let in_use = pool.size.saturating_sub(pool.idle);
+        let _ = writeln!(out, "db_pool_connections{{state=\"in_use\"}} {}", in_use);
+        out.push_str("# HELP db_pool_max_connections Most connections the pool may open.\n");
+        out.push_str("# TYPE db_pool_max_connections gauge\n");
+        let _ = writeln!(out, "db_pool_max_connections {}", pool.max);
+        out.push_str("# HELP db_pool_waiters Callers waiting for a database connection.\n");
+        out.push_str("# TYPE db_pool_waiters gauge\n");
+        let _ = writeln!(out, "db_pool_waiters {}", pool.waiters);
+    }
+
+    out.push_str("# HELP dependency_concurrency_limit Adaptive concurrency limit.\n");
+    out.push_str("# TYPE dependency_concurrency_limit gauge\n");
+    for limiter in &state.limiters {
+        let snapshot = limiter.snapshot();
+        let _ = writeln!(
+            out,
+            "dependency_concurrency_limit{{dependency=\"{}\"}} {}",
+            snapshot.name, snapshot.limit
+        );
+    }
+    out.push_str("# HELP dependency_calls_in_flight Calls holding a limiter slot.\n");
+    out.push_str("# TYPE dependency_calls_in_flight gauge\n");
+    for limiter in &state.limiters {
+        let snapshot = limiter.snapshot();
+        let _ = writeln!(
+            out,
+            "dependency_calls_in_flight{{dependency=\"{}\"}} {}",
+            snapshot.name, snapshot.in_flight
+        );
+    }
+
+    let breakers = state.breakers.snapshots();
+    out.push_str("# HELP circuit_breaker_state Breaker state: 0 closed, 1 half-open, 2 open.\n");
+    out.push_str("# TYPE circuit_breaker_state gauge\n");
+    for breaker in &breakers {
+        let state = breaker.state.gauge();
+        let _ = writeln!(out, "circuit_breaker_state{{dependency=\"{}\"}} {}", breaker.name, state);
+    }
+    out.push_str("# HELP circuit_breaker_trips_total Times a breaker opened.\n");
+    out.push_str("# TYPE circuit_breaker_trips_total counter\n");
+    for breaker in &breakers {
+        let (name, trips) = (&breaker.name, breaker.trips);
+        let _ = writeln!(out, "circuit_breaker_trips_total{{dependency=\"{}\"}} {}", name, trips);
+    }
+    out.push_str("# HELP circuit_breaker_rejections_total Calls refused by an open breaker.\n");
+    out.push_str("# TYPE circuit_breaker_rejections_total counter\n");
+    for breaker in &breakers {
+        let _ = writeln!(
+            out,
+            "circuit_breaker_rejections_total{{dependency=\"{}\"}} {}",
+            breaker.name, breaker.rejected
+        );
+    }
+
+    if let Some(hedger) = &state.hedger {
+        let stats = hedger.stats();
+        out.push_str("# HELP user_store_hedges_total Slow reads by hedging outcome.\n");
+        out.push_str("# TYPE user_store_hedges_total counter\n");
+        for (outcome, count) in [
+            ("won", stats.won),
+            ("lost", stats.sent - stats.won),
+            ("over_budget", stats.over_budget),
+        ] {
+            let _ = writeln!(out, "user_store_hedges_total{{outcome=\"{}\"}} {}", outcome, count);
+        }
+    }
+
+    let cache = state.user_cache.stats();
+    out.push_str("# HELP user_cache_lookups_total User lookups by how they were answered.\n");
+    out.push_str("# TYPE user_cache_lookups_total counter\n");
+    for (result, count) in [
+        ("hit", cache.hits),
+        ("negative_hit", cache.negative_hits),
+        ("filtered", cache.filtered),
+        ("miss", cache.misses),
+    ] {
+        let _ = writeln!(out, "user_cache_lookups_total{{result=\"{}\"}} {}", result, count);
+    }
+
+    out.push_str("# HELP http_requests_shed_total Requests refused by load shedding.\n");
+    out.push_str("# TYPE http_requests_shed_total counter\n");
+    for (priority, count) in state.load_shedder.shed_counts() {
+        let _ = writeln!(
+            out,
+            "http_requests_shed_total{{priority=\"{}\"}} {}",
+            priority.as_str(),
+            count
+        );
+    }
+
+    if state.config().fairness.enabled {
+        let tenants = state.fair_scheduler.snapshot(&state.config().fairness);
+        let gauges: [(&str, &str, fn(&TenantSnapshot) -> usize); 3] = [
+            ("tenant_requests_in_flight", "Requests in flight by tenant.", |t| t.in_flight),
+            ("tenant_request_quota", "Requests a tenant may have in flight.", |t| t.quota),
+            ("tenant_requests_waiting", "Requests waiting for quota by tenant.", |t| t.waiting),
+        ];
+        for (name, help, value) in gauges {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} gauge", name, help, name);
+            for tenant in &tenants {
+                let label = escape(&tenant.tenant);
+                let _ = writeln!(out, "{}{{tenant=\"{}\"}} {}", name, label, value(tenant));
+            }
+        }
+        out.push_str("# HELP tenant_requests_total Requests by tenant and admission outcome.\n");
+        out.push_str("# TYPE tenant_requests_total counter\n");
+        for tenant in &tenants {
+            let name = escape(&tenant.tenant);
+            let outcomes = [("admitted", tenant.admitted), ("throttled", tenant.throttled)];
+            for (outcome, count) in outcomes {
+                let _ = writeln!(
+                    out,
+                    "tenant_requests_total{{tenant=\"{}\",outcome=\"{}\"}} {}",
+                    name, outcome, count
+                );
+            }
+        }
+    }
+
+    out.push_str("# HELP access_log_dropped_total Access entries never persisted.\n");
+    out.push_str("# TYPE access_log_dropped_total counter\n");
+    let _ = writeln!(out, "access_log_dropped_total {}", state.access_log.dropped());
+
+    out.push_str("# HELP last_seen_dropped_total Last-seen updates over the pending limit.\n");
+    out.push_str("# TYPE last_seen_dropped_total counter\n");
+    let _ = writeln!(out, "last_seen_dropped_total {}", state.last_seen.dropped());
+
+    let activity = state.activity.stats();
+    out.push_str("# HELP activity_rows_total Activity rows by where they went.\n");
+    out.push_str("# TYPE activity_rows_total counter\n");
+    for (outcome, count) in [
+        ("spilled", activity.spilled),
+        ("rejected", activity.rejected),
+        ("copied", activity.copied),
+    ] {
+        let _ = writeln!(out, "activity_rows_total{{outcome=\"{}\"}} {}", outcome, count);
+    }
+    out.push_str("# HELP activity_rows_buffered Activity rows held in memory.\n");
+    out.push_str("# TYPE activity_rows_buffered gauge\n");
+    let _ = writeln!(out, "activity_rows_buffered {}", activity.buffered);
+
+    let tasks = state.tasks.snapshot();
+    out.push_str("# HELP background_tasks_running Supervised tasks still running.\n");
+    out.push_str("# TYPE background_tasks_running gauge\n");
+    for (task, counts) in &tasks {
+        let _ = writeln!(out, "background_tasks_running{{task=\"{}\"}} {}", task, counts.running);
+    }
+    out.push_str("# HELP background_tasks_total Finished supervised tasks by outcome.\n");
+    out.push_str("# TYPE background_tasks_total counter\n");
+    for (task, counts) in &tasks {
+        for (outcome, count) in [("completed", counts.completed), ("panicked", counts.panicked)] {
+            let _ = writeln!(
+                out,
+                "background_tasks_total{{task=\"{}\",outcome=\"{}\"}} {}",
+                task, outcome, count
+            );
+        }
+    }
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], out).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative() {
+        let metrics = Metrics::default();
+        metrics.observe("GET", "/api/v1/users/:id", 200, 0.02);
+        metrics.observe("GET", "/api/v1/users/:id", 404, 3.0);
+        let mut out = String::new();
+        metrics.render(&mut out);
+
+        let labels = "method=\"GET\",route=\"/api/v1/users/:id\"";
+        let bucket = |le: &str, count: u64| {
+            format!("http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}", labels, le, count)
+        };
+        assert!(out.contains(&bucket("0.01", 0)));
+        assert!(out.contains(&bucket("0.025", 1)));
+        assert!(out.contains(&bucket("+Inf", 2)));
+        assert!(out.contains(&format!("http_requests_total{{{},status=\"404\"}} 1", labels)));
+        assert!(out.contains("http_requests_in_flight 0"));
+    }
+
+    #[test]
+    fn test_routes_break_down_by_status_class() {
+        let metrics = Metrics::default();
+        for status in [200, 201, 404, 503] {
+            metrics.observe("POST", "/api/v1/users", status, 0.01);
+        }
+        metrics.observe("GET", "/api/v1/users", 200, 0.01);
+
+        let routes = metrics.requests_by_route();
+        assert_eq!(routes.len(), 2);
+        let post = &routes[1];
+        assert_eq!((post.method.as_str(), post.requests, post.server_errors), ("POST", 4, 1));
+        let statuses: Vec<_> = post.statuses.iter().map(|(class, n)| (*class, *n)).collect();
+        assert_eq!(statuses, [("2xx", 2), ("4xx", 1), ("5xx", 1)]);
+    }
+}
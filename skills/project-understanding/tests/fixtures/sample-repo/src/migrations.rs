@@ -0,0 +1,162 @@
+//! Versioned schema migrations embedded in the binary.
+//!
+//! `MIGRATIONS` lists every schema change in the order it applies. A
+//! backend with a schema records the versions it has applied and applies
+//! the rest on `UserRepository::migrate`; Postgres keeps them in
+//! `schema_migrations` and applies them in one transaction under an
+//! advisory lock, so concurrent runs apply each migration once and a failed
+//! one leaves the schema as it was. The first migration only creates what is
+//! missing, so databases set up by hand before migrations existed adopt it
+//! as they are.
+//!
+//! The same statements migrate SQLite, so write them in the SQL both accept.
+//! A migration only Postgres has a use for, such as an index built on one of
+//! its extensions, is marked `postgres_only`; SQLite records its version
+//! without running it, so both report the same schema version.
+//!
+//! Migrations run on `app migrate`, and at startup when
+//! `Config::migrate_on_startup` is set, before anything reads users. `GET
+//! /readyz` reports the latest version applied as `schema_version`, and
+//! `MigrationsCheck` keeps the instance unready until the last one this
+//! binary knows is applied.
+
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::health::{HealthCheck, HealthState};
+use crate::lifecycle::Component;
+use crate::repository::UserRepository;
+use crate::AppState;
+
+/// One schema change
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    /// Sortable version, recorded once applied
+    pub version: &'static str,
+    /// Statements applying it, separated by semicolons
+    pub sql: &'static str,
+    /// Whether only Postgres runs the statements
+    pub postgres_only: bool,
+}
+
+/// Schema changes in the order they apply; append new ones, never edit a released entry
+pub const MIGRATIONS: [Migration; 4] = [
+    Migration {
+        version: "0001_users",
+        sql: "CREATE TABLE IF NOT EXISTS users (id uuid PRIMARY KEY, \
+              username text NOT NULL CONSTRAINT username UNIQUE, email text NOT NULL, \
+              created_at timestamptz NOT NULL, is_active boolean NOT NULL, \
+              deleted_at timestamptz); \
+              CREATE UNIQUE INDEX IF NOT EXISTS email ON users (lower(email)); \
+              CREATE TABLE IF NOT EXISTS outbox (id uuid PRIMARY KEY, payload jsonb NOT NULL, \
+              created_at timestamptz NOT NULL, delivered_at timestamptz); \
+              CREATE INDEX IF NOT EXISTS outbox_pending ON outbox (created_at) \
+              WHERE delivered_at IS NULL; \
+              CREATE TABLE IF NOT EXISTS user_activity (user_id uuid PRIMARY KEY, \
+              last_seen_at timestamptz NOT NULL)",
+        postgres_only: false,
+    },
+    // Neither database drops a column's unique constraint the same way, so
+    // the table is rebuilt with usernames and emails unique per tenant; the
+    // column default keeps inserts from binaries that predate it working
+    Migration {
+        version: "0002_tenants",
+        sql: "CREATE TABLE users_by_tenant (id uuid CONSTRAINT id PRIMARY KEY, \
+              tenant_id text NOT NULL DEFAULT 'default', username text NOT NULL, \
+              email text NOT NULL, created_at timestamptz NOT NULL, \
+              is_active boolean NOT NULL, deleted_at timestamptz); \
+              INSERT INTO users_by_tenant \
+              SELECT id, 'default', username, email, created_at, is_active, deleted_at \
+              FROM users; \
+              DROP TABLE users; \
+              ALTER TABLE users_by_tenant RENAME TO users; \
+              CREATE UNIQUE INDEX username ON users (tenant_id, username); \
+              CREATE UNIQUE INDEX email ON users (tenant_id, lower(email)); \
+              CREATE INDEX users_tenant_created ON users (tenant_id, created_at)",
+        postgres_only: false,
+    },
+    Migration {
+        version: "0003_user_metadata",
+        sql: "ALTER TABLE users ADD COLUMN metadata jsonb NOT NULL DEFAULT '{}'",
+        postgres_only: false,
+    },
+    // Trigram indexes behind `search`; creating the extension needs a role
+    // allowed to, once per database
+    Migration {
+        version: "0004_user_search",
+        sql: "CREATE EXTENSION IF NOT EXISTS pg_trgm; \
+              CREATE INDEX users_username_trgm ON users USING gin (username gin_trgm_ops); \
+              CREATE INDEX users_email_trgm ON users USING gin (email gin_trgm_ops)",
+        postgres_only: true,
+    },
+];
+
+/// Lifecycle component migrating at startup when configured to
+pub struct Migrations;
+
+#[async_trait]
+impl Component for Migrations {
+    fn name(&self) -> &'static str {
+        "migrations"
+    }
+
+    fn timeout(&self) -> Duration {
+        // Rewriting a large table takes longer than starting a subsystem
+        Duration::from_secs(300)
+    }
+
+    async fn start(&self, state: &Arc<AppState>) -> Result<(), String> {
+        if !state.config().migrate_on_startup {
+            return Ok(());
+        }
+        let applied = state.users.migrate().await.map_err(|err| err.to_string())?;
+        if !applied.is_empty() {
+            tracing::info!("applied migrations {}", applied.join(", "));
+        }
+        Ok(())
+    }
+}
+
+/// Health check failing until the schema has every migration applied
+pub struct MigrationsCheck {
+    users: Arc<dyn UserRepository>,
+}
+
+impl MigrationsCheck {
+    /// Create a check for the given repository
+    pub fn new(users: Arc<dyn UserRepository>) -> Self {
+        Self { users }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for MigrationsCheck {
+    fn name(&self) -> &'static str {
+        "migrations"
+    }
+
+    async fn check(&self) -> Result<HealthState, String> {
+        if !self.users.has_schema() {
+            return Ok(HealthState::Operational);
+        }
+        let latest = MIGRATIONS[MIGRATIONS.len() - 1].version;
+        match self.users.schema_version().await.map_err(|err| err.to_string())? {
+            // A newer binary mid-rollout may have migrated further; migrations only add
+            Some(version) if version.as_str() >= latest => Ok(HealthState::Operational),
+            Some(version) => Err(format!("schema is at {}, expected {}", version, latest)),
+            None => Err(format!("no migrations applied, expected {}", latest)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_versions_are_unique_and_in_order() {
+        let versions: Vec<_> = MIGRATIONS.iter().map(|migration| migration.version).collect();
+        assert!(versions.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", versions);
+    }
+}
@@ -0,0 +1,197 @@
+//! Mock server generated from the OpenAPI spec.
+//!
+//! `app serve --mock` answers every documented route with an example built
+//! from its response schema, without touching storage. A request can pick
+//! any documented status with the `x-mock-status` header; otherwise
+//! `Config::mock` adds fixed latency and fails a fraction of requests with
+//! a 500 so clients can exercise their error paths.
+
+use axum::{
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+    routing::{on, MethodFilter, MethodRouter},
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::examples::{example_for, spec};
+use crate::{lifecycle, openapi, ApiResponse};
+
+/// Header selecting which documented response to return
+pub const STATUS_HEADER: &str = "x-mock-status";
+
+/// Mock server behavior
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MockConfig {
+    /// Delay added to every response
+    pub latency_ms: u64,
+    /// Fraction of requests, from 0.0 to 1.0, answered with an injected 500
+    pub error_rate: f64,
+}
+
+impl Default for MockConfig {
+    fn default() -> Self {
+        MockConfig {
+            latency_ms: 0,
+            error_rate: 0.0,
+        }
+    }
+}
+
+/// Documented responses of one operation, by status
+#[derive(Debug, Default)]
+struct MockOperation {
+    responses: BTreeMap<u16, Option<Value>>,
+}
+
+impl MockOperation {
+    /// Response for a request, honoring `x-mock-status` and error injection
+    fn respond(&self, headers: &HeaderMap, config: &MockConfig) -> Response {
+        let requested = headers
+            .get(STATUS_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u16>().ok());
+        let status = match requested {
+            Some(status) if self.responses.contains_key(&status) => status,
+            Some(status) => {
+                let message = format!("{} {} is not documented here", STATUS_HEADER, status);
+                return (StatusCode::BAD_REQUEST, Json(ApiResponse::<()>::error(message)))
+                    .into_response();
+            }
+            None if inject_error(config.error_rate) => {
+                let body = ApiResponse::<()>::error("injected failure");
+                return (StatusCode::INTERNAL_SERVER_ERROR, Json(body)).into_response();
+            }
+            None => self
+                .responses
+                .keys()
+                .copied()
+                .find(|status| (200..300).contains(status))
+                .unwrap_or(200),
+        };
+
+        let code = StatusCode::from_u16(status).unwrap_or(StatusCode::OK);
+        match self.responses.get(&status).cloned().flatten() {
+            Some(body) => (code, Json(body)).into_response(),
+            None if code.is_success() => code.into_response(),
+            None => (code, Json(ApiResponse::<()>::error(code.to_string()))).into_response(),
+        }
+    }
+}
+
+/// Whether to fail this request, with probability `rate`
+fn inject_error(rate: f64) -> bool {
+    if rate <= 0.0 {
+        return false;
+    }
+    let roll = uuid::Uuid::new_v4().as_u128() as f64 / u128::MAX as f64;
+    roll < rate
+}
+
+fn method_filter(method: &str) -> Option<MethodFilter> {
+    match method {
+        "get" => Some(MethodFilter::GET),
+        "post" => Some(MethodFilter::POST),
+        "put" => Some(MethodFilter::PUT),
+        "patch" => Some(MethodFilter::PATCH),
+        "delete" => Some(MethodFilter::DELETE),
+        _ => None,
+    }
+}
+
+/// Router answering every documented route
+pub fn router(config: MockConfig) -> Router {
+    let spec = spec();
+    let config = Arc::new(config);
+    let mut router = Router::new();
+    let Some(paths) = spec.get("paths").and_then(Value::as_object) else {
+        return router;
+    };
+
+    for (path, item) in paths {
+        let Some(methods) = item.as_object() else {
+            continue;
+        };
+        let mut method_router: Option<MethodRouter> = None;
+        for (method, operation) in methods {
+            let Some(filter) = method_filter(method) else {
+                continue;
+            };
+            let mut mock = MockOperation::default();
+            if let Some(responses) = operation.get("responses").and_then(Value::as_object) {
+                for (status, response) in responses {
+                    let Ok(status) = status.parse::<u16>() else {
+                        continue;
+                    };
+                    let body = response
+                        .pointer("/content/application~1json/schema")
+                        .map(|schema| example_for(schema, &spec));
+                    mock.responses.insert(status, body);
+                }
+            }
+
+            let mock = Arc::new(mock);
+            let config = config.clone();
+            let handler = move |headers: HeaderMap| async move {
+                tokio::time::sleep(Duration::from_millis(config.latency_ms)).await;
+                mock.respond(&headers, &config)
+            };
+            method_router = Some(match method_router {
+                Some(existing) => existing.on(filter, handler),
+                None => on(filter, handler),
+            });
+        }
+        if let Some(method_router) = method_router {
+            let pattern = path.replace('{', ":").replace('}', "");
+            router = router.route(&pattern, method_router);
+        }
+    }
+    router.merge(openapi::docs())
+}
+
+/// Serve the mock until SIGINT or SIGTERM
+pub async fn serve(addr: SocketAddr, config: MockConfig) {
+    tracing::warn!("serving mock responses on {}; no data is stored", addr);
+    axum::Server::bind(&addr)
+        .serve(router(config).into_make_service())
+        .with_graceful_shutdown(lifecycle::termination())
+        .await
+        .expect("mock server error");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operation() -> MockOperation {
+        let mut mock = MockOperation::default();
+        mock.responses.insert(200, Some(serde_json::json!({ "success": true })));
+        mock.responses.insert(404, None);
+        mock
+    }
+
+    #[test]
+    fn test_status_header_and_error_injection() {
+        let config = MockConfig::default();
+        assert_eq!(operation().respond(&HeaderMap::new(), &config).status(), StatusCode::OK);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(STATUS_HEADER, "404".parse().unwrap());
+        assert_eq!(operation().respond(&headers, &config).status(), StatusCode::NOT_FOUND);
+        headers.insert(STATUS_HEADER, "418".parse().unwrap());
+        assert_eq!(operation().respond(&headers, &config).status(), StatusCode::BAD_REQUEST);
+
+        let failing = MockConfig {
+            error_rate: 1.0,
+            ..MockConfig::default()
+        };
+        let response = operation().respond(&HeaderMap::new(), &failing);
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
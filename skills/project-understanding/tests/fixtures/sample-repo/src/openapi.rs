@@ -0,0 +1,196 @@
+//! Generated OpenAPI spec and Swagger UI.
+//!
+//! The spec is derived from the `#[utoipa::path]` annotations on handlers,
+//! so a new endpoint is documented by annotating it and listing it in
+//! `ApiDoc`. Served at `GET /openapi.json`, with Swagger UI at `/docs`,
+//! unless `Config::api_docs` is off.
+//!
+//! Error responses come from `handlers::ROUTES` rather than the annotations:
+//! every operation lists each error its route can answer with, with an
+//! example body carrying the stable `error_code`, and the codes it may carry
+//! in its description. Responses an annotation already describes keep its
+//! description ahead of the codes. The `ErrorCode` schema lists the whole
+//! registry.
+
+use axum::Router;
+use serde_json::json;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::{
+    ContentBuilder, ObjectBuilder, Ref, RefOr, ResponseBuilder, Schema, SchemaType,
+};
+use utoipa::{Modify, OpenApi};
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::error::ErrorCode;
+use crate::handlers::{CreateUser, ROUTES};
+use crate::pagination::{PageLinks, PageMeta};
+use crate::response_meta::{Meta, ResponseMeta};
+use crate::service::UserPatch;
+use crate::version::{self, VersionInfo};
+use crate::{
+    handlers, ErrorResponse, HealthResponse, User, UserListResponse, UserResponse,
+    VersionResponse, Warning,
+};
+
+/// Registers the admin bearer token scheme referenced by admin endpoints
+struct AdminToken;
+
+impl Modify for AdminToken {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "admin_token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+/// Documents the errors of each operation from its `handlers::ROUTES` entry
+struct ErrorCatalog;
+
+impl Modify for ErrorCatalog {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let codes = ObjectBuilder::new()
+            .schema_type(SchemaType::String)
+            .description(Some("Stable error code sent as `error_code`"))
+            .enum_values(Some(ErrorCode::ALL.iter().map(|error| error.code)))
+            .build();
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.schemas.insert("ErrorCode".to_string(), RefOr::T(Schema::Object(codes)));
+
+        for (path, item) in openapi.paths.paths.iter_mut() {
+            let pattern = path.replace('{', ":").replace('}', "");
+            for (method, operation) in item.operations.iter_mut() {
+                let method = serde_json::to_value(method)
+                    .ok()
+                    .and_then(|method| method.as_str().map(str::to_uppercase));
+                let Some(route) = ROUTES
+                    .iter()
+                    .find(|route| Some(route.method) == method.as_deref() && route.path == pattern)
+                else {
+                    continue;
+                };
+                let errors = route.possible_errors();
+                let mut statuses: Vec<_> = errors.iter().map(|error| error.status).collect();
+                statuses.dedup();
+                for status in statuses {
+                    let group: Vec<ErrorCode> =
+                        errors.iter().copied().filter(|error| error.status == status).collect();
+                    // Routes list their specific codes after the generic ones
+                    let example = *group.last().expect("status came from a listed error");
+                    let response = operation
+                        .responses
+                        .responses
+                        .entry(status.as_u16().to_string())
+                        .or_insert_with(|| {
+                            ResponseBuilder::new().description(example.title).build().into()
+                        });
+                    if let RefOr::T(response) = response {
+                        let codes: Vec<_> = group.iter().map(|error| error.code).collect();
+                        let codes = codes.join(" or ");
+                        response.description =
+                            format!("{}; `error_code` {}", response.description, codes);
+                        response
+                            .content
+                            .entry("application/json".to_string())
+                            .or_insert_with(|| error_content(example));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The envelope sent with `error`, as an example
+fn error_content(error: ErrorCode) -> utoipa::openapi::Content {
+    let example = json!({
+        "success": false,
+        "data": null,
+        "error": error.title.to_lowercase(),
+        "error_code": error.code,
+    });
+    ContentBuilder::new()
+        .schema(Ref::from_schema_name("ErrorResponse"))
+        .example(Some(example))
+        .build()
+}
+
+/// The API description
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "sample-repo", description = "User management API"),
+    paths(
+        handlers::health_check,
+        version::version,
+        handlers::list_users,
+        handlers::create_user,
+        handlers::get_user,
+        handlers::update_user,
+        handlers::patch_user,
+        handlers::delete_user,
+        handlers::restore_user,
+    ),
+    components(schemas(
+        User,
+        CreateUser,
+        UserPatch,
+        Warning,
+        Meta,
+        PageMeta,
+        PageLinks,
+        ResponseMeta,
+        HealthResponse,
+        VersionInfo,
+        VersionResponse,
+        UserResponse,
+        UserListResponse,
+        ErrorResponse
+    )),
+    modifiers(&AdminToken, &ErrorCatalog),
+    tags(
+        (name = "health", description = "Liveness"),
+        (name = "users", description = "User accounts"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Routes serving the spec and Swagger UI
+pub fn docs<S: Clone + Send + Sync + 'static>() -> Router<S> {
+    SwaggerUi::new("/docs")
+        .url("/openapi.json", ApiDoc::openapi())
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spec_covers_user_routes() {
+        let spec = ApiDoc::openapi();
+        let paths = ["/api/v1/users", "/api/v1/users/{id}", "/api/v1/users/{id}/restore"];
+        for path in paths {
+            assert!(spec.paths.paths.contains_key(path), "missing {}", path);
+        }
+        let components = spec.components.unwrap();
+        assert!(components.security_schemes.contains_key("admin_token"));
+    }
+
+    #[test]
+    fn test_operations_list_their_error_codes() {
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let responses = &spec["paths"]["/api/v1/users/{id}"]["get"]["responses"];
+        let example = |status: &str| &responses[status]["content"]["application/json"]["example"];
+        assert_eq!(example("404")["error_code"], "USER_NOT_FOUND");
+        assert_eq!(example("429")["error_code"], "RATE_LIMITED");
+        // Annotated responses keep their description
+        assert_eq!(responses["404"]["description"], "No such user; `error_code` USER_NOT_FOUND");
+
+        let restore = &spec["paths"]["/api/v1/users/{id}/restore"]["post"]["responses"];
+        let example = &restore["401"]["content"]["application/json"]["example"];
+        assert_eq!(example["error_code"], "UNAUTHORIZED");
+        assert!(spec["paths"]["/api/v1/users"]["post"]["responses"].get("404").is_none());
+        let catalog = spec["components"]["schemas"]["ErrorCode"]["enum"].as_array().unwrap();
+        assert_eq!(catalog.len(), ErrorCode::ALL.len());
+    }
+}
@@ -0,0 +1,251 @@
+//! Long-running operations.
+//!
+//! Work too slow to finish within a request runs as an operation instead.
+//! Clients opt in per request with `Prefer: respond-async` (RFC 7240); the
+//! endpoint then answers 202 Accepted with the operation in the body and its
+//! URL in `Location`, and `GET /api/v1/operations/:id` reports its status,
+//! progress and, once it finishes, the result or error. Without the header
+//! the endpoint works synchronously as before. So far user imports support
+//! it.
+//!
+//! Operations run as tasks on the instance that accepted them and are kept
+//! in memory for an hour after finishing, so they don't survive a restart
+//! and other instances can't report on them. A durable job queue would lift
+//! both limits; this tree doesn't have one yet.
+
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::error::{AppError, ErrorCode};
+use crate::substate::Shared;
+use crate::validation::ApiPath;
+use crate::versioning::V1_PREFIX;
+use crate::{ApiResponse, AppState};
+
+/// How long a finished operation stays available, in seconds
+const RETENTION_SECONDS: i64 = 3600;
+
+/// Where an operation is in its life
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationStatus {
+    /// Still working
+    Running,
+    /// Finished; `result` holds the outcome
+    Succeeded,
+    /// Gave up; `error` says why
+    Failed,
+}
+
+/// How much of an operation is done
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Progress {
+    /// Items processed so far
+    pub done: usize,
+    /// Items to process in all
+    pub total: usize,
+}
+
+/// A unit of work running in the background
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Operation {
+    /// Unique identifier
+    pub id: Uuid,
+    /// What the operation does, e.g. `user_import`
+    pub kind: String,
+    /// Where the operation is in its life
+    pub status: OperationStatus,
+    /// How much is done, once the operation knows its size
+    pub progress: Option<Progress>,
+    /// Outcome, once succeeded; shaped like the synchronous response's `data`
+    pub result: Option<serde_json::Value>,
+    /// Why the operation failed, if it did
+    pub error: Option<String>,
+    /// When the operation was accepted
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    /// When the operation succeeded or failed
+    pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl Operation {
+    /// Where clients poll the operation
+    pub fn url(&self) -> String {
+        format!("{}/operations/{}", V1_PREFIX, self.id)
+    }
+}
+
+/// Registry of this instance's operations
+#[derive(Debug, Default)]
+pub struct Operations {
+    operations: Mutex<HashMap<Uuid, Operation>>,
+}
+
+impl Operations {
+    /// Current state of an operation
+    pub fn get(&self, id: Uuid) -> Option<Operation> {
+        let operations = self.operations.lock().expect("operations lock poisoned");
+        operations.get(&id).cloned()
+    }
+
+    fn update(&self, id: Uuid, change: impl FnOnce(&mut Operation)) {
+        let mut operations = self.operations.lock().expect("operations lock poisoned");
+        if let Some(operation) = operations.get_mut(&id) {
+            change(operation);
+        }
+    }
+
+    fn insert(&self, operation: Operation) {
+        let cutoff = chrono::Utc::now() - chrono::Duration::seconds(RETENTION_SECONDS);
+        let mut operations = self.operations.lock().expect("operations lock poisoned");
+        operations.retain(|_, operation| operation.finished_at.map_or(true, |at| at > cutoff));
+        operations.insert(operation.id, operation);
+    }
+}
+
+/// Lets running work report how far it got
+#[derive(Clone)]
+pub struct ProgressReporter {
+    state: Arc<AppState>,
+    id: Uuid,
+}
+
+impl ProgressReporter {
+    /// Record that `done` of `total` items are processed
+    pub fn report(&self, done: usize, total: usize) {
+        self.state
+            .operations
+            .update(self.id, |operation| operation.progress = Some(Progress { done, total }));
+    }
+}
+
+/// Run `work` in the background as a new operation, returning it as accepted
+pub fn spawn<F, Fut>(state: &Arc<AppState>, kind: &str, work: F) -> Operation
+where
+    F: FnOnce(ProgressReporter) -> Fut,
+    Fut: Future<Output = Result<serde_json::Value, String>> + Send + 'static,
+{
+    let operation = Operation {
+        id: Uuid::new_v4(),
+        kind: kind.to_string(),
+        status: OperationStatus::Running,
+        progress: None,
+        result: None,
+        error: None,
+        created_at: chrono::Utc::now(),
+        finished_at: None,
+    };
+    state.operations.insert(operation.clone());
+    let id = operation.id;
+    let running = work(ProgressReporter { state: state.clone(), id });
+    state.tasks.spawn("operation", {
+        let state = state.clone();
+        async move {
+            let outcome = running.await;
+            state.operations.update(id, |operation| {
+                match outcome {
+                    Ok(result) => {
+                        operation.status = OperationStatus::Succeeded;
+                        operation.result = Some(result);
+                    }
+                    Err(error) => {
+                        tracing::warn!("operation {} ({}) failed: {}", id, operation.kind, error);
+                        operation.status = OperationStatus::Failed;
+                        operation.error = Some(error);
+                    }
+                }
+                operation.finished_at = Some(chrono::Utc::now());
+            });
+        }
+    });
+    operation
+}
+
+/// Whether the client asked for the request to run as an operation
+pub fn wants_async(headers: &HeaderMap) -> bool {
+    headers
+        .get_all("prefer")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|preference| preference.trim().eq_ignore_ascii_case("respond-async"))
+}
+
+/// 202 Accepted response pointing at an operation
+pub fn accepted(operation: Operation) -> Response {
+    let location =
+        HeaderValue::from_str(&operation.url()).expect("operation URL is a valid header");
+    let mut response =
+        (StatusCode::ACCEPTED, Json(ApiResponse::success(operation))).into_response();
+    response.headers_mut().insert(header::LOCATION, location);
+    response
+        .headers_mut()
+        .insert("preference-applied", HeaderValue::from_static("respond-async"));
+    response
+}
+
+/// Status of an operation
+pub async fn get_operation(
+    State(operations): State<Shared<Operations>>,
+    ApiPath(id): ApiPath<Uuid>,
+) -> Result<Json<ApiResponse<Operation>>, AppError> {
+    let operation = operations.get(id).ok_or_else(|| {
+        AppError::NotFound(format!("operation {} not found", id))
+            .with_code(ErrorCode::OPERATION_NOT_FOUND)
+    })?;
+    Ok(Json(ApiResponse::success(operation)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_operation_reports_progress_then_result() {
+        let state = AppState::new(crate::Config::default());
+        let (finish, finished) = tokio::sync::oneshot::channel::<()>();
+        let operation = spawn(&state, "test", |progress| async move {
+            progress.report(1, 2);
+            finished.await.map_err(|err| err.to_string())?;
+            Ok(serde_json::json!({ "imported": 2 }))
+        });
+        assert_eq!(operation.status, OperationStatus::Running);
+
+        let id = operation.id;
+        while state.operations.get(id).unwrap().progress.is_none() {
+            tokio::task::yield_now().await;
+        }
+        let progress = state.operations.get(id).unwrap().progress;
+        assert_eq!(progress, Some(Progress { done: 1, total: 2 }));
+        finish.send(()).unwrap();
+        while state.operations.get(id).unwrap().finished_at.is_none() {
+            tokio::task::yield_now().await;
+        }
+        let operation = state.operations.get(id).unwrap();
+        assert_eq!(operation.status, OperationStatus::Succeeded);
+        assert_eq!(operation.result.unwrap()["imported"], 2);
+    }
+
+    #[test]
+    fn test_respond_async_preference() {
+        let mut headers = HeaderMap::new();
+        assert!(!wants_async(&headers));
+        headers.insert("prefer", HeaderValue::from_static("return=minimal, Respond-Async"));
+        assert!(wants_async(&headers));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_operation_is_not_found() {
+        let operations = Shared(Arc::new(Operations::default()));
+        let result = get_operation(State(operations), ApiPath(Uuid::new_v4())).await;
+        assert_eq!(result.unwrap_err().status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,145 @@
+//! OpenTelemetry trace export.
+//!
+//! When `Config::otel.endpoint` is set, `telemetry::init` adds a
+//! `tracing-opentelemetry` layer so `tracing` spans, including the per-request
+//! root span, are exported over OTLP. Inbound W3C `traceparent` headers make
+//! the request span a child of the caller's trace.
+//!
+//! Export is sampled with `Config::trace_sampling.head_ratio`: the tracer
+//! follows the caller's sampled flag when a `traceparent` carries one and
+//! otherwise keeps that share of traces by trace ID, so production exports no
+//! more than the head sampler traces to stdout. The SDK decides when a span
+//! starts, before its outcome is known, so the tail rules keeping failed and
+//! slow requests apply to the stdout traces only.
+
+use axum::http::HeaderMap;
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{self, Sampler, Tracer};
+use opentelemetry_sdk::Resource;
+use serde::{Deserialize, Serialize};
+
+use crate::telemetry::SamplingConfig;
+
+/// OTLP exporter settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OtelConfig {
+    /// OTLP gRPC collector endpoint, e.g. `http://localhost:4317`; unset disables export
+    pub endpoint: Option<String>,
+    /// `service.name` resource attribute
+    pub service_name: String,
+}
+
+impl Default for OtelConfig {
+    fn default() -> Self {
+        OtelConfig {
+            endpoint: None,
+            service_name: "sample-api".to_string(),
+        }
+    }
+}
+
+/// Head sampler for exported traces, deferring to a sampled caller
+fn sampler(sampling: &SamplingConfig) -> Sampler {
+    let ratio = sampling.head_ratio.clamp(0.0, 1.0);
+    Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(ratio)))
+}
+
+/// Batch-exporting tracer, or `None` when export is disabled or fails to start
+pub fn tracer(config: &OtelConfig, sampling: &SamplingConfig) -> Option<Tracer> {
+    let endpoint = config.endpoint.as_ref()?;
+    let resource = Resource::new(vec![KeyValue::new(
+        "service.name",
+        config.service_name.clone(),
+    )]);
+    let installed = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint.clone()),
+        )
+        .with_trace_config(trace::config().with_sampler(sampler(sampling)).with_resource(resource))
+        .install_batch(opentelemetry_sdk::runtime::Tokio);
+    match installed {
+        Ok(tracer) => {
+            opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+            Some(tracer)
+        }
+        Err(err) => {
+            // The subscriber isn't installed yet, so this can't go through `tracing`
+            eprintln!("OTLP export to {} disabled: {}", endpoint, err);
+            None
+        }
+    }
+}
+
+/// Header reader for the propagator
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+/// Caller's trace context from `traceparent` and `tracestate`, if sent
+pub fn parent_context(headers: &HeaderMap) -> Context {
+    TraceContextPropagator::new().extract(&HeaderExtractor(headers))
+}
+
+/// Flush spans still queued for export
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{SamplingDecision, SpanKind, TraceContextExt, TraceId};
+    use opentelemetry_sdk::trace::ShouldSample;
+
+    #[test]
+    fn test_parent_context_from_traceparent() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".parse().unwrap(),
+        );
+        let context = parent_context(&headers);
+        let span = context.span();
+        let parent = span.span_context();
+        assert!(parent.is_remote());
+        assert_eq!(parent.trace_id().to_string(), "4bf92f3577b34da6a3ce929d0e0e4736");
+
+        assert!(!parent_context(&HeaderMap::new()).span().span_context().is_valid());
+    }
+
+    #[test]
+    fn test_export_follows_head_ratio_unless_the_caller_sampled() {
+        let sampler = sampler(&SamplingConfig {
+            head_ratio: 0.0,
+            ..SamplingConfig::default()
+        });
+        let decide = |parent: &Context| {
+            let trace_id = TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap();
+            sampler
+                .should_sample(Some(parent), trace_id, "request", &SpanKind::Server, &[], &[])
+                .decision
+        };
+        assert_eq!(decide(&Context::new()), SamplingDecision::Drop);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".parse().unwrap(),
+        );
+        assert_eq!(decide(&parent_context(&headers)), SamplingDecision::RecordAndSample);
+    }
+}
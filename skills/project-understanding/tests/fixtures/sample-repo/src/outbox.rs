@@ -0,0 +1,78 @@
+//! Outbox dispatcher.
+//!
+//! Mutations write their events to the outbox in the same transaction as
+//! the change itself. This loop publishes pending events to the event bus
+//! and marks them delivered. Delivery is at-least-once: an event whose
+//! publish succeeded but whose mark failed is published again.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::AppState;
+
+/// How often the outbox is polled when idle
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Events published per batch
+const BATCH_SIZE: usize = 100;
+
+/// Publish one batch, returning how many events were delivered
+pub async fn dispatch_batch(state: &AppState) -> usize {
+    let pending = match state.users.pending_events(BATCH_SIZE).await {
+        Ok(pending) => pending,
+        Err(err) => {
+            tracing::warn!("failed to read outbox: {}", err);
+            return 0;
+        }
+    };
+    if pending.is_empty() {
+        return 0;
+    }
+
+    let ids: Vec<uuid::Uuid> = pending.iter().map(|record| record.event.id).collect();
+    for record in pending {
+        state.events.publish(record.event);
+    }
+    if let Err(err) = state.users.mark_delivered(&ids).await {
+        tracing::warn!("failed to mark {} outbox events delivered: {}", ids.len(), err);
+        return 0;
+    }
+    ids.len()
+}
+
+/// Spawn the dispatcher loop
+pub fn spawn_dispatcher(state: Arc<AppState>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            // Marking events delivered is a write, so read-only mode pauses it
+            if state.read_only.is_enabled() {
+                tokio::time::sleep(POLL_INTERVAL).await;
+                continue;
+            }
+            // Drain without pausing while full batches keep coming
+            if dispatch_batch(&state).await < BATCH_SIZE {
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{DomainEvent, EventKind};
+    use crate::{Config, User};
+
+    #[tokio::test]
+    async fn test_dispatch_publishes_and_marks_delivered() {
+        let state = AppState::new(Config::default());
+        let mut events = state.events.subscribe();
+        let user = User::new("alice".to_string(), "alice@example.com".to_string());
+        let event = DomainEvent::user(EventKind::UserCreated, &user);
+        state.users.insert_with_event(user, event).await.unwrap();
+
+        assert_eq!(dispatch_batch(&state).await, 1);
+        assert_eq!(events.recv().await.unwrap().kind, EventKind::UserCreated);
+        assert_eq!(dispatch_batch(&state).await, 0);
+    }
+}
@@ -0,0 +1,164 @@
+//! Page-by-page listing, described in the response envelope.
+//!
+//! List endpoints take `page`, counted from 1, and `per_page` query
+//! parameters and answer with one page of their items. The envelope's
+//! `meta` gives the total across pages, the page, and its size; `links`
+//! gives the `self` URL and, where there are more pages either way, `next`
+//! and `prev`, so clients page through without reading headers. Links keep
+//! the rest of the query string, so filters carry over to every page.
+//!
+//! Without `per_page` the whole list is one page, as before lists were
+//! paged, so clients that don't page see no difference but the two new
+//! sections. `per_page` is at most `MAX_PER_PAGE`.
+
+use async_trait::async_trait;
+use axum::{
+    extract::{FromRequestParts, OriginalUri, Query},
+    http::request::Parts,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::error::AppError;
+
+/// Largest page a list endpoint returns
+pub const MAX_PER_PAGE: u64 = 500;
+
+/// Query parameters choosing a page
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PageParams {
+    /// Page to return, from 1; defaults to the first
+    pub page: Option<u64>,
+    /// Items per page, at most 500; defaults to every item on one page
+    pub per_page: Option<u64>,
+}
+
+/// Where a page sits in its list
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct PageMeta {
+    /// Items across every page
+    pub total: u64,
+    /// This page, from 1
+    pub page: u64,
+    /// Items per page
+    pub per_page: u64,
+}
+
+/// URLs of a page and its neighbours
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct PageLinks {
+    /// This page
+    #[serde(rename = "self")]
+    pub this: String,
+    /// The page after, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+    /// The page before, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prev: Option<String>,
+}
+
+/// The page a request asks for, and the URL it asked at
+#[derive(Debug, Clone)]
+pub struct PageRequest {
+    params: PageParams,
+    path: String,
+    query: Vec<String>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for PageRequest {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(params) = Query::<PageParams>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| AppError::BadRequest(rejection.body_text()))?;
+        if params.page == Some(0) || params.per_page == Some(0) {
+            return Err(AppError::BadRequest("page and per_page start at 1".to_string()));
+        }
+        // Nested routers see the path under their prefix; links need all of it
+        let uri = parts.extensions.get::<OriginalUri>().map_or(&parts.uri, |original| &original.0);
+        let query = uri
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter(|pair| {
+                let name = pair.split('=').next().unwrap_or_default();
+                !pair.is_empty() && name != "page" && name != "per_page"
+            })
+            .map(str::to_string)
+            .collect();
+        Ok(PageRequest {
+            params,
+            path: uri.path().to_string(),
+            query,
+        })
+    }
+}
+
+impl PageRequest {
+    /// URL of `page` of `per_page` items, keeping the other parameters
+    fn link(&self, page: u64, per_page: Option<u64>) -> String {
+        let mut query = self.query.clone();
+        query.push(format!("page={}", page));
+        if let Some(per_page) = per_page {
+            query.push(format!("per_page={}", per_page));
+        }
+        format!("{}?{}", self.path, query.join("&"))
+    }
+
+    /// Cut the requested page out of `items`, describing it
+    pub fn paginate<T>(&self, items: Vec<T>) -> (Vec<T>, PageMeta, PageLinks) {
+        let total = items.len() as u64;
+        let page = self.params.page.unwrap_or(1);
+        let requested = self.params.per_page.map(|per_page| per_page.min(MAX_PER_PAGE));
+        let per_page = requested.unwrap_or(total.max(1));
+        let skip = (page - 1).saturating_mul(per_page);
+        let items = items.into_iter().skip(skip as usize).take(per_page as usize).collect();
+        let pages = total.div_ceil(per_page).max(1);
+        let links = PageLinks {
+            this: self.link(page, requested),
+            next: (page < pages).then(|| self.link(page + 1, requested)),
+            prev: (page > 1).then(|| self.link((page - 1).min(pages), requested)),
+        };
+        let meta = PageMeta {
+            total,
+            page,
+            per_page,
+        };
+        (items, meta, links)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+
+    async fn request(uri: &str) -> Result<PageRequest, AppError> {
+        let (mut parts, _) = Request::get(uri).body(()).unwrap().into_parts();
+        PageRequest::from_request_parts(&mut parts, &()).await
+    }
+
+    #[tokio::test]
+    async fn test_pages_link_to_neighbours_keeping_filters() {
+        let request = request("/api/v1/users?meta.plan=pro&page=2&per_page=2").await.unwrap();
+        let (items, meta, links) = request.paginate((1..=5).collect::<Vec<_>>());
+        assert_eq!(items, [3, 4]);
+        assert_eq!(meta, PageMeta { total: 5, page: 2, per_page: 2 });
+        assert_eq!(links.this, "/api/v1/users?meta.plan=pro&page=2&per_page=2");
+        assert_eq!(links.next.as_deref(), Some("/api/v1/users?meta.plan=pro&page=3&per_page=2"));
+        assert_eq!(links.prev.as_deref(), Some("/api/v1/users?meta.plan=pro&page=1&per_page=2"));
+    }
+
+    #[tokio::test]
+    async fn test_without_per_page_everything_is_one_page() {
+        let (items, meta, links) = request("/api/v1/users").await.unwrap().paginate(vec![1, 2, 3]);
+        assert_eq!(items, [1, 2, 3]);
+        assert_eq!(meta, PageMeta { total: 3, page: 1, per_page: 3 });
+        assert_eq!((links.next, links.prev), (None, None));
+        assert!(request("/api/v1/users?page=0").await.is_err());
+    }
+}
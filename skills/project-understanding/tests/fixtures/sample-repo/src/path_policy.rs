@@ -0,0 +1,182 @@
+//! Trailing-slash and case normalization of request paths.
+//!
+//! Routes match exactly, so `/api/users/` is a 404 while `/api/users`
+//! works. `Config::path_policy.trailing_slash` decides what happens to such
+//! paths: `strict` routes them as sent, `redirect` answers with a permanent
+//! redirect to the path without the slash, and `rewrite` routes them as if
+//! they had been sent without it. With `case_insensitive`, a path matching a
+//! route in `handlers::ROUTES`, or its legacy `/api/*` alias, regardless of
+//! case is routed to that route; parameters such as IDs keep the case they
+//! were sent in. Case is always rewritten, never redirected, since the
+//! legacy clients that need it may not follow redirects. The query string
+//! is kept either way.
+//!
+//! Normalizing decides which route a request takes, so `normalize_path`
+//! wraps the router like `method_override` does, and its redirects don't
+//! pass through the router's layers.
+
+use axum::{
+    extract::State,
+    http::{Request, Uri},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::handlers::ROUTES;
+use crate::versioning::{LEGACY_PREFIX, V1_PREFIX};
+use crate::AppState;
+
+/// What happens to a path with a trailing slash
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrailingSlash {
+    /// Routed as sent
+    #[default]
+    Strict,
+    /// Permanently redirected to the path without it
+    Redirect,
+    /// Routed as if it had been sent without it
+    Rewrite,
+}
+
+/// Path normalization settings
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PathPolicyConfig {
+    /// Handling of trailing slashes
+    pub trailing_slash: TrailingSlash,
+    /// Match route paths regardless of case, for legacy clients
+    pub case_insensitive: bool,
+}
+
+/// `segments` spelled as `pattern` spells its literal segments, if they match
+/// it regardless of case, with the number of literal segments
+fn with_pattern_case(pattern: &str, segments: &[&str]) -> Option<(usize, String)> {
+    let literals: Vec<&str> = pattern.split('/').collect();
+    if literals.len() != segments.len() {
+        return None;
+    }
+    let mut matched = 0;
+    let mut canonical = Vec::with_capacity(segments.len());
+    for (literal, segment) in literals.iter().zip(segments) {
+        if literal.starts_with(':') {
+            canonical.push(*segment);
+        } else if literal.eq_ignore_ascii_case(segment) {
+            matched += 1;
+            canonical.push(*literal);
+        } else {
+            return None;
+        }
+    }
+    Some((matched, canonical.join("/")))
+}
+
+/// `path` as the route it matches regardless of case spells it; the most
+/// literal match wins, so `/USERS/EVENTS` is `/users/events`, not a user ID
+fn canonical_case(path: &str) -> Option<String> {
+    let segments: Vec<&str> = path.split('/').collect();
+    ROUTES
+        .iter()
+        .flat_map(|route| {
+            let legacy = route
+                .path
+                .strip_prefix(V1_PREFIX)
+                .map(|rest| format!("{}{}", LEGACY_PREFIX, rest));
+            let legacy = legacy.and_then(|legacy| with_pattern_case(&legacy, &segments));
+            [with_pattern_case(route.path, &segments), legacy]
+        })
+        .flatten()
+        .max_by_key(|(matched, _)| *matched)
+        .map(|(_, canonical)| canonical)
+}
+
+/// Middleware applying `Config::path_policy` before routing
+pub async fn normalize_path<B>(
+    State(state): State<Arc<AppState>>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let config = state.config();
+    let policy = &config.path_policy;
+    let path = req.uri().path();
+    let trimmed = match policy.trailing_slash {
+        TrailingSlash::Strict => path,
+        TrailingSlash::Redirect | TrailingSlash::Rewrite => match path.trim_end_matches('/') {
+            "" => "/",
+            trimmed => trimmed,
+        },
+    };
+    let slashed = trimmed.len() != path.len();
+    let canonical = policy.case_insensitive.then(|| canonical_case(trimmed)).flatten();
+    let normalized = canonical.unwrap_or_else(|| trimmed.to_string());
+    if normalized == path {
+        return next.run(req).await;
+    }
+
+    let target = match req.uri().query() {
+        Some(query) => format!("{}?{}", normalized, query),
+        None => normalized,
+    };
+    if slashed && policy.trailing_slash == TrailingSlash::Redirect {
+        return Redirect::permanent(&target).into_response();
+    }
+    let mut parts = req.uri().clone().into_parts();
+    parts.path_and_query = target.parse().ok();
+    if let Ok(uri) = Uri::from_parts(parts) {
+        tracing::debug!("{} routed as {}", req.uri().path(), uri.path());
+        *req.uri_mut() = uri;
+    }
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::StatusCode, routing::get, Router};
+    use tower::{Layer, ServiceExt};
+
+    async fn send(policy: PathPolicyConfig, path: &str) -> Response {
+        let state = AppState::new(crate::Config {
+            path_policy: policy,
+            ..crate::Config::default()
+        });
+        let router = Router::new()
+            .route("/api/v1/users", get(|| async { "users" }))
+            .route("/api/v1/users/:id", get(|uri: Uri| async move { uri.to_string() }));
+        let app = axum::middleware::from_fn_with_state(state, normalize_path).layer(router);
+        app.oneshot(Request::get(path).body(Body::empty()).unwrap()).await.unwrap()
+    }
+
+    fn policy(trailing_slash: TrailingSlash, case_insensitive: bool) -> PathPolicyConfig {
+        PathPolicyConfig {
+            trailing_slash,
+            case_insensitive,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_trailing_slash_policies() {
+        let strict = send(policy(TrailingSlash::Strict, false), "/api/v1/users/").await;
+        assert_eq!(strict.status(), StatusCode::NOT_FOUND);
+
+        let redirect = send(policy(TrailingSlash::Redirect, false), "/api/v1/users/?page=2").await;
+        assert_eq!(redirect.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(redirect.headers()["location"], "/api/v1/users?page=2");
+
+        let rewrite = send(policy(TrailingSlash::Rewrite, false), "/api/v1/users/").await;
+        assert_eq!(rewrite.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_case_insensitive_paths_keep_parameter_case() {
+        let sensitive = send(policy(TrailingSlash::Strict, false), "/API/V1/Users").await;
+        assert_eq!(sensitive.status(), StatusCode::NOT_FOUND);
+
+        let response = send(policy(TrailingSlash::Rewrite, true), "/API/v1/Users/AbC/?x=1").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"/api/v1/users/AbC?x=1");
+    }
+}
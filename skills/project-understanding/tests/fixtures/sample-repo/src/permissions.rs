@@ -0,0 +1,64 @@
+//! Field-level write permissions.
+//!
+//! A type lists the fields that need more than `Public` access to change by
+//! implementing `Writable`; fields it doesn't list may be changed by anyone
+//! allowed to call the route. Update handlers work out which fields a
+//! request actually changes and pass them to `authorize_changes` before
+//! touching storage, so PUT and PATCH enforce the same rules. A field with no
+//! role can't be changed through updates at all: a user's `email` changes
+//! only through the confirmed flow in `email_change`.
+
+use crate::auth::Role;
+use crate::error::{AppError, ErrorCode};
+
+/// Who may change a field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldGrant {
+    /// JSON field name
+    pub field: &'static str,
+    /// Least privileged role that may change the field; `None` for nobody
+    pub writable_by: Option<Role>,
+}
+
+/// Write policy of a type
+pub trait Writable {
+    /// Fields needing more than `Public` access to change
+    const GRANTS: &'static [FieldGrant];
+}
+
+/// Refuse the first changed field `role` may not change, naming it
+pub fn authorize_changes<T: Writable>(changed: &[&str], role: Role) -> Result<(), AppError> {
+    for field in changed {
+        let Some(grant) = T::GRANTS.iter().find(|grant| grant.field == *field) else {
+            continue;
+        };
+        let message = match grant.writable_by {
+            Some(needed) if role >= needed => continue,
+            Some(_) => format!("field `{}` requires admin", field),
+            None => format!("field `{}` can't be changed by updating the user", field),
+        };
+        return Err(AppError::Forbidden(message).with_code(ErrorCode::FIELD_NOT_WRITABLE));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::User;
+
+    #[test]
+    fn test_user_field_grants_per_role() {
+        assert!(authorize_changes::<User>(&["username"], Role::Public).is_ok());
+
+        let err = authorize_changes::<User>(&["username", "is_active"], Role::Public).unwrap_err();
+        assert_eq!(err.to_string(), "field `is_active` requires admin");
+        assert_eq!(err.code(), ErrorCode::FIELD_NOT_WRITABLE);
+        assert!(authorize_changes::<User>(&["is_active"], Role::Admin).is_ok());
+
+        for role in [Role::Public, Role::Admin] {
+            let err = authorize_changes::<User>(&["email"], role).unwrap_err();
+            assert!(err.to_string().contains("`email`"));
+        }
+    }
+}
@@ -0,0 +1,699 @@
+//! Postgres-backed user repository.
+//!
+//! Expects a `users` table with columns matching `User`:
+//! `id uuid primary key, tenant_id text, username text, email text,
+//! created_at timestamptz, is_active boolean, deleted_at timestamptz null`,
+//! whose every query filters on `tenant_id`, plus an `outbox` table
+//! `id uuid primary key, payload jsonb, created_at timestamptz,
+//! delivered_at timestamptz null` written in the same transaction as the
+//! mutation it describes, and a `user_activity` table
+//! `user_id uuid primary key, last_seen_at timestamptz`, all of which
+//! `migrations` creates.
+//!
+//! `Config::db_pool` sizes the connection pool. Callers waiting for a free
+//! connection are counted, so `pool_stats` reports queueing as well as
+//! usage; a wait longer than `acquire_timeout_ms` fails the query.
+//!
+//! Errors reaching the database, such as while Postgres restarts, are
+//! `RepositoryError::Unavailable` and reach clients as 503s. When no
+//! connection can be opened, the repository stops trying on every call:
+//! callers fail at once, readiness reports the outage through `ping`, and a
+//! background task retries with delays doubling from `reconnect_base_ms` up
+//! to `reconnect_max_ms` until the database answers again.
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::pool::PoolConnection;
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use sqlx::types::Json;
+use sqlx::{Postgres, Transaction};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::error::ErrorReport;
+use crate::events::DomainEvent;
+use crate::migrations::MIGRATIONS;
+use crate::repository::{
+    Change, LastSeen, OutboxRecord, OutboxStore, PoolStats, RepositoryError, UserQuery,
+    UserRepository,
+};
+use crate::search::{SearchHit, SearchPage, SearchQuery};
+use crate::{metadata, sql_comment, User};
+
+const USER_COLUMNS: &str =
+    "id, tenant_id, username, email, created_at, is_active, deleted_at, metadata";
+
+const CREATE_MIGRATIONS_TABLE: &str = "CREATE TABLE IF NOT EXISTS schema_migrations (\
+    version text PRIMARY KEY, applied_at timestamptz NOT NULL DEFAULT now())";
+
+/// Advisory lock key held while migrating, "migrate" in ASCII
+const MIGRATION_LOCK: i64 = 0x006d_6967_7261_7465;
+
+/// SQLSTATE of a query naming a table that doesn't exist
+const UNDEFINED_TABLE: &str = "42P01";
+
+/// Row shape of the `users` table
+#[derive(sqlx::FromRow)]
+struct UserRow {
+    id: Uuid,
+    tenant_id: String,
+    username: String,
+    email: String,
+    created_at: chrono::DateTime<chrono::Utc>,
+    is_active: bool,
+    deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+    metadata: Json<serde_json::Value>,
+}
+
+/// Row shape of a search: a user, its score, and the number of matches
+#[derive(sqlx::FromRow)]
+struct SearchRow {
+    #[sqlx(flatten)]
+    user: UserRow,
+    score: f32,
+    total: i64,
+}
+
+/// `ILIKE` pattern matching values that contain `text`
+fn contains_pattern(text: &str) -> String {
+    let escaped = text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+impl From<UserRow> for User {
+    fn from(row: UserRow) -> Self {
+        User {
+            id: row.id,
+            tenant_id: row.tenant_id,
+            username: row.username,
+            email: row.email,
+            created_at: row.created_at,
+            is_active: row.is_active,
+            deleted_at: row.deleted_at,
+            metadata: row.metadata.0,
+        }
+    }
+}
+
+/// Class of the errors Postgres sends while shutting down or starting up
+const OPERATOR_INTERVENTION: &str = "57P";
+
+impl From<sqlx::Error> for RepositoryError {
+    fn from(err: sqlx::Error) -> Self {
+        match &err {
+            sqlx::Error::Database(db) if db.is_unique_violation() => {
+                RepositoryError::Conflict(db.constraint().unwrap_or("unique").to_string())
+            }
+            sqlx::Error::Database(db)
+                if db.code().is_some_and(|code| code.starts_with(OPERATOR_INTERVENTION)) =>
+            {
+                RepositoryError::Unavailable(ErrorReport::new(err))
+            }
+            sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_)
+            | sqlx::Error::PoolTimedOut
+            | sqlx::Error::PoolClosed
+            | sqlx::Error::WorkerCrashed => RepositoryError::Unavailable(ErrorReport::new(err)),
+            _ => RepositoryError::Backend(ErrorReport::new(err)),
+        }
+    }
+}
+
+/// Connection pool settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PoolConfig {
+    /// Most connections open at once
+    pub max_connections: u32,
+    /// Connections kept open even when idle
+    pub min_connections: u32,
+    /// How long a query waits for a free connection before failing
+    pub acquire_timeout_ms: u64,
+    /// How long a connection may sit idle before it is closed; never when zero
+    pub idle_timeout_ms: u64,
+    /// First delay before retrying a database that can't be reached
+    pub reconnect_base_ms: u64,
+    /// Longest delay between retries, however long the outage lasts
+    pub reconnect_max_ms: u64,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_connections: 10,
+            min_connections: 0,
+            acquire_timeout_ms: 30_000,
+            idle_timeout_ms: 600_000,
+            reconnect_base_ms: 250,
+            reconnect_max_ms: 30_000,
+        }
+    }
+}
+
+/// Counts a caller waiting for a connection until dropped, even if it gives up
+struct Waiting<'a>(&'a AtomicU32);
+
+impl<'a> Waiting<'a> {
+    fn enter(waiters: &'a AtomicU32) -> Self {
+        waiters.fetch_add(1, Ordering::Relaxed);
+        Self(waiters)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Whether the database can be reached, shared with the task reconnecting
+struct Connectivity {
+    connected: AtomicBool,
+    base_delay: Duration,
+    max_delay: Duration,
+}
+
+/// Retry with doubling delays until the database answers again
+async fn reconnect(pool: PgPool, connectivity: Arc<Connectivity>) {
+    let mut delay = connectivity.base_delay;
+    loop {
+        tokio::time::sleep(delay).await;
+        match sqlx::query("SELECT 1").execute(&pool).await {
+            Ok(_) => break,
+            Err(err) => {
+                delay = delay.saturating_mul(2).min(connectivity.max_delay);
+                tracing::warn!("database still unavailable, retrying in {:?}: {}", delay, err);
+            }
+        }
+    }
+    connectivity.connected.store(true, Ordering::SeqCst);
+    tracing::info!("reconnected to the database");
+}
+
+/// User repository backed by a Postgres pool
+pub struct PgUserRepository {
+    pool: PgPool,
+    max_connections: u32,
+    waiters: AtomicU32,
+    connectivity: Arc<Connectivity>,
+    tag_queries: bool,
+}
+
+impl PgUserRepository {
+    /// Connect to the database at `url` with a pool sized by `config`
+    pub async fn connect(
+        url: &str,
+        config: &PoolConfig,
+        tag_queries: bool,
+    ) -> Result<Self, RepositoryError> {
+        let pool = Self::pool_options(config).connect(url).await?;
+        Ok(Self::with_pool(pool, config, tag_queries))
+    }
+
+    /// Set up a pool for `url` that connects on first use, as replicas do so
+    /// that one that is down at startup doesn't hold up the primary
+    pub fn connect_lazy(
+        url: &str,
+        config: &PoolConfig,
+        tag_queries: bool,
+    ) -> Result<Self, RepositoryError> {
+        let pool = Self::pool_options(config).connect_lazy(url)?;
+        Ok(Self::with_pool(pool, config, tag_queries))
+    }
+
+    fn pool_options(config: &PoolConfig) -> PgPoolOptions {
+        let idle_timeout = (config.idle_timeout_ms > 0)
+            .then(|| Duration::from_millis(config.idle_timeout_ms));
+        PgPoolOptions::new()
+            .max_connections(config.max_connections)
+            .min_connections(config.min_connections)
+            .acquire_timeout(Duration::from_millis(config.acquire_timeout_ms))
+            .idle_timeout(idle_timeout)
+    }
+
+    fn with_pool(pool: PgPool, config: &PoolConfig, tag_queries: bool) -> Self {
+        Self {
+            pool,
+            max_connections: config.max_connections,
+            waiters: AtomicU32::new(0),
+            connectivity: Arc::new(Connectivity {
+                connected: AtomicBool::new(true),
+                base_delay: Duration::from_millis(config.reconnect_base_ms),
+                max_delay: Duration::from_millis(config.reconnect_max_ms),
+            }),
+            tag_queries,
+        }
+    }
+
+    /// A connection from the pool, counting the wait for one
+    async fn connection(&self) -> Result<PoolConnection<Postgres>, RepositoryError> {
+        self.ensure_connected()?;
+        let _waiting = Waiting::enter(&self.waiters);
+        self.pool.acquire().await.map_err(|err| self.acquire_failed(err))
+    }
+
+    /// A transaction on a connection from the pool, counting the wait for one
+    async fn begin(&self) -> Result<Transaction<'static, Postgres>, RepositoryError> {
+        self.ensure_connected()?;
+        let _waiting = Waiting::enter(&self.waiters);
+        self.pool.begin().await.map_err(|err| self.acquire_failed(err))
+    }
+
+    /// Fail at once while reconnecting, rather than each caller timing out
+    fn ensure_connected(&self) -> Result<(), RepositoryError> {
+        if self.connectivity.connected.load(Ordering::SeqCst) {
+            Ok(())
+        } else {
+            let report = ErrorReport::new("database unavailable, reconnecting");
+            Err(RepositoryError::Unavailable(report))
+        }
+    }
+
+    /// The error of a failed acquire, starting to reconnect if the database is gone
+    fn acquire_failed(&self, err: sqlx::Error) -> RepositoryError {
+        // A timeout with room for more connections means none could be opened
+        let unreachable = match &err {
+            sqlx::Error::PoolTimedOut => self.pool.size() < self.max_connections,
+            sqlx::Error::Io(_) | sqlx::Error::Tls(_) => true,
+            _ => false,
+        };
+        if unreachable && self.connectivity.connected.swap(false, Ordering::SeqCst) {
+            tracing::warn!("lost the database, reconnecting: {}", err);
+            tokio::spawn(reconnect(self.pool.clone(), self.connectivity.clone()));
+        }
+        err.into()
+    }
+
+    /// Final SQL text for a statement, tagged when enabled
+    fn sql(&self, statement: &str) -> String {
+        if self.tag_queries {
+            sql_comment::annotate(statement)
+        } else {
+            statement.to_string()
+        }
+    }
+
+    /// Tagged statements are unique per request, so don't cache them
+    fn persistent(&self) -> bool {
+        !self.tag_queries
+    }
+
+    async fn fetch_one_by(
+        &self,
+        tenant: &str,
+        column: &str,
+        value: &str,
+    ) -> Result<Option<User>, RepositoryError> {
+        let sql = self.sql(&format!(
+            "SELECT {} FROM users WHERE tenant_id = $1 AND {} = $2",
+            USER_COLUMNS, column
+        ));
+        let row = sqlx::query_as::<_, UserRow>(&sql)
+            .persistent(self.persistent())
+            .bind(tenant)
+            .bind(value)
+            .fetch_optional(&mut *self.connection().await?)
+            .await?;
+        Ok(row.map(User::from))
+    }
+
+    async fn insert_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        user: &User,
+    ) -> Result<(), RepositoryError> {
+        let sql = self.sql(
+            "INSERT INTO users (id, tenant_id, username, email, created_at, is_active, deleted_at, \
+             metadata) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        );
+        sqlx::query(&sql)
+            .persistent(self.persistent())
+            .bind(user.id)
+            .bind(&user.tenant_id)
+            .bind(&user.username)
+            .bind(&user.email)
+            .bind(user.created_at)
+            .bind(user.is_active)
+            .bind(user.deleted_at)
+            .bind(Json(&user.metadata))
+            .execute(&mut **tx)
+            .await?;
+        Ok(())
+    }
+
+    async fn update_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        user: &User,
+    ) -> Result<bool, RepositoryError> {
+        let sql = self.sql(
+            "UPDATE users SET username = $2, email = $3, is_active = $4, deleted_at = $5, \
+             metadata = $7 WHERE id = $1 AND tenant_id = $6",
+        );
+        let result = sqlx::query(&sql)
+            .persistent(self.persistent())
+            .bind(user.id)
+            .bind(&user.username)
+            .bind(&user.email)
+            .bind(user.is_active)
+            .bind(user.deleted_at)
+            .bind(&user.tenant_id)
+            .bind(Json(&user.metadata))
+            .execute(&mut **tx)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn enqueue_in(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        event: &DomainEvent,
+    ) -> Result<(), RepositoryError> {
+        let sql = self.sql("INSERT INTO outbox (id, payload, created_at) VALUES ($1, $2, now())");
+        sqlx::query(&sql)
+            .persistent(self.persistent())
+            .bind(event.id)
+            .bind(Json(event))
+            .execute(&mut **tx)
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl OutboxStore for PgUserRepository {
+    async fn pending_events(&self, limit: usize) -> Result<Vec<OutboxRecord>, RepositoryError> {
+        let sql = self.sql(
+            "SELECT payload, created_at FROM outbox WHERE delivered_at IS NULL \
+             ORDER BY created_at LIMIT $1",
+        );
+        let rows: Vec<(Json<DomainEvent>, chrono::DateTime<chrono::Utc>)> =
+            sqlx::query_as(&sql)
+                .persistent(self.persistent())
+                .bind(limit as i64)
+                .fetch_all(&mut *self.connection().await?)
+                .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(Json(event), created_at)| OutboxRecord { event, created_at })
+            .collect())
+    }
+
+    async fn mark_delivered(&self, ids: &[Uuid]) -> Result<(), RepositoryError> {
+        let sql = self.sql("UPDATE outbox SET delivered_at = now() WHERE id = ANY($1)");
+        sqlx::query(&sql)
+            .persistent(self.persistent())
+            .bind(ids)
+            .execute(&mut *self.connection().await?)
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl UserRepository for PgUserRepository {
+    async fn list(&self, tenant: &str, query: &UserQuery) -> Result<Vec<User>, RepositoryError> {
+        let sql = self.sql(&format!(
+            "SELECT {} FROM users WHERE tenant_id = $1 AND ($2 OR deleted_at IS NULL) \
+             AND metadata @> $3 ORDER BY created_at",
+            USER_COLUMNS
+        ));
+        let rows = sqlx::query_as::<_, UserRow>(&sql)
+            .persistent(self.persistent())
+            .bind(tenant)
+            .bind(query.include_deleted)
+            .bind(Json(metadata::containing(&query.metadata)))
+            .fetch_all(&mut *self.connection().await?)
+            .await?;
+        Ok(rows.into_iter().map(User::from).collect())
+    }
+
+    async fn get(&self, tenant: &str, id: Uuid) -> Result<Option<User>, RepositoryError> {
+        let sql = self.sql(&format!(
+            "SELECT {} FROM users WHERE id = $1 AND tenant_id = $2",
+            USER_COLUMNS
+        ));
+        let row = sqlx::query_as::<_, UserRow>(&sql)
+            .persistent(self.persistent())
+            .bind(id)
+            .bind(tenant)
+            .fetch_optional(&mut *self.connection().await?)
+            .await?;
+        Ok(row.map(User::from))
+    }
+
+    async fn find_by_username(
+        &self,
+        tenant: &str,
+        username: &str,
+    ) -> Result<Option<User>, RepositoryError> {
+        self.fetch_one_by(tenant, "username", username).await
+    }
+
+    async fn find_by_email(
+        &self,
+        tenant: &str,
+        email: &str,
+    ) -> Result<Option<User>, RepositoryError> {
+        self.fetch_one_by(tenant, "lower(email)", &email.to_lowercase()).await
+    }
+
+    async fn search(
+        &self,
+        tenant: &str,
+        query: &SearchQuery,
+    ) -> Result<SearchPage, RepositoryError> {
+        // `%` is `pg_trgm`'s similarity test at its default threshold, which
+        // `search::THRESHOLD` matches; it and `ILIKE` use the trigram indexes
+        let matches = "FROM users WHERE tenant_id = $1 AND ($4 OR deleted_at IS NULL) \
+             AND (username % $2 OR username ILIKE $5 OR ($3 AND (email % $2 OR email ILIKE $5)))";
+        let sql = self.sql(&format!(
+            "SELECT {}, GREATEST(similarity(username, $2), \
+             CASE WHEN $3 THEN similarity(email, $2) ELSE 0::real END) AS score, \
+             count(*) OVER () AS total {} ORDER BY score DESC, username LIMIT $6 OFFSET $7",
+            USER_COLUMNS, matches
+        ));
+        let pattern = contains_pattern(&query.q);
+        let mut connection = self.connection().await?;
+        let rows = sqlx::query_as::<_, SearchRow>(&sql)
+            .persistent(self.persistent())
+            .bind(tenant)
+            .bind(&query.q)
+            .bind(query.emails)
+            .bind(query.include_deleted)
+            .bind(&pattern)
+            .bind(query.limit as i64)
+            .bind(query.offset as i64)
+            .fetch_all(&mut *connection)
+            .await?;
+        let total = match rows.first() {
+            Some(row) => row.total as u64,
+            None if query.offset == 0 => 0,
+            // Past the last page the window count has no row to ride on
+            None => {
+                let sql = self.sql(&format!("SELECT count(*) {}", matches));
+                let total: i64 = sqlx::query_scalar(&sql)
+                    .persistent(self.persistent())
+                    .bind(tenant)
+                    .bind(&query.q)
+                    .bind(query.emails)
+                    .bind(query.include_deleted)
+                    .bind(&pattern)
+                    .fetch_one(&mut *connection)
+                    .await?;
+                total as u64
+            }
+        };
+        let hits = rows
+            .into_iter()
+            .map(|row| SearchHit {
+                user: row.user.into(),
+                score: row.score,
+            })
+            .collect();
+        Ok(SearchPage {
+            hits,
+            total,
+            offset: query.offset,
+        })
+    }
+
+    async fn tenants(&self) -> Result<Vec<String>, RepositoryError> {
+        let sql = self.sql("SELECT DISTINCT tenant_id FROM users ORDER BY tenant_id");
+        let tenants = sqlx::query_scalar(&sql)
+            .persistent(self.persistent())
+            .fetch_all(&mut *self.connection().await?)
+            .await?;
+        Ok(tenants)
+    }
+
+    async fn insert(&self, user: User) -> Result<User, RepositoryError> {
+        let mut tx = self.begin().await?;
+        self.insert_in(&mut tx, &user).await?;
+        tx.commit().await?;
+        Ok(user)
+    }
+
+    async fn insert_with_event(
+        &self,
+        user: User,
+        event: DomainEvent,
+    ) -> Result<User, RepositoryError> {
+        let mut tx = self.begin().await?;
+        self.insert_in(&mut tx, &user).await?;
+        self.enqueue_in(&mut tx, &event).await?;
+        tx.commit().await?;
+        Ok(user)
+    }
+
+    async fn update(&self, user: User) -> Result<Option<User>, RepositoryError> {
+        let mut tx = self.begin().await?;
+        let updated = self.update_in(&mut tx, &user).await?;
+        tx.commit().await?;
+        Ok(updated.then_some(user))
+    }
+
+    async fn update_with_event(
+        &self,
+        user: User,
+        event: DomainEvent,
+    ) -> Result<Option<User>, RepositoryError> {
+        let mut tx = self.begin().await?;
+        if !self.update_in(&mut tx, &user).await? {
+            // Dropping the transaction rolls it back
+            return Ok(None);
+        }
+        self.enqueue_in(&mut tx, &event).await?;
+        tx.commit().await?;
+        Ok(Some(user))
+    }
+
+    async fn commit(&self, changes: &[Change]) -> Result<bool, RepositoryError> {
+        let mut tx = self.begin().await?;
+        for change in changes {
+            match change {
+                Change::Insert(user) => self.insert_in(&mut tx, user).await?,
+                Change::Update(user) => {
+                    if !self.update_in(&mut tx, user).await? {
+                        return Ok(false);
+                    }
+                }
+                Change::Enqueue(event) => self.enqueue_in(&mut tx, event).await?,
+            }
+        }
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn delete(&self, tenant: &str, id: Uuid) -> Result<bool, RepositoryError> {
+        let sql = self.sql("DELETE FROM users WHERE id = $1 AND tenant_id = $2");
+        let result = sqlx::query(&sql)
+            .persistent(self.persistent())
+            .bind(id)
+            .bind(tenant)
+            .execute(&mut *self.connection().await?)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn record_last_seen(&self, seen: &[LastSeen]) -> Result<(), RepositoryError> {
+        let sql = self.sql(
+            "INSERT INTO user_activity (user_id, last_seen_at) \
+             SELECT * FROM UNNEST($1::uuid[], $2::timestamptz[]) \
+             ON CONFLICT (user_id) DO UPDATE \
+             SET last_seen_at = GREATEST(user_activity.last_seen_at, EXCLUDED.last_seen_at)",
+        );
+        let (ids, times): (Vec<_>, Vec<_>) = seen.iter().copied().unzip();
+        sqlx::query(&sql)
+            .persistent(self.persistent())
+            .bind(ids)
+            .bind(times)
+            .execute(&mut *self.connection().await?)
+            .await?;
+        Ok(())
+    }
+
+    async fn last_seen(
+        &self,
+        tenant: &str,
+        id: Uuid,
+    ) -> Result<Option<chrono::DateTime<chrono::Utc>>, RepositoryError> {
+        let sql = self.sql(
+            "SELECT a.last_seen_at FROM user_activity a JOIN users u ON u.id = a.user_id \
+             WHERE a.user_id = $1 AND u.tenant_id = $2",
+        );
+        let at = sqlx::query_scalar(&sql)
+            .persistent(self.persistent())
+            .bind(id)
+            .bind(tenant)
+            .fetch_optional(&mut *self.connection().await?)
+            .await?;
+        Ok(at)
+    }
+
+    async fn migrate(&self) -> Result<Vec<&'static str>, RepositoryError> {
+        let mut tx = self.begin().await?;
+        sqlx::query("SELECT pg_advisory_xact_lock($1)")
+            .bind(MIGRATION_LOCK)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(CREATE_MIGRATIONS_TABLE).execute(&mut *tx).await?;
+        let recorded: Vec<String> = sqlx::query_scalar("SELECT version FROM schema_migrations")
+            .fetch_all(&mut *tx)
+            .await?;
+        let mut applied = Vec::new();
+        for migration in MIGRATIONS {
+            if recorded.iter().any(|recorded| recorded == migration.version) {
+                continue;
+            }
+            // Several statements, so sent unprepared
+            sqlx::Executor::execute(&mut *tx, migration.sql).await?;
+            sqlx::query("INSERT INTO schema_migrations (version) VALUES ($1)")
+                .bind(migration.version)
+                .execute(&mut *tx)
+                .await?;
+            applied.push(migration.version);
+        }
+        tx.commit().await?;
+        Ok(applied)
+    }
+
+    async fn schema_version(&self) -> Result<Option<String>, RepositoryError> {
+        let version: Result<Option<String>, _> =
+            sqlx::query_scalar("SELECT max(version) FROM schema_migrations")
+                .fetch_one(&mut *self.connection().await?)
+                .await;
+        match version {
+            Ok(version) => Ok(version),
+            // Never migrated
+            Err(sqlx::Error::Database(db)) if db.code().as_deref() == Some(UNDEFINED_TABLE) => {
+                Ok(None)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn has_schema(&self) -> bool {
+        true
+    }
+
+    async fn ping(&self) -> Result<(), RepositoryError> {
+        let sql = self.sql("SELECT 1");
+        sqlx::query(&sql)
+            .persistent(self.persistent())
+            .execute(&mut *self.connection().await?)
+            .await?;
+        Ok(())
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        Some(PoolStats {
+            size: self.pool.size(),
+            idle: self.pool.num_idle() as u32,
+            max: self.max_connections,
+            waiters: self.waiters.load(Ordering::Relaxed),
+        })
+    }
+}
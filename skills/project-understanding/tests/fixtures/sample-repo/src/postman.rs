@@ -0,0 +1,121 @@
+//! Postman collection export.
+//!
+//! `GET /api/v1/admin/postman` returns the documented API as a Postman
+//! v2.1 collection, which Insomnia imports as well. Requests are built from
+//! the same operations as `examples`, grouped into one folder per tag.
+//! Bearer auth is configured on the collection from the `adminToken`
+//! variable; public requests opt out of it.
+
+use axum::{extract::State, response::Json};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::auth::Admin;
+use crate::examples::{operations, OperationInfo};
+use crate::AppState;
+
+/// Postman schema URL for collection format v2.1
+const SCHEMA: &str = "https://schema.getpostman.com/json/collection/v2.1.0/collection.json";
+
+/// Postman request item for one operation
+fn item(operation: &OperationInfo) -> Value {
+    let segments: Vec<String> = operation
+        .path
+        .trim_start_matches('/')
+        .split('/')
+        .map(|segment| match segment.strip_prefix('{') {
+            Some(name) => format!(":{}", name.trim_end_matches('}')),
+            None => segment.to_string(),
+        })
+        .collect();
+    let variables: Vec<Value> = segments
+        .iter()
+        .filter_map(|segment| segment.strip_prefix(':'))
+        .map(|name| json!({ "key": name, "value": "" }))
+        .collect();
+
+    let mut request = json!({
+        "method": operation.method,
+        "url": {
+            "raw": format!("{{{{baseUrl}}}}/{}", segments.join("/")),
+            "host": ["{{baseUrl}}"],
+            "path": segments,
+            "variable": variables,
+        },
+    });
+    if !operation.admin {
+        request["auth"] = json!({ "type": "noauth" });
+    }
+    if let Some(body) = &operation.body {
+        request["header"] = json!([{ "key": "Content-Type", "value": "application/json" }]);
+        request["body"] = json!({
+            "mode": "raw",
+            "raw": serde_json::to_string_pretty(body).unwrap_or_default(),
+            "options": { "raw": { "language": "json" } },
+        });
+    }
+    json!({
+        "name": operation.summary.clone().unwrap_or_else(|| operation.name.clone()),
+        "request": request,
+    })
+}
+
+/// The collection, with `base_url` as the default `baseUrl`
+pub fn collection(base_url: &str) -> Value {
+    let mut folders: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+    for operation in operations() {
+        let folder = operation.tag.clone().unwrap_or_else(|| "other".to_string());
+        folders.entry(folder).or_default().push(item(&operation));
+    }
+    let folders: Vec<Value> = folders
+        .into_iter()
+        .map(|(name, items)| json!({ "name": name, "item": items }))
+        .collect();
+
+    json!({
+        "info": { "name": "sample-repo", "schema": SCHEMA },
+        "auth": {
+            "type": "bearer",
+            "bearer": [{ "key": "token", "value": "{{adminToken}}", "type": "string" }],
+        },
+        "variable": [
+            { "key": "baseUrl", "value": base_url },
+            { "key": "adminToken", "value": "" },
+        ],
+        "item": folders,
+    })
+}
+
+/// Export the collection
+pub async fn export_collection(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+) -> Json<Value> {
+    let base_url = format!("http://{}:{}", state.config().host, state.config().port);
+    Json(collection(&base_url))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collection_groups_by_tag_and_configures_auth() {
+        let collection = collection("http://localhost:8080");
+        let folders = collection["item"].as_array().unwrap();
+        let users = folders.iter().find(|folder| folder["name"] == "users").unwrap();
+        let items = users["item"].as_array().unwrap();
+
+        let restore = items
+            .iter()
+            .find(|item| item["request"]["url"]["raw"] == "{{baseUrl}}/api/v1/users/:id/restore")
+            .unwrap();
+        assert!(restore["request"].get("auth").is_none());
+        assert_eq!(restore["request"]["url"]["variable"][0]["key"], "id");
+
+        let create = items.iter().find(|item| item["name"] == "Create new user").unwrap();
+        assert_eq!(create["request"]["auth"]["type"], "noauth");
+        assert!(create["request"]["body"]["raw"].as_str().unwrap().contains("alice"));
+    }
+}
@@ -0,0 +1,293 @@
+//! Request priority classes and priority-aware load shedding.
+//!
+//! Every request is classified from its route and caller: health and metrics
+//! probes are `Critical`, bulk imports and exports are `Bulk`, and anything
+//! else is `Normal` unless the admin token is presented, which makes it
+//! `Critical` so break-glass traffic gets through an overload.
+//! `classify_request` stores the class in the request extensions for the
+//! rate limiter, the load shedder, and anything else that queues work.
+//!
+//! With `Config::priority.shedding` on, `shed_load` caps requests in flight
+//! per class: bulk work is refused once `bulk_share` of `max_in_flight` is in
+//! use, normal work at `normal_share`, and critical work never. Refused
+//! requests get a 503 with `Retry-After`.
+//!
+//! The instance counts as under pressure while normal work is at its limit
+//! or anything was shed within `pressure_window_ms`. `/readyz` reports
+//! it, and with `unready_under_pressure` turns not ready so a load balancer
+//! steers new traffic to other instances.
+
+use axum::{
+    extract::{FromRequestParts, MatchedPath, State},
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::auth::Admin;
+use crate::error::AppError;
+use crate::AppState;
+
+/// Routes classified without configuration, as `"METHOD /matched/path"`
+const BUILT_IN: [(&str, Priority); 8] = [
+    ("GET /healthz", Priority::Critical),
+    ("GET /readyz", Priority::Critical),
+    ("GET /health", Priority::Critical),
+    ("GET /health/ready", Priority::Critical),
+    ("GET /metrics", Priority::Critical),
+    ("GET /version", Priority::Critical),
+    ("POST /api/v1/users/import", Priority::Bulk),
+    ("GET /api/v1/admin/postman", Priority::Bulk),
+];
+
+/// How important a request is when capacity runs short, least important first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    /// Imports, exports, and other work that can be retried later
+    Bulk,
+    /// Regular API traffic
+    Normal,
+    /// Probes and admin traffic, never shed
+    Critical,
+}
+
+impl Priority {
+    /// Every class, least important first
+    pub const ALL: [Priority; 3] = [Priority::Bulk, Priority::Normal, Priority::Critical];
+
+    /// Label used in metrics
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Priority::Bulk => "bulk",
+            Priority::Normal => "normal",
+            Priority::Critical => "critical",
+        }
+    }
+}
+
+/// Classification and shedding settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PriorityConfig {
+    /// Refuse lower-priority requests when too many are in flight
+    pub shedding: bool,
+    /// Requests in flight that count as full capacity
+    pub max_in_flight: usize,
+    /// Fraction of capacity bulk requests may use
+    pub bulk_share: f64,
+    /// Fraction of capacity normal requests may use
+    pub normal_share: f64,
+    /// Per-route classes by `"METHOD /matched/path"`, overriding the built-in ones
+    pub routes: HashMap<String, Priority>,
+    /// How long after shedding a request the instance still counts as under pressure
+    pub pressure_window_ms: u64,
+    /// Fail readiness while under pressure
+    pub unready_under_pressure: bool,
+}
+
+impl Default for PriorityConfig {
+    fn default() -> Self {
+        PriorityConfig {
+            shedding: false,
+            max_in_flight: 256,
+            bulk_share: 0.5,
+            normal_share: 0.9,
+            routes: HashMap::new(),
+            pressure_window_ms: 5_000,
+            unready_under_pressure: false,
+        }
+    }
+}
+
+impl PriorityConfig {
+    /// Class fixed by the route, if any
+    fn route_priority(&self, route: &str) -> Option<Priority> {
+        self.routes.get(route).copied().or_else(|| {
+            BUILT_IN
+                .iter()
+                .find(|(built_in, _)| *built_in == route)
+                .map(|(_, priority)| *priority)
+        })
+    }
+
+    /// Requests in flight above which `priority` is refused
+    fn admission_limit(&self, priority: Priority) -> usize {
+        let share = match priority {
+            Priority::Bulk => self.bulk_share,
+            Priority::Normal => self.normal_share,
+            Priority::Critical => return usize::MAX,
+        };
+        (self.max_in_flight as f64 * share.clamp(0.0, 1.0)) as usize
+    }
+}
+
+/// Requests in flight and refusals by class
+#[derive(Debug, Default)]
+pub struct LoadShedder {
+    in_flight: AtomicUsize,
+    shed: [AtomicU64; 3],
+    last_shed: Mutex<Option<Instant>>,
+}
+
+impl LoadShedder {
+    /// Admit a request of `priority` unless its share of capacity is used up
+    fn try_admit(&self, config: &PriorityConfig, priority: Priority) -> Option<Admitted<'_>> {
+        let limit = config.admission_limit(priority);
+        let admitted = self
+            .in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                (current < limit).then_some(current + 1)
+            })
+            .is_ok();
+        if admitted {
+            Some(Admitted(&self.in_flight))
+        } else {
+            self.shed[priority as usize].fetch_add(1, Ordering::Relaxed);
+            *self.last_shed.lock().expect("load shedder lock poisoned") = Some(Instant::now());
+            None
+        }
+    }
+
+    /// Whether normal work is at its limit or was shed within the pressure window
+    pub fn under_pressure(&self, config: &PriorityConfig) -> bool {
+        self.under_pressure_at(config, Instant::now())
+    }
+
+    fn under_pressure_at(&self, config: &PriorityConfig, now: Instant) -> bool {
+        if !config.shedding {
+            return false;
+        }
+        let window = Duration::from_millis(config.pressure_window_ms);
+        let last_shed = *self.last_shed.lock().expect("load shedder lock poisoned");
+        let recently_shed = last_shed.is_some_and(|at| now.saturating_duration_since(at) < window);
+        let in_flight = self.in_flight.load(Ordering::Acquire);
+        recently_shed || in_flight >= config.admission_limit(Priority::Normal)
+    }
+
+    /// Requests refused so far, by class
+    pub fn shed_counts(&self) -> Vec<(Priority, u64)> {
+        Priority::ALL
+            .iter()
+            .map(|&priority| (priority, self.shed[priority as usize].load(Ordering::Relaxed)))
+            .collect()
+    }
+}
+
+/// An admitted request, released when dropped
+struct Admitted<'a>(&'a AtomicUsize);
+
+impl Drop for Admitted<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Class of a request from its matched route and caller
+async fn classify<B>(req: Request<B>, state: &Arc<AppState>) -> (Request<B>, Priority) {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| format!("{} {}", req.method(), path.as_str()));
+    if let Some(priority) = route.and_then(|route| state.config().priority.route_priority(&route)) {
+        return (req, priority);
+    }
+    let (mut parts, body) = req.into_parts();
+    let admin = Admin::from_request_parts(&mut parts, state).await.is_ok();
+    let priority = if admin { Priority::Critical } else { Priority::Normal };
+    (Request::from_parts(parts, body), priority)
+}
+
+/// Middleware storing each request's `Priority` in its extensions
+pub async fn classify_request<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let (mut req, priority) = classify(req, &state).await;
+    req.extensions_mut().insert(priority);
+    next.run(req).await
+}
+
+/// Middleware shedding the least important requests under load
+pub async fn shed_load<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let priority = req.extensions().get::<Priority>().copied().unwrap_or(Priority::Normal);
+    let current = state.config();
+    let config = &current.priority;
+    if !config.shedding {
+        return next.run(req).await;
+    }
+    let Some(admitted) = state.load_shedder.try_admit(config, priority) else {
+        let message = format!("shedding {} requests, retry shortly", priority.as_str());
+        return AppError::Overloaded(message).into_response();
+    };
+    let response = next.run(req).await;
+    drop(admitted);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bulk_is_shed_before_normal_and_critical_never() {
+        let config = PriorityConfig {
+            shedding: true,
+            max_in_flight: 4,
+            ..PriorityConfig::default()
+        };
+        let shedder = LoadShedder::default();
+        let held: Vec<_> = (0..2)
+            .map(|_| shedder.try_admit(&config, Priority::Bulk).unwrap())
+            .collect();
+        assert!(shedder.try_admit(&config, Priority::Bulk).is_none());
+        let normal = shedder.try_admit(&config, Priority::Normal).unwrap();
+        assert!(shedder.try_admit(&config, Priority::Normal).is_none());
+        let critical: Vec<_> = (0..4)
+            .map(|_| shedder.try_admit(&config, Priority::Critical).unwrap())
+            .collect();
+
+        let shed = shedder.shed_counts();
+        assert_eq!(shed, vec![(Priority::Bulk, 1), (Priority::Normal, 1), (Priority::Critical, 0)]);
+        drop((held, normal, critical));
+        assert_eq!(shedder.in_flight.load(Ordering::Acquire), 0);
+    }
+
+    #[test]
+    fn test_configured_routes_override_built_ins() {
+        let mut config = PriorityConfig::default();
+        assert_eq!(config.route_priority("GET /health"), Some(Priority::Critical));
+        assert_eq!(config.route_priority("GET /api/v1/users"), None);
+        config.routes.insert("GET /api/v1/admin/postman".to_string(), Priority::Normal);
+        assert_eq!(config.route_priority("GET /api/v1/admin/postman"), Some(Priority::Normal));
+    }
+
+    #[test]
+    fn test_pressure_lasts_for_the_window_after_shedding() {
+        let config = PriorityConfig {
+            shedding: true,
+            max_in_flight: 2,
+            ..PriorityConfig::default()
+        };
+        let shedder = LoadShedder::default();
+        assert!(!shedder.under_pressure(&config));
+        let bulk = shedder.try_admit(&config, Priority::Bulk).unwrap();
+        assert!(shedder.try_admit(&config, Priority::Bulk).is_none());
+        drop(bulk);
+
+        let now = Instant::now();
+        assert!(shedder.under_pressure_at(&config, now));
+        let later = now + Duration::from_millis(config.pressure_window_ms);
+        assert!(!shedder.under_pressure_at(&config, later));
+    }
+}
@@ -0,0 +1,91 @@
+//! Environment profiles.
+//!
+//! `APP_ENV` selects the profile, `dev`, `staging`, or `prod`, and the
+//! profile picks the defaults the `APP_CONFIG` file is laid over. `prod`
+//! keeps the strict built-in defaults, and is what an unset `APP_ENV`
+//! means, so a deployment only gets relaxed settings when it asks for them.
+//! `dev` turns on debug mode, which serves the console, logs at `debug`,
+//! traces every request, and allows CORS from any origin; `staging` keeps
+//! production settings but traces a tenth of requests. Whatever the file
+//! sets wins over the profile. The active profile is reported by
+//! `GET /health`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::Config;
+
+/// Environment variable selecting the profile
+pub const PROFILE_ENV: &str = "APP_ENV";
+
+/// Deployment environment
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Profile {
+    /// Local development
+    Dev,
+    /// Pre-production
+    Staging,
+    /// Production
+    #[default]
+    Prod,
+}
+
+impl Profile {
+    /// The profile named by `APP_ENV`, `prod` when unset
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var(PROFILE_ENV) {
+            Ok(name) => Self::parse(&name),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    fn parse(name: &str) -> Result<Self, String> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "dev" | "development" => Ok(Self::Dev),
+            "staging" => Ok(Self::Staging),
+            "prod" | "production" => Ok(Self::Prod),
+            other => Err(format!(
+                "unknown {} {:?}; expected dev, staging or prod",
+                PROFILE_ENV, other
+            )),
+        }
+    }
+
+    /// Defaults for this environment
+    pub fn defaults(self) -> Config {
+        let mut config = Config {
+            profile: self,
+            ..Config::default()
+        };
+        match self {
+            Self::Dev => {
+                config.debug = true;
+                config.log_level = "debug".to_string();
+                config.trace_sampling.head_ratio = 1.0;
+                config.cors.allowed_origins = vec!["*".to_string()];
+            }
+            Self::Staging => config.trace_sampling.head_ratio = 0.1,
+            Self::Prod => {}
+        }
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names_and_defaults_per_profile() {
+        assert_eq!(Profile::parse("Production"), Ok(Profile::Prod));
+        assert_eq!(Profile::parse("dev"), Ok(Profile::Dev));
+        assert!(Profile::parse("qa").is_err());
+
+        let dev = Profile::Dev.defaults();
+        assert!(dev.debug);
+        assert_eq!(dev.cors.allowed_origins, ["*"]);
+        let prod = Profile::Prod.defaults();
+        assert!(!prod.debug);
+        assert!(prod.cors.allowed_origins.is_empty());
+    }
+}
@@ -0,0 +1,520 @@
+//! Global request rate limiting.
+//!
+//! With `Config::rate_limit.enabled`, one token bucket admits at most
+//! `requests_per_second` on average with bursts of up to `burst`; requests
+//! finding it empty get a 429 with `Retry-After` set to when the next token
+//! arrives. Critical requests (see `priority`) are never refused, so probes
+//! and break-glass admin calls keep working through a flood.
+//!
+//! With `per_client.enabled`, each caller also gets its own bucket, keyed by
+//! its principal once its credentials check out, and otherwise by its address
+//! as `ClientIp` resolves it, so a made-up bearer token buys no fresh budget.
+//! Signatures are verified inside this layer, so partners are limited by
+//! address like anonymous callers. Every response then carries
+//! `X-RateLimit-Limit` (the burst), `X-RateLimit-Remaining`, and
+//! `X-RateLimit-Reset` (seconds until the bucket is full again).
+//!
+//! With `per_client.redis_url`, buckets live in Redis (5 or later, with the
+//! `redis` feature), so every instance spends the same budget. A script
+//! refills and takes from a bucket in one step on the server's clock, and a
+//! bucket expires once it would be full again. While Redis can't be reached,
+//! each instance falls back to its own buckets in process memory and reports
+//! `rate_limit` degraded; without `redis_url` those are the only buckets.
+//! Past `max_clients` callers, idle local buckets are evicted and new callers
+//! share one overflow bucket until room frees up.
+
+use async_trait::async_trait;
+use axum::{
+    extract::State,
+    http::{HeaderMap, HeaderValue, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::context::{Principal, RequestContext};
+use crate::degradation::Degradations;
+use crate::error::AppError;
+use crate::priority::Priority;
+use crate::secret::Secret;
+use crate::AppState;
+
+/// Rate limit settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Limit requests
+    pub enabled: bool,
+    /// Sustained rate
+    pub requests_per_second: f64,
+    /// Requests allowed at once after a quiet period
+    pub burst: u32,
+    /// Separate budgets per caller
+    pub per_client: ClientRateLimitConfig,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            enabled: false,
+            requests_per_second: 100.0,
+            burst: 200,
+            per_client: ClientRateLimitConfig::default(),
+        }
+    }
+}
+
+/// Per-caller rate limit settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClientRateLimitConfig {
+    /// Limit each caller and send `X-RateLimit-*` headers
+    pub enabled: bool,
+    /// Sustained rate per caller
+    pub requests_per_second: f64,
+    /// Requests a caller may make at once after a quiet period
+    pub burst: u32,
+    /// Callers tracked before new ones share the overflow bucket
+    pub max_clients: usize,
+    /// Redis server holding the buckets so instances share them; requires the
+    /// `redis` feature
+    pub redis_url: Option<Secret<String>>,
+}
+
+impl Default for ClientRateLimitConfig {
+    fn default() -> Self {
+        ClientRateLimitConfig {
+            enabled: false,
+            requests_per_second: 10.0,
+            burst: 20,
+            max_clients: 10_000,
+            redis_url: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+/// A caller's budget after taking a token
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quota {
+    /// Bucket size
+    pub limit: u32,
+    /// Whole tokens left
+    pub remaining: u32,
+    /// Time until the bucket is full again
+    pub reset: Duration,
+    /// Time until a token is available, if none was
+    pub retry_after: Option<Duration>,
+}
+
+impl Quota {
+    /// The budget of a bucket left holding `tokens`, having taken one if `taken`
+    fn after(capacity: f64, rate: f64, tokens: f64, taken: bool) -> Self {
+        Quota {
+            limit: capacity as u32,
+            remaining: tokens as u32,
+            reset: Duration::from_secs_f64((capacity - tokens).max(0.0) / rate),
+            retry_after: (!taken).then(|| Duration::from_secs_f64((1.0 - tokens) / rate)),
+        }
+    }
+
+    /// Write the `X-RateLimit-*` headers
+    fn insert_headers(&self, headers: &mut HeaderMap) {
+        let reset = self.reset.as_secs_f64().ceil() as u64;
+        headers.insert("x-ratelimit-limit", HeaderValue::from(self.limit));
+        headers.insert("x-ratelimit-remaining", HeaderValue::from(self.remaining));
+        headers.insert("x-ratelimit-reset", HeaderValue::from(reset));
+    }
+}
+
+/// Token bucket refilled continuously at a fixed rate
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    bucket: Mutex<Bucket>,
+}
+
+impl TokenBucket {
+    /// A full bucket of `burst` tokens refilled at `rate` per second
+    pub fn new(rate: f64, burst: u32) -> Self {
+        let capacity = f64::from(burst.max(1));
+        Self {
+            rate: rate.max(f64::MIN_POSITIVE),
+            capacity,
+            bucket: Mutex::new(Bucket {
+                tokens: capacity,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    /// Create a bucket from configuration
+    pub fn from_config(config: &RateLimitConfig) -> Self {
+        Self::new(config.requests_per_second, config.burst)
+    }
+
+    /// Take a token, or return how long until one is available
+    pub fn try_acquire(&self) -> Result<(), Duration> {
+        self.try_acquire_at(Instant::now())
+    }
+
+    fn try_acquire_at(&self, now: Instant) -> Result<(), Duration> {
+        match self.acquire_at(now).retry_after {
+            None => Ok(()),
+            Some(wait) => Err(wait),
+        }
+    }
+
+    /// Take a token if one is available and report what is left
+    fn acquire_at(&self, now: Instant) -> Quota {
+        let mut bucket = self.bucket.lock().expect("rate limit lock poisoned");
+        let elapsed = now.saturating_duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.capacity);
+        bucket.refilled = now;
+        let taken = bucket.tokens >= 1.0;
+        if taken {
+            bucket.tokens -= 1.0;
+        }
+        Quota::after(self.capacity, self.rate, bucket.tokens, taken)
+    }
+
+    /// Whether the bucket would be full at `now`, so forgetting it loses nothing
+    fn is_full_at(&self, now: Instant) -> bool {
+        let bucket = self.bucket.lock().expect("rate limit lock poisoned");
+        let elapsed = now.saturating_duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens + elapsed * self.rate >= self.capacity
+    }
+}
+
+/// Buckets kept outside the process, spent by every instance
+#[async_trait]
+pub trait SharedBuckets: Send + Sync {
+    /// Take a token from the caller's bucket
+    async fn acquire(&self, config: &ClientRateLimitConfig, client: &str) -> Result<Quota, String>;
+}
+
+#[cfg(feature = "redis")]
+mod redis_buckets {
+    use super::*;
+    use redis::aio::ConnectionManager;
+    use tokio::sync::OnceCell;
+
+    /// Refill a bucket hash and take a token, returning `{taken, tokens}`
+    const ACQUIRE: &str = r#"
+local capacity = tonumber(ARGV[1])
+local rate = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) + tonumber(time[2]) / 1000000
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'refilled')
+local tokens = tonumber(bucket[1]) or capacity
+local refilled = tonumber(bucket[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - refilled) * rate)
+local taken = 0
+if tokens >= 1 then
+  tokens = tokens - 1
+  taken = 1
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'refilled', tostring(now))
+local full_in = math.ceil((capacity - tokens) / rate * 1000) + 1
+redis.call('PEXPIRE', KEYS[1], math.min(full_in, 86400000))
+return {taken, tostring(tokens)}
+"#;
+
+    /// Buckets as Redis hashes, connected on first use
+    pub struct RedisBuckets {
+        client: redis::Client,
+        connection: OnceCell<ConnectionManager>,
+        script: redis::Script,
+    }
+
+    impl RedisBuckets {
+        /// A store for the server at `url`
+        pub fn open(url: &str) -> Result<Self, String> {
+            Ok(RedisBuckets {
+                client: redis::Client::open(url).map_err(|err| err.to_string())?,
+                connection: OnceCell::new(),
+                script: redis::Script::new(ACQUIRE),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl SharedBuckets for RedisBuckets {
+        async fn acquire(
+            &self,
+            config: &ClientRateLimitConfig,
+            client: &str,
+        ) -> Result<Quota, String> {
+            let connect = || ConnectionManager::new(self.client.clone());
+            let connection = self
+                .connection
+                .get_or_try_init(connect)
+                .await
+                .map_err(|err| err.to_string())?;
+            let capacity = f64::from(config.burst.max(1));
+            let rate = config.requests_per_second.max(f64::MIN_POSITIVE);
+            let (taken, tokens): (bool, String) = self
+                .script
+                .key(format!("rate_limit:{}", client))
+                .arg(capacity)
+                .arg(rate)
+                .invoke_async(&mut connection.clone())
+                .await
+                .map_err(|err| err.to_string())?;
+            let tokens = tokens.parse::<f64>().map_err(|err| err.to_string())?;
+            Ok(Quota::after(capacity, rate, tokens, taken))
+        }
+    }
+}
+
+/// Open the shared buckets on the Redis server at `url`
+#[cfg_attr(not(feature = "redis"), allow(unused_variables))]
+fn open_shared(url: &str) -> Result<Arc<dyn SharedBuckets>, String> {
+    #[cfg(feature = "redis")]
+    return Ok(Arc::new(redis_buckets::RedisBuckets::open(url)?));
+    #[cfg(not(feature = "redis"))]
+    return Err("redis support was not compiled in".to_string());
+}
+
+/// Key of the bucket shared by callers beyond `max_clients`
+const OVERFLOW: &str = "overflow";
+
+/// One token bucket per caller, shared through Redis when configured
+#[derive(Default)]
+pub struct ClientLimiter {
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+    shared: Mutex<Option<Arc<dyn SharedBuckets>>>,
+}
+
+impl ClientLimiter {
+    /// A limiter using the shared buckets `config` names, if any
+    pub fn from_config(config: &ClientRateLimitConfig) -> Self {
+        let limiter = ClientLimiter::default();
+        limiter.reconfigure(config);
+        limiter
+    }
+
+    /// Forget every local caller and reopen the shared buckets, so buckets are
+    /// recreated under new limits
+    pub fn reconfigure(&self, config: &ClientRateLimitConfig) {
+        self.buckets.lock().expect("client rate limit lock poisoned").clear();
+        let shared = config.redis_url.as_ref().and_then(|url| match open_shared(url.expose()) {
+            Ok(shared) => Some(shared),
+            Err(err) => {
+                tracing::warn!("rate limit buckets can't be shared: {}", err);
+                None
+            }
+        });
+        *self.shared.lock().expect("client rate limit lock poisoned") = shared;
+    }
+
+    /// Take a token from the caller's bucket, in Redis while it can be reached
+    pub async fn acquire(
+        &self,
+        config: &ClientRateLimitConfig,
+        client: &str,
+        degradations: &Degradations,
+    ) -> Quota {
+        let shared = self.shared.lock().expect("client rate limit lock poisoned").clone();
+        if let Some(shared) = shared {
+            match shared.acquire(config, client).await {
+                Ok(quota) => {
+                    degradations.recover("rate_limit");
+                    return quota;
+                }
+                Err(err) => degradations.degrade("rate_limit", "limit_per_instance", err),
+            }
+        }
+        self.acquire_at(config, client, Instant::now())
+    }
+
+    fn acquire_at(&self, config: &ClientRateLimitConfig, client: &str, now: Instant) -> Quota {
+        let mut buckets = self.buckets.lock().expect("client rate limit lock poisoned");
+        if !buckets.contains_key(client) && buckets.len() >= config.max_clients {
+            buckets.retain(|key, bucket| key == OVERFLOW || !bucket.is_full_at(now));
+        }
+        let key = if buckets.contains_key(client) || buckets.len() < config.max_clients {
+            client
+        } else {
+            OVERFLOW
+        };
+        buckets
+            .entry(key.to_string())
+            .or_insert_with(|| TokenBucket::new(config.requests_per_second, config.burst))
+            .acquire_at(now)
+    }
+}
+
+/// Bucket key of a caller: its verified principal, else its address
+fn client_key(context: Option<&RequestContext>) -> String {
+    let Some(context) = context else {
+        return "ip:unknown".to_string();
+    };
+    match (&context.principal, context.client_ip) {
+        (Principal::Anonymous, Some(ip)) => format!("ip:{}", ip),
+        (Principal::Anonymous, None) => "ip:unknown".to_string(),
+        (principal, _) => format!("principal:{}", principal),
+    }
+}
+
+/// Middleware rejecting requests over the global rate
+pub async fn limit_rate<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let critical = req.extensions().get::<Priority>() == Some(&Priority::Critical);
+    let current = state.config();
+    let config = &current.rate_limit;
+    let quota = match config.per_client.enabled {
+        true => {
+            let client = client_key(req.extensions().get::<RequestContext>());
+            let limiter = &state.client_limiter;
+            Some(limiter.acquire(&config.per_client, &client, &state.degradations).await)
+        }
+        false => None,
+    };
+    // A caller over its own budget doesn't spend the shared one
+    let wait = match quota.and_then(|quota| quota.retry_after) {
+        _ if critical => Ok(()),
+        Some(wait) => Err(wait),
+        None if config.enabled => state.rate_limiter.load().try_acquire(),
+        None => Ok(()),
+    };
+    let mut response = match wait {
+        Ok(()) => next.run(req).await,
+        Err(retry_after) => AppError::RateLimited(retry_after).into_response(),
+    };
+    if let Some(quota) = quota {
+        quota.insert_headers(response.headers_mut());
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestServer;
+    use crate::Config;
+    use reqwest::{Method, StatusCode};
+
+    #[test]
+    fn test_burst_then_refill() {
+        let bucket = TokenBucket::new(10.0, 2);
+        let start = Instant::now();
+        assert!(bucket.try_acquire_at(start).is_ok());
+        assert!(bucket.try_acquire_at(start).is_ok());
+        let wait = bucket.try_acquire_at(start).unwrap_err();
+        assert!((wait.as_secs_f64() - 0.1).abs() < 1e-6);
+
+        assert!(bucket.try_acquire_at(start + Duration::from_millis(100)).is_ok());
+        assert!(bucket.try_acquire_at(start + Duration::from_millis(100)).is_err());
+        // Refill never exceeds the burst size
+        let later = start + Duration::from_secs(60);
+        assert_eq!((0..3).filter(|_| bucket.try_acquire_at(later).is_ok()).count(), 2);
+    }
+
+    #[test]
+    fn test_clients_get_separate_budgets_and_share_overflow() {
+        let config = ClientRateLimitConfig {
+            enabled: true,
+            requests_per_second: 1.0,
+            burst: 2,
+            max_clients: 2,
+            ..ClientRateLimitConfig::default()
+        };
+        let limiter = ClientLimiter::default();
+        let start = Instant::now();
+        let first = limiter.acquire_at(&config, "ip:10.0.0.1", start);
+        assert_eq!((first.limit, first.remaining), (2, 1));
+        assert_eq!(first.reset, Duration::from_secs(1));
+        limiter.acquire_at(&config, "ip:10.0.0.1", start);
+        let refused = limiter.acquire_at(&config, "ip:10.0.0.1", start);
+        assert_eq!(refused.retry_after, Some(Duration::from_secs(1)));
+        assert_eq!(limiter.acquire_at(&config, "ip:10.0.0.2", start).retry_after, None);
+
+        // Two callers are tracked, so the third spends the overflow bucket
+        limiter.acquire_at(&config, "ip:10.0.0.3", start);
+        let fourth = limiter.acquire_at(&config, "ip:10.0.0.4", start);
+        assert_eq!(fourth.remaining, 0);
+        // Once a bucket is full again it is evicted to make room
+        let later = start + Duration::from_secs(5);
+        assert_eq!(limiter.acquire_at(&config, "ip:10.0.0.5", later).remaining, 1);
+    }
+
+    struct Unreachable;
+
+    #[async_trait]
+    impl SharedBuckets for Unreachable {
+        async fn acquire(&self, _: &ClientRateLimitConfig, _: &str) -> Result<Quota, String> {
+            Err("connection refused".to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_shared_buckets_fall_back_to_local_ones() {
+        let config = ClientRateLimitConfig {
+            enabled: true,
+            burst: 2,
+            ..ClientRateLimitConfig::default()
+        };
+        let limiter = ClientLimiter::default();
+        *limiter.shared.lock().unwrap() = Some(Arc::new(Unreachable));
+        let degradations = Degradations::default();
+        let quota = limiter.acquire(&config, "ip:10.0.0.1", &degradations).await;
+        assert_eq!((quota.remaining, quota.retry_after), (1, None));
+        assert_eq!(degradations.active()[0].mode, "limit_per_instance");
+    }
+
+    #[tokio::test]
+    async fn test_unverified_tokens_share_their_address_budget() {
+        let mut config = Config::default();
+        config.rate_limit.per_client = ClientRateLimitConfig {
+            enabled: true,
+            requests_per_second: 0.001,
+            burst: 2,
+            ..ClientRateLimitConfig::default()
+        };
+        let server = TestServer::start(config).await;
+        let remaining =
+            |response: &reqwest::Response| response.headers()["x-ratelimit-remaining"].clone();
+        let first = server.request(Method::GET, "/api/v1/users").bearer_auth("made-up-1");
+        assert_eq!(remaining(&first.send().await.unwrap()), "1");
+        let second = server.request(Method::GET, "/api/v1/users").bearer_auth("made-up-2");
+        assert_eq!(remaining(&second.send().await.unwrap()), "0");
+        let third = server.request(Method::GET, "/api/v1/users").bearer_auth("made-up-3");
+        assert_eq!(third.send().await.unwrap().status(), StatusCode::TOO_MANY_REQUESTS);
+        // The admin token checks out, so the admin has a budget of its own
+        let admin = server.admin(Method::GET, "/api/v1/users").send().await.unwrap();
+        assert_eq!(remaining(&admin), "1");
+    }
+
+    #[tokio::test]
+    async fn test_critical_requests_carry_headers_but_are_never_refused() {
+        let mut config = Config::default();
+        config.rate_limit.per_client = ClientRateLimitConfig {
+            enabled: true,
+            requests_per_second: 0.001,
+            burst: 1,
+            ..ClientRateLimitConfig::default()
+        };
+        let server = TestServer::start(config).await;
+        for _ in 0..3 {
+            let response = server.request(Method::GET, "/healthz").send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()["x-ratelimit-limit"], "1");
+            assert_eq!(response.headers()["x-ratelimit-remaining"], "0");
+        }
+        let response = server.request(Method::GET, "/api/v1/users").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+}
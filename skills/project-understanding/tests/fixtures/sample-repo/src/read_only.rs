@@ -0,0 +1,175 @@
+//! Read-only mode for disaster recovery.
+//!
+//! While the primary region recovers, an instance can keep serving reads
+//! without accepting writes it may not be able to reconcile. It starts
+//! read-only with `Config::read_only`, and an admin switches it either way
+//! at runtime with `PUT /api/v1/admin/read-only`; reloading the
+//! configuration leaves the current mode alone.
+//!
+//! In read-only mode `reject_writes` answers every request but GET, HEAD,
+//! and OPTIONS with a 503 `READ_ONLY` error, except the switch itself and
+//! GraphQL, whose queries arrive as POST too. User operations in `service`
+//! refuse writes as well, which covers GraphQL mutations and gRPC. The
+//! background writers pause: the outbox dispatcher stops marking events
+//! delivered, last-seen times stay buffered, activity rows spill, and demo
+//! resets are skipped. What is still buffered at shutdown is spilled or
+//! dropped rather than written.
+
+use axum::{
+    extract::State,
+    http::{Method, Request},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::auth::Admin;
+use crate::error::AppError;
+use crate::service::ServiceError;
+use crate::{ApiResponse, AppState};
+
+/// Route switching the mode, which stays writable in read-only mode
+pub const READ_ONLY_PATH: &str = "/api/v1/admin/read-only";
+
+/// Current mode, as reported by the admin API
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReadOnlyStatus {
+    /// Whether writes are refused
+    pub enabled: bool,
+    /// Why the mode was last switched
+    pub reason: Option<String>,
+    /// When the mode was last switched, unset since startup
+    pub since: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Request body for switching the mode
+#[derive(Debug, Deserialize)]
+pub struct SetReadOnly {
+    /// Refuse writes
+    pub enabled: bool,
+    /// Note for other operators, e.g. the incident being handled
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// Whether the instance refuses writes
+#[derive(Debug)]
+pub struct ReadOnlyMode {
+    enabled: AtomicBool,
+    status: Mutex<ReadOnlyStatus>,
+}
+
+impl ReadOnlyMode {
+    /// Start read-only or not
+    pub fn new(enabled: bool) -> Self {
+        let reason = enabled.then(|| "configured".to_string());
+        ReadOnlyMode {
+            enabled: AtomicBool::new(enabled),
+            status: Mutex::new(ReadOnlyStatus { enabled, reason, since: None }),
+        }
+    }
+
+    /// Whether writes are refused
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// `ServiceError::ReadOnly` if writes are refused
+    pub fn check(&self) -> Result<(), ServiceError> {
+        if self.is_enabled() {
+            Err(ServiceError::ReadOnly)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Switch the mode, returning the new status
+    pub fn set(&self, enabled: bool, reason: Option<String>) -> ReadOnlyStatus {
+        let mut status = self.status.lock().expect("read-only lock poisoned");
+        let note = reason.as_deref().unwrap_or("no reason given");
+        if enabled {
+            tracing::warn!("instance is read-only: {}", note);
+        } else {
+            tracing::info!("instance is writable again: {}", note);
+        }
+        *status = ReadOnlyStatus {
+            enabled,
+            reason,
+            since: Some(chrono::Utc::now()),
+        };
+        self.enabled.store(enabled, Ordering::Relaxed);
+        status.clone()
+    }
+
+    /// The current mode
+    pub fn status(&self) -> ReadOnlyStatus {
+        self.status.lock().expect("read-only lock poisoned").clone()
+    }
+}
+
+/// Middleware refusing writes in read-only mode
+pub async fn reject_writes<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let safe = [Method::GET, Method::HEAD, Method::OPTIONS].contains(req.method());
+    let exempt = matches!(req.uri().path(), READ_ONLY_PATH | "/graphql");
+    if safe || exempt || !state.read_only.is_enabled() {
+        return next.run(req).await;
+    }
+    AppError::from(ServiceError::ReadOnly).into_response()
+}
+
+/// The current mode
+pub async fn read_only_status(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+) -> Json<ApiResponse<ReadOnlyStatus>> {
+    Json(ApiResponse::success(state.read_only.status()))
+}
+
+/// Switch the mode
+pub async fn set_read_only(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    Json(request): Json<SetReadOnly>,
+) -> Json<ApiResponse<ReadOnlyStatus>> {
+    let status = state.read_only.set(request.enabled, request.reason);
+    Json(ApiResponse::success(status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::StatusCode, routing::get, Router};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_writes_refused_until_switched_back() {
+        let state = AppState::new(crate::Config {
+            read_only: true,
+            ..crate::Config::default()
+        });
+        let app = Router::new()
+            .route("/users", get(|| async { "list" }).post(|| async { StatusCode::CREATED }))
+            .layer(axum::middleware::from_fn_with_state(state.clone(), reject_writes))
+            .with_state(state.clone());
+        let send = |method: Method| {
+            let request = Request::builder().method(method).uri("/users");
+            app.clone().oneshot(request.body(Body::empty()).unwrap())
+        };
+
+        assert_eq!(send(Method::GET).await.unwrap().status(), StatusCode::OK);
+        let refused = send(Method::POST).await.unwrap();
+        assert_eq!(refused.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = hyper::body::to_bytes(refused.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("READ_ONLY"));
+
+        state.read_only.set(false, Some("primary recovered".to_string()));
+        assert_eq!(send(Method::POST).await.unwrap().status(), StatusCode::CREATED);
+        assert!(state.read_only.check().is_ok());
+    }
+}
@@ -0,0 +1,170 @@
+//! Role-based field redaction of JSON responses.
+//!
+//! A response type declares which of its fields need more than `Public`
+//! access by implementing `Redact`; handlers wrap their response in
+//! `Redacted::of::<T>` to attach that policy. `redact_responses` then removes
+//! each field the caller's role may not see from every object in the JSON
+//! body, so envelopes and lists of the type are covered without knowing their
+//! shape. Responses without a policy pass through untouched. This covers the
+//! REST routes only; GraphQL, gRPC, and the event streams serialize users
+//! themselves.
+
+use axum::{
+    body::{boxed, Body, Full},
+    extract::State,
+    http::{header, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+
+use crate::auth::Role;
+use crate::AppState;
+
+/// A field hidden from callers below `visible_to`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldPolicy {
+    /// JSON field name
+    pub field: &'static str,
+    /// Least privileged role that may see the field
+    pub visible_to: Role,
+}
+
+/// Redaction policy of a response type
+pub trait Redact {
+    /// Fields needing more than `Public` access
+    const FIELDS: &'static [FieldPolicy];
+}
+
+/// Policy attached to a response for `redact_responses`
+#[derive(Debug, Clone, Copy)]
+struct Policy(&'static [FieldPolicy]);
+
+/// A response redacted by the policy of `T`
+pub struct Redacted<R> {
+    response: R,
+    fields: &'static [FieldPolicy],
+}
+
+impl<R> Redacted<R> {
+    /// Redact `response` as a body containing `T`s
+    pub fn of<T: Redact>(response: R) -> Self {
+        Self {
+            response,
+            fields: T::FIELDS,
+        }
+    }
+}
+
+impl<R: IntoResponse> IntoResponse for Redacted<R> {
+    fn into_response(self) -> Response {
+        let mut response = self.response.into_response();
+        response.extensions_mut().insert(Policy(self.fields));
+        response
+    }
+}
+
+/// Remove the fields `role` may not see from every object in `value`
+pub fn redact(value: &mut serde_json::Value, fields: &[FieldPolicy], role: Role) {
+    match value {
+        serde_json::Value::Object(object) => {
+            for policy in fields.iter().filter(|policy| role < policy.visible_to) {
+                object.remove(policy.field);
+            }
+            for nested in object.values_mut() {
+                redact(nested, fields, role);
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                redact(item, fields, role);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Middleware applying the response's redaction policy for the caller's role
+pub async fn redact_responses<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let (mut parts, body) = req.into_parts();
+    let role = Role::of(&mut parts, &state).await;
+    let response = next.run(Request::from_parts(parts, body)).await;
+    let Some(Policy(fields)) = response.extensions().get::<Policy>().copied() else {
+        return response;
+    };
+    if fields.iter().all(|policy| role >= policy.visible_to) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let mut value = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => match serde_json::from_slice::<serde_json::Value>(&bytes) {
+            Ok(value) => value,
+            // Not JSON, so nothing here can be redacted by field
+            Err(_) => return Response::from_parts(parts, boxed(Full::from(bytes))),
+        },
+        Err(err) => {
+            tracing::error!("failed to buffer response for redaction: {}", err);
+            return Response::from_parts(parts, boxed(Body::empty()));
+        }
+    };
+    redact(&mut value, fields, role);
+    let body = serde_json::to_vec(&value).unwrap_or_default();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, boxed(Full::from(body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::StatusCode, response::Json, routing::get, Router};
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use crate::{ApiResponse, User};
+
+    async fn fetch(app: Router, token: Option<&str>) -> serde_json::Value {
+        let mut request = Request::get("/user");
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let response = app.oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_email_is_visible_to_admins_only() {
+        let mut config = crate::Config::default();
+        config.admin_token = Some("secret".to_string());
+        let state = AppState::new(config);
+        let user = User::new("alice".to_string(), "alice@example.com".to_string());
+        let app = Router::new()
+            .route(
+                "/user",
+                get(move || async move { Redacted::of::<User>(Json(ApiResponse::success(user))) }),
+            )
+            .layer(axum::middleware::from_fn_with_state(state.clone(), redact_responses))
+            .with_state(state);
+
+        let public = fetch(app.clone(), None).await;
+        assert_eq!(public["data"]["username"], "alice");
+        assert!(public["data"].get("email").is_none());
+        let wrong_token = fetch(app.clone(), Some("guess")).await;
+        assert!(wrong_token["data"].get("email").is_none());
+        let admin = fetch(app, Some("secret")).await;
+        assert_eq!(admin["data"]["email"], "alice@example.com");
+    }
+
+    #[test]
+    fn test_redaction_reaches_nested_lists() {
+        let mut value = json!({ "data": [{ "email": "a@example.com" }, { "email": null }] });
+        redact(&mut value, User::FIELDS, Role::Public);
+        assert_eq!(value, json!({ "data": [{}, {}] }));
+    }
+}
@@ -0,0 +1,253 @@
+//! Configuration loading and reloads without a restart.
+//!
+//! The configuration is the defaults of the `profile` selected by `APP_ENV`,
+//! overlaid with the JSON file named by `APP_CONFIG` when it is set; the file
+//! only needs the fields it changes. Secret references in it are resolved by
+//! `secret_source`. On SIGHUP, or `POST /api/v1/admin/reload`, the file is
+//! read again and the reloadable fields take effect at once: `log_level`,
+//! `rate_limit`, `cors`, `admin_token`, `signatures`, `alerting`, apart from
+//! its `interval_ms`, `maintenance`, and `feature_flags`. The rate limit buckets start full
+//! again under the new limits. Any other changed field is logged and reported as needing a
+//! restart, and keeps its running value until then, since listeners, pools,
+//! and background tasks were built from it at startup. A file that fails to
+//! read, parse, or pass `config_check` changes nothing. The same SIGHUP also
+//! has `tls` check its certificate files.
+
+use axum::{extract::State, response::Json};
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+
+use crate::auth::Admin;
+use crate::cors::Cors;
+use crate::error::AppError;
+use crate::profile::Profile;
+use crate::rate_limit::TokenBucket;
+use crate::validation::Validate;
+use crate::{debug_targets, secret_source, telemetry, ApiResponse, AppState, Config};
+
+/// Environment variable naming the JSON configuration file
+pub const CONFIG_PATH_ENV: &str = "APP_CONFIG";
+
+/// Top-level fields applied on reload
+const RELOADABLE: [&str; 8] = [
+    "log_level",
+    "rate_limit",
+    "cors",
+    "admin_token",
+    "signatures",
+    "alerting",
+    "maintenance",
+    "feature_flags",
+];
+
+/// Keeps a SIGHUP and an API reload from interleaving
+static RELOADING: Mutex<()> = Mutex::new(());
+
+/// What a reload changed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ReloadReport {
+    /// Changed fields now in effect
+    pub applied: Vec<String>,
+    /// Changed fields that keep their running value until a restart
+    pub needs_restart: Vec<String>,
+}
+
+/// Overlay `overrides` onto `base`, object by object
+fn merge(base: &mut Value, overrides: Value) {
+    match (base, overrides) {
+        (Value::Object(base), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                merge(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (base, value) => *base = value,
+    }
+}
+
+/// The profile's defaults with the `APP_CONFIG` file, if any, laid over them,
+/// secret references resolved, and every field checked
+pub async fn load() -> Result<Config, String> {
+    let profile = Profile::from_env()?;
+    let defaults = profile.defaults();
+    let mut config = serde_json::to_value(&defaults).map_err(|err| err.to_string())?;
+    let mut sets_database_url = false;
+    if let Ok(path) = std::env::var(CONFIG_PATH_ENV) {
+        let text = std::fs::read_to_string(&path)
+            .map_err(|err| format!("failed to read {}: {}", path, err))?;
+        let file: Value = serde_json::from_str(&text)
+            .map_err(|err| format!("invalid JSON in {}: {}", path, err))?;
+        sets_database_url = file.get("database_url").is_some();
+        merge(&mut config, file);
+    }
+    let config: Config = serde_json::from_value(config)
+        .map_err(|err| format!("invalid configuration: {}", err))?;
+    // Secrets serialize redacted, so one the file leaves alone keeps its default
+    let database_url = if sets_database_url {
+        config.database_url
+    } else {
+        defaults.database_url
+    };
+    // The environment decides the profile, not the file
+    let mut config = Config {
+        profile,
+        database_url,
+        ..config
+    };
+    secret_source::resolve(&mut config).await?;
+    let errors = config.validate();
+    if !errors.is_empty() {
+        return Err(format!("invalid configuration:\n  - {}", errors.join("\n  - ")));
+    }
+    Ok(config)
+}
+
+/// Top-level fields whose values differ
+fn changed_fields(before: &Config, after: &Config) -> Vec<String> {
+    let as_object = |config: &Config| match serde_json::to_value(config) {
+        Ok(Value::Object(fields)) => fields,
+        _ => serde_json::Map::new(),
+    };
+    let serialized = as_object(before);
+    let mut changed: Vec<String> = as_object(after)
+        .into_iter()
+        .filter(|(field, value)| serialized.get(field) != Some(value))
+        .map(|(field, _)| field)
+        .collect();
+    // Secrets serialize redacted, so compare their values
+    if before.database_url != after.database_url {
+        changed.push("database_url".to_string());
+    }
+    let replicas_changed = before.read_replicas.urls != after.read_replicas.urls;
+    if replicas_changed && !changed.iter().any(|field| field == "read_replicas") {
+        changed.push("read_replicas".to_string());
+    }
+    let alerting_changed = before.alerting.slack_webhook_url != after.alerting.slack_webhook_url
+        || before.alerting.pagerduty_routing_key != after.alerting.pagerduty_routing_key;
+    if alerting_changed && !changed.iter().any(|field| field == "alerting") {
+        changed.push("alerting".to_string());
+    }
+    let redis_changed = before.rate_limit.per_client.redis_url
+        != after.rate_limit.per_client.redis_url;
+    if redis_changed && !changed.iter().any(|field| field == "rate_limit") {
+        changed.push("rate_limit".to_string());
+    }
+    if before.blobs != after.blobs && !changed.iter().any(|field| field == "blobs") {
+        changed.push("blobs".to_string());
+    }
+    if before.mail.smtp != after.mail.smtp && !changed.iter().any(|field| field == "mail") {
+        changed.push("mail".to_string());
+    }
+    changed
+}
+
+/// Put the reloadable fields of `next` into effect
+pub fn apply(state: &AppState, next: Config) -> ReloadReport {
+    let _reloading = RELOADING.lock().expect("reload lock poisoned");
+    let current = state.config();
+    let (applied, needs_restart): (Vec<_>, Vec<_>) = changed_fields(&current, &next)
+        .into_iter()
+        .partition(|field| RELOADABLE.contains(&field.as_str()));
+
+    let mut updated = Config::clone(&current);
+    updated.log_level = next.log_level;
+    updated.rate_limit = next.rate_limit;
+    updated.cors = next.cors;
+    updated.admin_token = next.admin_token;
+    updated.signatures = next.signatures;
+    updated.alerting = next.alerting;
+    updated.maintenance = next.maintenance;
+    updated.feature_flags = next.feature_flags;
+    let changed = |field: &str| applied.iter().any(|applied| applied == field);
+    if changed("log_level") {
+        debug_targets::set_base_level(telemetry::base_level(&updated));
+    }
+    if changed("rate_limit") {
+        state.rate_limiter.store(Arc::new(TokenBucket::from_config(&updated.rate_limit)));
+        state.client_limiter.reconfigure(&updated.rate_limit.per_client);
+    }
+    if changed("cors") {
+        state.cors.store(Arc::new(Cors::new(&updated.cors)));
+    }
+    state.config.store(Arc::new(updated));
+
+    if !applied.is_empty() {
+        tracing::info!("configuration reloaded: {}", applied.join(", "));
+    }
+    if !needs_restart.is_empty() {
+        tracing::warn!("configuration changes need a restart: {}", needs_restart.join(", "));
+    }
+    ReloadReport { applied, needs_restart }
+}
+
+/// Reload on every SIGHUP
+pub fn spawn_listener(state: Arc<AppState>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            let mut hangups = match signal(SignalKind::hangup()) {
+                Ok(hangups) => hangups,
+                Err(err) => {
+                    tracing::warn!("no configuration reloads on SIGHUP: {}", err);
+                    return;
+                }
+            };
+            while hangups.recv().await.is_some() {
+                match load().await {
+                    Ok(next) => {
+                        apply(&state, next);
+                    }
+                    Err(err) => tracing::error!("configuration reload failed: {}", err),
+                }
+            }
+        }
+        #[cfg(not(unix))]
+        drop(state);
+    })
+}
+
+/// Re-read the configuration file and apply it
+pub async fn reload(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+) -> Result<Json<ApiResponse<ReloadReport>>, AppError> {
+    let next = load().await.map_err(AppError::BadRequest)?;
+    Ok(Json(ApiResponse::success(apply(&state, next))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_file_overrides_only_the_fields_it_names() {
+        let mut config = json!({ "port": 8080, "rate_limit": { "enabled": false, "burst": 10 } });
+        merge(&mut config, json!({ "rate_limit": { "enabled": true } }));
+        assert_eq!(
+            config,
+            json!({ "port": 8080, "rate_limit": { "enabled": true, "burst": 10 } })
+        );
+    }
+
+    #[test]
+    fn test_only_reloadable_fields_take_effect() {
+        let state = AppState::new(Config::default());
+        let mut next = Config::default();
+        next.rate_limit.enabled = !next.rate_limit.enabled;
+        next.port += 1;
+        next.database_url = crate::secret::Secret::new("memory:".to_string());
+
+        let report = apply(&state, next.clone());
+        assert_eq!(report.applied, ["rate_limit"]);
+        assert_eq!(report.needs_restart, ["port", "database_url"]);
+        assert_eq!(state.config().rate_limit.enabled, next.rate_limit.enabled);
+        assert_eq!(state.config().port, Config::default().port);
+
+        let again = apply(&state, next);
+        assert!(again.applied.is_empty());
+        assert_eq!(again.needs_restart, ["port", "database_url"]);
+    }
+}
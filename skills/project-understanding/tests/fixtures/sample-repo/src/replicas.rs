@@ -0,0 +1,416 @@
+//! Read replicas for read-heavy endpoints.
+//!
+//! `database_url` is the primary. With `Config::read_replicas.urls` set,
+//! reads made while serving a GET or HEAD request go to the replicas in
+//! turn, skipping any that failed their last check, and everything else goes
+//! to the primary: writes, reads made by other methods, and reads from
+//! background tasks. A read a replica can't answer because it is unreachable
+//! is retried on the primary and takes the replica out of rotation until
+//! `spawn_monitor` finds it answering again; the monitor pings every replica
+//! each `check_interval_ms`. Replicas lag the primary, so routes that must
+//! see a write the caller just made are listed in `primary_routes` and read
+//! from the primary too.
+//!
+//! `ReplicaCheck` reports `degraded` while any replica is out of rotation;
+//! the primary still serves, so the instance stays ready.
+
+use async_trait::async_trait;
+use axum::{
+    extract::{MatchedPath, State},
+    http::{Method, Request},
+    middleware::Next,
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::events::DomainEvent;
+use crate::health::{HealthCheck, HealthState};
+use crate::postgres::PgUserRepository;
+use crate::repository::{
+    Change, LastSeen, OutboxRecord, OutboxStore, PoolStats, RepositoryError, UserQuery,
+    UserRepository,
+};
+use crate::search::{SearchPage, SearchQuery};
+use crate::secret::Secret;
+use crate::{versioning, AppState, Config, User};
+
+tokio::task_local! {
+    static REPLICA_READS: bool;
+}
+
+/// Replica settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReplicaConfig {
+    /// Connection URLs of the read replicas, secret like `database_url`
+    pub urls: Vec<Secret<String>>,
+    /// How often every replica is pinged
+    pub check_interval_ms: u64,
+    /// Route patterns whose GETs read their own writes, so use the primary
+    pub primary_routes: Vec<String>,
+}
+
+impl Default for ReplicaConfig {
+    fn default() -> Self {
+        ReplicaConfig {
+            urls: Vec::new(),
+            check_interval_ms: 5_000,
+            primary_routes: Vec::new(),
+        }
+    }
+}
+
+/// One replica and whether it is in rotation
+pub struct Replica {
+    /// Position in `urls`, for logs that mustn't show the URL
+    pub name: String,
+    users: Arc<dyn UserRepository>,
+    healthy: AtomicBool,
+}
+
+impl Replica {
+    fn mark(&self, healthy: bool, detail: &dyn std::fmt::Display) {
+        if self.healthy.swap(healthy, Ordering::SeqCst) == healthy {
+            return;
+        }
+        if healthy {
+            tracing::info!("{} back in rotation", self.name);
+        } else {
+            tracing::warn!("{} out of rotation: {}", self.name, detail);
+        }
+    }
+}
+
+/// The replicas reads are spread over
+#[derive(Default)]
+pub struct ReplicaSet {
+    replicas: Vec<Replica>,
+    next: AtomicUsize,
+}
+
+impl ReplicaSet {
+    /// Replicas over the given stores, all in rotation
+    pub fn new(stores: Vec<Arc<dyn UserRepository>>) -> Self {
+        let replicas = stores
+            .into_iter()
+            .enumerate()
+            .map(|(index, users)| Replica {
+                name: format!("replica {}", index + 1),
+                users,
+                healthy: AtomicBool::new(true),
+            })
+            .collect();
+        Self {
+            replicas,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Pools for the configured replicas, connecting on first use; none for
+    /// in-memory storage, which has nothing to replicate
+    pub fn from_config(config: &Config) -> Self {
+        if config.demo.enabled || config.database_url.expose().starts_with("memory:") {
+            return Self::default();
+        }
+        let mut stores: Vec<Arc<dyn UserRepository>> = Vec::new();
+        for (index, url) in config.read_replicas.urls.iter().enumerate() {
+            let replica =
+                PgUserRepository::connect_lazy(url.expose(), &config.db_pool, config.sql_comments);
+            match replica {
+                Ok(replica) => stores.push(Arc::new(replica)),
+                Err(err) => tracing::error!("skipping replica {}: {}", index + 1, err),
+            }
+        }
+        Self::new(stores)
+    }
+
+    /// Whether any replica is configured
+    pub fn is_empty(&self) -> bool {
+        self.replicas.is_empty()
+    }
+
+    /// The next replica in rotation, if reads may use one
+    fn pick(&self) -> Option<&Replica> {
+        let count = self.replicas.len();
+        if count == 0 || !REPLICA_READS.try_with(|allowed| *allowed).unwrap_or(false) {
+            return None;
+        }
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        (0..count)
+            .map(|offset| &self.replicas[(start + offset) % count])
+            .find(|replica| replica.healthy.load(Ordering::SeqCst))
+    }
+
+    /// Ping every replica, putting each in or out of rotation
+    pub async fn check(&self) {
+        for replica in &self.replicas {
+            match replica.users.ping().await {
+                Ok(()) => replica.mark(true, &"answering"),
+                Err(err) => replica.mark(false, &err),
+            }
+        }
+    }
+
+    /// Replicas out of rotation
+    pub fn unhealthy(&self) -> Vec<&str> {
+        self.replicas
+            .iter()
+            .filter(|replica| !replica.healthy.load(Ordering::SeqCst))
+            .map(|replica| replica.name.as_str())
+            .collect()
+    }
+}
+
+/// Spawn the loop checking replicas on `read_replicas.check_interval_ms`
+pub fn spawn_monitor(state: Arc<AppState>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        if state.replicas.is_empty() {
+            return;
+        }
+        let interval = Duration::from_millis(state.config().read_replicas.check_interval_ms);
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            state.replicas.check().await;
+        }
+    })
+}
+
+/// Middleware letting reads of GET and HEAD requests use replicas, except on
+/// `primary_routes`
+pub async fn route_reads<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if state.replicas.is_empty() {
+        return next.run(req).await;
+    }
+    let reads_only = [Method::GET, Method::HEAD].contains(req.method());
+    let config = state.config();
+    let own_writes = req.extensions().get::<MatchedPath>().is_some_and(|route| {
+        let route = versioning::v1_route(route.as_str());
+        config.read_replicas.primary_routes.contains(&route)
+    });
+    REPLICA_READS.scope(reads_only && !own_writes, next.run(req)).await
+}
+
+/// Health check reporting `degraded` while a replica is out of rotation
+pub struct ReplicaCheck {
+    replicas: Arc<ReplicaSet>,
+}
+
+impl ReplicaCheck {
+    /// Create a check over the given replicas
+    pub fn new(replicas: Arc<ReplicaSet>) -> Self {
+        Self { replicas }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for ReplicaCheck {
+    fn name(&self) -> &'static str {
+        "read_replicas"
+    }
+
+    async fn check(&self) -> Result<HealthState, String> {
+        if self.replicas.unhealthy().is_empty() {
+            Ok(HealthState::Operational)
+        } else {
+            Ok(HealthState::Degraded)
+        }
+    }
+}
+
+/// Repository decorator sending allowed reads to replicas and the rest to the primary
+pub struct ReplicatedUserRepository {
+    primary: Arc<dyn UserRepository>,
+    replicas: Arc<ReplicaSet>,
+}
+
+impl ReplicatedUserRepository {
+    /// Wrap `primary`, spreading reads over `replicas`
+    pub fn new(primary: Arc<dyn UserRepository>, replicas: Arc<ReplicaSet>) -> Self {
+        Self { primary, replicas }
+    }
+
+    /// Run `read` on a replica if one may serve it, else or if it's unreachable on the primary
+    async fn read<'a, T, F, Fut>(&'a self, read: F) -> Result<T, RepositoryError>
+    where
+        F: Fn(&'a dyn UserRepository) -> Fut,
+        Fut: Future<Output = Result<T, RepositoryError>>,
+    {
+        if let Some(replica) = self.replicas.pick() {
+            match read(replica.users.as_ref()).await {
+                Err(RepositoryError::Unavailable(report)) => replica.mark(false, &report),
+                result => return result,
+            }
+        }
+        read(self.primary.as_ref()).await
+    }
+}
+
+#[async_trait]
+impl OutboxStore for ReplicatedUserRepository {
+    async fn pending_events(&self, limit: usize) -> Result<Vec<OutboxRecord>, RepositoryError> {
+        self.primary.pending_events(limit).await
+    }
+
+    async fn mark_delivered(&self, ids: &[Uuid]) -> Result<(), RepositoryError> {
+        self.primary.mark_delivered(ids).await
+    }
+}
+
+#[async_trait]
+impl UserRepository for ReplicatedUserRepository {
+    async fn list(&self, tenant: &str, query: &UserQuery) -> Result<Vec<User>, RepositoryError> {
+        self.read(|users| users.list(tenant, query)).await
+    }
+
+    async fn get(&self, tenant: &str, id: Uuid) -> Result<Option<User>, RepositoryError> {
+        self.read(|users| users.get(tenant, id)).await
+    }
+
+    async fn find_by_username(
+        &self,
+        tenant: &str,
+        username: &str,
+    ) -> Result<Option<User>, RepositoryError> {
+        self.read(|users| users.find_by_username(tenant, username)).await
+    }
+
+    async fn find_by_email(
+        &self,
+        tenant: &str,
+        email: &str,
+    ) -> Result<Option<User>, RepositoryError> {
+        self.read(|users| users.find_by_email(tenant, email)).await
+    }
+
+    async fn search(
+        &self,
+        tenant: &str,
+        query: &SearchQuery,
+    ) -> Result<SearchPage, RepositoryError> {
+        self.read(|users| users.search(tenant, query)).await
+    }
+
+    async fn tenants(&self) -> Result<Vec<String>, RepositoryError> {
+        self.primary.tenants().await
+    }
+
+    async fn insert(&self, user: User) -> Result<User, RepositoryError> {
+        self.primary.insert(user).await
+    }
+
+    async fn insert_with_event(
+        &self,
+        user: User,
+        event: DomainEvent,
+    ) -> Result<User, RepositoryError> {
+        self.primary.insert_with_event(user, event).await
+    }
+
+    async fn update(&self, user: User) -> Result<Option<User>, RepositoryError> {
+        self.primary.update(user).await
+    }
+
+    async fn update_with_event(
+        &self,
+        user: User,
+        event: DomainEvent,
+    ) -> Result<Option<User>, RepositoryError> {
+        self.primary.update_with_event(user, event).await
+    }
+
+    async fn commit(&self, changes: &[Change]) -> Result<bool, RepositoryError> {
+        self.primary.commit(changes).await
+    }
+
+    async fn delete(&self, tenant: &str, id: Uuid) -> Result<bool, RepositoryError> {
+        self.primary.delete(tenant, id).await
+    }
+
+    async fn record_last_seen(&self, seen: &[LastSeen]) -> Result<(), RepositoryError> {
+        self.primary.record_last_seen(seen).await
+    }
+
+    async fn last_seen(
+        &self,
+        tenant: &str,
+        id: Uuid,
+    ) -> Result<Option<chrono::DateTime<chrono::Utc>>, RepositoryError> {
+        self.read(|users| users.last_seen(tenant, id)).await
+    }
+
+    async fn migrate(&self) -> Result<Vec<&'static str>, RepositoryError> {
+        self.primary.migrate().await
+    }
+
+    async fn schema_version(&self) -> Result<Option<String>, RepositoryError> {
+        self.primary.schema_version().await
+    }
+
+    fn has_schema(&self) -> bool {
+        self.primary.has_schema()
+    }
+
+    async fn ping(&self) -> Result<(), RepositoryError> {
+        self.primary.ping().await
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        self.primary.pool_stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::InMemoryUserRepository;
+
+    #[tokio::test]
+    async fn test_only_scoped_reads_use_replicas() {
+        let primary = Arc::new(InMemoryUserRepository::new());
+        let replica = Arc::new(InMemoryUserRepository::new());
+        let user = User::new("alice".to_string(), "alice@example.com".to_string());
+        replica.insert(user.clone()).await.unwrap();
+        let replicas = Arc::new(ReplicaSet::new(vec![replica as Arc<dyn UserRepository>]));
+        let users = ReplicatedUserRepository::new(primary.clone(), replicas);
+
+        let tenant = &user.tenant_id;
+        assert!(users.get(tenant, user.id).await.unwrap().is_none());
+        let from_replica = REPLICA_READS.scope(true, users.get(tenant, user.id)).await.unwrap();
+        assert_eq!(from_replica.map(|found| found.id), Some(user.id));
+        let from_primary = REPLICA_READS.scope(false, users.get(tenant, user.id)).await.unwrap();
+        assert!(from_primary.is_none());
+
+        // Writes never reach a replica
+        let bob = User::new("bob".to_string(), "bob@example.com".to_string());
+        REPLICA_READS.scope(true, users.insert(bob.clone())).await.unwrap();
+        assert!(primary.get(tenant, bob.id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_round_robin_skips_unhealthy_replicas() {
+        let stores: Vec<Arc<dyn UserRepository>> = (0..3)
+            .map(|_| Arc::new(InMemoryUserRepository::new()) as Arc<dyn UserRepository>)
+            .collect();
+        let replicas = ReplicaSet::new(stores);
+        replicas.replicas[1].mark(false, &"connection refused");
+        assert_eq!(replicas.unhealthy(), ["replica 2"]);
+
+        let picked: Vec<String> = REPLICA_READS
+            .scope(true, async {
+                (0..4).map(|_| replicas.pick().unwrap().name.clone()).collect()
+            })
+            .await;
+        assert_eq!(picked, ["replica 1", "replica 3", "replica 3", "replica 1"]);
+    }
+}
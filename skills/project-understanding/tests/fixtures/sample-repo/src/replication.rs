@@ -0,0 +1,377 @@
+//! Active-active replication hooks between regions.
+//!
+//! With `Config::replication.region` set, every user event carries the
+//! region it was written in and a stamp per changed user field: a vector
+//! clock counting each region's writes to that field, the wall-clock time of
+//! the write, and the writing region. The sync agent that carries events
+//! between regions, usually a consumer of the peer's event stream, posts
+//! them to `POST /api/v1/admin/replication/events`.
+//!
+//! `apply_replicated` resolves each field on its own: a stamp whose clock
+//! has seen every write the local one has wins, one the local clock has
+//! already seen loses, and when the two are concurrent the later write wins,
+//! with the region name breaking ties so both regions pick the same value.
+//! Merging keeps the union of both clocks, so the next local write is seen
+//! as following both. Resolved users are written without an outbox entry,
+//! so a replicated write is never replicated back, and published on the
+//! local bus with their origin region for webhooks and subscribers. Events
+//! from this region, such as echoes through a shared broker, are ignored.
+//!
+//! Field stamps are kept in memory, so a restarted region treats the next
+//! replicated write to each field as newer than anything it has. The delay
+//! between a write and its replication here is the replication lag;
+//! readiness fails while the last event applied lagged by more than
+//! `max_lag_ms`, so traffic moves to a region that is caught up.
+
+use async_trait::async_trait;
+use axum::{extract::State, response::Json};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use crate::auth::Admin;
+use crate::error::AppError;
+use crate::events::DomainEvent;
+use crate::health::{HealthCheck, HealthState};
+use crate::service::ServiceError;
+use crate::{ApiResponse, AppState, User};
+
+/// User fields resolved independently; metadata is resolved as a whole
+pub const REPLICATED_FIELDS: [&str; 5] =
+    ["username", "email", "is_active", "deleted_at", "metadata"];
+
+/// Replication settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReplicationConfig {
+    /// This region's ID, e.g. `eu-west`; replication is off when unset
+    pub region: Option<String>,
+    /// Replication lag above which this region reports not ready
+    pub max_lag_ms: u64,
+}
+
+impl Default for ReplicationConfig {
+    fn default() -> Self {
+        ReplicationConfig {
+            region: None,
+            max_lag_ms: 30_000,
+        }
+    }
+}
+
+/// Writes seen from each region
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct VectorClock(BTreeMap<String, u64>);
+
+impl VectorClock {
+    /// Count one more write by `region`
+    pub fn tick(&mut self, region: &str) {
+        *self.0.entry(region.to_string()).or_default() += 1;
+    }
+
+    /// The writes either clock has seen
+    pub fn merge(&mut self, other: &VectorClock) {
+        for (region, &count) in &other.0 {
+            let entry = self.0.entry(region.clone()).or_default();
+            *entry = (*entry).max(count);
+        }
+    }
+
+    /// How this clock relates to `other`; `None` when they are concurrent
+    pub fn compare(&self, other: &VectorClock) -> Option<Ordering> {
+        let regions = self.0.keys().chain(other.0.keys());
+        let mut ordering = Ordering::Equal;
+        for region in regions {
+            let mine = self.0.get(region).copied().unwrap_or_default();
+            let theirs = other.0.get(region).copied().unwrap_or_default();
+            match (ordering, mine.cmp(&theirs)) {
+                (_, Ordering::Equal) => {}
+                (Ordering::Equal, next) => ordering = next,
+                (current, next) if current != next => return None,
+                _ => {}
+            }
+        }
+        Some(ordering)
+    }
+}
+
+/// When and where a field was last written
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stamp {
+    /// Writes to the field seen by the writer
+    pub clock: VectorClock,
+    /// Writer's wall-clock time
+    pub written_at: chrono::DateTime<chrono::Utc>,
+    /// Writing region
+    pub region: String,
+}
+
+impl Stamp {
+    /// Whether a write stamped `self` replaces one stamped `other`
+    fn wins_over(&self, other: &Stamp) -> bool {
+        match self.clock.compare(&other.clock) {
+            Some(ordering) => ordering == Ordering::Greater,
+            None => (self.written_at, &self.region) > (other.written_at, &other.region),
+        }
+    }
+}
+
+/// Stamps of a user's fields, by field name
+pub type UserStamps = BTreeMap<String, Stamp>;
+
+/// Copy one replicated field from `from` to `to`
+fn copy_field(field: &str, from: &User, to: &mut User) {
+    match field {
+        "username" => to.username = from.username.clone(),
+        "email" => to.email = from.email.clone(),
+        "is_active" => to.is_active = from.is_active,
+        "deleted_at" => to.deleted_at = from.deleted_at,
+        "metadata" => to.metadata = from.metadata.clone(),
+        _ => {}
+    }
+}
+
+/// Whether one replicated field differs between two users
+fn field_differs(field: &str, a: &User, b: &User) -> bool {
+    match field {
+        "username" => a.username != b.username,
+        "email" => a.email != b.email,
+        "is_active" => a.is_active != b.is_active,
+        "deleted_at" => a.deleted_at != b.deleted_at,
+        "metadata" => a.metadata != b.metadata,
+        _ => false,
+    }
+}
+
+/// Field stamps of every user written here, and the latest lag
+#[derive(Default)]
+pub struct Replication {
+    stamps: Mutex<HashMap<Uuid, UserStamps>>,
+    lag: Mutex<Option<chrono::Duration>>,
+}
+
+impl Replication {
+    /// Stamp the fields a local write changed, returning all of the user's stamps
+    fn stamp_local(&self, region: &str, before: Option<&User>, after: &User) -> UserStamps {
+        let mut stamps = self.stamps.lock().expect("replication lock poisoned");
+        let user_stamps = stamps.entry(after.id).or_default();
+        let now = chrono::Utc::now();
+        for field in REPLICATED_FIELDS {
+            if before.is_some_and(|before| !field_differs(field, before, after)) {
+                continue;
+            }
+            let stamp = user_stamps.entry(field.to_string()).or_insert_with(|| Stamp {
+                clock: VectorClock::default(),
+                written_at: now,
+                region: region.to_string(),
+            });
+            stamp.clock.tick(region);
+            stamp.written_at = now;
+            stamp.region = region.to_string();
+        }
+        user_stamps.clone()
+    }
+
+    /// `local` with every field the replicated write wins taken from `remote`,
+    /// or `None` when it wins none
+    fn resolve(
+        &self,
+        local: Option<&User>,
+        remote: &User,
+        remote_stamps: &UserStamps,
+    ) -> Option<User> {
+        let mut stamps = self.stamps.lock().expect("replication lock poisoned");
+        let user_stamps = stamps.entry(remote.id).or_default();
+        let Some(local) = local else {
+            user_stamps.extend(remote_stamps.clone());
+            return Some(remote.clone());
+        };
+        let mut merged = local.clone();
+        let mut changed = false;
+        for field in REPLICATED_FIELDS {
+            let Some(theirs) = remote_stamps.get(field) else {
+                continue;
+            };
+            let wins = match user_stamps.get(field) {
+                Some(ours) => theirs.wins_over(ours),
+                None => true,
+            };
+            let mut stamp = theirs.clone();
+            if let Some(ours) = user_stamps.get(field) {
+                stamp.clock.merge(&ours.clock);
+                if !wins {
+                    stamp = Stamp { clock: stamp.clock, ..ours.clone() };
+                }
+            }
+            if wins && field_differs(field, remote, &merged) {
+                copy_field(field, remote, &mut merged);
+                changed = true;
+            }
+            user_stamps.insert(field.to_string(), stamp);
+        }
+        changed.then_some(merged)
+    }
+
+    /// Lag of the last replicated event applied, if any
+    fn lag(&self) -> Option<chrono::Duration> {
+        *self.lag.lock().expect("replication lock poisoned")
+    }
+}
+
+/// `event` with this region and the stamps of the fields it changed, when
+/// replication is on
+pub fn tag(state: &AppState, before: Option<&User>, mut event: DomainEvent) -> DomainEvent {
+    if let Some(region) = &state.config().replication.region {
+        event.stamps = state.replication.stamp_local(region, before, &event.user);
+        event.region = Some(region.clone());
+    }
+    event
+}
+
+/// What applying a replicated event did
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// The user was written with the fields the event won
+    Applied,
+    /// Every field already had a newer or equal local write
+    Superseded,
+    /// The event came from this region
+    Ignored,
+}
+
+/// Resolve a replicated event against the local user and write the result
+pub async fn apply_replicated(
+    state: &AppState,
+    event: DomainEvent,
+) -> Result<Outcome, ServiceError> {
+    let config = state.config();
+    let Some(region) = &config.replication.region else {
+        return Err(ServiceError::Conflict("replication is not enabled".to_string()));
+    };
+    let origin = match &event.region {
+        Some(origin) if origin != region => origin,
+        Some(_) => return Ok(Outcome::Ignored),
+        None => return Err(ServiceError::Invalid(vec!["region is required".to_string()])),
+    };
+    let local = state.users.get(&event.user.tenant_id, event.user.id).await?;
+    let resolved = state.replication.resolve(local.as_ref(), &event.user, &event.stamps);
+    let lag = chrono::Utc::now() - event.occurred_at;
+    *state.replication.lag.lock().expect("replication lock poisoned") = Some(lag);
+    let Some(user) = resolved else {
+        return Ok(Outcome::Superseded);
+    };
+    let user = match local {
+        Some(_) => state.users.update(user).await?.ok_or(ServiceError::NotFound)?,
+        None => state.users.insert(user).await?,
+    };
+    tracing::debug!("applied {} of user {} from {}", event.kind.as_str(), user.id, origin);
+    state.events.publish(DomainEvent { user, ..event });
+    Ok(Outcome::Applied)
+}
+
+/// Apply an event replicated from another region
+pub async fn receive_event(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    Json(event): Json<DomainEvent>,
+) -> Result<Json<ApiResponse<Outcome>>, AppError> {
+    let outcome = apply_replicated(&state, event).await?;
+    Ok(Json(ApiResponse::success(outcome)))
+}
+
+/// Readiness check failing while replication lags behind `max_lag_ms`
+pub struct ReplicationLagCheck {
+    replication: Arc<Replication>,
+    max_lag: chrono::Duration,
+}
+
+impl ReplicationLagCheck {
+    /// Check the lag recorded in `replication`
+    pub fn new(replication: Arc<Replication>, config: &ReplicationConfig) -> Self {
+        Self {
+            replication,
+            max_lag: chrono::Duration::milliseconds(config.max_lag_ms as i64),
+        }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for ReplicationLagCheck {
+    fn name(&self) -> &'static str {
+        "replication"
+    }
+
+    async fn check(&self) -> Result<HealthState, String> {
+        match self.replication.lag() {
+            Some(lag) if lag > self.max_lag => {
+                Err(format!("replication lags by {}ms", lag.num_milliseconds()))
+            }
+            _ => Ok(HealthState::Operational),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventKind;
+
+    fn clock(counts: &[(&str, u64)]) -> VectorClock {
+        VectorClock(counts.iter().map(|(region, count)| (region.to_string(), *count)).collect())
+    }
+
+    #[test]
+    fn test_clocks_order_or_are_concurrent() {
+        let eu = clock(&[("eu", 2), ("us", 1)]);
+        assert_eq!(eu.compare(&clock(&[("eu", 1), ("us", 1)])), Some(Ordering::Greater));
+        assert_eq!(eu.compare(&clock(&[("eu", 2), ("us", 1)])), Some(Ordering::Equal));
+        assert_eq!(eu.compare(&clock(&[("eu", 1), ("us", 2)])), None);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_writes_resolve_per_field() {
+        let config = crate::Config {
+            replication: ReplicationConfig {
+                region: Some("eu".to_string()),
+                ..ReplicationConfig::default()
+            },
+            ..crate::Config::default()
+        };
+        let state = AppState::new(config);
+        let user = User::new("alice".to_string(), "alice@example.com".to_string());
+        let user = state.users.insert(user).await.unwrap();
+        let created = tag(&state, None, DomainEvent::user(EventKind::UserCreated, &user));
+
+        // US renames and deactivates the user, and changes the email a
+        // second before EU changes it differently without having seen that
+        let mut renamed = user.clone();
+        renamed.username = "alicia".to_string();
+        renamed.email = "alice@example.net".to_string();
+        renamed.is_active = false;
+        let mut stamps = created.stamps;
+        for field in ["username", "email", "is_active"] {
+            let stamp = stamps.get_mut(field).unwrap();
+            stamp.clock.tick("us");
+            stamp.written_at = chrono::Utc::now() - chrono::Duration::seconds(1);
+            stamp.region = "us".to_string();
+        }
+        let mut emailed = user.clone();
+        emailed.email = "alice@example.org".to_string();
+        tag(&state, Some(&user), DomainEvent::user(EventKind::UserUpdated, &emailed));
+        state.users.update(emailed).await.unwrap();
+
+        let mut event = DomainEvent::user(EventKind::UserUpdated, &renamed);
+        event.region = Some("us".to_string());
+        event.stamps = stamps;
+        assert_eq!(apply_replicated(&state, event.clone()).await.unwrap(), Outcome::Applied);
+        let merged = state.users.get(&user.tenant_id, user.id).await.unwrap().unwrap();
+        assert_eq!(merged.username, "alicia");
+        assert!(!merged.is_active);
+        assert_eq!(merged.email, "alice@example.org", "the later concurrent write wins");
+        assert_eq!(apply_replicated(&state, event).await.unwrap(), Outcome::Superseded);
+    }
+}
@@ -0,0 +1,623 @@
+//! User persistence layer.
+//!
+//! Handlers talk to storage through the `UserRepository` trait so the
+//! backing store can be swapped without touching the HTTP layer. Every user
+//! belongs to a tenant, and every read, update, and delete is scoped to one:
+//! a user of another tenant is as absent as one that doesn't exist.
+//!
+//! Usernames, and emails regardless of case, are unique within a tenant.
+//! Every backend enforces this itself, the databases with unique indexes and
+//! the in-memory store on each write, so two requests racing past the
+//! service's checks still can't both succeed: the second write fails with
+//! `RepositoryError::Conflict` naming the field, answered as a 409.
+
+use async_trait::async_trait;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use crate::error::ErrorReport;
+use crate::events::DomainEvent;
+use crate::search::{self, SearchPage, SearchQuery};
+use crate::{metadata, User};
+
+/// When a user was last seen
+pub type LastSeen = (Uuid, chrono::DateTime<chrono::Utc>);
+
+/// Errors returned by repository operations
+#[derive(Debug)]
+pub enum RepositoryError {
+    /// A unique field, named here, is already taken
+    Conflict(String),
+    /// The storage backend failed
+    Backend(ErrorReport),
+    /// The storage backend can't be reached, for now
+    Unavailable(ErrorReport),
+}
+
+impl std::fmt::Display for RepositoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RepositoryError::Conflict(field) => write!(f, "{} already exists", field),
+            RepositoryError::Backend(report) => write!(f, "storage error: {}", report),
+            RepositoryError::Unavailable(report) => write!(f, "storage unavailable: {}", report),
+        }
+    }
+}
+
+impl std::error::Error for RepositoryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RepositoryError::Conflict(_) => None,
+            RepositoryError::Backend(report) | RepositoryError::Unavailable(report) => {
+                Some(report)
+            }
+        }
+    }
+}
+
+/// Filters applied when listing users
+#[derive(Debug, Clone, Default)]
+pub struct UserQuery {
+    /// Include soft-deleted users
+    pub include_deleted: bool,
+    /// Metadata keys and the string each must hold
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl UserQuery {
+    /// Whether a user passes this filter
+    pub fn matches(&self, user: &User) -> bool {
+        let visible = self.include_deleted || !user.is_deleted();
+        visible && metadata::matches(&user.metadata, &self.metadata)
+    }
+}
+
+/// An event waiting in the outbox
+#[derive(Debug, Clone)]
+pub struct OutboxRecord {
+    /// The event, whose ID doubles as the outbox row ID
+    pub event: DomainEvent,
+    /// When the row was written
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// One write staged in a `UnitOfWork`
+#[derive(Debug, Clone)]
+pub enum Change {
+    /// Insert a new user
+    Insert(User),
+    /// Replace an existing user
+    Update(User),
+    /// Write an event to the outbox
+    Enqueue(DomainEvent),
+}
+
+/// Outbox of events written alongside mutations, drained by the dispatcher
+#[async_trait]
+pub trait OutboxStore: Send + Sync {
+    /// Oldest undelivered events, up to `limit`
+    async fn pending_events(&self, limit: usize) -> Result<Vec<OutboxRecord>, RepositoryError>;
+
+    /// Mark events delivered so they are not published again
+    async fn mark_delivered(&self, ids: &[Uuid]) -> Result<(), RepositoryError>;
+}
+
+/// Storage operations for users
+#[async_trait]
+pub trait UserRepository: OutboxStore {
+    /// List a tenant's users matching the query
+    async fn list(&self, tenant: &str, query: &UserQuery) -> Result<Vec<User>, RepositoryError>;
+
+    /// Fetch a tenant's user by ID
+    async fn get(&self, tenant: &str, id: Uuid) -> Result<Option<User>, RepositoryError>;
+
+    /// Fetch a tenant's user by username
+    async fn find_by_username(
+        &self,
+        tenant: &str,
+        username: &str,
+    ) -> Result<Option<User>, RepositoryError>;
+
+    /// Fetch a tenant's user by email address
+    async fn find_by_email(
+        &self,
+        tenant: &str,
+        email: &str,
+    ) -> Result<Option<User>, RepositoryError>;
+
+    /// A page of a tenant's users matching a search, best match first;
+    /// backends without a search index rank every user of the tenant
+    async fn search(
+        &self,
+        tenant: &str,
+        query: &SearchQuery,
+    ) -> Result<SearchPage, RepositoryError> {
+        let filter = UserQuery {
+            include_deleted: query.include_deleted,
+            ..UserQuery::default()
+        };
+        Ok(search::rank(self.list(tenant, &filter).await?, query))
+    }
+
+    /// Tenants with at least one user, for jobs spanning every tenant
+    async fn tenants(&self) -> Result<Vec<String>, RepositoryError>;
+
+    /// Insert a new user in its tenant
+    async fn insert(&self, user: User) -> Result<User, RepositoryError>;
+
+    /// Insert a new user and write `event` to the outbox atomically
+    async fn insert_with_event(
+        &self,
+        user: User,
+        event: DomainEvent,
+    ) -> Result<User, RepositoryError>;
+
+    /// Replace an existing user, returning `None` if its tenant has no such user
+    async fn update(&self, user: User) -> Result<Option<User>, RepositoryError>;
+
+    /// Replace an existing user and write `event` to the outbox atomically
+    async fn update_with_event(
+        &self,
+        user: User,
+        event: DomainEvent,
+    ) -> Result<Option<User>, RepositoryError>;
+
+    /// Apply `changes` atomically, in order; when a user to update does not
+    /// exist in its tenant nothing is written and `false` is returned
+    async fn commit(&self, changes: &[Change]) -> Result<bool, RepositoryError>;
+
+    /// Permanently delete a tenant's user, returning whether it existed
+    async fn delete(&self, tenant: &str, id: Uuid) -> Result<bool, RepositoryError>;
+
+    /// Record when users were last seen, keeping the later time for each
+    async fn record_last_seen(&self, seen: &[LastSeen]) -> Result<(), RepositoryError>;
+
+    /// When a tenant's user was last seen, if ever recorded
+    async fn last_seen(
+        &self,
+        tenant: &str,
+        id: Uuid,
+    ) -> Result<Option<chrono::DateTime<chrono::Utc>>, RepositoryError>;
+
+    /// Apply the schema migrations not yet applied, returning their versions
+    async fn migrate(&self) -> Result<Vec<&'static str>, RepositoryError> {
+        Ok(Vec::new())
+    }
+
+    /// Latest migration applied, for backends with a schema
+    async fn schema_version(&self) -> Result<Option<String>, RepositoryError> {
+        Ok(None)
+    }
+
+    /// Whether the backend has a schema for `migrate` to bring up to date
+    fn has_schema(&self) -> bool {
+        false
+    }
+
+    /// Check that the backend is reachable
+    async fn ping(&self) -> Result<(), RepositoryError> {
+        Ok(())
+    }
+
+    /// Connection pool figures, for backends with a pool
+    fn pool_stats(&self) -> Option<PoolStats> {
+        None
+    }
+}
+
+/// Users of every tenant matching `query`, for jobs spanning tenants
+pub async fn list_every_tenant(
+    users: &dyn UserRepository,
+    query: &UserQuery,
+) -> Result<Vec<User>, RepositoryError> {
+    let mut all = Vec::new();
+    for tenant in users.tenants().await? {
+        all.extend(users.list(&tenant, query).await?);
+    }
+    Ok(all)
+}
+
+/// Connection pool usage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PoolStats {
+    /// Open connections
+    pub size: u32,
+    /// Open connections not in use
+    pub idle: u32,
+    /// Most connections the pool may open
+    pub max: u32,
+    /// Callers waiting for a connection
+    pub waiters: u32,
+}
+
+impl PoolStats {
+    /// Whether every connection is busy and callers are queueing for one
+    pub fn exhausted(&self) -> bool {
+        self.size >= self.max && self.idle == 0 && self.waiters > 0
+    }
+}
+
+/// The unique field another user of `user`'s tenant already has, matching
+/// the database's unique indexes: usernames exactly, emails in any case
+fn clash(user: &User, other: &User) -> Option<&'static str> {
+    if other.id == user.id || other.tenant_id != user.tenant_id {
+        None
+    } else if other.username == user.username {
+        Some("username")
+    } else if other.email.eq_ignore_ascii_case(&user.email) {
+        Some("email")
+    } else {
+        None
+    }
+}
+
+/// Users and outbox guarded together so mutations and events commit as one
+#[derive(Default)]
+struct MemoryTables {
+    users: HashMap<Uuid, User>,
+    last_seen: HashMap<Uuid, chrono::DateTime<chrono::Utc>>,
+    outbox: Vec<OutboxRecord>,
+}
+
+impl MemoryTables {
+    /// Fail as a unique index would if `user` clashes with a stored user
+    /// other than one of `replaced`, or with one of `staged`
+    fn check_unique(
+        &self,
+        user: &User,
+        replaced: &HashSet<Uuid>,
+        staged: &[&User],
+    ) -> Result<(), RepositoryError> {
+        let stored = self.users.values().filter(|other| !replaced.contains(&other.id));
+        match stored.chain(staged.iter().copied()).find_map(|other| clash(user, other)) {
+            Some(field) => Err(RepositoryError::Conflict(field.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    fn insert(&mut self, user: &User) -> Result<(), RepositoryError> {
+        if self.users.contains_key(&user.id) {
+            return Err(RepositoryError::Conflict("id".to_string()));
+        }
+        self.check_unique(user, &HashSet::new(), &[])?;
+        self.users.insert(user.id, user.clone());
+        Ok(())
+    }
+
+    /// The stored user with `id`, if it belongs to `tenant`
+    fn get(&self, tenant: &str, id: Uuid) -> Option<&User> {
+        self.users.get(&id).filter(|user| user.tenant_id == tenant)
+    }
+
+    fn update(&mut self, user: &User) -> Result<bool, RepositoryError> {
+        if self.get(&user.tenant_id, user.id).is_none() {
+            return Ok(false);
+        }
+        self.check_unique(user, &HashSet::new(), &[])?;
+        self.users.insert(user.id, user.clone());
+        Ok(true)
+    }
+
+    fn enqueue(&mut self, event: DomainEvent) {
+        let record = OutboxRecord {
+            event,
+            created_at: chrono::Utc::now(),
+        };
+        self.outbox.push(record);
+    }
+
+    fn commit(&mut self, changes: &[Change]) -> Result<bool, RepositoryError> {
+        // Check every change first so a failing one leaves the tables untouched
+        let mut inserted = HashMap::new();
+        let written: Vec<&User> = changes
+            .iter()
+            .filter_map(|change| match change {
+                Change::Insert(user) | Change::Update(user) => Some(user),
+                Change::Enqueue(_) => None,
+            })
+            .collect();
+        let replaced: HashSet<Uuid> = written.iter().map(|user| user.id).collect();
+        for (index, user) in written.iter().enumerate() {
+            self.check_unique(user, &replaced, &written[..index])?;
+        }
+        for change in changes {
+            match change {
+                Change::Insert(user) => {
+                    let taken = inserted.insert(user.id, user.tenant_id.as_str()).is_some();
+                    if self.users.contains_key(&user.id) || taken {
+                        return Err(RepositoryError::Conflict("id".to_string()));
+                    }
+                }
+                Change::Update(user) => {
+                    let stored = self.get(&user.tenant_id, user.id).is_some();
+                    if !stored && inserted.get(&user.id) != Some(&user.tenant_id.as_str()) {
+                        return Ok(false);
+                    }
+                }
+                Change::Enqueue(_) => {}
+            }
+        }
+        for change in changes {
+            match change {
+                Change::Insert(user) | Change::Update(user) => {
+                    self.users.insert(user.id, user.clone());
+                }
+                Change::Enqueue(event) => self.enqueue(event.clone()),
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// In-memory repository used for development and tests
+#[derive(Default)]
+pub struct InMemoryUserRepository {
+    tables: RwLock<MemoryTables>,
+}
+
+impl InMemoryUserRepository {
+    /// Create an empty repository
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl OutboxStore for InMemoryUserRepository {
+    async fn pending_events(&self, limit: usize) -> Result<Vec<OutboxRecord>, RepositoryError> {
+        let tables = self.tables.read().await;
+        Ok(tables.outbox.iter().take(limit).cloned().collect())
+    }
+
+    async fn mark_delivered(&self, ids: &[Uuid]) -> Result<(), RepositoryError> {
+        let mut tables = self.tables.write().await;
+        tables.outbox.retain(|record| !ids.contains(&record.event.id));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl UserRepository for InMemoryUserRepository {
+    async fn list(&self, tenant: &str, query: &UserQuery) -> Result<Vec<User>, RepositoryError> {
+        let tables = self.tables.read().await;
+        let mut all: Vec<User> = tables
+            .users
+            .values()
+            .filter(|user| user.tenant_id == tenant && query.matches(user))
+            .cloned()
+            .collect();
+        all.sort_by_key(|user| user.created_at);
+        Ok(all)
+    }
+
+    async fn get(&self, tenant: &str, id: Uuid) -> Result<Option<User>, RepositoryError> {
+        Ok(self.tables.read().await.get(tenant, id).cloned())
+    }
+
+    async fn find_by_username(
+        &self,
+        tenant: &str,
+        username: &str,
+    ) -> Result<Option<User>, RepositoryError> {
+        let tables = self.tables.read().await;
+        Ok(tables
+            .users
+            .values()
+            .find(|user| user.tenant_id == tenant && user.username == username)
+            .cloned())
+    }
+
+    async fn find_by_email(
+        &self,
+        tenant: &str,
+        email: &str,
+    ) -> Result<Option<User>, RepositoryError> {
+        let tables = self.tables.read().await;
+        Ok(tables
+            .users
+            .values()
+            .find(|user| user.tenant_id == tenant && user.email.eq_ignore_ascii_case(email))
+            .cloned())
+    }
+
+    async fn tenants(&self) -> Result<Vec<String>, RepositoryError> {
+        let tables = self.tables.read().await;
+        let tenants: BTreeSet<_> = tables.users.values().map(|user| &user.tenant_id).collect();
+        Ok(tenants.into_iter().cloned().collect())
+    }
+
+    async fn insert(&self, user: User) -> Result<User, RepositoryError> {
+        self.tables.write().await.insert(&user)?;
+        Ok(user)
+    }
+
+    async fn insert_with_event(
+        &self,
+        user: User,
+        event: DomainEvent,
+    ) -> Result<User, RepositoryError> {
+        let mut tables = self.tables.write().await;
+        tables.insert(&user)?;
+        tables.enqueue(event);
+        Ok(user)
+    }
+
+    async fn update(&self, user: User) -> Result<Option<User>, RepositoryError> {
+        let updated = self.tables.write().await.update(&user)?;
+        Ok(updated.then_some(user))
+    }
+
+    async fn update_with_event(
+        &self,
+        user: User,
+        event: DomainEvent,
+    ) -> Result<Option<User>, RepositoryError> {
+        let mut tables = self.tables.write().await;
+        if !tables.update(&user)? {
+            return Ok(None);
+        }
+        tables.enqueue(event);
+        Ok(Some(user))
+    }
+
+    async fn commit(&self, changes: &[Change]) -> Result<bool, RepositoryError> {
+        self.tables.write().await.commit(changes)
+    }
+
+    async fn delete(&self, tenant: &str, id: Uuid) -> Result<bool, RepositoryError> {
+        let mut tables = self.tables.write().await;
+        if tables.get(tenant, id).is_none() {
+            return Ok(false);
+        }
+        Ok(tables.users.remove(&id).is_some())
+    }
+
+    async fn record_last_seen(&self, seen: &[LastSeen]) -> Result<(), RepositoryError> {
+        let mut tables = self.tables.write().await;
+        for &(id, at) in seen {
+            let last = tables.last_seen.entry(id).or_insert(at);
+            *last = (*last).max(at);
+        }
+        Ok(())
+    }
+
+    async fn last_seen(
+        &self,
+        tenant: &str,
+        id: Uuid,
+    ) -> Result<Option<chrono::DateTime<chrono::Utc>>, RepositoryError> {
+        let tables = self.tables.read().await;
+        match tables.get(tenant, id) {
+            Some(_) => Ok(tables.last_seen.get(&id).copied()),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventKind;
+    use crate::tenancy::DEFAULT_TENANT;
+
+    #[tokio::test]
+    async fn test_outbox_written_with_mutation() {
+        let repository = InMemoryUserRepository::new();
+        let user = User::new("alice".to_string(), "alice@example.com".to_string());
+        let event = DomainEvent::user(EventKind::UserCreated, &user);
+        let event_id = event.id;
+        repository.insert_with_event(user, event).await.unwrap();
+
+        let pending = repository.pending_events(10).await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].event.id, event_id);
+
+        repository.mark_delivered(&[event_id]).await.unwrap();
+        assert!(repository.pending_events(10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_failed_insert_writes_no_event() {
+        let repository = InMemoryUserRepository::new();
+        let user = User::new("alice".to_string(), "alice@example.com".to_string());
+        repository.insert(user.clone()).await.unwrap();
+
+        let event = DomainEvent::user(EventKind::UserCreated, &user);
+        assert!(repository.insert_with_event(user, event).await.is_err());
+        assert!(repository.pending_events(10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_commit_writes_all_changes_or_none() {
+        let repository = InMemoryUserRepository::new();
+        let alice = User::new("alice".to_string(), "alice@example.com".to_string());
+        let bob = User::new("bob".to_string(), "bob@example.com".to_string());
+        let event = DomainEvent::user(EventKind::UserCreated, &alice);
+        let changes = [Change::Insert(alice.clone()), Change::Enqueue(event)];
+        assert!(repository.commit(&changes).await.unwrap());
+
+        // Bob doesn't exist, so neither Alice's update nor the event is written
+        let mut renamed = alice.clone();
+        renamed.username = "alicia".to_string();
+        let event = DomainEvent::user(EventKind::UserUpdated, &renamed);
+        let changes = [Change::Update(renamed), Change::Update(bob), Change::Enqueue(event)];
+        assert!(!repository.commit(&changes).await.unwrap());
+        let stored = repository.get(DEFAULT_TENANT, alice.id).await.unwrap().unwrap();
+        assert_eq!(stored.username, "alice");
+        assert_eq!(repository.pending_events(10).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_usernames_and_emails_are_unique_per_tenant() {
+        let repository = InMemoryUserRepository::new();
+        let alice = User::new("alice".to_string(), "alice@example.com".to_string());
+        repository.insert(alice.clone()).await.unwrap();
+        fn conflict<T>(result: Result<T, RepositoryError>) -> String {
+            match result {
+                Err(RepositoryError::Conflict(field)) => field,
+                Err(err) => panic!("expected a conflict, got {}", err),
+                Ok(_) => panic!("expected a conflict, got success"),
+            }
+        }
+
+        let twin = User::new("alice".to_string(), "twin@example.com".to_string());
+        assert_eq!(conflict(repository.insert(twin).await), "username");
+        let shouting = User::new("bob".to_string(), "ALICE@example.com".to_string());
+        assert_eq!(conflict(repository.insert(shouting.clone()).await), "email");
+        let elsewhere = User {
+            tenant_id: "acme".to_string(),
+            ..shouting
+        };
+        repository.insert(elsewhere).await.unwrap();
+
+        // Two new users clashing with each other in one commit write neither
+        let carol = User::new("carol".to_string(), "carol@example.com".to_string());
+        let carol2 = User::new("carol".to_string(), "carol2@example.com".to_string());
+        let changes = [Change::Insert(carol.clone()), Change::Insert(carol2)];
+        assert_eq!(conflict(repository.commit(&changes).await), "username");
+        assert!(repository.get(DEFAULT_TENANT, carol.id).await.unwrap().is_none());
+        repository.insert(carol.clone()).await.unwrap();
+        let renamed = User {
+            username: "alice".to_string(),
+            ..carol
+        };
+        assert_eq!(conflict(repository.update(renamed).await), "username");
+    }
+
+    #[tokio::test]
+    async fn test_other_tenants_users_are_out_of_reach() {
+        let repository = InMemoryUserRepository::new();
+        let alice = User {
+            tenant_id: "acme".to_string(),
+            ..User::new("alice".to_string(), "alice@example.com".to_string())
+        };
+        repository.insert(alice.clone()).await.unwrap();
+
+        let query = UserQuery::default();
+        assert!(repository.get("globex", alice.id).await.unwrap().is_none());
+        assert!(repository.find_by_username("globex", "alice").await.unwrap().is_none());
+        assert!(repository.list("globex", &query).await.unwrap().is_empty());
+        assert!(!repository.delete("globex", alice.id).await.unwrap());
+
+        // Claiming the user for another tenant finds nothing to replace
+        let mut moved = alice.clone();
+        moved.tenant_id = "globex".to_string();
+        assert!(repository.update(moved.clone()).await.unwrap().is_none());
+        assert!(!repository.commit(&[Change::Update(moved)]).await.unwrap());
+
+        let stored = repository.get("acme", alice.id).await.unwrap().unwrap();
+        assert_eq!(stored.tenant_id, "acme");
+        assert_eq!(repository.tenants().await.unwrap(), ["acme"]);
+    }
+
+    #[test]
+    fn test_pool_exhausted_only_when_callers_queue() {
+        let pool = |size, idle, waiters| PoolStats { size, idle, max: 4, waiters };
+        assert!(pool(4, 0, 2).exhausted());
+        assert!(!pool(4, 0, 0).exhausted());
+        assert!(!pool(4, 1, 2).exhausted());
+        assert!(!pool(3, 0, 2).exhausted());
+    }
+}
@@ -0,0 +1,217 @@
+//! Diagnostic metadata in response envelopes.
+//!
+//! With `Config::response_meta` on, or for requests sending
+//! `X-Include-Meta: true`, `attach_meta` adds a `meta` object to every
+//! `ApiResponse` body: the request ID the server logged, how long the request
+//! took inside the server, the API version that served it, and any
+//! deprecation notices. They join any `meta` the handler set, such as a list
+//! endpoint's pagination, in the same object. Bodies that aren't envelopes,
+//! such as problem details and event streams, are left alone. Layered inside
+//! `compress_responses` so the body is still plain JSON here.
+
+use axum::{
+    body::{boxed, Body, Full},
+    extract::State,
+    http::{header, HeaderMap, Request},
+    middleware::Next,
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Instant;
+use utoipa::ToSchema;
+
+use crate::pagination::PageMeta;
+use crate::request_id::RequestId;
+use crate::versioning::{LEGACY_PREFIX, V1_PREFIX};
+use crate::AppState;
+
+/// Request header asking for `meta` when it's off by default
+pub const INCLUDE_META_HEADER: &str = "x-include-meta";
+
+/// Server-side view of a request, for client diagnostics
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ResponseMeta {
+    /// Request ID, as in the `X-Request-Id` header and server logs
+    pub request_id: Option<String>,
+    /// Time spent handling the request, in milliseconds
+    pub duration_ms: f64,
+    /// API version that served the request, e.g. `v1`
+    pub api_version: Option<String>,
+    /// Deprecation notices for the route, if any
+    pub deprecations: Vec<String>,
+}
+
+/// The `meta` object of an envelope: pagination set by list endpoints,
+/// diagnostics added by `attach_meta`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Meta {
+    /// Where the page sits in its list, for list endpoints
+    #[serde(flatten)]
+    pub page: Option<PageMeta>,
+    /// Diagnostics, when enabled or asked for
+    #[serde(flatten)]
+    pub diagnostics: Option<ResponseMeta>,
+}
+
+/// Whether the client asked for metadata
+fn requested(headers: &HeaderMap) -> bool {
+    headers
+        .get(INCLUDE_META_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("true") || value == "1")
+}
+
+/// API version a path belongs to
+fn api_version(path: &str) -> Option<&'static str> {
+    let under = |prefix: &str| {
+        path.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    };
+    // The legacy paths are aliases of v1
+    (under(V1_PREFIX) || under(LEGACY_PREFIX)).then_some("v1")
+}
+
+/// Notices matching the deprecation headers `versioning::deprecated` sets
+fn deprecations(headers: &HeaderMap) -> Vec<String> {
+    if !headers.contains_key("deprecation") {
+        return Vec::new();
+    }
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let mut notices = vec!["this path is deprecated".to_string()];
+    if let Some(link) = header("link") {
+        let successor = link.trim_start_matches('<').split('>').next().unwrap_or(link);
+        notices.push(format!("use {} instead", successor));
+    }
+    if let Some(sunset) = header("sunset") {
+        notices.push(format!("removed after {}", sunset));
+    }
+    notices
+}
+
+/// Middleware adding `meta` to envelopes when enabled or asked for
+pub async fn attach_meta<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if !state.config().response_meta && !requested(req.headers()) {
+        return next.run(req).await;
+    }
+    let started = Instant::now();
+    let request_id = req.extensions().get::<RequestId>().map(|id| id.as_str().to_string());
+    let api_version = api_version(req.uri().path());
+    let response = next.run(req).await;
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    if !is_json {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            tracing::error!("failed to buffer response for metadata: {}", err);
+            return Response::from_parts(parts, boxed(Body::empty()));
+        }
+    };
+    let mut value = match serde_json::from_slice::<serde_json::Value>(&bytes) {
+        Ok(value) if value.get("success").is_some() => value,
+        _ => return Response::from_parts(parts, boxed(Full::from(bytes))),
+    };
+    let meta = ResponseMeta {
+        request_id,
+        duration_ms: started.elapsed().as_secs_f64() * 1000.0,
+        api_version: api_version.map(str::to_string),
+        deprecations: deprecations(&parts.headers),
+    };
+    let serde_json::Value::Object(fields) = serde_json::to_value(meta).unwrap_or_default() else {
+        return Response::from_parts(parts, boxed(Full::from(bytes)));
+    };
+    match value.get_mut("meta") {
+        Some(serde_json::Value::Object(existing)) => existing.extend(fields),
+        _ => value["meta"] = serde_json::Value::Object(fields),
+    }
+    let body = serde_json::to_vec(&value).unwrap_or_default();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, boxed(Full::from(body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::HeaderValue, response::Json, routing::get, Router};
+    use tower::ServiceExt;
+
+    use crate::pagination::PageLinks;
+    use crate::ApiResponse;
+
+    #[tokio::test]
+    async fn test_meta_only_when_asked_for() {
+        let state = AppState::new(crate::Config::default());
+        let app = Router::new()
+            .route("/api/v1/users", get(|| async { Json(ApiResponse::success(1)) }))
+            .route(
+                "/api/v1/teams",
+                get(|| async {
+                    let page = PageMeta { total: 1, page: 1, per_page: 1 };
+                    let links = PageLinks {
+                        this: "/api/v1/teams?page=1".to_string(),
+                        next: None,
+                        prev: None,
+                    };
+                    Json(ApiResponse::page(vec![1], page, links))
+                }),
+            )
+            .layer(axum::middleware::from_fn_with_state(state.clone(), attach_meta))
+            .with_state(state);
+        let fetch = |path: &'static str, include: bool| {
+            let mut request = Request::get(path);
+            if include {
+                request = request.header(INCLUDE_META_HEADER, "true");
+            }
+            let request = request
+                .extension(RequestId("req-7".to_string()))
+                .body(Body::empty())
+                .unwrap();
+            async {
+                let response = app.clone().oneshot(request).await.unwrap();
+                let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+            }
+        };
+
+        assert!(fetch("/api/v1/users", false).await.get("meta").is_none());
+        let body = fetch("/api/v1/users", true).await;
+        assert_eq!(body["data"], 1);
+        assert_eq!(body["meta"]["request_id"], "req-7");
+        assert_eq!(body["meta"]["api_version"], "v1");
+        assert_eq!(body["meta"]["deprecations"], serde_json::json!([]));
+
+        // Diagnostics join a list endpoint's pagination
+        let body = fetch("/api/v1/teams", true).await;
+        assert_eq!(body["meta"]["total"], 1);
+        assert_eq!(body["meta"]["request_id"], "req-7");
+        assert_eq!(body["links"]["self"], "/api/v1/teams?page=1");
+    }
+
+    #[test]
+    fn test_deprecation_notices_follow_headers() {
+        let mut headers = HeaderMap::new();
+        assert!(deprecations(&headers).is_empty());
+        headers.insert("deprecation", HeaderValue::from_static("true"));
+        headers.insert(
+            "link",
+            HeaderValue::from_static("</api/v1/users>; rel=\"successor-version\""),
+        );
+        assert_eq!(
+            deprecations(&headers),
+            ["this path is deprecated", "use /api/v1/users instead"]
+        );
+        assert_eq!(api_version("/api/users"), Some("v1"));
+        assert_eq!(api_version("/health"), None);
+    }
+}
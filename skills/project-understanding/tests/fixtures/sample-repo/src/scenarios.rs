@@ -0,0 +1,339 @@
+//! End-to-end scenarios composing the subsystems.
+//!
+//! Each module's tests cover it alone; a scenario walks one flow across
+//! several, through the real router served on a local port with the
+//! in-memory backends and the background tasks the flow relies on, so a
+//! change in one module that breaks another is caught. The crate has no
+//! library target for `tests/` to link against, so scenarios live here and
+//! are compiled only for tests; `test_support` serves the router for them.
+//! Add one when a subsystem lands that other subsystems react to.
+
+use axum::{body::Bytes, extract::State, http::HeaderMap, routing::post, Router};
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+use crate::audit::AuditAction;
+use crate::context::TENANT_HEADER;
+use crate::jobs;
+use crate::lifecycle::BackgroundTask;
+use crate::test_support::TestServer;
+use crate::warmup::Warmup;
+use crate::webhooks::{self, SIGNATURE_HEADER};
+use crate::{outbox, Config};
+
+/// Start the router with the tasks that turn writes into deliveries
+async fn start(config: Config) -> TestServer {
+    TestServer::start_with(config, |lifecycle| {
+        lifecycle
+            .register(Warmup::new(Duration::from_secs(1)))
+            .register(BackgroundTask::new("webhooks", webhooks::spawn_dispatcher))
+            .register(BackgroundTask::new("outbox", outbox::spawn_dispatcher).after(&["webhooks"]));
+    })
+    .await
+}
+
+/// Local endpoint receiving webhook deliveries, returning its URL
+async fn webhook_receiver() -> (String, mpsc::UnboundedReceiver<(HeaderMap, Bytes)>) {
+    async fn receive(
+        State(deliveries): State<mpsc::UnboundedSender<(HeaderMap, Bytes)>>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) {
+        let _ = deliveries.send((headers, body));
+    }
+
+    let (sender, deliveries) = mpsc::unbounded_channel();
+    let app = Router::new().route("/hook", post(receive)).with_state(sender);
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+    tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app));
+    (format!("http://{}/hook", addr), deliveries)
+}
+
+/// A WebSocket to `/ws` served for `tenant`, subscribed to all its users
+///
+/// No WebSocket client is among the dependencies, so this speaks just enough
+/// of the protocol: the upgrade, one masked text frame, and unmasked server
+/// frames shorter than 64 KiB.
+async fn websocket(harness: &TestServer, tenant: &str) -> TcpStream {
+    let mut socket = TcpStream::connect(harness.addr()).await.unwrap();
+    let upgrade = format!(
+        "GET /ws HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\
+         {}: {}\r\n\r\n",
+        harness.addr(),
+        TENANT_HEADER,
+        tenant
+    );
+    socket.write_all(upgrade.as_bytes()).await.unwrap();
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        head.push(socket.read_u8().await.unwrap());
+    }
+    assert!(head.starts_with(b"HTTP/1.1 101"), "{}", String::from_utf8_lossy(&head));
+
+    // An all-zero mask leaves the payload as it is
+    let subscribe = br#"{"action":"subscribe","targets":["all"]}"#;
+    let mut frame = vec![0x81, 0x80 | subscribe.len() as u8, 0, 0, 0, 0];
+    frame.extend_from_slice(subscribe);
+    socket.write_all(&frame).await.unwrap();
+    let reply = next_message(&mut socket).await;
+    assert_eq!(reply["type"], "subscribed", "{}", reply);
+    socket
+}
+
+/// The next text message the server sends over `socket`
+async fn next_message(socket: &mut TcpStream) -> Value {
+    loop {
+        let opcode = socket.read_u8().await.unwrap() & 0x0f;
+        let len = match socket.read_u8().await.unwrap() & 0x7f {
+            126 => socket.read_u16().await.unwrap() as usize,
+            len => len as usize,
+        };
+        let mut payload = vec![0; len];
+        socket.read_exact(&mut payload).await.unwrap();
+        if opcode == 0x1 {
+            return serde_json::from_slice(&payload).unwrap();
+        }
+    }
+}
+
+/// SSE text received on `response` up to the frame mentioning `needle`
+async fn sse_until(response: &mut reqwest::Response, needle: &str) -> String {
+    let mut text = String::new();
+    while !text.contains(needle) {
+        let chunk = response.chunk().await.unwrap().expect("event stream ended");
+        text.push_str(&String::from_utf8_lossy(&chunk));
+    }
+    text
+}
+
+fn action(action: AuditAction) -> Value {
+    serde_json::to_value(action).unwrap()
+}
+
+#[tokio::test]
+async fn test_created_user_fires_signed_webhook_and_is_audited() {
+    let harness = start(Config::default()).await;
+    let (url, mut deliveries) = webhook_receiver().await;
+    let registration = json!({ "url": url, "secret": "shh", "events": ["user.created"] });
+    let registered = harness
+        .admin(Method::POST, "/api/v1/admin/webhooks")
+        .json(&registration)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(registered.status(), StatusCode::CREATED);
+
+    let (status, created) = harness.create_user("/api/v1/users", "scenario-alice").await;
+    assert_eq!(status, StatusCode::CREATED, "{}", created);
+    let id = created["data"]["id"].clone();
+
+    let delivery = tokio::time::timeout(Duration::from_secs(5), deliveries.recv()).await;
+    let (headers, body) = delivery.expect("no webhook delivered").unwrap();
+    let event: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(event["type"], "user.created");
+    assert_eq!(event["user"]["id"], id);
+    let signature = headers[SIGNATURE_HEADER].to_str().unwrap();
+    let (timestamp, signed) = signature
+        .strip_prefix("t=")
+        .and_then(|rest| rest.split_once(",v1="))
+        .expect("malformed signature header");
+    assert_eq!(webhooks::sign("shh", timestamp.parse().unwrap(), &body), signed);
+
+    let entries = harness.audit("user").await;
+    let create = action(AuditAction::Create);
+    assert!(
+        entries.iter().any(|entry| entry["entity_id"] == id && entry["action"] == create),
+        "{:?}",
+        entries
+    );
+}
+
+#[tokio::test]
+async fn test_read_only_mode_refuses_writes_until_switched_off() {
+    let harness = start(Config::default()).await;
+    let enable = json!({ "enabled": true, "reason": "region failover" });
+    let switched = harness
+        .admin(Method::PUT, "/api/v1/admin/read-only")
+        .json(&enable)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(switched.status(), StatusCode::OK);
+
+    let (status, refused) = harness.create_user("/api/v1/users", "scenario-bob").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(refused.to_string().contains("READ_ONLY"), "{}", refused);
+    let listed = harness.request(Method::GET, "/api/v1/users").send().await.unwrap();
+    assert_eq!(listed.status(), StatusCode::OK);
+
+    let disable = json!({ "enabled": false });
+    harness
+        .admin(Method::PUT, "/api/v1/admin/read-only")
+        .json(&disable)
+        .send()
+        .await
+        .unwrap();
+    let (status, created) = harness.create_user("/api/v1/users", "scenario-bob").await;
+    assert_eq!(status, StatusCode::CREATED, "{}", created);
+}
+
+#[tokio::test]
+async fn test_kill_switch_disables_route_and_alias_and_is_audited() {
+    let harness = start(Config::default()).await;
+    let switch_off = json!({
+        "route": "/api/v1/users",
+        "method": "POST",
+        "reason": "signup abuse",
+        "ttl_seconds": 600,
+    });
+    let switched = harness
+        .admin(Method::POST, "/api/v1/admin/kill-switches")
+        .json(&switch_off)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(switched.status(), StatusCode::CREATED);
+    let switch: Value = switched.json().await.unwrap();
+    let id = switch["data"]["id"].as_str().unwrap().to_string();
+
+    for path in ["/api/v1/users", "/api/users"] {
+        let (status, refused) = harness.create_user(path, "scenario-carol").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{}", path);
+        assert!(refused.to_string().contains("ENDPOINT_DISABLED"), "{}", refused);
+    }
+    let listed = harness.request(Method::GET, "/api/v1/users").send().await.unwrap();
+    assert_eq!(listed.status(), StatusCode::OK);
+
+    let path = format!("/api/v1/admin/kill-switches/{}", id);
+    let removed = harness.admin(Method::DELETE, &path).send().await.unwrap();
+    assert_eq!(removed.status(), StatusCode::NO_CONTENT);
+    let (status, created) = harness.create_user("/api/v1/users", "scenario-carol").await;
+    assert_eq!(status, StatusCode::CREATED, "{}", created);
+
+    let actions: Vec<_> = harness
+        .audit("kill_switch")
+        .await
+        .into_iter()
+        .map(|entry| entry["action"].clone())
+        .collect();
+    assert_eq!(actions, vec![action(AuditAction::Create), action(AuditAction::Delete)]);
+}
+
+#[tokio::test]
+async fn test_tenants_only_see_their_own_users_and_events() {
+    let harness = start(Config::default()).await;
+    let (url, mut deliveries) = webhook_receiver().await;
+    let registration = json!({ "url": url, "secret": "shh", "events": ["user.created"] });
+    let registered = harness
+        .admin(Method::POST, "/api/v1/admin/webhooks")
+        .header(TENANT_HEADER, "acme")
+        .json(&registration)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(registered.status(), StatusCode::CREATED);
+    let mut sse = harness
+        .request(Method::GET, "/api/v1/users/events")
+        .header(TENANT_HEADER, "acme")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(sse.status(), StatusCode::OK);
+    let mut ws = websocket(&harness, "acme").await;
+
+    let create = |tenant: &'static str, username: &'static str| {
+        let body = json!({ "username": username, "email": format!("{}@example.com", username) });
+        let request = harness.request(Method::POST, "/api/v1/users").header(TENANT_HEADER, tenant);
+        async move {
+            let response = request.json(&body).send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED);
+            let created: Value = response.json().await.unwrap();
+            created["data"]["id"].as_str().unwrap().to_string()
+        }
+    };
+    // Globex's user comes first, so a leak would be the first thing acme sees
+    let bob = create("globex", "scenario-bob").await;
+    let alice = create("acme", "scenario-alice").await;
+
+    let get = |tenant: &'static str, path: String| {
+        harness.request(Method::GET, &path).header(TENANT_HEADER, tenant).send()
+    };
+    let listed = get("acme", "/api/v1/users".to_string()).await.unwrap();
+    let listed: Value = listed.json().await.unwrap();
+    let ids: Vec<_> = listed["data"].as_array().unwrap().iter().map(|user| &user["id"]).collect();
+    assert_eq!(ids, vec![&json!(alice)]);
+    let bob_path = format!("/api/v1/users/{}", bob);
+    assert_eq!(get("acme", bob_path.clone()).await.unwrap().status(), StatusCode::NOT_FOUND);
+    assert_eq!(get("globex", bob_path).await.unwrap().status(), StatusCode::OK);
+
+    let wait = Duration::from_secs(5);
+    let streamed = tokio::time::timeout(wait, sse_until(&mut sse, &alice)).await;
+    let streamed = streamed.expect("no event streamed");
+    assert!(!streamed.contains(&bob), "{}", streamed);
+    let pushed = tokio::time::timeout(wait, next_message(&mut ws)).await.expect("no event pushed");
+    assert_eq!(pushed["event"]["user"]["id"], json!(alice), "{}", pushed);
+
+    let delivery = tokio::time::timeout(wait, deliveries.recv()).await;
+    let (_, body) = delivery.expect("no webhook delivered").unwrap();
+    let event: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(event["user"]["id"], json!(alice));
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(deliveries.try_recv().is_err(), "globex's user was delivered to acme's webhook");
+}
+
+#[tokio::test]
+async fn test_export_runs_on_the_job_queue_and_is_downloadable() {
+    let harness = TestServer::start_with(Config::default(), |lifecycle| {
+        lifecycle.register(BackgroundTask::new("jobs", jobs::spawn_workers));
+    })
+    .await;
+    let (status, created) = harness.create_user("/api/v1/users", "scenario-dave").await;
+    assert_eq!(status, StatusCode::CREATED, "{}", created);
+    let id = created["data"]["id"].as_str().unwrap().to_string();
+
+    let path = format!("/api/v1/users/{}/export", id);
+    let requested = harness.admin(Method::POST, &path).send().await.unwrap();
+    assert_eq!(requested.status(), StatusCode::ACCEPTED);
+    let requested: Value = requested.json().await.unwrap();
+    let export = requested["data"]["id"].as_str().unwrap().to_string();
+    let job_path = format!("/api/v1/admin/jobs/{}", export);
+    let job = harness.admin(Method::GET, &job_path).send().await.unwrap();
+    let job: Value = job.json().await.unwrap();
+    assert_eq!(job["data"]["kind"], jobs::USER_EXPORT);
+
+    let status_path = format!("/api/v1/users/{}/exports/{}", id, export);
+    let ready = async {
+        loop {
+            let response = harness.admin(Method::GET, &status_path).send().await.unwrap();
+            let status: Value = response.json().await.unwrap();
+            if status["data"]["state"] != "pending" {
+                return status;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    };
+    let status = tokio::time::timeout(Duration::from_secs(5), ready).await.expect("export stuck");
+    assert_eq!(status["data"]["state"], "ready", "{}", status);
+
+    let download = format!("{}/download", status_path);
+    let archive = harness.admin(Method::GET, &download).send().await.unwrap();
+    assert_eq!(archive.status(), StatusCode::OK);
+    let archive: Value = archive.json().await.unwrap();
+    assert_eq!(archive["profile"]["username"], "scenario-dave");
+    assert_eq!(archive["audit"][0]["action"], action(AuditAction::Create));
+
+    let listing = harness.admin(Method::GET, "/api/v1/admin/jobs").send().await.unwrap();
+    let listing: Value = listing.json().await.unwrap();
+    assert_eq!(listing["data"]["counts"]["succeeded"], 1, "{}", listing);
+    let retried = format!("{}/retry", job_path);
+    let refused = harness.admin(Method::POST, &retried).send().await.unwrap();
+    assert_eq!(refused.status(), StatusCode::CONFLICT);
+}
@@ -0,0 +1,335 @@
+//! Periodic maintenance tasks.
+//!
+//! The tasks are defined here, in `Task`; configuration only turns them on.
+//! A task runs every `interval_secs` once named in `Config::schedule.tasks`,
+//! its first run one interval after startup so a restart never triggers a
+//! burst of maintenance. Each run is recorded, and
+//! `GET /api/v1/admin/schedule` lists every task with whether it is enabled,
+//! when it runs next, and how its last run went.
+//!
+//! Every instance runs its own schedule. The tasks are idempotent, so two
+//! instances running the same one only repeat work, never undo it.
+
+use axum::{extract::State, response::Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::audit::AuditContext;
+use crate::auth::Admin;
+use crate::repository::{self, UserQuery};
+use crate::service::{self, UserPatch};
+use crate::{ApiResponse, AppState};
+
+/// How often the runner checks for due tasks
+const TICK: Duration = Duration::from_secs(1);
+
+/// When and how often one task runs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Schedule {
+    /// Seconds between runs
+    pub interval_secs: u64,
+}
+
+/// Maintenance tasks enabled and their settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScheduleConfig {
+    /// Tasks to run, by name; those not named are off
+    pub tasks: BTreeMap<String, Schedule>,
+    /// Days without being seen after which `deactivate_idle_users`
+    /// deactivates a user
+    pub idle_user_days: u32,
+    /// Days of audit entries `compact_audit_log` keeps
+    pub audit_retention_days: u32,
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        ScheduleConfig {
+            tasks: BTreeMap::new(),
+            idle_user_days: 90,
+            audit_retention_days: 365,
+        }
+    }
+}
+
+/// A maintenance task
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Task {
+    /// Drop idempotency keys and debug targets past their lifetime
+    PurgeExpired,
+    /// Deactivate active users not seen for `idle_user_days`
+    DeactivateIdleUsers,
+    /// Drop audit entries older than `audit_retention_days`
+    CompactAuditLog,
+}
+
+impl Task {
+    /// Every task, in the order they are listed
+    pub const ALL: [Task; 3] =
+        [Task::PurgeExpired, Task::DeactivateIdleUsers, Task::CompactAuditLog];
+
+    /// Name the task is configured by
+    pub fn name(self) -> &'static str {
+        match self {
+            Task::PurgeExpired => "purge_expired",
+            Task::DeactivateIdleUsers => "deactivate_idle_users",
+            Task::CompactAuditLog => "compact_audit_log",
+        }
+    }
+
+    /// Run the task once, returning what it did
+    pub async fn run(self, state: &AppState) -> Result<String, String> {
+        let config = state.config();
+        match self {
+            Task::PurgeExpired => {
+                let keys = state.idempotency.purge_expired(&config.idempotency, Instant::now());
+                let targets = state.debug_targets.purge_expired();
+                Ok(format!("removed {} idempotency keys and {} debug targets", keys, targets))
+            }
+            Task::DeactivateIdleUsers => {
+                let idle = chrono::Duration::days(config.schedule.idle_user_days.into());
+                let count = deactivate_idle_users(state, Utc::now() - idle).await?;
+                Ok(format!("deactivated {} users", count))
+            }
+            Task::CompactAuditLog => {
+                let retention = chrono::Duration::days(config.schedule.audit_retention_days.into());
+                let before = Utc::now() - retention;
+                let count = state.audit.prune(before).await.map_err(|err| err.to_string())?;
+                Ok(format!("removed {} audit entries", count))
+            }
+        }
+    }
+}
+
+/// An error for a task name in `schedule.tasks` that isn't defined
+pub fn check_name(name: &str) -> Option<String> {
+    if Task::ALL.iter().any(|task| task.name() == name) {
+        None
+    } else {
+        Some(format!("schedule.tasks names unknown task {:?}", name))
+    }
+}
+
+/// Deactivate active users last seen, or created if never seen, before
+/// `cutoff`, through the service so each change is audited and published
+async fn deactivate_idle_users(state: &AppState, cutoff: DateTime<Utc>) -> Result<u64, String> {
+    let context = AuditContext {
+        actor: "scheduler".to_string(),
+        request_id: format!("schedule-{}", Uuid::new_v4()),
+        client_ip: None,
+    };
+    let patch = UserPatch {
+        username: None,
+        email: None,
+        is_active: Some(false),
+        metadata: None,
+    };
+    let users = repository::list_every_tenant(state.users.as_ref(), &UserQuery::default())
+        .await
+        .map_err(|err| err.to_string())?;
+    let mut count = 0;
+    for user in users.into_iter().filter(|user| user.is_active) {
+        let seen = state.users.last_seen(&user.tenant_id, user.id).await;
+        let seen = seen.map_err(|err| err.to_string())?.unwrap_or(user.created_at);
+        if seen < cutoff {
+            service::update_user(state, &context, user, &patch)
+                .await
+                .map_err(|err| err.to_string())?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// How one run of a task went
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LastRun {
+    /// When it started
+    pub started_at: DateTime<Utc>,
+    /// How long it took
+    pub duration_ms: u64,
+    /// Whether it succeeded
+    pub ok: bool,
+    /// What it did, or why it failed
+    pub message: String,
+}
+
+/// Runs of one task since startup
+#[derive(Debug, Clone, Default, Serialize)]
+struct Runs {
+    next_run: Option<DateTime<Utc>>,
+    runs: u64,
+    failures: u64,
+    last_run: Option<LastRun>,
+}
+
+/// One task as reported to admins
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskReport {
+    /// Name the task is configured by
+    pub name: &'static str,
+    /// Whether `schedule.tasks` names it
+    pub enabled: bool,
+    /// Seconds between runs, when enabled
+    pub interval_secs: Option<u64>,
+    /// When it runs next, when enabled
+    pub next_run: Option<DateTime<Utc>>,
+    /// Runs since startup
+    pub runs: u64,
+    /// Runs since startup that failed
+    pub failures: u64,
+    /// The latest run, if any
+    pub last_run: Option<LastRun>,
+}
+
+/// Run history of each task on this instance
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    runs: Mutex<BTreeMap<&'static str, Runs>>,
+}
+
+impl Scheduler {
+    /// Tasks due at `now`, scheduling the first run of newly enabled ones
+    fn due(&self, config: &ScheduleConfig, now: DateTime<Utc>) -> Vec<Task> {
+        let mut runs = self.runs.lock().expect("scheduler lock poisoned");
+        let mut due = Vec::new();
+        for task in Task::ALL {
+            let entry = runs.entry(task.name()).or_default();
+            let Some(schedule) = config.tasks.get(task.name()) else {
+                entry.next_run = None;
+                continue;
+            };
+            let interval = chrono::Duration::seconds(schedule.interval_secs.max(1) as i64);
+            match entry.next_run {
+                Some(next) if next <= now => due.push(task),
+                Some(_) => {}
+                None => entry.next_run = Some(now + interval),
+            }
+        }
+        due
+    }
+
+    /// Record a finished run and schedule the next one
+    fn finish(&self, task: Task, run: LastRun, interval_secs: u64) {
+        let mut runs = self.runs.lock().expect("scheduler lock poisoned");
+        let entry = runs.entry(task.name()).or_default();
+        entry.runs += 1;
+        entry.failures += u64::from(!run.ok);
+        entry.next_run = Some(Utc::now() + chrono::Duration::seconds(interval_secs.max(1) as i64));
+        entry.last_run = Some(run);
+    }
+
+    /// Every task with its schedule and runs
+    pub fn report(&self, config: &ScheduleConfig) -> Vec<TaskReport> {
+        let runs = self.runs.lock().expect("scheduler lock poisoned");
+        Task::ALL
+            .iter()
+            .map(|task| {
+                let schedule = config.tasks.get(task.name());
+                let entry = runs.get(task.name()).cloned().unwrap_or_default();
+                TaskReport {
+                    name: task.name(),
+                    enabled: schedule.is_some(),
+                    interval_secs: schedule.map(|schedule| schedule.interval_secs),
+                    next_run: entry.next_run.filter(|_| schedule.is_some()),
+                    runs: entry.runs,
+                    failures: entry.failures,
+                    last_run: entry.last_run,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Run every task due at `now`, one after another
+pub async fn run_due(state: &AppState, now: DateTime<Utc>) {
+    let config = state.config().schedule.clone();
+    for task in state.scheduler.due(&config, now) {
+        let started_at = Utc::now();
+        let started = Instant::now();
+        let result = task.run(state).await;
+        let duration_ms = started.elapsed().as_millis() as u64;
+        match &result {
+            Ok(message) => tracing::info!(task = task.name(), duration_ms, "{}", message),
+            Err(err) => tracing::error!(task = task.name(), duration_ms, "task failed: {}", err),
+        }
+        let ok = result.is_ok();
+        let message = result.unwrap_or_else(|err| err);
+        let run = LastRun {
+            started_at,
+            duration_ms,
+            ok,
+            message,
+        };
+        let interval_secs = config.tasks.get(task.name()).map_or(1, |s| s.interval_secs);
+        state.scheduler.finish(task, run, interval_secs);
+    }
+}
+
+/// Spawn the task running the schedule
+pub fn spawn_runner(state: Arc<AppState>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            run_due(&state, Utc::now()).await;
+            tokio::time::sleep(TICK).await;
+        }
+    })
+}
+
+/// Every maintenance task and how its last run went
+pub async fn list_schedule(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+) -> Json<ApiResponse<Vec<TaskReport>>> {
+    let report = state.scheduler.report(&state.config().schedule);
+    Json(ApiResponse::success(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, User};
+
+    #[tokio::test]
+    async fn test_enabled_task_runs_once_due_and_is_reported() {
+        let mut config = Config::default();
+        let schedule = Schedule { interval_secs: 60 };
+        config.schedule.tasks.insert("deactivate_idle_users".to_string(), schedule);
+        let state = AppState::new(config);
+        let mut idle = User::new("idle".to_string(), "idle@example.com".to_string());
+        idle.created_at = Utc::now() - chrono::Duration::days(200);
+        let idle = state.users.insert(idle).await.unwrap();
+        let recent = User::new("recent".to_string(), "recent@example.com".to_string());
+        let recent = state.users.insert(recent).await.unwrap();
+
+        let now = Utc::now();
+        run_due(&state, now).await;
+        let report = state.scheduler.report(&state.config().schedule);
+        assert_eq!(report.iter().filter(|task| task.enabled).count(), 1);
+        let task = &report[1];
+        assert_eq!((task.name, task.runs), ("deactivate_idle_users", 0));
+        assert_eq!(task.next_run, Some(now + chrono::Duration::seconds(60)));
+
+        run_due(&state, now + chrono::Duration::seconds(60)).await;
+        let report = state.scheduler.report(&state.config().schedule);
+        let last_run = report[1].last_run.clone().unwrap();
+        assert_eq!((report[1].runs, last_run.ok), (1, true));
+        assert_eq!(last_run.message, "deactivated 1 users");
+        let tenant = &idle.tenant_id;
+        assert!(!state.users.get(tenant, idle.id).await.unwrap().unwrap().is_active);
+        assert!(state.users.get(tenant, recent.id).await.unwrap().unwrap().is_active);
+        assert_eq!(report[0].runs, 0);
+    }
+
+    #[test]
+    fn test_unknown_task_names_are_reported() {
+        assert_eq!(check_name("compact_audit_log"), None);
+        assert!(check_name("vacuum").is_some());
+    }
+}
@@ -0,0 +1,226 @@
+//! Typo-tolerant search of a tenant's users by username and email.
+//!
+//! `GET /api/v1/users/search?q=...` ranks users by trigram similarity, the
+//! measure of Postgres' `pg_trgm`: both strings are split into words of
+//! letters and digits, each padded and cut into three-character windows,
+//! and similarity is the share of windows they have in common. A user
+//! matches when its username or email is at least `THRESHOLD` similar to the
+//! query, which `pg_trgm` also uses by default, or contains it outright, so
+//! short prefixes match too. Results come best match first, ties by
+//! username, a page of `limit` from `offset`, with the total number of
+//! matches.
+//!
+//! Postgres answers from the trigram indexes of migration
+//! `0004_user_search`; the other backends score their users here. Emails
+//! are only searched for admins, since no one else may see them.
+
+use axum::{
+    extract::{Query, State},
+    response::Json,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use crate::auth::Admin;
+use crate::error::AppError;
+use crate::redaction::Redacted;
+use crate::tenancy::Tenant;
+use crate::{ApiResponse, AppState, User};
+
+/// Least similarity a username or email must have to the query to match
+pub const THRESHOLD: f32 = 0.3;
+
+/// Longest query accepted, in characters
+const MAX_QUERY: usize = 100;
+
+/// Largest page a search returns
+const MAX_LIMIT: usize = 100;
+
+/// Search parameters after validation
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchQuery {
+    /// Text to match, trimmed and lowercased
+    pub q: String,
+    /// Match email addresses as well as usernames
+    pub emails: bool,
+    /// Include soft-deleted users
+    pub include_deleted: bool,
+    /// Matches in the page
+    pub limit: usize,
+    /// Matches skipped before the page
+    pub offset: usize,
+}
+
+/// A user matching a search, and how well
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    /// The user
+    pub user: User,
+    /// Similarity to the query, from 0 to 1
+    pub score: f32,
+}
+
+/// One page of search results
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchPage {
+    /// Matches in the page, best first
+    pub hits: Vec<SearchHit>,
+    /// Matches in every page
+    pub total: u64,
+    /// Matches skipped before the page
+    pub offset: usize,
+}
+
+/// Trigrams of `text`, as `pg_trgm` extracts them
+fn trigrams(text: &str) -> BTreeSet<[char; 3]> {
+    let lower = text.to_lowercase();
+    let mut trigrams = BTreeSet::new();
+    for word in lower.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty()) {
+        let padded: Vec<char> = "  ".chars().chain(word.chars()).chain(" ".chars()).collect();
+        trigrams.extend(padded.windows(3).map(|window| [window[0], window[1], window[2]]));
+    }
+    trigrams
+}
+
+/// Trigram similarity of two strings, from 0 to 1
+pub fn similarity(a: &str, b: &str) -> f32 {
+    let (a, b) = (trigrams(a), trigrams(b));
+    let shared = a.intersection(&b).count();
+    let all = a.len() + b.len() - shared;
+    if all == 0 {
+        0.0
+    } else {
+        shared as f32 / all as f32
+    }
+}
+
+/// How well `user` matches `query`, or `None` if it doesn't
+pub fn score(user: &User, query: &SearchQuery) -> Option<f32> {
+    let mut fields = vec![user.username.to_lowercase()];
+    if query.emails {
+        fields.push(user.email.to_lowercase());
+    }
+    let best = fields.iter().map(|field| similarity(field, &query.q)).fold(0.0, f32::max);
+    let contains = fields.iter().any(|field| field.contains(&query.q));
+    (best >= THRESHOLD || contains).then_some(best)
+}
+
+/// Rank `users` against `query` and cut out the page it asks for
+pub fn rank(users: Vec<User>, query: &SearchQuery) -> SearchPage {
+    let mut hits: Vec<SearchHit> = users
+        .into_iter()
+        .filter(|user| query.include_deleted || !user.is_deleted())
+        .filter_map(|user| score(&user, query).map(|score| SearchHit { user, score }))
+        .collect();
+    hits.sort_by(|a, b| {
+        b.score.total_cmp(&a.score).then_with(|| a.user.username.cmp(&b.user.username))
+    });
+    let total = hits.len() as u64;
+    let hits = hits.into_iter().skip(query.offset).take(query.limit).collect();
+    SearchPage {
+        hits,
+        total,
+        offset: query.offset,
+    }
+}
+
+/// Query string of `GET /api/v1/users/search`
+#[derive(Debug, Deserialize)]
+pub struct SearchParams {
+    /// Text to search for
+    #[serde(default)]
+    pub q: String,
+    /// Matches in the page; defaults to 20, at most 100
+    pub limit: Option<usize>,
+    /// Matches to skip
+    #[serde(default)]
+    pub offset: usize,
+    /// Include soft-deleted users; admin only
+    #[serde(default)]
+    pub include_deleted: bool,
+}
+
+impl SearchParams {
+    /// Validate the parameters for a caller, admin or not
+    pub fn query(self, admin: Option<Admin>) -> Result<SearchQuery, AppError> {
+        let q = self.q.trim().to_lowercase();
+        let mut errors = Vec::new();
+        if q.is_empty() {
+            errors.push("q must not be empty".to_string());
+        }
+        if q.chars().count() > MAX_QUERY {
+            errors.push(format!("q must be at most {} characters", MAX_QUERY));
+        }
+        if !errors.is_empty() {
+            return Err(AppError::Validation(errors));
+        }
+        if self.include_deleted && admin.is_none() {
+            return Err(AppError::Forbidden("include_deleted requires admin".to_string()));
+        }
+        Ok(SearchQuery {
+            q,
+            emails: admin.is_some(),
+            include_deleted: self.include_deleted,
+            limit: self.limit.unwrap_or(20).clamp(1, MAX_LIMIT),
+            offset: self.offset,
+        })
+    }
+}
+
+/// Search a tenant's users by username, and by email for admins
+#[tracing::instrument(skip_all)]
+pub async fn search_users(
+    State(state): State<Arc<AppState>>,
+    admin: Option<Admin>,
+    Tenant(tenant): Tenant,
+    Query(params): Query<SearchParams>,
+) -> Result<Redacted<Json<ApiResponse<SearchPage>>>, AppError> {
+    let query = params.query(admin)?;
+    let page = state.users.search(&tenant, &query).await?;
+    Ok(Redacted::of::<User>(Json(ApiResponse::success(page))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(q: &str) -> SearchQuery {
+        SearchQuery {
+            q: q.to_string(),
+            emails: true,
+            include_deleted: false,
+            limit: 10,
+            offset: 0,
+        }
+    }
+
+    #[test]
+    fn test_similarity_matches_pg_trgm() {
+        // pg_trgm: similarity('word', 'two words') = 0.36363637
+        assert!((similarity("word", "two words") - 4.0 / 11.0).abs() < 1e-6);
+        assert_eq!(similarity("alice", "ALICE"), 1.0);
+        assert_eq!(similarity("", "alice"), 0.0);
+    }
+
+    #[test]
+    fn test_typos_match_and_rank_below_exact() {
+        let users = ["alice", "alicia", "bob", "malice"]
+            .iter()
+            .map(|name| User::new(name.to_string(), format!("{}@example.com", name)))
+            .collect::<Vec<_>>();
+        let page = rank(users.clone(), &query("alicce"));
+        let names: Vec<&str> = page.hits.iter().map(|hit| hit.user.username.as_str()).collect();
+        assert_eq!(names, ["alice", "alicia"]);
+
+        let page = rank(users.clone(), &query("alice"));
+        assert_eq!(page.total, 3);
+        let names: Vec<&str> = page.hits.iter().map(|hit| hit.user.username.as_str()).collect();
+        assert_eq!(names, ["alice", "alicia", "malice"]);
+        let second = SearchQuery {
+            offset: 2,
+            ..query("alice")
+        };
+        assert_eq!(rank(users, &second).hits.len(), 1);
+    }
+}
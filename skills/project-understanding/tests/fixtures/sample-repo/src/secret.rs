@@ -0,0 +1,63 @@
+//! Configuration values kept out of logs and dumps.
+//!
+//! A `Secret` prints and serializes as `[redacted]`, so a `Config` can be
+//! logged with `{:?}` or returned as JSON without leaking credentials. Code
+//! that needs the value asks for it with `expose`, which makes every use
+//! easy to find. Secrets still deserialize from their plain value, so
+//! configuration files don't change; since they don't serialize back,
+//! `reload` compares and carries them over directly.
+
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+
+/// What a secret shows instead of its value
+pub const REDACTED: &str = "[redacted]";
+
+/// A value that never appears in `Debug`, `Display`, or serialized output
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    /// Wrap a value
+    pub fn new(value: T) -> Self {
+        Secret(value)
+    }
+
+    /// The value itself
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret({})", REDACTED)
+    }
+}
+
+impl<T> fmt::Display for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<T> Serialize for Secret<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_is_redacted_everywhere_but_expose() {
+        let secret: Secret<String> = serde_json::from_str("\"postgres://app:pw@db/app\"").unwrap();
+        assert_eq!(secret.expose(), "postgres://app:pw@db/app");
+        assert_eq!(format!("{:?}", secret), "Secret([redacted])");
+        assert_eq!(secret.to_string(), REDACTED);
+        assert_eq!(serde_json::to_string(&secret).unwrap(), "\"[redacted]\"");
+    }
+}
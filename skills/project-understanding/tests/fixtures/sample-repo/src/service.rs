@@ -0,0 +1,575 @@
+//! User operations shared by the REST, GraphQL, and other front ends.
+//!
+//! Each operation applies the same visibility rules, emits the same outbox
+//! event, and writes the same audit entry regardless of which API invoked it.
+//! The write, its event, and its audit entry go through one `UnitOfWork`, so
+//! they are made together or not at all. Each operation acts within one
+//! tenant, which callers pass as the request's `Tenant`. Callers are
+//! responsible for authorization.
+
+use axum::http::StatusCode;
+use serde::Deserialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::audit::{AuditAction, AuditContext};
+use crate::cache::{Lookup, USERS_TAG};
+use crate::error::ErrorReport;
+use crate::events::{DomainEvent, EventKind};
+use crate::repository::{RepositoryError, UserQuery};
+use crate::unit_of_work::UnitOfWork;
+use crate::{metadata, replication, AppState, User};
+
+/// Errors returned by user operations
+#[derive(Debug)]
+pub enum ServiceError {
+    /// No visible user has the given ID
+    NotFound,
+    /// A unique field, named here, is already in use by another user
+    Taken(String),
+    /// The operation conflicts with the user's current state
+    Conflict(String),
+    /// The input failed validation
+    Invalid(Vec<String>),
+    /// The storage backend failed
+    Backend(ErrorReport),
+    /// The storage backend can't be reached, for now
+    Unavailable(ErrorReport),
+    /// The instance is in read-only mode
+    ReadOnly,
+}
+
+impl ServiceError {
+    /// HTTP status for REST responses
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ServiceError::NotFound => StatusCode::NOT_FOUND,
+            ServiceError::Taken(_) | ServiceError::Conflict(_) => StatusCode::CONFLICT,
+            ServiceError::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ServiceError::Backend(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ServiceError::Unavailable(_) | ServiceError::ReadOnly => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServiceError::NotFound => write!(f, "user not found"),
+            ServiceError::Taken(field) => write!(f, "{} already exists", field),
+            ServiceError::Conflict(message) => write!(f, "{}", message),
+            ServiceError::Invalid(errors) => write!(f, "invalid input: {}", errors.join("; ")),
+            ServiceError::Backend(report) => write!(f, "storage error: {}", report),
+            ServiceError::Unavailable(report) => write!(f, "storage unavailable: {}", report),
+            ServiceError::ReadOnly => write!(f, "instance is read-only; writes are refused"),
+        }
+    }
+}
+
+impl std::error::Error for ServiceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ServiceError::Backend(report) | ServiceError::Unavailable(report) => Some(report),
+            _ => None,
+        }
+    }
+}
+
+impl From<RepositoryError> for ServiceError {
+    fn from(err: RepositoryError) -> Self {
+        match err {
+            RepositoryError::Conflict(field) => ServiceError::Taken(field),
+            RepositoryError::Backend(report) => ServiceError::Backend(report),
+            RepositoryError::Unavailable(report) => ServiceError::Unavailable(report),
+        }
+    }
+}
+
+/// Check a username and email, returning every problem found
+pub fn validate(username: &str, email: &str) -> Vec<String> {
+    let mut errors = Vec::new();
+    let username = username.trim();
+    if username.is_empty() {
+        errors.push("username is required".to_string());
+    } else if username.len() > 64 {
+        errors.push("username must be at most 64 characters".to_string());
+    }
+    let allowed = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-');
+    if !username.chars().all(allowed) {
+        errors.push("username may only contain letters, digits, '.', '_' and '-'".to_string());
+    }
+    if !is_email(email.trim()) {
+        errors.push("email is not a valid address".to_string());
+    }
+    errors
+}
+
+/// Loose syntactic check: one `@`, no whitespace, and a dotted domain
+pub fn is_email(email: &str) -> bool {
+    let Some((local, domain)) = email.split_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && !domain.contains('@')
+        && !email.chars().any(char::is_whitespace)
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+}
+
+/// List a tenant's users passing `query`.
+///
+/// Served from the list cache, stale-while-revalidate per its `CachePolicy`.
+pub async fn list_users(
+    state: &AppState,
+    tenant: &str,
+    query: &UserQuery,
+) -> Result<Vec<User>, ServiceError> {
+    let key = format!(
+        "users?tenant={}&include_deleted={}&metadata={:?}",
+        tenant, query.include_deleted, query.metadata
+    );
+    match state.list_cache.lookup(&key) {
+        Lookup::Fresh(users) => return Ok(users),
+        Lookup::Stale { value, refresh } => {
+            if refresh {
+                let users = state.users.clone();
+                let cache = state.list_cache.clone();
+                let (tenant, query) = (tenant.to_string(), query.clone());
+                state.tasks.spawn("list_cache_refresh", async move {
+                    let fresh = users.list(&tenant, &query).await;
+                    if let Err(err) = &fresh {
+                        tracing::warn!("failed to refresh {}: {}", key, err);
+                    }
+                    cache.finish_refresh(&key, fresh.ok(), &[USERS_TAG]);
+                });
+            }
+            return Ok(value);
+        }
+        Lookup::Miss => {}
+    }
+    let users = state.users.list(tenant, query).await?;
+    state.list_cache.put(&key, users.clone(), &[USERS_TAG]);
+    Ok(users)
+}
+
+/// Fetch a tenant's user, treating soft-deleted users as missing unless asked
+pub async fn get_user(
+    state: &AppState,
+    tenant: &str,
+    id: Uuid,
+    include_deleted: bool,
+) -> Result<User, ServiceError> {
+    state
+        .users
+        .get(tenant, id)
+        .await?
+        .filter(|user| include_deleted || !user.is_deleted())
+        .ok_or(ServiceError::NotFound)
+}
+
+/// Create a user with a username and email unique in its tenant
+pub async fn create_user(
+    state: &AppState,
+    context: &AuditContext,
+    tenant: &str,
+    username: &str,
+    email: &str,
+) -> Result<User, ServiceError> {
+    create_user_with_metadata(state, context, tenant, username, email, metadata::empty()).await
+}
+
+/// Create a user as `create_user` does, starting with `metadata`
+pub async fn create_user_with_metadata(
+    state: &AppState,
+    context: &AuditContext,
+    tenant: &str,
+    username: &str,
+    email: &str,
+    metadata: serde_json::Value,
+) -> Result<User, ServiceError> {
+    state.read_only.check()?;
+    let mut errors = validate(username, email);
+    errors.extend(metadata::check(&metadata, state.config().metadata_max_bytes));
+    if !errors.is_empty() {
+        return Err(ServiceError::Invalid(errors));
+    }
+    let username = username.trim().to_string();
+    let email = email.trim().to_ascii_lowercase();
+    if state.users.find_by_username(tenant, &username).await?.is_some() {
+        return Err(ServiceError::Taken("username".to_string()));
+    }
+    if state.users.find_by_email(tenant, &email).await?.is_some() {
+        return Err(ServiceError::Taken("email".to_string()));
+    }
+
+    let created_at = state.services.clock.now();
+    let user = User {
+        id: state.services.ids.user_id(created_at),
+        tenant_id: tenant.to_string(),
+        created_at,
+        metadata,
+        ..User::new(username, email)
+    };
+    let event = replication::tag(state, None, DomainEvent::user(EventKind::UserCreated, &user));
+    let mut work = UnitOfWork::begin(state);
+    work.insert(&user);
+    work.enqueue(event);
+    work.audit(context, "user", user.id, AuditAction::Create, None, Some(&user));
+    work.commit().await?;
+    Ok(user)
+}
+
+/// Changes to a user; absent fields are left as they are
+#[derive(Debug, Clone, Default, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct UserPatch {
+    /// New username
+    #[schema(example = "alice")]
+    pub username: Option<String>,
+    /// New email address; not changeable through updates
+    pub email: Option<String>,
+    /// New active status; admin only
+    pub is_active: Option<bool>,
+    /// Metadata merged into the user's; a key set to `null` is removed
+    #[schema(value_type = Object)]
+    pub metadata: Option<serde_json::Value>,
+}
+
+impl UserPatch {
+    /// Patch setting every editable field to `user`'s, as a full replacement does
+    pub fn replacing(user: &User) -> Self {
+        UserPatch {
+            username: Some(user.username.clone()),
+            email: Some(user.email.clone()),
+            is_active: Some(user.is_active),
+            metadata: Some(user.metadata.clone()),
+        }
+    }
+
+    /// `user` with the patch applied, normalized as on create
+    fn apply(&self, user: &User) -> User {
+        let mut patched = user.clone();
+        if let Some(username) = &self.username {
+            patched.username = username.trim().to_string();
+        }
+        if let Some(email) = &self.email {
+            patched.email = email.trim().to_ascii_lowercase();
+        }
+        if let Some(is_active) = self.is_active {
+            patched.is_active = is_active;
+        }
+        if let Some(changes) = &self.metadata {
+            metadata::merge(&mut patched.metadata, changes);
+        }
+        patched
+    }
+
+    /// Fields the patch would change on `user`
+    pub fn changed_fields(&self, user: &User) -> Vec<&'static str> {
+        let patched = self.apply(user);
+        let mut changed = Vec::new();
+        if patched.username != user.username {
+            changed.push("username");
+        }
+        if patched.email != user.email {
+            changed.push("email");
+        }
+        if patched.is_active != user.is_active {
+            changed.push("is_active");
+        }
+        if patched.metadata != user.metadata {
+            changed.push("metadata");
+        }
+        changed
+    }
+}
+
+/// Apply a patch to a user loaded with `get_user`, keeping usernames unique in its tenant
+pub async fn update_user(
+    state: &AppState,
+    context: &AuditContext,
+    before: User,
+    patch: &UserPatch,
+) -> Result<User, ServiceError> {
+    state.read_only.check()?;
+    let user = patch.apply(&before);
+    let mut errors = validate(&user.username, &user.email);
+    errors.extend(metadata::check(&user.metadata, state.config().metadata_max_bytes));
+    if !errors.is_empty() {
+        return Err(ServiceError::Invalid(errors));
+    }
+    let changed = patch.changed_fields(&before);
+    if changed.is_empty() {
+        return Ok(before);
+    }
+    let taken = changed.contains(&"username")
+        && state.users.find_by_username(&user.tenant_id, &user.username).await?.is_some();
+    if taken {
+        return Err(ServiceError::Taken("username".to_string()));
+    }
+    let event = DomainEvent::user(EventKind::UserUpdated, &user);
+    let event = replication::tag(state, Some(&before), event);
+    let mut work = UnitOfWork::begin(state);
+    work.update(&user);
+    work.enqueue(event);
+    work.audit(context, "user", user.id, AuditAction::Update, Some(&before), Some(&user));
+    work.commit().await?;
+    Ok(user)
+}
+
+/// Soft-delete a tenant's user
+pub async fn delete_user(
+    state: &AppState,
+    context: &AuditContext,
+    tenant: &str,
+    id: Uuid,
+) -> Result<User, ServiceError> {
+    state.read_only.check()?;
+    let before = get_user(state, tenant, id, false).await?;
+    let mut user = before.clone();
+    user.soft_delete();
+    let event = DomainEvent::user(EventKind::UserDeleted, &user);
+    let event = replication::tag(state, Some(&before), event);
+    let mut work = UnitOfWork::begin(state);
+    work.update(&user);
+    work.enqueue(event);
+    work.audit(context, "user", user.id, AuditAction::Delete, Some(&before), Some(&user));
+    work.commit().await?;
+    Ok(user)
+}
+
+/// Restore a tenant's soft-deleted user
+pub async fn restore_user(
+    state: &AppState,
+    context: &AuditContext,
+    tenant: &str,
+    id: Uuid,
+) -> Result<User, ServiceError> {
+    state.read_only.check()?;
+    let before = get_user(state, tenant, id, true).await?;
+    if !before.is_deleted() {
+        return Err(ServiceError::Conflict("user is not deleted".to_string()));
+    }
+    let mut user = before.clone();
+    user.restore();
+    let event = DomainEvent::user(EventKind::UserUpdated, &user);
+    let event = replication::tag(state, Some(&before), event);
+    let mut work = UnitOfWork::begin(state);
+    work.update(&user);
+    work.enqueue(event);
+    work.audit(context, "user", user.id, AuditAction::Update, Some(&before), Some(&user));
+    work.commit().await?;
+    Ok(user)
+}
+
+/// Replace the email address of a user loaded with `get_user`, once the new
+/// address has been confirmed
+pub async fn change_email(
+    state: &AppState,
+    context: &AuditContext,
+    before: User,
+    email: &str,
+) -> Result<User, ServiceError> {
+    state.read_only.check()?;
+    let email = email.trim().to_ascii_lowercase();
+    if !is_email(&email) {
+        return Err(ServiceError::Invalid(vec!["email is not a valid address".to_string()]));
+    }
+    let owner = state.users.find_by_email(&before.tenant_id, &email).await?;
+    if owner.is_some_and(|owner| owner.id != before.id) {
+        return Err(ServiceError::Taken("email".to_string()));
+    }
+    let mut user = before.clone();
+    user.email = email;
+    let event = DomainEvent::user(EventKind::UserUpdated, &user);
+    let event = replication::tag(state, Some(&before), event);
+    let mut work = UnitOfWork::begin(state);
+    work.update(&user);
+    work.enqueue(event);
+    work.audit(context, "user", user.id, AuditAction::Update, Some(&before), Some(&user));
+    work.commit().await?;
+    Ok(user)
+}
+
+/// Anonymize a tenant's user for good, keeping the record for references
+pub async fn erase_user(
+    state: &AppState,
+    context: &AuditContext,
+    tenant: &str,
+    id: Uuid,
+) -> Result<User, ServiceError> {
+    state.read_only.check()?;
+    let before = get_user(state, tenant, id, true).await?;
+    if before.is_erased() {
+        return Err(ServiceError::Conflict("user is already erased".to_string()));
+    }
+    let mut user = before.clone();
+    user.erase();
+    let event = DomainEvent::user(EventKind::UserDeleted, &user);
+    let event = replication::tag(state, Some(&before), event);
+    let mut work = UnitOfWork::begin(state);
+    work.update(&user);
+    work.enqueue(event);
+    work.audit(context, "user", user.id, AuditAction::Delete, Some(&before), Some(&user));
+    work.commit().await?;
+    Ok(user)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::{AuditQuery, AuditStore};
+    use crate::tenancy::DEFAULT_TENANT as T;
+    use crate::Config;
+
+    fn context() -> AuditContext {
+        AuditContext {
+            actor: "admin".to_string(),
+            request_id: "test".to_string(),
+            client_ip: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delete_then_restore() {
+        let state = AppState::new(Config::default());
+        let user = create_user(&state, &context(), T, "alice", "Alice@Example.com").await.unwrap();
+        assert_eq!(user.email, "alice@example.com");
+
+        delete_user(&state, &context(), T, user.id).await.unwrap();
+        assert!(matches!(get_user(&state, T, user.id, false).await, Err(ServiceError::NotFound)));
+        assert!(matches!(
+            delete_user(&state, &context(), T, user.id).await,
+            Err(ServiceError::NotFound)
+        ));
+
+        let restored = restore_user(&state, &context(), T, user.id).await.unwrap();
+        assert!(!restored.is_deleted());
+        assert!(matches!(
+            restore_user(&state, &context(), T, user.id).await,
+            Err(ServiceError::Conflict(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_create_rejects_duplicates_and_invalid_input() {
+        let state = AppState::new(Config::default());
+        create_user(&state, &context(), T, "alice", "alice@example.com").await.unwrap();
+        assert!(matches!(
+            create_user(&state, &context(), T, "alice", "other@example.com").await,
+            Err(ServiceError::Taken(field)) if field == "username"
+        ));
+        assert!(matches!(
+            create_user(&state, &context(), T, "", "nope").await,
+            Err(ServiceError::Invalid(errors)) if errors.len() == 2
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_update_applies_only_changed_fields() {
+        let state = AppState::new(Config::default());
+        let alice = create_user(&state, &context(), T, "alice", "alice@example.com").await.unwrap();
+        create_user(&state, &context(), T, "bob", "bob@example.com").await.unwrap();
+
+        // Same email in another case is no change
+        let patch = UserPatch {
+            username: Some(" alicia ".to_string()),
+            email: Some("ALICE@example.com".to_string()),
+            ..UserPatch::default()
+        };
+        assert_eq!(patch.changed_fields(&alice), vec!["username"]);
+        let updated = update_user(&state, &context(), alice, &patch).await.unwrap();
+        assert_eq!(updated.username, "alicia");
+
+        let taken = UserPatch {
+            username: Some("bob".to_string()),
+            ..UserPatch::default()
+        };
+        assert!(matches!(
+            update_user(&state, &context(), updated, &taken).await,
+            Err(ServiceError::Taken(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_repeated_puts_and_deletes_record_one_change() {
+        let state = AppState::new(Config::default());
+        let alice = create_user(&state, &context(), T, "alice", "alice@example.com").await.unwrap();
+        let replace = UserPatch::replacing(&alice);
+        let first = update_user(&state, &context(), alice.clone(), &replace).await.unwrap();
+        update_user(&state, &context(), first, &replace).await.unwrap();
+
+        delete_user(&state, &context(), T, alice.id).await.unwrap();
+        assert!(delete_user(&state, &context(), T, alice.id).await.is_err());
+        let query = AuditQuery {
+            entity: "user".to_string(),
+            id: Some(alice.id),
+        };
+        let entries = state.audit.query(&query).await.unwrap();
+        let actions: Vec<_> = entries.into_iter().map(|entry| entry.action).collect();
+        // Newest first; the identical PUTs recorded nothing
+        assert_eq!(actions, [AuditAction::Delete, AuditAction::Create]);
+    }
+
+    #[tokio::test]
+    async fn test_metadata_merges_on_update_and_filters_lists() {
+        let state = AppState::new(Config {
+            metadata_max_bytes: 64,
+            ..Config::default()
+        });
+        let plan = serde_json::json!({"plan": "pro", "seats": 5});
+        let alice = create_user_with_metadata(&state, &context(), T, "alice", "a@example.com", plan)
+            .await
+            .unwrap();
+        create_user(&state, &context(), T, "bob", "bob@example.com").await.unwrap();
+
+        let patch = UserPatch {
+            metadata: Some(serde_json::json!({"seats": null, "crm": "42"})),
+            ..UserPatch::default()
+        };
+        let updated = update_user(&state, &context(), alice, &patch).await.unwrap();
+        assert_eq!(updated.metadata, serde_json::json!({"plan": "pro", "crm": "42"}));
+        let too_big = UserPatch {
+            metadata: Some(serde_json::json!({"notes": "x".repeat(64)})),
+            ..UserPatch::default()
+        };
+        let refused = update_user(&state, &context(), updated.clone(), &too_big).await;
+        assert!(matches!(refused, Err(ServiceError::Invalid(_))));
+
+        let query = UserQuery {
+            metadata: [("plan".to_string(), "pro".to_string())].into(),
+            ..UserQuery::default()
+        };
+        let listed = list_users(&state, T, &query).await.unwrap();
+        assert_eq!(listed.iter().map(|user| user.id).collect::<Vec<_>>(), [updated.id]);
+        assert_eq!(list_users(&state, T, &UserQuery::default()).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_tenants_share_no_users_or_usernames() {
+        let state = AppState::new(Config::default());
+        let acme = create_user(&state, &context(), "acme", "alice", "alice@example.com").await;
+        let acme = acme.unwrap();
+        let globex = create_user(&state, &context(), "globex", "alice", "alice@example.com").await;
+        assert_eq!(globex.unwrap().tenant_id, "globex");
+
+        let missing = get_user(&state, "globex", acme.id, true).await;
+        assert!(matches!(missing, Err(ServiceError::NotFound)));
+        let deleted = delete_user(&state, &context(), "globex", acme.id).await;
+        assert!(matches!(deleted, Err(ServiceError::NotFound)));
+        let listed = list_users(&state, "acme", &UserQuery::default()).await.unwrap();
+        assert_eq!(listed.iter().map(|user| user.id).collect::<Vec<_>>(), [acme.id]);
+    }
+
+    #[test]
+    fn test_validate_username_charset_and_email_shape() {
+        assert!(validate("alice.smith-2", "alice@example.com").is_empty());
+        assert_eq!(validate("alice smith", "alice@example.com").len(), 1);
+        assert_eq!(validate("alice", "alice@@example.com").len(), 1);
+        assert_eq!(validate("alice", "alice@example.").len(), 1);
+        assert_eq!(validate("alice", "al ice@example.com").len(), 1);
+    }
+}
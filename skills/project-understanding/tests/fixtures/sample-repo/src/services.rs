@@ -0,0 +1,265 @@
+//! The dependencies handlers call through `AppState::services`.
+//!
+//! User operations, outbound mail, the clock, and new IDs sit behind trait
+//! objects here, so a test can hand `AppState::with_services` a fake of any
+//! of them and drive the real router against it. `Services::from_config`
+//! wires the real implementations, and is the only place that does.
+//!
+//! The REST, GraphQL, and gRPC front ends call user operations and send mail
+//! through these; the operations in `service` and the importer take the
+//! creation time and ID of new users from them. Services are picked once at
+//! startup, so a reload never swaps them.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::audit::AuditContext;
+use crate::error::AppError;
+use crate::ids::IdStrategy;
+use crate::mail::{self, Email};
+use crate::repository::UserQuery;
+use crate::service::{self, ServiceError, UserPatch};
+use crate::{AppState, Config, User};
+
+/// User operations as the front ends call them, acting on `state`
+#[async_trait]
+pub trait UserService: Send + Sync {
+    /// List a tenant's users passing `query`
+    async fn list(
+        &self,
+        state: &AppState,
+        tenant: &str,
+        query: &UserQuery,
+    ) -> Result<Vec<User>, ServiceError>;
+
+    /// Fetch a tenant's user, soft-deleted ones only if asked
+    async fn get(
+        &self,
+        state: &AppState,
+        tenant: &str,
+        id: Uuid,
+        include_deleted: bool,
+    ) -> Result<User, ServiceError>;
+
+    /// Create a user starting with `metadata`
+    async fn create(
+        &self,
+        state: &AppState,
+        context: &AuditContext,
+        tenant: &str,
+        username: &str,
+        email: &str,
+        metadata: serde_json::Value,
+    ) -> Result<User, ServiceError>;
+
+    /// Apply a patch to a user loaded with `get`
+    async fn update(
+        &self,
+        state: &AppState,
+        context: &AuditContext,
+        before: User,
+        patch: &UserPatch,
+    ) -> Result<User, ServiceError>;
+
+    /// Soft-delete a tenant's user
+    async fn delete(
+        &self,
+        state: &AppState,
+        context: &AuditContext,
+        tenant: &str,
+        id: Uuid,
+    ) -> Result<User, ServiceError>;
+
+    /// Restore a tenant's soft-deleted user
+    async fn restore(
+        &self,
+        state: &AppState,
+        context: &AuditContext,
+        tenant: &str,
+        id: Uuid,
+    ) -> Result<User, ServiceError>;
+}
+
+/// The operations of `service`, against the repository
+pub struct UserOperations;
+
+#[async_trait]
+impl UserService for UserOperations {
+    async fn list(
+        &self,
+        state: &AppState,
+        tenant: &str,
+        query: &UserQuery,
+    ) -> Result<Vec<User>, ServiceError> {
+        service::list_users(state, tenant, query).await
+    }
+
+    async fn get(
+        &self,
+        state: &AppState,
+        tenant: &str,
+        id: Uuid,
+        include_deleted: bool,
+    ) -> Result<User, ServiceError> {
+        service::get_user(state, tenant, id, include_deleted).await
+    }
+
+    async fn create(
+        &self,
+        state: &AppState,
+        context: &AuditContext,
+        tenant: &str,
+        username: &str,
+        email: &str,
+        metadata: serde_json::Value,
+    ) -> Result<User, ServiceError> {
+        service::create_user_with_metadata(state, context, tenant, username, email, metadata).await
+    }
+
+    async fn update(
+        &self,
+        state: &AppState,
+        context: &AuditContext,
+        before: User,
+        patch: &UserPatch,
+    ) -> Result<User, ServiceError> {
+        service::update_user(state, context, before, patch).await
+    }
+
+    async fn delete(
+        &self,
+        state: &AppState,
+        context: &AuditContext,
+        tenant: &str,
+        id: Uuid,
+    ) -> Result<User, ServiceError> {
+        service::delete_user(state, context, tenant, id).await
+    }
+
+    async fn restore(
+        &self,
+        state: &AppState,
+        context: &AuditContext,
+        tenant: &str,
+        id: Uuid,
+    ) -> Result<User, ServiceError> {
+        service::restore_user(state, context, tenant, id).await
+    }
+}
+
+/// Outbound mail
+#[async_trait]
+pub trait MailService: Send + Sync {
+    /// Hand `email` over for sending, returning before it is sent
+    async fn send(&self, state: &AppState, email: Email) -> Result<(), AppError>;
+}
+
+/// Mail sent through the job queue or the mailer, as `mail::send` does
+pub struct QueuedMail;
+
+#[async_trait]
+impl MailService for QueuedMail {
+    async fn send(&self, state: &AppState, email: Email) -> Result<(), AppError> {
+        mail::send(state, email).await
+    }
+}
+
+/// The time new records are stamped with
+pub trait Clock: Send + Sync {
+    /// The current time
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system clock
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// IDs of new users
+pub trait IdGenerator: Send + Sync {
+    /// A new ID for a user created at `at`
+    fn user_id(&self, at: DateTime<Utc>) -> Uuid;
+}
+
+impl IdGenerator for IdStrategy {
+    fn user_id(&self, at: DateTime<Utc>) -> Uuid {
+        self.id_at(at)
+    }
+}
+
+/// Everything handlers call that a test may want to replace
+#[derive(Clone)]
+pub struct Services {
+    /// User operations
+    pub users: Arc<dyn UserService>,
+    /// Outbound mail
+    pub mail: Arc<dyn MailService>,
+    /// Time of new records
+    pub clock: Arc<dyn Clock>,
+    /// IDs of new users
+    pub ids: Arc<dyn IdGenerator>,
+}
+
+impl Services {
+    /// The real services, as `serve` runs them
+    pub fn from_config(config: &Config) -> Self {
+        Services {
+            users: Arc::new(UserOperations),
+            mail: Arc::new(QueuedMail),
+            clock: Arc::new(SystemClock),
+            ids: Arc::new(config.id_strategy),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{expect_data, TestServer};
+    use reqwest::{Method, StatusCode};
+
+    struct FixedClock(DateTime<Utc>);
+
+    impl Clock for FixedClock {
+        fn now(&self) -> DateTime<Utc> {
+            self.0
+        }
+    }
+
+    struct FixedIds(Uuid);
+
+    impl IdGenerator for FixedIds {
+        fn user_id(&self, _at: DateTime<Utc>) -> Uuid {
+            self.0
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handlers_use_the_services_they_are_given() {
+        let (id, now) = (Uuid::new_v4(), "2020-02-02T10:00:00Z".parse().unwrap());
+        let services = Services {
+            clock: Arc::new(FixedClock(now)),
+            ids: Arc::new(FixedIds(id)),
+            ..Services::from_config(&Config::default())
+        };
+        let server = TestServer::start_with_services(Config::default(), services).await;
+
+        let (status, body) = server.create_user("/api/v1/users", "alice").await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body["data"]["id"], serde_json::json!(id));
+        assert_eq!(body["data"]["created_at"], "2020-02-02T10:00:00Z");
+
+        let path = format!("/api/v1/users/{}", id);
+        let response = server.request(Method::GET, &path).send().await.unwrap();
+        assert_eq!(expect_data(response, StatusCode::OK).await["username"], "alice");
+        // Every user gets the same ID, so the second can't be stored
+        let (status, _) = server.create_user("/api/v1/users", "bob").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+}
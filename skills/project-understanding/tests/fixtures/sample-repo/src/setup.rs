@@ -0,0 +1,233 @@
+//! First-run bootstrap.
+//!
+//! On a fresh install with no users, startup arms a one-time setup token,
+//! taken from `Config::setup_token` or generated and logged. `POST /api/setup`
+//! with that token creates the initial admin user and base settings, and
+//! issues an admin bearer token if none is configured. The endpoint is then
+//! locked for the life of the process, and stays locked across restarts
+//! because users exist.
+//!
+//! A generated admin token lives only in memory and should be copied into
+//! `Config::admin_token` before the next restart.
+
+use async_trait::async_trait;
+use axum::{extract::State, http::StatusCode, response::Json};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::audit::AuditContext;
+use crate::auth::constant_time_eq;
+use crate::client_ip::ClientIp;
+use crate::lifecycle::Component;
+use crate::repository::{self, UserQuery};
+use crate::tenancy::DEFAULT_TENANT;
+use crate::request_id::RequestId;
+use crate::{metadata, ApiResponse, AppState, User};
+
+/// Instance-wide settings chosen during setup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstanceSettings {
+    /// Display name of this installation
+    pub instance_name: String,
+    /// Address shown to users who need help
+    #[serde(default)]
+    pub support_email: Option<String>,
+}
+
+/// Body of `POST /api/setup`
+#[derive(Debug, Deserialize)]
+pub struct SetupRequest {
+    /// The one-time setup token
+    pub token: String,
+    /// Username of the initial admin
+    pub username: String,
+    /// Email of the initial admin
+    pub email: String,
+    /// Base settings
+    pub settings: InstanceSettings,
+}
+
+/// Result of a completed setup
+#[derive(Debug, Serialize)]
+pub struct SetupResult {
+    /// The initial admin user
+    pub user: User,
+    /// Settings as stored
+    pub settings: InstanceSettings,
+    /// Admin bearer token, present only when none was configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_token: Option<String>,
+}
+
+/// Whether setup is still pending
+#[derive(Debug, Serialize)]
+pub struct SetupStatus {
+    /// True until the initial admin has been created
+    pub required: bool,
+}
+
+/// Setup token, settings, and any admin token issued by setup
+#[derive(Default)]
+pub struct SetupState {
+    /// Armed token; `None` when setup is locked
+    gate: Mutex<Option<String>>,
+    admin_token: RwLock<Option<String>>,
+    settings: RwLock<Option<InstanceSettings>>,
+}
+
+impl SetupState {
+    /// Admin token issued by setup, if any
+    pub fn admin_token(&self) -> Option<String> {
+        self.admin_token.read().expect("setup lock poisoned").clone()
+    }
+
+    /// Settings chosen during setup, if it has run
+    pub fn settings(&self) -> Option<InstanceSettings> {
+        self.settings.read().expect("setup lock poisoned").clone()
+    }
+
+    /// Arm the gate with `token`
+    pub async fn arm(&self, token: String) {
+        *self.gate.lock().await = Some(token);
+    }
+}
+
+/// Random token suitable for bearer use
+pub fn generate_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+/// Whether any user, including soft-deleted ones, exists
+async fn has_users(state: &AppState) -> Result<bool, StatusCode> {
+    let query = UserQuery {
+        include_deleted: true,
+        ..UserQuery::default()
+    };
+    let users = repository::list_every_tenant(&*state.users, &query)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(!users.is_empty())
+}
+
+/// Lifecycle component arming setup on an empty install
+pub struct Bootstrap;
+
+#[async_trait]
+impl Component for Bootstrap {
+    fn name(&self) -> &'static str {
+        "bootstrap"
+    }
+
+    async fn start(&self, state: &Arc<AppState>) -> Result<(), String> {
+        if has_users(state).await.map_err(|_| "could not count users".to_string())? {
+            return Ok(());
+        }
+        let token = match &state.config().setup_token {
+            Some(token) => token.clone(),
+            None => {
+                let token = generate_token();
+                tracing::warn!("no users exist; complete setup with token {}", token);
+                token
+            }
+        };
+        state.setup.arm(token).await;
+        Ok(())
+    }
+}
+
+/// Report whether setup is pending
+pub async fn setup_status(State(state): State<Arc<AppState>>) -> Json<ApiResponse<SetupStatus>> {
+    let required = state.setup.gate.lock().await.is_some();
+    Json(ApiResponse::success(SetupStatus { required }))
+}
+
+/// Create the initial admin and settings, then lock setup
+pub async fn run_setup(
+    State(state): State<Arc<AppState>>,
+    RequestId(request_id): RequestId,
+    ClientIp(client_ip): ClientIp,
+    Json(request): Json<SetupRequest>,
+) -> Result<(StatusCode, Json<ApiResponse<SetupResult>>), StatusCode> {
+    // Held throughout so concurrent attempts can't both succeed
+    let mut gate = state.setup.gate.lock().await;
+    let expected = gate.as_deref().ok_or(StatusCode::GONE)?;
+    if !constant_time_eq(request.token.as_bytes(), expected.as_bytes()) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if has_users(&state).await? {
+        // Someone created a user by other means since startup
+        *gate = None;
+        return Err(StatusCode::GONE);
+    }
+
+    let context = AuditContext {
+        actor: "setup".to_string(),
+        request_id,
+        client_ip,
+    };
+    let (username, email) = (&request.username, &request.email);
+    let user = state
+        .services
+        .users
+        .create(&state, &context, DEFAULT_TENANT, username, email, metadata::empty())
+        .await
+        .map_err(|err| err.status())?;
+    *state.setup.settings.write().expect("setup lock poisoned") = Some(request.settings.clone());
+    let admin_token = if state.config().admin_token.is_none() {
+        let token = generate_token();
+        *state.setup.admin_token.write().expect("setup lock poisoned") = Some(token.clone());
+        tracing::warn!("setup issued an in-memory admin token; set admin_token to keep it");
+        Some(token)
+    } else {
+        None
+    };
+    *gate = None;
+    tracing::info!("setup completed; initial admin is {}", user.username);
+
+    let result = SetupResult {
+        user,
+        settings: request.settings,
+        admin_token,
+    };
+    Ok((StatusCode::CREATED, Json(ApiResponse::success(result))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+
+    fn request(token: &str) -> Json<SetupRequest> {
+        Json(SetupRequest {
+            token: token.to_string(),
+            username: "root".to_string(),
+            email: "root@example.com".to_string(),
+            settings: InstanceSettings {
+                instance_name: "Acme".to_string(),
+                support_email: None,
+            },
+        })
+    }
+
+    #[tokio::test]
+    async fn test_setup_runs_once_with_the_right_token() {
+        let state = AppState::new(Config::default());
+        Bootstrap.start(&state).await.unwrap();
+        let token = state.setup.gate.lock().await.clone().unwrap();
+        let request_id = || RequestId("test".to_string());
+
+        let wrong = run_setup(State(state.clone()), request_id(), request("nope")).await;
+        assert_eq!(wrong.unwrap_err(), StatusCode::FORBIDDEN);
+
+        let (status, Json(response)) =
+            run_setup(State(state.clone()), request_id(), request(&token)).await.unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(response.data.unwrap().admin_token, state.setup.admin_token());
+        assert_eq!(state.setup.settings().unwrap().instance_name, "Acme");
+
+        let again = run_setup(State(state.clone()), request_id(), request(&token)).await;
+        assert_eq!(again.unwrap_err(), StatusCode::GONE);
+    }
+}
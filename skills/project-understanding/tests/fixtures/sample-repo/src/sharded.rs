@@ -0,0 +1,192 @@
+//! Contention-free shared state for per-request bookkeeping.
+//!
+//! Every request updates the same few pieces of shared state: the request
+//! counter, the user cache's lookup counters, and the metrics maps. Behind
+//! one lock each, busy workers queue on those locks, and even a lone atomic
+//! slows down as its cache line moves between cores on every increment.
+//! The types here split such state into `SHARDS` shards on separate cache
+//! lines. Writers only touch the shard of the thread they run on, so tokio's
+//! workers never contend with one another; readers combine every shard,
+//! a cost paid only by scrapes and stats endpoints.
+//!
+//! Sharding suits state that is written often and read rarely. State
+//! that must be consistent across all requests, such as a rate limiter's
+//! token bucket, stays behind a single lock.
+//!
+//! `bench_against_single_lock` compares the counter with the lock and the
+//! plain atomic it replaced under concurrent load:
+//! `cargo test --release bench_against_single_lock -- --ignored --nocapture`.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+/// Number of shards, comfortably above the worker count of one host
+const SHARDS: usize = 64;
+
+/// Keeps a shard on a cache line of its own
+#[derive(Debug, Default)]
+#[repr(align(128))]
+struct Padded<T>(T);
+
+/// Shard of the current thread, handed out round-robin on first use
+fn shard_index() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    thread_local! {
+        static INDEX: usize = NEXT.fetch_add(1, Ordering::Relaxed) % SHARDS;
+    }
+    INDEX.with(|index| *index)
+}
+
+/// Counter summed over per-thread shards
+#[derive(Debug)]
+pub struct ShardedCounter {
+    shards: Box<[Padded<AtomicU64>]>,
+}
+
+impl Default for ShardedCounter {
+    fn default() -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| Padded::default()).collect(),
+        }
+    }
+}
+
+impl ShardedCounter {
+    /// Add one
+    pub fn increment(&self) {
+        self.shards[shard_index()].0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Total over all shards
+    pub fn sum(&self) -> u64 {
+        self.shards.iter().map(|shard| shard.0.load(Ordering::Relaxed)).sum()
+    }
+
+    /// Start again from zero; increments racing the reset may survive it
+    pub fn reset(&self) {
+        for shard in self.shards.iter() {
+            shard.0.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Value split into per-thread shards, each behind its own lock
+#[derive(Debug)]
+pub struct Sharded<T> {
+    shards: Box<[Padded<Mutex<T>>]>,
+}
+
+impl<T: Default> Default for Sharded<T> {
+    fn default() -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| Padded::default()).collect(),
+        }
+    }
+}
+
+impl<T> Sharded<T> {
+    /// The current thread's shard, locked
+    pub fn local(&self) -> MutexGuard<'_, T> {
+        self.shards[shard_index()].0.lock().expect("shard lock poisoned")
+    }
+
+    /// Visit every shard, locking one at a time
+    pub fn each(&self, mut visit: impl FnMut(&T)) {
+        for shard in self.shards.iter() {
+            visit(&shard.0.lock().expect("shard lock poisoned"));
+        }
+    }
+
+    /// Put every shard back to its default, locking one at a time
+    pub fn reset(&self)
+    where
+        T: Default,
+    {
+        for shard in self.shards.iter() {
+            *shard.0.lock().expect("shard lock poisoned") = T::default();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, RwLock};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_shards_add_up_across_threads() {
+        let counter = Arc::new(ShardedCounter::default());
+        let routes = Arc::new(Sharded::<BTreeMap<&str, u64>>::default());
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let (counter, routes) = (counter.clone(), routes.clone());
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        counter.increment();
+                        *routes.local().entry("/users").or_default() += 1;
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(counter.sum(), 8000);
+        let mut total = 0;
+        routes.each(|shard| total += shard.get("/users").copied().unwrap_or(0));
+        assert_eq!(total, 8000);
+    }
+
+    /// Time `threads` threads running `work` 1M times each
+    fn time(threads: usize, work: impl Fn() + Send + Sync + 'static) -> Duration {
+        let work = Arc::new(work);
+        let started = Instant::now();
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                let work = work.clone();
+                std::thread::spawn(move || (0..1_000_000).for_each(|_| work()))
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        started.elapsed()
+    }
+
+    #[test]
+    #[ignore = "benchmark; run with --release --ignored --nocapture"]
+    fn bench_against_single_lock() {
+        let threads = std::thread::available_parallelism().map_or(4, |n| n.get()).max(4);
+        let lock = Arc::new(RwLock::new(0u64));
+        let atomic = Arc::new(AtomicU64::new(0));
+        let sharded = Arc::new(ShardedCounter::default());
+
+        let timings = [
+            ("RwLock<u64>", {
+                let lock = lock.clone();
+                time(threads, move || *lock.write().unwrap() += 1)
+            }),
+            ("AtomicU64", {
+                let atomic = atomic.clone();
+                time(threads, move || {
+                    atomic.fetch_add(1, Ordering::Relaxed);
+                })
+            }),
+            ("ShardedCounter", {
+                let sharded = sharded.clone();
+                time(threads, move || sharded.increment())
+            }),
+        ];
+        for (name, elapsed) in timings {
+            let total = format!("{}M increments on {} threads", threads, threads);
+            println!("{:>14}: {:?} for {}", name, elapsed, total);
+        }
+        let expected = threads as u64 * 1_000_000;
+        assert_eq!(*lock.read().unwrap(), expected);
+        assert_eq!(atomic.load(Ordering::Relaxed), expected);
+        assert_eq!(sharded.sum(), expected);
+    }
+}
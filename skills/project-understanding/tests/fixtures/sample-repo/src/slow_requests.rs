@@ -0,0 +1,187 @@
+//! Slow request detection.
+//!
+//! `detect_slow_requests` times each handler, excluding time spent queued by
+//! load shedding and tenant fairness, and logs a warning for any request over
+//! its route's threshold. The most recent offenders are kept in a ring buffer
+//! served at `GET /api/v1/admin/slow-requests`.
+
+use axum::{
+    extract::{MatchedPath, State},
+    http::Request,
+    middleware::Next,
+    response::{Json, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::auth::Admin;
+use crate::request_id::RequestId;
+use crate::{ApiResponse, AppState};
+
+/// Slow request thresholds and retention
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SlowRequestConfig {
+    /// Handler latency above which a request is reported
+    pub threshold_ms: u64,
+    /// Per-route thresholds keyed by route pattern, e.g. `/api/v1/users/import`
+    pub route_threshold_ms: HashMap<String, u64>,
+    /// Slow requests kept for the admin view
+    pub capacity: usize,
+}
+
+impl Default for SlowRequestConfig {
+    fn default() -> Self {
+        SlowRequestConfig {
+            threshold_ms: 500,
+            route_threshold_ms: HashMap::new(),
+            capacity: 100,
+        }
+    }
+}
+
+impl SlowRequestConfig {
+    /// Threshold for a route
+    fn threshold(&self, route: &str) -> Duration {
+        let millis = self.route_threshold_ms.get(route).copied().unwrap_or(self.threshold_ms);
+        Duration::from_millis(millis)
+    }
+}
+
+/// A request that ran over its threshold
+#[derive(Debug, Clone, Serialize)]
+pub struct SlowRequest {
+    /// When the handler finished
+    pub at: chrono::DateTime<chrono::Utc>,
+    /// HTTP method
+    pub method: String,
+    /// Matched route pattern
+    pub route: String,
+    /// Response status
+    pub status: u16,
+    /// Handler latency
+    pub latency_ms: u64,
+    /// Threshold it exceeded
+    pub threshold_ms: u64,
+    /// Request correlation ID
+    pub request_id: String,
+}
+
+/// Ring buffer of the most recent slow requests
+#[derive(Debug, Default)]
+pub struct SlowRequestLog {
+    recent: Mutex<VecDeque<SlowRequest>>,
+}
+
+impl SlowRequestLog {
+    /// Keep a slow request, evicting the oldest beyond `capacity`
+    pub fn record(&self, request: SlowRequest, capacity: usize) {
+        let mut recent = self.recent.lock().expect("slow request log lock poisoned");
+        while recent.len() >= capacity.max(1) {
+            recent.pop_front();
+        }
+        recent.push_back(request);
+    }
+
+    /// Kept slow requests, newest first
+    pub fn recent(&self) -> Vec<SlowRequest> {
+        let recent = self.recent.lock().expect("slow request log lock poisoned");
+        recent.iter().rev().cloned().collect()
+    }
+}
+
+/// Middleware reporting handlers that exceed their route's threshold
+pub async fn detect_slow_requests<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = req.method().to_string();
+    let request_id = req
+        .extensions()
+        .get::<RequestId>()
+        .map(|id| id.to_string())
+        .unwrap_or_default();
+    let started = Instant::now();
+
+    let response = next.run(req).await;
+
+    let latency = started.elapsed();
+    let current = state.config();
+    let config = &current.slow_requests;
+    let threshold = config.threshold(&route);
+    if latency > threshold {
+        let slow = SlowRequest {
+            at: chrono::Utc::now(),
+            method,
+            route,
+            status: response.status().as_u16(),
+            latency_ms: latency.as_millis() as u64,
+            threshold_ms: threshold.as_millis() as u64,
+            request_id,
+        };
+        tracing::warn!(
+            method = %slow.method,
+            route = %slow.route,
+            latency_ms = slow.latency_ms,
+            threshold_ms = slow.threshold_ms,
+            request_id = %slow.request_id,
+            "slow request: {} {} took {}ms",
+            slow.method,
+            slow.route,
+            slow.latency_ms
+        );
+        state.slow_requests.record(slow, config.capacity);
+    }
+    response
+}
+
+/// Most recent slow requests, newest first
+pub async fn list_slow_requests(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+) -> Json<ApiResponse<Vec<SlowRequest>>> {
+    Json(ApiResponse::success(state.slow_requests.recent()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slow(route: &str) -> SlowRequest {
+        SlowRequest {
+            at: chrono::Utc::now(),
+            method: "GET".to_string(),
+            route: route.to_string(),
+            status: 200,
+            latency_ms: 900,
+            threshold_ms: 500,
+            request_id: "test".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_ring_buffer_keeps_newest() {
+        let log = SlowRequestLog::default();
+        for route in ["/a", "/b", "/c"] {
+            log.record(slow(route), 2);
+        }
+        let routes: Vec<_> = log.recent().into_iter().map(|slow| slow.route).collect();
+        assert_eq!(routes, vec!["/c".to_string(), "/b".to_string()]);
+    }
+
+    #[test]
+    fn test_route_threshold_overrides_default() {
+        let mut config = SlowRequestConfig::default();
+        config.route_threshold_ms.insert("/api/v1/users/import".to_string(), 30_000);
+        assert_eq!(config.threshold("/api/v1/users/import"), Duration::from_secs(30));
+        assert_eq!(config.threshold("/api/v1/users"), Duration::from_millis(500));
+    }
+}
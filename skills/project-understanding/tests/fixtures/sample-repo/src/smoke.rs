@@ -0,0 +1,235 @@
+//! Golden-path smoke test against a live deployment.
+//!
+//! `app smoke --base-url https://...` walks one user through its lifecycle
+//! and prints pass/fail per step, exiting non-zero on any failure. Each run
+//! works in a fresh `smoke-<id>` tenant, named by `X-Tenant-Id` on every
+//! request, so concurrent runs and real data are unaffected, and a deployment
+//! with `tenancy.required` set is served like any other. This API has no
+//! signup or login; those steps report as skipped rather than passing
+//! vacuously.
+
+use reqwest::{Client, Method, RequestBuilder, StatusCode};
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::context::TENANT_HEADER;
+
+/// Result of one step
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The check held
+    Passed,
+    /// The check failed, with what was observed
+    Failed(String),
+    /// The step could not run, with why
+    Skipped(String),
+}
+
+/// One line of the report
+#[derive(Debug, Clone)]
+pub struct StepReport {
+    /// Step name
+    pub name: &'static str,
+    /// What happened
+    pub outcome: Outcome,
+    /// Time taken
+    pub elapsed: Duration,
+}
+
+/// Runs the scenario against one deployment
+pub struct Smoke {
+    client: Client,
+    base_url: String,
+    admin_token: Option<String>,
+    tenant: String,
+    reports: Vec<StepReport>,
+}
+
+impl Smoke {
+    /// Create a run against `base_url`, e.g. `https://api.example.com`
+    pub fn new(base_url: &str, admin_token: Option<String>) -> Self {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("failed to build smoke test HTTP client");
+        Self {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            admin_token,
+            tenant: format!("smoke-{}", &Uuid::new_v4().simple().to_string()[..12]),
+            reports: Vec::new(),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// A request to `path` in the run's tenant
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.client.request(method, self.url(path)).header(TENANT_HEADER, &self.tenant)
+    }
+
+    fn record(&mut self, name: &'static str, started: Instant, outcome: Outcome) {
+        self.reports.push(StepReport {
+            name,
+            outcome,
+            elapsed: started.elapsed(),
+        });
+    }
+
+    /// Send a request and require `expected`, returning the JSON body if any
+    async fn check(
+        &mut self,
+        name: &'static str,
+        request: reqwest::RequestBuilder,
+        expected: StatusCode,
+    ) -> Option<Value> {
+        let started = Instant::now();
+        let outcome = match request.send().await {
+            Ok(response) if response.status() == expected => {
+                let body = response.json::<Value>().await.unwrap_or(Value::Null);
+                self.record(name, started, Outcome::Passed);
+                return Some(body);
+            }
+            Ok(response) => {
+                let status = response.status();
+                let body = response.text().await.unwrap_or_default();
+                Outcome::Failed(format!("expected {}, got {}: {}", expected, status, body))
+            }
+            Err(err) => Outcome::Failed(err.to_string()),
+        };
+        self.record(name, started, outcome);
+        None
+    }
+
+    fn skip(&mut self, name: &'static str, reason: &str) {
+        self.record(name, Instant::now(), Outcome::Skipped(reason.to_string()));
+    }
+
+    /// Run every step, returning the report
+    pub async fn run(mut self) -> Vec<StepReport> {
+        let ready = self.client.get(self.url("/readyz"));
+        if self.check("readiness", ready, StatusCode::OK).await.is_none() {
+            return self.reports;
+        }
+        self.skip("signup, verify, login", "the API has no account endpoints");
+
+        let username = self.tenant.clone();
+        let body = json!({ "username": username, "email": format!("{}@example.com", username) });
+        let create = self.request(Method::POST, "/api/v1/users").json(&body);
+        let Some(created) = self.check("create user", create, StatusCode::CREATED).await else {
+            return self.reports;
+        };
+        let Some(id) = created.pointer("/data/id").and_then(Value::as_str).map(str::to_string)
+        else {
+            self.record("create user", Instant::now(), Outcome::Failed("no ID".to_string()));
+            return self.reports;
+        };
+        let user_path = format!("/api/v1/users/{}", id);
+
+        let read = self.request(Method::GET, &user_path);
+        if let Some(user) = self.check("read user", read, StatusCode::OK).await {
+            if user.pointer("/data/username").and_then(Value::as_str) != Some(username.as_str()) {
+                let outcome = Outcome::Failed(format!("unexpected body: {}", user));
+                self.record("read user matches", Instant::now(), outcome);
+            }
+        }
+        let list = self.request(Method::GET, "/api/v1/users");
+        if let Some(users) = self.check("list users", list, StatusCode::OK).await {
+            let listed = users["data"]
+                .as_array()
+                .map_or(false, |users| users.iter().any(|user| user["id"] == id.as_str()));
+            if !listed {
+                let outcome = Outcome::Failed("created user missing from list".to_string());
+                self.record("list includes user", Instant::now(), outcome);
+            }
+        }
+
+        let renamed = format!("{}-renamed", username);
+        let patch = json!({ "username": renamed });
+        let update = self.request(Method::PATCH, &user_path).json(&patch);
+        if self.check("update user", update, StatusCode::OK).await.is_some() {
+            let reread = self.request(Method::GET, &user_path);
+            if let Some(user) = self.check("read updated user", reread, StatusCode::OK).await {
+                let persisted = user.pointer("/data/username").and_then(Value::as_str);
+                if persisted != Some(renamed.as_str()) {
+                    let outcome = Outcome::Failed(format!("update not persisted: {}", user));
+                    self.record("update persisted", Instant::now(), outcome);
+                }
+            }
+        }
+
+        let delete = self.request(Method::DELETE, &user_path);
+        self.check("delete user", delete, StatusCode::NO_CONTENT).await;
+        let gone = self.request(Method::GET, &user_path);
+        self.check("deleted user is hidden", gone, StatusCode::NOT_FOUND).await;
+        match self.admin_token.clone() {
+            Some(token) => {
+                let path = format!("{}?include_deleted=true", user_path);
+                let deleted = self.request(Method::GET, &path).bearer_auth(token);
+                self.check("admin sees deleted user", deleted, StatusCode::OK).await;
+            }
+            None => self.skip("admin sees deleted user", "no --admin-token given"),
+        }
+        self.reports
+    }
+}
+
+/// Print a report, returning whether every step passed or was skipped
+pub fn print_report(reports: &[StepReport]) -> bool {
+    let mut ok = true;
+    for report in reports {
+        let (label, detail) = match &report.outcome {
+            Outcome::Passed => ("PASS", String::new()),
+            Outcome::Failed(detail) => {
+                ok = false;
+                ("FAIL", format!(": {}", detail))
+            }
+            Outcome::Skipped(reason) => ("SKIP", format!(": {}", reason)),
+        };
+        println!("{} {} ({:?}){}", label, report.name, report.elapsed, detail);
+    }
+    ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::Lifecycle;
+    use crate::repository::UserQuery;
+    use crate::tenancy::DEFAULT_TENANT;
+    use crate::warmup::Warmup;
+    use crate::{handlers, AppState, Config};
+    use std::net::SocketAddr;
+
+    #[tokio::test]
+    async fn test_smoke_passes_against_in_process_server() {
+        let mut config = Config {
+            admin_token: Some("secret".to_string()),
+            ..Config::default()
+        };
+        config.tenancy.required = true;
+        let state = AppState::new(config);
+        let mut lifecycle = Lifecycle::new();
+        lifecycle.register(Warmup::new(Duration::from_secs(1)));
+        lifecycle.start(&state).await.unwrap();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = handlers::create_router(state.clone());
+        let app = app.into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app));
+
+        let reports = Smoke::new(&format!("http://{}", addr), Some("secret".to_string()))
+            .run()
+            .await;
+        assert!(print_report(&reports), "{:?}", reports);
+        assert!(reports.iter().any(|report| report.name == "delete user"));
+        assert!(reports.iter().any(|report| report.name == "read updated user"));
+        // Nothing of the run lands outside its own tenant
+        let query = UserQuery::default();
+        assert!(state.users.list(DEFAULT_TENANT, &query).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,261 @@
+//! Durable disk spool for outbound deliveries.
+//!
+//! With `Config::spool.enabled`, deliveries that can't reach their
+//! destination are appended to a per-channel log under `dir` instead of being
+//! dropped: webhooks whose retries ran out (`webhooks.log`) and events the
+//! stream broker rejected (`event_stream.log`). Each channel retries its
+//! spool every `retry_interval_ms`, oldest records first, and keeps what
+//! still fails, so a destination that is down for hours gets its backlog
+//! once it returns, including after a restart. There is no outbound mail in
+//! this tree, so there is no mail channel.
+//!
+//! `fsync` sets durability against power loss: `always` syncs every append,
+//! `interval` syncs an append when the last sync is at least
+//! `fsync_interval_ms` old, so a burst costs one sync, and `never` leaves it
+//! to the OS. Delivery is at-least-once: records being retried when the
+//! process dies are retried again on start. Appends that would grow a channel
+//! past `max_bytes` are refused.
+//!
+//! Webhook records carry the endpoint's signing secret so they can be signed
+//! after a restart; spool files are created readable by their owner only.
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::future::Future;
+use std::io;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+/// When appends are flushed to stable storage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FsyncPolicy {
+    /// Sync every append
+    Always,
+    /// Sync at most once per `fsync_interval_ms`
+    Interval,
+    /// Let the OS write back
+    Never,
+}
+
+/// Spool settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpoolConfig {
+    /// Spool undeliverable webhooks and stream events to disk
+    pub enabled: bool,
+    /// Directory holding one log per channel
+    pub dir: PathBuf,
+    /// Durability of appends
+    pub fsync: FsyncPolicy,
+    /// Minimum time between syncs under the `interval` policy
+    pub fsync_interval_ms: u64,
+    /// Size at which a channel refuses new records
+    pub max_bytes: u64,
+    /// How often spooled records are retried
+    pub retry_interval_ms: u64,
+}
+
+impl Default for SpoolConfig {
+    fn default() -> Self {
+        SpoolConfig {
+            enabled: false,
+            dir: PathBuf::from("spool"),
+            fsync: FsyncPolicy::Interval,
+            fsync_interval_ms: 1_000,
+            max_bytes: 256 * 1024 * 1024,
+            retry_interval_ms: 30_000,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Log {
+    /// Size of the log file, measured on first append
+    bytes: Option<u64>,
+    synced: Option<Instant>,
+}
+
+/// Outcome of retrying a spool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retried {
+    /// Records delivered and removed
+    pub delivered: usize,
+    /// Records that failed again and stay spooled
+    pub kept: usize,
+}
+
+/// Append-only log of records of one channel
+pub struct Spool<T> {
+    config: SpoolConfig,
+    path: PathBuf,
+    /// Records taken for a retry; left behind if the process dies mid-retry
+    retrying: PathBuf,
+    log: Mutex<Log>,
+    records: PhantomData<fn() -> T>,
+}
+
+impl<T: Serialize + DeserializeOwned> Spool<T> {
+    /// Spool for `channel`, if spooling is enabled
+    pub fn new(config: &SpoolConfig, channel: &str) -> Option<Self> {
+        config.enabled.then(|| Self {
+            config: config.clone(),
+            path: config.dir.join(format!("{}.log", channel)),
+            retrying: config.dir.join(format!("{}.retrying", channel)),
+            log: Mutex::new(Log::default()),
+            records: PhantomData,
+        })
+    }
+
+    /// Append a record, failing if the spool is full
+    pub async fn append(&self, record: &T) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        self.append_lines(&line, true).await
+    }
+
+    async fn append_lines(&self, lines: &[u8], bounded: bool) -> io::Result<()> {
+        let mut log = self.log.lock().await;
+        let size = match log.bytes {
+            Some(size) => size,
+            None => file_len(&self.path).await?,
+        };
+        if bounded && size + lines.len() as u64 > self.config.max_bytes {
+            return Err(io::Error::new(io::ErrorKind::Other, "spool is full"));
+        }
+        tokio::fs::create_dir_all(&self.config.dir).await?;
+        let mut file = open_append(&self.path).await?;
+        file.write_all(lines).await?;
+        let interval = Duration::from_millis(self.config.fsync_interval_ms);
+        let sync = match self.config.fsync {
+            FsyncPolicy::Always => true,
+            FsyncPolicy::Interval => log.synced.map_or(true, |at| at.elapsed() >= interval),
+            FsyncPolicy::Never => false,
+        };
+        if sync {
+            file.sync_data().await?;
+            log.synced = Some(Instant::now());
+        } else {
+            file.flush().await?;
+        }
+        log.bytes = Some(size + lines.len() as u64);
+        Ok(())
+    }
+
+    /// Offer every spooled record to `deliver`, oldest first, and keep the
+    /// ones it hands back. Only one retry may run at a time per spool.
+    pub async fn retry<F, Fut>(&self, mut deliver: F) -> io::Result<Retried>
+    where
+        F: FnMut(T) -> Fut,
+        Fut: Future<Output = Result<(), T>>,
+    {
+        {
+            let mut log = self.log.lock().await;
+            // Records left by an interrupted retry go first, on their own
+            if file_len(&self.retrying).await? == 0 && file_len(&self.path).await? > 0 {
+                tokio::fs::rename(&self.path, &self.retrying).await?;
+                log.bytes = Some(0);
+            }
+        }
+        let contents = match tokio::fs::read_to_string(&self.retrying).await {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok(Retried { delivered: 0, kept: 0 })
+            }
+            Err(err) => return Err(err),
+        };
+
+        let mut retried = Retried { delivered: 0, kept: 0 };
+        let mut kept = Vec::new();
+        for line in contents.lines() {
+            let record = match serde_json::from_str(line) {
+                Ok(record) => record,
+                Err(err) => {
+                    tracing::error!("dropping unreadable record in {:?}: {}", self.retrying, err);
+                    continue;
+                }
+            };
+            match deliver(record).await {
+                Ok(()) => retried.delivered += 1,
+                Err(record) => {
+                    retried.kept += 1;
+                    serde_json::to_writer(&mut kept, &record)?;
+                    kept.push(b'\n');
+                }
+            }
+        }
+        // Kept records were already accepted once, so they aren't refused now
+        if !kept.is_empty() {
+            self.append_lines(&kept, false).await?;
+        }
+        tokio::fs::remove_file(&self.retrying).await?;
+        Ok(retried)
+    }
+}
+
+async fn file_len(path: &Path) -> io::Result<u64> {
+    match tokio::fs::metadata(path).await {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(0),
+        Err(err) => Err(err),
+    }
+}
+
+async fn open_append(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    options.open(path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn config() -> SpoolConfig {
+        SpoolConfig {
+            enabled: true,
+            dir: std::env::temp_dir().join(format!("spool-{}", Uuid::new_v4())),
+            fsync: FsyncPolicy::Always,
+            max_bytes: 8,
+            ..SpoolConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_keeps_failures_across_reopen() {
+        let config = config();
+        let spool: Spool<u32> = Spool::new(&config, "test").unwrap();
+        for record in [1, 2, 3] {
+            spool.append(&record).await.unwrap();
+        }
+        // "1\n2\n3\n" is 6 bytes, so "10\n" would pass the 8-byte limit
+        spool.append(&10).await.unwrap_err();
+
+        let retried = spool
+            .retry(|record| async move { if record == 2 { Err(record) } else { Ok(()) } })
+            .await
+            .unwrap();
+        assert_eq!(retried, Retried { delivered: 2, kept: 1 });
+
+        // A new process sees only the record that failed
+        let reopened: Spool<u32> = Spool::new(&config, "test").unwrap();
+        let mut seen = Vec::new();
+        reopened
+            .retry(|record| {
+                seen.push(record);
+                async { Ok(()) }
+            })
+            .await
+            .unwrap();
+        assert_eq!(seen, vec![2]);
+        let empty = reopened.retry(|_| async { Ok(()) }).await.unwrap();
+        assert_eq!(empty, Retried { delivered: 0, kept: 0 });
+        let _ = std::fs::remove_dir_all(&config.dir);
+    }
+}
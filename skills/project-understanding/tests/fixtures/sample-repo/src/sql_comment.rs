@@ -0,0 +1,103 @@
+//! Query tagging for database-side correlation.
+//!
+//! When enabled, every repository query gets a trailing comment such as
+//! `/* request_id='…',tenant='acme',route='/api/users/:id',handler='get_user' */`
+//! so slow-query logs on the database can be traced back to the API call.
+//! The request ID and tenant, taken from the `RequestContext`, and the route
+//! come from a task-local set by middleware; the handler is the name of the
+//! innermost `tracing` span.
+
+use axum::{extract::MatchedPath, http::Request, middleware::Next, response::Response};
+
+use crate::context::RequestContext;
+
+tokio::task_local! {
+    static QUERY_CONTEXT: QueryContext;
+}
+
+/// Request attributes attached to queries
+#[derive(Debug, Clone, Default)]
+pub struct QueryContext {
+    /// Request ID
+    pub request_id: Option<String>,
+    /// Tenant the request is made for
+    pub tenant: Option<String>,
+    /// Matched route pattern
+    pub route: Option<String>,
+}
+
+/// Keep only characters that cannot terminate the comment or quoted value
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "-_./:".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Build the comment for the current request context and handler span
+pub fn current_comment() -> Option<String> {
+    let context = QUERY_CONTEXT.try_with(Clone::clone).unwrap_or_default();
+    let handler = tracing::Span::current().metadata().map(|meta| meta.name());
+    let tags: Vec<String> = [
+        ("request_id", context.request_id.as_deref()),
+        ("tenant", context.tenant.as_deref()),
+        ("route", context.route.as_deref()),
+        ("handler", handler),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| value.map(|value| format!("{}='{}'", key, sanitize(value))))
+    .collect();
+    (!tags.is_empty()).then(|| format!("/* {} */", tags.join(",")))
+}
+
+/// Append the current tags to a SQL statement
+pub fn annotate(sql: &str) -> String {
+    match current_comment() {
+        Some(comment) => format!("{} {}", sql, comment),
+        None => sql.to_string(),
+    }
+}
+
+/// Middleware capturing the request attributes used for tagging
+pub async fn scope_query_context<B>(req: Request<B>, next: Next<B>) -> Response {
+    let request = req.extensions().get::<RequestContext>();
+    let context = QueryContext {
+        request_id: request.map(|context| context.request_id.clone()),
+        tenant: request.and_then(|context| context.tenant.clone()),
+        route: req
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|path| path.as_str().to_string()),
+    };
+    QUERY_CONTEXT.scope(context, next.run(req)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_prevents_comment_escape() {
+        assert_eq!(sanitize("abc*/ DROP TABLE users; --"), "abc_/_DROP_TABLE_users__--");
+        assert_eq!(sanitize("it's"), "it_s");
+    }
+
+    #[tokio::test]
+    async fn test_annotate_uses_task_context() {
+        let context = QueryContext {
+            request_id: Some("req-1".to_string()),
+            tenant: None,
+            route: Some("/api/users/:id".to_string()),
+        };
+        let sql = QUERY_CONTEXT
+            .scope(context, async { annotate("SELECT 1") })
+            .await;
+        assert_eq!(sql, "SELECT 1 /* request_id='req-1',route='/api/users/:id' */");
+    }
+}
@@ -0,0 +1,457 @@
+//! SQLite-backed user repository for small self-hosted deployments.
+//!
+//! Compiled in with the `sqlite` feature and chosen by a `sqlite:` scheme in
+//! `database_url`, e.g. `sqlite:///var/lib/app/users.db`; the file is created
+//! when missing and opened in WAL mode, so reads don't wait for writes. The
+//! tables are those of `postgres`, created by the same `migrations`, whose
+//! statements both databases accept: SQLite keeps the Postgres column types
+//! as declared, stores IDs as blobs and times as RFC 3339 text, and honours
+//! the same unique and partial indexes.
+//!
+//! One process owns the file. There are no replicas, no activity `COPY`, and
+//! no reconnecting, since there is no server to lose; migrations rely on
+//! SQLite's write lock rather than an advisory lock.
+
+use async_trait::async_trait;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions};
+use sqlx::types::Json;
+use sqlx::{Sqlite, Transaction};
+use std::str::FromStr;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::events::DomainEvent;
+use crate::migrations::MIGRATIONS;
+use crate::postgres::PoolConfig;
+use crate::repository::{
+    Change, LastSeen, OutboxRecord, OutboxStore, RepositoryError, UserQuery, UserRepository,
+};
+use crate::{metadata, User};
+
+const USER_COLUMNS: &str =
+    "id, tenant_id, username, email, created_at, is_active, deleted_at, metadata";
+
+const CREATE_MIGRATIONS_TABLE: &str = "CREATE TABLE IF NOT EXISTS schema_migrations (\
+    version text PRIMARY KEY, applied_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP)";
+
+/// Row shape of the `users` table
+#[derive(sqlx::FromRow)]
+struct UserRow {
+    id: Uuid,
+    tenant_id: String,
+    username: String,
+    email: String,
+    created_at: chrono::DateTime<chrono::Utc>,
+    is_active: bool,
+    deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+    metadata: Json<serde_json::Value>,
+}
+
+impl From<UserRow> for User {
+    fn from(row: UserRow) -> Self {
+        User {
+            id: row.id,
+            tenant_id: row.tenant_id,
+            username: row.username,
+            email: row.email,
+            created_at: row.created_at,
+            is_active: row.is_active,
+            deleted_at: row.deleted_at,
+            metadata: row.metadata.0,
+        }
+    }
+}
+
+/// The error of a failed statement, naming the column of a unique violation,
+/// which SQLite reports only in its message, e.g.
+/// `UNIQUE constraint failed: users.username`
+fn conflict_or(err: sqlx::Error) -> RepositoryError {
+    if let sqlx::Error::Database(db) = &err {
+        if db.is_unique_violation() {
+            let message = db.message();
+            let field = ["username", "email", "id"]
+                .into_iter()
+                .find(|field| message.contains(field))
+                .unwrap_or("unique");
+            return RepositoryError::Conflict(field.to_string());
+        }
+    }
+    err.into()
+}
+
+/// User repository backed by a SQLite file
+pub struct SqliteUserRepository {
+    pool: SqlitePool,
+}
+
+impl SqliteUserRepository {
+    /// Open the database at `url`, creating the file if it doesn't exist
+    pub async fn open(url: &str, config: &PoolConfig) -> Result<Self, RepositoryError> {
+        let options = SqliteConnectOptions::from_str(url)?
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(Duration::from_millis(config.acquire_timeout_ms));
+        let pool = SqlitePoolOptions::new()
+            .max_connections(config.max_connections)
+            .acquire_timeout(Duration::from_millis(config.acquire_timeout_ms))
+            .connect_with(options)
+            .await?;
+        Ok(Self { pool })
+    }
+
+    async fn fetch_one_by(
+        &self,
+        tenant: &str,
+        column: &str,
+        value: &str,
+    ) -> Result<Option<User>, RepositoryError> {
+        let sql = format!(
+            "SELECT {} FROM users WHERE tenant_id = ? AND {} = ?",
+            USER_COLUMNS, column
+        );
+        let row = sqlx::query_as::<_, UserRow>(&sql)
+            .bind(tenant)
+            .bind(value)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(User::from))
+    }
+
+    async fn insert_in(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        user: &User,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query(
+            "INSERT INTO users (id, tenant_id, username, email, created_at, is_active, deleted_at, \
+             metadata) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(user.id)
+        .bind(&user.tenant_id)
+        .bind(&user.username)
+        .bind(&user.email)
+        .bind(user.created_at)
+        .bind(user.is_active)
+        .bind(user.deleted_at)
+        .bind(Json(&user.metadata))
+        .execute(&mut **tx)
+        .await
+        .map_err(conflict_or)?;
+        Ok(())
+    }
+
+    async fn update_in(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        user: &User,
+    ) -> Result<bool, RepositoryError> {
+        let result = sqlx::query(
+            "UPDATE users SET username = ?, email = ?, is_active = ?, deleted_at = ?, \
+             metadata = ? WHERE id = ? AND tenant_id = ?",
+        )
+        .bind(&user.username)
+        .bind(&user.email)
+        .bind(user.is_active)
+        .bind(user.deleted_at)
+        .bind(Json(&user.metadata))
+        .bind(user.id)
+        .bind(&user.tenant_id)
+        .execute(&mut **tx)
+        .await
+        .map_err(conflict_or)?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn enqueue_in(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        event: &DomainEvent,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query("INSERT INTO outbox (id, payload, created_at) VALUES (?, ?, ?)")
+            .bind(event.id)
+            .bind(Json(event))
+            .bind(chrono::Utc::now())
+            .execute(&mut **tx)
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl OutboxStore for SqliteUserRepository {
+    async fn pending_events(&self, limit: usize) -> Result<Vec<OutboxRecord>, RepositoryError> {
+        let rows: Vec<(Json<DomainEvent>, chrono::DateTime<chrono::Utc>)> = sqlx::query_as(
+            "SELECT payload, created_at FROM outbox WHERE delivered_at IS NULL \
+             ORDER BY created_at LIMIT ?",
+        )
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(Json(event), created_at)| OutboxRecord { event, created_at })
+            .collect())
+    }
+
+    async fn mark_delivered(&self, ids: &[Uuid]) -> Result<(), RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        let now = chrono::Utc::now();
+        for id in ids {
+            sqlx::query("UPDATE outbox SET delivered_at = ? WHERE id = ?")
+                .bind(now)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl UserRepository for SqliteUserRepository {
+    async fn list(&self, tenant: &str, query: &UserQuery) -> Result<Vec<User>, RepositoryError> {
+        let sql = format!(
+            "SELECT {} FROM users WHERE tenant_id = ? AND (? OR deleted_at IS NULL) \
+             AND NOT EXISTS (SELECT 1 FROM json_each(?) AS wanted \
+             WHERE json_extract(users.metadata, '$.\"' || wanted.key || '\"') IS NOT wanted.value) \
+             ORDER BY created_at",
+            USER_COLUMNS
+        );
+        // Without JSONB containment, each filtered key is compared on its own;
+        // keys are checked to need no quoting inside the path
+        let rows = sqlx::query_as::<_, UserRow>(&sql)
+            .bind(tenant)
+            .bind(query.include_deleted)
+            .bind(Json(metadata::containing(&query.metadata)))
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(User::from).collect())
+    }
+
+    async fn get(&self, tenant: &str, id: Uuid) -> Result<Option<User>, RepositoryError> {
+        let sql = format!("SELECT {} FROM users WHERE id = ? AND tenant_id = ?", USER_COLUMNS);
+        let row = sqlx::query_as::<_, UserRow>(&sql)
+            .bind(id)
+            .bind(tenant)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(User::from))
+    }
+
+    async fn find_by_username(
+        &self,
+        tenant: &str,
+        username: &str,
+    ) -> Result<Option<User>, RepositoryError> {
+        self.fetch_one_by(tenant, "username", username).await
+    }
+
+    async fn find_by_email(
+        &self,
+        tenant: &str,
+        email: &str,
+    ) -> Result<Option<User>, RepositoryError> {
+        self.fetch_one_by(tenant, "lower(email)", &email.to_lowercase()).await
+    }
+
+    async fn tenants(&self) -> Result<Vec<String>, RepositoryError> {
+        let tenants = sqlx::query_scalar("SELECT DISTINCT tenant_id FROM users ORDER BY tenant_id")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(tenants)
+    }
+
+    async fn insert(&self, user: User) -> Result<User, RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        self.insert_in(&mut tx, &user).await?;
+        tx.commit().await?;
+        Ok(user)
+    }
+
+    async fn insert_with_event(
+        &self,
+        user: User,
+        event: DomainEvent,
+    ) -> Result<User, RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        self.insert_in(&mut tx, &user).await?;
+        self.enqueue_in(&mut tx, &event).await?;
+        tx.commit().await?;
+        Ok(user)
+    }
+
+    async fn update(&self, user: User) -> Result<Option<User>, RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        let updated = self.update_in(&mut tx, &user).await?;
+        tx.commit().await?;
+        Ok(updated.then_some(user))
+    }
+
+    async fn update_with_event(
+        &self,
+        user: User,
+        event: DomainEvent,
+    ) -> Result<Option<User>, RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        if !self.update_in(&mut tx, &user).await? {
+            // Dropping the transaction rolls it back
+            return Ok(None);
+        }
+        self.enqueue_in(&mut tx, &event).await?;
+        tx.commit().await?;
+        Ok(Some(user))
+    }
+
+    async fn commit(&self, changes: &[Change]) -> Result<bool, RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        for change in changes {
+            match change {
+                Change::Insert(user) => self.insert_in(&mut tx, user).await?,
+                Change::Update(user) => {
+                    if !self.update_in(&mut tx, user).await? {
+                        return Ok(false);
+                    }
+                }
+                Change::Enqueue(event) => self.enqueue_in(&mut tx, event).await?,
+            }
+        }
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn delete(&self, tenant: &str, id: Uuid) -> Result<bool, RepositoryError> {
+        let result = sqlx::query("DELETE FROM users WHERE id = ? AND tenant_id = ?")
+            .bind(id)
+            .bind(tenant)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn record_last_seen(&self, seen: &[LastSeen]) -> Result<(), RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        for &(id, at) in seen {
+            sqlx::query(
+                "INSERT INTO user_activity (user_id, last_seen_at) VALUES (?, ?) \
+                 ON CONFLICT (user_id) DO UPDATE \
+                 SET last_seen_at = max(user_activity.last_seen_at, excluded.last_seen_at)",
+            )
+            .bind(id)
+            .bind(at)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn last_seen(
+        &self,
+        tenant: &str,
+        id: Uuid,
+    ) -> Result<Option<chrono::DateTime<chrono::Utc>>, RepositoryError> {
+        let at = sqlx::query_scalar(
+            "SELECT a.last_seen_at FROM user_activity a JOIN users u ON u.id = a.user_id \
+             WHERE a.user_id = ? AND u.tenant_id = ?",
+        )
+        .bind(id)
+        .bind(tenant)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(at)
+    }
+
+    async fn migrate(&self) -> Result<Vec<&'static str>, RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        // Writing first takes the database's write lock for the whole run
+        sqlx::query(CREATE_MIGRATIONS_TABLE).execute(&mut *tx).await?;
+        let recorded: Vec<String> = sqlx::query_scalar("SELECT version FROM schema_migrations")
+            .fetch_all(&mut *tx)
+            .await?;
+        let mut applied = Vec::new();
+        for migration in MIGRATIONS {
+            if recorded.iter().any(|recorded| recorded == migration.version) {
+                continue;
+            }
+            if !migration.postgres_only {
+                sqlx::Executor::execute(&mut *tx, migration.sql).await?;
+            }
+            sqlx::query("INSERT INTO schema_migrations (version) VALUES (?)")
+                .bind(migration.version)
+                .execute(&mut *tx)
+                .await?;
+            applied.push(migration.version);
+        }
+        tx.commit().await?;
+        Ok(applied)
+    }
+
+    async fn schema_version(&self) -> Result<Option<String>, RepositoryError> {
+        let version: Result<Option<String>, _> =
+            sqlx::query_scalar("SELECT max(version) FROM schema_migrations")
+                .fetch_one(&self.pool)
+                .await;
+        match version {
+            Ok(version) => Ok(version),
+            // Never migrated
+            Err(sqlx::Error::Database(db)) if db.message().contains("no such table") => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn has_schema(&self) -> bool {
+        true
+    }
+
+    async fn ping(&self) -> Result<(), RepositoryError> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventKind;
+
+    async fn open() -> SqliteUserRepository {
+        let config = PoolConfig {
+            // Each connection to `:memory:` opens a database of its own
+            max_connections: 1,
+            ..PoolConfig::default()
+        };
+        let users = SqliteUserRepository::open("sqlite::memory:", &config).await.unwrap();
+        let applied = users.migrate().await.unwrap();
+        let versions: Vec<_> = MIGRATIONS.iter().map(|migration| migration.version).collect();
+        assert_eq!(applied, versions);
+        users
+    }
+
+    #[tokio::test]
+    async fn test_shared_migrations_apply_once() {
+        let users = open().await;
+        assert!(users.migrate().await.unwrap().is_empty());
+        assert_eq!(users.schema_version().await.unwrap().as_deref(), Some("0004_user_search"));
+    }
+
+    #[tokio::test]
+    async fn test_commit_and_unique_email_regardless_of_case() {
+        let users = open().await;
+        let alice = User::new("alice".to_string(), "alice@example.com".to_string());
+        let event = DomainEvent::user(EventKind::UserCreated, &alice);
+        let changes = [Change::Insert(alice.clone()), Change::Enqueue(event)];
+        assert!(users.commit(&changes).await.unwrap());
+        let tenant = &alice.tenant_id;
+        assert_eq!(users.get(tenant, alice.id).await.unwrap().unwrap().username, "alice");
+        let found = users.find_by_email(tenant, "ALICE@example.com").await.unwrap();
+        assert_eq!(found.map(|user| user.id), Some(alice.id));
+
+        let copy = User::new("alicia".to_string(), "Alice@Example.com".to_string());
+        assert!(matches!(
+            users.insert(copy).await,
+            Err(RepositoryError::Conflict(field)) if field == "email"
+        ));
+        assert_eq!(users.pending_events(10).await.unwrap().len(), 1);
+    }
+}
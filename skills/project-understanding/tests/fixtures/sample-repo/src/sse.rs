@@ -0,0 +1,86 @@
+//! Server-Sent Events stream of user changes.
+//!
+//! Each user event is sent with its ID so clients reconnecting with
+//! `Last-Event-ID` receive what they missed from the bus history. When that
+//! cannot be guaranteed, a `resync` event tells the client to refetch. Only
+//! events about users of the request's tenant are sent. The stream ends once
+//! the client disconnects.
+
+use axum::{
+    extract::State,
+    http::HeaderMap,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures::stream::{self, Stream, StreamExt};
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+use crate::cancellation::Cancellation;
+use crate::events::DomainEvent;
+use crate::tenancy::Tenant;
+use crate::AppState;
+
+/// Header browsers send when reconnecting an `EventSource`
+const LAST_EVENT_ID_HEADER: &str = "last-event-id";
+
+/// SSE frame for a domain event
+fn to_sse(event: &DomainEvent) -> Event {
+    Event::default()
+        .id(event.id.to_string())
+        .event(event.kind.as_str())
+        .json_data(event)
+        .expect("domain events always serialize")
+}
+
+/// Frame telling the client its view may be stale
+fn resync() -> Event {
+    Event::default().event("resync").data("events were missed; refetch /api/v1/users")
+}
+
+/// Stream user create, update, and delete events
+pub async fn stream_user_events(
+    State(state): State<Arc<AppState>>,
+    Cancellation(cancellation): Cancellation,
+    Tenant(tenant): Tenant,
+    headers: HeaderMap,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let last_event_id = headers
+        .get(LAST_EVENT_ID_HEADER)
+        .and_then(|value| value.to_str().ok());
+
+    let (replay, receiver) = match last_event_id {
+        None => (Vec::new(), state.events.subscribe()),
+        Some(raw) => match raw.parse::<Uuid>() {
+            Ok(id) => {
+                let (replay, receiver) = state.events.subscribe_after(id);
+                let frames = match replay {
+                    Some(events) => events
+                        .iter()
+                        .filter(|event| event.user.tenant_id == tenant)
+                        .map(to_sse)
+                        .collect(),
+                    None => vec![resync()],
+                };
+                (frames, receiver)
+            }
+            Err(_) => (vec![resync()], state.events.subscribe()),
+        },
+    };
+
+    let live = stream::unfold((receiver, tenant), |(mut receiver, tenant)| async move {
+        let frame = loop {
+            match receiver.recv().await {
+                Ok(event) if event.user.tenant_id == tenant => break to_sse(&event),
+                Ok(_) => continue,
+                Err(RecvError::Lagged(_)) => break resync(),
+                Err(RecvError::Closed) => return None,
+            }
+        };
+        Some((frame, (receiver, tenant)))
+    });
+
+    let frames = stream::iter(replay).chain(live).take_until(cancellation.cancelled_owned());
+    Sse::new(frames.map(Ok)).keep_alive(KeepAlive::default())
+}
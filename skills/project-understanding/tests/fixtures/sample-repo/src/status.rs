@@ -0,0 +1,226 @@
+//! Public status page summary.
+//!
+//! Reports only coarse component states and announcement text, never
+//! probe error details or configuration. The page lists four components,
+//! `api`, `database`, `cache`, and `queue`, each as bad as the worst health
+//! check counting toward it, so internal check names stay off the page.
+//! Announcements name the same components.
+//!
+//! The page is public and unauthenticated, so it is served from health
+//! results up to `MAX_AGE` old, whoever else ran them: however often it is
+//! fetched, each dependency is probed for it at most once per `MAX_AGE`.
+//! It is rate limited and shed like any other normal request.
+
+use axum::{extract::State, response::Json};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::announcements::{Announcement, Severity};
+use crate::health::{CheckReport, HealthState};
+use crate::{ApiResponse, AppState};
+
+/// State of one component on the status page
+#[derive(Debug, Serialize)]
+pub struct ComponentStatus {
+    /// Component name
+    pub name: String,
+    /// Coarse state
+    pub state: HealthState,
+}
+
+/// Incident or maintenance note attached to the status page
+#[derive(Debug, Serialize)]
+pub struct IncidentNote {
+    /// Headline
+    pub title: String,
+    /// Description
+    pub message: String,
+    /// Kind of announcement
+    pub severity: Severity,
+    /// Affected components
+    pub components: Vec<String>,
+    /// When it started
+    pub started_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<Announcement> for IncidentNote {
+    fn from(announcement: Announcement) -> Self {
+        IncidentNote {
+            title: announcement.title,
+            message: announcement.message,
+            severity: announcement.severity,
+            components: announcement.components,
+            started_at: announcement.started_at,
+        }
+    }
+}
+
+/// Status page body
+#[derive(Debug, Serialize)]
+pub struct StatusPage {
+    /// Worst state across all components
+    pub status: HealthState,
+    /// Per-component states
+    pub components: Vec<ComponentStatus>,
+    /// Active incidents and maintenance windows
+    pub incidents: Vec<IncidentNote>,
+    /// When this summary was generated
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Oldest health result the page is served from
+pub const MAX_AGE: Duration = Duration::from_secs(10);
+
+/// Public components, in the order the page lists them
+pub const COMPONENTS: [&str; 4] = ["api", "database", "cache", "queue"];
+
+/// Public component an internal health check counts toward
+fn component_of(check: &str) -> &'static str {
+    match check {
+        "database" | "migrations" | "read_replicas" | "replication" => "database",
+        "user_cache" => "cache",
+        "jobs" => "queue",
+        _ => "api",
+    }
+}
+
+/// Worst state of each public component across the checks counting toward it
+pub fn components(reports: &[CheckReport]) -> Vec<(String, HealthState)> {
+    COMPONENTS
+        .iter()
+        .map(|&component| {
+            let state = reports
+                .iter()
+                .filter(|report| component_of(report.name) == component)
+                .map(|report| report.state)
+                .fold(HealthState::Operational, Ord::max);
+            (component.to_string(), state)
+        })
+        .collect()
+}
+
+/// Lowest state a component can report while an announcement names it
+fn announced_floor(severity: Severity) -> HealthState {
+    match severity {
+        Severity::Info => HealthState::Operational,
+        Severity::Maintenance | Severity::Incident => HealthState::Degraded,
+    }
+}
+
+/// Combine probed states with active announcements
+pub fn summarize(probed: Vec<(String, HealthState)>, active: Vec<Announcement>) -> StatusPage {
+    let components: Vec<ComponentStatus> = probed
+        .into_iter()
+        .map(|(name, probed_state)| {
+            let state = active
+                .iter()
+                .filter(|a| a.components.iter().any(|c| *c == name))
+                .map(|a| announced_floor(a.severity))
+                .fold(probed_state, Ord::max);
+            ComponentStatus { name, state }
+        })
+        .collect();
+    let status = components
+        .iter()
+        .map(|component| component.state)
+        .max()
+        .unwrap_or(HealthState::Operational);
+    let incidents = active
+        .into_iter()
+        .filter(|a| a.severity != Severity::Info)
+        .map(IncidentNote::from)
+        .collect();
+
+    StatusPage {
+        status,
+        components,
+        incidents,
+        updated_at: chrono::Utc::now(),
+    }
+}
+
+/// Status page endpoint
+pub async fn status_page(State(state): State<Arc<AppState>>) -> Json<ApiResponse<StatusPage>> {
+    // The API is answering this request, so it is up unless a check says otherwise
+    let reports = state.readiness.recent(&state.health_checks, MAX_AGE).await;
+    let probed = components(&reports);
+    let active = state.announcements.active().await;
+    Json(ApiResponse::success(summarize(probed, active)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn announcement(severity: Severity, components: &[&str]) -> Announcement {
+        Announcement {
+            id: uuid::Uuid::new_v4(),
+            title: "Elevated errors".to_string(),
+            message: "Investigating".to_string(),
+            severity,
+            components: components.iter().map(|c| c.to_string()).collect(),
+            started_at: chrono::Utc::now(),
+            resolved_at: None,
+        }
+    }
+
+    #[test]
+    fn test_incident_degrades_named_component() {
+        let probed = vec![
+            ("api".to_string(), HealthState::Operational),
+            ("database".to_string(), HealthState::Operational),
+        ];
+        let page = summarize(probed, vec![announcement(Severity::Incident, &["database"])]);
+        assert_eq!(page.components[0].state, HealthState::Operational);
+        assert_eq!(page.components[1].state, HealthState::Degraded);
+        assert_eq!(page.status, HealthState::Degraded);
+        assert_eq!(page.incidents.len(), 1);
+    }
+
+    #[test]
+    fn test_probe_outage_wins_over_announcement() {
+        let probed = vec![("database".to_string(), HealthState::Outage)];
+        let page = summarize(probed, vec![announcement(Severity::Maintenance, &["database"])]);
+        assert_eq!(page.status, HealthState::Outage);
+    }
+
+    fn report(name: &'static str, state: HealthState) -> CheckReport {
+        CheckReport {
+            name,
+            state,
+            fresh: true,
+            age_ms: Some(0),
+        }
+    }
+
+    #[test]
+    fn test_checks_are_folded_into_public_components() {
+        let reports = [
+            report("database", HealthState::Operational),
+            report("replication", HealthState::Degraded),
+            report("user_cache", HealthState::Operational),
+            report("jobs", HealthState::Outage),
+            report("fallbacks", HealthState::Operational),
+        ];
+        let page = summarize(components(&reports), vec![]);
+        let names: Vec<&str> = page.components.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, COMPONENTS);
+        let states: Vec<HealthState> = page.components.iter().map(|c| c.state).collect();
+        let expected = [
+            HealthState::Operational,
+            HealthState::Degraded,
+            HealthState::Operational,
+            HealthState::Outage,
+        ];
+        assert_eq!(states, expected);
+        assert_eq!(page.status, HealthState::Outage);
+    }
+
+    #[test]
+    fn test_info_is_not_an_incident() {
+        let page = summarize(vec![], vec![announcement(Severity::Info, &[])]);
+        assert_eq!(page.status, HealthState::Operational);
+        assert!(page.incidents.is_empty());
+    }
+}
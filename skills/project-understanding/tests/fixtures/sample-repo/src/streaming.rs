@@ -0,0 +1,270 @@
+//! Event streaming to an external broker.
+//!
+//! When `Config::event_stream` is set, user lifecycle events from the bus
+//! are serialized as JSON and published to a Kafka topic or NATS subject.
+//! Each backend is compiled only with its cargo feature (`kafka`, `nats`);
+//! configuring a backend that was not compiled in fails startup.
+//! Events the broker rejects go to the disk spool when one is configured and
+//! are published again every `Config::spool.retry_interval_ms`.
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+
+use crate::events::DomainEvent;
+use crate::lifecycle::Component;
+use crate::AppState;
+
+/// Supported brokers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamBackend {
+    /// Apache Kafka, requires the `kafka` feature
+    Kafka,
+    /// NATS, requires the `nats` feature
+    Nats,
+}
+
+/// Broker connection settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamConfig {
+    /// Which broker to publish to
+    pub backend: StreamBackend,
+    /// Kafka bootstrap servers or NATS server URL
+    pub servers: String,
+    /// Kafka topic or NATS subject
+    pub topic: String,
+}
+
+/// A broker client that accepts serialized events
+#[async_trait]
+pub trait EventPublisher: Send + Sync {
+    /// Publish one payload; `key` is the user ID so per-user order is kept
+    async fn publish(&self, key: &str, payload: &[u8]) -> Result<(), String>;
+}
+
+/// Serialize an event into the wire format shared by all backends
+pub fn encode(event: &DomainEvent) -> Vec<u8> {
+    serde_json::to_vec(event).expect("domain events always serialize")
+}
+
+#[cfg(feature = "kafka")]
+mod kafka {
+    use super::*;
+    use rdkafka::config::ClientConfig;
+    use rdkafka::producer::{FutureProducer, FutureRecord};
+    use std::time::Duration;
+
+    /// Publisher backed by an rdkafka producer
+    pub struct KafkaPublisher {
+        producer: FutureProducer,
+        topic: String,
+    }
+
+    impl KafkaPublisher {
+        /// Create a producer for the configured cluster and topic
+        pub fn connect(config: &StreamConfig) -> Result<Self, String> {
+            let producer = ClientConfig::new()
+                .set("bootstrap.servers", &config.servers)
+                .set("message.timeout.ms", "5000")
+                .create()
+                .map_err(|err| err.to_string())?;
+            Ok(Self {
+                producer,
+                topic: config.topic.clone(),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl EventPublisher for KafkaPublisher {
+        async fn publish(&self, key: &str, payload: &[u8]) -> Result<(), String> {
+            let record = FutureRecord::to(&self.topic).key(key).payload(payload);
+            self.producer
+                .send(record, Duration::from_secs(5))
+                .await
+                .map(|_| ())
+                .map_err(|(err, _)| err.to_string())
+        }
+    }
+}
+
+#[cfg(feature = "nats")]
+mod nats {
+    use super::*;
+
+    /// Publisher backed by a NATS client
+    pub struct NatsPublisher {
+        client: async_nats::Client,
+        subject: String,
+    }
+
+    impl NatsPublisher {
+        /// Connect to the configured server
+        pub async fn connect(config: &StreamConfig) -> Result<Self, String> {
+            let client = async_nats::connect(&config.servers)
+                .await
+                .map_err(|err| err.to_string())?;
+            Ok(Self {
+                client,
+                subject: config.topic.clone(),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl EventPublisher for NatsPublisher {
+        async fn publish(&self, _key: &str, payload: &[u8]) -> Result<(), String> {
+            self.client
+                .publish(self.subject.clone(), payload.to_vec().into())
+                .await
+                .map_err(|err| err.to_string())
+        }
+    }
+}
+
+/// Build a publisher for the configured backend
+pub async fn connect(config: &StreamConfig) -> Result<Arc<dyn EventPublisher>, String> {
+    match config.backend {
+        #[cfg(feature = "kafka")]
+        StreamBackend::Kafka => Ok(Arc::new(kafka::KafkaPublisher::connect(config)?)),
+        #[cfg(feature = "nats")]
+        StreamBackend::Nats => Ok(Arc::new(nats::NatsPublisher::connect(config).await?)),
+        #[allow(unreachable_patterns)]
+        backend => Err(format!("{:?} support was not compiled in", backend)),
+    }
+}
+
+/// Publish one event under its user's key
+async fn publish(publisher: &dyn EventPublisher, event: &DomainEvent) -> Result<(), String> {
+    publisher.publish(&event.user.id.to_string(), &encode(event)).await
+}
+
+/// Publish spooled events again, keeping those the broker still rejects
+async fn retry_spooled(state: &AppState, publisher: &dyn EventPublisher) {
+    let Some(spool) = &state.event_spool else {
+        return;
+    };
+    let retried = spool
+        .retry(|event| async move {
+            match publish(publisher, &event).await {
+                Ok(()) => Ok(()),
+                Err(_) => Err(event),
+            }
+        })
+        .await;
+    match retried {
+        Ok(retried) if retried.kept == 0 => {
+            if retried.delivered > 0 {
+                tracing::info!("published {} spooled events", retried.delivered);
+            }
+            state.degradations.recover("event_stream");
+        }
+        Ok(_) => {}
+        Err(err) => tracing::warn!("failed to retry spooled events: {}", err),
+    }
+}
+
+/// Spawn the publisher loop for an already connected broker
+fn spawn_publisher(
+    state: &Arc<AppState>,
+    config: StreamConfig,
+    publisher: Arc<dyn EventPublisher>,
+) -> JoinHandle<()> {
+    let mut events = state.events.subscribe();
+    let state = state.clone();
+    tokio::spawn(async move {
+        let mut retries = tokio::time::interval(Duration::from_millis(
+            state.config().spool.retry_interval_ms,
+        ));
+        loop {
+            let received = tokio::select! {
+                received = events.recv() => received,
+                _ = retries.tick() => {
+                    retry_spooled(&state, publisher.as_ref()).await;
+                    continue;
+                }
+            };
+            let event = match received {
+                Ok(event) => event,
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("event stream publisher lagged, skipped {} events", skipped);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            let Err(err) = publish(publisher.as_ref(), &event).await else {
+                if state.event_spool.is_none() {
+                    state.degradations.recover("event_stream");
+                }
+                continue;
+            };
+            let (id, topic) = (event.id, &config.topic);
+            tracing::warn!("failed to publish event {} to {}: {}", id, topic, err);
+            let Some(spool) = &state.event_spool else {
+                state.degradations.degrade("event_stream", "dropping_events", err);
+                continue;
+            };
+            match spool.append(&event).await {
+                Ok(()) => state.degradations.degrade("event_stream", "spool_to_disk", err),
+                Err(spool_err) => tracing::error!("lost event {}: {}", id, spool_err),
+            }
+        }
+    })
+}
+
+/// Lifecycle component running the publisher when a stream is configured.
+///
+/// A configured broker that can't be reached fails startup rather than
+/// silently dropping events.
+#[derive(Default)]
+pub struct EventStream {
+    handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+#[async_trait]
+impl Component for EventStream {
+    fn name(&self) -> &'static str {
+        "event_stream"
+    }
+
+    async fn start(&self, state: &Arc<AppState>) -> Result<(), String> {
+        let Some(config) = state.config().event_stream.clone() else {
+            return Ok(());
+        };
+        if state.config().demo.enabled {
+            tracing::info!("demo mode: not publishing events to {:?}", config.backend);
+            return Ok(());
+        }
+        let publisher = connect(&config).await?;
+        let handle = spawn_publisher(state, config, publisher);
+        *self.handle.lock().expect("publisher handle lock poisoned") = Some(handle);
+        Ok(())
+    }
+
+    async fn stop(&self) -> Result<(), String> {
+        if let Some(handle) = self.handle.lock().expect("publisher handle lock poisoned").take() {
+            handle.abort();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventKind;
+    use crate::User;
+
+    #[test]
+    fn test_encode_uses_event_type_names() {
+        let user = User::new("alice".to_string(), "alice@example.com".to_string());
+        let payload = encode(&DomainEvent::user(EventKind::UserCreated, &user));
+        let value: serde_json::Value = serde_json::from_slice(&payload).unwrap();
+        assert_eq!(value["type"], "user.created");
+        assert_eq!(value["user"]["username"], "alice");
+    }
+}
@@ -0,0 +1,82 @@
+//! Pieces of `AppState` that handlers can extract on their own.
+//!
+//! A handler taking `State<Arc<AppState>>` depends on all of the state, and
+//! a test of it has to build all of it. Through axum's `FromRef`, a handler
+//! can instead ask for one component, e.g. `State(cache): State<Shared<UserCache>>`.
+//! The router state stays `Arc<AppState>`; each impl below clones the
+//! component's `Arc` out of it, so the handler holds nothing else, and a
+//! test can call it with `State(Shared(component))` built on its own.
+//! Handlers that need several components or the configuration keep taking
+//! the whole state.
+
+use axum::extract::FromRef;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use crate::bloom::ExistenceFilter;
+use crate::cache::UserCache;
+use crate::integrity::IntegrityStatus;
+use crate::operations::Operations;
+use crate::repository::UserRepository;
+use crate::AppState;
+
+/// One component of the application state
+pub struct Shared<T: ?Sized>(pub Arc<T>);
+
+impl<T: ?Sized> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T: ?Sized> Deref for Shared<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl FromRef<Arc<AppState>> for Shared<dyn UserRepository> {
+    fn from_ref(state: &Arc<AppState>) -> Self {
+        Self(state.users.clone())
+    }
+}
+
+impl FromRef<Arc<AppState>> for Shared<UserCache> {
+    fn from_ref(state: &Arc<AppState>) -> Self {
+        Self(state.user_cache.clone())
+    }
+}
+
+impl FromRef<Arc<AppState>> for Shared<ExistenceFilter> {
+    fn from_ref(state: &Arc<AppState>) -> Self {
+        Self(state.existence_filter.clone())
+    }
+}
+
+impl FromRef<Arc<AppState>> for Shared<IntegrityStatus> {
+    fn from_ref(state: &Arc<AppState>) -> Self {
+        Self(state.integrity.clone())
+    }
+}
+
+impl FromRef<Arc<AppState>> for Shared<Operations> {
+    fn from_ref(state: &Arc<AppState>) -> Self {
+        Self(state.operations.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_components_are_shared_with_the_state() {
+        let state = AppState::new(crate::Config::default());
+        let Shared(cache) = Shared::<UserCache>::from_ref(&state);
+        assert!(Arc::ptr_eq(&cache, &state.user_cache));
+        let Shared(operations) = Shared::<Operations>::from_ref(&state);
+        assert!(Arc::ptr_eq(&operations, &state.operations));
+    }
+}
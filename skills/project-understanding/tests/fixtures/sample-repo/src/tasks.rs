@@ -0,0 +1,158 @@
+//! Supervision of tasks spawned off requests and events.
+//!
+//! Work that outlives the code starting it goes through
+//! `TaskSupervisor::spawn` instead of bare `tokio::spawn`. Examples are
+//! webhook deliveries, list cache refreshes, and async operations. The
+//! supervisor counts its tasks by name, logs a task that panics instead of
+//! letting it vanish, and on shutdown waits up to `DRAIN_TIMEOUT` for the
+//! running ones to finish. Long-lived loops are `lifecycle` components
+//! instead and aren't counted here. Counts are exported at `/metrics`.
+
+use async_trait::async_trait;
+use futures::FutureExt;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio_util::task::TaskTracker;
+
+use crate::lifecycle::Component;
+use crate::AppState;
+
+/// Longest shutdown waits for supervised tasks
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Tasks of one name since startup
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TaskCounts {
+    /// Still running
+    pub running: u64,
+    /// Ran to the end
+    pub completed: u64,
+    /// Panicked
+    pub panicked: u64,
+}
+
+/// Tracks spawned tasks so none fail silently or get cut off at shutdown
+#[derive(Debug, Default)]
+pub struct TaskSupervisor {
+    tracker: TaskTracker,
+    counts: Arc<Mutex<BTreeMap<&'static str, TaskCounts>>>,
+}
+
+/// Moves a task out of `running` however it ends, aborts included
+struct Running {
+    name: &'static str,
+    counts: Arc<Mutex<BTreeMap<&'static str, TaskCounts>>>,
+    outcome: Option<bool>,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().expect("task counts lock poisoned");
+        let counts = counts.entry(self.name).or_default();
+        counts.running -= 1;
+        match self.outcome {
+            Some(true) => counts.completed += 1,
+            Some(false) => counts.panicked += 1,
+            None => {}
+        }
+    }
+}
+
+impl TaskSupervisor {
+    /// Run `task` in the background under `name`
+    pub fn spawn<F>(&self, name: &'static str, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.counts
+            .lock()
+            .expect("task counts lock poisoned")
+            .entry(name)
+            .or_default()
+            .running += 1;
+        let mut running = Running {
+            name,
+            counts: self.counts.clone(),
+            outcome: None,
+        };
+        self.tracker.spawn(async move {
+            let outcome = AssertUnwindSafe(task).catch_unwind().await;
+            if let Err(panic) = &outcome {
+                let message = panic
+                    .downcast_ref::<&str>()
+                    .copied()
+                    .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                    .unwrap_or("unknown panic");
+                tracing::error!("background task {} panicked: {}", name, message);
+            }
+            running.outcome = Some(outcome.is_ok());
+        });
+    }
+
+    /// Counts by task name
+    pub fn snapshot(&self) -> BTreeMap<&'static str, TaskCounts> {
+        self.counts.lock().expect("task counts lock poisoned").clone()
+    }
+
+    /// Stop taking tasks and wait for the running ones, returning how many remain
+    pub async fn drain(&self, timeout: Duration) -> usize {
+        self.tracker.close();
+        if tokio::time::timeout(timeout, self.tracker.wait()).await.is_err() {
+            return self.tracker.len();
+        }
+        0
+    }
+}
+
+/// Component draining the supervisor after everything else has stopped
+#[derive(Default)]
+pub struct TaskDrain {
+    state: Mutex<Option<Arc<AppState>>>,
+}
+
+#[async_trait]
+impl Component for TaskDrain {
+    fn name(&self) -> &'static str {
+        "tasks"
+    }
+
+    async fn start(&self, state: &Arc<AppState>) -> Result<(), String> {
+        *self.state.lock().expect("task drain lock poisoned") = Some(state.clone());
+        Ok(())
+    }
+
+    async fn stop(&self) -> Result<(), String> {
+        let state = self.state.lock().expect("task drain lock poisoned").take();
+        let Some(state) = state else {
+            return Ok(());
+        };
+        match state.tasks.drain(DRAIN_TIMEOUT).await {
+            0 => Ok(()),
+            left => Err(format!("{} tasks still running after {:?}", left, DRAIN_TIMEOUT)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_panics_are_counted_and_drain_waits() {
+        let supervisor = TaskSupervisor::default();
+        let (finish, finished) = tokio::sync::oneshot::channel::<()>();
+        supervisor.spawn("delivery", async {
+            finished.await.unwrap();
+        });
+        supervisor.spawn("delivery", async { panic!("endpoint exploded") });
+
+        assert_eq!(supervisor.drain(Duration::from_millis(20)).await, 1);
+        finish.send(()).unwrap();
+        assert_eq!(supervisor.drain(Duration::from_secs(5)).await, 0);
+        let counts = supervisor.snapshot()["delivery"];
+        assert_eq!(counts, TaskCounts { running: 0, completed: 1, panicked: 1 });
+    }
+}
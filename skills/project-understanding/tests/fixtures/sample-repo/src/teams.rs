@@ -0,0 +1,462 @@
+//! Teams: named groups of users within a tenant.
+//!
+//! A team belongs to the tenant it was created in, and only users of that
+//! tenant can join it. Each membership carries the member's role in the
+//! team, `owner`, `maintainer`, or `member`, for permissions to build on.
+//! Anyone can list a tenant's teams and their members; creating, renaming,
+//! and deleting teams and changing memberships require the admin token and
+//! are audited. `POST /api/v1/teams/:id/members` adds a user, or changes the
+//! role of one already in the team.
+//!
+//! Teams live in memory on each instance, like announcements and webhooks,
+//! so a restart starts without any.
+
+use axum::{extract::State, http::StatusCode, response::Json};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+use uuid::Uuid;
+
+use crate::audit::{self, AuditAction, AuditContext};
+use crate::auth::Admin;
+use crate::error::{AppError, ErrorCode};
+use crate::pagination::PageRequest;
+use crate::tenancy::Tenant;
+use crate::validation::{ApiPath, Validate, ValidatedJson};
+use crate::{ApiResponse, AppState};
+
+/// Longest team name accepted
+const MAX_NAME_LEN: usize = 64;
+
+/// What a member may do in a team, least privileged first
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum TeamRole {
+    /// Belongs to the team
+    #[default]
+    Member,
+    /// Manages the team's members
+    Maintainer,
+    /// Owns the team
+    Owner,
+}
+
+/// A group of users in one tenant
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Team {
+    /// Unique identifier
+    pub id: Uuid,
+    /// Tenant the team belongs to
+    pub tenant_id: String,
+    /// Name, unique within the tenant regardless of case
+    pub name: String,
+    /// What the team is for
+    pub description: Option<String>,
+    /// When the team was created
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// A user's place in a team
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Membership {
+    /// The member
+    pub user_id: Uuid,
+    /// The member's role in the team
+    pub role: TeamRole,
+    /// When the user joined or last changed role
+    pub since: chrono::DateTime<chrono::Utc>,
+}
+
+/// Request body for creating or replacing a team
+#[derive(Debug, Deserialize)]
+pub struct TeamFields {
+    /// Team name
+    pub name: String,
+    /// What the team is for
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+impl Validate for TeamFields {
+    fn validate(&self) -> Vec<String> {
+        let name = self.name.trim();
+        if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+            vec![format!("name must be 1 to {} characters", MAX_NAME_LEN)]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+/// Request body for adding a member or changing their role
+#[derive(Debug, Deserialize)]
+pub struct AddMember {
+    /// User joining the team
+    pub user_id: Uuid,
+    /// Role in the team, `member` by default
+    #[serde(default)]
+    pub role: TeamRole,
+}
+
+/// Why a team change was refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TeamError {
+    /// No such team in the tenant
+    NotFound,
+    /// Another team of the tenant has the name
+    NameTaken,
+}
+
+impl std::fmt::Display for TeamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TeamError::NotFound => write!(f, "team not found"),
+            TeamError::NameTaken => write!(f, "team name already exists"),
+        }
+    }
+}
+
+impl From<TeamError> for AppError {
+    fn from(err: TeamError) -> Self {
+        match err {
+            TeamError::NotFound => {
+                AppError::NotFound(err.to_string()).with_code(ErrorCode::TEAM_NOT_FOUND)
+            }
+            TeamError::NameTaken => {
+                AppError::Conflict(err.to_string()).with_code(ErrorCode::TEAM_NAME_TAKEN)
+            }
+        }
+    }
+}
+
+/// A team and its members, by user ID
+#[derive(Debug)]
+struct Entry {
+    team: Team,
+    members: BTreeMap<Uuid, Membership>,
+}
+
+/// In-memory team store, every lookup scoped to a tenant
+#[derive(Debug, Default)]
+pub struct TeamStore {
+    teams: RwLock<HashMap<Uuid, Entry>>,
+}
+
+/// The entry for `id`, if it belongs to `tenant`
+fn entry<'a>(teams: &'a HashMap<Uuid, Entry>, tenant: &str, id: Uuid) -> Option<&'a Entry> {
+    teams.get(&id).filter(|entry| entry.team.tenant_id == tenant)
+}
+
+/// The entry for `id` to change, if it belongs to `tenant`
+fn entry_mut<'a>(
+    teams: &'a mut HashMap<Uuid, Entry>,
+    tenant: &str,
+    id: Uuid,
+) -> Result<&'a mut Entry, TeamError> {
+    teams
+        .get_mut(&id)
+        .filter(|entry| entry.team.tenant_id == tenant)
+        .ok_or(TeamError::NotFound)
+}
+
+/// Whether a team of `tenant` other than `except` is called `name`
+fn name_taken(
+    teams: &HashMap<Uuid, Entry>,
+    tenant: &str,
+    name: &str,
+    except: Option<Uuid>,
+) -> bool {
+    teams.values().any(|entry| {
+        entry.team.tenant_id == tenant
+            && Some(entry.team.id) != except
+            && entry.team.name.eq_ignore_ascii_case(name)
+    })
+}
+
+impl TeamStore {
+    /// Create a team in `tenant`
+    pub fn create(&self, tenant: &str, fields: TeamFields) -> Result<Team, TeamError> {
+        let mut teams = self.teams.write().expect("teams lock poisoned");
+        let name = fields.name.trim();
+        if name_taken(&teams, tenant, name, None) {
+            return Err(TeamError::NameTaken);
+        }
+        let team = Team {
+            id: Uuid::new_v4(),
+            tenant_id: tenant.to_string(),
+            name: name.to_string(),
+            description: fields.description,
+            created_at: chrono::Utc::now(),
+        };
+        let members = BTreeMap::new();
+        teams.insert(team.id, Entry { team: team.clone(), members });
+        Ok(team)
+    }
+
+    /// A team of `tenant` by ID
+    pub fn get(&self, tenant: &str, id: Uuid) -> Option<Team> {
+        let teams = self.teams.read().expect("teams lock poisoned");
+        entry(&teams, tenant, id).map(|entry| entry.team.clone())
+    }
+
+    /// Every team of `tenant`, by name
+    pub fn list(&self, tenant: &str) -> Vec<Team> {
+        let teams = self.teams.read().expect("teams lock poisoned");
+        let mut listed: Vec<Team> = teams
+            .values()
+            .filter(|entry| entry.team.tenant_id == tenant)
+            .map(|entry| entry.team.clone())
+            .collect();
+        listed.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+        listed
+    }
+
+    /// Replace a team's name and description, returning it before and after
+    pub fn update(
+        &self,
+        tenant: &str,
+        id: Uuid,
+        fields: TeamFields,
+    ) -> Result<(Team, Team), TeamError> {
+        let mut teams = self.teams.write().expect("teams lock poisoned");
+        let name = fields.name.trim();
+        entry_mut(&mut teams, tenant, id)?;
+        if name_taken(&teams, tenant, name, Some(id)) {
+            return Err(TeamError::NameTaken);
+        }
+        let entry = entry_mut(&mut teams, tenant, id)?;
+        let before = entry.team.clone();
+        entry.team.name = name.to_string();
+        entry.team.description = fields.description;
+        Ok((before, entry.team.clone()))
+    }
+
+    /// Delete a team and its memberships, returning the team if it existed
+    pub fn delete(&self, tenant: &str, id: Uuid) -> Option<Team> {
+        let mut teams = self.teams.write().expect("teams lock poisoned");
+        entry(&teams, tenant, id)?;
+        teams.remove(&id).map(|entry| entry.team)
+    }
+
+    /// Members of a team, by user ID
+    pub fn members(&self, tenant: &str, id: Uuid) -> Result<Vec<Membership>, TeamError> {
+        let teams = self.teams.read().expect("teams lock poisoned");
+        let entry = entry(&teams, tenant, id).ok_or(TeamError::NotFound)?;
+        Ok(entry.members.values().cloned().collect())
+    }
+
+    /// Teams of `tenant` that `user_id` belongs to, with their membership
+    pub fn memberships_of(&self, tenant: &str, user_id: Uuid) -> Vec<(Team, Membership)> {
+        let teams = self.teams.read().expect("teams lock poisoned");
+        let mut memberships: Vec<(Team, Membership)> = teams
+            .values()
+            .filter(|entry| entry.team.tenant_id == tenant)
+            .filter_map(|entry| {
+                let membership = entry.members.get(&user_id)?;
+                Some((entry.team.clone(), membership.clone()))
+            })
+            .collect();
+        memberships.sort_by(|a, b| a.0.name.to_lowercase().cmp(&b.0.name.to_lowercase()));
+        memberships
+    }
+
+    /// Add a member or change their role, returning the membership replaced
+    pub fn set_member(
+        &self,
+        tenant: &str,
+        id: Uuid,
+        user_id: Uuid,
+        role: TeamRole,
+    ) -> Result<(Option<Membership>, Membership), TeamError> {
+        let mut teams = self.teams.write().expect("teams lock poisoned");
+        let entry = entry_mut(&mut teams, tenant, id)?;
+        let membership = Membership {
+            user_id,
+            role,
+            since: chrono::Utc::now(),
+        };
+        let replaced = entry.members.insert(user_id, membership.clone());
+        Ok((replaced, membership))
+    }
+
+    /// Remove a member, returning their membership if they had one
+    pub fn remove_member(
+        &self,
+        tenant: &str,
+        id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<Membership>, TeamError> {
+        let mut teams = self.teams.write().expect("teams lock poisoned");
+        Ok(entry_mut(&mut teams, tenant, id)?.members.remove(&user_id))
+    }
+}
+
+/// Teams of the request's tenant, a page at a time when `per_page` is given
+pub async fn list_teams(
+    State(state): State<Arc<AppState>>,
+    Tenant(tenant): Tenant,
+    page: PageRequest,
+) -> Json<ApiResponse<Vec<Team>>> {
+    let (teams, meta, links) = page.paginate(state.teams.list(&tenant));
+    Json(ApiResponse::page(teams, meta, links))
+}
+
+/// Create a team
+pub async fn create_team(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    context: AuditContext,
+    Tenant(tenant): Tenant,
+    ValidatedJson(fields): ValidatedJson<TeamFields>,
+) -> Result<(StatusCode, Json<ApiResponse<Team>>), AppError> {
+    let team = state.teams.create(&tenant, fields)?;
+    audit::record(&state, &context, "team", team.id, AuditAction::Create, None, Some(&team))
+        .await;
+    Ok((StatusCode::CREATED, Json(ApiResponse::success(team))))
+}
+
+/// A team by ID
+pub async fn get_team(
+    State(state): State<Arc<AppState>>,
+    Tenant(tenant): Tenant,
+    ApiPath(id): ApiPath<Uuid>,
+) -> Result<Json<ApiResponse<Team>>, AppError> {
+    let team = state.teams.get(&tenant, id).ok_or(TeamError::NotFound)?;
+    Ok(Json(ApiResponse::success(team)))
+}
+
+/// Rename a team or change its description
+pub async fn update_team(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    context: AuditContext,
+    Tenant(tenant): Tenant,
+    ApiPath(id): ApiPath<Uuid>,
+    ValidatedJson(fields): ValidatedJson<TeamFields>,
+) -> Result<Json<ApiResponse<Team>>, AppError> {
+    let (before, team) = state.teams.update(&tenant, id, fields)?;
+    audit::record(&state, &context, "team", id, AuditAction::Update, Some(&before), Some(&team))
+        .await;
+    Ok(Json(ApiResponse::success(team)))
+}
+
+/// Delete a team along with its memberships
+pub async fn delete_team(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    context: AuditContext,
+    Tenant(tenant): Tenant,
+    ApiPath(id): ApiPath<Uuid>,
+) -> Result<StatusCode, AppError> {
+    match state.teams.delete(&tenant, id) {
+        Some(team) => {
+            audit::record(&state, &context, "team", id, AuditAction::Delete, Some(&team), None)
+                .await;
+            Ok(StatusCode::NO_CONTENT)
+        }
+        None if state.config().idempotent_deletes => Ok(StatusCode::NO_CONTENT),
+        None => Err(TeamError::NotFound.into()),
+    }
+}
+
+/// Members of a team
+pub async fn list_members(
+    State(state): State<Arc<AppState>>,
+    Tenant(tenant): Tenant,
+    ApiPath(id): ApiPath<Uuid>,
+) -> Result<Json<ApiResponse<Vec<Membership>>>, AppError> {
+    Ok(Json(ApiResponse::success(state.teams.members(&tenant, id)?)))
+}
+
+/// Add a user of the tenant to a team, or change their role in it
+pub async fn add_member(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    context: AuditContext,
+    Tenant(tenant): Tenant,
+    ApiPath(id): ApiPath<Uuid>,
+    Json(request): Json<AddMember>,
+) -> Result<(StatusCode, Json<ApiResponse<Membership>>), AppError> {
+    if state.teams.get(&tenant, id).is_none() {
+        return Err(TeamError::NotFound.into());
+    }
+    // Looking the user up in the tenant keeps other tenants' users out
+    state.services.users.get(&state, &tenant, request.user_id, false).await?;
+    let (replaced, membership) =
+        state.teams.set_member(&tenant, id, request.user_id, request.role)?;
+    let (action, status) = match replaced {
+        Some(_) => (AuditAction::Update, StatusCode::OK),
+        None => (AuditAction::Create, StatusCode::CREATED),
+    };
+    let (before, after) = (replaced.as_ref(), Some(&membership));
+    audit::record(&state, &context, "team_membership", id, action, before, after).await;
+    Ok((status, Json(ApiResponse::success(membership))))
+}
+
+/// Remove a member from a team
+pub async fn remove_member(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    context: AuditContext,
+    Tenant(tenant): Tenant,
+    ApiPath((id, user_id)): ApiPath<(Uuid, Uuid)>,
+) -> Result<StatusCode, AppError> {
+    match state.teams.remove_member(&tenant, id, user_id)? {
+        Some(membership) => {
+            let (action, before) = (AuditAction::Delete, Some(&membership));
+            audit::record(&state, &context, "team_membership", id, action, before, None).await;
+            Ok(StatusCode::NO_CONTENT)
+        }
+        None if state.config().idempotent_deletes => Ok(StatusCode::NO_CONTENT),
+        None => Err(AppError::NotFound(format!("user {} is not a member", user_id))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(name: &str) -> TeamFields {
+        TeamFields {
+            name: name.to_string(),
+            description: None,
+        }
+    }
+
+    #[test]
+    fn test_teams_are_scoped_to_their_tenant() {
+        let store = TeamStore::default();
+        let team = store.create("acme", fields(" Platform ")).unwrap();
+        assert_eq!(team.name, "Platform");
+        assert_eq!(store.create("acme", fields("platform")), Err(TeamError::NameTaken));
+        let other = store.create("globex", fields("Platform")).unwrap();
+
+        assert!(store.get("globex", team.id).is_none());
+        assert_eq!(store.list("acme"), vec![team.clone()]);
+        assert!(store.delete("globex", team.id).is_none());
+        let user = Uuid::new_v4();
+        let denied = store.set_member("globex", team.id, user, TeamRole::Owner);
+        assert_eq!(denied.unwrap_err(), TeamError::NotFound);
+        assert!(store.members("acme", team.id).unwrap().is_empty());
+        assert!(store.delete("globex", other.id).is_some());
+    }
+
+    #[test]
+    fn test_adding_a_member_again_changes_their_role() {
+        let store = TeamStore::default();
+        let team = store.create("acme", fields("Platform")).unwrap();
+        let user = Uuid::new_v4();
+        let (replaced, _) = store.set_member("acme", team.id, user, TeamRole::Member).unwrap();
+        assert!(replaced.is_none());
+        let (replaced, membership) =
+            store.set_member("acme", team.id, user, TeamRole::Maintainer).unwrap();
+        assert_eq!(replaced.map(|membership| membership.role), Some(TeamRole::Member));
+        assert_eq!(store.members("acme", team.id).unwrap(), vec![membership]);
+
+        assert!(store.remove_member("acme", team.id, user).unwrap().is_some());
+        assert!(store.remove_member("acme", team.id, user).unwrap().is_none());
+        assert!(fields("").validate().len() == 1 && fields("Ops").validate().is_empty());
+    }
+}
@@ -0,0 +1,453 @@
+//! Request tracing with head and tail-based sampling.
+//!
+//! Every request gets a root `request` span. `SamplingLayer` buffers the
+//! spans of each trace and, when the root closes, decides whether to hand
+//! the trace to the exporter: either it was head-sampled up front, or the
+//! tail rules keep it because it failed or ran slower than its route's
+//! threshold. Traces that can no longer be kept are never buffered.
+//!
+//! Logs are human-readable by default; `Config::log_format = "json"` writes
+//! one JSON object per line for shipping to a log store.
+//!
+//! With `Config::otel` configured, spans are also exported over OTLP, the
+//! request span continuing the caller's `traceparent`. Export is head-sampled
+//! at the same ratio (see `otel`), so it can't undo the sampling here.
+
+use axum::{
+    extract::MatchedPath,
+    http::Request,
+    middleware::Next,
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::field::{Field, Visit};
+use tracing::{span, Instrument, Subscriber};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+use crate::debug_targets::TargetedFilter;
+use crate::context::RequestContext;
+use crate::{otel, Config};
+
+/// Name of the root span opened for each request
+pub const REQUEST_SPAN: &str = "request";
+
+/// Log line format
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Pretty,
+    /// One JSON object per line, event fields at the top level
+    Json,
+}
+
+/// Sampling configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SamplingConfig {
+    /// Fraction of requests traced up front, from 0.0 to 1.0
+    pub head_ratio: f64,
+    /// Keep failed or slow requests regardless of the head decision
+    pub tail_enabled: bool,
+    /// Status code at or above which a request counts as failed
+    pub error_status: u16,
+    /// Latency above which a request counts as slow
+    pub slow_threshold_ms: u64,
+    /// Per-route slow thresholds keyed by route pattern, e.g. `/api/users/:id`
+    pub route_slow_threshold_ms: HashMap<String, u64>,
+}
+
+impl Default for SamplingConfig {
+    fn default() -> Self {
+        SamplingConfig {
+            head_ratio: 0.01,
+            tail_enabled: true,
+            error_status: 500,
+            slow_threshold_ms: 1000,
+            route_slow_threshold_ms: HashMap::new(),
+        }
+    }
+}
+
+/// Why a trace was kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SampleReason {
+    /// Selected by the head sampler
+    Head,
+    /// The request failed
+    Error,
+    /// The request exceeded its latency threshold
+    Slow,
+}
+
+/// Sampling decisions
+#[derive(Debug, Clone)]
+pub struct Sampler {
+    config: SamplingConfig,
+}
+
+impl Sampler {
+    /// Create a sampler from configuration
+    pub fn new(config: SamplingConfig) -> Self {
+        Self { config }
+    }
+
+    /// Deterministic head decision, so every span of a trace agrees
+    pub fn head_sample(&self, trace_id: &str) -> bool {
+        let ratio = self.config.head_ratio;
+        if ratio >= 1.0 {
+            return true;
+        }
+        if ratio <= 0.0 {
+            return false;
+        }
+        let mut hasher = DefaultHasher::new();
+        trace_id.hash(&mut hasher);
+        (hasher.finish() as f64 / u64::MAX as f64) < ratio
+    }
+
+    /// Whether traces must be buffered in case the tail rules keep them
+    pub fn tail_enabled(&self) -> bool {
+        self.config.tail_enabled
+    }
+
+    /// Slow threshold for a route
+    pub fn slow_threshold(&self, route: &str) -> Duration {
+        let millis = self
+            .config
+            .route_slow_threshold_ms
+            .get(route)
+            .copied()
+            .unwrap_or(self.config.slow_threshold_ms);
+        Duration::from_millis(millis)
+    }
+
+    /// Tail decision once the request has finished
+    pub fn tail_reason(
+        &self,
+        route: &str,
+        status: Option<u16>,
+        duration: Duration,
+    ) -> Option<SampleReason> {
+        if !self.config.tail_enabled {
+            return None;
+        }
+        if status.map_or(false, |status| status >= self.config.error_status) {
+            return Some(SampleReason::Error);
+        }
+        if duration > self.slow_threshold(route) {
+            return Some(SampleReason::Slow);
+        }
+        None
+    }
+}
+
+/// A completed span
+#[derive(Debug, Clone, Serialize)]
+pub struct FinishedSpan {
+    /// Span name
+    pub name: &'static str,
+    /// Wall-clock start
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    /// Duration in microseconds
+    pub duration_us: u128,
+    /// Recorded fields
+    pub fields: BTreeMap<String, String>,
+}
+
+/// A completed, sampled trace
+#[derive(Debug, Clone, Serialize)]
+pub struct FinishedTrace {
+    /// Trace identifier (the request ID)
+    pub trace_id: String,
+    /// Why it was kept
+    pub reason: SampleReason,
+    /// Spans in completion order, root last
+    pub spans: Vec<FinishedSpan>,
+}
+
+/// Destination for sampled traces
+pub trait SpanExporter: Send + Sync + 'static {
+    /// Export one trace; must not emit `tracing` events itself
+    fn export(&self, trace: FinishedTrace);
+}
+
+/// Exporter writing each trace as a JSON line on stdout
+pub struct StdoutExporter;
+
+impl SpanExporter for StdoutExporter {
+    fn export(&self, trace: FinishedTrace) {
+        if let Ok(line) = serde_json::to_string(&trace) {
+            println!("{}", line);
+        }
+    }
+}
+
+/// Field values captured from span attributes and records
+#[derive(Default)]
+struct FieldMap(BTreeMap<String, String>);
+
+impl Visit for FieldMap {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0.insert(field.name().to_string(), format!("{:?}", value));
+    }
+}
+
+/// Per-span timing kept in span extensions
+struct SpanTiming {
+    started: Instant,
+    started_at: chrono::DateTime<chrono::Utc>,
+    fields: FieldMap,
+}
+
+/// Per-trace buffer kept in the root span's extensions
+struct TraceBuffer {
+    trace_id: String,
+    head_sampled: bool,
+    spans: Vec<FinishedSpan>,
+}
+
+/// Layer applying the sampling policy before export
+pub struct SamplingLayer<E> {
+    sampler: Sampler,
+    exporter: Arc<E>,
+}
+
+impl<E: SpanExporter> SamplingLayer<E> {
+    /// Create a layer sending kept traces to `exporter`
+    pub fn new(sampler: Sampler, exporter: Arc<E>) -> Self {
+        Self { sampler, exporter }
+    }
+}
+
+impl<S, E> Layer<S> for SamplingLayer<E>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    E: SpanExporter,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        let mut fields = FieldMap::default();
+        attrs.record(&mut fields);
+
+        let is_root = span.parent().is_none();
+        if is_root && span.name() != REQUEST_SPAN {
+            return;
+        }
+        if !is_root {
+            // Only buffer children of traces that may still be kept
+            let tracked = span
+                .scope()
+                .from_root()
+                .next()
+                .map_or(false, |root| root.extensions().get::<TraceBuffer>().is_some());
+            if !tracked {
+                return;
+            }
+        }
+
+        let mut extensions = span.extensions_mut();
+        if is_root {
+            let trace_id = fields
+                .0
+                .get("request_id")
+                .cloned()
+                .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+            let head_sampled = self.sampler.head_sample(&trace_id);
+            if !head_sampled && !self.sampler.tail_enabled() {
+                return;
+            }
+            extensions.insert(TraceBuffer {
+                trace_id,
+                head_sampled,
+                spans: Vec::new(),
+            });
+        }
+        extensions.insert(SpanTiming {
+            started: Instant::now(),
+            started_at: chrono::Utc::now(),
+            fields,
+        });
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        if let Some(timing) = span.extensions_mut().get_mut::<SpanTiming>() {
+            values.record(&mut timing.fields);
+        }
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else { return };
+        let Some(timing) = span.extensions_mut().remove::<SpanTiming>() else { return };
+        let duration = timing.started.elapsed();
+        let finished = FinishedSpan {
+            name: span.name(),
+            started_at: timing.started_at,
+            duration_us: duration.as_micros(),
+            fields: timing.fields.0,
+        };
+
+        if span.parent().is_some() {
+            if let Some(root) = span.scope().from_root().next() {
+                if let Some(buffer) = root.extensions_mut().get_mut::<TraceBuffer>() {
+                    buffer.spans.push(finished);
+                }
+            }
+            return;
+        }
+
+        let Some(mut buffer) = span.extensions_mut().remove::<TraceBuffer>() else { return };
+        let route = finished.fields.get("route").map(String::as_str).unwrap_or("");
+        let status = finished
+            .fields
+            .get("status")
+            .and_then(|status| status.parse().ok());
+        let reason = if buffer.head_sampled {
+            Some(SampleReason::Head)
+        } else {
+            self.sampler.tail_reason(route, status, duration)
+        };
+        if let Some(reason) = reason {
+            buffer.spans.push(finished);
+            self.exporter.export(FinishedTrace {
+                trace_id: buffer.trace_id,
+                reason,
+                spans: buffer.spans,
+            });
+        }
+    }
+}
+
+/// Level of `Config::log_level`, `info` when it doesn't parse
+pub fn base_level(config: &Config) -> tracing::level_filters::LevelFilter {
+    config
+        .log_level
+        .parse()
+        .unwrap_or(tracing::level_filters::LevelFilter::INFO)
+}
+
+/// Install the global subscriber: logs in `Config::log_format` plus sampled traces
+pub fn init(config: &Config) {
+    use tracing_subscriber::prelude::*;
+
+    let base_level = base_level(config);
+    let sampling = SamplingLayer::new(
+        Sampler::new(config.trace_sampling.clone()),
+        Arc::new(StdoutExporter),
+    );
+    let export = otel::tracer(&config.otel, &config.trace_sampling)
+        .map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer));
+    let filter = TargetedFilter::new(base_level);
+    let (pretty, json) = match config.log_format {
+        LogFormat::Pretty => (Some(tracing_subscriber::fmt::layer().with_filter(filter)), None),
+        LogFormat::Json => {
+            let layer = tracing_subscriber::fmt::layer()
+                .json()
+                .flatten_event(true)
+                .with_current_span(false)
+                .with_span_list(false);
+            (None, Some(layer.with_filter(filter)))
+        }
+    };
+    tracing_subscriber::registry()
+        .with(pretty)
+        .with(json)
+        .with(sampling)
+        .with(export)
+        .init();
+}
+
+/// Middleware opening the root span for each request
+pub async fn trace_request<B>(req: Request<B>, next: Next<B>) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let context = req.extensions().get::<RequestContext>();
+    let request_id = context.map(|context| context.request_id.as_str()).unwrap_or_default();
+    let tenant = context.and_then(|context| context.tenant.as_deref());
+    let span = tracing::info_span!(
+        REQUEST_SPAN,
+        method = %req.method(),
+        route = %route,
+        request_id = %request_id,
+        tenant = tenant,
+        status = tracing::field::Empty,
+        latency_ms = tracing::field::Empty,
+        otel.name = %format!("{} {}", req.method(), route),
+        otel.kind = "server",
+    );
+    span.set_parent(otel::parent_context(req.headers()));
+
+    let started = Instant::now();
+    let response = next.run(req).instrument(span.clone()).await;
+    span.record("status", response.status().as_u16());
+    span.record("latency_ms", started.elapsed().as_millis() as u64);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sampler(head_ratio: f64) -> Sampler {
+        let mut config = SamplingConfig {
+            head_ratio,
+            ..SamplingConfig::default()
+        };
+        config
+            .route_slow_threshold_ms
+            .insert("/api/users/import".to_string(), 30_000);
+        Sampler::new(config)
+    }
+
+    #[test]
+    fn test_head_ratio_bounds() {
+        assert!(sampler(1.0).head_sample("any"));
+        assert!(!sampler(0.0).head_sample("any"));
+    }
+
+    #[test]
+    fn test_head_decision_is_deterministic() {
+        let sampler = sampler(0.5);
+        assert_eq!(sampler.head_sample("abc"), sampler.head_sample("abc"));
+    }
+
+    #[test]
+    fn test_tail_keeps_errors_and_slow_requests() {
+        let sampler = sampler(0.0);
+        let fast = Duration::from_millis(5);
+        let slow = Duration::from_secs(2);
+        assert_eq!(sampler.tail_reason("/api/users", Some(503), fast), Some(SampleReason::Error));
+        assert_eq!(sampler.tail_reason("/api/users", Some(200), slow), Some(SampleReason::Slow));
+        assert_eq!(sampler.tail_reason("/api/users", Some(200), fast), None);
+    }
+
+    #[test]
+    fn test_log_format_names() {
+        let format: LogFormat = serde_json::from_str("\"json\"").unwrap();
+        assert_eq!(format, LogFormat::Json);
+        assert_eq!(LogFormat::default(), LogFormat::Pretty);
+    }
+
+    #[test]
+    fn test_route_threshold_override() {
+        let sampler = sampler(0.0);
+        let slow = Duration::from_secs(2);
+        assert_eq!(sampler.tail_reason("/api/users/import", Some(200), slow), None);
+    }
+}
@@ -0,0 +1,179 @@
+//! Tenants: isolated customers served by one deployment.
+//!
+//! Every user belongs to one tenant, recorded as `User::tenant_id`, and the
+//! repository scopes every query by it: lookups, lists, and deletes take the
+//! tenant, and writes only replace a stored user of the same tenant, so a
+//! caller working for one tenant can't read or change another's users, even
+//! by ID. Usernames and emails are unique within a tenant.
+//!
+//! `attach_context` works out a request's tenant from `Config::tenancy.sources`
+//! in order: the `X-Tenant-Id` header, or the subdomain of `Host` under
+//! `base_domain`. Handlers take the result as a `Tenant`. A request naming no
+//! tenant is served the `default` tenant, or refused when `required` is set;
+//! deployments that predate tenants keep all their users there. Both sources
+//! are set by whoever sends the request: run a multi-tenant deployment behind
+//! a gateway that authenticates callers and sets the header or host from
+//! their credentials, such as a JWT's tenant claim, and rejects values the
+//! caller sent. Bearer tokens reaching the service are opaque admin tokens,
+//! with no claims of their own to read.
+
+use async_trait::async_trait;
+use axum::{
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderMap},
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::context::{RequestContext, TENANT_HEADER};
+use crate::error::AppError;
+use crate::AppState;
+
+/// Tenant of requests that don't name one, and of users from before tenants
+pub const DEFAULT_TENANT: &str = "default";
+
+/// Longest tenant ID accepted, that of a DNS label
+const MAX_ID_LEN: usize = 63;
+
+/// Where a request's tenant is read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TenantSource {
+    /// The `X-Tenant-Id` header
+    Header,
+    /// The first label of `Host` under `TenancyConfig::base_domain`
+    Subdomain,
+}
+
+/// How requests are assigned to tenants
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TenancyConfig {
+    /// Sources tried in order; the first naming a tenant wins
+    pub sources: Vec<TenantSource>,
+    /// Domain whose subdomains name tenants, e.g. `api.example.com`
+    pub base_domain: Option<String>,
+    /// Refuse requests naming no tenant instead of serving the default one
+    pub required: bool,
+}
+
+impl Default for TenancyConfig {
+    fn default() -> Self {
+        TenancyConfig {
+            sources: vec![TenantSource::Header],
+            base_domain: None,
+            required: false,
+        }
+    }
+}
+
+/// Tenant of users deserialized without one
+pub fn default_tenant() -> String {
+    DEFAULT_TENANT.to_string()
+}
+
+/// Why a tenant ID is unusable, if it is
+pub fn check_id(tenant: &str) -> Option<String> {
+    let valid = tenant
+        .bytes()
+        .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_');
+    if tenant.is_empty() || tenant.len() > MAX_ID_LEN || !valid {
+        Some(format!(
+            "tenant {:?} must be 1 to {} letters, digits, '-' or '_'",
+            tenant, MAX_ID_LEN
+        ))
+    } else {
+        None
+    }
+}
+
+/// Tenant a request names, by the configured sources
+pub fn resolve(config: &TenancyConfig, headers: &HeaderMap) -> Option<String> {
+    let value = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    config.sources.iter().find_map(|source| match source {
+        TenantSource::Header => value(TENANT_HEADER).map(str::to_string),
+        TenantSource::Subdomain => {
+            let base = config.base_domain.as_deref()?.to_ascii_lowercase();
+            let host = value(header::HOST)?;
+            let host = host.split(':').next().unwrap_or(host).to_ascii_lowercase();
+            let label = host.strip_suffix(&base)?.strip_suffix('.')?;
+            (!label.is_empty() && !label.contains('.')).then(|| label.to_string())
+        }
+    })
+}
+
+/// Tenant to serve a request naming `named`, or why it can't be served
+pub fn serve(config: &TenancyConfig, named: Option<String>) -> Result<String, String> {
+    match named {
+        Some(tenant) => match check_id(&tenant) {
+            Some(problem) => Err(problem),
+            None => Ok(tenant),
+        },
+        None if config.required => {
+            Err(format!("requests must name a tenant with {}", TENANT_HEADER))
+        }
+        None => Ok(default_tenant()),
+    }
+}
+
+/// The tenant a request is served for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tenant(pub String);
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for Tenant {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let context = match RequestContext::from_request_parts(parts, state).await {
+            Ok(context) => context,
+            Err(never) => match never {},
+        };
+        serve(&state.config().tenancy, context.tenant)
+            .map(Tenant)
+            .map_err(AppError::BadRequest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_tenant_is_read_from_the_first_source_naming_one() {
+        let config = TenancyConfig {
+            sources: vec![TenantSource::Subdomain, TenantSource::Header],
+            base_domain: Some("api.example.com".to_string()),
+            required: false,
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(TENANT_HEADER, HeaderValue::from_static("globex"));
+        assert_eq!(resolve(&config, &headers).as_deref(), Some("globex"));
+
+        headers.insert(header::HOST, HeaderValue::from_static("Acme.api.example.com:8080"));
+        assert_eq!(resolve(&config, &headers).as_deref(), Some("acme"));
+        let shouted = TenancyConfig {
+            base_domain: Some("API.Example.com".to_string()),
+            ..config.clone()
+        };
+        assert_eq!(resolve(&shouted, &headers).as_deref(), Some("acme"));
+        for host in ["api.example.com", "a.b.api.example.com", "acme.example.org"] {
+            headers.insert(header::HOST, HeaderValue::from_static(host));
+            assert_eq!(resolve(&config, &headers).as_deref(), Some("globex"), "{}", host);
+        }
+        assert!(resolve(&TenancyConfig::default(), &HeaderMap::new()).is_none());
+
+        assert_eq!(serve(&config, None).as_deref(), Ok(DEFAULT_TENANT));
+        assert!(serve(&config, Some("acme/eu".to_string())).is_err());
+        let required = TenancyConfig {
+            required: true,
+            ..config
+        };
+        assert!(serve(&required, None).is_err());
+        assert_eq!(serve(&required, Some("acme-eu_1".to_string())).as_deref(), Ok("acme-eu_1"));
+    }
+}
@@ -0,0 +1,208 @@
+//! Helpers for testing against the API, shared by every test that needs them.
+//!
+//! `TestServer` serves the real router on a local port with the in-memory
+//! backends and each connection's peer address, accepting `ADMIN_TOKEN`, and
+//! registers whichever background tasks the test relies on, or calls the
+//! fake `Services` it is given.
+//! `UserBuilder` makes users with defaults for every field a test doesn't
+//! care about, and can insert them straight into the repository without
+//! going through the API. `expect_data` and `expect_error` check a
+//! response's status and envelope and hand back what the test wants from it.
+//!
+//! Compiled for the crate's own tests, and with the `test-support` feature
+//! for builds that test against the API from outside it.
+
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::lifecycle::Lifecycle;
+use crate::repository::InMemoryUserRepository;
+use crate::services::Services;
+use crate::{handlers, AppState, Config, User};
+
+/// Admin token every test server accepts
+pub const ADMIN_TOKEN: &str = "test-admin";
+
+/// A server running the full router, as `serve` would run it
+pub struct TestServer {
+    /// State the router serves, for arranging and inspecting directly
+    pub state: Arc<AppState>,
+    client: Client,
+    base_url: String,
+}
+
+impl TestServer {
+    /// Start the router with no background tasks
+    pub async fn start(config: Config) -> Self {
+        Self::start_with(config, |_| {}).await
+    }
+
+    /// Start the router with the tasks `register` adds to the lifecycle
+    pub async fn start_with(config: Config, register: impl FnOnce(&mut Lifecycle)) -> Self {
+        Self::serve(AppState::new(Self::configure(config)), register).await
+    }
+
+    /// Start the router calling `services` instead of the real ones
+    pub async fn start_with_services(config: Config, services: Services) -> Self {
+        let users = Arc::new(InMemoryUserRepository::new());
+        let state = AppState::with_services(Self::configure(config), users, services);
+        Self::serve(state, |_| {}).await
+    }
+
+    fn configure(config: Config) -> Config {
+        Config {
+            admin_token: Some(ADMIN_TOKEN.to_string()),
+            ..config
+        }
+    }
+
+    async fn serve(state: Arc<AppState>, register: impl FnOnce(&mut Lifecycle)) -> Self {
+        let mut lifecycle = Lifecycle::new();
+        register(&mut lifecycle);
+        lifecycle.start(&state).await.unwrap();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = handlers::create_router(state.clone());
+        let app = app.into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app));
+        TestServer {
+            state,
+            client: Client::new(),
+            base_url: format!("http://{}", addr),
+        }
+    }
+
+    /// Address the router is served on, as `host:port`
+    pub fn addr(&self) -> &str {
+        self.base_url.trim_start_matches("http://")
+    }
+
+    /// An anonymous request to `path`
+    pub fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.client.request(method, format!("{}{}", self.base_url, path))
+    }
+
+    /// A request to `path` carrying the admin token
+    pub fn admin(&self, method: Method, path: &str) -> RequestBuilder {
+        self.request(method, path).bearer_auth(ADMIN_TOKEN)
+    }
+
+    /// Create a user through `path`, returning the response status and body
+    pub async fn create_user(&self, path: &str, username: &str) -> (StatusCode, Value) {
+        let body = json!({ "username": username, "email": format!("{}@example.com", username) });
+        let response = self.request(Method::POST, path).json(&body).send().await.unwrap();
+        let status = response.status();
+        (status, response.json().await.unwrap_or(Value::Null))
+    }
+
+    /// Audit entries recorded for `entity`
+    pub async fn audit(&self, entity: &str) -> Vec<Value> {
+        let path = format!("/api/v1/audit?entity={}", entity);
+        let response = self.admin(Method::GET, &path).send().await.unwrap();
+        let data = expect_data(response, StatusCode::OK).await;
+        data.as_array().cloned().unwrap_or_default()
+    }
+}
+
+/// A user to be, with defaults for what the test leaves out
+#[derive(Debug, Clone)]
+pub struct UserBuilder {
+    user: User,
+}
+
+impl UserBuilder {
+    /// An active user of the default tenant, emailed at `example.com`
+    pub fn new(username: &str) -> Self {
+        let email = format!("{}@example.com", username);
+        UserBuilder {
+            user: User::new(username.to_string(), email),
+        }
+    }
+
+    /// With this email address
+    pub fn email(mut self, email: &str) -> Self {
+        self.user.email = email.to_string();
+        self
+    }
+
+    /// In this tenant instead of the default one
+    pub fn tenant(mut self, tenant: &str) -> Self {
+        self.user.tenant_id = tenant.to_string();
+        self
+    }
+
+    /// With this metadata key set
+    pub fn metadata(mut self, key: &str, value: Value) -> Self {
+        self.user.metadata[key] = value;
+        self
+    }
+
+    /// Deactivated
+    pub fn inactive(mut self) -> Self {
+        self.user.deactivate();
+        self
+    }
+
+    /// Soft-deleted
+    pub fn deleted(mut self) -> Self {
+        self.user.deleted_at = Some(chrono::Utc::now());
+        self
+    }
+
+    /// The user, unsaved
+    pub fn build(self) -> User {
+        self.user
+    }
+
+    /// Save the user in `state`'s repository, unaudited and unpublished
+    pub async fn insert(self, state: &AppState) -> User {
+        state.users.insert(self.user).await.unwrap()
+    }
+}
+
+/// Check a successful response's status and envelope, returning its `data`
+pub async fn expect_data(response: Response, status: StatusCode) -> Value {
+    assert_eq!(response.status(), status, "{}", response.url());
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["success"], true, "{}", body);
+    body["data"].clone()
+}
+
+/// Check a failed response's status and error code, returning its body
+pub async fn expect_error(response: Response, status: StatusCode, code: &str) -> Value {
+    assert_eq!(response.status(), status, "{}", response.url());
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["error_code"], code, "{}", body);
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_built_users_are_served_by_the_test_server() {
+        let server = TestServer::start(Config::default()).await;
+        let alice = UserBuilder::new("alice").metadata("plan", json!("pro"));
+        let alice = alice.insert(&server.state).await;
+        UserBuilder::new("bob").deleted().insert(&server.state).await;
+
+        let response = server.request(Method::GET, "/api/v1/users").send().await.unwrap();
+        let users = expect_data(response, StatusCode::OK).await;
+        assert_eq!(users.as_array().map(Vec::len), Some(1));
+        assert_eq!(users[0]["id"], json!(alice.id));
+        assert_eq!(users[0]["metadata"]["plan"], "pro");
+
+        let path = format!("/api/v1/users/{}", uuid::Uuid::new_v4());
+        let response = server.request(Method::GET, &path).send().await.unwrap();
+        expect_error(response, StatusCode::NOT_FOUND, "USER_NOT_FOUND").await;
+
+        let carol = UserBuilder::new("carol").email("carol@example.org").tenant("acme");
+        let carol = carol.inactive().build();
+        assert_eq!((carol.email.as_str(), carol.tenant_id.as_str()), ("carol@example.org", "acme"));
+        assert!(!carol.is_active && !carol.is_deleted());
+    }
+}
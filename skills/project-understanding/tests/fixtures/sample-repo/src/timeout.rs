@@ -0,0 +1,178 @@
+//! Request timeouts.
+//!
+//! `enforce_timeout` drops a handler that hasn't produced a response within
+//! its route's timeout and answers 504 instead, so a hung query can't hold a
+//! connection forever. Dropping the handler cancels the work it was awaiting;
+//! tasks it spawned keep running. Only the time to the response head counts,
+//! so streams such as SSE and WebSocket upgrades aren't cut off.
+//!
+//! Callers can shorten the timeout for their own request, so we stop working
+//! once they've given up: `X-Request-Deadline` gives an RFC 3339 time, and
+//! `grpc-timeout` a relative one such as `250m` (250 milliseconds), as gRPC
+//! clients send. The earlier of the caller's deadline and the route's
+//! timeout applies, and a deadline already past is answered with 504 at
+//! once. As with route timeouts, the repository calls the handler was
+//! awaiting are dropped with it. The deadline is set in the request's
+//! `RequestContext` as the request arrives, so time spent in outer layers
+//! counts toward it.
+
+use axum::{
+    extract::{FromRequestParts, MatchedPath, State},
+    http::{HeaderMap, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::context::RequestContext;
+use crate::error::AppError;
+use crate::AppState;
+
+/// Request timeout settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeoutConfig {
+    /// Longest a handler may run; 0 disables the timeout
+    pub request_timeout_ms: u64,
+    /// Per-route timeouts keyed by route pattern, e.g. `/api/v1/users/import`
+    pub route_timeout_ms: HashMap<String, u64>,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        TimeoutConfig {
+            request_timeout_ms: 30_000,
+            route_timeout_ms: HashMap::new(),
+        }
+    }
+}
+
+impl TimeoutConfig {
+    /// Timeout for a route, if it has one
+    fn timeout(&self, route: Option<&str>) -> Option<Duration> {
+        let millis = route
+            .and_then(|route| self.route_timeout_ms.get(route))
+            .copied()
+            .unwrap_or(self.request_timeout_ms);
+        (millis > 0).then(|| Duration::from_millis(millis))
+    }
+}
+
+/// Header with the caller's deadline, as an RFC 3339 time
+pub const DEADLINE_HEADER: &str = "x-request-deadline";
+
+/// Header with the caller's timeout, gRPC style, e.g. `5S` or `250m`
+pub const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
+
+/// Parse a `grpc-timeout` value: up to eight digits and a unit
+fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    let (digits, unit) = value.split_at(value.len().checked_sub(1)?);
+    if digits.is_empty() || digits.len() > 8 {
+        return None;
+    }
+    let amount: u64 = digits.parse().ok()?;
+    Some(match unit {
+        "H" => Duration::from_secs(amount * 3600),
+        "M" => Duration::from_secs(amount * 60),
+        "S" => Duration::from_secs(amount),
+        "m" => Duration::from_millis(amount),
+        "u" => Duration::from_micros(amount),
+        "n" => Duration::from_nanos(amount),
+        _ => return None,
+    })
+}
+
+/// Time the caller is still willing to wait, if it said
+fn caller_budget(headers: &HeaderMap, now: chrono::DateTime<chrono::Utc>) -> Option<Duration> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let deadline = header(DEADLINE_HEADER)
+        .and_then(|value| chrono::DateTime::parse_from_rfc3339(value.trim()).ok())
+        .map(|deadline| (deadline.with_timezone(&chrono::Utc) - now).to_std().unwrap_or_default());
+    let timeout = header(GRPC_TIMEOUT_HEADER).and_then(|value| parse_grpc_timeout(value.trim()));
+    match (deadline, timeout) {
+        (Some(deadline), Some(timeout)) => Some(deadline.min(timeout)),
+        (deadline, timeout) => deadline.or(timeout),
+    }
+}
+
+/// How long a request may take: the shorter of its route's timeout and its
+/// caller's budget
+pub fn limit(
+    config: &TimeoutConfig,
+    route: Option<&str>,
+    headers: &HeaderMap,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Option<Duration> {
+    match (config.timeout(route), caller_budget(headers, now)) {
+        (Some(timeout), Some(budget)) => Some(timeout.min(budget)),
+        (timeout, budget) => timeout.or(budget),
+    }
+}
+
+/// Middleware answering 504 for handlers past the request context's deadline
+pub async fn enforce_timeout<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let route = req.extensions().get::<MatchedPath>().map(|path| path.as_str().to_string());
+    let (mut parts, body) = req.into_parts();
+    let context = match RequestContext::from_request_parts(&mut parts, &state).await {
+        Ok(context) => context,
+        Err(never) => match never {},
+    };
+    let req = Request::from_parts(parts, body);
+    let Some(deadline) = context.deadline else {
+        return next.run(req).await;
+    };
+    let limit = deadline - context.started;
+    if limit.is_zero() {
+        tracing::debug!("caller's deadline passed before its request was handled");
+        return AppError::Timeout(limit).into_response();
+    }
+    match tokio::time::timeout_at(deadline.into(), next.run(req)).await {
+        Ok(response) => response,
+        Err(_) => {
+            let route = route.as_deref().unwrap_or("unmatched");
+            tracing::warn!("request to {} timed out after {:?}", route, limit);
+            AppError::Timeout(limit).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route_timeout_overrides_default() {
+        let mut config = TimeoutConfig::default();
+        config.route_timeout_ms.insert("/api/v1/users/import".to_string(), 120_000);
+        config.route_timeout_ms.insert("/api/v1/admin/postman".to_string(), 0);
+        assert_eq!(config.timeout(Some("/api/v1/users/import")), Some(Duration::from_secs(120)));
+        assert_eq!(config.timeout(Some("/api/v1/admin/postman")), None);
+        assert_eq!(config.timeout(None), Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn test_caller_budget_from_either_header() {
+        let now = chrono::Utc::now();
+        let mut headers = HeaderMap::new();
+        assert_eq!(caller_budget(&headers, now), None);
+
+        headers.insert(GRPC_TIMEOUT_HEADER, "250m".parse().unwrap());
+        assert_eq!(caller_budget(&headers, now), Some(Duration::from_millis(250)));
+        let deadline = (now + chrono::Duration::milliseconds(100)).to_rfc3339();
+        headers.insert(DEADLINE_HEADER, deadline.parse().unwrap());
+        assert_eq!(caller_budget(&headers, now), Some(Duration::from_millis(100)));
+
+        let passed = (now - chrono::Duration::seconds(1)).to_rfc3339();
+        headers.insert(DEADLINE_HEADER, passed.parse().unwrap());
+        assert_eq!(caller_budget(&headers, now), Some(Duration::ZERO));
+        assert_eq!(parse_grpc_timeout("123456789S"), None);
+        assert_eq!(parse_grpc_timeout("5x"), None);
+    }
+}
@@ -0,0 +1,219 @@
+//! HTTPS termination with rustls.
+//!
+//! With `Config::tls` set the API listener speaks HTTPS itself, using the
+//! PEM certificate chain and private key at `cert_path` and `key_path`, so no
+//! reverse proxy is needed in front of it. With `redirect_http_port` also
+//! set, a second listener on that port answers every plain-HTTP request with
+//! a permanent redirect to the same path over HTTPS, one per address served
+//! over HTTPS. All stop together on shutdown, after in-flight requests
+//! finish.
+//!
+//! The certificate files are re-read every `RELOAD_CHECK_INTERVAL`, and at
+//! once on SIGHUP. When they changed, the new pair replaces the old one for
+//! connections accepted from then on, so renewals such as Let's Encrypt's
+//! need no restart; established connections keep the certificate they
+//! started with. A pair that fails to load is logged and the previous one
+//! stays in use.
+
+use axum::{
+    extract::{IntoMakeServiceWithConnectInfo, State},
+    http::{header, HeaderMap, StatusCode, Uri},
+    response::{IntoResponse, Redirect, Response},
+    Router,
+};
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::listener;
+
+/// How often the certificate files are checked for changes
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// HTTPS settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// PEM file with the certificate chain, leaf first
+    pub cert_path: PathBuf,
+    /// PEM file with the private key
+    pub key_path: PathBuf,
+    /// Port redirecting plain HTTP to HTTPS; no redirects when unset
+    #[serde(default)]
+    pub redirect_http_port: Option<u16>,
+}
+
+/// Contents of the certificate chain and key files
+async fn read_pair(config: &TlsConfig) -> std::io::Result<(Vec<u8>, Vec<u8>)> {
+    let cert = tokio::fs::read(&config.cert_path).await?;
+    let key = tokio::fs::read(&config.key_path).await?;
+    Ok((cert, key))
+}
+
+/// Swap in new certificate files as they appear, for new connections
+async fn watch_certificates(
+    rustls: RustlsConfig,
+    config: TlsConfig,
+    mut loaded: (Vec<u8>, Vec<u8>),
+) {
+    let mut interval = tokio::time::interval(RELOAD_CHECK_INTERVAL);
+    interval.tick().await;
+    #[cfg(unix)]
+    let mut hangups = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        .map_err(|err| tracing::warn!("no certificate reloads on SIGHUP: {}", err))
+        .ok();
+    loop {
+        #[cfg(unix)]
+        let hangup = async {
+            match hangups.as_mut() {
+                Some(hangups) => {
+                    hangups.recv().await;
+                }
+                None => std::future::pending().await,
+            }
+        };
+        #[cfg(not(unix))]
+        let hangup = std::future::pending::<()>();
+        let asked = tokio::select! {
+            _ = interval.tick() => false,
+            _ = hangup => true,
+        };
+
+        let pair = match read_pair(&config).await {
+            Ok(pair) => pair,
+            Err(err) => {
+                tracing::warn!("failed to read TLS certificate files, keeping current: {}", err);
+                continue;
+            }
+        };
+        if pair == loaded && !asked {
+            continue;
+        }
+        match rustls.reload_from_pem(pair.0.clone(), pair.1.clone()).await {
+            Ok(()) => {
+                tracing::info!("reloaded TLS certificate from {}", config.cert_path.display());
+                loaded = pair;
+            }
+            Err(err) => {
+                tracing::error!("failed to load new TLS certificate, keeping current: {}", err)
+            }
+        }
+    }
+}
+
+/// HTTPS URL for a plain-HTTP request to `host`, served on `https_port`
+fn redirect_target(host: &str, https_port: u16, uri: &Uri) -> Option<String> {
+    let authority: axum::http::uri::Authority = host.parse().ok()?;
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+    Some(match https_port {
+        443 => format!("https://{}{}", authority.host(), path),
+        port => format!("https://{}:{}{}", authority.host(), port, path),
+    })
+}
+
+async fn redirect(State(https_port): State<u16>, headers: HeaderMap, uri: Uri) -> Response {
+    let target = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .and_then(|host| redirect_target(host, https_port, &uri));
+    match target {
+        Some(target) => Redirect::permanent(&target).into_response(),
+        None => (StatusCode::BAD_REQUEST, "missing or invalid Host header").into_response(),
+    }
+}
+
+/// Redirect plain HTTP on `port` to HTTPS on `addrs` until `handle` stops
+///
+/// Each IP gets one redirect listener, pointing at its first HTTPS port.
+pub(crate) fn spawn_redirects(handle: &Handle, addrs: &[SocketAddr], port: u16) {
+    let mut ips = Vec::new();
+    for addr in addrs {
+        if ips.contains(&addr.ip()) {
+            continue;
+        }
+        ips.push(addr.ip());
+        let from = SocketAddr::new(addr.ip(), port);
+        let v6_only = addr.is_ipv6() && addrs.iter().any(SocketAddr::is_ipv4);
+        let listener = listener::bind(from, false, v6_only).and_then(|listener| {
+            listener.set_nonblocking(true)?;
+            Ok(listener)
+        });
+        let listener = match listener {
+            Ok(listener) => listener,
+            Err(err) => {
+                tracing::error!("failed to bind HTTPS redirect listener on {}: {}", from, err);
+                continue;
+            }
+        };
+        let redirects = Router::new().fallback(redirect).with_state(addr.port());
+        let server = axum_server::from_tcp(listener).handle(handle.clone());
+        tracing::info!("redirecting plain HTTP on {} to HTTPS", from);
+        tokio::spawn(async move {
+            if let Err(err) = server.serve(redirects.into_make_service()).await {
+                tracing::error!("HTTPS redirect listener failed: {}", err);
+            }
+        });
+    }
+}
+
+/// Shut down gracefully through `handle` once `shutdown` resolves
+pub(crate) fn stop_on(handle: &Handle, shutdown: impl Future<Output = ()> + Send + 'static) {
+    let handle = handle.clone();
+    tokio::spawn(async move {
+        shutdown.await;
+        handle.graceful_shutdown(None);
+    });
+}
+
+/// Addresses of `listeners`
+pub(crate) fn local_addrs(listeners: &[TcpListener]) -> std::io::Result<Vec<SocketAddr>> {
+    listeners.iter().map(TcpListener::local_addr).collect()
+}
+
+/// Serve `app` over HTTPS on `listeners` until `shutdown` resolves
+pub async fn serve(
+    listeners: Vec<TcpListener>,
+    config: &TlsConfig,
+    app: IntoMakeServiceWithConnectInfo<Router, SocketAddr>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> std::io::Result<()> {
+    let loaded = read_pair(config).await?;
+    let rustls = RustlsConfig::from_pem(loaded.0.clone(), loaded.1.clone()).await?;
+    let addrs = local_addrs(&listeners)?;
+    let handle = Handle::new();
+    let watcher = tokio::spawn(watch_certificates(rustls.clone(), config.clone(), loaded));
+
+    if let Some(port) = config.redirect_http_port {
+        spawn_redirects(&handle, &addrs, port);
+    }
+    stop_on(&handle, shutdown);
+    let servers = listeners.into_iter().map(|listener| {
+        axum_server::from_tcp_rustls(listener, rustls.clone())
+            .handle(handle.clone())
+            .serve(app.clone())
+    });
+    let served = futures::future::try_join_all(servers).await;
+    watcher.abort();
+    served.map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redirect_keeps_path_and_swaps_port() {
+        let uri: Uri = "/api/v1/users?limit=5".parse().unwrap();
+        assert_eq!(
+            redirect_target("api.example.com:8080", 8443, &uri).unwrap(),
+            "https://api.example.com:8443/api/v1/users?limit=5"
+        );
+        assert_eq!(
+            redirect_target("api.example.com", 443, &"/".parse().unwrap()).unwrap(),
+            "https://api.example.com/"
+        );
+        assert_eq!(redirect_target("not a host", 443, &uri), None);
+    }
+}
@@ -0,0 +1,129 @@
+//! Units of work committing several writes as one.
+//!
+//! A mutation stages its writes on a `UnitOfWork`: users inserted and
+//! updated, the outbox events describing them, and the audit entries saying
+//! who made them. `commit` hands the user and outbox writes to
+//! `UserRepository::commit`, which applies all of them in one transaction or
+//! none; dropping the unit instead is a rollback, as nothing was written yet.
+//! The audit log is stored apart from users, so its entries are appended once
+//! the transaction has committed: a unit that fails records none of them.
+
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::audit::{self, AuditAction, AuditContext, AuditEntry};
+use crate::events::DomainEvent;
+use crate::repository::Change;
+use crate::service::ServiceError;
+use crate::{AppState, User};
+
+/// Writes staged to commit together
+pub struct UnitOfWork<'a> {
+    state: &'a AppState,
+    changes: Vec<Change>,
+    audit: Vec<AuditEntry>,
+}
+
+impl<'a> UnitOfWork<'a> {
+    /// Start staging writes against `state`
+    pub fn begin(state: &'a AppState) -> Self {
+        UnitOfWork {
+            state,
+            changes: Vec::new(),
+            audit: Vec::new(),
+        }
+    }
+
+    /// Insert a new user
+    pub fn insert(&mut self, user: &User) {
+        self.changes.push(Change::Insert(user.clone()));
+    }
+
+    /// Replace an existing user
+    pub fn update(&mut self, user: &User) {
+        self.changes.push(Change::Update(user.clone()));
+    }
+
+    /// Write an event to the outbox
+    pub fn enqueue(&mut self, event: DomainEvent) {
+        self.changes.push(Change::Enqueue(event));
+    }
+
+    /// Record a mutation in the audit log once the unit commits
+    pub fn audit<T: Serialize>(
+        &mut self,
+        context: &AuditContext,
+        entity: &str,
+        entity_id: Uuid,
+        action: AuditAction,
+        before: Option<&T>,
+        after: Option<&T>,
+    ) {
+        let entry = audit::entry(context, entity, entity_id, action, before, after);
+        self.audit.push(entry);
+    }
+
+    /// Apply every staged write, or none; `NotFound` if a user to update is gone
+    pub async fn commit(self) -> Result<(), ServiceError> {
+        if !self.state.users.commit(&self.changes).await? {
+            return Err(ServiceError::NotFound);
+        }
+        for entry in self.audit {
+            audit::append(self.state, entry).await;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::{AuditQuery, AuditStore};
+    use crate::events::EventKind;
+    use crate::tenancy::DEFAULT_TENANT;
+    use crate::Config;
+
+    fn context() -> AuditContext {
+        AuditContext {
+            actor: "admin".to_string(),
+            request_id: "test".to_string(),
+            client_ip: None,
+        }
+    }
+
+    async fn audited(state: &AppState) -> usize {
+        let query = AuditQuery { entity: "user".to_string(), id: None };
+        state.audit.query(&query).await.unwrap().len()
+    }
+
+    #[tokio::test]
+    async fn test_failed_or_dropped_unit_writes_and_records_nothing() {
+        let state = AppState::new(Config::default());
+        let alice = User::new("alice".to_string(), "alice@example.com".to_string());
+
+        let mut work = UnitOfWork::begin(&state);
+        work.insert(&alice);
+        work.enqueue(DomainEvent::user(EventKind::UserCreated, &alice));
+        drop(work);
+        assert!(state.users.get(DEFAULT_TENANT, alice.id).await.unwrap().is_none());
+
+        // Updating a user that was never inserted fails the whole unit
+        let mut work = UnitOfWork::begin(&state);
+        work.insert(&alice);
+        work.update(&User::new("bob".to_string(), "bob@example.com".to_string()));
+        work.audit(&context(), "user", alice.id, AuditAction::Create, None, Some(&alice));
+        assert!(matches!(work.commit().await, Err(ServiceError::NotFound)));
+        assert!(state.users.get(DEFAULT_TENANT, alice.id).await.unwrap().is_none());
+        assert!(state.users.pending_events(10).await.unwrap().is_empty());
+        assert_eq!(audited(&state).await, 0);
+
+        let mut work = UnitOfWork::begin(&state);
+        work.insert(&alice);
+        work.enqueue(DomainEvent::user(EventKind::UserCreated, &alice));
+        work.audit(&context(), "user", alice.id, AuditAction::Create, None, Some(&alice));
+        work.commit().await.unwrap();
+        assert!(state.users.get(DEFAULT_TENANT, alice.id).await.unwrap().is_some());
+        assert_eq!(state.users.pending_events(10).await.unwrap().len(), 1);
+        assert_eq!(audited(&state).await, 1);
+    }
+}
@@ -0,0 +1,146 @@
+//! Request extractors that reject with `AppError`.
+//!
+//! `ValidatedJson<T>` deserializes like `Json<T>` and then runs the DTO's
+//! `Validate` rules, rejecting with a 422 that lists every failing field.
+//! Unknown fields are a deserialization error for DTOs that deny them,
+//! which is how client-supplied IDs and timestamps are refused.
+//!
+//! `ApiPath<T>` is `Path<T>` with a JSON 400 explaining a malformed
+//! segment, such as a user ID that is not a UUID.
+
+use async_trait::async_trait;
+use axum::{
+    body::HttpBody,
+    extract::{
+        rejection::{JsonRejection, PathRejection},
+        FromRequest, FromRequestParts, Path,
+    },
+    http::{request::Parts, Request},
+    BoxError, Json,
+};
+use serde::de::DeserializeOwned;
+
+use crate::error::{AppError, ErrorReport};
+
+/// Field rules for a request body
+pub trait Validate {
+    /// Every problem found, each naming its field
+    fn validate(&self) -> Vec<String>;
+}
+
+/// JSON body that has passed `Validate`
+#[derive(Debug)]
+pub struct ValidatedJson<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state)
+            .await
+            .map_err(|rejection| match rejection {
+                JsonRejection::JsonDataError(err) => AppError::Validation(vec![err.body_text()]),
+                other => AppError::BadRequest(other.body_text()),
+            })?;
+        let errors = value.validate();
+        if !errors.is_empty() {
+            return Err(AppError::Validation(errors));
+        }
+        Ok(ValidatedJson(value))
+    }
+}
+
+/// Path parameters, rejected as JSON when they don't parse
+#[derive(Debug)]
+pub struct ApiPath<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ApiPath<T>
+where
+    T: DeserializeOwned + Send,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        match Path::<T>::from_request_parts(parts, state).await {
+            Ok(Path(value)) => Ok(ApiPath(value)),
+            Err(PathRejection::FailedToDeserializePathParams(err)) => {
+                Err(AppError::BadRequest(err.body_text()))
+            }
+            // Only reachable if a handler is mounted on a route without the parameter
+            Err(other) => Err(AppError::Internal(ErrorReport::new(other.body_text()))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct Name {
+        name: String,
+    }
+
+    impl Validate for Name {
+        fn validate(&self) -> Vec<String> {
+            match self.name.is_empty() {
+                true => vec!["name is required".to_string()],
+                false => Vec::new(),
+            }
+        }
+    }
+
+    async fn extract(body: &str) -> Result<ValidatedJson<Name>, AppError> {
+        let req = Request::builder()
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        ValidatedJson::<Name>::from_request(req, &()).await
+    }
+
+    #[tokio::test]
+    async fn test_rejects_invalid_and_unknown_fields() {
+        assert_eq!(extract(r#"{"name":"ok"}"#).await.unwrap().0.name, "ok");
+        assert!(matches!(
+            extract(r#"{"name":""}"#).await,
+            Err(AppError::Validation(errors)) if errors == vec!["name is required"]
+        ));
+        assert!(matches!(
+            extract(r#"{"name":"ok","id":1}"#).await,
+            Err(AppError::Validation(_))
+        ));
+        assert!(matches!(extract("{").await, Err(AppError::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn test_malformed_path_id_is_a_json_400() {
+        use axum::{http::StatusCode, routing::get, Router};
+        use tower::ServiceExt;
+
+        let app = Router::new().route(
+            "/users/:id",
+            get(|ApiPath(id): ApiPath<uuid::Uuid>| async move { id.to_string() }),
+        );
+        let req = Request::builder().uri("/users/nope").body(Body::empty()).unwrap();
+        let response = app.oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["success"], false);
+        assert!(body["error"].as_str().is_some_and(|error| !error.is_empty()));
+    }
+}
@@ -0,0 +1,79 @@
+//! What build is running.
+//!
+//! `build.rs` embeds the git commit, the rustc version, and the build time
+//! as compile-time environment variables, and `GET /version` reports them
+//! with the crate version and how long the process has been up, so operators
+//! can tell exactly what is deployed where. A build outside a git checkout
+//! reports its commit as `unknown`.
+
+use axum::{extract::State, response::Json};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Serialize;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+use crate::{ApiResponse, AppState};
+
+/// Crate version from `Cargo.toml`
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Commit the binary was built from
+pub const GIT_SHA: &str = env!("BUILD_GIT_SHA");
+
+/// `rustc --version` of the compiler that built the binary
+pub const RUSTC_VERSION: &str = env!("BUILD_RUSTC_VERSION");
+
+/// Unix seconds when the binary was built
+const BUILD_TIMESTAMP: &str = env!("BUILD_TIMESTAMP");
+
+/// The running build and process
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct VersionInfo {
+    /// Crate version
+    #[schema(example = "0.1.0")]
+    pub version: &'static str,
+    /// Git commit SHA, or `unknown`
+    pub git_sha: &'static str,
+    /// When the binary was built
+    pub built_at: Option<DateTime<Utc>>,
+    /// Compiler that built it
+    #[schema(example = "rustc 1.74.0 (79e9716c9 2023-11-13)")]
+    pub rustc_version: &'static str,
+    /// Seconds since the process started
+    pub uptime_secs: u64,
+}
+
+/// When the binary was built, if the build script recorded it
+fn built_at() -> Option<DateTime<Utc>> {
+    let seconds = BUILD_TIMESTAMP.parse().ok()?;
+    Utc.timestamp_opt(seconds, 0).single()
+}
+
+/// Build and uptime of this instance
+#[utoipa::path(
+    get,
+    path = "/version",
+    tag = "health",
+    responses((status = 200, description = "The running build", body = VersionResponse))
+)]
+pub async fn version(State(state): State<Arc<AppState>>) -> Json<ApiResponse<VersionInfo>> {
+    Json(ApiResponse::success(VersionInfo {
+        version: VERSION,
+        git_sha: GIT_SHA,
+        built_at: built_at(),
+        rustc_version: RUSTC_VERSION,
+        uptime_secs: state.started.elapsed().as_secs(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_info_is_embedded() {
+        assert!(!GIT_SHA.is_empty());
+        assert!(RUSTC_VERSION.starts_with("rustc") || RUSTC_VERSION == "unknown");
+        assert!(built_at().is_some_and(|at| at <= Utc::now()));
+    }
+}
@@ -0,0 +1,83 @@
+//! API version prefixes and deprecation signaling.
+//!
+//! The REST API lives under `/api/v1`. The unversioned `/api/*` paths are
+//! kept as aliases of v1 for existing clients; responses on them carry a
+//! `Deprecation` header, a `Link` to the successor path, and a `Sunset`
+//! date when `Config::legacy_api_sunset` is set.
+
+use axum::{
+    extract::State,
+    http::{HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+
+use crate::AppState;
+
+/// Prefix of the unversioned aliases
+pub const LEGACY_PREFIX: &str = "/api";
+
+/// Prefix of the current API version
+pub const V1_PREFIX: &str = "/api/v1";
+
+/// Format a timestamp as an HTTP-date
+fn http_date(at: DateTime<Utc>) -> String {
+    at.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Path of the v1 endpoint a legacy path is an alias of
+fn successor(path: &str) -> String {
+    let rest = path.strip_prefix(LEGACY_PREFIX).unwrap_or(path);
+    format!("{}{}", V1_PREFIX, rest)
+}
+
+/// The v1 route pattern a matched route is, or is the legacy alias of
+pub fn v1_route(route: &str) -> String {
+    if route.starts_with(V1_PREFIX) || !route.starts_with(LEGACY_PREFIX) {
+        route.to_string()
+    } else {
+        successor(route)
+    }
+}
+
+/// Middleware marking responses from the legacy aliases as deprecated
+pub async fn deprecated<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let successor = successor(req.uri().path());
+    let mut response = next.run(req).await;
+    mark_deprecated(&mut response, &successor, state.config().legacy_api_sunset);
+    response
+}
+
+/// Mark `response` as served by a path replaced by `successor`
+pub fn mark_deprecated(response: &mut Response, successor: &str, sunset: Option<DateTime<Utc>>) {
+    let link = format!("<{}>; rel=\"successor-version\"", successor);
+    let headers = response.headers_mut();
+    headers.insert("deprecation", HeaderValue::from_static("true"));
+    if let Ok(link) = HeaderValue::from_str(&link) {
+        headers.append("link", link);
+    }
+    if let Some(sunset) = sunset {
+        if let Ok(sunset) = HeaderValue::from_str(&http_date(sunset)) {
+            headers.insert("sunset", sunset);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_successor_and_sunset_formatting() {
+        assert_eq!(successor("/api/users/42"), "/api/v1/users/42");
+        let at = chrono::Utc.with_ymd_and_hms(2027, 6, 30, 0, 0, 0).unwrap();
+        assert_eq!(http_date(at), "Wed, 30 Jun 2027 00:00:00 GMT");
+    }
+}
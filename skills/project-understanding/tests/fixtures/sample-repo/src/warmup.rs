@@ -0,0 +1,234 @@
+//! Cache warmup on boot.
+//!
+//! Runs as a lifecycle component before the server starts listening, so
+//! the first requests after a deploy hit warm caches. Sources share one time
+//! budget; whatever hasn't finished when it runs out is reported as partial
+//! rather than failing startup. Readiness stays false until warmup has run.
+
+use async_trait::async_trait;
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::lifecycle::Component;
+use crate::repository::{self, UserQuery};
+use crate::AppState;
+
+/// Something that can preload a cache
+#[async_trait]
+pub trait WarmupSource: Send + Sync {
+    /// Source name shown in reports
+    fn name(&self) -> &'static str;
+
+    /// Load entries, returning how many were cached
+    async fn warm(&self, state: &AppState) -> Result<usize, String>;
+}
+
+/// Preloads the most recently created users into the user cache
+pub struct RecentUsers {
+    limit: usize,
+}
+
+impl RecentUsers {
+    /// Preload up to `limit` users
+    pub fn new(limit: usize) -> Self {
+        Self { limit }
+    }
+}
+
+#[async_trait]
+impl WarmupSource for RecentUsers {
+    fn name(&self) -> &'static str {
+        "recent_users"
+    }
+
+    async fn warm(&self, state: &AppState) -> Result<usize, String> {
+        let users = repository::list_every_tenant(&*state.users, &UserQuery::default())
+            .await
+            .map_err(|err| err.to_string())?;
+        let recent: Vec<_> = users.into_iter().rev().take(self.limit).collect();
+        let loaded = recent.len();
+        for user in recent {
+            state.user_cache.put(user);
+        }
+        Ok(loaded)
+    }
+}
+
+/// How a source's warmup ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceOutcome {
+    /// Finished loading
+    Complete,
+    /// Returned an error
+    Failed,
+    /// Was cut off by the budget
+    TimedOut,
+    /// Never started because the budget was already spent
+    Skipped,
+}
+
+/// Warmup result for one source
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceReport {
+    /// Source name
+    pub name: &'static str,
+    /// How it ended
+    pub outcome: SourceOutcome,
+    /// Entries loaded; zero unless complete
+    pub loaded: usize,
+    /// Error detail for failed sources
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Result of the whole warmup phase
+#[derive(Debug, Clone, Serialize)]
+pub struct WarmupReport {
+    /// Whether every source completed
+    pub complete: bool,
+    /// Time spent warming
+    pub elapsed_ms: u64,
+    /// Per-source results
+    pub sources: Vec<SourceReport>,
+}
+
+/// Latest warmup report, `None` until warmup has run
+#[derive(Default)]
+pub struct WarmupStatus {
+    report: RwLock<Option<WarmupReport>>,
+}
+
+impl WarmupStatus {
+    /// The report, if warmup has run
+    pub fn report(&self) -> Option<WarmupReport> {
+        self.report.read().expect("warmup lock poisoned").clone()
+    }
+
+    fn set(&self, report: WarmupReport) {
+        *self.report.write().expect("warmup lock poisoned") = Some(report);
+    }
+}
+
+/// Lifecycle component running every source within a shared budget
+pub struct Warmup {
+    sources: Vec<Box<dyn WarmupSource>>,
+    budget: Duration,
+}
+
+impl Warmup {
+    /// Create a warmup phase allowed `budget` in total
+    pub fn new(budget: Duration) -> Self {
+        Self {
+            sources: Vec::new(),
+            budget,
+        }
+    }
+
+    /// Add a source; sources run in the order added
+    pub fn with_source(mut self, source: impl WarmupSource + 'static) -> Self {
+        self.sources.push(Box::new(source));
+        self
+    }
+
+    /// Warm every source and return the report
+    pub async fn run(&self, state: &AppState) -> WarmupReport {
+        let started = Instant::now();
+        let deadline = started + self.budget;
+        let mut sources = Vec::with_capacity(self.sources.len());
+        for source in &self.sources {
+            let (outcome, loaded, error) = if Instant::now() >= deadline {
+                (SourceOutcome::Skipped, 0, None)
+            } else {
+                match tokio::time::timeout_at(deadline, source.warm(state)).await {
+                    Ok(Ok(loaded)) => (SourceOutcome::Complete, loaded, None),
+                    Ok(Err(err)) => (SourceOutcome::Failed, 0, Some(err)),
+                    Err(_) => (SourceOutcome::TimedOut, 0, None),
+                }
+            };
+            sources.push(SourceReport {
+                name: source.name(),
+                outcome,
+                loaded,
+                error,
+            });
+        }
+        WarmupReport {
+            complete: sources.iter().all(|source| source.outcome == SourceOutcome::Complete),
+            elapsed_ms: started.elapsed().as_millis() as u64,
+            sources,
+        }
+    }
+}
+
+#[async_trait]
+impl Component for Warmup {
+    fn name(&self) -> &'static str {
+        "cache_warmup"
+    }
+
+    fn timeout(&self) -> Duration {
+        // `run` enforces the budget itself; this only guards against a bug there
+        self.budget + Duration::from_secs(5)
+    }
+
+    async fn start(&self, state: &Arc<AppState>) -> Result<(), String> {
+        let report = self.run(state).await;
+        if report.complete {
+            tracing::info!("cache warmup finished in {}ms", report.elapsed_ms);
+        } else {
+            tracing::warn!(
+                "cache warmup partial after {}ms: {:?}",
+                report.elapsed_ms,
+                report.sources
+            );
+        }
+        state.warmup.set(report);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, User};
+
+    struct SlowSource;
+
+    #[async_trait]
+    impl WarmupSource for SlowSource {
+        fn name(&self) -> &'static str {
+            "slow"
+        }
+
+        async fn warm(&self, _state: &AppState) -> Result<usize, String> {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok(0)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_partial_warmup_is_reported() {
+        let state = AppState::new(Config::default());
+        for name in ["alice", "bob"] {
+            let user = User::new(name.to_string(), format!("{}@example.com", name));
+            state.users.insert(user).await.unwrap();
+        }
+        let warmup = Warmup::new(Duration::from_millis(20))
+            .with_source(RecentUsers::new(1))
+            .with_source(SlowSource)
+            .with_source(RecentUsers::new(10));
+
+        warmup.start(&state).await.unwrap();
+        let report = state.warmup.report().unwrap();
+        assert!(!report.complete);
+        let outcomes: Vec<_> = report.sources.iter().map(|source| source.outcome).collect();
+        assert_eq!(
+            outcomes,
+            vec![SourceOutcome::Complete, SourceOutcome::TimedOut, SourceOutcome::Skipped]
+        );
+        assert_eq!(state.user_cache.len(), 1);
+    }
+}
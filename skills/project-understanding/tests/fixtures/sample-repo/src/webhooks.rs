@@ -0,0 +1,539 @@
+//! Outbound webhooks for user lifecycle events.
+//!
+//! Admins register endpoints with a shared secret. An endpoint belongs to
+//! the tenant it was registered for, is listed and removed only through that
+//! tenant, and is sent only events about that tenant's users. The dispatcher
+//! listens on the event bus and POSTs each matching event, signed with
+//! HMAC-SHA256, retrying failed deliveries with exponential backoff.
+//! Deliveries that exhaust their retries go to the disk spool when one is
+//! configured and are attempted again every `Config::spool.retry_interval_ms`.
+//! With circuit breakers enabled, attempts at a URL whose breaker is open
+//! fail at once, without a request.
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::Json,
+};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast::error::RecvError, RwLock};
+use uuid::Uuid;
+
+use crate::auth::Admin;
+use crate::breaker::{Breakers, CircuitBreaker};
+use crate::validation::ApiPath;
+use crate::events::{DomainEvent, EventKind};
+use crate::spool::Spool;
+use crate::tenancy::Tenant;
+use crate::{ApiResponse, AppState};
+
+/// Header carrying the delivery signature
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+
+/// Maximum number of delivery attempts kept in the log
+const DELIVERY_LOG_CAPACITY: usize = 1000;
+
+/// A registered webhook endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookEndpoint {
+    /// Unique identifier
+    pub id: Uuid,
+    /// Tenant whose users' events are delivered
+    pub tenant: String,
+    /// Target URL
+    pub url: String,
+    /// Shared signing secret, never returned to clients
+    #[serde(skip_serializing)]
+    pub secret: String,
+    /// Event kinds delivered to this endpoint; all kinds when empty
+    pub events: Vec<EventKind>,
+    /// Registration timestamp
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl WebhookEndpoint {
+    /// Whether this endpoint wants the given event: its kind, about its tenant
+    pub fn wants(&self, event: &DomainEvent) -> bool {
+        event.user.tenant_id == self.tenant
+            && (self.events.is_empty() || self.events.contains(&event.kind))
+    }
+}
+
+/// Request body for registering a webhook
+#[derive(Debug, Deserialize)]
+pub struct NewWebhook {
+    /// Target URL
+    pub url: String,
+    /// Shared signing secret
+    pub secret: String,
+    /// Event kinds to deliver
+    #[serde(default)]
+    pub events: Vec<EventKind>,
+}
+
+/// One delivery attempt
+#[derive(Debug, Clone, Serialize)]
+pub struct DeliveryRecord {
+    /// Endpoint the event was sent to
+    pub webhook_id: Uuid,
+    /// Event delivered
+    pub event_id: Uuid,
+    /// Kind of event
+    pub event: EventKind,
+    /// 1-based attempt number
+    pub attempt: u32,
+    /// HTTP status returned, if a response was received
+    pub status: Option<u16>,
+    /// Transport error, if no response was received
+    pub error: Option<String>,
+    /// Whether this attempt succeeded
+    pub success: bool,
+    /// When the attempt finished
+    pub at: chrono::DateTime<chrono::Utc>,
+}
+
+/// A delivery that ran out of retries, kept in the spool
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SpooledWebhook {
+    /// Endpoint the event was meant for
+    pub webhook_id: Uuid,
+    /// Target URL, kept so the delivery survives a restart
+    pub url: String,
+    /// Signing secret of the endpoint
+    pub secret: String,
+    /// Attempts made so far
+    pub attempts: u32,
+    /// Event to deliver
+    pub event: DomainEvent,
+}
+
+/// Spool of undeliverable webhooks
+pub type WebhookSpool = Spool<SpooledWebhook>;
+
+/// Retry schedule for failed deliveries
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total attempts including the first
+    pub max_attempts: u32,
+    /// Delay before the first retry
+    pub base_delay: Duration,
+    /// Upper bound on any single delay
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 6,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(300),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay after the given failed attempt (1-based), doubling each time
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+}
+
+/// Registered endpoints and the delivery log
+pub struct WebhookRegistry {
+    endpoints: RwLock<Vec<WebhookEndpoint>>,
+    deliveries: RwLock<VecDeque<DeliveryRecord>>,
+    client: reqwest::Client,
+    retry: RetryPolicy,
+    sandboxed: bool,
+    breakers: Option<Arc<Breakers>>,
+}
+
+impl WebhookRegistry {
+    /// Create an empty registry
+    pub fn new(retry: RetryPolicy) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("failed to build webhook HTTP client");
+        Self {
+            endpoints: RwLock::new(Vec::new()),
+            deliveries: RwLock::new(VecDeque::new()),
+            client,
+            retry,
+            sandboxed: false,
+            breakers: None,
+        }
+    }
+
+    /// Log deliveries instead of sending them when `sandboxed` is set
+    pub fn sandboxed(mut self, sandboxed: bool) -> Self {
+        self.sandboxed = sandboxed;
+        self
+    }
+
+    /// Give each target URL a breaker from `breakers`, failing attempts at once
+    /// while it is open
+    pub fn with_breakers(mut self, breakers: Arc<Breakers>) -> Self {
+        self.breakers = Some(breakers);
+        self
+    }
+
+    /// Register an endpoint for `tenant`
+    pub async fn register(&self, tenant: &str, new: NewWebhook) -> WebhookEndpoint {
+        let endpoint = WebhookEndpoint {
+            id: Uuid::new_v4(),
+            tenant: tenant.to_string(),
+            url: new.url,
+            secret: new.secret,
+            events: new.events,
+            created_at: chrono::Utc::now(),
+        };
+        self.endpoints.write().await.push(endpoint.clone());
+        endpoint
+    }
+
+    /// Remove a tenant's endpoint, returning whether it existed
+    pub async fn unregister(&self, tenant: &str, id: Uuid) -> bool {
+        let mut endpoints = self.endpoints.write().await;
+        let before = endpoints.len();
+        endpoints.retain(|endpoint| endpoint.id != id || endpoint.tenant != tenant);
+        endpoints.len() != before
+    }
+
+    /// All registered endpoints
+    pub async fn endpoints(&self) -> Vec<WebhookEndpoint> {
+        self.endpoints.read().await.clone()
+    }
+
+    /// Delivery attempts for an endpoint, newest first
+    pub async fn deliveries(&self, webhook_id: Uuid) -> Vec<DeliveryRecord> {
+        let deliveries = self.deliveries.read().await;
+        deliveries
+            .iter()
+            .rev()
+            .filter(|record| record.webhook_id == webhook_id)
+            .cloned()
+            .collect()
+    }
+
+    async fn log(&self, record: DeliveryRecord) {
+        let mut deliveries = self.deliveries.write().await;
+        if deliveries.len() == DELIVERY_LOG_CAPACITY {
+            deliveries.pop_front();
+        }
+        deliveries.push_back(record);
+    }
+
+    /// Deliver an event to one endpoint, retrying until success or exhaustion;
+    /// returns whether it was delivered
+    pub async fn deliver(&self, endpoint: &WebhookEndpoint, event: &DomainEvent) -> bool {
+        let body = match serde_json::to_vec(event) {
+            Ok(body) => body,
+            Err(err) => {
+                tracing::error!("failed to serialize event {}: {}", event.id, err);
+                return false;
+            }
+        };
+        if self.sandboxed {
+            tracing::info!(
+                "sandbox: would deliver event {} to {} ({} bytes)",
+                event.id,
+                endpoint.url,
+                body.len()
+            );
+            self.log(DeliveryRecord {
+                webhook_id: endpoint.id,
+                event_id: event.id,
+                event: event.kind,
+                attempt: 1,
+                status: None,
+                error: Some("sandboxed; not sent".to_string()),
+                success: true,
+                at: chrono::Utc::now(),
+            })
+            .await;
+            return true;
+        }
+
+        for attempt in 1..=self.retry.max_attempts {
+            let (id, url, secret) = (endpoint.id, &endpoint.url, &endpoint.secret);
+            if self.attempt(id, url, secret, event, &body, attempt).await {
+                return true;
+            }
+            if attempt < self.retry.max_attempts {
+                tokio::time::sleep(self.retry.backoff(attempt)).await;
+            }
+        }
+        tracing::warn!(
+            "giving up delivering event {} to webhook {}",
+            event.id,
+            endpoint.id
+        );
+        false
+    }
+
+    /// Send one attempt and log it, returning whether it succeeded
+    async fn attempt(
+        &self,
+        webhook_id: Uuid,
+        url: &str,
+        secret: &str,
+        event: &DomainEvent,
+        body: &[u8],
+        attempt: u32,
+    ) -> bool {
+        let timestamp = chrono::Utc::now().timestamp();
+        let signature = sign(secret, timestamp, body);
+        let breaker = self.breakers.as_ref().and_then(|breakers| {
+            breakers.get(&format!("webhook:{}", url))
+        });
+        let (status, error) = match breaker.as_deref().map(CircuitBreaker::admit).transpose() {
+            Err(open) => (None, Some(open.to_string())),
+            Ok(admitted) => {
+                let result = self
+                    .client
+                    .post(url)
+                    .header("content-type", "application/json")
+                    .header(SIGNATURE_HEADER, format!("t={},v1={}", timestamp, signature))
+                    .body(body.to_vec())
+                    .send()
+                    .await;
+                let (status, error) = match result {
+                    Ok(response) => (Some(response.status().as_u16()), None),
+                    Err(err) => (None, Some(err.to_string())),
+                };
+                // A target answering 4xx is up, only refusing this event
+                if let Some(admitted) = admitted {
+                    admitted.finish(error.is_some() || status.is_some_and(|status| status >= 500));
+                }
+                (status, error)
+            }
+        };
+        let success = status.map_or(false, |status| (200..300).contains(&status));
+        self.log(DeliveryRecord {
+            webhook_id,
+            event_id: event.id,
+            event: event.kind,
+            attempt,
+            status,
+            error,
+            success,
+            at: chrono::Utc::now(),
+        })
+        .await;
+        success
+    }
+
+    /// Try a spooled delivery once more, handing it back if it fails again
+    async fn redeliver(&self, mut spooled: SpooledWebhook) -> Result<(), SpooledWebhook> {
+        let Ok(body) = serde_json::to_vec(&spooled.event) else {
+            return Ok(());
+        };
+        spooled.attempts += 1;
+        let (id, url, secret) = (spooled.webhook_id, &spooled.url, &spooled.secret);
+        if self.attempt(id, url, secret, &spooled.event, &body, spooled.attempts).await {
+            Ok(())
+        } else {
+            Err(spooled)
+        }
+    }
+}
+
+impl Default for WebhookRegistry {
+    fn default() -> Self {
+        Self::new(RetryPolicy::default())
+    }
+}
+
+/// Hex HMAC-SHA256 of `"{timestamp}.{body}"`
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Spawn the task delivering bus events to registered endpoints
+pub fn spawn_dispatcher(state: Arc<AppState>) -> tokio::task::JoinHandle<()> {
+    let mut events = state.events.subscribe();
+    tokio::spawn(async move {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("webhook dispatcher lagged, skipped {} events", skipped);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            for endpoint in state.webhooks.endpoints().await {
+                if !endpoint.wants(&event) {
+                    continue;
+                }
+                state.tasks.spawn("webhook_delivery", {
+                    let (state, event) = (state.clone(), event.clone());
+                    async move {
+                        if !state.webhooks.deliver(&endpoint, &event).await {
+                            spool(&state, endpoint, event).await;
+                        }
+                    }
+                });
+            }
+        }
+    })
+}
+
+/// Keep a delivery that ran out of retries for the spool drainer
+async fn spool(state: &AppState, endpoint: WebhookEndpoint, event: DomainEvent) {
+    let Some(spool) = &state.webhook_spool else {
+        return;
+    };
+    let (webhook_id, event_id) = (endpoint.id, event.id);
+    let spooled = SpooledWebhook {
+        webhook_id,
+        url: endpoint.url,
+        secret: endpoint.secret,
+        attempts: state.webhooks.retry.max_attempts,
+        event,
+    };
+    match spool.append(&spooled).await {
+        Ok(()) => state.degradations.degrade("webhooks", "spool_to_disk", "retries exhausted"),
+        Err(err) => tracing::error!("lost event {} for webhook {}: {}", event_id, webhook_id, err),
+    }
+}
+
+/// Spawn the task retrying spooled deliveries, when spooling is enabled
+pub fn spawn_spool_drainer(state: Arc<AppState>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let Some(spool) = &state.webhook_spool else {
+            return;
+        };
+        let mut ticks = tokio::time::interval(Duration::from_millis(
+            state.config().spool.retry_interval_ms,
+        ));
+        loop {
+            ticks.tick().await;
+            let result = spool
+                .retry(|spooled| {
+                    let state = state.clone();
+                    async move { state.webhooks.redeliver(spooled).await }
+                })
+                .await;
+            match result {
+                Ok(retried) if retried.kept == 0 => {
+                    if retried.delivered > 0 {
+                        tracing::info!("delivered {} spooled webhooks", retried.delivered);
+                    }
+                    state.degradations.recover("webhooks");
+                }
+                Ok(_) => {}
+                Err(err) => tracing::warn!("failed to retry spooled webhooks: {}", err),
+            }
+        }
+    })
+}
+
+/// Register a webhook endpoint
+pub async fn create_webhook(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    Tenant(tenant): Tenant,
+    Json(new): Json<NewWebhook>,
+) -> Result<(StatusCode, Json<ApiResponse<WebhookEndpoint>>), StatusCode> {
+    let url = reqwest::Url::parse(&new.url).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if !matches!(url.scheme(), "http" | "https") || new.secret.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let endpoint = state.webhooks.register(&tenant, new).await;
+    Ok((StatusCode::CREATED, Json(ApiResponse::success(endpoint))))
+}
+
+/// List the tenant's webhook endpoints
+pub async fn list_webhooks(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    Tenant(tenant): Tenant,
+) -> Json<ApiResponse<Vec<WebhookEndpoint>>> {
+    let mut endpoints = state.webhooks.endpoints().await;
+    endpoints.retain(|endpoint| endpoint.tenant == tenant);
+    Json(ApiResponse::success(endpoints))
+}
+
+/// Remove a webhook endpoint
+pub async fn delete_webhook(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    Tenant(tenant): Tenant,
+    ApiPath(id): ApiPath<Uuid>,
+) -> StatusCode {
+    if state.webhooks.unregister(&tenant, id).await || state.config().idempotent_deletes {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+/// Delivery log for a webhook endpoint
+pub async fn list_deliveries(
+    State(state): State<Arc<AppState>>,
+    _admin: Admin,
+    ApiPath(id): ApiPath<Uuid>,
+) -> Json<ApiResponse<Vec<DeliveryRecord>>> {
+    Json(ApiResponse::success(state.webhooks.deliveries(id).await))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_up_to_cap() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(5),
+        };
+        assert_eq!(policy.backoff(1), Duration::from_secs(1));
+        assert_eq!(policy.backoff(2), Duration::from_secs(2));
+        assert_eq!(policy.backoff(3), Duration::from_secs(4));
+        assert_eq!(policy.backoff(4), Duration::from_secs(5));
+        assert_eq!(policy.backoff(40), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_signature_depends_on_timestamp_and_body() {
+        let signature = sign("secret", 1700000000, b"{}");
+        assert_eq!(signature.len(), 64);
+        assert_eq!(signature, sign("secret", 1700000000, b"{}"));
+        assert_ne!(signature, sign("secret", 1700000001, b"{}"));
+        assert_ne!(signature, sign("other", 1700000000, b"{}"));
+    }
+
+    #[tokio::test]
+    async fn test_endpoints_only_want_their_tenants_events() {
+        let registry = WebhookRegistry::default();
+        let new = NewWebhook {
+            url: "http://hooks.example.com".to_string(),
+            secret: "shh".to_string(),
+            events: vec![EventKind::UserCreated],
+        };
+        let endpoint = registry.register("acme", new).await;
+        let mut user = crate::User::new("alice".to_string(), "alice@example.com".to_string());
+        user.tenant_id = "acme".to_string();
+        assert!(endpoint.wants(&DomainEvent::user(EventKind::UserCreated, &user)));
+        assert!(!endpoint.wants(&DomainEvent::user(EventKind::UserDeleted, &user)));
+        user.tenant_id = "globex".to_string();
+        assert!(!endpoint.wants(&DomainEvent::user(EventKind::UserCreated, &user)));
+
+        assert!(!registry.unregister("globex", endpoint.id).await);
+        assert!(registry.unregister("acme", endpoint.id).await);
+    }
+}
@@ -0,0 +1,250 @@
+//! Write-behind coalescing of high-frequency updates.
+//!
+//! `WriteBehind` merges updates per key in memory so a key touched on every
+//! request costs one write per flush instead of one per request. It backs
+//! user last-seen times: with `Config::last_seen.enabled`, requests naming a
+//! user in `X-User-Id` (the caller's claim, as for debug targets) record the
+//! time, and `LastSeenFlusher` writes the pending times every
+//! `flush_interval_ms`, early once `max_pending` users are waiting, and one
+//! final time on shutdown.
+//!
+//! Loss bounds: a crash loses at most the last `flush_interval_ms` of
+//! updates. Between flushes at most `max_pending` distinct users are held;
+//! updates for further users are dropped and counted at `/metrics`. A failed
+//! flush is merged back and retried with the next one.
+
+use async_trait::async_trait;
+use axum::{extract::State, http::Request, middleware::Next, response::Response};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::lifecycle::Component;
+use crate::AppState;
+
+/// Last-seen tracking settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LastSeenConfig {
+    /// Record when users were last seen
+    pub enabled: bool,
+    /// Longest an update waits in memory, and so the most a crash loses
+    pub flush_interval_ms: u64,
+    /// Distinct users held between flushes
+    pub max_pending: usize,
+}
+
+impl Default for LastSeenConfig {
+    fn default() -> Self {
+        LastSeenConfig {
+            enabled: false,
+            flush_interval_ms: 5_000,
+            max_pending: 10_000,
+        }
+    }
+}
+
+/// Pending updates merged per key
+pub struct WriteBehind<K, V> {
+    pending: Mutex<HashMap<K, V>>,
+    /// Combine two updates of one key; must not depend on their order
+    merge: fn(&mut V, V),
+    max_pending: usize,
+    full: Notify,
+    dropped: AtomicU64,
+}
+
+impl<K: Hash + Eq, V> WriteBehind<K, V> {
+    /// Hold up to `max_pending` keys, combining updates with `merge`
+    pub fn new(max_pending: usize, merge: fn(&mut V, V)) -> Self {
+        Self {
+            pending: Mutex::new(HashMap::new()),
+            merge,
+            max_pending: max_pending.max(1),
+            full: Notify::new(),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Merge an update into the pending one for its key
+    pub fn record(&self, key: K, value: V) {
+        let mut pending = self.pending.lock().expect("write-behind lock poisoned");
+        let len = pending.len();
+        match pending.entry(key) {
+            Entry::Occupied(mut entry) => (self.merge)(entry.get_mut(), value),
+            Entry::Vacant(entry) if len < self.max_pending => {
+                entry.insert(value);
+                if len + 1 == self.max_pending {
+                    self.full.notify_one();
+                }
+            }
+            Entry::Vacant(_) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Take every pending update for writing
+    pub fn take(&self) -> Vec<(K, V)> {
+        let mut pending = self.pending.lock().expect("write-behind lock poisoned");
+        std::mem::take(&mut *pending).into_iter().collect()
+    }
+
+    /// Put back updates whose write failed, merged with any recorded since
+    pub fn restore(&self, updates: Vec<(K, V)>) {
+        for (key, value) in updates {
+            self.record(key, value);
+        }
+    }
+
+    /// Updates lost because too many keys were pending
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Pending last-seen times by user
+pub type LastSeenBuffer = WriteBehind<Uuid, chrono::DateTime<chrono::Utc>>;
+
+/// Buffer for last-seen times, keeping the later of two
+pub fn last_seen_buffer(config: &LastSeenConfig) -> LastSeenBuffer {
+    WriteBehind::new(config.max_pending, |pending, seen| *pending = (*pending).max(seen))
+}
+
+/// Write every pending last-seen time, keeping them for the next try on failure
+async fn flush(state: &AppState) {
+    // Held until the instance is writable again, or dropped at shutdown
+    if state.read_only.is_enabled() {
+        return;
+    }
+    let seen = state.last_seen.take();
+    if seen.is_empty() {
+        return;
+    }
+    match state.users.record_last_seen(&seen).await {
+        Ok(()) => state.degradations.recover("last_seen"),
+        Err(err) => {
+            tracing::warn!("failed to flush {} last-seen times: {}", seen.len(), err);
+            state.degradations.degrade("last_seen", "buffer_in_memory", &err);
+            state.last_seen.restore(seen);
+        }
+    }
+}
+
+/// Middleware recording when the calling user was seen
+pub async fn track_last_seen<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if state.config().last_seen.enabled {
+        let user = req
+            .headers()
+            .get("x-user-id")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<Uuid>().ok());
+        if let Some(user) = user {
+            state.last_seen.record(user, chrono::Utc::now());
+        }
+    }
+    next.run(req).await
+}
+
+/// Periodic flusher that writes what is left when stopped
+#[derive(Default)]
+pub struct LastSeenFlusher {
+    running: Mutex<Option<(JoinHandle<()>, Arc<Notify>)>>,
+}
+
+#[async_trait]
+impl Component for LastSeenFlusher {
+    fn name(&self) -> &'static str {
+        "last_seen"
+    }
+
+    async fn start(&self, state: &Arc<AppState>) -> Result<(), String> {
+        if !state.config().last_seen.enabled {
+            return Ok(());
+        }
+        let stopping = Arc::new(Notify::new());
+        let interval = Duration::from_millis(state.config().last_seen.flush_interval_ms);
+        let handle = tokio::spawn({
+            let (state, stopping) = (state.clone(), stopping.clone());
+            async move {
+                loop {
+                    tokio::select! {
+                        _ = tokio::time::sleep(interval) => {}
+                        _ = state.last_seen.full.notified() => {}
+                        _ = stopping.notified() => break,
+                    }
+                    flush(&state).await;
+                }
+                flush(&state).await;
+            }
+        });
+        *self.running.lock().expect("flusher lock poisoned") = Some((handle, stopping));
+        Ok(())
+    }
+
+    async fn stop(&self) -> Result<(), String> {
+        let running = self.running.lock().expect("flusher lock poisoned").take();
+        if let Some((handle, stopping)) = running {
+            // Stored permit, so a flush in progress finishes before the final one
+            stopping.notify_one();
+            handle.await.map_err(|err| err.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_updates_merge_per_key_within_capacity() {
+        let buffer = WriteBehind::new(2, |total: &mut u32, more| *total += more);
+        buffer.record("a", 1);
+        buffer.record("a", 2);
+        buffer.record("b", 1);
+        buffer.record("c", 1);
+        assert_eq!(buffer.dropped(), 1);
+
+        let mut taken = buffer.take();
+        taken.sort();
+        assert_eq!(taken, vec![("a", 3), ("b", 1)]);
+        assert!(buffer.take().is_empty());
+        buffer.record("a", 1);
+        buffer.restore(taken);
+        let mut restored = buffer.take();
+        restored.sort();
+        assert_eq!(restored, vec![("a", 4), ("b", 1)]);
+    }
+
+    #[tokio::test]
+    async fn test_stop_flushes_pending_times() {
+        let mut config = crate::Config::default();
+        config.last_seen = LastSeenConfig {
+            enabled: true,
+            flush_interval_ms: 60_000,
+            ..LastSeenConfig::default()
+        };
+        let state = AppState::new(config);
+        let flusher = LastSeenFlusher::default();
+        flusher.start(&state).await.unwrap();
+
+        let user = crate::User::new("alice".to_string(), "alice@example.com".to_string());
+        let user = state.users.insert(user).await.unwrap();
+        let at = chrono::Utc::now();
+        state.last_seen.record(user.id, at);
+        flusher.stop().await.unwrap();
+        assert_eq!(state.users.last_seen(&user.tenant_id, user.id).await.unwrap(), Some(at));
+    }
+}
@@ -0,0 +1,226 @@
+//! WebSocket endpoint for realtime user updates.
+//!
+//! Clients send `{"action": "subscribe", "targets": ["all"]}` or a list of
+//! user IDs, and `unsubscribe` with the same shape. Matching bus events are
+//! pushed as `{"type": "event", "event": ...}`. Each connection reads the bus
+//! at its own pace: a client too slow to keep up is told how many events it
+//! missed, and one that stops accepting writes or answering pings is dropped.
+//! A connection only sees users of the tenant it was opened for, `all`
+//! included.
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+use crate::events::DomainEvent;
+use crate::tenancy::Tenant;
+use crate::AppState;
+
+/// How often the server pings an idle connection
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a single write may block before the client is considered stuck
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Target name subscribing to every user
+const ALL_TARGET: &str = "all";
+
+/// Messages accepted from clients
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum ClientMessage {
+    /// Start receiving events for the targets
+    Subscribe { targets: Vec<String> },
+    /// Stop receiving events for the targets
+    Unsubscribe { targets: Vec<String> },
+}
+
+/// Messages sent to clients
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    /// A user event matching the subscription
+    Event { event: DomainEvent },
+    /// Current subscription after a change
+    Subscribed { all: bool, users: Vec<Uuid> },
+    /// Events were dropped because the client fell behind
+    Lagged { skipped: u64 },
+    /// The last client message was rejected
+    Error { message: String },
+}
+
+/// What a connection is subscribed to
+#[derive(Debug)]
+struct Subscription {
+    tenant: String,
+    all: bool,
+    users: HashSet<Uuid>,
+}
+
+impl Subscription {
+    /// An empty subscription to users of `tenant`
+    fn for_tenant(tenant: String) -> Self {
+        Subscription {
+            tenant,
+            all: false,
+            users: HashSet::new(),
+        }
+    }
+
+    /// Whether an event should be forwarded
+    fn wants(&self, event: &DomainEvent) -> bool {
+        event.user.tenant_id == self.tenant && (self.all || self.users.contains(&event.user.id))
+    }
+
+    /// Apply a client message, returning the reply
+    fn handle(&mut self, text: &str) -> ServerMessage {
+        let (subscribe, targets) = match serde_json::from_str(text) {
+            Ok(ClientMessage::Subscribe { targets }) => (true, targets),
+            Ok(ClientMessage::Unsubscribe { targets }) => (false, targets),
+            Err(err) => return ServerMessage::Error { message: err.to_string() },
+        };
+
+        let mut users = Vec::with_capacity(targets.len());
+        let mut all = false;
+        for target in &targets {
+            if target == ALL_TARGET {
+                all = true;
+            } else {
+                match target.parse::<Uuid>() {
+                    Ok(id) => users.push(id),
+                    Err(_) => {
+                        return ServerMessage::Error {
+                            message: format!("invalid target {:?}", target),
+                        }
+                    }
+                }
+            }
+        }
+
+        if subscribe {
+            self.all |= all;
+            self.users.extend(users);
+        } else {
+            self.all &= !all;
+            for id in &users {
+                self.users.remove(id);
+            }
+        }
+        self.current()
+    }
+
+    fn current(&self) -> ServerMessage {
+        let mut users: Vec<Uuid> = self.users.iter().copied().collect();
+        users.sort();
+        ServerMessage::Subscribed { all: self.all, users }
+    }
+}
+
+/// Upgrade to a realtime update connection
+pub async fn websocket(
+    State(state): State<Arc<AppState>>,
+    Tenant(tenant): Tenant,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    upgrade.on_upgrade(move |socket| serve_connection(socket, state, tenant))
+}
+
+/// Send with a deadline, returning whether the write succeeded
+async fn send(socket: &mut WebSocket, message: Message) -> bool {
+    matches!(tokio::time::timeout(SEND_TIMEOUT, socket.send(message)).await, Ok(Ok(())))
+}
+
+async fn serve_connection(mut socket: WebSocket, state: Arc<AppState>, tenant: String) {
+    let mut events = state.events.subscribe();
+    let mut subscription = Subscription::for_tenant(tenant);
+    let mut keepalive = tokio::time::interval(PING_INTERVAL);
+    let mut last_seen = Instant::now();
+
+    loop {
+        let reply = tokio::select! {
+            incoming = socket.recv() => {
+                let Some(Ok(message)) = incoming else { break };
+                last_seen = Instant::now();
+                match message {
+                    Message::Text(text) => Some(subscription.handle(&text)),
+                    Message::Close(_) => break,
+                    // Pings are answered by the library; pongs only refresh `last_seen`
+                    _ => None,
+                }
+            }
+            received = events.recv() => match received {
+                Ok(event) if subscription.wants(&event) => Some(ServerMessage::Event { event }),
+                Ok(_) => None,
+                Err(RecvError::Lagged(skipped)) => Some(ServerMessage::Lagged { skipped }),
+                Err(RecvError::Closed) => break,
+            },
+            _ = keepalive.tick() => {
+                if last_seen.elapsed() > PING_INTERVAL * 2 {
+                    tracing::debug!("closing websocket that stopped answering pings");
+                    break;
+                }
+                if !send(&mut socket, Message::Ping(Vec::new())).await {
+                    break;
+                }
+                None
+            }
+        };
+
+        if let Some(reply) = reply {
+            let text = serde_json::to_string(&reply).expect("server messages always serialize");
+            if !send(&mut socket, Message::Text(text)).await {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventKind;
+    use crate::tenancy::default_tenant;
+    use crate::User;
+
+    #[test]
+    fn test_subscription_filters_by_user() {
+        let alice = User::new("alice".to_string(), "alice@example.com".to_string());
+        let bob = User::new("bob".to_string(), "bob@example.com".to_string());
+        let mut subscription = Subscription::for_tenant(default_tenant());
+        subscription.handle(&format!(
+            r#"{{"action":"subscribe","targets":["{}"]}}"#,
+            alice.id
+        ));
+
+        assert!(subscription.wants(&DomainEvent::user(EventKind::UserUpdated, &alice)));
+        assert!(!subscription.wants(&DomainEvent::user(EventKind::UserUpdated, &bob)));
+
+        subscription.handle(r#"{"action":"subscribe","targets":["all"]}"#);
+        assert!(subscription.wants(&DomainEvent::user(EventKind::UserUpdated, &bob)));
+        let globex = User {
+            tenant_id: "globex".to_string(),
+            ..bob.clone()
+        };
+        assert!(!subscription.wants(&DomainEvent::user(EventKind::UserUpdated, &globex)));
+        subscription.handle(r#"{"action":"unsubscribe","targets":["all"]}"#);
+        assert!(!subscription.wants(&DomainEvent::user(EventKind::UserUpdated, &bob)));
+    }
+
+    #[test]
+    fn test_invalid_target_is_rejected_without_changes() {
+        let mut subscription = Subscription::for_tenant(default_tenant());
+        let reply = subscription.handle(r#"{"action":"subscribe","targets":["all","nope"]}"#);
+        assert!(matches!(reply, ServerMessage::Error { .. }));
+        assert!(!subscription.all);
+    }
+}